@@ -30,13 +30,14 @@
 // TODO: I believe this example isn't tested ^ which kills the point of having it
 
 use smoldot::{
+    database::full_sqlite,
     identity::seed_phrase,
     libp2p::{
         multiaddr::{Multiaddr, ProtocolRef},
         PeerId,
     },
 };
-use std::{io, net::SocketAddr, path::PathBuf};
+use std::{io, net::SocketAddr, num::NonZeroU64, path::PathBuf};
 
 // Note: the doc-comments applied to this struct and its field are visible when the binary is
 // started with `--help`.
@@ -60,6 +61,15 @@ pub enum CliOptionsCommand {
     /// Computes the 256 bits BLAKE2 hash of a file and prints the hexadecimal-encoded hash.
     #[command(name = "blake2-256bits-hash")]
     Blake2256BitsHash(CliOptionsBlake2256Hash),
+    /// Re-executes a block found in the local database and prints the changes it performs to the
+    /// storage, decoded using the metadata of the runtime. The node must not be running.
+    #[command(name = "storage-diff")]
+    StorageDiff(CliOptionsStorageDiff),
+    /// Executes the storage migrations of a new runtime against the latest finalized block found
+    /// in the local database, and reports failures and weight overruns. The runtime must support
+    /// the `TryRuntime` API. The node must not be running.
+    #[command(name = "simulate-runtime-upgrade")]
+    SimulateRuntimeUpgrade(CliOptionsSimulateRuntimeUpgrade),
 }
 
 #[derive(Debug, clap::Parser)]
@@ -108,6 +118,13 @@ pub struct CliOptionsRun {
     /// chain is not a parachain.
     #[arg(long, default_value = "256M", value_parser = parse_max_bytes)]
     pub relay_chain_database_cache_size: MaxBytes,
+    /// Storage to keep in the database: "archive" to keep everything, or a number of finalized
+    /// blocks whose storage to keep. Also applies to the relay chain if the chain is a parachain.
+    #[arg(long, default_value = "256", value_parser = parse_pruning)]
+    pub pruning: Pruning,
+    /// How to sync the chain if the database is empty: full, warp.
+    #[arg(long, default_value = "full")]
+    pub sync_mode: SyncMode,
 }
 
 #[derive(Debug, clap::Parser)]
@@ -122,6 +139,48 @@ pub struct CliOptionsBlake2256Hash {
     pub file: PathBuf,
 }
 
+#[derive(Debug, clap::Parser)]
+pub struct CliOptionsStorageDiff {
+    /// Hexadecimal-encoded hash of the block to re-execute.
+    #[arg(value_parser = parse_block_hash)]
+    pub block_hash: BlockHash,
+    /// Chain the block belongs to ("Polkadot", "Kusama", "Westend", or a file path).
+    #[arg(long, default_value = "polkadot")]
+    pub chain: CliChain,
+    /// Maximum size of the cache used by the database.
+    #[arg(long, default_value = "256M", value_parser = parse_max_bytes)]
+    pub database_cache_size: MaxBytes,
+}
+
+#[derive(Debug, clap::Parser)]
+pub struct CliOptionsSimulateRuntimeUpgrade {
+    /// Path of the Wasm file of the new runtime.
+    pub wasm_file: PathBuf,
+    /// Chain to upgrade ("Polkadot", "Kusama", "Westend", or a file path).
+    #[arg(long, default_value = "polkadot")]
+    pub chain: CliChain,
+    /// Checks to perform alongside with the migrations: none, all, pre-and-post, try-state.
+    #[arg(long, default_value = "all")]
+    pub checks: UpgradeChecks,
+    /// Maximum size of the cache used by the database.
+    #[arg(long, default_value = "256M", value_parser = parse_max_bytes)]
+    pub database_cache_size: MaxBytes,
+}
+
+#[derive(Debug, Clone, clap::ValueEnum)]
+pub enum SyncMode {
+    Full,
+    Warp,
+}
+
+#[derive(Debug, Clone, clap::ValueEnum)]
+pub enum UpgradeChecks {
+    None,
+    All,
+    PreAndPost,
+    TryState,
+}
+
 #[derive(Debug, Clone)]
 pub enum CliChain {
     Polkadot,
@@ -304,6 +363,34 @@ fn parse_max_bytes(string: &str) -> Result<MaxBytes, String> {
     Ok(MaxBytes(real_value))
 }
 
+#[derive(Debug, Clone)]
+pub struct BlockHash(pub [u8; 32]);
+
+fn parse_block_hash(string: &str) -> Result<BlockHash, String> {
+    let string = string.strip_prefix("0x").unwrap_or(string);
+    let mut hash = [0; 32];
+    hex::decode_to_slice(string, &mut hash)
+        .map_err(|_| "Block hash must be 32 hexadecimal-encoded bytes".to_string())?;
+    Ok(BlockHash(hash))
+}
+
+#[derive(Debug, Clone)]
+pub struct Pruning(pub full_sqlite::PruningMode);
+
+fn parse_pruning(string: &str) -> Result<Pruning, String> {
+    if string.eq_ignore_ascii_case("archive") {
+        return Ok(Pruning(full_sqlite::PruningMode::Archive));
+    }
+
+    let Ok(num_finalized_blocks) = string.parse::<NonZeroU64>() else {
+        return Err("Pruning must be either \"archive\" or a non-zero number of blocks".into());
+    };
+
+    Ok(Pruning(full_sqlite::PruningMode::Pruned {
+        num_finalized_blocks,
+    }))
+}
+
 // `clap` requires error types to implement the `std::error::Error` trait.
 // For this reason, we locally define some wrappers.
 fn decode_ed25519_private_key(phrase: &str) -> Result<Box<[u8; 32]>, String> {
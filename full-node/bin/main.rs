@@ -21,6 +21,7 @@
 use std::{
     borrow::Cow,
     fs, io,
+    path::PathBuf,
     sync::Arc,
     thread,
     time::{Duration, SystemTime, UNIX_EPOCH},
@@ -47,6 +48,123 @@ async fn async_main() {
             let hash = blake2_rfc::blake2b::blake2b(32, &[], &content);
             println!("0x{}", hex::encode(hash));
         }
+        cli::CliOptionsCommand::StorageDiff(opt) => storage_diff(opt).await,
+        cli::CliOptionsCommand::SimulateRuntimeUpgrade(opt) => simulate_runtime_upgrade(opt).await,
+    }
+}
+
+async fn storage_diff(cli_options: cli::CliOptionsStorageDiff) {
+    let chain_spec = chain_spec_bytes(&cli_options.chain);
+    let sqlite_database_path = existing_database_path(&chain_spec);
+
+    let diff = smoldot_full_node::block_storage_diff(smoldot_full_node::BlockStorageDiffConfig {
+        chain_spec,
+        sqlite_database_path,
+        sqlite_cache_size: cli_options.database_cache_size.0,
+        block_hash: cli_options.block_hash.0,
+    })
+    .await
+    .unwrap_or_else(|err| panic!("Failed to obtain the storage diff: {err}"));
+
+    for entry in diff {
+        let key = entry
+            .decoded_key
+            .unwrap_or_else(|| format!("0x{}", hex::encode(&entry.key)));
+        let value = match (entry.decoded_new_value, entry.new_value) {
+            (Some(decoded), _) => decoded,
+            (None, Some(value)) => format!("0x{}", hex::encode(value)),
+            (None, None) => "<removed>".to_owned(),
+        };
+        println!("{key} = {value}");
+    }
+}
+
+async fn simulate_runtime_upgrade(cli_options: cli::CliOptionsSimulateRuntimeUpgrade) {
+    let chain_spec = chain_spec_bytes(&cli_options.chain);
+    let sqlite_database_path = existing_database_path(&chain_spec);
+    let new_code = fs::read(&cli_options.wasm_file).expect("Failed to read Wasm file");
+
+    let report =
+        smoldot_full_node::simulate_runtime_upgrade(smoldot_full_node::RuntimeUpgradeConfig {
+            chain_spec,
+            sqlite_database_path,
+            sqlite_cache_size: cli_options.database_cache_size.0,
+            new_code: new_code.into(),
+            checks: match cli_options.checks {
+                cli::UpgradeChecks::None => smoldot_full_node::UpgradeChecks::None,
+                cli::UpgradeChecks::All => smoldot_full_node::UpgradeChecks::All,
+                cli::UpgradeChecks::PreAndPost => smoldot_full_node::UpgradeChecks::PreAndPost,
+                cli::UpgradeChecks::TryState => smoldot_full_node::UpgradeChecks::TryState,
+            },
+        })
+        .await
+        .unwrap_or_else(|err| {
+            if let smoldot_full_node::RuntimeUpgradeError::UpgradeFailed { logs, .. } = &err {
+                print!("{logs}");
+            }
+            panic!("{err}")
+        });
+
+    print!("{}", report.logs);
+    println!(
+        "Upgraded from {} {} to {} {} at block #{} (0x{})",
+        report.old_spec_name,
+        report.old_spec_version,
+        report.new_spec_name,
+        report.new_spec_version,
+        report.block_number,
+        hex::encode(report.block_hash)
+    );
+    println!("Storage entries modified: {}", report.num_storage_changes);
+    println!(
+        "Consumed weight: ref_time = {}, proof_size = {}",
+        report.consumed_weight.ref_time, report.consumed_weight.proof_size
+    );
+    println!(
+        "Maximum block weight: ref_time = {}, proof_size = {}",
+        report.max_block_weight.ref_time, report.max_block_weight.proof_size
+    );
+
+    if report.is_weight_overrun() {
+        eprintln!("The migrations exceed the maximum weight of a block");
+        std::process::exit(1);
+    }
+}
+
+/// Returns the path of the database of the given chain, at the same location as the one used by
+/// the `run` command.
+///
+/// # Panic
+///
+/// Panics if the chain specification is invalid or if the database doesn't exist.
+///
+fn existing_database_path(chain_spec: &[u8]) -> PathBuf {
+    let chain_id = smoldot::chain_spec::ChainSpec::from_json_bytes(chain_spec)
+        .expect("Failed to decode chain specification")
+        .id()
+        .to_owned();
+
+    let sqlite_database_path = directories::ProjectDirs::from("io", "smoldot", "smoldot")
+        .expect("Failed to fetch $HOME directory")
+        .data_dir()
+        .join(chain_id)
+        .join("database");
+    if !sqlite_database_path.exists() {
+        panic!("No database found at {}", sqlite_database_path.display());
+    }
+    sqlite_database_path
+}
+
+fn chain_spec_bytes(chain: &cli::CliChain) -> Cow<'static, [u8]> {
+    match chain {
+        cli::CliChain::Polkadot => {
+            (&include_bytes!("../../demo-chain-specs/polkadot.json")[..]).into()
+        }
+        cli::CliChain::Kusama => (&include_bytes!("../../demo-chain-specs/kusama.json")[..]).into(),
+        cli::CliChain::Westend => {
+            (&include_bytes!("../../demo-chain-specs/westend.json")[..]).into()
+        }
+        cli::CliChain::Custom(path) => fs::read(path).expect("Failed to read chain specs").into(),
     }
 }
 
@@ -191,16 +309,7 @@ async fn run(cli_options: cli::CliOptionsRun) {
         cli::Output::Auto => unreachable!(), // Handled above.
     };
 
-    let chain_spec: Cow<[u8]> = match &cli_options.chain {
-        cli::CliChain::Polkadot => {
-            (&include_bytes!("../../demo-chain-specs/polkadot.json")[..]).into()
-        }
-        cli::CliChain::Kusama => (&include_bytes!("../../demo-chain-specs/kusama.json")[..]).into(),
-        cli::CliChain::Westend => {
-            (&include_bytes!("../../demo-chain-specs/westend.json")[..]).into()
-        }
-        cli::CliChain::Custom(path) => fs::read(path).expect("Failed to read chain specs").into(),
-    };
+    let chain_spec = chain_spec_bytes(&cli_options.chain);
 
     let parsed_chain_spec = {
         smoldot::chain_spec::ChainSpec::from_json_bytes(&chain_spec)
@@ -276,6 +385,7 @@ async fn run(cli_options: cli::CliOptionsRun) {
                         .join("database.sqlite")
                 }),
                 sqlite_cache_size: cli_options.relay_chain_database_cache_size.0,
+                sqlite_pruning: cli_options.pruning.0,
                 keystore_path: base_storage_directory
                     .as_ref()
                     .map(|path| path.join(parsed_relay_spec.id()).join("keys")),
@@ -322,6 +432,42 @@ async fn run(cli_options: cli::CliOptionsRun) {
         key
     };
 
+    // Determine which certificate to use for WebRTC connections.
+    //
+    // Its hash is part of the WebRTC addresses of the node, so it is loaded from disk if possible
+    // in order for these addresses to not change between restarts.
+    let webrtc_certificate = if let Some(dir) = base_storage_directory.as_ref() {
+        let certificate_path = dir.join("webrtc_certificate.der");
+        let private_key_path = dir.join("webrtc_certificate_private_key.secret");
+        let certificate = if certificate_path.exists() && private_key_path.exists() {
+            let certificate =
+                fs::read(&certificate_path).expect("failed to read WebRTC certificate file");
+            let private_key = fs::read(&private_key_path)
+                .expect("failed to read WebRTC certificate private key file");
+            smoldot_full_node::WebRtcCertificate::from_der(certificate, private_key)
+                .expect("invalid WebRTC certificate or private key file content")
+        } else {
+            let certificate = smoldot_full_node::WebRtcCertificate::generate();
+            // The private key file might already exist with a read-only permission.
+            let _ = fs::remove_file(&private_key_path);
+            fs::write(&private_key_path, certificate.private_key_der())
+                .expect("failed to write WebRTC certificate private key file");
+            fs::write(&certificate_path, certificate.certificate_der())
+                .expect("failed to write WebRTC certificate file");
+            certificate
+        };
+        // On Unix platforms, set the permission as 0o400 (only reading and by owner is permitted).
+        // TODO: do something equivalent on Windows
+        #[cfg(unix)]
+        let _ = fs::set_permissions(
+            &private_key_path,
+            std::os::unix::fs::PermissionsExt::from_mode(0o400),
+        );
+        certificate
+    } else {
+        smoldot_full_node::WebRtcCertificate::generate()
+    };
+
     // Create an executor where tasks are going to be spawned onto.
     let executor = Arc::new(smol::Executor::new());
     for n in 0..thread::available_parallelism()
@@ -387,11 +533,15 @@ async fn run(cli_options: cli::CliOptionsRun) {
             keystore_memory: cli_options.keystore_memory,
             sqlite_database_path,
             sqlite_cache_size: cli_options.database_cache_size.0,
+            sqlite_pruning: cli_options.pruning.0,
             keystore_path,
         },
         relay_chain,
         libp2p_key,
         listen_addresses: cli_options.listen_addr,
+        memory_network: None,
+        time_source: None,
+        webrtc_certificate,
         json_rpc: if let Some(address) = cli_options.json_rpc_address.0 {
             Some(smoldot_full_node::JsonRpcConfig {
                 address,
@@ -406,6 +556,11 @@ async fn run(cli_options: cli::CliOptionsRun) {
         },
         log_callback: log_callback.clone(),
         jaeger_agent: cli_options.jaeger,
+        offchain_http_client: None,
+        sync_mode: match cli_options.sync_mode {
+            cli::SyncMode::Full => smoldot_full_node::SyncMode::Full,
+            cli::SyncMode::Warp => smoldot_full_node::SyncMode::Warp,
+        },
     })
     .await;
 
@@ -0,0 +1,82 @@
+// Smoldot
+// Copyright (C) 2023  Pierre Krieger
+// SPDX-License-Identifier: GPL-3.0-or-later WITH Classpath-exception-2.0
+
+// This program is free software: you can redistribute it and/or modify
+// it under the terms of the GNU General Public License as published by
+// the Free Software Foundation, either version 3 of the License, or
+// (at your option) any later version.
+
+// This program is distributed in the hope that it will be useful,
+// but WITHOUT ANY WARRANTY; without even the implied warranty of
+// MERCHANTABILITY or FITNESS FOR A PARTICULAR PURPOSE.  See the
+// GNU General Public License for more details.
+
+// You should have received a copy of the GNU General Public License
+// along with this program.  If not, see <http://www.gnu.org/licenses/>.
+
+//! On-disk storage of the runtimes compiled ahead of time.
+//!
+//! Compiling a runtime can take several seconds. The [`CompiledRuntimesCache`] stores the
+//! outcome of the compilation in a directory, in order for the runtimes to not have to be
+//! compiled again when the node restarts.
+//!
+//! Each entry is stored in a separate file whose name is derived from the
+//! [`vm::CompiledModuleKey`]. Entries that have been accidentally corrupted, for example
+//! truncated, or that have been produced by a different version of the compiler are compiled
+//! again and overwritten.
+//!
+//! The content of the files is turned into machine code without being validated. The directory
+//! must therefore not be writable by anyone other than the node.
+
+use smoldot::executor::vm;
+use std::{fs, io, path::PathBuf};
+
+/// Implementation of [`vm::CompiledModuleCache`] that stores the entries as files in a
+/// directory.
+pub struct CompiledRuntimesCache {
+    /// Directory where the entries are stored. Created when the first entry is stored.
+    directory: PathBuf,
+}
+
+impl CompiledRuntimesCache {
+    /// Builds a new cache that stores its entries in the given directory.
+    pub fn new(directory: PathBuf) -> Self {
+        CompiledRuntimesCache { directory }
+    }
+
+    /// Returns the path of the file containing the entry with the given key.
+    fn entry_path(&self, key: &vm::CompiledModuleKey) -> PathBuf {
+        self.directory.join(format!(
+            "{}-{:016x}-{}.bin",
+            hex::encode(key.code_hash),
+            key.engine_hash,
+            u32::from(key.heap_pages)
+        ))
+    }
+
+    fn try_store(&self, key: &vm::CompiledModuleKey, data: &[u8]) -> Result<(), io::Error> {
+        fs::create_dir_all(&self.directory)?;
+
+        // The data is first written to a temporary file then moved, so that a crash or a
+        // concurrent load never observes a partially-written entry.
+        let path = self.entry_path(key);
+        let temporary_path = path.with_extension("tmp");
+        fs::write(&temporary_path, data)?;
+        fs::rename(&temporary_path, &path)
+    }
+}
+
+// SAFETY: The files are only written by `store`. The directory is located next to the database,
+// which is assumed to only be writable by the node.
+unsafe impl vm::CompiledModuleCache for CompiledRuntimesCache {
+    fn load(&self, key: &vm::CompiledModuleKey) -> Option<Vec<u8>> {
+        fs::read(self.entry_path(key)).ok()
+    }
+
+    fn store(&self, key: &vm::CompiledModuleKey, data: &[u8]) {
+        // Failing to store the entry only means that the runtime will have to be compiled
+        // again later.
+        let _ = self.try_store(key, data);
+    }
+}
@@ -24,7 +24,10 @@
 // TODO: doc
 // TODO: re-review this once finished
 
-use crate::{database_thread, jaeger_service, network_service, LogCallback, LogLevel};
+use crate::{
+    database_thread, jaeger_service, network_service, runtime_call, LogCallback, LogLevel,
+    TimeSource,
+};
 
 use core::num::NonZeroU32;
 use futures_channel::{mpsc, oneshot};
@@ -47,10 +50,10 @@ use smoldot::{
 use std::{
     array,
     borrow::Cow,
-    iter, mem,
-    num::NonZeroU64,
+    cmp, iter, mem,
+    num::{NonZeroU64, NonZeroUsize},
     sync::Arc,
-    time::{Duration, Instant, SystemTime, UNIX_EPOCH},
+    time::{Duration, Instant},
 };
 
 /// Configuration for a [`ConsensusService`].
@@ -61,6 +64,9 @@ pub struct Config {
     /// Function called in order to notify of something.
     pub log_callback: Arc<dyn LogCallback + Send + Sync>,
 
+    /// Source of the time used to determine the current slot and when to author blocks.
+    pub time_source: Arc<dyn TimeSource + Send + Sync>,
+
     /// Database to use to read and write information about the chain.
     pub database: Arc<database_thread::DatabaseThread>,
 
@@ -104,6 +110,23 @@ pub struct Config {
     /// Note that this value doesn't determine the moment when creating the block has ended, but
     /// the moment when creating the block should start its final phase.
     pub slot_duration_author_ratio: u16,
+
+    /// Channel used to ask for the transactions to include in the blocks authored locally.
+    ///
+    /// If `None`, or if no answer is received before the end of the authoring, the blocks
+    /// authored locally don't contain any transaction.
+    pub block_author_transactions: Option<async_channel::Sender<BlockAuthorTransactionsRequest>>,
+}
+
+/// Request sent through [`Config::block_author_transactions`] when a block is about to be
+/// authored.
+pub struct BlockAuthorTransactionsRequest {
+    /// Hash of the block the new block is going to be built upon.
+    pub parent_hash: [u8; 32],
+
+    /// Sender to use to answer the request with the list of SCALE-encoded transactions to try
+    /// include in the new block, in the order in which they should be included.
+    pub result_tx: oneshot::Sender<Vec<Vec<u8>>>,
 }
 
 /// Identifier for a blocks request to be performed.
@@ -126,10 +149,96 @@ pub struct ConsensusService {
     to_background_tx: Mutex<mpsc::Sender<ToBackground>>,
 }
 
+/// Return value of [`ConsensusService::subscribe_all`].
+pub struct SubscribeAll {
+    /// SCALE-encoded header of the finalized block at the time of the subscription.
+    pub finalized_block_scale_encoded_header: Vec<u8>,
+
+    /// Hash of the finalized block at the time of the subscription.
+    pub finalized_block_hash: [u8; 32],
+
+    /// Specification of the runtime of the finalized block.
+    pub finalized_block_runtime: executor::CoreVersion,
+
+    /// List of all known non-finalized blocks at the time of subscription.
+    ///
+    /// Only one element in this list has [`BlockNotification::is_new_best`] equal to true.
+    ///
+    /// The blocks are guaranteed to be ordered so that parents are always found before their
+    /// children.
+    pub non_finalized_blocks_ancestry_order: Vec<BlockNotification>,
+
+    /// Channel onto which new blocks are sent. The channel gets closed if it is full when a new
+    /// block needs to be reported.
+    pub new_blocks: async_channel::Receiver<Notification>,
+}
+
+/// Notification about a new block or a new finalized block.
+///
+/// See [`ConsensusService::subscribe_all`].
+#[derive(Debug, Clone)]
+pub enum Notification {
+    /// A non-finalized block has been finalized.
+    Finalized {
+        /// BLAKE2 hash of the block that has been finalized.
+        ///
+        /// A block with this hash is guaranteed to have earlier been reported in a
+        /// [`BlockNotification`], either in [`SubscribeAll::non_finalized_blocks_ancestry_order`]
+        /// or in a [`Notification::Block`].
+        ///
+        /// If multiple blocks are finalized at the same time, only one
+        /// [`Notification::Finalized`] is generated and contains the highest finalized block.
+        hash: [u8; 32],
+
+        /// Hash of the best block after the finalization.
+        ///
+        /// A block with this hash is guaranteed to have earlier been reported in a
+        /// [`BlockNotification`], either in [`SubscribeAll::non_finalized_blocks_ancestry_order`]
+        /// or in a [`Notification::Block`], or to be equal to [`Notification::Finalized::hash`].
+        best_block_hash: [u8; 32],
+    },
+
+    /// A new block has been added to the list of unfinalized blocks.
+    Block(BlockNotification),
+}
+
+/// Notification about a new block.
+///
+/// See [`ConsensusService::subscribe_all`].
+#[derive(Debug, Clone)]
+pub struct BlockNotification {
+    /// True if this block is considered as the best block of the chain.
+    pub is_new_best: bool,
+
+    /// SCALE-encoded header of the block.
+    pub scale_encoded_header: Vec<u8>,
+
+    /// BLAKE2 hash of the block.
+    pub block_hash: [u8; 32],
+
+    /// BLAKE2 hash of the header of the parent of this block.
+    ///
+    /// A block with this hash is guaranteed to have earlier been reported in a
+    /// [`BlockNotification`], either in [`SubscribeAll::non_finalized_blocks_ancestry_order`] or
+    /// in a [`Notification::Block`], or to be the finalized block.
+    pub parent_hash: [u8; 32],
+
+    /// If the runtime of the block is different from the one of its parent, contains the
+    /// specification of the new runtime. `None` if the runtime is unchanged.
+    pub new_runtime: Option<executor::CoreVersion>,
+}
+
 enum ToBackground {
     GetSyncState {
         result_tx: oneshot::Sender<SyncState>,
     },
+    SubscribeAll {
+        buffer_size: usize,
+        result_tx: oneshot::Sender<SubscribeAll>,
+    },
+    InjectGrandpaJustification {
+        scale_encoded_justification: Vec<u8>,
+    },
 }
 
 impl ConsensusService {
@@ -137,6 +246,7 @@ impl ConsensusService {
     pub async fn new(config: Config) -> Arc<Self> {
         // Perform the initial access to the database to load a bunch of information.
         let (
+            finalized_block_hash,
             finalized_block_number,
             finalized_heap_pages,
             finalized_code,
@@ -189,6 +299,7 @@ impl ConsensusService {
                         .unwrap() // TODO: better error?
                         .map(|(hp, _)| hp);
                     (
+                        finalized_block_hash,
                         finalized_block_number,
                         finalized_heap_pages,
                         finalized_code,
@@ -221,6 +332,11 @@ impl ConsensusService {
             );
         }
 
+        let is_babe = matches!(
+            finalized_chain_information.as_ref().consensus,
+            chain_information::ChainInformationConsensusRef::Babe { .. }
+        );
+
         let mut sync = all::AllSync::new(all::Config {
             chain_information: finalized_chain_information,
             block_number_bytes: config.block_number_bytes,
@@ -244,19 +360,74 @@ impl ConsensusService {
             code_trie_node_hint: None,
         });
 
-        let finalized_runtime = {
-            // Builds the runtime of the finalized block.
-            // Assumed to always be valid, otherwise the block wouldn't have been
-            // saved in the database, hence the large number of unwraps here.
-            let heap_pages =
-                executor::storage_heap_pages_to_value(finalized_heap_pages.as_deref()).unwrap(); // TODO: better error message?
-            executor::host::HostVmPrototype::new(executor::host::Config {
-                module: finalized_code,
-                heap_pages,
-                exec_hint: executor::vm::ExecHint::CompileAheadOfTime, // TODO: probably should be decided by the optimisticsync
-                allow_unresolved_imports: false,
-            })
-            .unwrap() // TODO: better error message?
+        // Builds the runtime of the finalized block.
+        // Assumed to always be valid, otherwise the block wouldn't have been
+        // saved in the database, hence the large number of unwraps here.
+        let finalized_heap_pages =
+            executor::storage_heap_pages_to_value(finalized_heap_pages.as_deref()).unwrap(); // TODO: better error message?
+        let finalized_runtime = executor::host::HostVmPrototype::new(executor::host::Config {
+            module: &finalized_code,
+            heap_pages: finalized_heap_pages,
+            exec_hint: executor::vm::ExecHint::CompileAheadOfTime, // TODO: probably should be decided by the optimisticsync
+            allow_unresolved_imports: false,
+            compiled_module_cache: config.database.compiled_runtimes_cache(),
+            resource_limits: Default::default(),
+            allocation_strategy: Default::default(),
+        })
+        .unwrap(); // TODO: better error message?
+
+        // The duration of a Babe slot isn't part of the chain information, and is instead
+        // obtained by calling the runtime of the finalized block. This value can't be modified
+        // after the genesis.
+        let (finalized_runtime, babe_slot_duration) = if is_babe {
+            let cache_key = (
+                <[u8; 32]>::try_from(
+                    blake2_rfc::blake2b::blake2b(32, &[], &finalized_code).as_bytes(),
+                )
+                .unwrap(),
+                finalized_heap_pages,
+            );
+            let runtime_caches = Mutex::new(lru::LruCache::new(NonZeroUsize::new(1).unwrap()));
+            runtime_caches
+                .lock()
+                .await
+                .put(cache_key, finalized_runtime);
+
+            let slot_duration = match runtime_call::runtime_call(
+                &config.database,
+                &runtime_caches,
+                finalized_block_hash,
+                "BabeApi_configuration",
+                iter::empty::<Vec<u8>>(),
+            )
+            .await
+            {
+                Ok(output) if output.len() >= 8 => NonZeroU64::new(u64::from_le_bytes(
+                    <[u8; 8]>::try_from(&output[..8]).unwrap(),
+                )),
+                Ok(_) => None,
+                Err(error) => {
+                    config.log_callback.log(
+                        LogLevel::Warn,
+                        format!("babe-configuration-call-error; error={error}"),
+                    );
+                    None
+                }
+            };
+
+            if slot_duration.is_none() {
+                config.log_callback.log(
+                    LogLevel::Warn,
+                    "Failed to determine the Babe slot duration. Blocks will not be authored."
+                        .to_string(),
+                );
+            }
+
+            // `runtime_call` puts the runtime back in the cache in all situations.
+            let finalized_runtime = runtime_caches.lock().await.pop(&cache_key).unwrap();
+            (finalized_runtime, slot_duration)
+        } else {
+            (finalized_runtime, None)
         };
 
         let block_author_sync_source = sync.add_source(None, best_block_number, best_block_hash);
@@ -270,16 +441,20 @@ impl ConsensusService {
             block_authoring: None,
             authored_block: None,
             slot_duration_author_ratio: config.slot_duration_author_ratio,
+            babe_slot_duration,
             keystore: config.keystore,
+            block_author_transactions: config.block_author_transactions,
             finalized_runtime: Arc::new(Mutex::new(Some(finalized_runtime))),
             network_service: config.network_service.0,
             network_chain_index: config.network_service.1,
             to_background_rx,
             from_network_service: config.network_events_receiver,
             database: config.database,
+            blocks_notifications: Vec::new(),
             peers_source_id_map: Default::default(),
             tasks_executor: config.tasks_executor,
             log_callback: config.log_callback,
+            time_source: config.time_source,
             block_requests_finished_tx,
             block_requests_finished_rx,
             jaeger_service: config.jaeger_service,
@@ -306,6 +481,45 @@ impl ConsensusService {
             .await;
         result_rx.await.unwrap()
     }
+
+    /// Subscribes to the state of the chain: the current state and the new blocks.
+    ///
+    /// All new blocks are reported. Only up to `buffer_size` block notifications are buffered
+    /// in the channel. If the channel is full when a new notification is attempted to be pushed,
+    /// the channel gets closed.
+    ///
+    /// Blocks are reported only after they have been inserted in the database.
+    ///
+    /// See [`SubscribeAll`] for information about the return value.
+    pub async fn subscribe_all(&self, buffer_size: usize) -> SubscribeAll {
+        let (result_tx, result_rx) = oneshot::channel();
+        let _ = self
+            .to_background_tx
+            .lock()
+            .await
+            .send(ToBackground::SubscribeAll {
+                buffer_size,
+                result_tx,
+            })
+            .await;
+        result_rx.await.unwrap()
+    }
+
+    /// Injects a GrandPa justification produced locally, for example by the GrandPa voter of the
+    /// local node.
+    ///
+    /// The justification is verified similarly to the ones received from the network. If it
+    /// targets a block that isn't known to the service, it is silently discarded.
+    pub async fn inject_grandpa_justification(&self, scale_encoded_justification: Vec<u8>) {
+        let _ = self
+            .to_background_tx
+            .lock()
+            .await
+            .send(ToBackground::InjectGrandpaJustification {
+                scale_encoded_justification,
+            })
+            .await;
+    }
 }
 
 struct SyncBackground {
@@ -346,6 +560,10 @@ struct SyncBackground {
     /// See [`Config::slot_duration_author_ratio`].
     slot_duration_author_ratio: u16,
 
+    /// Duration, in milliseconds, of a Babe slot. `None` if the chain isn't using Babe, or if
+    /// the slot duration couldn't be determined, in which case no block is authored.
+    babe_slot_duration: Option<NonZeroU64>,
+
     /// After a block has been authored, it is inserted here while waiting for the `sync` to
     /// import it. Contains the block height, the block hash, the SCALE-encoded block header, and
     /// the list of SCALE-encoded extrinsics of the block.
@@ -354,6 +572,9 @@ struct SyncBackground {
     /// See [`Config::keystore`].
     keystore: Arc<keystore::Keystore>,
 
+    /// See [`Config::block_author_transactions`].
+    block_author_transactions: Option<async_channel::Sender<BlockAuthorTransactionsRequest>>,
+
     /// Runtime of the latest finalized block.
     ///
     /// The runtime is extracted when necessary then put back it place.
@@ -389,6 +610,9 @@ struct SyncBackground {
     /// See [`Config::log_callback`].
     log_callback: Arc<dyn LogCallback + Send + Sync>,
 
+    /// See [`Config::time_source`].
+    time_source: Arc<dyn TimeSource + Send + Sync>,
+
     /// Block requests that have been emitted on the networking service and that are still in
     /// progress. Each entry in this field also has an entry in [`SyncBackground::sync`].
     block_requests_finished_rx: mpsc::Receiver<(
@@ -407,6 +631,10 @@ struct SyncBackground {
     /// See [`Config::database`].
     database: Arc<database_thread::DatabaseThread>,
 
+    /// List of senders to report blocks-related events to. Senders are removed if they are
+    /// closed or full.
+    blocks_notifications: Vec<async_channel::Sender<Notification>>,
+
     /// How to report events about blocks.
     jaeger_service: Arc<jaeger_service::JaegerService>,
 }
@@ -495,45 +723,98 @@ impl SyncBackground {
                                 finalized_authorities_list, // TODO: field name not appropriate; should probably change the chain_information module
                                 slot_duration,
                             },
-                        ) => Some(
-                            block_authoring.insert((
+                        ) => {
+                            // Blocks must have a slot number strictly greater than the one of
+                            // their parent. If the best block has been authored during the
+                            // current slot, pretend that we are at the start of the next slot so
+                            // that the authoring waits for it.
+                            let now_from_unix_epoch = cmp::max(
+                                self.time_source.now_from_unix_epoch(),
+                                self.sync
+                                    .best_block_header()
+                                    .digest
+                                    .aura_pre_runtime()
+                                    .map_or(Duration::new(0, 0), |pre_digest| {
+                                        Duration::from_millis(
+                                            pre_digest
+                                                .slot_number
+                                                .saturating_add(1)
+                                                .saturating_mul(slot_duration.get()),
+                                        )
+                                    }),
+                            );
+
+                            Some(block_authoring.insert((
                                 author::build::Builder::new(author::build::Config {
                                     consensus: author::build::ConfigConsensus::Aura {
                                         current_authorities: finalized_authorities_list,
                                         local_authorities: local_authorities.iter(),
-                                        now_from_unix_epoch: SystemTime::now()
-                                            .duration_since(SystemTime::UNIX_EPOCH)
-                                            .unwrap(),
+                                        now_from_unix_epoch,
                                         slot_duration,
                                     },
                                 }),
                                 local_authorities,
+                            )))
+                        }
+                        (
+                            block_authoring @ None,
+                            chain_information::ChainInformationConsensusRef::Babe {
+                                slots_per_epoch,
+                                finalized_block_epoch_information, // TODO: field name not appropriate; these are the epochs of the best block
+                                finalized_next_epoch_transition,
+                            },
+                        ) if self.babe_slot_duration.is_some() => Some(
+                            block_authoring.insert((
+                                author::build::Builder::new(author::build::Config {
+                                    consensus: author::build::ConfigConsensus::Babe {
+                                        now_from_unix_epoch: self.time_source.now_from_unix_epoch(),
+                                        slot_duration: self.babe_slot_duration.unwrap(),
+                                        slots_per_epoch,
+                                        parent_slot_number: self
+                                            .sync
+                                            .best_block_header()
+                                            .digest
+                                            .babe_pre_runtime()
+                                            .map(|pre_digest| pre_digest.slot_number()),
+                                        parent_block_epoch: finalized_block_epoch_information,
+                                        parent_block_next_epoch: finalized_next_epoch_transition,
+                                        local_authorities: local_authorities.iter(),
+                                    },
+                                }),
+                                local_authorities,
                             )),
                         ),
                         (None, chain_information::ChainInformationConsensusRef::Babe { .. }) => {
-                            None // TODO: the block authoring doesn't support Babe at the moment
+                            // The Babe slot duration is unknown, and blocks can't be authored.
+                            None
                         }
                         (None, _) => todo!(),
                     };
 
                 match &block_authoring {
-                    Some((author::build::Builder::Ready(_), _)) => {
-                        future::Either::Left(future::Either::Left(future::ready(Instant::now())))
-                    }
+                    Some((
+                        author::build::Builder::Ready(_) | author::build::Builder::VrfSign(_),
+                        _,
+                    )) => future::Either::Left(future::Either::Left(future::ready(()))),
                     Some((author::build::Builder::WaitSlot(when), _)) => {
-                        let delay = (UNIX_EPOCH + when.when())
-                            .duration_since(SystemTime::now())
-                            .unwrap_or_else(|_| Duration::new(0, 0));
-                        future::Either::Right(future::FutureExt::fuse(smol::Timer::after(delay)))
+                        let delay = when
+                            .when()
+                            .saturating_sub(self.time_source.now_from_unix_epoch());
+                        future::Either::Right(future::FutureExt::fuse(
+                            self.time_source.sleep(delay),
+                        ))
                     }
                     None => future::Either::Left(future::Either::Right(future::pending())),
                     Some((author::build::Builder::Idle, _)) => {
-                        // If the block authoring is idle, which happens in case of error,
-                        // sleep for an arbitrary duration before resetting it.
+                        // If the block authoring is idle, which happens in case of error or if
+                        // none of the local authorities can claim a Babe slot, sleep for an
+                        // arbitrary duration before resetting it.
                         // This prevents the authoring from trying over and over again to generate
                         // a bad block.
                         let delay = Duration::from_secs(2);
-                        future::Either::Right(future::FutureExt::fuse(smol::Timer::after(delay)))
+                        future::Either::Right(future::FutureExt::fuse(
+                            self.time_source.sleep(delay),
+                        ))
                     }
                 }
             };
@@ -553,6 +834,31 @@ impl SyncBackground {
                             self.author_block().await;
                             continue;
                         }
+                        Some((author::build::Builder::VrfSign(vrf_sign), local_authorities)) => {
+                            // Determine whether the local authority can claim the slot. This
+                            // process is fast, and thus the syncing state machine is frozen
+                            // for the duration.
+                            let signature = self.keystore.sign_sr25519_vrf(
+                                keystore::KeyNamespace::Babe,
+                                vrf_sign.public_key(),
+                                vrf_sign.transcript_label(),
+                                vrf_sign.transcript_items(),
+                            ).await;
+                            let builder = match signature {
+                                Ok(signature) => vrf_sign.inject_vrf_output(signature.output, signature.proof),
+                                Err(error) => {
+                                    // Because the keystore is subject to race conditions, it is
+                                    // possible for the key to have been removed in parallel.
+                                    self.log_callback.log(
+                                        LogLevel::Warn,
+                                        format!("vrf-sign-error; error={error}"),
+                                    );
+                                    vrf_sign.skip()
+                                }
+                            };
+                            self.block_authoring = Some((builder, local_authorities));
+                            continue;
+                        }
                         Some((author::build::Builder::Idle, _)) => {
                             self.block_authoring = None;
                             continue;
@@ -574,6 +880,83 @@ impl SyncBackground {
                                 finalized_block_number: self.sync.finalized_block_header().number,
                             });
                         },
+                        Some(ToBackground::SubscribeAll { buffer_size, result_tx }) => {
+                            let (tx, new_blocks) = async_channel::bounded(buffer_size);
+
+                            let finalized_block_scale_encoded_header =
+                                self.sync.finalized_block_header().scale_encoding_vec(self.sync.block_number_bytes());
+                            let finalized_block_hash =
+                                self.sync.finalized_block_header().hash(self.sync.block_number_bytes());
+
+                            let non_finalized_blocks_ancestry_order = {
+                                let best_block_hash = self.sync.best_block_hash();
+                                let mut list = Vec::new();
+                                for block in self.sync.non_finalized_blocks_ancestry_order() {
+                                    let block_hash = block.hash(self.sync.block_number_bytes());
+                                    let NonFinalizedBlock::Verified { runtime } =
+                                        &self.sync[(block.number, &block_hash)]
+                                    else {
+                                        // Blocks that haven't been verified yet aren't in the
+                                        // database and are thus not reported.
+                                        continue;
+                                    };
+                                    let parent_runtime = if *block.parent_hash == finalized_block_hash {
+                                        &self.finalized_runtime
+                                    } else {
+                                        match &self.sync[(block.number - 1, block.parent_hash)] {
+                                            NonFinalizedBlock::Verified { runtime } => runtime,
+                                            _ => unreachable!(),
+                                        }
+                                    };
+
+                                    list.push(BlockNotification {
+                                        is_new_best: block_hash == best_block_hash,
+                                        scale_encoded_header: block.scale_encoding_vec(self.sync.block_number_bytes()),
+                                        block_hash,
+                                        parent_hash: *block.parent_hash,
+                                        new_runtime: if !Arc::ptr_eq(runtime, parent_runtime) {
+                                            Some(runtime.try_lock().unwrap().as_ref().unwrap().runtime_version().clone())
+                                        } else {
+                                            None
+                                        },
+                                    });
+                                }
+                                list
+                            };
+
+                            let finalized_block_runtime = self
+                                .finalized_runtime
+                                .try_lock()
+                                .unwrap()
+                                .as_ref()
+                                .unwrap()
+                                .runtime_version()
+                                .clone();
+
+                            self.blocks_notifications.push(tx);
+                            let _ = result_tx.send(SubscribeAll {
+                                finalized_block_scale_encoded_header,
+                                finalized_block_hash,
+                                finalized_block_runtime,
+                                non_finalized_blocks_ancestry_order,
+                                new_blocks,
+                            });
+                        },
+                        Some(ToBackground::InjectGrandpaJustification { scale_encoded_justification }) => {
+                            match self.sync.inject_justification(
+                                self.block_author_sync_source,
+                                *b"FRNK",
+                                scale_encoded_justification,
+                            ) {
+                                all::InjectJustificationOutcome::Queued => {}
+                                all::InjectJustificationOutcome::Discarded => {
+                                    self.log_callback.log(
+                                        LogLevel::Debug,
+                                        "local-justification-discarded".to_string(),
+                                    );
+                                }
+                            }
+                        },
                         None => {
                             // Shutdown.
                             return
@@ -593,12 +976,13 @@ impl SyncBackground {
                             // However, a source of that `PeerId` might already exist but be
                             // considered as disconnected. If that is the case, we simply mark it
                             // as no longer disconnected.
-                            match self.peers_source_id_map.entry(peer_id) {
+                            let source_id = match self.peers_source_id_map.entry(peer_id.clone()) {
                                 hashbrown::hash_map::Entry::Occupied(entry) => {
                                     let id = *entry.get();
                                     let is_disconnected = &mut self.sync[id].as_mut().unwrap().is_disconnected;
                                     debug_assert!(*is_disconnected);
                                     *is_disconnected = false;
+                                    id
                                 }
                                 hashbrown::hash_map::Entry::Vacant(entry) => {
                                     let id = self.sync.add_source(Some(NetworkSourceInfo {
@@ -606,6 +990,33 @@ impl SyncBackground {
                                         is_disconnected: false,
                                     }), best_block_number, best_block_hash);
                                     entry.insert(id);
+                                    id
+                                }
+                            };
+
+                            // The handshake received by the remote contains the local best block
+                            // at the time when the connection was opened. If the local best block
+                            // has been updated in between, for example because a block has been
+                            // authored while the connection was being opened, the remote isn't
+                            // aware of the new best block and no block announce has been sent to
+                            // it. For this reason, the local best block is announced now.
+                            if best_block_hash != self.sync.best_block_hash() {
+                                let best_block_number = self.sync.best_block_number();
+                                let best_block_hash = self.sync.best_block_hash();
+                                let scale_encoded_header = self.sync.best_block_header()
+                                    .scale_encoding_vec(self.sync.block_number_bytes());
+                                if self
+                                    .network_service
+                                    .clone()
+                                    .send_block_announce(peer_id, self.network_chain_index, scale_encoded_header, true)
+                                    .await
+                                    .is_ok()
+                                {
+                                    self.sync.try_add_known_block_to_source(
+                                        source_id,
+                                        best_block_number,
+                                        best_block_hash,
+                                    );
                                 }
                             }
                         },
@@ -646,7 +1057,8 @@ impl SyncBackground {
                             }
                         },
                         _ => {
-                            // Different chain index.
+                            // Different chain index, or GrandPa votes, which are processed by
+                            // the GrandPa service.
                         }
                     }
                 },
@@ -732,19 +1144,43 @@ impl SyncBackground {
         // Most parts of the block authorship can't be accelerated, in particular the
         // initialization and the signing at the end. This end of authoring threshold is only
         // checked when deciding whether to continue including more transactions in the block.
-        // TODO: use this
         // TODO: Substrate nodes increase the time available for authoring if it detects that slots have been skipped, in order to account for the possibility that the initialization of a block or the inclusion of an extrinsic takes too long
         let authoring_end = {
             let start = authoring_start.slot_start_from_unix_epoch();
             let end = authoring_start.slot_end_from_unix_epoch();
             debug_assert!(start < end);
-            debug_assert!(SystemTime::now() >= SystemTime::UNIX_EPOCH + start);
-            SystemTime::UNIX_EPOCH
-                + start
+            debug_assert!(self.time_source.now_from_unix_epoch() >= start);
+            start
                 + (end - start) * u32::from(self.slot_duration_author_ratio)
                     / u32::from(u16::max_value())
         };
 
+        // Ask for the transactions to include in the block. If no answer is received before the
+        // end of the authoring, the block is authored without any transaction.
+        let mut transactions_to_include = {
+            let mut transactions = Vec::new();
+            if let Some(block_author_transactions) = &self.block_author_transactions {
+                let (result_tx, result_rx) = oneshot::channel();
+                if block_author_transactions
+                    .try_send(BlockAuthorTransactionsRequest {
+                        parent_hash: self.sync.best_block_hash(),
+                        result_tx,
+                    })
+                    .is_ok()
+                {
+                    let timeout =
+                        authoring_end.saturating_sub(self.time_source.now_from_unix_epoch());
+                    transactions =
+                        smol::future::or(async { result_rx.await.unwrap_or_default() }, async {
+                            self.time_source.sleep(timeout).await;
+                            Vec::new()
+                        })
+                        .await;
+                }
+            }
+            transactions.into_iter()
+        };
+
         // Actual block production now happening.
         let (new_block_header, new_block_body, authoring_logs) = {
             let parent_hash = self.sync.best_block_hash();
@@ -768,11 +1204,9 @@ impl SyncBackground {
                     block_number_bytes: self.sync.block_number_bytes(),
                     parent_hash: &self.sync.best_block_hash(),
                     parent_number: self.sync.best_block_number(),
-                    now_from_unix_epoch: SystemTime::now()
-                        .duration_since(SystemTime::UNIX_EPOCH)
-                        .unwrap(),
+                    now_from_unix_epoch: self.time_source.now_from_unix_epoch(),
                     parent_runtime,
-                    block_body_capacity: 0, // TODO: could be set to the size of the tx pool
+                    block_body_capacity: transactions_to_include.len(),
                     max_log_level: 0,
                     calculate_trie_changes: true,
                 })
@@ -787,10 +1221,15 @@ impl SyncBackground {
                         // successful, and the only thing remaining to do is sign the block
                         // header. Signing is done through `self.keystore`.
 
-                        // TODO: correct key namespace
+                        let key_namespace = match self.sync.best_block_consensus() {
+                            chain_information::ChainInformationConsensusRef::Babe { .. } => {
+                                keystore::KeyNamespace::Babe
+                            }
+                            _ => keystore::KeyNamespace::Aura,
+                        };
                         let data_to_sign = seal.to_sign();
                         let sign_future = self.keystore.sign(
-                            keystore::KeyNamespace::Aura,
+                            key_namespace,
                             &local_authorities[seal.authority_index()],
                             &data_to_sign,
                         );
@@ -842,8 +1281,16 @@ impl SyncBackground {
                     // Part of the block production consists in adding transactions to the block.
                     // These transactions are extracted from the transactions pool.
                     author::build::BuilderAuthoring::ApplyExtrinsic(apply) => {
-                        // TODO: actually implement including transactions in the blocks
-                        block_authoring = apply.finish();
+                        // Stop including transactions once the end of the authoring has been
+                        // reached.
+                        block_authoring = match transactions_to_include.next() {
+                            Some(transaction)
+                                if self.time_source.now_from_unix_epoch() < authoring_end =>
+                            {
+                                apply.add_extrinsic(transaction)
+                            }
+                            _ => apply.finish(),
+                        };
                     }
                     author::build::BuilderAuthoring::ApplyExtrinsicResult { result, resume } => {
                         if let Err(error) = result {
@@ -857,8 +1304,14 @@ impl SyncBackground {
                             );
                         }
 
-                        // TODO: actually implement including transactions in the blocks
-                        block_authoring = resume.finish();
+                        block_authoring = match transactions_to_include.next() {
+                            Some(transaction)
+                                if self.time_source.now_from_unix_epoch() < authoring_end =>
+                            {
+                                resume.add_extrinsic(transaction)
+                            }
+                            _ => resume.finish(),
+                        };
                     }
 
                     // Access to the best block storage.
@@ -977,9 +1430,12 @@ impl SyncBackground {
         // or if the runtime code being executed contains a very heavy operation.
         // In any case, there is not much that a node operator can do except try increase the
         // performance of their machine.
-        match authoring_end.elapsed() {
-            Ok(now_minus_end) if now_minus_end < Duration::from_millis(500) => {}
-            _ => {
+        match self
+            .time_source
+            .now_from_unix_epoch()
+            .checked_sub(authoring_end)
+        {
+            Some(now_minus_end) if now_minus_end >= Duration::from_millis(500) => {
                 self.log_callback.log(
                     LogLevel::Warn,
                     format!(
@@ -988,6 +1444,7 @@ impl SyncBackground {
                     ),
                 );
             }
+            _ => {}
         }
 
         // Switch the block authoring to a state where we won't try to generate a new block again
@@ -1172,15 +1629,21 @@ impl SyncBackground {
         }
     }
 
+    /// Sends the given notification to all the subscribers of [`ConsensusService::subscribe_all`].
+    ///
+    /// Subscribers whose channel is full or closed are removed.
+    fn dispatch_notification(&mut self, notification: Notification) {
+        self.blocks_notifications
+            .retain(|sender| sender.try_send(notification.clone()).is_ok());
+    }
+
     async fn process_blocks(mut self) -> (Self, bool) {
         // The sync state machine can be in a few various states. At the time of writing:
         // idle, verifying header, verifying block, verifying grandpa warp sync proof,
         // verifying storage proof.
         // If the state is one of the "verifying" states, perform the actual verification and
         // loop again until the sync is in an idle state.
-        let unix_time = SystemTime::now()
-            .duration_since(SystemTime::UNIX_EPOCH)
-            .unwrap();
+        let unix_time = self.time_source.now_from_unix_epoch();
 
         // TODO: move this?
         let block_number_bytes = self.sync.block_number_bytes();
@@ -1296,12 +1759,17 @@ impl SyncBackground {
                             self.database
                                 .with_database_detached({
                                     let scale_encoded_header = header_verification_success.scale_encoded_header().to_vec();
+                                    let scale_encoded_extrinsics = header_verification_success
+                                        .scale_encoded_extrinsics()
+                                        .unwrap()
+                                        .map(|ext| ext.as_ref().to_vec())
+                                        .collect::<Vec<_>>();
                                     move |database| {
                                         // TODO: overhead for building the SCALE encoding of the header
                                         let result = database.insert(
                                             &scale_encoded_header,
                                             is_new_best,
-                                            iter::empty::<Vec<u8>>(), // TODO:,no /!\
+                                            scale_encoded_extrinsics.into_iter(),
                                             storage_changes.trie_changes_iter_ordered().unwrap().filter_map(
                                                 |(_child_trie, key, change)| {
                                                     let body_only::TrieChange::InsertUpdate {
@@ -1386,6 +1854,8 @@ impl SyncBackground {
                                 header_verification_success.finish(NonFinalizedBlock::NotVerified);
 
                             // Store the storage of the children.
+                            let new_runtime_version =
+                                new_runtime.as_ref().map(|r| r.runtime_version().clone());
                             self.sync[(height, &hash_to_verify)] = NonFinalizedBlock::Verified {
                                 runtime: if let Some(new_runtime) = new_runtime {
                                     Arc::new(Mutex::new(Some(new_runtime)))
@@ -1394,6 +1864,16 @@ impl SyncBackground {
                                 },
                             };
 
+                            // Notify the subscribers. This is done only now that the block has
+                            // been inserted in the database.
+                            self.dispatch_notification(Notification::Block(BlockNotification {
+                                is_new_best,
+                                scale_encoded_header: scale_encoded_header.clone(),
+                                block_hash: hash_to_verify,
+                                parent_hash,
+                                new_runtime: new_runtime_version,
+                            }));
+
                             if is_new_best {
                                 // Update the networking.
                                 let fut = self.network_service.set_local_best_block(
@@ -1616,12 +2096,33 @@ impl SyncBackground {
                         self.finalized_runtime = runtime;
                         let new_finalized_hash =
                             finalized_block.header.hash(self.sync.block_number_bytes());
+                        // The GrandPa justification is stored in the database in order to be
+                        // able to later serve warp sync requests.
+                        let grandpa_justification = finalized_block
+                            .justifications
+                            .into_iter()
+                            .find(|(engine_id, _)| engine_id == b"FRNK")
+                            .map(|(_, justification)| justification);
                         // TODO: what if best block changed?
                         self.database
                             .with_database_detached(move |database| {
                                 database.set_finalized(&new_finalized_hash).unwrap();
+                                if let Some(grandpa_justification) = grandpa_justification {
+                                    database
+                                        .set_block_justification(
+                                            &new_finalized_hash,
+                                            &grandpa_justification,
+                                        )
+                                        .unwrap();
+                                }
                             })
                             .await;
+
+                        self.dispatch_notification(Notification::Finalized {
+                            hash: new_finalized_hash,
+                            best_block_hash: self.sync.best_block_hash(),
+                        });
+
                         (self, true)
                     }
                     (sync_out, all::FinalityProofVerifyOutcome::GrandpaCommitPending) => {
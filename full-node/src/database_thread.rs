@@ -20,8 +20,8 @@
 
 use futures_channel::oneshot;
 use smol::{channel, lock::Mutex, stream::StreamExt as _};
-use smoldot::database::full_sqlite::SqliteFullDatabase;
-use std::thread;
+use smoldot::{database::full_sqlite::SqliteFullDatabase, executor::vm};
+use std::{sync::Arc, thread};
 
 /// Handle to the thread were the database accesses are performed.
 ///
@@ -30,6 +30,9 @@ use std::thread;
 /// Use the `From` trait implementation to build a [`DatabaseThread`].
 pub struct DatabaseThread {
     sender: Mutex<channel::Sender<Exec>>,
+
+    /// See [`DatabaseThread::compiled_runtimes_cache`].
+    compiled_runtimes_cache: Option<Arc<dyn vm::CompiledModuleCache>>,
 }
 
 type Exec = Box<dyn FnOnce(&SqliteFullDatabase) + Send>;
@@ -68,6 +71,24 @@ impl DatabaseThread {
             .await
             .unwrap();
     }
+
+    /// Sets the cache returned by [`DatabaseThread::compiled_runtimes_cache`].
+    pub fn with_compiled_runtimes_cache(
+        mut self,
+        cache: Option<Arc<dyn vm::CompiledModuleCache>>,
+    ) -> DatabaseThread {
+        self.compiled_runtimes_cache = cache;
+        self
+    }
+
+    /// Returns the cache where the runtimes of the blocks of this database are stored after
+    /// having been compiled, if any.
+    ///
+    /// Must be passed as [`smoldot::executor::host::Config::compiled_module_cache`] when
+    /// compiling a runtime ahead of time.
+    pub fn compiled_runtimes_cache(&self) -> Option<Arc<dyn vm::CompiledModuleCache>> {
+        self.compiled_runtimes_cache.clone()
+    }
 }
 
 impl From<SqliteFullDatabase> for DatabaseThread {
@@ -87,6 +108,7 @@ impl From<SqliteFullDatabase> for DatabaseThread {
 
         DatabaseThread {
             sender: Mutex::new(sender),
+            compiled_runtimes_cache: None,
         }
     }
 }
@@ -0,0 +1,798 @@
+// Smoldot
+// Copyright (C) 2023  Pierre Krieger
+// SPDX-License-Identifier: GPL-3.0-or-later WITH Classpath-exception-2.0
+
+// This program is free software: you can redistribute it and/or modify
+// it under the terms of the GNU General Public License as published by
+// the Free Software Foundation, either version 3 of the License, or
+// (at your option) any later version.
+
+// This program is distributed in the hope that it will be useful,
+// but WITHOUT ANY WARRANTY; without even the implied warranty of
+// MERCHANTABILITY or FITNESS FOR A PARTICULAR PURPOSE.  See the
+// GNU General Public License for more details.
+
+// You should have received a copy of the GNU General Public License
+// along with this program.  If not, see <http://www.gnu.org/licenses/>.
+
+//! Background GrandPa service.
+//!
+//! The [`GrandpaService`] runs a [`voter::GrandpaVoter`] that follows the blocks verified by the
+//! [`consensus_service::ConsensusService`] and the GrandPa votes received from the network.
+//!
+//! If the keystore contains the key of one of the authorities of the current authorities set,
+//! the service signs and gossips votes on behalf of this authority. Whenever a round finalizes
+//! a block, the corresponding justification is injected into the consensus service and the
+//! commit is sent to the peers of the network.
+//!
+//! Before the local authority votes, the identifier of the authorities set and the number of the
+//! round are stored in the database. When the node restarts, the voter starts at the round
+//! following the one stored in the database, in order to never vote twice in the same round.
+//! When the authorities set changes, the voter starts again at round 1 from the latest finalized
+//! block.
+//!
+//! Whenever a peer reports through a neighbor packet that it is ahead of the local node, a catch
+//! up request is sent to it. The precommits found in the catch up responses and in the commit
+//! messages received from the network are used to skip directly to the round the other
+//! authorities are in.
+
+use crate::{
+    consensus_service, database_thread, network_service, LogCallback, LogLevel, TimeSource,
+};
+
+use futures_lite::FutureExt as _;
+use futures_util::{stream, StreamExt as _};
+use smoldot::{
+    chain::chain_information,
+    database::full_sqlite,
+    finality::grandpa::voter,
+    header,
+    identity::keystore,
+    informant::HashDisplay,
+    libp2p::PeerId,
+    network::{protocol, service},
+};
+use std::{
+    future::Future,
+    pin::Pin,
+    sync::Arc,
+    time::{Duration, Instant},
+};
+
+/// Number of rounds a peer must be ahead of the local node before a catch up request is sent
+/// to it.
+const CATCH_UP_THRESHOLD: u64 = 2;
+
+/// Configuration for a [`GrandpaService`].
+pub struct Config {
+    /// Function that can be used to spawn background tasks.
+    ///
+    /// The tasks passed as parameter must be executed until they shut down.
+    pub tasks_executor: Arc<dyn Fn(Pin<Box<dyn Future<Output = ()> + Send>>) + Send + Sync>,
+
+    /// Function called in order to notify of something.
+    pub log_callback: Arc<dyn LogCallback + Send + Sync>,
+
+    /// Source of the time used to determine when rounds time out.
+    pub time_source: Arc<dyn TimeSource + Send + Sync>,
+
+    /// Consensus service of the chain. Used to follow the blocks of the chain and to finalize
+    /// blocks.
+    pub consensus_service: Arc<consensus_service::ConsensusService>,
+
+    /// Access to the network, and index of the chain to use to gossip votes from the point of
+    /// view of the network service.
+    pub network_service: (Arc<network_service::NetworkService>, usize),
+
+    /// Receiver for events coming from the network, as returned by
+    /// [`network_service::NetworkService::new`].
+    pub network_events_receiver: stream::BoxStream<'static, network_service::Event>,
+
+    /// Database to access blocks and the authorities sets.
+    pub database: Arc<database_thread::DatabaseThread>,
+
+    /// Keystore containing the Ed25519 keys of the local authority, if any.
+    pub keystore: Arc<keystore::Keystore>,
+
+    /// Number of bytes used to encode the block number in headers.
+    pub block_number_bytes: usize,
+
+    /// Expected maximum duration for a message to reach all the other authorities.
+    pub gossip_duration: Duration,
+}
+
+/// See [the module-level documentation](..).
+pub struct GrandpaService {
+    /// The background task stops when this sender is dropped.
+    _keep_alive: async_channel::Sender<()>,
+}
+
+impl GrandpaService {
+    /// Builds a new service.
+    pub async fn new(config: Config) -> Arc<Self> {
+        let (keep_alive, from_foreground) = async_channel::bounded(1);
+
+        let background = Background {
+            log_callback: config.log_callback,
+            time_source: config.time_source,
+            consensus_service: config.consensus_service,
+            network_service: config.network_service.0,
+            network_chain_index: config.network_service.1,
+            network_events_receiver: config.network_events_receiver,
+            database: config.database,
+            keystore: config.keystore,
+            block_number_bytes: config.block_number_bytes,
+            gossip_duration: config.gossip_duration,
+            from_foreground,
+            voter: None,
+            reported_round: None,
+            catch_up_requested: None,
+        };
+
+        (config.tasks_executor)(Box::pin(background.run()));
+
+        Arc::new(GrandpaService {
+            _keep_alive: keep_alive,
+        })
+    }
+}
+
+struct Background {
+    log_callback: Arc<dyn LogCallback + Send + Sync>,
+    time_source: Arc<dyn TimeSource + Send + Sync>,
+    consensus_service: Arc<consensus_service::ConsensusService>,
+    network_service: Arc<network_service::NetworkService>,
+    network_chain_index: usize,
+    network_events_receiver: stream::BoxStream<'static, network_service::Event>,
+    database: Arc<database_thread::DatabaseThread>,
+    keystore: Arc<keystore::Keystore>,
+    block_number_bytes: usize,
+    gossip_duration: Duration,
+
+    /// Channel whose sender is held by the [`GrandpaService`]. Closed when the service is
+    /// destroyed.
+    from_foreground: async_channel::Receiver<()>,
+
+    /// The voter, or `None` if we aren't subscribed to the consensus service or if the chain
+    /// doesn't use GrandPa.
+    voter: Option<voter::GrandpaVoter<Instant>>,
+
+    /// Set id and round number that have last been reported to the network service. Used to
+    /// avoid sending neighbor packets when nothing has changed.
+    reported_round: Option<(u64, u64)>,
+
+    /// Set id and round number of the latest catch up request that has been sent out. Used to
+    /// avoid sending the same request to every peer that reports being ahead of us.
+    catch_up_requested: Option<(u64, u64)>,
+}
+
+impl Background {
+    async fn run(mut self) {
+        // Receiver of the notifications about the blocks of the chain, or `None` if we aren't
+        // subscribed.
+        let mut new_blocks: Option<async_channel::Receiver<consensus_service::Notification>> = None;
+
+        loop {
+            // Subscribe to the blocks of the chain. This is done again whenever the subscription
+            // is lost or when the authorities set changes.
+            // The consensus service never waits for the GrandPa service, and the subscription
+            // can thus be performed in a blocking way.
+            if new_blocks.is_none() {
+                let subscription = self.consensus_service.subscribe_all(32).await;
+                new_blocks = Some(subscription.new_blocks);
+                self.reset(
+                    subscription.finalized_block_hash,
+                    subscription.finalized_block_scale_encoded_header,
+                )
+                .await;
+                for block in subscription.non_finalized_blocks_ancestry_order {
+                    self.on_new_block(&block);
+                }
+            }
+
+            self.process_actions().await;
+
+            enum WhatHappened {
+                SubscriptionDead,
+                Notification(consensus_service::Notification),
+                NetworkEvent(network_service::Event),
+                WakeUp,
+                ForegroundClosed,
+            }
+
+            let outcome: WhatHappened = {
+                let next_block = async {
+                    match new_blocks.as_ref().unwrap().recv().await {
+                        Ok(n) => WhatHappened::Notification(n),
+                        Err(_) => WhatHappened::SubscriptionDead,
+                    }
+                };
+
+                let network_event = async {
+                    // We expect the network events channel to never shut down.
+                    WhatHappened::NetworkEvent(self.network_events_receiver.next().await.unwrap())
+                };
+
+                let wake_up = async {
+                    match self.voter.as_ref().and_then(|v| v.wake_up_after()) {
+                        Some(when) => {
+                            self.time_source.sleep_until(when).await;
+                            WhatHappened::WakeUp
+                        }
+                        None => smol::future::pending().await,
+                    }
+                };
+
+                let foreground_closed = async {
+                    let _ = self.from_foreground.recv().await;
+                    WhatHappened::ForegroundClosed
+                };
+
+                next_block
+                    .or(network_event)
+                    .or(wake_up)
+                    .or(foreground_closed)
+                    .await
+            };
+
+            match outcome {
+                WhatHappened::ForegroundClosed => return,
+                WhatHappened::WakeUp => {}
+                WhatHappened::SubscriptionDead => {
+                    self.log_callback.log(
+                        LogLevel::Warn,
+                        "grandpa-service-blocks-subscription-lost".to_string(),
+                    );
+                    new_blocks = None;
+                }
+                WhatHappened::Notification(consensus_service::Notification::Block(block)) => {
+                    self.on_new_block(&block);
+                }
+                WhatHappened::Notification(consensus_service::Notification::Finalized {
+                    hash,
+                    best_block_hash,
+                }) => {
+                    if !self.on_finalized(hash, best_block_hash).await {
+                        // The authorities set has changed. Subscribing again resets the voter
+                        // with the new authorities.
+                        new_blocks = None;
+                    }
+                }
+                WhatHappened::NetworkEvent(network_service::Event::GrandpaVote {
+                    chain_index,
+                    peer_id,
+                    message,
+                }) if chain_index == self.network_chain_index => {
+                    self.on_vote_message(peer_id, message).await;
+                }
+                WhatHappened::NetworkEvent(network_service::Event::GrandpaNeighborPacket {
+                    chain_index,
+                    peer_id,
+                    state,
+                }) if chain_index == self.network_chain_index => {
+                    self.on_neighbor_packet(peer_id, state).await;
+                }
+                WhatHappened::NetworkEvent(network_service::Event::GrandpaCommit {
+                    chain_index,
+                    peer_id,
+                    message,
+                }) if chain_index == self.network_chain_index => {
+                    let message = message.decode();
+                    let precommits = message
+                        .message
+                        .precommits
+                        .iter()
+                        .zip(message.message.auth_data.iter())
+                        .map(
+                            |(precommit, (signature, public_key))| voter::SignedPrecommit {
+                                target_hash: *precommit.target_hash,
+                                target_number: precommit.target_number,
+                                signature: **signature,
+                                authority_public_key: **public_key,
+                            },
+                        )
+                        .collect::<Vec<_>>();
+                    self.on_completed_round(
+                        peer_id,
+                        message.round_number,
+                        message.set_id,
+                        precommits,
+                    );
+                }
+                WhatHappened::NetworkEvent(network_service::Event::GrandpaCatchUp {
+                    chain_index,
+                    peer_id,
+                    message,
+                }) if chain_index == self.network_chain_index => {
+                    let message = message.decode();
+                    let precommits = message
+                        .precommits
+                        .iter()
+                        .map(|precommit| voter::SignedPrecommit {
+                            target_hash: *precommit.target_hash,
+                            target_number: precommit.target_number,
+                            signature: *precommit.signature,
+                            authority_public_key: *precommit.authority_public_key,
+                        })
+                        .collect::<Vec<_>>();
+                    self.on_completed_round(
+                        peer_id,
+                        message.round_number,
+                        message.set_id,
+                        precommits,
+                    );
+                }
+                WhatHappened::NetworkEvent(network_service::Event::GrandpaCatchUpRequest {
+                    chain_index,
+                    peer_id,
+                    request,
+                }) if chain_index == self.network_chain_index => {
+                    self.on_catch_up_request(peer_id, request).await;
+                }
+                WhatHappened::NetworkEvent(_) => {
+                    // Different chain index, or events not related to GrandPa.
+                }
+            }
+        }
+    }
+
+    /// Rebuilds the voter from scratch, using the given block as the finalized block.
+    async fn reset(&mut self, finalized_block_hash: [u8; 32], finalized_block_header: Vec<u8>) {
+        self.voter = None;
+        self.catch_up_requested = None;
+
+        let finalized_block_number =
+            match header::decode(&finalized_block_header, self.block_number_bytes) {
+                Ok(h) => h.number,
+                Err(_) => unreachable!(),
+            };
+
+        let Some((set_id, authorities)) =
+            authorities_set(&self.database, &*self.log_callback, finalized_block_hash).await
+        else {
+            return;
+        };
+
+        // Find whether the keystore contains the key of one of the authorities.
+        let local_authority = self
+            .keystore
+            .keys()
+            .await
+            .filter(|(namespace, _)| *namespace == keystore::KeyNamespace::Grandpa)
+            .map(|(_, public_key)| public_key)
+            .find(|public_key| authorities.iter().any(|a| a.public_key == *public_key));
+
+        // If the local authority has already voted during this authorities set, for example
+        // before the node restarted, start at the round after the one it has voted in.
+        let last_voted_round = self
+            .database
+            .with_database(|database| database.grandpa_last_voted_round())
+            .await;
+        let round_number = match last_voted_round {
+            Ok(Some((last_set_id, last_round))) if last_set_id == set_id => last_round + 1,
+            Ok(_) => 1,
+            Err(error) => {
+                // Starting at round 1 could lead to an equivocation. Better not vote at all.
+                self.log_callback.log(
+                    LogLevel::Warn,
+                    format!("grandpa-service-database-error; error={}", error),
+                );
+                return;
+            }
+        };
+
+        self.log_callback.log(
+            LogLevel::Debug,
+            format!(
+                "grandpa-voter-reset; set_id={}; round_number={}; num_authorities={}; \
+                finalized_block_hash={}; is_authority={:?}",
+                set_id,
+                round_number,
+                authorities.len(),
+                HashDisplay(&finalized_block_hash),
+                local_authority.is_some()
+            ),
+        );
+
+        self.voter = Some(voter::GrandpaVoter::new(voter::Config {
+            block_number_bytes: self.block_number_bytes,
+            set_id,
+            authorities,
+            local_authority,
+            finalized_block_hash,
+            finalized_block_number,
+            round_number,
+            gossip_duration: self.gossip_duration,
+            now: self.time_source.now(),
+        }));
+    }
+
+    /// Called when the consensus service reports a new block.
+    fn on_new_block(&mut self, block: &consensus_service::BlockNotification) {
+        let Some(voter) = &mut self.voter else { return };
+
+        // Blocks reported by the consensus service have already been verified and their header
+        // is thus always valid.
+        voter.add_block(&block.scale_encoded_header).unwrap();
+        if block.is_new_best {
+            voter.set_best_block(&block.block_hash);
+        }
+    }
+
+    /// Called when the consensus service reports a new finalized block.
+    ///
+    /// Returns `false` if the voter must be rebuilt, for example because the authorities set has
+    /// changed.
+    async fn on_finalized(&mut self, hash: [u8; 32], best_block_hash: [u8; 32]) -> bool {
+        if self.voter.is_none() {
+            return true;
+        }
+
+        let new_set_id = authorities_set(&self.database, &*self.log_callback, hash)
+            .await
+            .map(|(set_id, _)| set_id);
+
+        let voter = self.voter.as_mut().unwrap();
+        if new_set_id != Some(voter.set_id()) {
+            return false;
+        }
+
+        if let Err(error) = voter.set_finalized_block(&hash) {
+            // The voter has missed a block notification. Rebuilding it from the consensus
+            // service brings it back in sync.
+            self.log_callback.log(
+                LogLevel::Warn,
+                format!(
+                    "grandpa-voter-finalize-error; hash={}; error={}",
+                    HashDisplay(&hash),
+                    error
+                ),
+            );
+            return false;
+        }
+        voter.set_best_block(&best_block_hash);
+        true
+    }
+
+    /// Called when a GrandPa vote is received from the network.
+    async fn on_vote_message(
+        &mut self,
+        peer_id: PeerId,
+        message: service::EncodedGrandpaVoteMessage,
+    ) {
+        let Some(voter) = &mut self.voter else { return };
+
+        let decoded = message.decode();
+        let (kind, target_hash, target_number) = match decoded.message {
+            protocol::MessageRef::Prevote(v) => {
+                (voter::VoteKind::Prevote, v.target_hash, v.target_number)
+            }
+            protocol::MessageRef::Precommit(v) => {
+                (voter::VoteKind::Precommit, v.target_hash, v.target_number)
+            }
+            // Primary proposals are only a hint and can be ignored.
+            protocol::MessageRef::PrimaryPropose(_) => return,
+        };
+
+        let vote = voter::SignedVote {
+            round_number: decoded.round_number,
+            set_id: decoded.set_id,
+            kind,
+            target_hash: *target_hash,
+            target_number,
+            signature: *decoded.signature,
+            authority_public_key: *decoded.authority_public_key,
+        };
+
+        match voter.inject_vote(&vote) {
+            Ok(voter::InjectVoteOutcome::Accepted) => {
+                // Votes are gossiped to the other peers the first time they are seen.
+                self.network_service
+                    .broadcast_grandpa_vote(self.network_chain_index, vote)
+                    .await;
+            }
+            Ok(voter::InjectVoteOutcome::Duplicate) => {}
+            Ok(voter::InjectVoteOutcome::Equivocation {
+                first_target,
+                second_target,
+            }) => {
+                self.log_callback.log(
+                    LogLevel::Warn,
+                    format!(
+                        "grandpa-equivocation; authority={}; round_number={}; \
+                        first_target={}; second_target={}",
+                        HashDisplay(&vote.authority_public_key),
+                        vote.round_number,
+                        HashDisplay(&first_target.0),
+                        HashDisplay(&second_target.0)
+                    ),
+                );
+            }
+            Err(error) => {
+                self.log_callback.log(
+                    LogLevel::Debug,
+                    format!(
+                        "grandpa-vote-discarded; peer_id={}; round_number={}; error={}",
+                        peer_id, vote.round_number, error
+                    ),
+                );
+            }
+        }
+    }
+
+    /// Called when a GrandPa neighbor packet is received from the network.
+    async fn on_neighbor_packet(&mut self, peer_id: PeerId, state: service::GrandpaState) {
+        let Some(voter) = &self.voter else { return };
+
+        // Only authorities need to be in the same round as the other authorities.
+        if !voter.is_authority()
+            || state.set_id != voter.set_id()
+            || state.round_number < voter.round_number() + CATCH_UP_THRESHOLD
+        {
+            return;
+        }
+
+        let request = (state.set_id, state.round_number);
+        if self.catch_up_requested.map_or(false, |(set_id, round)| {
+            set_id == request.0 && round >= request.1
+        }) {
+            return;
+        }
+
+        self.log_callback.log(
+            LogLevel::Debug,
+            format!(
+                "grandpa-catch-up-request; peer_id={}; local_round_number={}; \
+                remote_round_number={}",
+                peer_id,
+                voter.round_number(),
+                state.round_number
+            ),
+        );
+
+        let result = self
+            .network_service
+            .send_grandpa_catch_up_request(
+                peer_id,
+                self.network_chain_index,
+                // The remote can only provide the votes of the rounds it has completed.
+                protocol::CatchUpRequest {
+                    round_number: state.round_number - 1,
+                    set_id: state.set_id,
+                },
+            )
+            .await;
+        if result.is_ok() {
+            self.catch_up_requested = Some(request);
+        }
+    }
+
+    /// Called when a GrandPa catch up request is received from the network.
+    async fn on_catch_up_request(&mut self, peer_id: PeerId, request: protocol::CatchUpRequest) {
+        let Some(voter) = &self.voter else { return };
+
+        let Some(catch_up) = voter.catch_up(request.round_number, request.set_id) else {
+            self.log_callback.log(
+                LogLevel::Debug,
+                format!(
+                    "grandpa-catch-up-request-ignored; peer_id={}; set_id={}; round_number={}",
+                    peer_id, request.set_id, request.round_number
+                ),
+            );
+            return;
+        };
+
+        self.log_callback.log(
+            LogLevel::Debug,
+            format!(
+                "grandpa-catch-up-response; peer_id={}; round_number={}",
+                peer_id, catch_up.round_number
+            ),
+        );
+
+        // Failing to send the response means that the peer has disconnected in the meanwhile,
+        // which is fine.
+        let _ = self
+            .network_service
+            .send_grandpa_catch_up(peer_id, self.network_chain_index, catch_up)
+            .await;
+    }
+
+    /// Called when the precommits of a completed round are received from the network, either in
+    /// a commit message or in a catch up response.
+    fn on_completed_round(
+        &mut self,
+        peer_id: PeerId,
+        round_number: u64,
+        set_id: u64,
+        precommits: Vec<voter::SignedPrecommit>,
+    ) {
+        let Some(voter) = &mut self.voter else { return };
+
+        match voter.inject_completed_round(
+            round_number,
+            set_id,
+            precommits.into_iter(),
+            self.time_source.now(),
+        ) {
+            Ok(false) => {}
+            Ok(true) => {
+                self.log_callback.log(
+                    LogLevel::Debug,
+                    format!(
+                        "grandpa-catch-up; peer_id={}; round_number={}",
+                        peer_id,
+                        voter.round_number()
+                    ),
+                );
+            }
+            Err(error) => {
+                self.log_callback.log(
+                    LogLevel::Debug,
+                    format!(
+                        "grandpa-completed-round-discarded; peer_id={}; round_number={}; \
+                        error={}",
+                        peer_id, round_number, error
+                    ),
+                );
+            }
+        }
+    }
+
+    /// Performs all the actions requested by the voter.
+    async fn process_actions(&mut self) {
+        loop {
+            let Some(voter) = &mut self.voter else { return };
+
+            let round = (voter.set_id(), voter.round_number());
+            if self.reported_round != Some(round) {
+                self.reported_round = Some(round);
+                let grandpa_state = service::GrandpaState {
+                    round_number: voter.round_number(),
+                    set_id: voter.set_id(),
+                    commit_finalized_height: voter.finalized_block_number(),
+                };
+                self.network_service
+                    .set_local_grandpa_state(self.network_chain_index, grandpa_state)
+                    .await;
+            }
+
+            let voter = self.voter.as_mut().unwrap();
+            match voter.next_action(&self.time_source.now()) {
+                None => return,
+                Some(voter::Action::SignVote(sign_vote)) => {
+                    // The round is stored in the database before the vote is signed, so that
+                    // the node doesn't vote a second time in this round after a restart.
+                    let set_id = voter.set_id();
+                    let round_number = sign_vote.round_number();
+                    let persisted = self
+                        .database
+                        .with_database(move |database| {
+                            database.set_grandpa_last_voted_round(set_id, round_number)
+                        })
+                        .await;
+                    if let Err(error) = persisted {
+                        self.log_callback.log(
+                            LogLevel::Warn,
+                            format!("grandpa-service-database-error; error={}", error),
+                        );
+                        continue;
+                    }
+
+                    let signature = self
+                        .keystore
+                        .sign(
+                            keystore::KeyNamespace::Grandpa,
+                            sign_vote.authority_public_key(),
+                            sign_vote.to_sign(),
+                        )
+                        .await;
+
+                    let signature = match signature {
+                        Ok(s) => s,
+                        Err(error) => {
+                            // Because the keystore is subject to race conditions, it is
+                            // possible for the key to have been removed in parallel.
+                            self.log_callback.log(
+                                LogLevel::Warn,
+                                format!("grandpa-vote-sign-error; error={error}"),
+                            );
+                            continue;
+                        }
+                    };
+
+                    let (target_hash, target_number) = sign_vote.target();
+                    self.log_callback.log(
+                        LogLevel::Debug,
+                        format!(
+                            "grandpa-vote-emitted; kind={:?}; round_number={}; target_hash={}; \
+                            target_number={}",
+                            sign_vote.kind(),
+                            sign_vote.round_number(),
+                            HashDisplay(target_hash),
+                            target_number
+                        ),
+                    );
+
+                    let vote = self
+                        .voter
+                        .as_mut()
+                        .unwrap()
+                        .inject_local_signature(sign_vote, signature);
+                    self.network_service
+                        .broadcast_grandpa_vote(self.network_chain_index, vote)
+                        .await;
+                }
+                Some(voter::Action::Finalized {
+                    completed_round,
+                    commit,
+                }) => {
+                    self.log_callback.log(
+                        LogLevel::Debug,
+                        format!(
+                            "grandpa-round-finalized; round_number={}; target_hash={}; \
+                            target_number={}",
+                            completed_round,
+                            HashDisplay(&commit.target_hash),
+                            commit.target_number
+                        ),
+                    );
+
+                    // The voter will be notified of the finalization through the consensus
+                    // service once the justification has been verified.
+                    self.consensus_service
+                        .inject_grandpa_justification(
+                            commit.scale_encoded_justification(self.block_number_bytes),
+                        )
+                        .await;
+                    self.network_service
+                        .broadcast_grandpa_commit(self.network_chain_index, commit)
+                        .await;
+                }
+            }
+        }
+    }
+}
+
+/// Reads from the database the identifier and list of authorities of the authorities set
+/// in charge of finalizing the children of the given block.
+///
+/// Returns `None` if the chain doesn't use GrandPa.
+async fn authorities_set(
+    database: &database_thread::DatabaseThread,
+    log_callback: &(dyn LogCallback + Send + Sync),
+    block_hash: [u8; 32],
+) -> Option<(u64, Vec<header::GrandpaAuthority>)> {
+    let chain_information = database
+        .with_database(move |database: &full_sqlite::SqliteFullDatabase| {
+            database.to_chain_information(&block_hash)
+        })
+        .await;
+
+    let chain_information = match chain_information {
+        Ok(info) => info,
+        Err(error) => {
+            log_callback.log(
+                LogLevel::Warn,
+                format!(
+                    "grandpa-service-database-error; block_hash={}; error={}",
+                    HashDisplay(&block_hash),
+                    error
+                ),
+            );
+            return None;
+        }
+    };
+
+    match chain_information.as_ref().finality {
+        chain_information::ChainInformationFinalityRef::Grandpa {
+            after_finalized_block_authorities_set_id,
+            finalized_triggered_authorities,
+            ..
+        } => Some((
+            after_finalized_block_authorities_set_id,
+            finalized_triggered_authorities.to_vec(),
+        )),
+        chain_information::ChainInformationFinalityRef::Outsourced => None,
+    }
+}
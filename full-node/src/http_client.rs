@@ -0,0 +1,389 @@
+// Smoldot
+// Copyright (C) 2023  Pierre Krieger
+// SPDX-License-Identifier: GPL-3.0-or-later WITH Classpath-exception-2.0
+
+// This program is free software: you can redistribute it and/or modify
+// it under the terms of the GNU General Public License as published by
+// the Free Software Foundation, either version 3 of the License, or
+// (at your option) any later version.
+
+// This program is distributed in the hope that it will be useful,
+// but WITHOUT ANY WARRANTY; without even the implied warranty of
+// MERCHANTABILITY or FITNESS FOR A PARTICULAR PURPOSE.  See the
+// GNU General Public License for more details.
+
+// You should have received a copy of the GNU General Public License
+// along with this program.  If not, see <http://www.gnu.org/licenses/>.
+
+//! HTTP requests performed by the off-chain workers.
+//!
+//! The [`OffchainHttpRequests`] keeps track of the HTTP requests started by the runtime during
+//! one off-chain worker call, and sends them through a [`HttpClient`] once they are complete.
+//!
+//! The [`DefaultHttpClient`] is used when the API user doesn't provide its own [`HttpClient`].
+
+use crate::{HttpClient, HttpRequest, HttpResponse, TimeSource};
+
+use futures_lite::FutureExt as _;
+use futures_util::future;
+use smol::io::{AsyncReadExt as _, AsyncWriteExt as _};
+use smoldot::executor::runtime_host::{HttpError, HttpRequestStatus};
+use std::{future::Future, sync::Arc, time::Duration};
+
+/// Implementation of [`HttpClient`] that sends HTTP/1.1 requests over plain-text TCP
+/// connections.
+///
+/// Only `http://` URIs are supported. Requests towards other URIs fail with an I/O error.
+///
+/// Responses, including their headers, larger than [`MAX_RESPONSE_SIZE`] bytes also fail with an
+/// I/O error.
+pub struct DefaultHttpClient;
+
+/// Maximum size, in bytes, of a response received by the [`DefaultHttpClient`].
+const MAX_RESPONSE_SIZE: usize = 16 * 1024 * 1024;
+
+impl HttpClient for DefaultHttpClient {
+    fn request(
+        &self,
+        request: HttpRequest,
+    ) -> future::BoxFuture<'static, Result<HttpResponse, ()>> {
+        Box::pin(perform_request(request))
+    }
+}
+
+async fn perform_request(request: HttpRequest) -> Result<HttpResponse, ()> {
+    let rest = request.uri.strip_prefix("http://").ok_or(())?;
+    let (authority, path) = match rest.find('/') {
+        Some(pos) => (&rest[..pos], &rest[pos..]),
+        None => (rest, "/"),
+    };
+    let (host, port) = match authority.rsplit_once(':') {
+        Some((host, port)) => (host, port.parse::<u16>().map_err(|_| ())?),
+        None => (authority, 80),
+    };
+
+    let mut socket = smol::net::TcpStream::connect((host, port))
+        .await
+        .map_err(|_| ())?;
+
+    // The connection is closed by the server after the response, which lets us read the
+    // response until EOF.
+    let mut request_header = format!(
+        "{} {} HTTP/1.1\r\nHost: {}\r\nConnection: close\r\nContent-Length: {}\r\n",
+        request.method,
+        path,
+        authority,
+        request.body.len()
+    );
+    for (name, value) in &request.headers {
+        if ["host", "connection", "content-length"]
+            .iter()
+            .any(|h| name.eq_ignore_ascii_case(h))
+        {
+            continue;
+        }
+        request_header.push_str(&format!("{name}: {value}\r\n"));
+    }
+    request_header.push_str("\r\n");
+
+    socket
+        .write_all(request_header.as_bytes())
+        .await
+        .map_err(|_| ())?;
+    socket.write_all(&request.body).await.map_err(|_| ())?;
+    socket.flush().await.map_err(|_| ())?;
+
+    // One byte more than the limit is read in order to detect responses that are too large.
+    let mut response = Vec::new();
+    (&mut socket)
+        .take(u64::try_from(MAX_RESPONSE_SIZE).unwrap() + 1)
+        .read_to_end(&mut response)
+        .await
+        .map_err(|_| ())?;
+    if response.len() > MAX_RESPONSE_SIZE {
+        return Err(());
+    }
+    parse_response(&response)
+}
+
+/// Parses an HTTP/1.1 response whose body ends at the end of the data.
+fn parse_response(data: &[u8]) -> Result<HttpResponse, ()> {
+    let mut headers = [httparse::EMPTY_HEADER; 64];
+    let mut response = httparse::Response::new(&mut headers);
+    let httparse::Status::Complete(body_offset) = response.parse(data).map_err(|_| ())? else {
+        return Err(());
+    };
+
+    let status_code = response.code.ok_or(())?;
+    let headers = response
+        .headers
+        .iter()
+        .map(|h| {
+            (
+                h.name.to_owned(),
+                String::from_utf8_lossy(h.value).into_owned(),
+            )
+        })
+        .collect::<Vec<_>>();
+
+    let is_chunked = headers.iter().any(|(name, value)| {
+        name.eq_ignore_ascii_case("transfer-encoding") && value.eq_ignore_ascii_case("chunked")
+    });
+    let content_length = headers
+        .iter()
+        .find(|(name, _)| name.eq_ignore_ascii_case("content-length"))
+        .and_then(|(_, value)| value.trim().parse::<usize>().ok());
+
+    let mut body = &data[body_offset..];
+    let body = if is_chunked {
+        let mut decoded = Vec::new();
+        loop {
+            let httparse::Status::Complete((offset, size)) =
+                httparse::parse_chunk_size(body).map_err(|_| ())?
+            else {
+                return Err(());
+            };
+            if size == 0 {
+                break decoded;
+            }
+            let size = usize::try_from(size).map_err(|_| ())?;
+            decoded.extend_from_slice(body.get(offset..offset + size).ok_or(())?);
+            // Each chunk is followed with a `\r\n`.
+            body = body.get(offset + size + 2..).ok_or(())?;
+        }
+    } else if let Some(content_length) = content_length {
+        body.get(..content_length).ok_or(())?.to_vec()
+    } else {
+        body.to_vec()
+    };
+
+    Ok(HttpResponse {
+        status_code,
+        headers,
+        body,
+    })
+}
+
+/// State of the HTTP requests started by the runtime during an off-chain worker call.
+///
+/// The body of a request is buffered until the runtime indicates that it is complete, or until
+/// the runtime waits for the response, at which point the request is passed to the
+/// [`HttpClient`].
+pub struct OffchainHttpRequests {
+    /// Client that performs the requests.
+    client: Arc<dyn HttpClient + Send + Sync>,
+
+    /// Source of the time used to determine when the deadlines are reached.
+    time_source: Arc<dyn TimeSource + Send + Sync>,
+
+    /// Identifier to assign to the next request.
+    next_request_id: u16,
+
+    /// List of requests, indexed by their identifier.
+    requests: hashbrown::HashMap<u16, RequestState, fnv::FnvBuildHasher>,
+}
+
+enum RequestState {
+    /// The runtime is still adding headers or writing the body.
+    Building(HttpRequest),
+    /// The request has been passed to the client.
+    InProgress(future::BoxFuture<'static, Result<HttpResponse, ()>>),
+    /// The response has been received. `body_read` is the number of bytes of the body that the
+    /// runtime has already read.
+    Finished {
+        response: HttpResponse,
+        body_read: usize,
+    },
+    /// The client has failed to perform the request.
+    Failed,
+}
+
+impl OffchainHttpRequests {
+    /// Initializes a new empty list of requests.
+    pub fn new(
+        client: Arc<dyn HttpClient + Send + Sync>,
+        time_source: Arc<dyn TimeSource + Send + Sync>,
+    ) -> Self {
+        OffchainHttpRequests {
+            client,
+            time_source,
+            next_request_id: 0,
+            requests: hashbrown::HashMap::with_capacity_and_hasher(0, Default::default()),
+        }
+    }
+
+    /// Starts a new request. Returns its identifier, or `Err` if no identifier is available.
+    pub fn start(&mut self, method: &str, uri: &str) -> Result<u16, ()> {
+        let request_id = self.next_request_id;
+        self.next_request_id = self.next_request_id.checked_add(1).ok_or(())?;
+        self.requests.insert(
+            request_id,
+            RequestState::Building(HttpRequest {
+                method: method.to_owned(),
+                uri: uri.to_owned(),
+                headers: Vec::new(),
+                body: Vec::new(),
+            }),
+        );
+        Ok(request_id)
+    }
+
+    /// Adds a header to a request that hasn't been sent yet.
+    pub fn add_header(&mut self, request_id: u16, name: &str, value: &str) -> Result<(), ()> {
+        match self.requests.get_mut(&request_id) {
+            Some(RequestState::Building(request)) => {
+                request.headers.push((name.to_owned(), value.to_owned()));
+                Ok(())
+            }
+            _ => Err(()),
+        }
+    }
+
+    /// Appends a chunk to the body of a request that hasn't been sent yet. An empty chunk
+    /// indicates that the body is complete, in which case the request is sent.
+    pub fn write_body(&mut self, request_id: u16, chunk: &[u8]) -> Result<(), HttpError> {
+        match self.requests.get_mut(&request_id) {
+            Some(RequestState::Building(request)) if chunk.is_empty() => {
+                let request = request.clone();
+                self.requests.insert(
+                    request_id,
+                    RequestState::InProgress(self.client.request(request)),
+                );
+                Ok(())
+            }
+            Some(RequestState::Building(request)) => {
+                request.body.extend_from_slice(chunk);
+                Ok(())
+            }
+            _ => Err(HttpError::Invalid),
+        }
+    }
+
+    /// Waits until the response to each of the given requests has been received, or until the
+    /// deadline (a UNIX timestamp in milliseconds) has been reached.
+    ///
+    /// The requests whose body isn't complete yet are sent.
+    pub async fn wait(
+        &mut self,
+        request_ids: impl Iterator<Item = u16>,
+        deadline: Option<u64>,
+    ) -> Vec<HttpRequestStatus> {
+        let request_ids = request_ids.collect::<Vec<_>>();
+
+        for request_id in &request_ids {
+            if let Some(RequestState::Building(_)) = self.requests.get(request_id) {
+                self.write_body(*request_id, &[]).unwrap();
+            }
+        }
+
+        let mut timer = Box::pin(deadline_timer(self.time_source.clone(), deadline));
+        loop {
+            let (ids, futures): (Vec<u16>, Vec<_>) = self
+                .requests
+                .iter_mut()
+                .filter(|(id, _)| request_ids.contains(id))
+                .filter_map(|(id, state)| match state {
+                    RequestState::InProgress(future) => Some((*id, future)),
+                    _ => None,
+                })
+                .unzip();
+            if ids.is_empty() {
+                break;
+            }
+
+            let next_response = async {
+                let (result, index, _) = future::select_all(futures).await;
+                Some((ids[index], result))
+            };
+            let deadline_reached = async {
+                (&mut timer).await;
+                None
+            };
+
+            match next_response.or(deadline_reached).await {
+                Some((request_id, Ok(response))) => {
+                    self.requests.insert(
+                        request_id,
+                        RequestState::Finished {
+                            response,
+                            body_read: 0,
+                        },
+                    );
+                }
+                Some((request_id, Err(()))) => {
+                    self.requests.insert(request_id, RequestState::Failed);
+                }
+                None => break,
+            }
+        }
+
+        request_ids
+            .iter()
+            .map(|request_id| match self.requests.get(request_id) {
+                None | Some(RequestState::Building(_)) => HttpRequestStatus::Invalid,
+                Some(RequestState::InProgress(_)) => HttpRequestStatus::DeadlineReached,
+                Some(RequestState::Finished { response, .. }) => {
+                    HttpRequestStatus::Finished(response.status_code)
+                }
+                Some(RequestState::Failed) => HttpRequestStatus::IoError,
+            })
+            .collect()
+    }
+
+    /// Returns the headers of the response to the given request. Empty if the response hasn't
+    /// been received.
+    pub fn response_headers(&self, request_id: u16) -> Vec<(String, String)> {
+        match self.requests.get(&request_id) {
+            Some(RequestState::Finished { response, .. }) => response.headers.clone(),
+            _ => Vec::new(),
+        }
+    }
+
+    /// Returns the next chunk of at most `max_size` bytes of the body of the response to the
+    /// given request, waiting for the response if necessary. Returns an empty chunk if the body
+    /// has been entirely read.
+    pub async fn read_body(
+        &mut self,
+        request_id: u16,
+        max_size: usize,
+        deadline: Option<u64>,
+    ) -> Result<Vec<u8>, HttpError> {
+        if let Some(RequestState::Building(_) | RequestState::InProgress(_)) =
+            self.requests.get(&request_id)
+        {
+            self.wait(std::iter::once(request_id), deadline).await;
+        }
+
+        match self.requests.get_mut(&request_id) {
+            Some(RequestState::Finished {
+                response,
+                body_read,
+            }) => {
+                let chunk_end = body_read.saturating_add(max_size).min(response.body.len());
+                let chunk = response.body[*body_read..chunk_end].to_vec();
+                *body_read = chunk_end;
+                Ok(chunk)
+            }
+            Some(RequestState::InProgress(_)) => Err(HttpError::DeadlineReached),
+            Some(RequestState::Failed) => Err(HttpError::IoError),
+            None | Some(RequestState::Building(_)) => Err(HttpError::Invalid),
+        }
+    }
+}
+
+/// Returns a future that finishes when the given UNIX timestamp, in milliseconds, is reached, or
+/// never if `None`.
+fn deadline_timer(
+    time_source: Arc<dyn TimeSource + Send + Sync>,
+    deadline: Option<u64>,
+) -> impl Future<Output = ()> {
+    async move {
+        let Some(deadline) = deadline else {
+            return future::pending().await;
+        };
+
+        let duration =
+            Duration::from_millis(deadline).saturating_sub(time_source.now_from_unix_epoch());
+        time_source.sleep(duration).await;
+    }
+}
@@ -15,18 +15,24 @@
 // You should have received a copy of the GNU General Public License
 // along with this program.  If not, see <http://www.gnu.org/licenses/>.
 
-use crate::{LogCallback, LogLevel};
+use crate::{
+    consensus_service, database_thread, runtime_call, transactions_service, LogCallback, LogLevel,
+};
 use futures_util::FutureExt;
 use smol::{
     future,
+    lock::Mutex,
     net::{TcpListener, TcpStream},
 };
-use smoldot::json_rpc::service;
+use smoldot::{
+    identity::keystore,
+    json_rpc::{methods, service},
+};
 use std::{
     future::Future,
     io, mem,
     net::SocketAddr,
-    num::NonZeroU32,
+    num::{NonZeroU32, NonZeroUsize},
     pin::Pin,
     sync::{
         atomic::{AtomicU32, Ordering},
@@ -35,6 +41,7 @@ use std::{
     time::Duration,
 };
 
+mod chain_head;
 mod requests_handler;
 
 /// Configuration for a [`JsonRpcService`].
@@ -55,6 +62,24 @@ pub struct Config {
 
     /// Maximum number of JSON-RPC clients until new ones are rejected.
     pub max_json_rpc_clients: u32,
+
+    /// Database to access blocks.
+    pub database: Arc<database_thread::DatabaseThread>,
+
+    /// Number of bytes used to encode the block number in headers.
+    pub block_number_bytes: usize,
+
+    /// Hash of the genesis block of the chain.
+    pub genesis_block_hash: [u8; 32],
+
+    /// Consensus service of the chain. Used to follow the blocks of the chain.
+    pub consensus_service: Arc<consensus_service::ConsensusService>,
+
+    /// Transactions service of the chain. Used to submit transactions.
+    pub transactions_service: Arc<transactions_service::TransactionsService>,
+
+    /// Keystore of the node. Used to generate new session keys.
+    pub keystore: Arc<keystore::Keystore>,
 }
 
 /// Running JSON-RPC service. Holds a server open for as long as it is alive.
@@ -99,10 +124,19 @@ impl JsonRpcService {
         let on_service_dropped = service_dropped.listen();
 
         let (to_requests_handlers, from_background) = async_channel::bounded(8);
+        let runtime_caches = Arc::new(Mutex::new(lru::LruCache::new(
+            NonZeroUsize::new(4).unwrap(),
+        )));
         for _ in 0..config.max_parallel_requests {
             requests_handler::spawn_requests_handler(requests_handler::Config {
                 tasks_executor: config.tasks_executor.clone(),
                 receiver: from_background.clone(),
+                database: config.database.clone(),
+                block_number_bytes: config.block_number_bytes,
+                genesis_block_hash: config.genesis_block_hash,
+                runtime_caches: runtime_caches.clone(),
+                transactions_service: config.transactions_service.clone(),
+                keystore: config.keystore.clone(),
             });
         }
 
@@ -112,6 +146,9 @@ impl JsonRpcService {
             tasks_executor: config.tasks_executor.clone(),
             log_callback: config.log_callback,
             to_requests_handlers,
+            consensus_service: config.consensus_service,
+            database: config.database,
+            runtime_caches,
             num_json_rpc_clients: Arc::new(AtomicU32::new(0)),
             max_json_rpc_clients: config.max_json_rpc_clients,
         };
@@ -160,6 +197,15 @@ struct JsonRpcBackground {
     /// Channel used to send requests to the tasks that process said requests.
     to_requests_handlers: async_channel::Sender<requests_handler::Message>,
 
+    /// See [`Config::consensus_service`].
+    consensus_service: Arc<consensus_service::ConsensusService>,
+
+    /// See [`Config::database`].
+    database: Arc<database_thread::DatabaseThread>,
+
+    /// Runtimes that have been compiled in the past, shared between all the JSON-RPC clients.
+    runtime_caches: Arc<Mutex<runtime_call::RuntimeCaches>>,
+
     /// Number of clients currently alive.
     num_json_rpc_clients: Arc<AtomicU32>,
 
@@ -242,9 +288,25 @@ impl JsonRpcBackground {
                 io,
                 self.num_json_rpc_clients.clone(),
             );
+            // The `chainHead` functions are processed by a separate task dedicated to this
+            // client.
+            // This channel is unbounded, but the number of requests that can be queued is in
+            // practice bounded by `max_pending_requests`. Using a bounded channel could lead to
+            // a deadlock, as the tasks processing these requests might wait for the client main
+            // task to process notifications.
+            let (to_chain_head, chain_head_rx) = async_channel::unbounded();
+            chain_head::spawn_chain_head_client_task(chain_head::Config {
+                tasks_executor: self.tasks_executor.clone(),
+                log_callback: self.log_callback.clone(),
+                receiver: chain_head_rx,
+                consensus_service: self.consensus_service.clone(),
+                database: self.database.clone(),
+                runtime_caches: self.runtime_caches.clone(),
+            });
             spawn_client_main_task(
                 &self.tasks_executor,
                 self.to_requests_handlers.clone(),
+                to_chain_head,
                 client_main_task,
             );
         }
@@ -432,6 +494,7 @@ fn spawn_client_io_task(
 fn spawn_client_main_task(
     tasks_executor: &Arc<dyn Fn(Pin<Box<dyn Future<Output = ()> + Send>>) + Send + Sync>,
     to_requests_handlers: async_channel::Sender<requests_handler::Message>,
+    to_chain_head: async_channel::Sender<chain_head::Message>,
     mut client_main_task: service::ClientMainTask,
 ) {
     tasks_executor(Box::pin(async move {
@@ -442,25 +505,58 @@ fn spawn_client_main_task(
                     request_process,
                 } => {
                     client_main_task = task;
-                    to_requests_handlers
-                        .send(requests_handler::Message::Request(request_process))
-                        .await
-                        .unwrap();
+                    match request_process.request() {
+                        methods::MethodCall::chainHead_unstable_body { .. }
+                        | methods::MethodCall::chainHead_unstable_call { .. }
+                        | methods::MethodCall::chainHead_unstable_header { .. }
+                        | methods::MethodCall::chainHead_unstable_stopOperation { .. }
+                        | methods::MethodCall::chainHead_unstable_storage { .. }
+                        | methods::MethodCall::chainHead_unstable_continue { .. }
+                        | methods::MethodCall::chainHead_unstable_unpin { .. } => {
+                            to_chain_head
+                                .send(chain_head::Message::Request(request_process))
+                                .await
+                                .unwrap();
+                        }
+                        _ => {
+                            to_requests_handlers
+                                .send(requests_handler::Message::Request(request_process))
+                                .await
+                                .unwrap();
+                        }
+                    }
                 }
                 service::Event::HandleSubscriptionStart {
                     task,
                     subscription_start,
                 } => {
                     client_main_task = task;
-                    to_requests_handlers
-                        .send(requests_handler::Message::SubscriptionStart(
-                            subscription_start,
-                        ))
-                        .await
-                        .unwrap();
+                    match subscription_start.request() {
+                        methods::MethodCall::chainHead_unstable_follow { .. } => {
+                            to_chain_head
+                                .send(chain_head::Message::SubscriptionStart(subscription_start))
+                                .await
+                                .unwrap();
+                        }
+                        _ => {
+                            to_requests_handlers
+                                .send(requests_handler::Message::SubscriptionStart(
+                                    subscription_start,
+                                ))
+                                .await
+                                .unwrap();
+                        }
+                    }
                 }
-                service::Event::SubscriptionDestroyed { task, .. } => {
+                service::Event::SubscriptionDestroyed {
+                    task,
+                    subscription_id,
+                } => {
                     client_main_task = task;
+                    to_chain_head
+                        .send(chain_head::Message::SubscriptionDestroyed { subscription_id })
+                        .await
+                        .unwrap();
                 }
                 service::Event::SerializedRequestsIoClosed => {
                     // JSON-RPC client has disconnected.
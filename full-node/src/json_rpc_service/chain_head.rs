@@ -0,0 +1,1234 @@
+// Smoldot
+// Copyright (C) 2023  Pierre Krieger
+// SPDX-License-Identifier: GPL-3.0-or-later WITH Classpath-exception-2.0
+
+// This program is free software: you can redistribute it and/or modify
+// it under the terms of the GNU General Public License as published by
+// the Free Software Foundation, either version 3 of the License, or
+// (at your option) any later version.
+
+// This program is distributed in the hope that it will be useful,
+// but WITHOUT ANY WARRANTY; without even the implied warranty of
+// MERCHANTABILITY or FITNESS FOR A PARTICULAR PURPOSE.  See the
+// GNU General Public License for more details.
+
+// You should have received a copy of the GNU General Public License
+// along with this program.  If not, see <http://www.gnu.org/licenses/>.
+
+//! All JSON-RPC method handlers that related to the `chainHead` API.
+//!
+//! Each JSON-RPC client has its own task, spawned with [`spawn_chain_head_client_task`], that
+//! keeps track of the `chainHead_unstable_follow` subscriptions of this client and routes the
+//! requests that concern a specific subscription to the task dedicated to this subscription.
+//!
+//! Blocks are reported by the [`consensus_service::ConsensusService`] only after they have
+//! been inserted in the database, and all the operations (body, storage, call) are answered
+//! by reading the database.
+
+use crate::{consensus_service, database_thread, runtime_call, LogCallback, LogLevel};
+
+use futures_lite::FutureExt as _;
+use futures_util::FutureExt as _;
+use smol::lock::Mutex;
+use smoldot::{
+    chain::fork_tree,
+    database::full_sqlite,
+    executor,
+    json_rpc::{methods, service},
+    trie,
+};
+use std::{collections::HashMap, future::Future, iter, pin::Pin, sync::Arc};
+
+/// Maximum number of items that a storage operation reports at once. After this number of
+/// items has been reported, the operation waits for a `chainHead_unstable_continue`.
+const STORAGE_ITEMS_BATCH_SIZE: usize = 16;
+
+/// Maximum number of blocks that can be pinned at the same time by a single follow
+/// subscription. If this limit is exceeded, the subscription is stopped, as required by the
+/// specification. This is the same limit as the light client.
+const MAX_PINNED_BLOCKS: usize = 32;
+
+pub struct Config {
+    /// Function that can be used to spawn background tasks.
+    ///
+    /// The tasks passed as parameter must be executed until they shut down.
+    pub tasks_executor: Arc<dyn Fn(Pin<Box<dyn Future<Output = ()> + Send>>) + Send + Sync>,
+
+    /// Function called in order to notify of something.
+    pub log_callback: Arc<dyn LogCallback + Send + Sync>,
+
+    pub receiver: async_channel::Receiver<Message>,
+
+    /// Consensus service of the chain. Used to follow the blocks of the chain.
+    pub consensus_service: Arc<consensus_service::ConsensusService>,
+
+    /// Database to access blocks.
+    pub database: Arc<database_thread::DatabaseThread>,
+
+    /// Runtimes that have been compiled in the past, shared between all the JSON-RPC clients.
+    pub runtime_caches: Arc<Mutex<runtime_call::RuntimeCaches>>,
+}
+
+pub enum Message {
+    /// One of the `chainHead_unstable_*` functions that target a specific follow subscription
+    /// has been called.
+    Request(service::RequestProcess),
+    /// `chainHead_unstable_follow` has been called.
+    SubscriptionStart(service::SubscriptionStartProcess),
+    /// A subscription of the JSON-RPC client has been destroyed.
+    SubscriptionDestroyed { subscription_id: String },
+}
+
+/// Spawns the task dedicated to a single JSON-RPC client.
+///
+/// The task stops when all the senders of [`Config::receiver`] have been dropped.
+pub fn spawn_chain_head_client_task(config: Config) {
+    (config.tasks_executor.clone())(Box::pin(async move {
+        // For each active follow subscription, a sender that delivers requests to the task
+        // dedicated to this subscription.
+        let mut follow_tasks = HashMap::<
+            String,
+            service::DeliverSender<service::RequestProcess>,
+            fnv::FnvBuildHasher,
+        >::with_capacity_and_hasher(2, Default::default());
+
+        loop {
+            match config.receiver.recv().await {
+                Ok(Message::Request(request)) => {
+                    let follow_subscription = match request.request() {
+                        methods::MethodCall::chainHead_unstable_body {
+                            follow_subscription,
+                            ..
+                        }
+                        | methods::MethodCall::chainHead_unstable_call {
+                            follow_subscription,
+                            ..
+                        }
+                        | methods::MethodCall::chainHead_unstable_header {
+                            follow_subscription,
+                            ..
+                        }
+                        | methods::MethodCall::chainHead_unstable_stopOperation {
+                            follow_subscription,
+                            ..
+                        }
+                        | methods::MethodCall::chainHead_unstable_storage {
+                            follow_subscription,
+                            ..
+                        }
+                        | methods::MethodCall::chainHead_unstable_continue {
+                            follow_subscription,
+                            ..
+                        }
+                        | methods::MethodCall::chainHead_unstable_unpin {
+                            follow_subscription,
+                            ..
+                        } => follow_subscription.into_owned(),
+                        _ => unreachable!(),
+                    };
+
+                    let send_outcome =
+                        if let Some(sender) = follow_tasks.get_mut(&follow_subscription) {
+                            sender.deliver(request).await
+                        } else {
+                            Err(request)
+                        };
+
+                    if let Err(request) = send_outcome {
+                        // The subscription doesn't exist or is dead. Report a response that
+                        // indicates that to the JSON-RPC client.
+                        match request.request() {
+                            methods::MethodCall::chainHead_unstable_body { .. } => {
+                                request.respond(methods::Response::chainHead_unstable_body(
+                                    methods::ChainHeadBodyCallReturn::LimitReached {},
+                                ))
+                            }
+                            methods::MethodCall::chainHead_unstable_call { .. } => {
+                                request.respond(methods::Response::chainHead_unstable_call(
+                                    methods::ChainHeadBodyCallReturn::LimitReached {},
+                                ))
+                            }
+                            methods::MethodCall::chainHead_unstable_storage { .. } => request
+                                .respond(methods::Response::chainHead_unstable_storage(
+                                    methods::ChainHeadStorageReturn::LimitReached {},
+                                )),
+                            methods::MethodCall::chainHead_unstable_header { .. } => {
+                                request.respond(methods::Response::chainHead_unstable_header(None))
+                            }
+                            methods::MethodCall::chainHead_unstable_stopOperation { .. } => request
+                                .respond(methods::Response::chainHead_unstable_stopOperation(())),
+                            methods::MethodCall::chainHead_unstable_continue { .. } => {
+                                request.respond(methods::Response::chainHead_unstable_continue(()))
+                            }
+                            methods::MethodCall::chainHead_unstable_unpin { .. } => {
+                                request.respond(methods::Response::chainHead_unstable_unpin(()))
+                            }
+                            _ => unreachable!(),
+                        }
+                    }
+                }
+                Ok(Message::SubscriptionStart(request)) => {
+                    let methods::MethodCall::chainHead_unstable_follow { with_runtime } =
+                        request.request()
+                    else {
+                        unreachable!()
+                    };
+
+                    // As mentioned in the spec, the JSON-RPC server accepts 2 or more
+                    // subscriptions per JSON-RPC client. We choose to accept only exactly 2 in
+                    // order to make sure that JSON-RPC client implementations are made aware of
+                    // this limit. This is the same limit as the light client.
+                    if follow_tasks.len() >= 2 {
+                        config.log_callback.log(
+                            LogLevel::Debug,
+                            "json-rpc-chain-head-follow-limit-reached".to_string(),
+                        );
+                        request.fail(service::ErrorResponse::ApplicationDefined(
+                            -32100,
+                            "Maximum number of `chainHead_unstable_follow` subscriptions reached",
+                        ));
+                        continue;
+                    }
+
+                    let (tx, rx) = service::deliver_channel();
+                    let subscription = request.accept();
+                    follow_tasks.insert(subscription.subscription_id().to_owned(), tx);
+
+                    let (to_main_task, from_operation_handlers) = async_channel::bounded(8);
+                    (config.tasks_executor)(Box::pin(
+                        FollowTask {
+                            tasks_executor: config.tasks_executor.clone(),
+                            log_callback: config.log_callback.clone(),
+                            database: config.database.clone(),
+                            runtime_caches: config.runtime_caches.clone(),
+                            with_runtime,
+                            non_finalized_blocks: fork_tree::ForkTree::new(),
+                            current_best_block: [0; 32],
+                            pinned_blocks_headers: HashMap::with_capacity_and_hasher(
+                                0,
+                                Default::default(),
+                            ),
+                            next_operation_id: 1,
+                            operations_in_progress: HashMap::with_capacity_and_hasher(
+                                32,
+                                Default::default(),
+                            ),
+                            available_operation_slots: 32, // TODO: make configurable? adjust dynamically?
+                            to_main_task,
+                            from_operation_handlers,
+                        }
+                        .run(
+                            config.consensus_service.clone(),
+                            subscription,
+                            rx,
+                        ),
+                    ));
+                }
+                Ok(Message::SubscriptionDestroyed { subscription_id }) => {
+                    // Dropping the sender stops the corresponding task, if any.
+                    follow_tasks.remove(&subscription_id);
+                }
+                Err(_) => return,
+            }
+        }
+    }));
+}
+
+/// Task dedicated to a single `chainHead_unstable_follow` subscription.
+struct FollowTask {
+    /// See [`Config::tasks_executor`].
+    tasks_executor: Arc<dyn Fn(Pin<Box<dyn Future<Output = ()> + Send>>) + Send + Sync>,
+
+    /// See [`Config::log_callback`].
+    log_callback: Arc<dyn LogCallback + Send + Sync>,
+
+    /// See [`Config::database`].
+    database: Arc<database_thread::DatabaseThread>,
+
+    /// See [`Config::runtime_caches`].
+    runtime_caches: Arc<Mutex<runtime_call::RuntimeCaches>>,
+
+    /// Value of the `withRuntime` parameter passed to `chainHead_unstable_follow`.
+    with_runtime: bool,
+
+    /// Tree of hashes of all the current non-finalized blocks. This includes unpinned blocks.
+    non_finalized_blocks: fork_tree::ForkTree<[u8; 32]>,
+
+    /// Hash of the block that has last been reported to the JSON-RPC client as the best block,
+    /// either explicitly or implicitly through the `initialized` event.
+    current_best_block: [u8; 32],
+
+    /// For each pinned block hash, the SCALE-encoded header of the block.
+    ///
+    /// Note that the database might have discarded a pinned block after it has been pruned, in
+    /// which case operations on this block generate an `operationInaccessible` event.
+    pinned_blocks_headers: HashMap<[u8; 32], Vec<u8>, fnv::FnvBuildHasher>,
+
+    /// Identifier to assign to the next body/call/storage operation.
+    next_operation_id: u128,
+
+    /// List of body/call/storage operations currently in progress. Keys are operation IDs.
+    operations_in_progress: HashMap<String, Operation, fnv::FnvBuildHasher>,
+
+    /// Number of operation slots that are still available. Each operation in progress
+    /// occupies one or more slots.
+    available_operation_slots: u32,
+
+    /// Sending side of [`FollowTask::from_operation_handlers`]. Cloned for each operation.
+    to_main_task: async_channel::Sender<OperationEvent>,
+
+    /// Events generated by the operations in progress.
+    from_operation_handlers: async_channel::Receiver<OperationEvent>,
+}
+
+/// Reason why a follow subscription must be stopped.
+#[derive(Debug, derive_more::Display)]
+enum FollowError {
+    /// The consensus service has reported the finalization of a block that isn't known.
+    #[display(fmt = "Finalized block isn't known")]
+    UnknownFinalizedBlock,
+    /// The JSON-RPC client hasn't unpinned blocks quickly enough.
+    #[display(fmt = "Maximum number of pinned blocks reached")]
+    TooManyPinnedBlocks,
+}
+
+struct OperationEvent {
+    operation_id: String,
+    notification: methods::FollowEvent<'static>,
+    is_done: bool,
+}
+
+struct Operation {
+    /// Number of slots of [`FollowTask::available_operation_slots`] occupied by this operation.
+    occupied_slots: u32,
+
+    /// Notified when the operation must stop.
+    interrupt: event_listener::Event,
+
+    /// For operations that can wait for a `chainHead_unstable_continue`, sender that wakes up
+    /// the operation.
+    on_continue: Option<async_channel::Sender<()>>,
+}
+
+impl FollowTask {
+    async fn run(
+        mut self,
+        consensus_service: Arc<consensus_service::ConsensusService>,
+        mut subscription: service::Subscription,
+        mut messages_rx: service::DeliverReceiver<service::RequestProcess>,
+    ) {
+        let subscription_id = subscription.subscription_id().to_owned();
+
+        let subscribe_all = consensus_service.subscribe_all(32).await;
+
+        self.pinned_blocks_headers.insert(
+            subscribe_all.finalized_block_hash,
+            subscribe_all.finalized_block_scale_encoded_header,
+        );
+        self.current_best_block = subscribe_all.finalized_block_hash;
+
+        subscription
+            .send_notification(methods::ServerToClient::chainHead_unstable_followEvent {
+                subscription: (&subscription_id).into(),
+                result: methods::FollowEvent::Initialized {
+                    finalized_block_hash: methods::HashHexString(
+                        subscribe_all.finalized_block_hash,
+                    ),
+                    finalized_block_runtime: if self.with_runtime {
+                        Some(convert_runtime_spec(&subscribe_all.finalized_block_runtime))
+                    } else {
+                        None
+                    },
+                },
+            })
+            .await;
+
+        let mut initial_blocks_result = Ok(());
+        for block in subscribe_all.non_finalized_blocks_ancestry_order {
+            initial_blocks_result = self
+                .on_new_block(&mut subscription, &subscription_id, block)
+                .await;
+            if initial_blocks_result.is_err() {
+                break;
+            }
+        }
+
+        let new_blocks = subscribe_all.new_blocks;
+
+        loop {
+            if let Err(error) = initial_blocks_result {
+                self.stop(&mut subscription, &subscription_id, error).await;
+                break;
+            }
+
+            enum WhatHappened {
+                SubscriptionDead,
+                Notification(consensus_service::Notification),
+                OperationEvent(OperationEvent),
+                Unsubscribed,
+                NewRequest(service::RequestProcess),
+            }
+
+            let outcome: WhatHappened = {
+                let next_block = async {
+                    match new_blocks.recv().await {
+                        Ok(n) => WhatHappened::Notification(n),
+                        Err(_) => WhatHappened::SubscriptionDead,
+                    }
+                };
+
+                let operation_event = async {
+                    // `self` holds a sender, thus the channel can never be closed.
+                    let event = self.from_operation_handlers.recv().await.unwrap();
+                    WhatHappened::OperationEvent(event)
+                };
+
+                let message = async {
+                    match messages_rx.next().await {
+                        Some(rq) => WhatHappened::NewRequest(rq),
+                        None => WhatHappened::Unsubscribed,
+                    }
+                };
+
+                next_block
+                    .or(message)
+                    .or(operation_event)
+                    .or(async {
+                        subscription.wait_until_stale().await;
+                        WhatHappened::Unsubscribed
+                    })
+                    .await
+            };
+
+            match outcome {
+                WhatHappened::Unsubscribed => break,
+                WhatHappened::SubscriptionDead => {
+                    // The consensus service has closed the channel because the JSON-RPC
+                    // client isn't pulling notifications quickly enough.
+                    subscription
+                        .send_notification(
+                            methods::ServerToClient::chainHead_unstable_followEvent {
+                                subscription: (&subscription_id).into(),
+                                result: methods::FollowEvent::Stop {},
+                            },
+                        )
+                        .await;
+                    break;
+                }
+
+                WhatHappened::OperationEvent(OperationEvent {
+                    operation_id,
+                    notification,
+                    is_done,
+                }) => {
+                    let operation_is_valid = if is_done {
+                        if let Some(operation) = self.operations_in_progress.remove(&operation_id) {
+                            self.available_operation_slots += operation.occupied_slots;
+                            true
+                        } else {
+                            false
+                        }
+                    } else {
+                        self.operations_in_progress.contains_key(&operation_id)
+                    };
+
+                    if operation_is_valid {
+                        subscription
+                            .send_notification(
+                                methods::ServerToClient::chainHead_unstable_followEvent {
+                                    subscription: (&subscription_id).into(),
+                                    result: notification,
+                                },
+                            )
+                            .await;
+                    }
+                }
+
+                WhatHappened::Notification(consensus_service::Notification::Finalized {
+                    hash,
+                    best_block_hash,
+                }) => {
+                    if let Err(error) = self
+                        .on_finalized(&mut subscription, &subscription_id, hash, best_block_hash)
+                        .await
+                    {
+                        self.stop(&mut subscription, &subscription_id, error).await;
+                        break;
+                    }
+                }
+
+                WhatHappened::Notification(consensus_service::Notification::Block(block)) => {
+                    if let Err(error) = self
+                        .on_new_block(&mut subscription, &subscription_id, block)
+                        .await
+                    {
+                        self.stop(&mut subscription, &subscription_id, error).await;
+                        break;
+                    }
+                }
+
+                WhatHappened::NewRequest(request) => self.on_foreground_message(request),
+            }
+        }
+
+        // Interrupt all the operations that are still in progress.
+        for (_, operation) in self.operations_in_progress.drain() {
+            operation.interrupt.notify(usize::max_value());
+        }
+    }
+
+    /// Sends a `stop` event to the JSON-RPC client. The subscription must then be ended.
+    async fn stop(
+        &mut self,
+        subscription: &mut service::Subscription,
+        subscription_id: &str,
+        error: FollowError,
+    ) {
+        self.log_callback.log(
+            LogLevel::Debug,
+            format!("json-rpc-chain-head-follow-stopped; error={error}"),
+        );
+
+        subscription
+            .send_notification(methods::ServerToClient::chainHead_unstable_followEvent {
+                subscription: subscription_id.into(),
+                result: methods::FollowEvent::Stop {},
+            })
+            .await;
+    }
+
+    /// Pins the given block and reports it to the JSON-RPC client.
+    ///
+    /// Returns an error if the maximum number of pinned blocks has been exceeded, in which case
+    /// the subscription must be stopped.
+    async fn on_new_block(
+        &mut self,
+        subscription: &mut service::Subscription,
+        subscription_id: &str,
+        block: consensus_service::BlockNotification,
+    ) -> Result<(), FollowError> {
+        if self.pinned_blocks_headers.len() >= MAX_PINNED_BLOCKS {
+            return Err(FollowError::TooManyPinnedBlocks);
+        }
+
+        let _was_in = self
+            .pinned_blocks_headers
+            .insert(block.block_hash, block.scale_encoded_header);
+        debug_assert!(_was_in.is_none());
+
+        // A parent that isn't found in the tree is the finalized block.
+        // TODO: O(n)
+        let parent_node_index = self.non_finalized_blocks.find(|b| *b == block.parent_hash);
+        self.non_finalized_blocks
+            .insert(parent_node_index, block.block_hash);
+
+        subscription
+            .send_notification(methods::ServerToClient::chainHead_unstable_followEvent {
+                subscription: subscription_id.into(),
+                result: methods::FollowEvent::NewBlock {
+                    block_hash: methods::HashHexString(block.block_hash),
+                    parent_block_hash: methods::HashHexString(block.parent_hash),
+                    new_runtime: if self.with_runtime {
+                        block.new_runtime.as_ref().map(convert_runtime_spec)
+                    } else {
+                        None
+                    },
+                },
+            })
+            .await;
+
+        if block.is_new_best && self.current_best_block != block.block_hash {
+            self.current_best_block = block.block_hash;
+            subscription
+                .send_notification(methods::ServerToClient::chainHead_unstable_followEvent {
+                    subscription: subscription_id.into(),
+                    result: methods::FollowEvent::BestBlockChanged {
+                        best_block_hash: methods::HashHexString(block.block_hash),
+                    },
+                })
+                .await;
+        }
+
+        Ok(())
+    }
+
+    /// Prunes the blocks that are no longer relevant after `hash` has been finalized, and
+    /// reports the finalization to the JSON-RPC client.
+    ///
+    /// Returns an error if `hash` isn't a known non-finalized block, in which case the
+    /// subscription must be stopped.
+    async fn on_finalized(
+        &mut self,
+        subscription: &mut service::Subscription,
+        subscription_id: &str,
+        hash: [u8; 32],
+        best_block_hash: [u8; 32],
+    ) -> Result<(), FollowError> {
+        let node_index = self
+            .non_finalized_blocks
+            .find(|b| *b == hash)
+            .ok_or(FollowError::UnknownFinalizedBlock)?;
+
+        let mut finalized_blocks_hashes = Vec::new();
+        let mut pruned_blocks_hashes = Vec::new();
+
+        for pruned in self.non_finalized_blocks.prune_ancestors(node_index) {
+            if pruned.is_prune_target_ancestor {
+                finalized_blocks_hashes.push(methods::HashHexString(pruned.user_data));
+            } else {
+                pruned_blocks_hashes.push(methods::HashHexString(pruned.user_data));
+            }
+        }
+
+        // The best block might have been pruned, in which case the new best block must be
+        // reported before the `finalized` event.
+        if self.current_best_block != best_block_hash {
+            self.current_best_block = best_block_hash;
+            subscription
+                .send_notification(methods::ServerToClient::chainHead_unstable_followEvent {
+                    subscription: subscription_id.into(),
+                    result: methods::FollowEvent::BestBlockChanged {
+                        best_block_hash: methods::HashHexString(best_block_hash),
+                    },
+                })
+                .await;
+        }
+
+        subscription
+            .send_notification(methods::ServerToClient::chainHead_unstable_followEvent {
+                subscription: subscription_id.into(),
+                result: methods::FollowEvent::Finalized {
+                    finalized_blocks_hashes,
+                    pruned_blocks_hashes,
+                },
+            })
+            .await;
+
+        Ok(())
+    }
+
+    fn on_foreground_message(&mut self, request: service::RequestProcess) {
+        match request.request() {
+            methods::MethodCall::chainHead_unstable_body { .. } => {
+                self.start_chain_head_body(request);
+            }
+            methods::MethodCall::chainHead_unstable_storage { .. } => {
+                self.start_chain_head_storage(request);
+            }
+            methods::MethodCall::chainHead_unstable_call { .. } => {
+                self.start_chain_head_call(request);
+            }
+            methods::MethodCall::chainHead_unstable_stopOperation { operation_id, .. } => {
+                if let Some(operation) = self.operations_in_progress.remove(&*operation_id) {
+                    operation.interrupt.notify(usize::max_value());
+                    self.available_operation_slots += operation.occupied_slots;
+                }
+                request.respond(methods::Response::chainHead_unstable_stopOperation(()));
+            }
+            methods::MethodCall::chainHead_unstable_continue { operation_id, .. } => {
+                match self
+                    .operations_in_progress
+                    .get(&*operation_id)
+                    .and_then(|op| op.on_continue.as_ref())
+                {
+                    Some(on_continue) => {
+                        // The channel has a capacity of one. If it is full, then a continue
+                        // is already pending.
+                        let _ = on_continue.try_send(());
+                        request.respond(methods::Response::chainHead_unstable_continue(()));
+                    }
+                    None => request.fail(service::ErrorResponse::InvalidParams),
+                }
+            }
+            methods::MethodCall::chainHead_unstable_header {
+                follow_subscription: _,
+                hash,
+            } => {
+                let response = self.pinned_blocks_headers.get(&hash.0).cloned();
+                request.respond(methods::Response::chainHead_unstable_header(
+                    response.map(methods::HexString),
+                ));
+            }
+            methods::MethodCall::chainHead_unstable_unpin {
+                follow_subscription: _,
+                hash,
+            } => {
+                let all_hashes = match &hash {
+                    methods::HashHexStringSingleOrArray::Single(hash) => {
+                        either::Left(iter::once(&hash.0))
+                    }
+                    methods::HashHexStringSingleOrArray::Array(hashes) => {
+                        either::Right(hashes.iter().map(|h| &h.0))
+                    }
+                };
+
+                let is_valid = all_hashes
+                    .clone()
+                    .all(|hash| self.pinned_blocks_headers.contains_key(hash));
+
+                if is_valid {
+                    for hash in all_hashes {
+                        self.pinned_blocks_headers.remove(hash);
+                    }
+                    request.respond(methods::Response::chainHead_unstable_unpin(()));
+                } else {
+                    request.fail(service::ErrorResponse::InvalidParams);
+                }
+            }
+            _ => unreachable!(),
+        }
+    }
+
+    /// Allocates a new operation ID and inserts an entry in
+    /// [`FollowTask::operations_in_progress`].
+    ///
+    /// Returns the operation ID and a listener that is notified when the operation must stop.
+    fn insert_operation(
+        &mut self,
+        occupied_slots: u32,
+        on_continue: Option<async_channel::Sender<()>>,
+    ) -> (String, event_listener::EventListener) {
+        let operation_id = self.next_operation_id.to_string();
+        self.next_operation_id += 1;
+
+        let interrupt = event_listener::Event::new();
+        let on_interrupt = interrupt.listen();
+
+        let _was_in = self.operations_in_progress.insert(
+            operation_id.clone(),
+            Operation {
+                occupied_slots,
+                interrupt,
+                on_continue,
+            },
+        );
+        debug_assert!(_was_in.is_none());
+
+        (operation_id, on_interrupt)
+    }
+
+    fn start_chain_head_body(&mut self, request: service::RequestProcess) {
+        let methods::MethodCall::chainHead_unstable_body { hash, .. } = request.request() else {
+            unreachable!()
+        };
+
+        if !self.pinned_blocks_headers.contains_key(&hash.0) {
+            // Block isn't pinned. Request is invalid.
+            request.fail(service::ErrorResponse::InvalidParams);
+            return;
+        }
+
+        // Check whether there is an operation slot available.
+        self.available_operation_slots = match self.available_operation_slots.checked_sub(1) {
+            Some(s) => s,
+            None => {
+                request.respond(methods::Response::chainHead_unstable_body(
+                    methods::ChainHeadBodyCallReturn::LimitReached {},
+                ));
+                return;
+            }
+        };
+
+        let (operation_id, on_interrupt) = self.insert_operation(1, None);
+
+        request.respond(methods::Response::chainHead_unstable_body(
+            methods::ChainHeadBodyCallReturn::Started {
+                operation_id: (&operation_id).into(),
+            },
+        ));
+
+        // Finish the request asynchronously.
+        let database = self.database.clone();
+        let to_main_task = self.to_main_task.clone();
+        let block_hash = hash.0;
+        (self.tasks_executor)(Box::pin(async move {
+            let future = database.with_database(move |database| {
+                // `block_extrinsics` doesn't detect whether the block is in the database, so
+                // we check this beforehand.
+                if database.block_scale_encoded_header(&block_hash)?.is_none() {
+                    return Ok(None);
+                }
+                Ok::<_, full_sqlite::AccessError>(
+                    database
+                        .block_extrinsics(&block_hash)?
+                        .map(|body| body.collect::<Vec<_>>()),
+                )
+            });
+
+            // Drive the future, but cancel execution if the JSON-RPC client stops the
+            // operation.
+            let outcome = match future.map(Some).or(on_interrupt.map(|()| None)).await {
+                Some(v) => v,
+                None => return,
+            };
+
+            let notification = match outcome {
+                Ok(Some(body)) => methods::FollowEvent::OperationBodyDone {
+                    operation_id: operation_id.clone().into(),
+                    value: body.into_iter().map(methods::HexString).collect(),
+                },
+                Ok(None) => methods::FollowEvent::OperationInaccessible {
+                    operation_id: operation_id.clone().into(),
+                },
+                Err(error) => methods::FollowEvent::OperationError {
+                    operation_id: operation_id.clone().into(),
+                    error: error.to_string().into(),
+                },
+            };
+
+            let _ = to_main_task
+                .send(OperationEvent {
+                    operation_id,
+                    notification,
+                    is_done: true,
+                })
+                .await;
+        }));
+    }
+
+    fn start_chain_head_storage(&mut self, request: service::RequestProcess) {
+        let methods::MethodCall::chainHead_unstable_storage {
+            hash,
+            mut items,
+            child_trie,
+            ..
+        } = request.request()
+        else {
+            unreachable!()
+        };
+
+        if !self.pinned_blocks_headers.contains_key(&hash.0) {
+            // Block isn't pinned. Request is invalid.
+            request.fail(service::ErrorResponse::InvalidParams);
+            return;
+        }
+
+        // Scrap some of the items so that it fits in the number of operation slots.
+        let (discarded_items, occupied_slots) = if self.available_operation_slots == 0 {
+            request.respond(methods::Response::chainHead_unstable_storage(
+                methods::ChainHeadStorageReturn::LimitReached {},
+            ));
+            return;
+        } else if u32::try_from(items.len())
+            .map_or(true, |num_items| num_items > self.available_operation_slots)
+        {
+            // This block is reached only if `items.len() > available_slots`. Since
+            // `items.len()` is a `usize`, we know that `available_slots` fits in a `usize` as
+            // well.
+            let available_slots = usize::try_from(self.available_operation_slots).unwrap();
+            let discarded_items = items.len() - available_slots;
+            items.truncate(available_slots);
+            (discarded_items, self.available_operation_slots)
+        } else {
+            // Since this block is reached only if `items.len() <= available_slots` and that
+            // `available_slots` is a `u32`, we know that `items.len()` fits in a `u32` as well.
+            (0, u32::try_from(items.len()).unwrap())
+        };
+        self.available_operation_slots -= occupied_slots;
+
+        let (on_continue_tx, on_continue_rx) = async_channel::bounded(1);
+        let (operation_id, on_interrupt) =
+            self.insert_operation(occupied_slots, Some(on_continue_tx));
+
+        request.respond(methods::Response::chainHead_unstable_storage(
+            methods::ChainHeadStorageReturn::Started {
+                operation_id: (&operation_id).into(),
+                discarded_items,
+            },
+        ));
+
+        // Storage of child tries is found in the database under the node of the main trie
+        // that holds the root of the child trie.
+        let parent_path = child_trie.map(|child_trie| {
+            trie::bytes_to_nibbles(b":child_storage:default:".iter().copied())
+                .chain(trie::bytes_to_nibbles(child_trie.0.into_iter()))
+                .map(u8::from)
+                .collect::<Vec<_>>()
+        });
+
+        let operation = StorageOperation {
+            database: self.database.clone(),
+            block_hash: hash.0,
+            parent_path,
+            operation_id,
+            to_main_task: self.to_main_task.clone(),
+            on_continue: on_continue_rx,
+            pending_items: Vec::with_capacity(STORAGE_ITEMS_BATCH_SIZE),
+        };
+
+        // Finish the request asynchronously.
+        (self.tasks_executor)(Box::pin(async move {
+            // Drive the operation, but cancel execution if the JSON-RPC client stops the
+            // operation.
+            operation.run(items).or(on_interrupt).await
+        }));
+    }
+
+    fn start_chain_head_call(&mut self, request: service::RequestProcess) {
+        let methods::MethodCall::chainHead_unstable_call {
+            hash,
+            function,
+            call_parameters,
+            ..
+        } = request.request()
+        else {
+            unreachable!()
+        };
+        let function = function.into_owned();
+
+        // It is invalid to call this function for a "without runtime" subscription.
+        if !self.with_runtime || !self.pinned_blocks_headers.contains_key(&hash.0) {
+            request.fail(service::ErrorResponse::InvalidParams);
+            return;
+        }
+
+        // Check whether there is an operation slot available.
+        self.available_operation_slots = match self.available_operation_slots.checked_sub(1) {
+            Some(s) => s,
+            None => {
+                request.respond(methods::Response::chainHead_unstable_call(
+                    methods::ChainHeadBodyCallReturn::LimitReached {},
+                ));
+                return;
+            }
+        };
+
+        let (operation_id, on_interrupt) = self.insert_operation(1, None);
+
+        request.respond(methods::Response::chainHead_unstable_call(
+            methods::ChainHeadBodyCallReturn::Started {
+                operation_id: (&operation_id).into(),
+            },
+        ));
+
+        // Finish the call asynchronously.
+        let database = self.database.clone();
+        let runtime_caches = self.runtime_caches.clone();
+        let to_main_task = self.to_main_task.clone();
+        (self.tasks_executor)(Box::pin(async move {
+            let future = runtime_call::runtime_call(
+                &database,
+                &runtime_caches,
+                hash.0,
+                &function,
+                iter::once(&call_parameters.0),
+            );
+
+            // Drive the future, but cancel execution if the JSON-RPC client stops the
+            // operation.
+            let outcome = match future.map(Some).or(on_interrupt.map(|()| None)).await {
+                Some(v) => v,
+                None => return,
+            };
+
+            let notification = match outcome {
+                Ok(output) => methods::FollowEvent::OperationCallDone {
+                    operation_id: operation_id.clone().into(),
+                    output: methods::HexString(output),
+                },
+                Err(runtime_call::RuntimeCallError::StorageAccess(
+                    full_sqlite::StorageAccessError::Pruned
+                    | full_sqlite::StorageAccessError::UnknownBlock,
+                )) => methods::FollowEvent::OperationInaccessible {
+                    operation_id: operation_id.clone().into(),
+                },
+                Err(error) => methods::FollowEvent::OperationError {
+                    operation_id: operation_id.clone().into(),
+                    error: error.to_string().into(),
+                },
+            };
+
+            let _ = to_main_task
+                .send(OperationEvent {
+                    operation_id,
+                    notification,
+                    is_done: true,
+                })
+                .await;
+        }));
+    }
+}
+
+/// Storage operation in progress.
+struct StorageOperation {
+    database: Arc<database_thread::DatabaseThread>,
+    block_hash: [u8; 32],
+
+    /// Path, in nibbles, of the node of the main trie that holds the child trie being
+    /// accessed. `None` if the main trie is accessed.
+    parent_path: Option<Vec<u8>>,
+
+    operation_id: String,
+    to_main_task: async_channel::Sender<OperationEvent>,
+
+    /// Receives a message whenever the JSON-RPC client calls `chainHead_unstable_continue`.
+    on_continue: async_channel::Receiver<()>,
+
+    /// Items that haven't been reported to the JSON-RPC client yet.
+    pending_items: Vec<methods::ChainHeadStorageResponseItem>,
+}
+
+impl StorageOperation {
+    async fn run(mut self, items: Vec<methods::ChainHeadStorageRequestItem>) {
+        match self.run_inner(items).await {
+            Ok(()) => {
+                if !self.pending_items.is_empty() {
+                    self.flush_items().await;
+                }
+                self.send_event(
+                    methods::FollowEvent::OperationStorageDone {
+                        operation_id: self.operation_id.clone().into(),
+                    },
+                    true,
+                )
+                .await;
+            }
+            Err(StorageOperationError::Interrupted) => {}
+            Err(StorageOperationError::Access(
+                full_sqlite::StorageAccessError::Pruned
+                | full_sqlite::StorageAccessError::UnknownBlock,
+            )) => {
+                self.send_event(
+                    methods::FollowEvent::OperationInaccessible {
+                        operation_id: self.operation_id.clone().into(),
+                    },
+                    true,
+                )
+                .await;
+            }
+            Err(StorageOperationError::Access(error)) => {
+                self.send_event(
+                    methods::FollowEvent::OperationError {
+                        operation_id: self.operation_id.clone().into(),
+                        error: error.to_string().into(),
+                    },
+                    true,
+                )
+                .await;
+            }
+        }
+    }
+
+    async fn run_inner(
+        &mut self,
+        items: Vec<methods::ChainHeadStorageRequestItem>,
+    ) -> Result<(), StorageOperationError> {
+        for item in items {
+            let key_nibbles = trie::bytes_to_nibbles(item.key.0.iter().copied())
+                .map(u8::from)
+                .collect::<Vec<_>>();
+
+            match item.ty {
+                methods::ChainHeadStorageType::Value | methods::ChainHeadStorageType::Hash => {
+                    let Some(value) = self.storage_get(key_nibbles).await? else {
+                        continue;
+                    };
+                    let is_hash = matches!(item.ty, methods::ChainHeadStorageType::Hash);
+                    self.push_item(storage_response_item(item.key.0, value, is_hash))
+                        .await?;
+                }
+                methods::ChainHeadStorageType::ClosestDescendantMerkleValue => {
+                    let block_hash = self.block_hash;
+                    let parent_path = self.parent_path.clone();
+                    let merkle_value = self
+                        .database
+                        .with_database(move |database| {
+                            database.block_storage_closest_descendant_merkle_value(
+                                &block_hash,
+                                parent_path.iter().map(|p| p.iter().copied()),
+                                key_nibbles.iter().copied(),
+                            )
+                        })
+                        .await?;
+                    let Some(merkle_value) = merkle_value else {
+                        continue;
+                    };
+                    self.push_item(methods::ChainHeadStorageResponseItem {
+                        key: item.key,
+                        value: None,
+                        hash: None,
+                        closest_descendant_merkle_value: Some(methods::HexString(merkle_value)),
+                    })
+                    .await?;
+                }
+                methods::ChainHeadStorageType::DescendantsValues
+                | methods::ChainHeadStorageType::DescendantsHashes => {
+                    let is_hash =
+                        matches!(item.ty, methods::ChainHeadStorageType::DescendantsHashes);
+
+                    // Iterate over all the keys that start with the requested key, including
+                    // the requested key itself.
+                    let mut next_key_start = key_nibbles.clone();
+                    loop {
+                        let block_hash = self.block_hash;
+                        let parent_path = self.parent_path.clone();
+                        let prefix = key_nibbles.clone();
+                        let next = self
+                            .database
+                            .with_database(move |database| {
+                                let Some(key) = database.block_storage_next_key(
+                                    &block_hash,
+                                    parent_path.iter().map(|p| p.iter().copied()),
+                                    next_key_start.iter().copied(),
+                                    prefix.iter().copied(),
+                                    false,
+                                )?
+                                else {
+                                    return Ok(None);
+                                };
+                                let value = database.block_storage_get(
+                                    &block_hash,
+                                    parent_path.iter().map(|p| p.iter().copied()),
+                                    key.iter().copied(),
+                                )?;
+                                Ok::<_, full_sqlite::StorageAccessError>(Some((key, value)))
+                            })
+                            .await?;
+
+                        let Some((found_key, value)) = next else {
+                            break;
+                        };
+
+                        if let Some((value, _)) = value {
+                            let key_bytes = trie::nibbles_to_bytes_truncate(
+                                found_key
+                                    .iter()
+                                    .map(|n| trie::Nibble::try_from(*n).unwrap()),
+                            )
+                            .collect::<Vec<_>>();
+                            self.push_item(storage_response_item(key_bytes, value, is_hash))
+                                .await?;
+                        }
+
+                        // The database functions only support an "or equal" mode, we append
+                        // a `0` nibble at the end of the key in order to find the next one.
+                        next_key_start = found_key;
+                        next_key_start.push(0);
+                    }
+                }
+            }
+        }
+
+        Ok(())
+    }
+
+    /// Reads the storage value of the given key from the database.
+    async fn storage_get(
+        &self,
+        key_nibbles: Vec<u8>,
+    ) -> Result<Option<Vec<u8>>, full_sqlite::StorageAccessError> {
+        let block_hash = self.block_hash;
+        let parent_path = self.parent_path.clone();
+        let value = self
+            .database
+            .with_database(move |database| {
+                database.block_storage_get(
+                    &block_hash,
+                    parent_path.iter().map(|p| p.iter().copied()),
+                    key_nibbles.iter().copied(),
+                )
+            })
+            .await?;
+        Ok(value.map(|(value, _)| value))
+    }
+
+    /// Adds an item to the list of items to report. If the list is full, reports the items
+    /// and waits for the JSON-RPC client to call `chainHead_unstable_continue`.
+    async fn push_item(
+        &mut self,
+        item: methods::ChainHeadStorageResponseItem,
+    ) -> Result<(), StorageOperationError> {
+        if self.pending_items.len() >= STORAGE_ITEMS_BATCH_SIZE {
+            self.flush_items().await;
+            self.send_event(
+                methods::FollowEvent::OperationWaitingForContinue {
+                    operation_id: self.operation_id.clone().into(),
+                },
+                false,
+            )
+            .await;
+            self.on_continue
+                .recv()
+                .await
+                .map_err(|_| StorageOperationError::Interrupted)?;
+        }
+
+        self.pending_items.push(item);
+        Ok(())
+    }
+
+    /// Reports all the items of [`StorageOperation::pending_items`] to the JSON-RPC client.
+    async fn flush_items(&mut self) {
+        let items = std::mem::take(&mut self.pending_items);
+        self.send_event(
+            methods::FollowEvent::OperationStorageItems {
+                operation_id: self.operation_id.clone().into(),
+                items,
+            },
+            false,
+        )
+        .await;
+    }
+
+    async fn send_event(&self, notification: methods::FollowEvent<'static>, is_done: bool) {
+        let _ = self
+            .to_main_task
+            .send(OperationEvent {
+                operation_id: self.operation_id.clone(),
+                notification,
+                is_done,
+            })
+            .await;
+    }
+}
+
+#[derive(derive_more::From)]
+enum StorageOperationError {
+    /// Error while accessing the storage of the block.
+    Access(full_sqlite::StorageAccessError),
+    /// The operation has been stopped by the JSON-RPC client.
+    #[from(ignore)]
+    Interrupted,
+}
+
+/// Builds a [`methods::ChainHeadStorageResponseItem`] containing either the given value or its
+/// hash.
+fn storage_response_item(
+    key: Vec<u8>,
+    value: Vec<u8>,
+    is_hash: bool,
+) -> methods::ChainHeadStorageResponseItem {
+    if is_hash {
+        let hash = blake2_rfc::blake2b::blake2b(32, &[], &value);
+        methods::ChainHeadStorageResponseItem {
+            key: methods::HexString(key),
+            value: None,
+            hash: Some(methods::HexString(hash.as_bytes().to_vec())),
+            closest_descendant_merkle_value: None,
+        }
+    } else {
+        methods::ChainHeadStorageResponseItem {
+            key: methods::HexString(key),
+            value: Some(methods::HexString(value)),
+            hash: None,
+            closest_descendant_merkle_value: None,
+        }
+    }
+}
+
+fn convert_runtime_spec(runtime: &executor::CoreVersion) -> methods::MaybeRuntimeSpec<'_> {
+    let runtime = runtime.decode();
+    methods::MaybeRuntimeSpec::Valid {
+        spec: methods::RuntimeSpec {
+            impl_name: runtime.impl_name.into(),
+            spec_name: runtime.spec_name.into(),
+            impl_version: runtime.impl_version,
+            spec_version: runtime.spec_version,
+            transaction_version: runtime.transaction_version,
+            apis: runtime
+                .apis
+                .map(|api| (methods::HexString(api.name_hash.to_vec()), api.version))
+                .collect(),
+        },
+    }
+}
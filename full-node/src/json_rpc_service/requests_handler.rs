@@ -15,9 +15,18 @@
 // You should have received a copy of the GNU General Public License
 // along with this program.  If not, see <http://www.gnu.org/licenses/>.
 
-use smol::stream::StreamExt as _;
-use smoldot::json_rpc::{methods, service};
-use std::{future::Future, pin::Pin, sync::Arc};
+use crate::{database_thread, runtime_call, transactions_service, util};
+
+use smol::{future, lock::Mutex, stream::StreamExt as _};
+use smoldot::{
+    database::full_sqlite,
+    executor::{runtime_host, vm},
+    header,
+    identity::keystore,
+    json_rpc::{self, methods, service},
+    trie,
+};
+use std::{future::Future, iter, pin::Pin, sync::Arc};
 
 pub struct Config {
     /// Function that can be used to spawn background tasks.
@@ -26,6 +35,26 @@ pub struct Config {
     pub tasks_executor: Arc<dyn Fn(Pin<Box<dyn Future<Output = ()> + Send>>) + Send + Sync>,
 
     pub receiver: async_channel::Receiver<Message>,
+
+    /// Database to access blocks.
+    pub database: Arc<database_thread::DatabaseThread>,
+
+    /// Number of bytes used to encode the block number in headers.
+    pub block_number_bytes: usize,
+
+    /// Hash of the genesis block of the chain.
+    pub genesis_block_hash: [u8; 32],
+
+    /// Runtimes that have been compiled in the past, shared between all the requests handlers.
+    ///
+    /// Indexed by the hash of the runtime code and the number of heap pages.
+    pub runtime_caches: Arc<Mutex<runtime_call::RuntimeCaches>>,
+
+    /// Transactions service of the chain.
+    pub transactions_service: Arc<transactions_service::TransactionsService>,
+
+    /// Keystore of the node.
+    pub keystore: Arc<keystore::Keystore>,
 }
 
 pub enum Message {
@@ -34,10 +63,16 @@ pub enum Message {
 }
 
 pub fn spawn_requests_handler(mut config: Config) {
-    (config.tasks_executor)(Box::pin(async move {
+    (config.tasks_executor.clone())(Box::pin(async move {
         loop {
             match config.receiver.next().await {
                 Some(Message::Request(request)) => match request.request() {
+                    methods::MethodCall::author_rotateKeys {} => {
+                        author_rotate_keys(&config, request).await
+                    }
+                    methods::MethodCall::author_submitExtrinsic { .. } => {
+                        author_submit_extrinsic(&config, request).await
+                    }
                     methods::MethodCall::rpc_methods {} => {
                         request.respond(methods::Response::rpc_methods(methods::RpcMethods {
                             methods: methods::MethodCall::method_names()
@@ -55,16 +90,914 @@ pub fn spawn_requests_handler(mut config: Config) {
                             env!("CARGO_PKG_VERSION").into(),
                         ));
                     }
+                    methods::MethodCall::chain_getBlock { .. } => {
+                        chain_get_block(&config, request).await
+                    }
+                    methods::MethodCall::chain_getBlockHash { .. } => {
+                        chain_get_block_hash(&config, request).await
+                    }
+                    methods::MethodCall::chain_getFinalizedHead {} => {
+                        chain_get_finalized_head(&config, request).await
+                    }
+                    methods::MethodCall::chain_getHeader { .. } => {
+                        chain_get_header(&config, request).await
+                    }
+                    methods::MethodCall::chainHead_unstable_genesisHash {} => {
+                        request.respond(methods::Response::chainHead_unstable_genesisHash(
+                            methods::HashHexString(config.genesis_block_hash),
+                        ));
+                    }
+                    methods::MethodCall::state_call { .. } => state_call(&config, request).await,
+                    methods::MethodCall::state_getKeysPaged { .. } => {
+                        state_get_keys_paged(&config, request).await
+                    }
+                    methods::MethodCall::state_getMetadata { .. } => {
+                        state_get_metadata(&config, request).await
+                    }
+                    methods::MethodCall::state_getRuntimeVersion { .. } => {
+                        state_get_runtime_version(&config, request).await
+                    }
+                    methods::MethodCall::state_getStorage { .. } => {
+                        state_get_storage(&config, request).await
+                    }
+                    methods::MethodCall::state_traceBlock { .. } => {
+                        state_trace_block(&config, request).await
+                    }
+                    methods::MethodCall::system_dryRun { .. } => {
+                        system_dry_run(&config, request).await
+                    }
+                    _ => request.fail(service::ErrorResponse::ServerError(
+                        -32000,
+                        "Not implemented in smoldot yet",
+                    )),
+                },
+                Some(Message::SubscriptionStart(request)) => match request.request() {
+                    methods::MethodCall::author_submitAndWatchExtrinsic { .. }
+                    | methods::MethodCall::transaction_unstable_submitAndWatch { .. } => {
+                        submit_and_watch_transaction(&config, request).await
+                    }
                     _ => request.fail(service::ErrorResponse::ServerError(
                         -32000,
                         "Not implemented in smoldot yet",
                     )),
                 },
-                Some(Message::SubscriptionStart(request)) => request.fail(
-                    service::ErrorResponse::ServerError(-32000, "Not implemented in smoldot yet"),
-                ),
                 None => return,
             }
         }
     }));
 }
+
+/// Handles a call to [`methods::MethodCall::author_rotateKeys`].
+async fn author_rotate_keys(config: &Config, request: service::RequestProcess) {
+    let block_hash = match block_hash_or_best(config, None).await {
+        Ok(h) => h,
+        Err(error) => {
+            request.fail(service::ErrorResponse::ServerError(
+                -32000,
+                &error.to_string(),
+            ));
+            return;
+        }
+    };
+
+    // The runtime generates one key for each of its session key types, saves them in the
+    // keystore, and returns the concatenation of their public keys. The parameter is an
+    // `Option<Vec<u8>>` containing a seed, which isn't supported by the keystore.
+    let output = match runtime_call::runtime_call_with_keystore(
+        &config.database,
+        &config.runtime_caches,
+        &config.keystore,
+        block_hash,
+        "SessionKeys_generate_session_keys",
+        iter::once(&[0u8]),
+    )
+    .await
+    {
+        Ok(output) => output,
+        Err(error) => {
+            request.fail(service::ErrorResponse::ServerError(
+                -32000,
+                &error.to_string(),
+            ));
+            return;
+        }
+    };
+
+    match util::decode_scale_bytes(&output) {
+        Ok(session_keys) => request.respond(methods::Response::author_rotateKeys(
+            methods::HexString(session_keys.to_vec()),
+        )),
+        Err(()) => request.fail(service::ErrorResponse::ServerError(
+            -32000,
+            "Failed to decode the output of the runtime",
+        )),
+    }
+}
+
+/// Handles a call to [`methods::MethodCall::author_submitExtrinsic`].
+async fn author_submit_extrinsic(config: &Config, request: service::RequestProcess) {
+    let methods::MethodCall::author_submitExtrinsic { transaction } = request.request() else {
+        unreachable!()
+    };
+
+    // In Substrate, `author_submitExtrinsic` returns the hash of the transaction.
+    let transaction_hash =
+        <[u8; 32]>::try_from(blake2_rfc::blake2b::blake2b(32, &[], &transaction.0).as_bytes())
+            .unwrap();
+
+    config
+        .transactions_service
+        .submit_transaction(transaction.0)
+        .await;
+
+    request.respond(methods::Response::author_submitExtrinsic(
+        methods::HashHexString(transaction_hash),
+    ));
+}
+
+/// Handles a call to [`methods::MethodCall::author_submitAndWatchExtrinsic`] or to
+/// [`methods::MethodCall::transaction_unstable_submitAndWatch`].
+async fn submit_and_watch_transaction(config: &Config, request: service::SubscriptionStartProcess) {
+    let (transaction, is_legacy) = match request.request() {
+        methods::MethodCall::author_submitAndWatchExtrinsic { transaction } => (transaction, true),
+        methods::MethodCall::transaction_unstable_submitAndWatch { transaction } => {
+            (transaction, false)
+        }
+        _ => unreachable!(),
+    };
+
+    let transaction_updates = config
+        .transactions_service
+        .submit_and_watch_transaction(transaction.0, 16)
+        .await;
+
+    // The notifications are sent from a separate task, as sending a notification waits for the
+    // JSON-RPC client to pull it.
+    (config.tasks_executor)(Box::pin(async move {
+        let mut subscription = request.accept();
+        let subscription_id = subscription.subscription_id().to_owned();
+
+        let mut included_block = None;
+        let mut num_broadcasted_peers = 0;
+
+        loop {
+            let status_update =
+                match future::or(async { Some(transaction_updates.recv().await) }, async {
+                    subscription.wait_until_stale().await;
+                    None
+                })
+                .await
+                {
+                    Some(Ok(status)) => status,
+                    Some(Err(_)) if !is_legacy => break,
+                    Some(Err(_)) => {
+                        // The legacy API has no way to indicate that no new notification is
+                        // expected. Wait for the client to unsubscribe.
+                        subscription.wait_until_stale().await;
+                        break;
+                    }
+                    None => break,
+                };
+
+            let notification = if is_legacy {
+                let result = match status_update {
+                    transactions_service::TransactionStatus::Broadcast(peers) => {
+                        methods::TransactionStatus::Broadcast(
+                            peers.into_iter().map(|peer| peer.to_base58()).collect(),
+                        )
+                    }
+                    transactions_service::TransactionStatus::Validated => continue,
+                    transactions_service::TransactionStatus::IncludedBlockUpdate {
+                        block_hash: Some((block_hash, _)),
+                    } => {
+                        included_block = Some(block_hash);
+                        methods::TransactionStatus::InBlock(methods::HashHexString(block_hash))
+                    }
+                    transactions_service::TransactionStatus::IncludedBlockUpdate {
+                        block_hash: None,
+                    } => match included_block.take() {
+                        Some(block_hash) => methods::TransactionStatus::Retracted(
+                            methods::HashHexString(block_hash),
+                        ),
+                        None => continue,
+                    },
+                    transactions_service::TransactionStatus::Dropped(
+                        transactions_service::DropReason::Finalized { block_hash, .. },
+                    ) => methods::TransactionStatus::Finalized(methods::HashHexString(block_hash)),
+                    transactions_service::TransactionStatus::Dropped(_) => {
+                        methods::TransactionStatus::Dropped
+                    }
+                };
+
+                methods::ServerToClient::author_extrinsicUpdate {
+                    subscription: (&subscription_id).into(),
+                    result,
+                }
+            } else {
+                let result = match status_update {
+                    transactions_service::TransactionStatus::Broadcast(peers) => {
+                        num_broadcasted_peers += peers.len();
+                        methods::TransactionWatchEvent::Broadcasted {
+                            num_peers: u32::try_from(num_broadcasted_peers)
+                                .unwrap_or(u32::max_value()),
+                        }
+                    }
+                    transactions_service::TransactionStatus::Validated => {
+                        methods::TransactionWatchEvent::Validated {}
+                    }
+                    transactions_service::TransactionStatus::IncludedBlockUpdate { block_hash } => {
+                        methods::TransactionWatchEvent::BestChainBlockIncluded {
+                            block: block_hash.map(|(hash, index)| {
+                                methods::TransactionWatchEventBlock {
+                                    hash: methods::HashHexString(hash),
+                                    index: methods::NumberAsString(index),
+                                }
+                            }),
+                        }
+                    }
+                    transactions_service::TransactionStatus::Dropped(
+                        transactions_service::DropReason::Finalized { block_hash, index },
+                    ) => methods::TransactionWatchEvent::Finalized {
+                        block: methods::TransactionWatchEventBlock {
+                            hash: methods::HashHexString(block_hash),
+                            index: methods::NumberAsString(index),
+                        },
+                    },
+                    transactions_service::TransactionStatus::Dropped(
+                        transactions_service::DropReason::GapInChain,
+                    ) => methods::TransactionWatchEvent::Dropped {
+                        error: "gap in chain of blocks".into(),
+                        broadcasted: num_broadcasted_peers != 0,
+                    },
+                    transactions_service::TransactionStatus::Dropped(
+                        transactions_service::DropReason::MaxPendingTransactionsReached,
+                    ) => methods::TransactionWatchEvent::Dropped {
+                        error: "transactions pool full".into(),
+                        broadcasted: num_broadcasted_peers != 0,
+                    },
+                    transactions_service::TransactionStatus::Dropped(
+                        transactions_service::DropReason::Invalid(error),
+                    ) => methods::TransactionWatchEvent::Invalid {
+                        error: error.to_string().into(),
+                    },
+                    transactions_service::TransactionStatus::Dropped(
+                        transactions_service::DropReason::ValidateError(error),
+                    ) => methods::TransactionWatchEvent::Error {
+                        error: error.to_string().into(),
+                    },
+                };
+
+                methods::ServerToClient::transaction_unstable_watchEvent {
+                    subscription: (&subscription_id).into(),
+                    result,
+                }
+            };
+
+            subscription.send_notification(notification).await;
+        }
+    }));
+}
+
+/// Handles a call to [`methods::MethodCall::chain_getBlock`].
+async fn chain_get_block(config: &Config, request: service::RequestProcess) {
+    let methods::MethodCall::chain_getBlock { hash } = request.request() else {
+        unreachable!()
+    };
+
+    // `hash` equal to `None` means "best block".
+    let hash = hash.map(|h| h.0);
+
+    let result = config
+        .database
+        .with_database(move |database| {
+            let hash = match hash {
+                Some(h) => h,
+                None => database.best_block_hash()?,
+            };
+            let Some(header) = database.block_scale_encoded_header(&hash)? else {
+                return Ok(None);
+            };
+            let Some(body) = database.block_extrinsics(&hash)? else {
+                return Ok(None);
+            };
+            Ok::<_, full_sqlite::AccessError>(Some((header, body.collect::<Vec<_>>())))
+        })
+        .await;
+
+    match result {
+        Ok(Some((header, body))) => {
+            let header = match methods::Header::from_scale_encoded_header(
+                &header,
+                config.block_number_bytes,
+            ) {
+                Ok(h) => h,
+                Err(error) => {
+                    request.fail(service::ErrorResponse::ServerError(
+                        -32000,
+                        &format!("Failed to decode block header: {error}"),
+                    ));
+                    return;
+                }
+            };
+
+            request.respond(methods::Response::chain_getBlock(methods::Block {
+                extrinsics: body.into_iter().map(methods::HexString).collect(),
+                header,
+                // Justifications aren't stored in the database.
+                justifications: None,
+            }))
+        }
+        Ok(None) => request.respond_null(),
+        Err(error) => request.fail(service::ErrorResponse::ServerError(
+            -32000,
+            &error.to_string(),
+        )),
+    }
+}
+
+/// Handles a call to [`methods::MethodCall::chain_getBlockHash`].
+async fn chain_get_block_hash(config: &Config, request: service::RequestProcess) {
+    let methods::MethodCall::chain_getBlockHash { height } = request.request() else {
+        unreachable!()
+    };
+
+    let block_number_bytes = config.block_number_bytes;
+    let result = config
+        .database
+        .with_database(move |database| match height {
+            // Note that the database only contains blocks that descend from the finalized
+            // block. If multiple blocks have the given height, the one that is in the chain of
+            // the current best block is the one that must be returned.
+            Some(height) => {
+                let mut candidates = database.block_hash_by_number(height)?.collect::<Vec<_>>();
+                if candidates.len() <= 1 {
+                    return Ok(candidates.pop());
+                }
+
+                let mut iter_hash = database.best_block_hash()?;
+                loop {
+                    if candidates.contains(&iter_hash) {
+                        return Ok(Some(iter_hash));
+                    }
+
+                    let Some(header) = database.block_scale_encoded_header(&iter_hash)? else {
+                        return Ok(None);
+                    };
+                    let Ok(header) = header::decode(&header, block_number_bytes) else {
+                        return Ok(None);
+                    };
+                    if header.number <= height {
+                        return Ok(None);
+                    }
+                    iter_hash = *header.parent_hash;
+                }
+            }
+            None => database.best_block_hash().map(Some),
+        })
+        .await;
+
+    match result {
+        Ok(Some(hash)) => request.respond(methods::Response::chain_getBlockHash(
+            methods::HashHexString(hash),
+        )),
+        Ok(None) => request.respond_null(),
+        Err(error) => request.fail(service::ErrorResponse::ServerError(
+            -32000,
+            &error.to_string(),
+        )),
+    }
+}
+
+/// Handles a call to [`methods::MethodCall::chain_getFinalizedHead`].
+async fn chain_get_finalized_head(config: &Config, request: service::RequestProcess) {
+    match config
+        .database
+        .with_database(|database| database.finalized_block_hash())
+        .await
+    {
+        Ok(hash) => request.respond(methods::Response::chain_getFinalizedHead(
+            methods::HashHexString(hash),
+        )),
+        Err(error) => request.fail(service::ErrorResponse::ServerError(
+            -32000,
+            &error.to_string(),
+        )),
+    }
+}
+
+/// Handles a call to [`methods::MethodCall::chain_getHeader`].
+async fn chain_get_header(config: &Config, request: service::RequestProcess) {
+    let methods::MethodCall::chain_getHeader { hash } = request.request() else {
+        unreachable!()
+    };
+
+    // `hash` equal to `None` means "best block".
+    let hash = hash.map(|h| h.0);
+
+    let result = config
+        .database
+        .with_database(move |database| {
+            let hash = match hash {
+                Some(h) => h,
+                None => database.best_block_hash()?,
+            };
+            database.block_scale_encoded_header(&hash)
+        })
+        .await;
+
+    match result {
+        Ok(Some(header)) => {
+            match methods::Header::from_scale_encoded_header(&header, config.block_number_bytes) {
+                Ok(header) => request.respond(methods::Response::chain_getHeader(header)),
+                Err(error) => request.fail(service::ErrorResponse::ServerError(
+                    -32000,
+                    &format!("Failed to decode block header: {error}"),
+                )),
+            }
+        }
+        Ok(None) => request.respond_null(),
+        Err(error) => request.fail(service::ErrorResponse::ServerError(
+            -32000,
+            &error.to_string(),
+        )),
+    }
+}
+
+/// Handles a call to [`methods::MethodCall::state_call`].
+async fn state_call(config: &Config, request: service::RequestProcess) {
+    let methods::MethodCall::state_call {
+        name,
+        parameters,
+        hash,
+    } = request.request()
+    else {
+        unreachable!()
+    };
+
+    let block_hash = match block_hash_or_best(config, hash.map(|h| h.0)).await {
+        Ok(h) => h,
+        Err(error) => {
+            request.fail(service::ErrorResponse::ServerError(
+                -32000,
+                &error.to_string(),
+            ));
+            return;
+        }
+    };
+
+    match runtime_call::runtime_call(
+        &config.database,
+        &config.runtime_caches,
+        block_hash,
+        &name,
+        iter::once(&parameters.0),
+    )
+    .await
+    {
+        Ok(output) => request.respond(methods::Response::state_call(methods::HexString(output))),
+        Err(error) => request.fail(service::ErrorResponse::ServerError(
+            -32000,
+            &error.to_string(),
+        )),
+    }
+}
+
+/// Handles a call to [`methods::MethodCall::system_dryRun`].
+async fn system_dry_run(config: &Config, request: service::RequestProcess) {
+    let methods::MethodCall::system_dryRun { extrinsic, hash } = request.request() else {
+        unreachable!()
+    };
+
+    let block_hash = match block_hash_or_best(config, hash.map(|h| h.0)).await {
+        Ok(h) => h,
+        Err(error) => {
+            request.fail(service::ErrorResponse::ServerError(
+                -32000,
+                &error.to_string(),
+            ));
+            return;
+        }
+    };
+
+    // The encoding of the output of the runtime depends on the version of the `BlockBuilder` API.
+    let api_version =
+        match runtime_call::runtime_of_block(&config.database, &config.runtime_caches, block_hash)
+            .await
+        {
+            Ok((cache_key, runtime)) => {
+                let api_version = runtime
+                    .runtime_version()
+                    .decode()
+                    .apis
+                    .find_version("BlockBuilder");
+                config.runtime_caches.lock().await.put(cache_key, runtime);
+                api_version
+            }
+            Err(error) => {
+                request.fail(service::ErrorResponse::ServerError(
+                    -32000,
+                    &error.to_string(),
+                ));
+                return;
+            }
+        };
+    let Some(api_version) =
+        api_version.filter(|v| json_rpc::dry_run::SUPPORTED_API_VERSIONS.contains(v))
+    else {
+        request.fail(service::ErrorResponse::ServerError(
+            -32000,
+            "Unsupported version of the BlockBuilder runtime API",
+        ));
+        return;
+    };
+
+    // The extrinsic is applied directly on top of the storage of the block, without initializing
+    // a new block beforehand, in the same way as Substrate does.
+    let output = match runtime_call::runtime_call(
+        &config.database,
+        &config.runtime_caches,
+        block_hash,
+        json_rpc::dry_run::APPLY_EXTRINSIC_FUNCTION_NAME,
+        iter::once(&extrinsic.0),
+    )
+    .await
+    {
+        Ok(output) => output,
+        Err(error) => {
+            request.fail(service::ErrorResponse::ServerError(
+                -32000,
+                &error.to_string(),
+            ));
+            return;
+        }
+    };
+
+    // The result is always returned in the format of the latest version of the API.
+    match json_rpc::dry_run::decode_apply_extrinsic_result(&output, api_version) {
+        Ok(result) => request.respond(methods::Response::system_dryRun(methods::HexString(
+            json_rpc::dry_run::encode_apply_extrinsic_result(&result),
+        ))),
+        Err(error) => request.fail(service::ErrorResponse::ServerError(
+            -32000,
+            &format!("Failed to decode runtime output: {error}"),
+        )),
+    }
+}
+
+/// Handles a call to [`methods::MethodCall::state_getKeysPaged`].
+async fn state_get_keys_paged(config: &Config, request: service::RequestProcess) {
+    let methods::MethodCall::state_getKeysPaged {
+        prefix,
+        count,
+        start_key,
+        hash,
+    } = request.request()
+    else {
+        unreachable!()
+    };
+
+    // `hash` equal to `None` means "best block".
+    let hash = hash.map(|h| h.0);
+
+    // A prefix of `None` means "empty".
+    let prefix_nibbles = trie::bytes_to_nibbles(prefix.map_or(Vec::new(), |p| p.0).into_iter())
+        .map(u8::from)
+        .collect::<Vec<_>>();
+
+    // The start key is excluded from the results. Since the database functions only support
+    // an "or equal" mode, we append a `0` nibble at the end of the start key.
+    let start_key_nibbles = start_key.map(|start_key| {
+        trie::bytes_to_nibbles(start_key.0.into_iter())
+            .map(u8::from)
+            .chain(iter::once(0))
+            .collect::<Vec<_>>()
+    });
+
+    let result = config
+        .database
+        .with_database(move |database| {
+            let hash = match hash {
+                Some(h) => h,
+                None => database.best_block_hash()?,
+            };
+
+            let mut out = Vec::with_capacity(usize::try_from(count).unwrap_or(usize::max_value()));
+            let mut key_iter = match start_key_nibbles {
+                Some(start) if start > prefix_nibbles => start,
+                _ => prefix_nibbles.clone(),
+            };
+
+            while out.len() < usize::try_from(count).unwrap_or(usize::max_value()) {
+                let Some(next) = database.block_storage_next_key(
+                    &hash,
+                    iter::empty::<iter::Empty<u8>>(),
+                    key_iter.iter().copied(),
+                    prefix_nibbles.iter().copied(),
+                    false,
+                )?
+                else {
+                    break;
+                };
+
+                out.push(methods::HexString(
+                    trie::nibbles_to_bytes_truncate(
+                        next.iter().map(|n| trie::Nibble::try_from(*n).unwrap()),
+                    )
+                    .collect::<Vec<_>>(),
+                ));
+
+                key_iter = next;
+                key_iter.push(0);
+            }
+
+            Ok::<_, full_sqlite::StorageAccessError>(out)
+        })
+        .await;
+
+    match result {
+        Ok(keys) => request.respond(methods::Response::state_getKeysPaged(keys)),
+        Err(error) => request.fail(service::ErrorResponse::ServerError(
+            -32000,
+            &error.to_string(),
+        )),
+    }
+}
+
+/// Handles a call to [`methods::MethodCall::state_getMetadata`].
+async fn state_get_metadata(config: &Config, request: service::RequestProcess) {
+    let methods::MethodCall::state_getMetadata { hash } = request.request() else {
+        unreachable!()
+    };
+
+    let block_hash = match block_hash_or_best(config, hash.map(|h| h.0)).await {
+        Ok(h) => h,
+        Err(error) => {
+            request.fail(service::ErrorResponse::ServerError(
+                -32000,
+                &error.to_string(),
+            ));
+            return;
+        }
+    };
+
+    let result = runtime_call::runtime_call(
+        &config.database,
+        &config.runtime_caches,
+        block_hash,
+        "Metadata_metadata",
+        iter::empty::<Vec<u8>>(),
+    )
+    .await;
+    let result = result
+        .as_ref()
+        .map(|output| methods::remove_metadata_length_prefix(output));
+
+    match result {
+        Ok(Ok(metadata)) => request.respond(methods::Response::state_getMetadata(
+            methods::HexString(metadata.to_vec()),
+        )),
+        Ok(Err(error)) => request.fail(service::ErrorResponse::ServerError(
+            -32000,
+            &format!("Failed to decode metadata from runtime. Error: {error}"),
+        )),
+        Err(error) => request.fail(service::ErrorResponse::ServerError(
+            -32000,
+            &error.to_string(),
+        )),
+    }
+}
+
+/// Handles a call to [`methods::MethodCall::state_getRuntimeVersion`].
+async fn state_get_runtime_version(config: &Config, request: service::RequestProcess) {
+    let methods::MethodCall::state_getRuntimeVersion { at } = request.request() else {
+        unreachable!()
+    };
+
+    let block_hash = match block_hash_or_best(config, at.map(|h| h.0)).await {
+        Ok(h) => h,
+        Err(error) => {
+            request.fail(service::ErrorResponse::ServerError(
+                -32000,
+                &error.to_string(),
+            ));
+            return;
+        }
+    };
+
+    let (cache_key, runtime) =
+        match runtime_call::runtime_of_block(&config.database, &config.runtime_caches, block_hash)
+            .await
+        {
+            Ok(rt) => rt,
+            Err(error) => {
+                request.fail(service::ErrorResponse::ServerError(
+                    -32000,
+                    &error.to_string(),
+                ));
+                return;
+            }
+        };
+
+    {
+        let runtime_spec = runtime.runtime_version().decode();
+        request.respond(methods::Response::state_getRuntimeVersion(
+            methods::RuntimeVersion {
+                spec_name: runtime_spec.spec_name.into(),
+                impl_name: runtime_spec.impl_name.into(),
+                authoring_version: u64::from(runtime_spec.authoring_version),
+                spec_version: u64::from(runtime_spec.spec_version),
+                impl_version: u64::from(runtime_spec.impl_version),
+                transaction_version: runtime_spec.transaction_version.map(u64::from),
+                state_version: runtime_spec.state_version.map(u8::from).map(u64::from),
+                apis: runtime_spec
+                    .apis
+                    .map(|api| (methods::HexString(api.name_hash.to_vec()), api.version))
+                    .collect(),
+            },
+        ));
+    }
+
+    config.runtime_caches.lock().await.put(cache_key, runtime);
+}
+
+/// Handles a call to [`methods::MethodCall::state_getStorage`].
+async fn state_get_storage(config: &Config, request: service::RequestProcess) {
+    let methods::MethodCall::state_getStorage { key, hash } = request.request() else {
+        unreachable!()
+    };
+
+    // `hash` equal to `None` means "best block".
+    let hash = hash.map(|h| h.0);
+
+    let key_nibbles = trie::bytes_to_nibbles(key.0.into_iter())
+        .map(u8::from)
+        .collect::<Vec<_>>();
+
+    let result = config
+        .database
+        .with_database(move |database| {
+            let hash = match hash {
+                Some(h) => h,
+                None => database.best_block_hash()?,
+            };
+            database.block_storage_get(
+                &hash,
+                iter::empty::<iter::Empty<u8>>(),
+                key_nibbles.iter().copied(),
+            )
+        })
+        .await;
+
+    match result {
+        Ok(Some((value, _))) => request.respond(methods::Response::state_getStorage(
+            methods::HexString(value),
+        )),
+        Ok(None) => request.respond_null(),
+        Err(error) => request.fail(service::ErrorResponse::ServerError(
+            -32000,
+            &error.to_string(),
+        )),
+    }
+}
+
+/// Handles a call to [`methods::MethodCall::state_traceBlock`].
+async fn state_trace_block(config: &Config, request: service::RequestProcess) {
+    let methods::MethodCall::state_traceBlock { block } = request.request() else {
+        unreachable!()
+    };
+
+    let result = config
+        .database
+        .with_database(move |database| {
+            let Some(header) = database.block_scale_encoded_header(&block.0)? else {
+                return Ok(None);
+            };
+            let Some(body) = database.block_extrinsics(&block.0)? else {
+                return Ok(None);
+            };
+            Ok::<_, full_sqlite::AccessError>(Some((header, body.collect::<Vec<_>>())))
+        })
+        .await;
+
+    let (header, body) = match result {
+        Ok(Some(b)) => b,
+        Ok(None) => {
+            request.fail(service::ErrorResponse::ServerError(-32000, "Unknown block"));
+            return;
+        }
+        Err(error) => {
+            request.fail(service::ErrorResponse::ServerError(
+                -32000,
+                &error.to_string(),
+            ));
+            return;
+        }
+    };
+
+    let (parent_hash, execute_block_parameter) =
+        match runtime_call::execute_block_parameter(&header, config.block_number_bytes, &body) {
+            Ok(p) => p,
+            Err(error) => {
+                request.fail(service::ErrorResponse::ServerError(
+                    -32000,
+                    &format!("Failed to decode block header: {error}"),
+                ));
+                return;
+            }
+        };
+
+    let (result, execution_trace) = runtime_call::runtime_call_traced(
+        &config.database,
+        &config.runtime_caches,
+        parent_hash,
+        "Core_execute_block",
+        iter::once(&execute_block_parameter),
+    )
+    .await;
+
+    let Some(execution_trace) = execution_trace else {
+        // The runtime hasn't started executing.
+        let error = result.err().unwrap();
+        request.fail(service::ErrorResponse::ServerError(
+            -32000,
+            &error.to_string(),
+        ));
+        return;
+    };
+
+    request.respond(methods::Response::state_traceBlock(methods::BlockTrace {
+        block_hash: block,
+        host_function_calls: execution_trace
+            .host_function_calls
+            .into_iter()
+            .map(|call| methods::BlockTraceHostFunctionCall {
+                name: call.name.to_owned(),
+                parameters: call
+                    .parameters
+                    .into_iter()
+                    .map(|param| match param {
+                        vm::WasmValue::I32(v) => i64::from(v),
+                        vm::WasmValue::I64(v) => v,
+                    })
+                    .collect(),
+                duration_nanos: u64::try_from(call.duration.as_nanos()).unwrap_or(u64::MAX),
+            })
+            .collect(),
+        storage_accesses: execution_trace
+            .storage_accesses
+            .into_iter()
+            .map(|access| {
+                let (ty, value_size) = match access.kind {
+                    runtime_host::StorageAccessKind::Read { value_size } => {
+                        (methods::BlockTraceStorageAccessType::Read, value_size)
+                    }
+                    runtime_host::StorageAccessKind::Write { value_size } => {
+                        (methods::BlockTraceStorageAccessType::Write, value_size)
+                    }
+                    runtime_host::StorageAccessKind::Append { value_size } => (
+                        methods::BlockTraceStorageAccessType::Append,
+                        Some(value_size),
+                    ),
+                    runtime_host::StorageAccessKind::ClearPrefix => {
+                        (methods::BlockTraceStorageAccessType::ClearPrefix, None)
+                    }
+                };
+                methods::BlockTraceStorageAccess {
+                    ty,
+                    child_trie: access.child_trie.map(methods::HexString),
+                    key: methods::HexString(access.key),
+                    value_size: value_size.map(|s| u64::try_from(s).unwrap()),
+                }
+            })
+            .collect(),
+        allocator: methods::BlockTraceAllocatorStats {
+            bytes_allocated: execution_trace.allocator_stats.bytes_allocated,
+            bytes_allocated_peak: execution_trace.allocator_stats.bytes_allocated_peak,
+            bytes_allocated_sum: execution_trace.allocator_stats.bytes_allocated_sum,
+            address_space_used: execution_trace.allocator_stats.address_space_used,
+            address_space_used_peak: execution_trace.allocator_stats.address_space_used_peak,
+            fragmented_bytes: execution_trace.allocator_stats.fragmented_bytes,
+            allocations_per_order: execution_trace
+                .allocator_stats
+                .allocations_per_order
+                .to_vec(),
+        },
+        error: result.err().map(|error| error.to_string()),
+    }));
+}
+
+/// Returns `block_hash` if it is `Some`, or the hash of the current best block otherwise.
+async fn block_hash_or_best(
+    config: &Config,
+    block_hash: Option<[u8; 32]>,
+) -> Result<[u8; 32], full_sqlite::AccessError> {
+    match block_hash {
+        Some(h) => Ok(h),
+        None => {
+            config
+                .database
+                .with_database(|database| database.best_block_hash())
+                .await
+        }
+    }
+}
@@ -28,19 +28,42 @@ use smoldot::{
     identity::keystore,
     informant::HashDisplay,
     libp2p::{
-        connection, multiaddr,
+        connection::{self, tls},
+        memory, multiaddr,
         peer_id::{self, PeerId},
     },
     trie,
 };
-use std::{array, borrow::Cow, iter, mem, net::SocketAddr, path::PathBuf, sync::Arc};
+use std::{
+    array,
+    borrow::Cow,
+    iter, mem,
+    net::SocketAddr,
+    num::{NonZeroU32, NonZeroUsize},
+    path::PathBuf,
+    sync::Arc,
+    time::{Duration, Instant, SystemTime},
+};
 
+mod compiled_runtimes_cache;
 mod consensus_service;
 mod database_thread;
+mod grandpa_service;
+mod http_client;
 mod jaeger_service;
 mod json_rpc_service;
 mod network_service;
+mod offchain_worker_service;
+mod runtime_call;
+mod runtime_upgrade;
+mod storage_diff;
+mod transactions_service;
 mod util;
+mod warp_sync;
+
+pub use network_service::{InvalidWebRtcCertificateError, WebRtcCertificate};
+pub use runtime_upgrade::{RuntimeUpgradeError, RuntimeUpgradeReport, UpgradeChecks, Weight};
+pub use storage_diff::{BlockStorageDiffError, StorageDiffEntry};
 
 pub struct Config<'a> {
     /// Chain to connect to.
@@ -50,8 +73,23 @@ pub struct Config<'a> {
     pub relay_chain: Option<ChainConfig<'a>>,
     /// Ed25519 private key of network identity.
     pub libp2p_key: Box<[u8; 32]>,
-    /// List of addresses to listen on.
+    /// List of addresses to listen on. Addresses ending with `/ws` accept WebSocket connections,
+    /// and addresses ending with `/webrtc-direct` accept WebRTC connections. Addresses of the
+    /// form `/memory/...` listen on [`Config::memory_network`].
     pub listen_addresses: Vec<multiaddr::Multiaddr>,
+    /// In-process network that `/memory/...` addresses refer to. Makes it possible to connect
+    /// multiple nodes running within the same process without going through the operating
+    /// system. If `None`, such addresses aren't supported.
+    pub memory_network: Option<memory::MemoryNetwork>,
+    /// Source of the time of the node, used for example to determine when to author blocks and
+    /// when network requests time out. If `None`, the system clock is used.
+    ///
+    /// Combined with [`Config::memory_network`], this makes it possible to run multiple nodes
+    /// within the same process whose time is driven by a simulated clock.
+    pub time_source: Option<Arc<dyn TimeSource + Send + Sync>>,
+    /// Certificate used by the WebRTC listeners. Its hash is part of the WebRTC listening
+    /// addresses, and as such it should be the same between restarts.
+    pub webrtc_certificate: WebRtcCertificate,
     /// Configuration of the JSON-RPC server. If `None`, no server is started.
     pub json_rpc: Option<JsonRpcConfig>,
     /// Function that can be used to spawn background tasks.
@@ -62,6 +100,24 @@ pub struct Config<'a> {
     pub log_callback: Arc<dyn LogCallback + Send + Sync>,
     /// Address of a Jaeger agent to send traces to. If `None`, do not send Jaeger traces.
     pub jaeger_agent: Option<SocketAddr>,
+    /// Client used to perform the HTTP requests of the off-chain workers. If `None`, a default
+    /// client that only supports plain-text `http://` URIs is used.
+    pub offchain_http_client: Option<Arc<dyn HttpClient + Send + Sync>>,
+    /// How to sync the chain if the database doesn't contain any block other than the genesis.
+    pub sync_mode: SyncMode,
+}
+
+/// See [`Config::sync_mode`].
+#[derive(Debug, Copy, Clone, PartialEq, Eq)]
+pub enum SyncMode {
+    /// Download and execute every block since the genesis.
+    Full,
+    /// Warp sync to a recent finalized block using GrandPa warp sync proofs, download the
+    /// entire storage of this block, then continue syncing in full mode from there.
+    ///
+    /// Ignored if the database already contains blocks other than the genesis. Falls back to
+    /// [`SyncMode::Full`] if the chain doesn't support warp syncing.
+    Warp,
 }
 
 /// See [`Config::json_rpc`].
@@ -88,6 +144,85 @@ impl<T: ?Sized + Fn(LogLevel, String)> LogCallback for T {
     }
 }
 
+/// Performs the HTTP requests of the off-chain workers.
+///
+/// See [`Config::offchain_http_client`].
+pub trait HttpClient {
+    /// Sends the given request to the server and returns the response, or `Err` in case of
+    /// I/O error.
+    ///
+    /// The off-chain worker might stop polling the returned future if its deadline is reached.
+    fn request(&self, request: HttpRequest)
+        -> future::BoxFuture<'static, Result<HttpResponse, ()>>;
+}
+
+/// Source of the current time and of the timers of the node.
+///
+/// See [`Config::time_source`].
+pub trait TimeSource {
+    /// Returns the time elapsed since the UNIX epoch.
+    fn now_from_unix_epoch(&self) -> Duration;
+
+    /// Returns the current value of a monotonic clock. Values returned by this function are
+    /// only ever compared with each other.
+    fn now(&self) -> Instant;
+
+    /// Returns a future that becomes ready once [`TimeSource::now`] reaches `when`.
+    fn sleep_until(&self, when: Instant) -> future::BoxFuture<'static, ()>;
+
+    /// Returns a future that becomes ready after the given duration has elapsed.
+    fn sleep(&self, duration: Duration) -> future::BoxFuture<'static, ()> {
+        self.sleep_until(self.now() + duration)
+    }
+}
+
+/// Implementation of [`TimeSource`] that uses the system clock. Used if [`Config::time_source`]
+/// is `None`.
+struct SystemTimeSource;
+
+impl TimeSource for SystemTimeSource {
+    fn now_from_unix_epoch(&self) -> Duration {
+        // A system clock set before the UNIX epoch is considered as being at the UNIX epoch.
+        SystemTime::now()
+            .duration_since(SystemTime::UNIX_EPOCH)
+            .unwrap_or(Duration::new(0, 0))
+    }
+
+    fn now(&self) -> Instant {
+        Instant::now()
+    }
+
+    fn sleep_until(&self, when: Instant) -> future::BoxFuture<'static, ()> {
+        Box::pin(async move {
+            smol::Timer::at(when).await;
+        })
+    }
+}
+
+/// HTTP request passed to [`HttpClient::request`].
+#[derive(Debug, Clone)]
+pub struct HttpRequest {
+    /// HTTP method of the request, such as `GET` or `POST`.
+    pub method: String,
+    /// URI the request must be sent to.
+    pub uri: String,
+    /// List of names and values of the headers of the request.
+    pub headers: Vec<(String, String)>,
+    /// Body of the request.
+    pub body: Vec<u8>,
+}
+
+/// HTTP response returned by [`HttpClient::request`].
+#[derive(Debug, Clone)]
+pub struct HttpResponse {
+    /// HTTP status code of the response.
+    pub status_code: u16,
+    /// List of names and values of the headers of the response.
+    pub headers: Vec<(String, String)>,
+    /// Body of the response.
+    pub body: Vec<u8>,
+}
+
 /// Log level of a log entry.
 #[derive(Debug)]
 pub enum LogLevel {
@@ -111,6 +246,8 @@ pub struct ChainConfig<'a> {
     pub sqlite_database_path: Option<PathBuf>,
     /// Maximum size, in bytes, of the cache SQLite uses.
     pub sqlite_cache_size: usize,
+    /// Which storage the database keeps. See [`full_sqlite::Config::pruning`].
+    pub sqlite_pruning: full_sqlite::PruningMode,
     /// Path to the directory where cryptographic keys are stored on disk.
     ///
     /// If `None`, no keys are stored in disk.
@@ -123,6 +260,10 @@ pub struct Client {
     json_rpc_service: Option<json_rpc_service::JsonRpcService>,
     consensus_service: Arc<consensus_service::ConsensusService>,
     relay_chain_consensus_service: Option<Arc<consensus_service::ConsensusService>>,
+    /// Only kept alive in order for the GrandPa voter to keep running.
+    _grandpa_service: Arc<grandpa_service::GrandpaService>,
+    /// Only kept alive in order for the off-chain workers to keep running.
+    _offchain_worker_service: Arc<offchain_worker_service::OffchainWorkerService>,
     network_service: Arc<network_service::NetworkService>,
     network_known_best: Arc<Mutex<Option<u64>>>,
 }
@@ -156,6 +297,31 @@ impl Client {
         self.consensus_service.sync_state().await
     }
 
+    /// Waits until the summary of the state of the chain, as returned by [`Client::sync_state`],
+    /// matches the given condition, and returns it.
+    ///
+    /// The condition is checked again every time a block is imported or finalized.
+    pub async fn wait_sync_state(
+        &self,
+        mut condition: impl FnMut(&consensus_service::SyncState) -> bool,
+    ) -> consensus_service::SyncState {
+        loop {
+            let subscription = self.consensus_service.subscribe_all(16).await;
+            loop {
+                let state = self.consensus_service.sync_state().await;
+                if condition(&state) {
+                    return state;
+                }
+
+                // The channel is closed if it is full, in which case a new subscription is
+                // created.
+                if subscription.new_blocks.recv().await.is_err() {
+                    break;
+                }
+            }
+        }
+    }
+
     // TODO: not the best API
     pub async fn relay_chain_sync_state(&self) -> Option<consensus_service::SyncState> {
         if let Some(s) = &self.relay_chain_consensus_service {
@@ -195,63 +361,82 @@ pub async fn start(mut config: Config<'_>) -> Client {
         format!("sqlite-version; version={}", full_sqlite::sqlite_version()),
     );
 
+    let time_source = config
+        .time_source
+        .clone()
+        .unwrap_or_else(|| Arc::new(SystemTimeSource));
+
     let (database, database_existed) = {
+        let compiled_runtimes_cache = compiled_runtimes_cache(&config.chain.sqlite_database_path);
         let (db, existed) = open_database(
             &chain_spec,
             genesis_chain_information.as_ref(),
             config.chain.sqlite_database_path,
             config.chain.sqlite_cache_size,
+            config.chain.sqlite_pruning,
         )
-        .await;
+        .await
+        .expect("Failed to initialize the database from the chain specs");
 
-        (Arc::new(database_thread::DatabaseThread::from(db)), existed)
+        let database = database_thread::DatabaseThread::from(db)
+            .with_compiled_runtimes_cache(compiled_runtimes_cache);
+        (Arc::new(database), existed)
     };
 
     let relay_chain_database = if let Some(relay_chain) = &config.relay_chain {
-        Some(Arc::new(database_thread::DatabaseThread::from(
+        let database = database_thread::DatabaseThread::from(
             open_database(
                 relay_chain_spec.as_ref().unwrap(),
                 relay_genesis_chain_information.as_ref().unwrap().as_ref(),
                 relay_chain.sqlite_database_path.clone(),
                 relay_chain.sqlite_cache_size,
+                relay_chain.sqlite_pruning,
             )
             .await
+            .expect("Failed to initialize the database from the relay chain specs")
             .0,
-        )))
+        )
+        .with_compiled_runtimes_cache(compiled_runtimes_cache(&relay_chain.sqlite_database_path));
+        Some(Arc::new(database))
     } else {
         None
     };
 
-    let database_finalized_block_hash = database
-        .with_database(|db| db.finalized_block_hash().unwrap())
-        .await;
-    let database_finalized_block_number = header::decode(
-        &database
-            .with_database(move |db| {
-                db.block_scale_encoded_header(&database_finalized_block_hash)
-                    .unwrap()
-                    .unwrap()
-            })
-            .await,
-        chain_spec.block_number_bytes().into(),
-    )
-    .unwrap()
-    .number;
+    let genesis_block_hash = genesis_chain_information
+        .as_ref()
+        .finalized_block_header
+        .hash(chain_spec.block_number_bytes().into());
+
+    // Warp syncing is only performed if the database doesn't contain anything other than the
+    // genesis block.
+    let warp_sync_needed = config.sync_mode == SyncMode::Warp
+        && database
+            .with_database(|db| db.finalized_block_hash().unwrap())
+            .await
+            == genesis_block_hash;
 
-    let noise_key = {
+    let new_noise_key = |libp2p_key: &[u8; 32]| {
         let mut noise_static_key = zeroize::Zeroizing::new([0u8; 32]);
         rand::thread_rng().fill_bytes(&mut *noise_static_key);
-        connection::NoiseKey::new(&config.libp2p_key, &noise_static_key)
+        connection::NoiseKey::new(libp2p_key, &noise_static_key)
+    };
+    let noise_key = new_noise_key(&config.libp2p_key);
+    let warp_sync_noise_key = if warp_sync_needed {
+        Some(new_noise_key(&config.libp2p_key))
+    } else {
+        None
+    };
+    // Certificate presented at the TLS layer of QUIC connections. Just like the Noise key, it is
+    // derived from the libp2p key, but uses a randomly-generated key of its own.
+    let quic_certificate = {
+        let mut certificate_key = zeroize::Zeroizing::new([0u8; 32]);
+        rand::thread_rng().fill_bytes(&mut *certificate_key);
+        tls::generate_certificate(&config.libp2p_key, &certificate_key)
     };
     zeroize::Zeroize::zeroize(&mut *config.libp2p_key);
     let local_peer_id =
         peer_id::PublicKey::Ed25519(*noise_key.libp2p_public_ed25519_key()).into_peer_id();
 
-    let genesis_block_hash = genesis_chain_information
-        .as_ref()
-        .finalized_block_header
-        .hash(chain_spec.block_number_bytes().into());
-
     let jaeger_service = jaeger_service::JaegerService::new(jaeger_service::Config {
         tasks_executor: &mut |task| (config.tasks_executor)(task),
         service_name: local_peer_id.to_string(),
@@ -260,10 +445,119 @@ pub async fn start(mut config: Config<'_>) -> Client {
     .await
     .unwrap();
 
+    let bootstrap_nodes = {
+        let mut list = Vec::with_capacity(
+            chain_spec.boot_nodes().len() + config.chain.additional_bootnodes.len(),
+        );
+
+        for node in chain_spec.boot_nodes() {
+            match node {
+                chain_spec::Bootnode::UnrecognizedFormat(raw) => {
+                    panic!("Failed to parse bootnode in chain specification: {raw}")
+                }
+                chain_spec::Bootnode::Parsed { multiaddr, peer_id } => {
+                    let multiaddr: multiaddr::Multiaddr = match multiaddr.parse() {
+                        Ok(a) => a,
+                        Err(_) => {
+                            panic!("Failed to parse bootnode in chain specification: {multiaddr}")
+                        }
+                    };
+                    let peer_id = PeerId::from_bytes(peer_id.to_vec()).unwrap();
+                    list.push((peer_id, multiaddr));
+                }
+            }
+        }
+
+        list.extend(config.chain.additional_bootnodes);
+        list
+    };
+
+    if let Some(warp_sync_noise_key) = warp_sync_noise_key {
+        // The warp syncing uses a separate network service whose events aren't shared with
+        // anything else. This service is destroyed once the warp syncing is finished.
+        let (warp_sync_network_service, warp_sync_network_events_receivers) =
+            network_service::NetworkService::new(network_service::Config {
+                listen_addresses: Vec::new(),
+                memory_network: config.memory_network.clone(),
+                num_events_receivers: 1,
+                chains: vec![network_service::ChainConfig {
+                    fork_id: chain_spec.fork_id().map(|n| n.to_owned()),
+                    block_number_bytes: usize::from(chain_spec.block_number_bytes()),
+                    database: database.clone(),
+                    has_grandpa_protocol: matches!(
+                        genesis_chain_information.as_ref().finality,
+                        chain::chain_information::ChainInformationFinalityRef::Grandpa { .. }
+                    ),
+                    genesis_block_hash,
+                    best_block: (0, genesis_block_hash),
+                    bootstrap_nodes: bootstrap_nodes.clone(),
+                }],
+                identify_agent_version: concat!(
+                    env!("CARGO_PKG_NAME"),
+                    " ",
+                    env!("CARGO_PKG_VERSION")
+                )
+                .to_owned(),
+                noise_key: warp_sync_noise_key,
+                webrtc_certificate: config.webrtc_certificate.clone(),
+                quic_certificate: quic_certificate.clone(),
+                tasks_executor: {
+                    let executor = config.tasks_executor.clone();
+                    Box::new(move |task| executor(task))
+                },
+                log_callback: config.log_callback.clone(),
+                time_source: time_source.clone(),
+                jaeger_service: jaeger_service.clone(),
+            })
+            .await
+            .unwrap();
+
+        match warp_sync::warp_sync(warp_sync::Config {
+            log_callback: config.log_callback.clone(),
+            time_source: time_source.clone(),
+            network_service: (warp_sync_network_service, 0),
+            network_events_receiver: warp_sync_network_events_receivers
+                .into_iter()
+                .next()
+                .unwrap(),
+            database: database.clone(),
+            genesis_chain_information: genesis_chain_information.clone(),
+            block_number_bytes: usize::from(chain_spec.block_number_bytes()),
+        })
+        .await
+        {
+            Ok(()) => {}
+            Err(warp_sync::WarpSyncError::Unsupported(error)) => {
+                config.log_callback.log(
+                    LogLevel::Warn,
+                    format!("warp-sync-unsupported; error={error}; fallback=full"),
+                );
+            }
+            Err(error) => panic!("Failed to warp sync: {error}"),
+        }
+    }
+
+    let database_finalized_block_hash = database
+        .with_database(|db| db.finalized_block_hash().unwrap())
+        .await;
+    let database_finalized_block_number = header::decode(
+        &database
+            .with_database(move |db| {
+                db.block_scale_encoded_header(&database_finalized_block_hash)
+                    .unwrap()
+                    .unwrap()
+            })
+            .await,
+        chain_spec.block_number_bytes().into(),
+    )
+    .unwrap()
+    .number;
+
     let (network_service, network_events_receivers) =
         network_service::NetworkService::new(network_service::Config {
             listen_addresses: config.listen_addresses,
-            num_events_receivers: 2 + if relay_chain_database.is_some() { 1 } else { 0 },
+            memory_network: config.memory_network,
+            num_events_receivers: 4 + if relay_chain_database.is_some() { 1 } else { 0 },
             chains: iter::once(network_service::ChainConfig {
                 fork_id: chain_spec.fork_id().map(|n| n.to_owned()),
                 block_number_bytes: usize::from(chain_spec.block_number_bytes()),
@@ -284,32 +578,7 @@ pub async fn start(mut config: Config<'_>) -> Client {
                         })
                         .await
                 },
-                bootstrap_nodes: {
-                    let mut list = Vec::with_capacity(
-                        chain_spec.boot_nodes().len() + config.chain.additional_bootnodes.len(),
-                    );
-
-                    for node in chain_spec.boot_nodes() {
-                        match node {
-                            chain_spec::Bootnode::UnrecognizedFormat(raw) => {
-                                panic!("Failed to parse bootnode in chain specification: {raw}")
-                            }
-                            chain_spec::Bootnode::Parsed { multiaddr, peer_id } => {
-                                let multiaddr: multiaddr::Multiaddr = match multiaddr.parse() {
-                                    Ok(a) => a,
-                                    Err(_) => panic!(
-                                        "Failed to parse bootnode in chain specification: {multiaddr}"
-                                    ),
-                                };
-                                let peer_id = PeerId::from_bytes(peer_id.to_vec()).unwrap();
-                                list.push((peer_id, multiaddr));
-                            }
-                        }
-                    }
-
-                    list.extend(config.chain.additional_bootnodes);
-                    list
-                },
+                bootstrap_nodes,
             })
             .chain(
                 if let Some(relay_chains_specs) = &relay_chain_spec {
@@ -370,11 +639,14 @@ pub async fn start(mut config: Config<'_>) -> Client {
             .collect(),
             identify_agent_version: concat!(env!("CARGO_PKG_NAME"), " ", env!("CARGO_PKG_VERSION")).to_owned(),
             noise_key,
+            webrtc_certificate: config.webrtc_certificate,
+            quic_certificate,
             tasks_executor: {
                 let executor = config.tasks_executor.clone();
                 Box::new(move |task| executor(task))
             },
             log_callback: config.log_callback.clone(),
+            time_source: time_source.clone(),
             jaeger_service: jaeger_service.clone(),
         })
         .await
@@ -393,23 +665,73 @@ pub async fn start(mut config: Config<'_>) -> Client {
         keystore
     });
 
+    let (block_author_transactions_tx, block_author_transactions_rx) = async_channel::bounded(4);
+
     let consensus_service = consensus_service::ConsensusService::new(consensus_service::Config {
         tasks_executor: {
             let executor = config.tasks_executor.clone();
             Box::new(move |task| executor(task))
         },
         log_callback: config.log_callback.clone(),
+        time_source: time_source.clone(),
         genesis_block_hash,
         network_events_receiver: network_events_receivers.next().unwrap(),
         network_service: (network_service.clone(), 0),
-        database,
+        database: database.clone(),
         block_number_bytes: usize::from(chain_spec.block_number_bytes()),
-        keystore,
+        keystore: keystore.clone(),
         jaeger_service: jaeger_service.clone(),
         slot_duration_author_ratio: 43691_u16,
+        block_author_transactions: Some(block_author_transactions_tx),
     })
     .await;
 
+    let grandpa_service = grandpa_service::GrandpaService::new(grandpa_service::Config {
+        tasks_executor: config.tasks_executor.clone(),
+        log_callback: config.log_callback.clone(),
+        time_source: time_source.clone(),
+        consensus_service: consensus_service.clone(),
+        network_service: (network_service.clone(), 0),
+        network_events_receiver: network_events_receivers.next().unwrap(),
+        database: database.clone(),
+        keystore: keystore.clone(),
+        block_number_bytes: usize::from(chain_spec.block_number_bytes()),
+        gossip_duration: Duration::from_secs(1),
+    })
+    .await;
+
+    let transactions_service =
+        transactions_service::TransactionsService::new(transactions_service::Config {
+            tasks_executor: config.tasks_executor.clone(),
+            log_callback: config.log_callback.clone(),
+            consensus_service: consensus_service.clone(),
+            network_service: (network_service.clone(), 0),
+            network_events_receiver: network_events_receivers.next().unwrap(),
+            database: database.clone(),
+            block_number_bytes: usize::from(chain_spec.block_number_bytes()),
+            block_author_transactions: block_author_transactions_rx,
+            max_pending_transactions: NonZeroU32::new(4096).unwrap(),
+            max_concurrent_validations: NonZeroU32::new(4).unwrap(),
+        })
+        .await;
+
+    let offchain_worker_service =
+        offchain_worker_service::OffchainWorkerService::new(offchain_worker_service::Config {
+            tasks_executor: config.tasks_executor.clone(),
+            log_callback: config.log_callback.clone(),
+            consensus_service: consensus_service.clone(),
+            transactions_service: transactions_service.clone(),
+            database: database.clone(),
+            keystore: keystore.clone(),
+            block_number_bytes: usize::from(chain_spec.block_number_bytes()),
+            http_client: config
+                .offchain_http_client
+                .clone()
+                .unwrap_or_else(|| Arc::new(http_client::DefaultHttpClient)),
+            time_source: time_source.clone(),
+        })
+        .await;
+
     let relay_chain_consensus_service = if let Some(relay_chain_database) = relay_chain_database {
         Some(
             consensus_service::ConsensusService::new(consensus_service::Config {
@@ -418,6 +740,7 @@ pub async fn start(mut config: Config<'_>) -> Client {
                     Box::new(move |task| executor(task))
                 },
                 log_callback: config.log_callback.clone(),
+                time_source: time_source.clone(),
                 genesis_block_hash: relay_genesis_chain_information
                     .as_ref()
                     .unwrap()
@@ -449,6 +772,7 @@ pub async fn start(mut config: Config<'_>) -> Client {
                 }),
                 jaeger_service, // TODO: consider passing a different jaeger service with a different service name
                 slot_duration_author_ratio: 43691_u16,
+                block_author_transactions: None,
             })
             .await,
         )
@@ -470,6 +794,12 @@ pub async fn start(mut config: Config<'_>) -> Client {
             bind_address: json_rpc_config.address,
             max_parallel_requests: 32,
             max_json_rpc_clients: json_rpc_config.max_json_rpc_clients,
+            database,
+            block_number_bytes: usize::from(chain_spec.block_number_bytes()),
+            genesis_block_hash,
+            consensus_service: consensus_service.clone(),
+            transactions_service,
+            keystore,
         })
         .await;
 
@@ -547,18 +877,179 @@ pub async fn start(mut config: Config<'_>) -> Client {
     Client {
         consensus_service,
         relay_chain_consensus_service,
+        _grandpa_service: grandpa_service,
+        _offchain_worker_service: offchain_worker_service,
         json_rpc_service,
         network_service,
         network_known_best,
     }
 }
 
+/// Configuration for [`block_storage_diff`].
+#[derive(Debug)]
+pub struct BlockStorageDiffConfig<'a> {
+    /// Specification of the chain the database belongs to.
+    pub chain_spec: Cow<'a, [u8]>,
+    /// Path to the SQLite database. The database must already exist, and must not be in use by
+    /// a running node.
+    pub sqlite_database_path: PathBuf,
+    /// Maximum size, in bytes, of the cache SQLite uses.
+    pub sqlite_cache_size: usize,
+    /// Hash of the block to re-execute.
+    pub block_hash: [u8; 32],
+}
+
+/// Opens the database, re-executes the given block on top of the storage of its parent, and
+/// returns the list of changes that the block performs to the storage, decoded using the
+/// metadata of the runtime.
+///
+/// The storage of the parent of the block must still be present in the database, in other words
+/// it must not have been pruned.
+///
+/// Nothing is written to the database.
+///
+/// # Panic
+///
+/// Panics if the chain specification can't be decoded.
+///
+pub async fn block_storage_diff(
+    config: BlockStorageDiffConfig<'_>,
+) -> Result<Vec<StorageDiffEntry>, BlockStorageDiffError> {
+    let chain_spec = chain_spec::ChainSpec::from_json_bytes(&config.chain_spec)
+        .expect("Failed to decode chain specs");
+    let block_number_bytes = usize::from(chain_spec.block_number_bytes());
+
+    let database = match open_existing_database(
+        block_number_bytes,
+        config.sqlite_database_path,
+        config.sqlite_cache_size,
+    )
+    .map_err(BlockStorageDiffError::DatabaseOpen)?
+    {
+        Some(database) => database,
+        None => return Err(BlockStorageDiffError::EmptyDatabase),
+    };
+    let runtime_caches = Mutex::new(lru::LruCache::new(NonZeroUsize::new(1).unwrap()));
+
+    storage_diff::block_storage_diff(
+        &database,
+        &runtime_caches,
+        block_number_bytes,
+        config.block_hash,
+    )
+    .await
+}
+
+/// Configuration for [`simulate_runtime_upgrade`].
+#[derive(Debug)]
+pub struct RuntimeUpgradeConfig<'a> {
+    /// Specification of the chain the database belongs to.
+    pub chain_spec: Cow<'a, [u8]>,
+    /// Path to the SQLite database. The database must already exist, and must not be in use by
+    /// a running node.
+    pub sqlite_database_path: PathBuf,
+    /// Maximum size, in bytes, of the cache SQLite uses.
+    pub sqlite_cache_size: usize,
+    /// Wasm code of the new runtime. Must support the `TryRuntime` runtime API.
+    pub new_code: Cow<'a, [u8]>,
+    /// Which checks to perform alongside with the storage migrations.
+    pub checks: UpgradeChecks,
+}
+
+/// Opens the database and executes the storage migrations of the new runtime against the
+/// storage of the latest finalized block, as if the runtime had been upgraded right after this
+/// block.
+///
+/// A failure of the migrations or of the checks is reported as an error, while the weight they
+/// consume can be compared with the maximum weight of a block using
+/// [`RuntimeUpgradeReport::is_weight_overrun`].
+///
+/// Nothing is written to the database.
+///
+/// # Panic
+///
+/// Panics if the chain specification can't be decoded.
+///
+pub async fn simulate_runtime_upgrade(
+    config: RuntimeUpgradeConfig<'_>,
+) -> Result<RuntimeUpgradeReport, RuntimeUpgradeError> {
+    let chain_spec = chain_spec::ChainSpec::from_json_bytes(&config.chain_spec)
+        .expect("Failed to decode chain specs");
+    let block_number_bytes = usize::from(chain_spec.block_number_bytes());
+
+    let database = match open_existing_database(
+        block_number_bytes,
+        config.sqlite_database_path,
+        config.sqlite_cache_size,
+    )
+    .map_err(RuntimeUpgradeError::DatabaseOpen)?
+    {
+        Some(database) => database,
+        None => return Err(RuntimeUpgradeError::EmptyDatabase),
+    };
+    let runtime_caches = Mutex::new(lru::LruCache::new(NonZeroUsize::new(1).unwrap()));
+
+    runtime_upgrade::simulate_runtime_upgrade(
+        &database,
+        &runtime_caches,
+        block_number_bytes,
+        &config.new_code,
+        config.checks,
+    )
+    .await
+}
+
+/// Opens an existing database for the purpose of inspecting it. Returns `None` if the database
+/// doesn't exist or is empty.
+fn open_existing_database(
+    block_number_bytes: usize,
+    path: PathBuf,
+    sqlite_cache_size: usize,
+) -> Result<Option<database_thread::DatabaseThread>, full_sqlite::InternalError> {
+    let database = match full_sqlite::open(full_sqlite::Config {
+        block_number_bytes,
+        cache_size: sqlite_cache_size,
+        // Pruning only happens when blocks are finalized, which never happens here.
+        pruning: full_sqlite::PruningMode::Archive,
+        ty: full_sqlite::ConfigTy::Disk {
+            path: &path,
+            memory_map_size: 1000000000, // TODO: make configurable
+        },
+    })? {
+        full_sqlite::DatabaseOpen::Open(database) => database,
+        full_sqlite::DatabaseOpen::Empty(_) => return Ok(None),
+    };
+
+    Ok(Some(
+        database_thread::DatabaseThread::from(database)
+            .with_compiled_runtimes_cache(compiled_runtimes_cache(&Some(path))),
+    ))
+}
+
+/// Returns the cache where the runtimes compiled ahead of time are stored, in a directory next
+/// to the database.
+///
+/// If `db_path` is `None`, the database is in memory and compiled runtimes aren't stored.
+fn compiled_runtimes_cache(
+    db_path: &Option<PathBuf>,
+) -> Option<Arc<dyn executor::vm::CompiledModuleCache>> {
+    let db_path = db_path.as_ref()?;
+    Some(Arc::new(
+        compiled_runtimes_cache::CompiledRuntimesCache::new(
+            db_path.with_file_name("compiled-runtimes"),
+        ),
+    ))
+}
+
 /// Opens the database from the file system, or create a new database if none is found.
 ///
 /// If `db_path` is `None`, open the database in memory instead.
 ///
 /// The returned boolean is `true` if the database existed before.
 ///
+/// Returns an error if the database is empty and the genesis block can't be built from the chain
+/// specification.
+///
 /// # Panic
 ///
 /// Panics if the database can't be open. This function is expected to be called from the `main`
@@ -569,11 +1060,13 @@ async fn open_database(
     genesis_chain_information: chain::chain_information::ChainInformationRef<'_>,
     db_path: Option<PathBuf>,
     sqlite_cache_size: usize,
-) -> (full_sqlite::SqliteFullDatabase, bool) {
+    sqlite_pruning: full_sqlite::PruningMode,
+) -> Result<(full_sqlite::SqliteFullDatabase, bool), OpenDatabaseError> {
     // The `unwrap()` here can panic for example in case of access denied.
     match full_sqlite::open(full_sqlite::Config {
         block_number_bytes: chain_spec.block_number_bytes().into(),
         cache_size: sqlite_cache_size,
+        pruning: sqlite_pruning,
         ty: if let Some(path) = &db_path {
             full_sqlite::ConfigTy::Disk {
                 path,
@@ -595,164 +1088,74 @@ async fn open_database(
                 panic!("Mismatch between database and chain specification. Shutting down node.");
             }
 
-            (database, true)
+            Ok((database, true))
         }
 
         // The database doesn't exist or is empty.
         full_sqlite::DatabaseOpen::Empty(empty) => {
-            let genesis_storage = chain_spec.genesis_storage().into_genesis_items().unwrap(); // TODO: return error instead
+            let genesis_storage = chain_spec.genesis_storage().into_genesis_items().ok_or(
+                OpenDatabaseError::GenesisStorage(
+                    chain_spec::FromGenesisStorageError::UnknownStorageItems,
+                ),
+            )?;
 
             // In order to determine the state_version of the genesis block, we need to compile
             // the runtime.
-            // TODO: return errors instead of panicking
             // TODO: consider not throwing away the runtime
             let state_version = executor::host::HostVmPrototype::new(executor::host::Config {
-                module: genesis_storage.value(b":code").unwrap(),
+                module: genesis_storage.value(b":code").ok_or(
+                    OpenDatabaseError::GenesisStorage(
+                        chain_spec::FromGenesisStorageError::RuntimeNotFound,
+                    ),
+                )?,
                 heap_pages: executor::storage_heap_pages_to_value(
                     genesis_storage.value(b":heappages"),
                 )
-                .unwrap(),
+                .map_err(|err| {
+                    OpenDatabaseError::GenesisStorage(
+                        chain_spec::FromGenesisStorageError::HeapPagesDecode(err),
+                    )
+                })?,
                 exec_hint: executor::vm::ExecHint::Oneshot,
                 allow_unresolved_imports: true,
+                compiled_module_cache: None,
+                resource_limits: Default::default(),
+                allocation_strategy: Default::default(),
             })
-            .unwrap()
+            .map_err(|err| {
+                OpenDatabaseError::GenesisStorage(
+                    chain_spec::FromGenesisStorageError::VmInitialization(err),
+                )
+            })?
             .runtime_version()
             .decode()
             .state_version
-            .map(u8::from)
-            .unwrap_or(0);
-
-            // The chain specification only contains trie nodes that have a storage value attached
-            // to them, while the database needs to know all trie nodes (including branch nodes).
-            // The good news is that we can determine the latter from the former, which we do
-            // here.
-            // TODO: consider moving this block to the chain spec module
-            // TODO: poorly optimized
-            let mut trie_structure = {
-                let mut trie_structure = trie::trie_structure::TrieStructure::new();
-                for (key, value) in genesis_storage.iter() {
-                    match trie_structure.node(trie::bytes_to_nibbles(key.iter().copied())) {
-                        trie::trie_structure::Entry::Vacant(e) => {
-                            e.insert_storage_value().insert(
-                                (Some(value), None::<trie::trie_node::MerkleValueOutput>),
-                                (None, None),
-                            );
-                        }
-                        trie::trie_structure::Entry::Occupied(
-                            trie::trie_structure::NodeAccess::Branch(mut e),
-                        ) => {
-                            *e.user_data() = (Some(value), None);
-                            e.insert_storage_value();
-                        }
-                        trie::trie_structure::Entry::Occupied(
-                            trie::trie_structure::NodeAccess::Storage(_),
-                        ) => {
-                            // Duplicate entry.
-                            panic!() // TODO: don't panic?
-                        }
-                    }
-                }
+            .unwrap_or(trie::TrieEntryVersion::V0);
 
-                // Calculate the Merkle values of the nodes.
-                for node_index in trie_structure
-                    .iter_ordered()
-                    .collect::<Vec<_>>()
-                    .into_iter()
-                    .rev()
-                {
-                    let mut node_access = trie_structure.node_by_index(node_index).unwrap();
-
-                    let children = core::array::from_fn::<_, 16, _>(|n| {
-                        node_access
-                            .child(trie::Nibble::try_from(u8::try_from(n).unwrap()).unwrap())
-                            .map(|mut child| child.user_data().1.as_ref().unwrap().clone())
-                    });
-
-                    let is_root_node = node_access.is_root_node();
-                    let partial_key = node_access.partial_key().collect::<Vec<_>>().into_iter();
-
-                    // We have to hash the storage value ahead of time if necessary due to borrow
-                    // checking difficulties.
-                    let storage_value_hashed =
-                        match (node_access.user_data().0.as_ref(), state_version) {
-                            (Some(v), 1) => {
-                                if v.len() >= 33 {
-                                    Some(blake2_rfc::blake2b::blake2b(32, &[], v))
-                                } else {
-                                    None
-                                }
-                            }
-                            _ => None,
-                        };
-                    let storage_value = match (
-                        node_access.user_data().0.as_ref(),
-                        storage_value_hashed.as_ref(),
-                    ) {
-                        (_, Some(storage_value_hashed)) => trie::trie_node::StorageValue::Hashed(
-                            <&[u8; 32]>::try_from(storage_value_hashed.as_bytes()).unwrap(),
-                        ),
-                        (Some(v), None) => trie::trie_node::StorageValue::Unhashed(&v[..]),
-                        (None, _) => trie::trie_node::StorageValue::None,
-                    };
-
-                    let merkle_value = trie::trie_node::calculate_merkle_value(
-                        trie::trie_node::Decoded {
-                            children,
-                            partial_key,
-                            storage_value,
-                        },
-                        trie::HashFunction::Blake2,
-                        is_root_node,
-                    )
-                    .unwrap();
-
-                    node_access.into_user_data().1 = Some(merkle_value);
-                }
-
-                trie_structure
-            };
+            // Child tries are stored in the database alongside with the main trie. The entries
+            // of the main trie that point to the child tries are provided by the chain spec.
+            let mut genesis_storage_full_trie = Vec::new();
+            for child_trie in genesis_storage.child_tries() {
+                genesis_storage_full_trie.extend(genesis_trie_nodes(
+                    child_trie.iter().map(|(key, value)| (key, value, false)),
+                    state_version,
+                )?);
+            }
 
-            // Build the iterator of trie nodes.
-            let genesis_storage_full_trie = trie_structure
-                .iter_unordered()
-                .collect::<Vec<_>>()
-                .into_iter()
-                .map(|node_index| {
-                    let (storage_value, Some(merkle_value)) = &trie_structure[node_index] else {
-                        unreachable!()
-                    };
-                    // Cloning to solve borrow checker restriction. // TODO: optimize?
-                    let storage_value = if let Some(storage_value) = storage_value {
-                        // TODO: child tries support?
-                        full_sqlite::InsertTrieNodeStorageValue::Value {
-                            value: Cow::Owned(storage_value.to_vec()),
-                            references_merkle_value: false,
-                        }
-                    } else {
-                        full_sqlite::InsertTrieNodeStorageValue::NoValue
-                    };
-                    let merkle_value = merkle_value.as_ref().to_owned();
-                    let mut node_access = trie_structure.node_by_index(node_index).unwrap();
-
-                    full_sqlite::InsertTrieNode {
-                        storage_value,
-                        merkle_value: Cow::Owned(merkle_value),
-                        children_merkle_values: array::from_fn::<_, 16, _>(|n| {
-                            let child_index =
-                                trie::Nibble::try_from(u8::try_from(n).unwrap()).unwrap();
-                            if let Some(mut child) = node_access.child(child_index) {
-                                Some(Cow::Owned(
-                                    child.user_data().1.as_ref().unwrap().as_ref().to_vec(),
-                                ))
-                            } else {
-                                None
-                            }
-                        }),
-                        partial_key_nibbles: Cow::Owned(
-                            node_access.partial_key().map(u8::from).collect::<Vec<_>>(),
-                        ),
-                    }
-                });
+            let child_tries_roots = genesis_storage
+                .child_tries_roots(state_version)
+                .collect::<Vec<_>>();
+            genesis_storage_full_trie.extend(genesis_trie_nodes(
+                genesis_storage
+                    .iter()
+                    .map(|(key, value)| (key, value, false))
+                    .chain(
+                        child_tries_roots
+                            .iter()
+                            .map(|(key, root_hash)| (&key[..], &root_hash[..], true)),
+                    ),
+                state_version,
+            )?);
 
             // The finalized block is the genesis block. As such, it has an empty body and
             // no justification.
@@ -761,11 +1164,168 @@ async fn open_database(
                     genesis_chain_information,
                     iter::empty(),
                     None,
-                    genesis_storage_full_trie,
-                    state_version,
+                    genesis_storage_full_trie.into_iter(),
+                    u8::from(state_version),
                 )
                 .unwrap();
-            (database, false)
+            Ok((database, false))
         }
     }
 }
+
+/// Error potentially returned by [`open_database`].
+#[derive(Debug, derive_more::Display)]
+enum OpenDatabaseError {
+    /// Failed to determine the content of the genesis block from the chain specification.
+    #[display(fmt = "{_0}")]
+    GenesisStorage(chain_spec::FromGenesisStorageError),
+    /// The same key is found multiple times in the genesis storage of the chain specification.
+    #[display(fmt = "Duplicate key in the genesis storage: 0x{}", "hex::encode(_0)")]
+    DuplicateGenesisStorageKey(Vec<u8>),
+}
+
+/// Builds the list of all the trie nodes of the trie made of the given storage entries.
+///
+/// Each entry consists of a key, a storage value, and a boolean indicating whether the storage
+/// value is the Merkle value of the root of a child trie.
+///
+/// Returns an error if the same key is found multiple times in `entries`.
+fn genesis_trie_nodes<'a>(
+    entries: impl Iterator<Item = (&'a [u8], &'a [u8], bool)>,
+    state_version: trie::TrieEntryVersion,
+) -> Result<Vec<full_sqlite::InsertTrieNode<'static>>, OpenDatabaseError> {
+    // The chain specification only contains trie nodes that have a storage value attached
+    // to them, while the database needs to know all trie nodes (including branch nodes).
+    // The good news is that we can determine the latter from the former, which we do
+    // here.
+    // TODO: consider moving this block to the chain spec module
+    // TODO: poorly optimized
+    let mut trie_structure = {
+        let mut trie_structure = trie::trie_structure::TrieStructure::new();
+        for (key, value, references_merkle_value) in entries {
+            match trie_structure.node(trie::bytes_to_nibbles(key.iter().copied())) {
+                trie::trie_structure::Entry::Vacant(e) => {
+                    e.insert_storage_value().insert(
+                        (
+                            Some((value, references_merkle_value)),
+                            None::<trie::trie_node::MerkleValueOutput>,
+                        ),
+                        (None, None),
+                    );
+                }
+                trie::trie_structure::Entry::Occupied(
+                    trie::trie_structure::NodeAccess::Branch(mut e),
+                ) => {
+                    *e.user_data() = (Some((value, references_merkle_value)), None);
+                    e.insert_storage_value();
+                }
+                trie::trie_structure::Entry::Occupied(
+                    trie::trie_structure::NodeAccess::Storage(_),
+                ) => {
+                    return Err(OpenDatabaseError::DuplicateGenesisStorageKey(key.to_vec()));
+                }
+            }
+        }
+
+        // Calculate the Merkle values of the nodes.
+        for node_index in trie_structure
+            .iter_ordered()
+            .collect::<Vec<_>>()
+            .into_iter()
+            .rev()
+        {
+            let mut node_access = trie_structure.node_by_index(node_index).unwrap();
+
+            let children = core::array::from_fn::<_, 16, _>(|n| {
+                node_access
+                    .child(trie::Nibble::try_from(u8::try_from(n).unwrap()).unwrap())
+                    .map(|mut child| child.user_data().1.as_ref().unwrap().clone())
+            });
+
+            let is_root_node = node_access.is_root_node();
+            let partial_key = node_access.partial_key().collect::<Vec<_>>().into_iter();
+
+            // We have to hash the storage value ahead of time if necessary due to borrow
+            // checking difficulties.
+            let storage_value_hashed = match (node_access.user_data().0.as_ref(), state_version) {
+                (Some((v, _)), trie::TrieEntryVersion::V1) => {
+                    if v.len() >= 33 {
+                        Some(blake2_rfc::blake2b::blake2b(32, &[], v))
+                    } else {
+                        None
+                    }
+                }
+                _ => None,
+            };
+            let storage_value = match (
+                node_access.user_data().0.as_ref(),
+                storage_value_hashed.as_ref(),
+            ) {
+                (_, Some(storage_value_hashed)) => trie::trie_node::StorageValue::Hashed(
+                    <&[u8; 32]>::try_from(storage_value_hashed.as_bytes()).unwrap(),
+                ),
+                (Some((v, _)), None) => trie::trie_node::StorageValue::Unhashed(&v[..]),
+                (None, _) => trie::trie_node::StorageValue::None,
+            };
+
+            let merkle_value = trie::trie_node::calculate_merkle_value(
+                trie::trie_node::Decoded {
+                    children,
+                    partial_key,
+                    storage_value,
+                },
+                trie::HashFunction::Blake2,
+                is_root_node,
+            )
+            .unwrap();
+
+            node_access.into_user_data().1 = Some(merkle_value);
+        }
+
+        trie_structure
+    };
+
+    // Build the list of trie nodes.
+    let trie_nodes = trie_structure
+        .iter_unordered()
+        .collect::<Vec<_>>()
+        .into_iter()
+        .map(|node_index| {
+            let (storage_value, Some(merkle_value)) = &trie_structure[node_index] else {
+                unreachable!()
+            };
+            // Cloning to solve borrow checker restriction. // TODO: optimize?
+            let storage_value =
+                if let Some((storage_value, references_merkle_value)) = storage_value {
+                    full_sqlite::InsertTrieNodeStorageValue::Value {
+                        value: Cow::Owned(storage_value.to_vec()),
+                        references_merkle_value: *references_merkle_value,
+                    }
+                } else {
+                    full_sqlite::InsertTrieNodeStorageValue::NoValue
+                };
+            let merkle_value = merkle_value.as_ref().to_owned();
+            let mut node_access = trie_structure.node_by_index(node_index).unwrap();
+
+            full_sqlite::InsertTrieNode {
+                storage_value,
+                merkle_value: Cow::Owned(merkle_value),
+                children_merkle_values: array::from_fn::<_, 16, _>(|n| {
+                    let child_index = trie::Nibble::try_from(u8::try_from(n).unwrap()).unwrap();
+                    if let Some(mut child) = node_access.child(child_index) {
+                        Some(Cow::Owned(
+                            child.user_data().1.as_ref().unwrap().as_ref().to_vec(),
+                        ))
+                    } else {
+                        None
+                    }
+                }),
+                partial_key_nibbles: Cow::Owned(
+                    node_access.partial_key().map(u8::from).collect::<Vec<_>>(),
+                ),
+            }
+        })
+        .collect::<Vec<_>>();
+
+    Ok(trie_nodes)
+}
@@ -21,13 +21,18 @@
 //! Importantly, its design is oriented towards the particular use case of the full node.
 //!
 //! The [`NetworkService`] spawns one background task (using the [`Config::tasks_executor`]) for
-//! each active TCP socket, plus one for each TCP listening socket. Messages are exchanged between
-//! the service and these background tasks.
+//! each active TCP socket, plus one for each TCP listening socket. Listening sockets whose
+//! address ends with `/ws` perform the server side of the WebSocket handshake on each incoming
+//! connection. Similarly, one background task is spawned for each WebRTC listening UDP socket,
+//! plus one for each WebRTC connection. Messages are exchanged between the service and these
+//! background tasks.
 
 // TODO: doc
 // TODO: re-review this once finished
 
-use crate::{database_thread, jaeger_service, util, LogCallback, LogLevel};
+use crate::{
+    database_thread, jaeger_service, runtime_call, util, LogCallback, LogLevel, TimeSource,
+};
 
 use core::{cmp, future::Future, mem, pin::Pin, task::Poll, time::Duration};
 use futures_channel::oneshot;
@@ -35,27 +40,31 @@ use hashbrown::HashMap;
 use smol::{
     channel, future,
     lock::Mutex,
-    net::TcpStream,
     stream::{Stream, StreamExt as _},
 };
 use smoldot::{
     database::full_sqlite,
+    finality::grandpa::voter,
     header,
     informant::HashDisplay,
     libp2p::{
-        connection,
+        connection::{self, tls},
+        memory,
         multiaddr::{Multiaddr, ProtocolRef},
         peer_id::{self, PeerId},
-        peers,
+        peers, quic, websocket,
     },
     network::{protocol, service},
+    trie,
 };
 use std::{
+    borrow::Cow,
     io, iter,
-    net::{IpAddr, SocketAddr},
+    net::{IpAddr, Ipv4Addr, Ipv6Addr, SocketAddr},
     num::NonZeroUsize,
     sync::Arc,
     time::Instant,
+    vec,
 };
 
 mod tasks;
@@ -68,12 +77,26 @@ pub struct Config {
     /// Function called in order to notify of something.
     pub log_callback: Arc<dyn LogCallback + Send + Sync>,
 
+    /// Source of the time used for the timeouts of the networking.
+    pub time_source: Arc<dyn TimeSource + Send + Sync>,
+
     /// Number of event receivers returned by [`NetworkService::new`].
     pub num_events_receivers: usize,
 
-    /// Addresses to listen for incoming connections.
+    /// Addresses to listen for incoming connections. Must be of the form `/ip4/.../tcp/...` or
+    /// `/ip6/.../tcp/...`, optionally followed with `/ws` in order to accept WebSocket
+    /// connections, or of the form `/ip4/.../udp/.../webrtc-direct` or
+    /// `/ip6/.../udp/.../webrtc-direct` in order to accept WebRTC connections. WebRTC addresses
+    /// can be followed with `/certhash/...`, in which case the hash must match
+    /// [`Config::webrtc_certificate`]. Addresses of the form `/ip4/.../udp/.../quic-v1` or
+    /// `/ip6/.../udp/.../quic-v1` accept QUIC connections. Addresses of the form `/memory/...`
+    /// listen on [`Config::memory_network`].
     pub listen_addresses: Vec<Multiaddr>,
 
+    /// In-process network that `/memory/...` addresses refer to, both when listening and when
+    /// dialing. If `None`, such addresses aren't supported.
+    pub memory_network: Option<memory::MemoryNetwork>,
+
     /// List of block chains to be connected to.
     pub chains: Vec<ChainConfig>,
 
@@ -85,6 +108,14 @@ pub struct Config {
     /// Signed using the actual libp2p key.
     pub noise_key: connection::NoiseKey,
 
+    /// Certificate used at the DTLS layer of the WebRTC listeners. Unused if
+    /// [`Config::listen_addresses`] doesn't contain any WebRTC address.
+    pub webrtc_certificate: WebRtcCertificate,
+
+    /// Certificate used at the TLS layer of QUIC connections, both incoming and outgoing. Must
+    /// have been generated from the same libp2p key as [`Config::noise_key`].
+    pub quic_certificate: tls::Certificate,
+
     /// Service to use to report traces.
     pub jaeger_service: Arc<jaeger_service::JaegerService>,
 }
@@ -116,6 +147,76 @@ pub struct ChainConfig {
     pub has_grandpa_protocol: bool,
 }
 
+/// Self-signed certificate used at the DTLS layer of WebRTC connections.
+///
+/// The hash of this certificate is part of the addresses the node is reachable at. It should
+/// therefore be stored and reused between restarts, otherwise these addresses change.
+#[derive(Clone)]
+pub struct WebRtcCertificate {
+    inner: str0m::config::DtlsCert,
+}
+
+impl WebRtcCertificate {
+    /// Generates a new random certificate.
+    pub fn generate() -> Self {
+        let inner = str0m::crypto::from_feature_flags()
+            .dtls_provider
+            .generate_certificate()
+            .unwrap();
+        WebRtcCertificate { inner }
+    }
+
+    /// Builds a certificate from the DER encodings of a certificate and of its private key, as
+    /// previously returned by [`WebRtcCertificate::certificate_der`] and
+    /// [`WebRtcCertificate::private_key_der`].
+    pub fn from_der(
+        certificate: Vec<u8>,
+        private_key: Vec<u8>,
+    ) -> Result<Self, InvalidWebRtcCertificateError> {
+        let inner = str0m::config::DtlsCert {
+            certificate,
+            private_key,
+        };
+
+        // Make sure that the certificate can actually be used, as failing to do so later would
+        // make all incoming connections fail.
+        str0m::crypto::from_feature_flags()
+            .dtls_provider
+            .new_dtls(
+                &inner,
+                Instant::now(),
+                str0m::config::DtlsVersion::Dtls12,
+                None,
+            )
+            .map_err(|_| InvalidWebRtcCertificateError)?;
+
+        Ok(WebRtcCertificate { inner })
+    }
+
+    /// Returns the DER encoding of the certificate.
+    pub fn certificate_der(&self) -> &[u8] {
+        &self.inner.certificate
+    }
+
+    /// Returns the DER encoding of the private key of the certificate.
+    pub fn private_key_der(&self) -> &[u8] {
+        &self.inner.private_key
+    }
+
+    /// Returns the multihash of the certificate, as found in `/certhash` multiaddr components.
+    fn multihash(&self) -> Vec<u8> {
+        let sha256 = str0m::crypto::from_feature_flags()
+            .sha256_provider
+            .sha256(&self.inner.certificate);
+        tasks::sha256_multihash(&sha256)
+    }
+}
+
+/// Error potentially returned by [`WebRtcCertificate::from_der`].
+#[derive(Debug, derive_more::Display)]
+#[display(fmt = "Invalid WebRTC certificate or private key")]
+pub struct InvalidWebRtcCertificateError;
+
 /// Event generated by the events reporters returned by [`NetworkService::new`].
 #[derive(Debug, Clone)]
 pub enum Event {
@@ -135,6 +236,36 @@ pub enum Event {
         scale_encoded_header: Vec<u8>,
         is_best: bool,
     },
+    GrandpaVote {
+        chain_index: usize,
+        peer_id: PeerId,
+        message: service::EncodedGrandpaVoteMessage,
+    },
+    GrandpaNeighborPacket {
+        chain_index: usize,
+        peer_id: PeerId,
+        state: service::GrandpaState,
+    },
+    GrandpaCommit {
+        chain_index: usize,
+        peer_id: PeerId,
+        message: service::EncodedGrandpaCommitMessage,
+    },
+    GrandpaCatchUp {
+        chain_index: usize,
+        peer_id: PeerId,
+        message: service::EncodedGrandpaCatchUpMessage,
+    },
+    GrandpaCatchUpRequest {
+        chain_index: usize,
+        peer_id: PeerId,
+        request: protocol::CatchUpRequest,
+    },
+    Transactions {
+        chain_index: usize,
+        peer_id: PeerId,
+        transactions: service::EncodedTransactions,
+    },
 }
 
 pub struct NetworkService {
@@ -154,6 +285,14 @@ pub struct NetworkService {
     foreground_shutdown: event_listener::Event,
 }
 
+/// Maximum number of incoming connections of a TCP listening socket whose WebSocket handshake is
+/// in progress at the same time.
+const WEBSOCKET_MAX_PENDING_HANDSHAKES: usize = 64;
+
+/// Maximum number of incoming connections from the same IP address of a TCP listening socket
+/// whose WebSocket handshake is in progress at the same time.
+const WEBSOCKET_MAX_PENDING_HANDSHAKES_PER_IP: usize = 4;
+
 enum ToBackground {
     FromConnectionTask {
         connection_id: service::ConnectionId,
@@ -168,10 +307,30 @@ enum ToBackground {
         info: service::StartConnect<Instant>,
     },
     IncomingConnection {
-        socket: TcpStream,
+        socket: Box<dyn tasks::AsyncReadWrite + Send + Unpin>,
+        multiaddr: Multiaddr,
+        when_connected: Instant,
+    },
+    QuicConnectionEstablishSuccess {
+        connection: quinn::Connection,
+        info: service::StartConnect<Instant>,
+    },
+    IncomingQuicConnection {
+        connection: quinn::Connection,
         multiaddr: Multiaddr,
         when_connected: Instant,
     },
+    IncomingWebRtcConnection(tasks::WebRtcIncomingConnection),
+    WebRtcConnectionEstablished {
+        multiaddr: Multiaddr,
+        handshake_kind: service::MultiStreamHandshakeKind,
+        when_connected: Instant,
+        result_tx: oneshot::Sender<(
+            service::ConnectionId,
+            service::MultiStreamConnectionTask<Instant, str0m::channel::ChannelId>,
+            channel::Receiver<service::CoordinatorToConnection<Instant>>,
+        )>,
+    },
     StartKademliaDiscoveries {
         when_done: oneshot::Sender<()>,
     },
@@ -182,30 +341,123 @@ enum ToBackground {
         is_best: bool,
         result_tx: oneshot::Sender<Result<(), QueueNotificationError>>,
     },
+    ForegroundAnnounceTransaction {
+        chain_index: usize,
+        transaction: Vec<u8>,
+        result_tx: oneshot::Sender<Vec<PeerId>>,
+    },
     ForegroundSetLocalBestBlock {
         chain_index: usize,
         best_hash: [u8; 32],
         best_number: u64,
     },
+    ForegroundSetLocalGrandpaState {
+        chain_index: usize,
+        grandpa_state: service::GrandpaState,
+    },
+    ForegroundBroadcastGrandpaVote {
+        chain_index: usize,
+        vote: voter::SignedVote,
+    },
+    ForegroundBroadcastGrandpaCommit {
+        chain_index: usize,
+        commit: voter::Commit,
+    },
+    ForegroundSendGrandpaCatchUpRequest {
+        target: PeerId,
+        chain_index: usize,
+        request: protocol::CatchUpRequest,
+        result_tx: oneshot::Sender<Result<(), QueueNotificationError>>,
+    },
+    ForegroundSendGrandpaCatchUp {
+        target: PeerId,
+        chain_index: usize,
+        catch_up: voter::CatchUp,
+        result_tx: oneshot::Sender<Result<(), QueueNotificationError>>,
+    },
     ForegroundBlocksRequest {
         target: PeerId,
         chain_index: usize,
         config: protocol::BlocksRequestConfig,
         result_tx: oneshot::Sender<Result<Vec<protocol::BlockData>, BlocksRequestError>>,
     },
+    ForegroundGrandpaWarpSyncRequest {
+        target: PeerId,
+        chain_index: usize,
+        begin_hash: [u8; 32],
+        result_tx: oneshot::Sender<
+            Result<service::EncodedGrandpaWarpSyncResponse, GrandpaWarpSyncRequestError>,
+        >,
+    },
+    ForegroundStateRequest {
+        target: PeerId,
+        chain_index: usize,
+        block_hash: [u8; 32],
+        child_trie: Option<Vec<u8>>,
+        start_key: Vec<u8>,
+        result_tx: oneshot::Sender<Result<service::EncodedStateResponse, StateRequestError>>,
+    },
+    ForegroundStorageProofRequest {
+        target: PeerId,
+        chain_index: usize,
+        config: protocol::StorageProofRequestConfig<vec::IntoIter<Vec<u8>>>,
+        result_tx: oneshot::Sender<Result<service::EncodedMerkleProof, StorageProofRequestError>>,
+    },
+    ForegroundCallProofRequest {
+        target: PeerId,
+        chain_index: usize,
+        config: protocol::CallProofRequestConfig<'static, vec::IntoIter<Vec<u8>>>,
+        result_tx: oneshot::Sender<Result<service::EncodedMerkleProof, CallProofRequestError>>,
+    },
     ForegroundGetNumEstablishedConnections {
         result_tx: oneshot::Sender<usize>,
     },
+    InRequestResponse {
+        request_id: service::InRequestId,
+        response: InRequestResponse,
+    },
     ForegroundGetNumPeers {
         chain_index: usize,
         result_tx: oneshot::Sender<usize>,
     },
     ForegroundShutdown,
 }
+
+/// Response to an incoming request, built by a separate task and sent back to the background
+/// task.
+enum InRequestResponse {
+    /// `None` if the proof couldn't be generated.
+    StorageProof(Option<Vec<u8>>),
+    /// `None` if the proof couldn't be generated.
+    CallProof(Option<Vec<u8>>),
+    /// List of SCALE-encoded headers and justifications, and whether the proof is finished.
+    /// `None` if the request is denied.
+    GrandpaWarpSync(Option<(Vec<(Vec<u8>, Vec<u8>)>, bool)>),
+    /// `None` if the request is denied.
+    State(Option<Vec<u8>>),
+}
 struct Inner {
     /// Value provided through [`Config::identify_agent_version`].
     identify_agent_version: String,
 
+    /// Addresses the node is listening on, sent back when receiving an identification request.
+    listen_addresses: Vec<Multiaddr>,
+
+    /// See [`Config::webrtc_certificate`].
+    webrtc_certificate: WebRtcCertificate,
+
+    /// Configuration used when opening outgoing QUIC connections. Built from
+    /// [`Config::quic_certificate`].
+    quic_client_config: quinn::ClientConfig,
+
+    /// QUIC endpoints that outgoing QUIC connections are opened from. Contains the endpoints of
+    /// the QUIC listeners, plus endpoints that are lazily created when no listener of the
+    /// appropriate IP version exists.
+    quic_endpoints: Vec<quinn::Endpoint>,
+
+    /// See [`Config::memory_network`].
+    memory_network: Option<memory::MemoryNetwork>,
+
     /// Sending events through the public API.
     ///
     /// Contains either senders, or a `Future` that is currently sending an event and will yield
@@ -218,6 +470,9 @@ struct Inner {
     /// Databases to use to read blocks from when answering requests.
     databases: Vec<Arc<database_thread::DatabaseThread>>,
 
+    /// For each chain, runtimes used in order to answer call proof requests.
+    runtime_caches: Vec<Arc<Mutex<runtime_call::RuntimeCaches>>>,
+
     /// Identity of the local node.
     local_peer_id: PeerId,
 
@@ -239,6 +494,9 @@ struct Inner {
     /// See [`Config::log_callback`].
     log_callback: Arc<dyn LogCallback + Send + Sync>,
 
+    /// See [`Config::time_source`].
+    time_source: Arc<dyn TimeSource + Send + Sync>,
+
     active_connections: HashMap<
         service::ConnectionId,
         channel::Sender<service::CoordinatorToConnection<Instant>>,
@@ -265,6 +523,36 @@ struct Inner {
         fnv::FnvBuildHasher,
     >,
 
+    /// List of all GrandPa warp sync requests that have been started but not finished yet.
+    grandpa_warp_sync_requests: HashMap<
+        service::OutRequestId,
+        oneshot::Sender<
+            Result<service::EncodedGrandpaWarpSyncResponse, GrandpaWarpSyncRequestError>,
+        >,
+        fnv::FnvBuildHasher,
+    >,
+
+    /// List of all state requests that have been started but not finished yet.
+    state_requests: HashMap<
+        service::OutRequestId,
+        oneshot::Sender<Result<service::EncodedStateResponse, StateRequestError>>,
+        fnv::FnvBuildHasher,
+    >,
+
+    /// List of all storage proof requests that have been started but not finished yet.
+    storage_proof_requests: HashMap<
+        service::OutRequestId,
+        oneshot::Sender<Result<service::EncodedMerkleProof, StorageProofRequestError>>,
+        fnv::FnvBuildHasher,
+    >,
+
+    /// List of all call proof requests that have been started but not finished yet.
+    call_proof_requests: HashMap<
+        service::OutRequestId,
+        oneshot::Sender<Result<service::EncodedMerkleProof, CallProofRequestError>>,
+        fnv::FnvBuildHasher,
+    >,
+
     /// List of Kademlia discovery operations that have been started but not finished yet.
     kademlia_discovery_operations:
         HashMap<service::KademliaOperationId, usize, fnv::FnvBuildHasher>,
@@ -302,13 +590,16 @@ impl NetworkService {
                     None
                 },
                 allow_inbound_block_requests: true,
+                allow_inbound_light_requests: true,
+                allow_inbound_grandpa_warp_sync_requests: true,
+                allow_inbound_state_requests: true,
             });
 
             databases.push(chain.database.clone());
         }
 
         let mut network = service::ChainNetwork::new(service::Config {
-            now: Instant::now(),
+            now: config.time_source.now(),
             chains,
             connections_capacity: 100, // TODO: ?
             peers_capacity: 100,       // TODO: ?
@@ -321,7 +612,12 @@ impl NetworkService {
         // Add the bootnodes to the inner state machine.
         for (chain_index, chain) in config.chains.into_iter().enumerate() {
             for (peer_id, addr) in chain.bootstrap_nodes {
-                network.discover(&Instant::now(), chain_index, peer_id, iter::once(addr));
+                network.discover(
+                    &config.time_source.now(),
+                    chain_index,
+                    peer_id,
+                    iter::once(addr),
+                );
             }
         }
 
@@ -336,7 +632,19 @@ impl NetworkService {
         let mut inner = Inner {
             local_peer_id: local_peer_id.clone(),
             identify_agent_version: config.identify_agent_version,
+            listen_addresses: Vec::with_capacity(config.listen_addresses.len()),
+            webrtc_certificate: config.webrtc_certificate,
+            quic_client_config: quic::client_config(&config.quic_certificate),
+            quic_endpoints: Vec::new(),
+            memory_network: config.memory_network,
             event_senders: either::Left(event_senders),
+            runtime_caches: (0..databases.len())
+                .map(|_| {
+                    Arc::new(Mutex::new(lru::LruCache::new(
+                        NonZeroUsize::new(2).unwrap(),
+                    )))
+                })
+                .collect(),
             databases,
             num_pending_out_attempts: 0,
             to_background_rx,
@@ -344,6 +652,7 @@ impl NetworkService {
             process_network_service_events: true,
             tasks_executor: config.tasks_executor,
             log_callback: config.log_callback.clone(),
+            time_source: config.time_source.clone(),
             network,
             slots_assign_backoff: hashbrown::HashMap::with_capacity_and_hasher(
                 50, // TODO: ?
@@ -357,6 +666,19 @@ impl NetworkService {
                 50, // TODO: ?
                 Default::default(),
             ),
+            grandpa_warp_sync_requests: hashbrown::HashMap::with_capacity_and_hasher(
+                4,
+                Default::default(),
+            ),
+            state_requests: hashbrown::HashMap::with_capacity_and_hasher(4, Default::default()),
+            storage_proof_requests: hashbrown::HashMap::with_capacity_and_hasher(
+                4,
+                Default::default(),
+            ),
+            call_proof_requests: hashbrown::HashMap::with_capacity_and_hasher(
+                4,
+                Default::default(),
+            ),
             kademlia_discovery_operations: hashbrown::HashMap::with_capacity_and_hasher(
                 4,
                 Default::default(),
@@ -364,37 +686,201 @@ impl NetworkService {
             jaeger_service: config.jaeger_service.clone(),
         };
 
+        // Multihash of the certificate used by the WebRTC listeners, advertised to other nodes as
+        // part of the listening addresses.
+        let webrtc_certificate_multihash = inner.webrtc_certificate.multihash();
+
         // For each listening address in the configuration, create a background task dedicated to
         // listening on that address.
         for listen_address in config.listen_addresses {
             // Try to parse the requested address and create the corresponding listening socket.
-            let tcp_listener: smol::net::TcpListener = {
+            let (tcp_listener, is_websocket): (smol::net::TcpListener, bool) = {
+                enum ListenAddress<'a> {
+                    Tcp(SocketAddr, bool),
+                    WebRtc(SocketAddr, Option<Cow<'a, [u8]>>),
+                    Quic(SocketAddr),
+                    Memory(u64),
+                }
+
                 let addr = {
-                    let mut iter = listen_address.iter();
+                    let mut iter = listen_address.iter().fuse();
                     let proto1 = iter.next();
                     let proto2 = iter.next();
                     let proto3 = iter.next();
-                    match (proto1, proto2, proto3) {
-                        (Some(ProtocolRef::Ip4(ip)), Some(ProtocolRef::Tcp(port)), None) => {
-                            Some(SocketAddr::from((ip, port)))
+                    let proto4 = iter.next();
+                    let has_more = iter.next().is_some();
+                    match (proto1, proto2, proto3, proto4) {
+                        _ if has_more => None,
+                        (Some(ProtocolRef::Ip4(ip)), Some(ProtocolRef::Tcp(port)), None, None) => {
+                            Some(ListenAddress::Tcp(SocketAddr::from((ip, port)), false))
+                        }
+                        (Some(ProtocolRef::Ip6(ip)), Some(ProtocolRef::Tcp(port)), None, None) => {
+                            Some(ListenAddress::Tcp(SocketAddr::from((ip, port)), false))
                         }
-                        (Some(ProtocolRef::Ip6(ip)), Some(ProtocolRef::Tcp(port)), None) => {
-                            Some(SocketAddr::from((ip, port)))
+                        (
+                            Some(ProtocolRef::Ip4(ip)),
+                            Some(ProtocolRef::Tcp(port)),
+                            Some(ProtocolRef::Ws),
+                            None,
+                        ) => Some(ListenAddress::Tcp(SocketAddr::from((ip, port)), true)),
+                        (
+                            Some(ProtocolRef::Ip6(ip)),
+                            Some(ProtocolRef::Tcp(port)),
+                            Some(ProtocolRef::Ws),
+                            None,
+                        ) => Some(ListenAddress::Tcp(SocketAddr::from((ip, port)), true)),
+                        (
+                            Some(ProtocolRef::Ip4(ip)),
+                            Some(ProtocolRef::Udp(port)),
+                            Some(ProtocolRef::WebRtcDirect),
+                            None,
+                        ) => Some(ListenAddress::WebRtc(SocketAddr::from((ip, port)), None)),
+                        (
+                            Some(ProtocolRef::Ip6(ip)),
+                            Some(ProtocolRef::Udp(port)),
+                            Some(ProtocolRef::WebRtcDirect),
+                            None,
+                        ) => Some(ListenAddress::WebRtc(SocketAddr::from((ip, port)), None)),
+                        (
+                            Some(ProtocolRef::Ip4(ip)),
+                            Some(ProtocolRef::Udp(port)),
+                            Some(ProtocolRef::WebRtcDirect),
+                            Some(ProtocolRef::Certhash(hash)),
+                        ) => Some(ListenAddress::WebRtc(
+                            SocketAddr::from((ip, port)),
+                            Some(hash),
+                        )),
+                        (
+                            Some(ProtocolRef::Ip6(ip)),
+                            Some(ProtocolRef::Udp(port)),
+                            Some(ProtocolRef::WebRtcDirect),
+                            Some(ProtocolRef::Certhash(hash)),
+                        ) => Some(ListenAddress::WebRtc(
+                            SocketAddr::from((ip, port)),
+                            Some(hash),
+                        )),
+                        (
+                            Some(ProtocolRef::Ip4(ip)),
+                            Some(ProtocolRef::Udp(port)),
+                            Some(ProtocolRef::QuicV1),
+                            None,
+                        ) => Some(ListenAddress::Quic(SocketAddr::from((ip, port)))),
+                        (
+                            Some(ProtocolRef::Ip6(ip)),
+                            Some(ProtocolRef::Udp(port)),
+                            Some(ProtocolRef::QuicV1),
+                            None,
+                        ) => Some(ListenAddress::Quic(SocketAddr::from((ip, port)))),
+                        (Some(ProtocolRef::Memory(port)), None, None, None) => {
+                            Some(ListenAddress::Memory(port))
                         }
                         _ => None,
                     }
                 };
 
-                if let Some(addr) = addr {
-                    match smol::net::TcpListener::bind(addr).await {
-                        Ok(l) => l,
-                        Err(err) => {
-                            return Err(InitError::ListenerIo(listen_address, err));
+                match addr {
+                    Some(ListenAddress::Tcp(addr, is_websocket)) => {
+                        match smol::net::TcpListener::bind(addr).await {
+                            Ok(l) => {
+                                inner.listen_addresses.push(listen_address.clone());
+                                (l, is_websocket)
+                            }
+                            Err(err) => {
+                                return Err(InitError::ListenerIo(listen_address, err));
+                            }
                         }
                     }
-                } else {
-                    // TODO: support WebSocket server
-                    return Err(InitError::BadListenMultiaddr(listen_address));
+                    Some(ListenAddress::WebRtc(addr, certhash)) => {
+                        // The certificate hash is optional in the configuration, but if it is
+                        // present it must match the certificate that is actually used.
+                        if certhash.is_some_and(|h| *h != webrtc_certificate_multihash[..]) {
+                            return Err(InitError::WebRtcCerthashMismatch(listen_address));
+                        }
+
+                        let socket = match smol::net::UdpSocket::bind(addr).await {
+                            Ok(s) => s,
+                            Err(err) => {
+                                return Err(InitError::ListenerIo(listen_address, err));
+                            }
+                        };
+
+                        let advertised_address = listen_address
+                            .iter()
+                            .take(3)
+                            .chain(iter::once(ProtocolRef::Certhash(Cow::Borrowed(
+                                &webrtc_certificate_multihash,
+                            ))))
+                            .collect::<Multiaddr>();
+                        config.log_callback.log(
+                            LogLevel::Info,
+                            format!("webrtc-listening; multiaddr={}", advertised_address),
+                        );
+                        inner.listen_addresses.push(advertised_address);
+
+                        // Spawn a background task dedicated to this listener.
+                        (inner.tasks_executor)(Box::pin(tasks::webrtc_listener_task(
+                            socket,
+                            foreground_shutdown.listen(),
+                            to_background_tx.clone(),
+                        )));
+                        continue;
+                    }
+                    Some(ListenAddress::Quic(addr)) => {
+                        let endpoint = match std::net::UdpSocket::bind(addr).and_then(|socket| {
+                            quinn::Endpoint::new(
+                                quinn::EndpointConfig::default(),
+                                Some(quic::server_config(&config.quic_certificate)),
+                                socket,
+                                Arc::new(quinn::SmolRuntime),
+                            )
+                        }) {
+                            Ok(e) => e,
+                            Err(err) => {
+                                return Err(InitError::ListenerIo(listen_address, err));
+                            }
+                        };
+
+                        inner.listen_addresses.push(listen_address.clone());
+                        inner.quic_endpoints.push(endpoint.clone());
+
+                        // Spawn a background task dedicated to this listener.
+                        (inner.tasks_executor)(Box::pin(tasks::quic_listener_task(
+                            config.log_callback.clone(),
+                            config.time_source.clone(),
+                            endpoint,
+                            foreground_shutdown.listen(),
+                            to_background_tx.clone(),
+                        )));
+                        continue;
+                    }
+                    Some(ListenAddress::Memory(port)) => {
+                        let Some(memory_network) = &inner.memory_network else {
+                            return Err(InitError::BadListenMultiaddr(listen_address));
+                        };
+
+                        let listener = match memory_network.listen(port) {
+                            Ok(l) => l,
+                            Err(err) => {
+                                return Err(InitError::ListenerIo(listen_address, err));
+                            }
+                        };
+
+                        inner.listen_addresses.push(listen_address.clone());
+
+                        // Spawn a background task dedicated to this listener.
+                        (inner.tasks_executor)(Box::pin(tasks::memory_listener_task(
+                            config.log_callback.clone(),
+                            config.time_source.clone(),
+                            listener,
+                            foreground_shutdown.listen(),
+                            to_background_tx.clone(),
+                        )));
+                        continue;
+                    }
+                    None => {
+                        // TODO: support WebSocket secure server
+                        return Err(InitError::BadListenMultiaddr(listen_address));
+                    }
                 }
             };
 
@@ -402,29 +888,83 @@ impl NetworkService {
             (inner.tasks_executor)(Box::pin({
                 let to_background_tx = to_background_tx.clone();
                 let log_callback = config.log_callback.clone();
+                let time_source = config.time_source.clone();
                 let mut on_foreground_shutdown = foreground_shutdown.listen();
                 async move {
+                    // WebSocket handshakes that are in progress. They are performed within this
+                    // task rather than the background task in order to not block the latter.
+                    let mut websocket_handshakes = futures_util::stream::FuturesUnordered::new();
+                    // Number of entries in `websocket_handshakes` for each remote IP address.
+                    let mut websocket_handshakes_per_ip =
+                        hashbrown::HashMap::<IpAddr, usize, fnv::FnvBuildHasher>::default();
+
                     loop {
-                        let Some(accept_result) = future::or(
+                        let Some(event) = future::or(
                             async {
                                 (&mut on_foreground_shutdown).await;
                                 None
                             },
-                            async { Some(tcp_listener.accept().await) },
+                            future::or(
+                                async { Some(either::Left(tcp_listener.accept().await)) },
+                                async {
+                                    if websocket_handshakes.is_empty() {
+                                        future::pending::<()>().await;
+                                    }
+                                    Some(either::Right(websocket_handshakes.next().await.unwrap()))
+                                },
+                            ),
                         )
                         .await
                         else {
                             break;
                         };
 
-                        let (socket, addr) = match accept_result {
-                            Ok(v) => v,
-                            Err(_) => {
+                        let (socket, addr) = match event {
+                            either::Left(Ok(v)) => v,
+                            either::Left(Err(_)) => {
                                 // Errors here can happen if the accept failed, for example if no
                                 // file descriptor is available.
                                 // A wait is added in order to avoid having a busy-loop failing to
                                 // accept connections.
-                                smol::Timer::after(Duration::from_secs(2)).await;
+                                time_source.sleep(Duration::from_secs(2)).await;
+                                continue;
+                            }
+                            either::Right((ip, multiaddr, result)) => {
+                                let hashbrown::hash_map::Entry::Occupied(mut num_same_ip) =
+                                    websocket_handshakes_per_ip.entry(ip)
+                                else {
+                                    unreachable!()
+                                };
+                                *num_same_ip.get_mut() -= 1;
+                                if *num_same_ip.get() == 0 {
+                                    num_same_ip.remove();
+                                }
+
+                                match result {
+                                    Ok(socket) => {
+                                        log_callback.log(
+                                            LogLevel::Debug,
+                                            format!("incoming-connection; multiaddr={}", multiaddr),
+                                        );
+
+                                        let _ = to_background_tx
+                                            .send(ToBackground::IncomingConnection {
+                                                socket: Box::new(socket),
+                                                multiaddr,
+                                                when_connected: time_source.now(),
+                                            })
+                                            .await;
+                                    }
+                                    Err(err) => {
+                                        log_callback.log(
+                                            LogLevel::Debug,
+                                            format!(
+                                                "incoming-websocket-handshake-error; multiaddr={}; error={}",
+                                                multiaddr, err
+                                            ),
+                                        );
+                                    }
+                                }
                                 continue;
                             }
                         };
@@ -446,8 +986,55 @@ impl NetworkService {
                             ProtocolRef::Tcp(addr.port()),
                         ]
                         .into_iter()
+                        .chain(if is_websocket {
+                            Some(ProtocolRef::Ws)
+                        } else {
+                            None
+                        })
                         .collect::<Multiaddr>();
 
+                        if is_websocket {
+                            // Each handshake in progress holds a socket and some memory, while
+                            // opening a TCP connection is cheap. The number of handshakes in
+                            // progress is thus limited, both globally and for each remote IP
+                            // address. Connections that go above the limit are immediately
+                            // closed.
+                            let num_same_ip = websocket_handshakes_per_ip
+                                .get(&addr.ip())
+                                .copied()
+                                .unwrap_or(0);
+                            if websocket_handshakes.len() >= WEBSOCKET_MAX_PENDING_HANDSHAKES
+                                || num_same_ip >= WEBSOCKET_MAX_PENDING_HANDSHAKES_PER_IP
+                            {
+                                log_callback.log(
+                                    LogLevel::Debug,
+                                    format!(
+                                        "incoming-websocket-handshake-refused; multiaddr={}",
+                                        multiaddr
+                                    ),
+                                );
+                                continue;
+                            }
+                            *websocket_handshakes_per_ip.entry(addr.ip()).or_insert(0) += 1;
+
+                            // The handshake must finish within a certain time, in order to not
+                            // accumulate connections that never finish their handshake.
+                            let ip = addr.ip();
+                            let timeout = time_source.sleep(Duration::from_secs(10));
+                            websocket_handshakes.push(async move {
+                                let result = future::or(
+                                    websocket::websocket_server_handshake(socket),
+                                    async {
+                                        timeout.await;
+                                        Err(io::Error::from(io::ErrorKind::TimedOut))
+                                    },
+                                )
+                                .await;
+                                (ip, multiaddr, result)
+                            });
+                            continue;
+                        }
+
                         log_callback.log(
                             LogLevel::Debug,
                             format!("incoming-connection; multiaddr={}", multiaddr),
@@ -455,9 +1042,9 @@ impl NetworkService {
 
                         let _ = to_background_tx
                             .send(ToBackground::IncomingConnection {
-                                socket,
+                                socket: Box::new(socket),
                                 multiaddr,
-                                when_connected: Instant::now(),
+                                when_connected: time_source.now(),
                             })
                             .await;
                     }
@@ -481,6 +1068,7 @@ impl NetworkService {
         // This is done through a separate task due to ease of implementation.
         (inner.tasks_executor)(Box::pin({
             let to_background_tx = to_background_tx.clone();
+            let time_source = config.time_source.clone();
             let mut on_foreground_shutdown = foreground_shutdown.listen();
             async move {
                 let mut next_discovery = Duration::from_secs(1);
@@ -488,7 +1076,7 @@ impl NetworkService {
                 loop {
                     let still_alive = future::race(
                         async {
-                            smol::Timer::after(next_discovery).await;
+                            time_source.sleep(next_discovery).await;
                             true
                         },
                         async {
@@ -589,6 +1177,98 @@ impl NetworkService {
             .await;
     }
 
+    /// Updates the GrandPa round and set of the local node, and sends a neighbor packet to all
+    /// the peers we have a GrandPa substream with.
+    pub async fn set_local_grandpa_state(
+        &self,
+        chain_index: usize,
+        grandpa_state: service::GrandpaState,
+    ) {
+        let _ = self
+            .to_background_tx
+            .lock()
+            .await
+            .send(ToBackground::ForegroundSetLocalGrandpaState {
+                chain_index,
+                grandpa_state,
+            })
+            .await;
+    }
+
+    /// Sends a GrandPa vote to all the peers we have a GrandPa substream with.
+    pub async fn broadcast_grandpa_vote(&self, chain_index: usize, vote: voter::SignedVote) {
+        let _ = self
+            .to_background_tx
+            .lock()
+            .await
+            .send(ToBackground::ForegroundBroadcastGrandpaVote { chain_index, vote })
+            .await;
+    }
+
+    /// Sends a GrandPa commit message to all the peers we have a GrandPa substream with.
+    pub async fn broadcast_grandpa_commit(&self, chain_index: usize, commit: voter::Commit) {
+        let _ = self
+            .to_background_tx
+            .lock()
+            .await
+            .send(ToBackground::ForegroundBroadcastGrandpaCommit {
+                chain_index,
+                commit,
+            })
+            .await;
+    }
+
+    /// Asks the given peer to send back the votes of the latest GrandPa round it has completed.
+    ///
+    /// The response, if any, is later reported as an [`Event::GrandpaCatchUp`].
+    pub async fn send_grandpa_catch_up_request(
+        &self,
+        target: PeerId,
+        chain_index: usize,
+        request: protocol::CatchUpRequest,
+    ) -> Result<(), QueueNotificationError> {
+        let (result_tx, result_rx) = oneshot::channel();
+
+        let _ = self
+            .to_background_tx
+            .lock()
+            .await
+            .send(ToBackground::ForegroundSendGrandpaCatchUpRequest {
+                target,
+                chain_index,
+                request,
+                result_tx,
+            })
+            .await;
+
+        result_rx.await.unwrap()
+    }
+
+    /// Sends the votes of a completed GrandPa round to the given peer, in response to an
+    /// [`Event::GrandpaCatchUpRequest`].
+    pub async fn send_grandpa_catch_up(
+        &self,
+        target: PeerId,
+        chain_index: usize,
+        catch_up: voter::CatchUp,
+    ) -> Result<(), QueueNotificationError> {
+        let (result_tx, result_rx) = oneshot::channel();
+
+        let _ = self
+            .to_background_tx
+            .lock()
+            .await
+            .send(ToBackground::ForegroundSendGrandpaCatchUp {
+                target,
+                chain_index,
+                catch_up,
+                result_tx,
+            })
+            .await;
+
+        result_rx.await.unwrap()
+    }
+
     pub async fn send_block_announce(
         self: Arc<Self>,
         target: PeerId,
@@ -614,6 +1294,30 @@ impl NetworkService {
         result_rx.await.unwrap()
     }
 
+    /// Sends a transaction to all the peers we have a transactions substream with.
+    ///
+    /// Returns the list of peers the transaction has been sent to.
+    pub async fn announce_transaction(
+        self: Arc<Self>,
+        chain_index: usize,
+        transaction: Vec<u8>,
+    ) -> Vec<PeerId> {
+        let (result_tx, result_rx) = oneshot::channel();
+
+        let _ = self
+            .to_background_tx
+            .lock()
+            .await
+            .send(ToBackground::ForegroundAnnounceTransaction {
+                chain_index,
+                transaction,
+                result_tx,
+            })
+            .await;
+
+        result_rx.await.unwrap()
+    }
+
     /// Sends a blocks request to the given peer.
     // TODO: more docs
     // TODO: proper error type
@@ -707,12 +1411,205 @@ impl NetworkService {
 
         result
     }
-}
 
-impl Drop for NetworkService {
-    fn drop(&mut self) {
-        self.foreground_shutdown.notify(usize::max_value());
-    }
+    /// Sends a GrandPa warp sync request to the given peer.
+    pub async fn grandpa_warp_sync_request(
+        self: Arc<Self>,
+        target: PeerId,
+        chain_index: usize,
+        begin_hash: [u8; 32],
+    ) -> Result<service::EncodedGrandpaWarpSyncResponse, GrandpaWarpSyncRequestError> {
+        let (result_tx, result_rx) = oneshot::channel();
+
+        let _ = self
+            .to_background_tx
+            .lock()
+            .await
+            .send(ToBackground::ForegroundGrandpaWarpSyncRequest {
+                target: target.clone(),
+                chain_index,
+                begin_hash,
+                result_tx,
+            })
+            .await;
+
+        let result = result_rx.await.unwrap();
+
+        match &result {
+            Ok(response) => {
+                let decoded = response.decode();
+                self.log_callback.log(LogLevel::Debug, format!(
+                    "warp-sync-request-ended; peer_id={}; chain_index={}; begin_hash={}; outcome=success; num_fragments={}; is_finished={:?}",
+                    target, chain_index, HashDisplay(&begin_hash), decoded.fragments.len(), decoded.is_finished
+                ));
+            }
+            Err(err) => {
+                self.log_callback.log(LogLevel::Debug, format!(
+                    "warp-sync-request-ended; peer_id={}; chain_index={}; begin_hash={}; outcome=failure; error={}",
+                    target, chain_index, HashDisplay(&begin_hash), err
+                ));
+            }
+        }
+
+        result
+    }
+
+    /// Sends a state request to the given peer.
+    ///
+    /// The response is a Merkle proof of the storage entries that follow `start_key` in the
+    /// storage of the given block.
+    pub async fn state_request(
+        self: Arc<Self>,
+        target: PeerId,
+        chain_index: usize,
+        block_hash: [u8; 32],
+        start_key: protocol::StateRequestStart<'_>,
+    ) -> Result<service::EncodedStateResponse, StateRequestError> {
+        let (result_tx, result_rx) = oneshot::channel();
+
+        let (child_trie, start_key) = match start_key {
+            protocol::StateRequestStart::MainTrie(key) => (None, key.to_vec()),
+            protocol::StateRequestStart::ChildTrieDefault { child_trie, key } => {
+                (Some(child_trie.to_vec()), key.to_vec())
+            }
+        };
+
+        let _ = self
+            .to_background_tx
+            .lock()
+            .await
+            .send(ToBackground::ForegroundStateRequest {
+                target: target.clone(),
+                chain_index,
+                block_hash,
+                child_trie,
+                start_key,
+                result_tx,
+            })
+            .await;
+
+        let result = result_rx.await.unwrap();
+
+        match &result {
+            Ok(response) => {
+                self.log_callback.log(LogLevel::Debug, format!(
+                    "state-request-ended; peer_id={}; chain_index={}; block_hash={}; outcome=success; proof_size={}",
+                    target, chain_index, HashDisplay(&block_hash), response.decode().len()
+                ));
+            }
+            Err(err) => {
+                self.log_callback.log(LogLevel::Debug, format!(
+                    "state-request-ended; peer_id={}; chain_index={}; block_hash={}; outcome=failure; error={}",
+                    target, chain_index, HashDisplay(&block_hash), err
+                ));
+            }
+        }
+
+        result
+    }
+
+    /// Sends a storage proof request to the given peer.
+    pub async fn storage_proof_request(
+        self: Arc<Self>,
+        target: PeerId,
+        chain_index: usize,
+        config: protocol::StorageProofRequestConfig<impl Iterator<Item = impl AsRef<[u8]> + Clone>>,
+    ) -> Result<service::EncodedMerkleProof, StorageProofRequestError> {
+        let (result_tx, result_rx) = oneshot::channel();
+
+        let _ = self
+            .to_background_tx
+            .lock()
+            .await
+            .send(ToBackground::ForegroundStorageProofRequest {
+                target: target.clone(),
+                chain_index,
+                config: protocol::StorageProofRequestConfig {
+                    block_hash: config.block_hash,
+                    keys: config
+                        .keys
+                        .map(|key| key.as_ref().to_vec())
+                        .collect::<Vec<_>>()
+                        .into_iter(),
+                },
+                result_tx,
+            })
+            .await;
+
+        let result = result_rx.await.unwrap();
+
+        match &result {
+            Ok(response) => {
+                self.log_callback.log(LogLevel::Debug, format!(
+                    "storage-proof-request-ended; peer_id={}; chain_index={}; outcome=success; proof_size={}",
+                    target, chain_index, response.decode().len()
+                ));
+            }
+            Err(err) => {
+                self.log_callback.log(LogLevel::Debug, format!(
+                    "storage-proof-request-ended; peer_id={}; chain_index={}; outcome=failure; error={}",
+                    target, chain_index, err
+                ));
+            }
+        }
+
+        result
+    }
+
+    /// Sends a call proof request to the given peer.
+    pub async fn call_proof_request(
+        self: Arc<Self>,
+        target: PeerId,
+        chain_index: usize,
+        config: protocol::CallProofRequestConfig<'_, impl Iterator<Item = impl AsRef<[u8]>>>,
+    ) -> Result<service::EncodedMerkleProof, CallProofRequestError> {
+        let (result_tx, result_rx) = oneshot::channel();
+
+        let _ = self
+            .to_background_tx
+            .lock()
+            .await
+            .send(ToBackground::ForegroundCallProofRequest {
+                target: target.clone(),
+                chain_index,
+                config: protocol::CallProofRequestConfig {
+                    block_hash: config.block_hash,
+                    method: config.method.into_owned().into(),
+                    parameter_vectored: config
+                        .parameter_vectored
+                        .map(|v| v.as_ref().to_vec())
+                        .collect::<Vec<_>>()
+                        .into_iter(),
+                },
+                result_tx,
+            })
+            .await;
+
+        let result = result_rx.await.unwrap();
+
+        match &result {
+            Ok(response) => {
+                self.log_callback.log(LogLevel::Debug, format!(
+                    "call-proof-request-ended; peer_id={}; chain_index={}; outcome=success; proof_size={}",
+                    target, chain_index, response.decode().len()
+                ));
+            }
+            Err(err) => {
+                self.log_callback.log(LogLevel::Debug, format!(
+                    "call-proof-request-ended; peer_id={}; chain_index={}; outcome=failure; error={}",
+                    target, chain_index, err
+                ));
+            }
+        }
+
+        result
+    }
+}
+
+impl Drop for NetworkService {
+    fn drop(&mut self) {
+        self.foreground_shutdown.notify(usize::max_value());
+    }
 }
 
 /// Error when initializing the network service.
@@ -724,6 +1621,9 @@ pub enum InitError {
     /// A listening address passed through the configuration isn't valid.
     #[display(fmt = "A listening address passed through the configuration isn't valid: {_0}")]
     BadListenMultiaddr(Multiaddr),
+    /// The certificate hash of a WebRTC listening address doesn't match the certificate.
+    #[display(fmt = "Certificate hash of {_0} doesn't match the WebRTC certificate")]
+    WebRtcCerthashMismatch(Multiaddr),
 }
 
 /// Error returned by [`NetworkService::blocks_request`].
@@ -736,7 +1636,53 @@ pub enum BlocksRequestError {
     Request(service::BlocksRequestError),
 }
 
-/// Error returned by [`NetworkService::send_block_announce`].
+/// Error returned by [`NetworkService::grandpa_warp_sync_request`].
+#[derive(Debug, derive_more::Display)]
+pub enum GrandpaWarpSyncRequestError {
+    /// No established connection with the target.
+    NoConnection,
+    /// Error during the request.
+    #[display(fmt = "{_0}")]
+    Request(service::GrandpaWarpSyncRequestError),
+}
+
+/// Error returned by [`NetworkService::state_request`].
+#[derive(Debug, derive_more::Display)]
+pub enum StateRequestError {
+    /// No established connection with the target.
+    NoConnection,
+    /// Error during the request.
+    #[display(fmt = "{_0}")]
+    Request(service::StateRequestError),
+}
+
+/// Error returned by [`NetworkService::storage_proof_request`].
+#[derive(Debug, derive_more::Display)]
+pub enum StorageProofRequestError {
+    /// No established connection with the target.
+    NoConnection,
+    /// Storage proof request is too large and can't be sent.
+    RequestTooLarge,
+    /// Error during the request.
+    #[display(fmt = "{_0}")]
+    Request(service::StorageProofRequestError),
+}
+
+/// Error returned by [`NetworkService::call_proof_request`].
+#[derive(Debug, derive_more::Display)]
+pub enum CallProofRequestError {
+    /// No established connection with the target.
+    NoConnection,
+    /// Call proof request is too large and can't be sent.
+    RequestTooLarge,
+    /// Error during the request.
+    #[display(fmt = "{_0}")]
+    Request(service::CallProofRequestError),
+}
+
+/// Error returned by [`NetworkService::send_block_announce`],
+/// [`NetworkService::send_grandpa_catch_up_request`], and
+/// [`NetworkService::send_grandpa_catch_up`].
 #[derive(Debug, derive_more::Display)]
 pub enum QueueNotificationError {
     /// No established connection with the target.
@@ -761,27 +1707,9 @@ fn run(mut inner: Inner) {
 
 async fn background_task(mut inner: Inner) {
     loop {
-        // Pull messages that the coordinator has generated in destination to the various
-        // connections.
-        while let Some((connection_id, message)) = inner.network.pull_message_to_connection() {
-            // Note that it is critical for the sending to not take too long here, in order to not
-            // block the process of the network service.
-            // In particular, if sending the message to the connection is blocked due to sending
-            // a message on the connection-to-coordinator channel, this will result in a deadlock.
-            // For this reason, the connection task is always ready to immediately accept a message
-            // on the coordinator-to-connection channel.
-            inner
-                .active_connections
-                .get_mut(&connection_id)
-                .unwrap()
-                .send(message)
-                .await
-                .unwrap();
-        }
-
         if inner.process_network_service_events && matches!(inner.event_senders, either::Left(_)) {
             let event = loop {
-                let inner_event = match inner.network.next_event(Instant::now()) {
+                let inner_event = match inner.network.next_event(inner.time_source.now()) {
                     Some(ev) => ev,
                     None => break None,
                 };
@@ -927,6 +1855,46 @@ async fn background_task(mut inner: Inner) {
                             .unwrap()
                             .send(response.map_err(BlocksRequestError::Request));
                     }
+                    service::Event::RequestResult {
+                        request_id,
+                        response: service::RequestResult::GrandpaWarpSync(response),
+                    } => {
+                        let _ = inner
+                            .grandpa_warp_sync_requests
+                            .remove(&request_id)
+                            .unwrap()
+                            .send(response.map_err(GrandpaWarpSyncRequestError::Request));
+                    }
+                    service::Event::RequestResult {
+                        request_id,
+                        response: service::RequestResult::State(response),
+                    } => {
+                        let _ = inner
+                            .state_requests
+                            .remove(&request_id)
+                            .unwrap()
+                            .send(response.map_err(StateRequestError::Request));
+                    }
+                    service::Event::RequestResult {
+                        request_id,
+                        response: service::RequestResult::StorageProof(response),
+                    } => {
+                        let _ = inner
+                            .storage_proof_requests
+                            .remove(&request_id)
+                            .unwrap()
+                            .send(response.map_err(StorageProofRequestError::Request));
+                    }
+                    service::Event::RequestResult {
+                        request_id,
+                        response: service::RequestResult::CallProof(response),
+                    } => {
+                        let _ = inner
+                            .call_proof_requests
+                            .remove(&request_id)
+                            .unwrap()
+                            .send(response.map_err(CallProofRequestError::Request));
+                    }
                     service::Event::RequestResult { .. } => {
                         // We never start a request of any other kind.
                         unreachable!()
@@ -968,7 +1936,7 @@ async fn background_task(mut inner: Inner) {
                                     }
 
                                     inner.network.discover(
-                                        &Instant::now(),
+                                        &inner.time_source.now(),
                                         chain_index,
                                         peer_id,
                                         valid_addrs,
@@ -991,9 +1959,11 @@ async fn background_task(mut inner: Inner) {
                             LogLevel::Debug,
                             format!("identify-request; peer_id={}", peer_id),
                         );
-                        inner
-                            .network
-                            .respond_identify(request_id, &inner.identify_agent_version);
+                        inner.network.respond_identify(
+                            request_id,
+                            &inner.identify_agent_version,
+                            inner.listen_addresses.iter(),
+                        );
                     }
                     service::Event::BlocksRequestIn {
                         peer_id,
@@ -1042,47 +2012,329 @@ async fn background_task(mut inner: Inner) {
                             },
                         );
                     }
-                    service::Event::GrandpaNeighborPacket {
-                        chain_index,
+                    service::Event::StorageProofRequestIn {
                         peer_id,
-                        state,
-                    } => {
-                        inner.log_callback.log(LogLevel::Debug, format!(
-                            "grandpa-neighbor-packet; peer_id={}; chain_index={}; round_number={}; set_id={}; commit_finalized_height={}",
-                            peer_id,
-                            chain_index,
-                            state.round_number,
-                            state.set_id,
-                            state.commit_finalized_height,
-                        ));
-                        // TODO: report to the sync state machine
-                    }
-                    service::Event::GrandpaCommitMessage {
                         chain_index,
-                        peer_id,
-                        message,
+                        request,
+                        request_id,
                     } => {
                         inner.log_callback.log(
                             LogLevel::Debug,
                             format!(
-                            "grandpa-commit-message; peer_id={}; chain_index={}; target_hash={}",
-                            peer_id,
-                            chain_index,
-                            HashDisplay(message.decode().message.target_hash),
-                        ),
+                                "incoming-storage-proof-request; peer_id={}; chain_index={}",
+                                peer_id, chain_index
+                            ),
                         );
+
+                        let database = inner.databases[chain_index].clone();
+                        let block_number_bytes = inner.network.block_number_bytes(chain_index);
+                        let log_callback = inner.log_callback.clone();
+                        let to_background_tx = inner.to_background_tx.clone();
+                        (inner.tasks_executor)(Box::pin(async move {
+                            let child_trie = request.child_trie().map(|name| name.to_vec());
+                            let request = request.decode();
+                            let keys = request
+                                .keys
+                                .map(|key| runtime_call::ProofKey {
+                                    child_trie: child_trie.clone(),
+                                    key_nibbles: trie::bytes_to_nibbles(key.iter().copied())
+                                        .map(u8::from)
+                                        .collect(),
+                                })
+                                .collect();
+                            let proof = match runtime_call::storage_proof(
+                                &database,
+                                block_number_bytes,
+                                request.block_hash,
+                                keys,
+                            )
+                            .await
+                            {
+                                Ok(proof) => Some(proof),
+                                Err(error) => {
+                                    log_callback.log(
+                                        LogLevel::Debug,
+                                        format!(
+                                            "incoming-storage-proof-request-error; error={}",
+                                            error
+                                        ),
+                                    );
+                                    None
+                                }
+                            };
+                            let _ = to_background_tx
+                                .send(ToBackground::InRequestResponse {
+                                    request_id,
+                                    response: InRequestResponse::StorageProof(proof),
+                                })
+                                .await;
+                        }));
                     }
-                    service::Event::ProtocolError { peer_id, error } => {
+                    service::Event::CallProofRequestIn {
+                        peer_id,
+                        chain_index,
+                        request,
+                        request_id,
+                    } => {
                         inner.log_callback.log(
-                            LogLevel::Warn,
-                            format!("protocol-error; peer_id={}; error={}", peer_id, error),
+                            LogLevel::Debug,
+                            format!(
+                                "incoming-call-proof-request; peer_id={}; chain_index={}",
+                                peer_id, chain_index
+                            ),
                         );
-                        for chain_index in 0..inner.network.num_chains() {
-                            inner.unassign_slot_and_ban(chain_index, peer_id.clone());
-                        }
-                        inner.process_network_service_events = true;
-                    }
-                }
+
+                        let database = inner.databases[chain_index].clone();
+                        let runtime_caches = inner.runtime_caches[chain_index].clone();
+                        let block_number_bytes = inner.network.block_number_bytes(chain_index);
+                        let log_callback = inner.log_callback.clone();
+                        let to_background_tx = inner.to_background_tx.clone();
+                        (inner.tasks_executor)(Box::pin(async move {
+                            let request = request.decode();
+                            let proof = match runtime_call::runtime_call_proof(
+                                &database,
+                                &runtime_caches,
+                                block_number_bytes,
+                                request.block_hash,
+                                &request.method,
+                                request.parameter_vectored,
+                            )
+                            .await
+                            {
+                                Ok(proof) => Some(proof),
+                                Err(error) => {
+                                    log_callback.log(
+                                        LogLevel::Debug,
+                                        format!(
+                                            "incoming-call-proof-request-error; error={}",
+                                            error
+                                        ),
+                                    );
+                                    None
+                                }
+                            };
+                            let _ = to_background_tx
+                                .send(ToBackground::InRequestResponse {
+                                    request_id,
+                                    response: InRequestResponse::CallProof(proof),
+                                })
+                                .await;
+                        }));
+                    }
+                    service::Event::GrandpaWarpSyncRequestIn {
+                        peer_id,
+                        chain_index,
+                        begin_hash,
+                        request_id,
+                    } => {
+                        inner.log_callback.log(
+                            LogLevel::Debug,
+                            format!(
+                                "incoming-grandpa-warp-sync-request; peer_id={}; chain_index={}; begin_hash={}",
+                                peer_id, chain_index, HashDisplay(&begin_hash)
+                            ),
+                        );
+
+                        let database = inner.databases[chain_index].clone();
+                        let block_number_bytes = inner.network.block_number_bytes(chain_index);
+                        let log_callback = inner.log_callback.clone();
+                        let to_background_tx = inner.to_background_tx.clone();
+                        (inner.tasks_executor)(Box::pin(async move {
+                            let response = match grandpa_warp_sync_response(
+                                &database,
+                                block_number_bytes,
+                                begin_hash,
+                            )
+                            .await
+                            {
+                                Ok(response) => response,
+                                Err(error) => {
+                                    log_callback.log(
+                                        LogLevel::Warn,
+                                        format!(
+                                            "incoming-grandpa-warp-sync-request-error; error={}",
+                                            error
+                                        ),
+                                    );
+                                    None
+                                }
+                            };
+                            let _ = to_background_tx
+                                .send(ToBackground::InRequestResponse {
+                                    request_id,
+                                    response: InRequestResponse::GrandpaWarpSync(response),
+                                })
+                                .await;
+                        }));
+                    }
+                    service::Event::StateRequestIn {
+                        peer_id,
+                        chain_index,
+                        request,
+                        request_id,
+                    } => {
+                        inner.log_callback.log(
+                            LogLevel::Debug,
+                            format!(
+                                "incoming-state-request; peer_id={}; chain_index={}",
+                                peer_id, chain_index
+                            ),
+                        );
+
+                        let database = inner.databases[chain_index].clone();
+                        let block_number_bytes = inner.network.block_number_bytes(chain_index);
+                        let log_callback = inner.log_callback.clone();
+                        let to_background_tx = inner.to_background_tx.clone();
+                        (inner.tasks_executor)(Box::pin(async move {
+                            let proof = match state_response(&database, block_number_bytes, request)
+                                .await
+                            {
+                                Ok(proof) => Some(proof),
+                                Err(error) => {
+                                    log_callback.log(
+                                        LogLevel::Debug,
+                                        format!("incoming-state-request-error; error={}", error),
+                                    );
+                                    None
+                                }
+                            };
+                            let _ = to_background_tx
+                                .send(ToBackground::InRequestResponse {
+                                    request_id,
+                                    response: InRequestResponse::State(proof),
+                                })
+                                .await;
+                        }));
+                    }
+                    service::Event::GrandpaNeighborPacket {
+                        chain_index,
+                        peer_id,
+                        state,
+                    } => {
+                        inner.log_callback.log(LogLevel::Debug, format!(
+                            "grandpa-neighbor-packet; peer_id={}; chain_index={}; round_number={}; set_id={}; commit_finalized_height={}",
+                            peer_id,
+                            chain_index,
+                            state.round_number,
+                            state.set_id,
+                            state.commit_finalized_height,
+                        ));
+                        // TODO: report to the sync state machine
+                        break Some(Event::GrandpaNeighborPacket {
+                            chain_index,
+                            peer_id,
+                            state,
+                        });
+                    }
+                    service::Event::GrandpaCommitMessage {
+                        chain_index,
+                        peer_id,
+                        message,
+                    } => {
+                        inner.log_callback.log(
+                            LogLevel::Debug,
+                            format!(
+                            "grandpa-commit-message; peer_id={}; chain_index={}; target_hash={}",
+                            peer_id,
+                            chain_index,
+                            HashDisplay(message.decode().message.target_hash),
+                        ),
+                        );
+                        break Some(Event::GrandpaCommit {
+                            chain_index,
+                            peer_id,
+                            message,
+                        });
+                    }
+                    service::Event::Transactions {
+                        chain_index,
+                        peer_id,
+                        transactions,
+                    } => {
+                        inner.log_callback.log(
+                            LogLevel::Debug,
+                            format!(
+                                "transactions; peer_id={}; chain_index={}; num_transactions={}",
+                                peer_id,
+                                chain_index,
+                                transactions.decode().len(),
+                            ),
+                        );
+                        break Some(Event::Transactions {
+                            chain_index,
+                            peer_id,
+                            transactions,
+                        });
+                    }
+                    service::Event::GrandpaCatchUpMessage {
+                        chain_index,
+                        peer_id,
+                        message,
+                    } => {
+                        inner.log_callback.log(
+                            LogLevel::Debug,
+                            format!(
+                                "grandpa-catch-up-message; peer_id={}; chain_index={}; round_number={}",
+                                peer_id,
+                                chain_index,
+                                message.decode().round_number,
+                            ),
+                        );
+                        break Some(Event::GrandpaCatchUp {
+                            chain_index,
+                            peer_id,
+                            message,
+                        });
+                    }
+                    service::Event::GrandpaCatchUpRequest {
+                        chain_index,
+                        peer_id,
+                        request,
+                    } => {
+                        inner.log_callback.log(
+                            LogLevel::Debug,
+                            format!(
+                                "grandpa-catch-up-request; peer_id={}; chain_index={}; round_number={}",
+                                peer_id, chain_index, request.round_number,
+                            ),
+                        );
+                        break Some(Event::GrandpaCatchUpRequest {
+                            chain_index,
+                            peer_id,
+                            request,
+                        });
+                    }
+                    service::Event::GrandpaVoteMessage {
+                        chain_index,
+                        peer_id,
+                        message,
+                    } => {
+                        inner.log_callback.log(
+                            LogLevel::Debug,
+                            format!(
+                                "grandpa-vote-message; peer_id={}; chain_index={}; round_number={}",
+                                peer_id,
+                                chain_index,
+                                message.decode().round_number,
+                            ),
+                        );
+                        break Some(Event::GrandpaVote {
+                            chain_index,
+                            peer_id,
+                            message,
+                        });
+                    }
+                    service::Event::ProtocolError { peer_id, error } => {
+                        inner.log_callback.log(
+                            LogLevel::Warn,
+                            format!("protocol-error; peer_id={}; error={}", peer_id, error),
+                        );
+                        for chain_index in 0..inner.network.num_chains() {
+                            inner.unassign_slot_and_ban(chain_index, peer_id.clone());
+                        }
+                        inner.process_network_service_events = true;
+                    }
+                }
             };
 
             // Dispatch the event to the various senders.
@@ -1115,7 +2367,7 @@ async fn background_task(mut inner: Inner) {
 
             // TODO: doc
             for chain_index in 0..inner.network.num_chains() {
-                let now = Instant::now();
+                let now = inner.time_source.now();
 
                 // Clean up the content of `slots_assign_backoff`.
                 // TODO: the background task should be woken up when the ban expires
@@ -1161,20 +2413,70 @@ async fn background_task(mut inner: Inner) {
                     break;
                 }
 
-                let start_connect = match inner.network.next_start_connect(Instant::now) {
-                    Some(sc) => sc,
-                    None => break,
-                };
+                let start_connect =
+                    match inner.network.next_start_connect(|| inner.time_source.now()) {
+                        Some(sc) => sc,
+                        None => break,
+                    };
 
                 inner.num_pending_out_attempts += 1;
 
                 // Perform the connection process in a separate task.
                 let to_background_tx = inner.to_background_tx.clone();
                 let log_callback = inner.log_callback.clone();
+                let time_source = inner.time_source.clone();
+                let memory_network = inner.memory_network.clone();
+
+                // QUIC connections are opened from a QUIC endpoint shared between all the
+                // connections rather than from a socket dedicated to the connection.
+                if let Some((address, version)) = tasks::multiaddr_to_quic(&start_connect.multiaddr)
+                {
+                    let endpoint = inner.quic_endpoint(&address);
+                    let mut client_config = inner.quic_client_config.clone();
+                    client_config.version(version);
+
+                    (inner.tasks_executor)(Box::pin(async move {
+                        let result = match endpoint {
+                            Some(endpoint) => {
+                                tasks::opening_quic_connection_task(
+                                    endpoint,
+                                    client_config,
+                                    address,
+                                    start_connect.clone(),
+                                    log_callback,
+                                    time_source,
+                                )
+                                .await
+                            }
+                            None => Err(()),
+                        };
+
+                        let _ = to_background_tx
+                            .send(match result {
+                                Ok(connection) => ToBackground::QuicConnectionEstablishSuccess {
+                                    connection,
+                                    info: start_connect,
+                                },
+                                Err(()) => ToBackground::ConnectionEstablishFail {
+                                    info: start_connect,
+                                },
+                            })
+                            .await;
+                    }));
+
+                    inner.process_network_service_events = true;
+                    continue;
+                }
+
                 (inner.tasks_executor)(Box::pin(async move {
                     // TODO: interrupt immediately if `to_background_tx` is dropped
-                    if let Ok(socket) =
-                        tasks::opening_connection_task(start_connect.clone(), log_callback).await
+                    if let Ok(socket) = tasks::opening_connection_task(
+                        start_connect.clone(),
+                        memory_network,
+                        log_callback,
+                        time_source,
+                    )
+                    .await
                     {
                         let _ = to_background_tx
                             .send(ToBackground::ConnectionEstablishSuccess {
@@ -1195,6 +2497,27 @@ async fn background_task(mut inner: Inner) {
             }
         }
 
+        // Pull messages that the coordinator has generated in destination to the various
+        // connections.
+        // This is done right before waiting for the next message, as processing the events
+        // above might have generated messages as well, for example in order to accept an
+        // incoming substream, and nothing would wake up the task in order to send them.
+        while let Some((connection_id, message)) = inner.network.pull_message_to_connection() {
+            // Note that it is critical for the sending to not take too long here, in order to not
+            // block the process of the network service.
+            // In particular, if sending the message to the connection is blocked due to sending
+            // a message on the connection-to-coordinator channel, this will result in a deadlock.
+            // For this reason, the connection task is always ready to immediately accept a message
+            // on the coordinator-to-connection channel.
+            inner
+                .active_connections
+                .get_mut(&connection_id)
+                .unwrap()
+                .send(message)
+                .await
+                .unwrap();
+        }
+
         let message = match inner.event_senders {
             either::Left(_) => inner.to_background_rx.next().await.unwrap(),
             either::Right(sending) => {
@@ -1256,6 +2579,7 @@ async fn background_task(mut inner: Inner) {
 
                 (inner.tasks_executor)(Box::pin(tasks::established_connection_task(
                     inner.log_callback.clone(),
+                    inner.time_source.clone(),
                     info.multiaddr.to_string(),
                     socket,
                     connection_id,
@@ -1284,6 +2608,7 @@ async fn background_task(mut inner: Inner) {
 
                 (inner.tasks_executor)(Box::pin(tasks::established_connection_task(
                     inner.log_callback.clone(),
+                    inner.time_source.clone(),
                     multiaddr.to_string(),
                     socket,
                     connection_id,
@@ -1295,11 +2620,110 @@ async fn background_task(mut inner: Inner) {
                 inner.process_network_service_events = true;
             }
 
+            ToBackground::QuicConnectionEstablishSuccess { connection, info } => {
+                inner.num_pending_out_attempts -= 1;
+
+                // The certificate is missing only if the remote doesn't follow the libp2p
+                // specification, in which case the connection task reports the handshake as
+                // failed.
+                let remote_tls_certificate =
+                    quic::remote_certificate(&connection).unwrap_or_default();
+                let (connection_id, connection_task) =
+                    inner.network.pending_outcome_ok_multi_stream(
+                        info.id,
+                        service::MultiStreamHandshakeKind::Quic {
+                            remote_tls_certificate,
+                        },
+                    );
+
+                let (tx, rx) = channel::bounded(16); // TODO: ?!
+                inner.active_connections.insert(connection_id, tx);
+
+                (inner.tasks_executor)(Box::pin(tasks::quic_connection_task(
+                    inner.log_callback.clone(),
+                    inner.time_source.clone(),
+                    info.multiaddr.to_string(),
+                    connection,
+                    connection_id,
+                    connection_task,
+                    rx,
+                    inner.to_background_tx.clone(),
+                )));
+
+                inner.process_network_service_events = true;
+            }
+
+            ToBackground::IncomingQuicConnection {
+                connection,
+                multiaddr,
+                when_connected,
+            } => {
+                let remote_tls_certificate =
+                    quic::remote_certificate(&connection).unwrap_or_default();
+                let (connection_id, connection_task) =
+                    inner.network.add_multi_stream_incoming_connection(
+                        when_connected,
+                        service::MultiStreamHandshakeKind::Quic {
+                            remote_tls_certificate,
+                        },
+                        multiaddr.clone(),
+                    );
+
+                let (tx, rx) = channel::bounded(16); // TODO: ?!
+                inner.active_connections.insert(connection_id, tx);
+
+                (inner.tasks_executor)(Box::pin(tasks::quic_connection_task(
+                    inner.log_callback.clone(),
+                    inner.time_source.clone(),
+                    multiaddr.to_string(),
+                    connection,
+                    connection_id,
+                    connection_task,
+                    rx,
+                    inner.to_background_tx.clone(),
+                )));
+
+                inner.process_network_service_events = true;
+            }
+
+            ToBackground::IncomingWebRtcConnection(incoming) => {
+                (inner.tasks_executor)(Box::pin(tasks::webrtc_connection_task(
+                    inner.log_callback.clone(),
+                    inner.time_source.clone(),
+                    inner.webrtc_certificate.inner.clone(),
+                    incoming,
+                    inner.to_background_tx.clone(),
+                )));
+            }
+
+            ToBackground::WebRtcConnectionEstablished {
+                multiaddr,
+                handshake_kind,
+                when_connected,
+                result_tx,
+            } => {
+                let (connection_id, connection_task) =
+                    inner.network.add_multi_stream_incoming_connection(
+                        when_connected,
+                        handshake_kind,
+                        multiaddr,
+                    );
+
+                let (tx, rx) = channel::bounded(16); // TODO: ?!
+                inner.active_connections.insert(connection_id, tx);
+
+                // The connection task always waits for this response, and can only be gone if the
+                // node is shutting down.
+                let _ = result_tx.send((connection_id, connection_task, rx));
+
+                inner.process_network_service_events = true;
+            }
+
             ToBackground::StartKademliaDiscoveries { when_done } => {
                 for chain_index in 0..inner.databases.len() {
                     let operation_id = inner
                         .network
-                        .start_kademlia_discovery_round(Instant::now(), chain_index);
+                        .start_kademlia_discovery_round(inner.time_source.now(), chain_index);
                     let _prev_val = inner
                         .kademlia_discovery_operations
                         .insert(operation_id, chain_index);
@@ -1335,6 +2759,30 @@ async fn background_task(mut inner: Inner) {
 
                 let _ = result_tx.send(result);
             }
+            ToBackground::ForegroundAnnounceTransaction {
+                chain_index,
+                transaction,
+                result_tx,
+            } => {
+                // TODO: keep track of which peer knows about which transaction, and don't send it again
+                let mut sent_peers = Vec::new();
+                for peer in inner
+                    .network
+                    .opened_transactions_substream(chain_index)
+                    .cloned()
+                    .collect::<Vec<_>>()
+                {
+                    if inner
+                        .network
+                        .announce_transaction(&peer, chain_index, &transaction)
+                        .is_ok()
+                    {
+                        sent_peers.push(peer);
+                    }
+                }
+
+                let _ = result_tx.send(sent_peers);
+            }
             ToBackground::ForegroundSetLocalBestBlock {
                 chain_index,
                 best_hash,
@@ -1344,6 +2792,142 @@ async fn background_task(mut inner: Inner) {
                     .network
                     .set_local_best_block(chain_index, best_hash, best_number);
             }
+            ToBackground::ForegroundSetLocalGrandpaState {
+                chain_index,
+                grandpa_state,
+            } => {
+                inner
+                    .network
+                    .set_local_grandpa_state(chain_index, grandpa_state);
+            }
+            ToBackground::ForegroundBroadcastGrandpaVote { chain_index, vote } => {
+                let (target_hash, target_number) = (&vote.target_hash, vote.target_number);
+                inner.network.broadcast_grandpa_vote(
+                    chain_index,
+                    protocol::VoteMessageRef {
+                        round_number: vote.round_number,
+                        set_id: vote.set_id,
+                        message: match vote.kind {
+                            voter::VoteKind::Prevote => {
+                                protocol::MessageRef::Prevote(protocol::UnsignedPrevoteRef {
+                                    target_hash,
+                                    target_number,
+                                })
+                            }
+                            voter::VoteKind::Precommit => {
+                                protocol::MessageRef::Precommit(protocol::UnsignedPrecommitRef {
+                                    target_hash,
+                                    target_number,
+                                })
+                            }
+                        },
+                        signature: &vote.signature,
+                        authority_public_key: &vote.authority_public_key,
+                    },
+                );
+            }
+            ToBackground::ForegroundBroadcastGrandpaCommit {
+                chain_index,
+                commit,
+            } => {
+                inner.network.broadcast_grandpa_commit(
+                    chain_index,
+                    protocol::CommitMessageRef {
+                        round_number: commit.round_number,
+                        set_id: commit.set_id,
+                        message: protocol::CompactCommitRef {
+                            target_hash: &commit.target_hash,
+                            target_number: commit.target_number,
+                            precommits: commit
+                                .precommits
+                                .iter()
+                                .map(|p| protocol::UnsignedPrecommitRef {
+                                    target_hash: &p.target_hash,
+                                    target_number: p.target_number,
+                                })
+                                .collect(),
+                            auth_data: commit
+                                .precommits
+                                .iter()
+                                .map(|p| (&p.signature, &p.authority_public_key))
+                                .collect(),
+                        },
+                    },
+                );
+            }
+            ToBackground::ForegroundSendGrandpaCatchUpRequest {
+                target,
+                chain_index,
+                request,
+                result_tx,
+            } => {
+                // The call to `send_grandpa_catch_up_request` below panics if we have no
+                // GrandPa substream with the target.
+                let result = if inner
+                    .network
+                    .can_send_grandpa_messages(&target, chain_index)
+                {
+                    inner
+                        .network
+                        .send_grandpa_catch_up_request(&target, chain_index, request)
+                        .map_err(QueueNotificationError::Queue)
+                } else {
+                    Err(QueueNotificationError::NoConnection)
+                };
+
+                let _ = result_tx.send(result);
+            }
+            ToBackground::ForegroundSendGrandpaCatchUp {
+                target,
+                chain_index,
+                catch_up,
+                result_tx,
+            } => {
+                // The call to `send_grandpa_catch_up` below panics if we have no GrandPa
+                // substream with the target.
+                let result = if inner
+                    .network
+                    .can_send_grandpa_messages(&target, chain_index)
+                {
+                    inner
+                        .network
+                        .send_grandpa_catch_up(
+                            &target,
+                            chain_index,
+                            protocol::CatchUpRef {
+                                set_id: catch_up.set_id,
+                                round_number: catch_up.round_number,
+                                prevotes: catch_up
+                                    .prevotes
+                                    .iter()
+                                    .map(|p| protocol::PrevoteRef {
+                                        target_hash: &p.target_hash,
+                                        target_number: p.target_number,
+                                        signature: &p.signature,
+                                        authority_public_key: &p.authority_public_key,
+                                    })
+                                    .collect(),
+                                precommits: catch_up
+                                    .precommits
+                                    .iter()
+                                    .map(|p| protocol::PrecommitRef {
+                                        target_hash: &p.target_hash,
+                                        target_number: p.target_number,
+                                        signature: &p.signature,
+                                        authority_public_key: &p.authority_public_key,
+                                    })
+                                    .collect(),
+                                base_hash: &catch_up.base_hash,
+                                base_number: catch_up.base_number,
+                            },
+                        )
+                        .map_err(QueueNotificationError::Queue)
+                } else {
+                    Err(QueueNotificationError::NoConnection)
+                };
+
+                let _ = result_tx.send(result);
+            }
             ToBackground::ForegroundBlocksRequest {
                 target,
                 chain_index,
@@ -1353,7 +2937,7 @@ async fn background_task(mut inner: Inner) {
                 // The call to `start_blocks_request` below panics if we have no active connection.
                 if inner.network.can_start_requests(&target) {
                     let request_id = inner.network.start_blocks_request(
-                        Instant::now(),
+                        inner.time_source.now(),
                         &target,
                         chain_index,
                         config,
@@ -1366,9 +2950,153 @@ async fn background_task(mut inner: Inner) {
                     let _ = result_tx.send(Err(BlocksRequestError::NoConnection));
                 }
             }
+            ToBackground::ForegroundGrandpaWarpSyncRequest {
+                target,
+                chain_index,
+                begin_hash,
+                result_tx,
+            } => {
+                // The call to `start_grandpa_warp_sync_request` below panics if we have no
+                // active connection.
+                if inner.network.can_start_requests(&target) {
+                    let request_id = inner.network.start_grandpa_warp_sync_request(
+                        inner.time_source.now(),
+                        &target,
+                        chain_index,
+                        begin_hash,
+                        Duration::from_secs(12),
+                    );
+
+                    inner
+                        .grandpa_warp_sync_requests
+                        .insert(request_id, result_tx);
+                } else {
+                    let _ = result_tx.send(Err(GrandpaWarpSyncRequestError::NoConnection));
+                }
+            }
+            ToBackground::ForegroundStateRequest {
+                target,
+                chain_index,
+                block_hash,
+                child_trie,
+                start_key,
+                result_tx,
+            } => {
+                // The call to `start_state_request` below panics if we have no active connection.
+                if inner.network.can_start_requests(&target) {
+                    let request_id = inner.network.start_state_request(
+                        inner.time_source.now(),
+                        &target,
+                        chain_index,
+                        &block_hash,
+                        match &child_trie {
+                            Some(child_trie) => protocol::StateRequestStart::ChildTrieDefault {
+                                child_trie,
+                                key: &start_key,
+                            },
+                            None => protocol::StateRequestStart::MainTrie(&start_key),
+                        },
+                        Duration::from_secs(12),
+                    );
+
+                    inner.state_requests.insert(request_id, result_tx);
+                } else {
+                    let _ = result_tx.send(Err(StateRequestError::NoConnection));
+                }
+            }
+            ToBackground::ForegroundStorageProofRequest {
+                target,
+                chain_index,
+                config,
+                result_tx,
+            } => {
+                // The call to `start_storage_proof_request` below panics if we have no active
+                // connection.
+                if inner.network.can_start_requests(&target) {
+                    match inner.network.start_storage_proof_request(
+                        inner.time_source.now(),
+                        &target,
+                        chain_index,
+                        config,
+                        Duration::from_secs(12),
+                    ) {
+                        Ok(request_id) => {
+                            inner.storage_proof_requests.insert(request_id, result_tx);
+                        }
+                        Err(service::StartRequestError::RequestTooLarge) => {
+                            let _ = result_tx.send(Err(StorageProofRequestError::RequestTooLarge));
+                        }
+                    }
+                } else {
+                    let _ = result_tx.send(Err(StorageProofRequestError::NoConnection));
+                }
+            }
+            ToBackground::ForegroundCallProofRequest {
+                target,
+                chain_index,
+                config,
+                result_tx,
+            } => {
+                // The call to `start_call_proof_request` below panics if we have no active
+                // connection.
+                if inner.network.can_start_requests(&target) {
+                    match inner.network.start_call_proof_request(
+                        inner.time_source.now(),
+                        &target,
+                        chain_index,
+                        config,
+                        Duration::from_secs(12),
+                    ) {
+                        Ok(request_id) => {
+                            inner.call_proof_requests.insert(request_id, result_tx);
+                        }
+                        Err(service::StartRequestError::RequestTooLarge) => {
+                            let _ = result_tx.send(Err(CallProofRequestError::RequestTooLarge));
+                        }
+                    }
+                } else {
+                    let _ = result_tx.send(Err(CallProofRequestError::NoConnection));
+                }
+            }
             ToBackground::ForegroundGetNumEstablishedConnections { result_tx } => {
                 let _ = result_tx.send(inner.network.num_established_connections());
             }
+            ToBackground::InRequestResponse {
+                request_id,
+                response,
+            } => {
+                match response {
+                    InRequestResponse::StorageProof(proof) => inner
+                        .network
+                        .respond_storage_proof(request_id, proof.as_deref()),
+                    InRequestResponse::CallProof(proof) => inner
+                        .network
+                        .respond_call_proof(request_id, proof.as_deref()),
+                    InRequestResponse::GrandpaWarpSync(response) => {
+                        inner.network.respond_grandpa_warp_sync(
+                            request_id,
+                            response.as_ref().map(|(fragments, is_finished)| {
+                                protocol::GrandpaWarpSyncResponse {
+                                    fragments: fragments
+                                        .iter()
+                                        .map(|(header, justification)| {
+                                            protocol::GrandpaWarpSyncResponseFragment {
+                                                scale_encoded_header: header,
+                                                scale_encoded_justification: justification,
+                                            }
+                                        })
+                                        .collect(),
+                                    is_finished: *is_finished,
+                                }
+                            }),
+                        )
+                    }
+                    InRequestResponse::State(proof) => {
+                        inner.network.respond_state(request_id, proof.as_deref())
+                    }
+                }
+                inner.process_network_service_events = true;
+            }
             ToBackground::ForegroundGetNumPeers {
                 chain_index,
                 result_tx,
@@ -1383,7 +3111,7 @@ impl Inner {
     fn unassign_slot_and_ban(&mut self, chain_index: usize, peer_id: PeerId) {
         self.network.unassign_slot(chain_index, &peer_id);
 
-        let new_expiration = Instant::now() + Duration::from_secs(20); // TODO: arbitrary constant
+        let new_expiration = self.time_source.now() + Duration::from_secs(20); // TODO: arbitrary constant
         match self.slots_assign_backoff.entry((peer_id, chain_index)) {
             hashbrown::hash_map::Entry::Occupied(e) if *e.get() < new_expiration => {
                 *e.into_mut() = new_expiration;
@@ -1394,6 +3122,46 @@ impl Inner {
             }
         }
     }
+
+    /// Returns a QUIC endpoint that can be used in order to open a connection towards the given
+    /// address. Returns `None` if no such endpoint exists and creating one failed.
+    fn quic_endpoint(&mut self, target: &SocketAddr) -> Option<quinn::Endpoint> {
+        // Endpoints that listen on a specific IP address can't necessarily reach the target.
+        if let Some(endpoint) = self.quic_endpoints.iter().find(|endpoint| {
+            endpoint.local_addr().is_ok_and(|local_addr| {
+                local_addr.ip().is_unspecified() && local_addr.is_ipv4() == target.is_ipv4()
+            })
+        }) {
+            return Some(endpoint.clone());
+        }
+
+        let bind_address = if target.is_ipv4() {
+            SocketAddr::from((Ipv4Addr::UNSPECIFIED, 0))
+        } else {
+            SocketAddr::from((Ipv6Addr::UNSPECIFIED, 0))
+        };
+
+        match std::net::UdpSocket::bind(bind_address).and_then(|socket| {
+            quinn::Endpoint::new(
+                quinn::EndpointConfig::default(),
+                None,
+                socket,
+                Arc::new(quinn::SmolRuntime),
+            )
+        }) {
+            Ok(endpoint) => {
+                self.quic_endpoints.push(endpoint.clone());
+                Some(endpoint)
+            }
+            Err(err) => {
+                self.log_callback.log(
+                    LogLevel::Warn,
+                    format!("quic-endpoint-creation-error; error={}", err),
+                );
+                None
+            }
+        }
+    }
 }
 
 /// Builds the response to a block request by reading from the given database.
@@ -1474,3 +3242,222 @@ async fn blocks_request_response(
         })
         .await
 }
+
+/// Builds the response to a GrandPa warp sync request by reading from the given database.
+///
+/// Returns `None` if the request should be denied, for example because the starting block isn't
+/// in the finalized chain stored in the database.
+async fn grandpa_warp_sync_response(
+    database: &database_thread::DatabaseThread,
+    block_number_bytes: usize,
+    begin_hash: [u8; 32],
+) -> Result<Option<(Vec<(Vec<u8>, Vec<u8>)>, bool)>, full_sqlite::AccessError> {
+    // Maximum total size of the headers and justifications of a response. Responses are
+    // allowed to be bigger than this in case a single fragment goes above this limit.
+    const MAX_RESPONSE_SIZE: usize = 8 * 1024 * 1024;
+
+    database
+        .with_database(move |database| {
+            let decode_header = |header: &[u8]| {
+                header::decode(header, block_number_bytes)
+                    .map_err(full_sqlite::CorruptedError::BlockHeaderCorrupted)
+                    .map_err(full_sqlite::AccessError::Corrupted)
+                    .map(|header| header.number)
+            };
+
+            // The starting block must be part of the finalized chain.
+            let Some(begin_header) = database.block_scale_encoded_header(&begin_hash)? else {
+                return Ok(None);
+            };
+            let begin_number = decode_header(&begin_header)?;
+            let finalized_number = {
+                let finalized_hash = database.finalized_block_hash()?;
+                let finalized_header = database
+                    .block_scale_encoded_header(&finalized_hash)?
+                    .ok_or(full_sqlite::AccessError::Corrupted(
+                        full_sqlite::CorruptedError::MissingBlockHeader,
+                    ))?;
+                decode_header(&finalized_header)?
+            };
+            if begin_number > finalized_number
+                || database
+                    .block_hash_by_number(begin_number)?
+                    .all(|hash| hash != begin_hash)
+            {
+                return Ok(None);
+            }
+
+            // Each fragment consists in a block that changes the list of GrandPa authorities,
+            // together with the justification that proves its finality. If the end of the
+            // finalized chain is reached, the latest justified block is added at the end.
+            let mut fragments = Vec::new();
+            let mut total_size = 0;
+            let mut latest_justified = None;
+            let mut next_block_number = begin_number + 1;
+
+            loop {
+                let blocks = database.finalized_blocks_with_justification(next_block_number, 64)?;
+                if blocks.is_empty() {
+                    break;
+                }
+
+                for (scale_encoded_header, scale_encoded_justification) in blocks {
+                    let decoded = header::decode(&scale_encoded_header, block_number_bytes)
+                        .map_err(full_sqlite::CorruptedError::BlockHeaderCorrupted)
+                        .map_err(full_sqlite::AccessError::Corrupted)?;
+                    next_block_number = decoded.number + 1;
+
+                    let changes_authorities = decoded.digest.logs().any(|log| {
+                        matches!(
+                            log,
+                            header::DigestItemRef::GrandpaConsensus(
+                                header::GrandpaConsensusLogRef::ScheduledChange(_)
+                                    | header::GrandpaConsensusLogRef::ForcedChange { .. }
+                            )
+                        )
+                    });
+
+                    if !changes_authorities {
+                        latest_justified =
+                            Some((scale_encoded_header, scale_encoded_justification));
+                        continue;
+                    }
+
+                    let fragment_size =
+                        scale_encoded_header.len() + scale_encoded_justification.len();
+                    if !fragments.is_empty() && total_size + fragment_size > MAX_RESPONSE_SIZE {
+                        return Ok(Some((fragments, false)));
+                    }
+
+                    total_size += fragment_size;
+                    fragments.push((scale_encoded_header, scale_encoded_justification));
+                    latest_justified = None;
+                }
+            }
+
+            fragments.extend(latest_justified);
+            Ok(Some((fragments, true)))
+        })
+        .await
+}
+
+/// Builds the response to a state request by reading from the given database.
+///
+/// The response contains a compact Merkle proof of the keys that follow the start key of the
+/// request. The content of each default child trie immediately follows the key of the main trie
+/// that refers to it.
+async fn state_response(
+    database: &database_thread::DatabaseThread,
+    block_number_bytes: usize,
+    request: service::EncodedStateRequest,
+) -> Result<Vec<u8>, StateResponseError> {
+    // Once the proof reaches this size, no new key is added to it.
+    const MAX_PROOF_SIZE: usize = 2 * 1024 * 1024;
+
+    const CHILD_TRIE_PREFIX: &[u8] = b":child_storage:default:";
+
+    database
+        .with_database(move |database| {
+            let request = request.decode();
+
+            let state_root =
+                runtime_call::block_state_root(database, block_number_bytes, request.block_hash)?;
+            let mut proof = runtime_call::StorageProofBuilder::new();
+
+            // Name of the child trie being iterated, or `None` for the main trie, and key within
+            // this trie from which to continue iterating.
+            let (mut child_trie, start_key) = match request.start_key {
+                protocol::StateRequestStart::MainTrie(key) => (None, key),
+                protocol::StateRequestStart::ChildTrieDefault { child_trie, key } => {
+                    (Some(child_trie.to_vec()), key)
+                }
+            };
+            let mut key_nibbles = trie::bytes_to_nibbles(start_key.iter().copied())
+                .map(u8::from)
+                .collect::<Vec<_>>();
+            proof.add_lookup(
+                database,
+                &state_root,
+                &runtime_call::ProofKey {
+                    child_trie: child_trie.clone(),
+                    key_nibbles: key_nibbles.clone(),
+                },
+            )?;
+
+            // The start key itself has already been sent to the requester and is excluded. This
+            // is done by appending a `0` nibble to it.
+            if !key_nibbles.is_empty() {
+                key_nibbles.push(0);
+            }
+
+            while proof.size() < MAX_PROOF_SIZE {
+                let child_trie_path = child_trie.as_ref().map(|child_trie| {
+                    trie::bytes_to_nibbles(CHILD_TRIE_PREFIX.iter().chain(child_trie).copied())
+                        .map(u8::from)
+                        .collect::<Vec<_>>()
+                });
+
+                let next_key = database.block_storage_next_key(
+                    request.block_hash,
+                    child_trie_path.iter().map(|p| p.iter().copied()),
+                    key_nibbles.iter().copied(),
+                    iter::empty(),
+                    false,
+                )?;
+
+                let Some(next_key) = next_key else {
+                    // Once the end of a child trie is reached, continue with the main trie after
+                    // the key that refers to this child trie.
+                    match child_trie_path {
+                        Some(child_trie_path) => {
+                            child_trie = None;
+                            key_nibbles = child_trie_path;
+                            key_nibbles.push(0);
+                            continue;
+                        }
+                        None => break,
+                    }
+                };
+
+                proof.add_lookup(
+                    database,
+                    &state_root,
+                    &runtime_call::ProofKey {
+                        child_trie: child_trie.clone(),
+                        key_nibbles: next_key.clone(),
+                    },
+                )?;
+
+                let next_key_bytes = trie::nibbles_to_bytes_suffix_extend(
+                    next_key.iter().map(|n| trie::Nibble::try_from(*n).unwrap()),
+                )
+                .collect::<Vec<_>>();
+                match next_key_bytes.strip_prefix(CHILD_TRIE_PREFIX) {
+                    Some(child_trie_name) if child_trie.is_none() && next_key.len() % 2 == 0 => {
+                        // The content of the child trie immediately follows.
+                        child_trie = Some(child_trie_name.to_vec());
+                        key_nibbles = Vec::new();
+                    }
+                    _ => {
+                        key_nibbles = next_key;
+                        key_nibbles.push(0);
+                    }
+                }
+            }
+
+            trie::compact_proof::encode(&proof.build(), &state_root)
+                .map_err(StateResponseError::CompactProof)
+        })
+        .await
+}
+
+/// Error potentially returned by [`state_response`].
+#[derive(Debug, derive_more::Display, derive_more::From)]
+enum StateResponseError {
+    /// Error while accessing the storage of the block in the database.
+    #[display(fmt = "{_0}")]
+    StorageAccess(full_sqlite::StorageAccessError),
+    /// The storage of the block can't be turned into a compact proof.
+    #[display(fmt = "Failed to build compact proof: {_0}")]
+    CompactProof(trie::compact_proof::EncodeError),
+}
@@ -15,8 +15,9 @@
 // You should have received a copy of the GNU General Public License
 // along with this program.  If not, see <http://www.gnu.org/licenses/>.
 
-use crate::{LogCallback, LogLevel};
-use core::future::Future;
+use crate::{LogCallback, LogLevel, TimeSource};
+use core::{future::Future, iter, mem};
+use futures_channel::oneshot;
 use futures_lite::future;
 use futures_util::StreamExt as _;
 use smol::{
@@ -26,16 +27,22 @@ use smol::{
 };
 use smoldot::{
     libp2p::{
+        collection::SubstreamFate,
+        memory,
         multiaddr::{Multiaddr, ProtocolRef},
-        websocket, with_buffers,
+        quic, read_write, websocket, with_buffers,
     },
     network::service::{self, CoordinatorToConnection},
 };
 use std::{
+    borrow::Cow,
     io,
-    net::{IpAddr, SocketAddr},
+    net::{IpAddr, Ipv4Addr, Ipv6Addr, SocketAddr},
     pin,
-    sync::Arc,
+    sync::{
+        atomic::{AtomicBool, Ordering},
+        Arc,
+    },
     time::{Duration, Instant},
 };
 
@@ -45,11 +52,13 @@ impl<T> AsyncReadWrite for T where T: AsyncRead + AsyncWrite {}
 /// Asynchronous task managing a specific connection, including the dialing process.
 pub(super) async fn opening_connection_task(
     start_connect: service::StartConnect<Instant>,
+    memory_network: Option<memory::MemoryNetwork>,
     log_callback: Arc<dyn LogCallback + Send + Sync>,
+    time_source: Arc<dyn TimeSource + Send + Sync>,
 ) -> Result<impl AsyncReadWrite, ()> {
     // Convert the `multiaddr` (typically of the form `/ip4/a.b.c.d/tcp/d`) into
     // a `Future<dyn Output = Result<TcpStream, ...>>`.
-    let socket = match multiaddr_to_socket(&start_connect.multiaddr) {
+    let socket = match multiaddr_to_socket(&start_connect.multiaddr, memory_network) {
         Ok(socket) => socket,
         Err(_) => {
             log_callback.log(
@@ -63,7 +72,7 @@ pub(super) async fn opening_connection_task(
     // Finishing ongoing connection process.
     let socket = async move { socket.await.map_err(|_| ()) }
         .or(async move {
-            smol::Timer::at(start_connect.timeout).await;
+            time_source.sleep_until(start_connect.timeout).await;
             Err(())
         })
         .await?;
@@ -72,8 +81,10 @@ pub(super) async fn opening_connection_task(
 }
 
 /// Asynchronous task managing a specific connection.
+#[allow(clippy::too_many_arguments)]
 pub(super) async fn established_connection_task(
     log_callback: Arc<dyn LogCallback + Send + Sync>,
+    time_source: Arc<dyn TimeSource + Send + Sync>,
     address: String,
     socket: impl AsyncReadWrite,
     connection_id: service::ConnectionId,
@@ -94,7 +105,8 @@ pub(super) async fn established_connection_task(
         // processing the socket might generate a message, we only process the socket if no
         // message is currently being sent.
         if message_sending.is_none() {
-            if let Ok(mut socket_read_write) = socket.as_mut().read_write_access(Instant::now()) {
+            if let Ok(mut socket_read_write) = socket.as_mut().read_write_access(time_source.now())
+            {
                 let read_bytes_before = socket_read_write.read_bytes;
                 let written_bytes_before = socket_read_write.write_bytes_queued;
                 let write_closed = socket_read_write.write_bytes_queueable.is_none();
@@ -183,9 +195,11 @@ pub(super) async fn established_connection_task(
                 // is `None`, we also call `wait_read_write_again` only when `message_sending` is
                 // `None`.
                 let fut = if message_sending.is_none() {
-                    Some(socket.as_mut().wait_read_write_again(|when| async move {
-                        smol::Timer::at(when).await;
-                    }))
+                    Some(
+                        socket
+                            .as_mut()
+                            .wait_read_write_again(|when| time_source.sleep_until(when)),
+                    )
                 } else {
                     None
                 };
@@ -231,9 +245,24 @@ pub(super) async fn established_connection_task(
 /// protocols aren't supported.
 fn multiaddr_to_socket(
     addr: &Multiaddr,
+    memory_network: Option<memory::MemoryNetwork>,
 ) -> Result<impl Future<Output = Result<impl AsyncReadWrite, io::Error>>, ()> {
     let mut iter = addr.iter().fuse();
     let proto1 = iter.next().ok_or(())?;
+
+    // Memory addresses are the only ones that consist of a single component.
+    if let ProtocolRef::Memory(port) = proto1 {
+        let memory_network = memory_network.ok_or(())?;
+        if iter.next().is_some() {
+            return Err(());
+        }
+        return Ok(either::Right(async move {
+            memory_network
+                .connect(port)
+                .map(futures_util::future::Either::Right)
+        }));
+    }
+
     let proto2 = iter.next().ok_or(())?;
     let proto3 = iter.next();
 
@@ -280,7 +309,7 @@ fn multiaddr_to_socket(
         _ => return Err(()),
     };
 
-    Ok(async move {
+    Ok(either::Left(async move {
         let tcp_socket = match addr {
             either::Left(socket_addr) => smol::net::TcpStream::connect(socket_addr).await,
             either::Right((dns, port)) => smol::net::TcpStream::connect((&dns[..], port)).await,
@@ -305,10 +334,1202 @@ fn multiaddr_to_socket(
                     url: "/",
                 })
                 .await
-                .map(futures_util::future::Either::Right)
+                .map(|socket| {
+                    futures_util::future::Either::Left(futures_util::future::Either::Right(socket))
+                })
             }
-            (Ok(tcp_socket), None) => Ok(futures_util::future::Either::Left(tcp_socket)),
+            (Ok(tcp_socket), None) => Ok(futures_util::future::Either::Left(
+                futures_util::future::Either::Left(tcp_socket),
+            )),
             (Err(err), _) => Err(err),
         }
+    }))
+}
+
+/// Asynchronous task managing a WebRTC listening socket.
+///
+/// All the WebRTC connections of a listener share the same UDP socket. This task dispatches the
+/// incoming datagrams to the task of each connection based on their source address, and notifies
+/// the coordinator whenever a datagram from an unknown source starts a new connection.
+pub(super) async fn webrtc_listener_task(
+    socket: smol::net::UdpSocket,
+    mut on_shutdown: event_listener::EventListener,
+    to_background_tx: channel::Sender<super::ToBackground>,
+) {
+    let socket = Arc::new(socket);
+
+    // For each remote address, the corresponding connection.
+    let mut connections =
+        hashbrown::HashMap::<SocketAddr, WebRtcListenerConnection, fnv::FnvBuildHasher>::default();
+
+    let mut buffer = vec![0; 65536];
+
+    loop {
+        let Some(result) = future::or(
+            async {
+                (&mut on_shutdown).await;
+                None
+            },
+            async { Some(socket.recv_from(&mut buffer).await) },
+        )
+        .await
+        else {
+            break;
+        };
+
+        let (datagram, remote_addr) = match result {
+            Ok((num_read, remote_addr)) => (buffer[..num_read].to_vec(), remote_addr),
+            Err(_) => {
+                // Errors here can happen for example when an ICMP message is received in
+                // response to a datagram previously sent. They can be ignored.
+                continue;
+            }
+        };
+
+        // Send the datagram to the existing connection, if any. Datagrams are silently dropped
+        // if the connection task is too slow to process them, like a network would do.
+        let datagram = match connections.get(&remote_addr) {
+            None => datagram,
+            Some(connection) => match connection.datagrams_tx.try_send(datagram) {
+                Ok(()) | Err(channel::TrySendError::Full(_)) => continue,
+                Err(channel::TrySendError::Closed(datagram)) => {
+                    connections.remove(&remote_addr);
+                    datagram
+                }
+            },
+        };
+
+        // Datagram from an unknown source. Only STUN binding requests can open a new connection.
+        // The username of the request contains the ICE ufrag chosen by the remote, which,
+        // according to the libp2p specification, is also used as the ufrag of the local side and
+        // as the ICE password of both sides.
+        let ice_ufrag = match str0m::ice::StunMessage::parse(&datagram) {
+            Ok(msg) if msg.is_binding_request() => match msg.split_username() {
+                Some((local_ufrag, _)) => local_ufrag.to_owned(),
+                None => continue,
+            },
+            _ => continue,
+        };
+
+        // Clean up the connections that no longer exist before inserting a new one.
+        connections.retain(|_, connection| !connection.datagrams_tx.is_closed());
+
+        // STUN binding requests aren't authenticated and are cheap to send, while each of them
+        // leads to a new connection task. The number of connections whose handshake is in
+        // progress is thus limited, both globally and for each remote IP address. Datagrams
+        // that go above the limit are silently dropped.
+        let (num_pending, num_pending_same_ip) = connections
+            .iter()
+            .filter(|(_, connection)| !connection.handshake_finished.load(Ordering::Relaxed))
+            .fold((0, 0), |(total, same_ip), (addr, _)| {
+                (
+                    total + 1,
+                    same_ip + usize::from(addr.ip() == remote_addr.ip()),
+                )
+            });
+        if num_pending >= WEBRTC_MAX_PENDING_HANDSHAKES
+            || num_pending_same_ip >= WEBRTC_MAX_PENDING_HANDSHAKES_PER_IP
+        {
+            continue;
+        }
+
+        let (datagrams_tx, datagrams_rx) = channel::bounded(64);
+        datagrams_tx.try_send(datagram).unwrap();
+        let handshake_finished = Arc::new(AtomicBool::new(false));
+        connections.insert(
+            remote_addr,
+            WebRtcListenerConnection {
+                datagrams_tx,
+                handshake_finished: handshake_finished.clone(),
+            },
+        );
+
+        let _ = to_background_tx
+            .send(super::ToBackground::IncomingWebRtcConnection(
+                WebRtcIncomingConnection {
+                    socket: socket.clone(),
+                    remote_addr,
+                    ice_ufrag,
+                    datagrams_rx,
+                    handshake_finished,
+                },
+            ))
+            .await;
+    }
+}
+
+/// Maximum number of incoming WebRTC connections whose ICE and DTLS handshakes are in progress
+/// at the same time.
+const WEBRTC_MAX_PENDING_HANDSHAKES: usize = 64;
+
+/// Maximum number of incoming WebRTC connections from the same IP address whose ICE and DTLS
+/// handshakes are in progress at the same time.
+const WEBRTC_MAX_PENDING_HANDSHAKES_PER_IP: usize = 4;
+
+/// Connection known to [`webrtc_listener_task`].
+struct WebRtcListenerConnection {
+    /// Channel towards the task of the connection.
+    datagrams_tx: channel::Sender<Vec<u8>>,
+    /// Set to `true` by the task of the connection once the ICE and DTLS handshakes have
+    /// finished.
+    handshake_finished: Arc<AtomicBool>,
+}
+
+/// Incoming WebRTC connection reported by [`webrtc_listener_task`].
+pub(super) struct WebRtcIncomingConnection {
+    /// Socket shared between all the connections of the listener.
+    socket: Arc<smol::net::UdpSocket>,
+    /// Address of the remote.
+    remote_addr: SocketAddr,
+    /// ICE ufrag chosen by the remote, also used as the local ufrag and as the ICE password.
+    ice_ufrag: String,
+    /// Datagrams received from the remote.
+    datagrams_rx: channel::Receiver<Vec<u8>>,
+    /// Must be set to `true` once the ICE and DTLS handshakes have finished.
+    handshake_finished: Arc<AtomicBool>,
+}
+
+/// Asynchronous task managing an incoming WebRTC connection, including the ICE, DTLS, and SCTP
+/// handshakes.
+pub(super) async fn webrtc_connection_task(
+    log_callback: Arc<dyn LogCallback + Send + Sync>,
+    time_source: Arc<dyn TimeSource + Send + Sync>,
+    certificate: str0m::config::DtlsCert,
+    incoming: WebRtcIncomingConnection,
+    connection_to_coordinator: channel::Sender<super::ToBackground>,
+) {
+    let WebRtcIncomingConnection {
+        socket,
+        remote_addr,
+        ice_ufrag,
+        mut datagrams_rx,
+        handshake_finished,
+    } = incoming;
+
+    let address = [
+        match remote_addr.ip() {
+            IpAddr::V4(ip) => ProtocolRef::Ip4(ip.octets()),
+            IpAddr::V6(ip) => ProtocolRef::Ip6(ip.octets()),
+        },
+        ProtocolRef::Udp(remote_addr.port()),
+        ProtocolRef::WebRtcDirect,
+    ]
+    .into_iter()
+    .collect::<Multiaddr>();
+
+    // The destination address of incoming datagrams isn't known when the socket is bound to an
+    // unspecified address. Since the ICE state machine needs a local candidate, a loopback
+    // address is used as a placeholder instead. The source address of the datagrams that are
+    // sent out is ignored anyway.
+    let local_addr = match socket.local_addr() {
+        Ok(SocketAddr::V4(addr)) if addr.ip().is_unspecified() => {
+            SocketAddr::new(IpAddr::V4(Ipv4Addr::LOCALHOST), addr.port())
+        }
+        Ok(SocketAddr::V6(addr)) if addr.ip().is_unspecified() => {
+            SocketAddr::new(IpAddr::V6(Ipv6Addr::LOCALHOST), addr.port())
+        }
+        Ok(addr) => addr,
+        Err(_) => return,
+    };
+
+    let mut rtc = str0m::Rtc::builder()
+        .set_ice_lite(true)
+        .set_dtls_cert(certificate)
+        // The certificate of the remote isn't known in advance. It is instead verified as part
+        // of the Noise handshake.
+        .set_fingerprint_verification(false)
+        .set_local_ice_credentials(str0m::IceCreds {
+            ufrag: ice_ufrag.clone(),
+            pass: ice_ufrag.clone(),
+        })
+        .build(time_source.now());
+
+    match (
+        str0m::Candidate::host(local_addr, "udp"),
+        str0m::Candidate::host(remote_addr, "udp"),
+    ) {
+        (Ok(local_candidate), Ok(remote_candidate)) => {
+            rtc.add_local_candidate(local_candidate);
+            rtc.add_remote_candidate(remote_candidate);
+        }
+        _ => {
+            log_callback.log(
+                LogLevel::Debug,
+                format!("webrtc-bad-candidate; address={}", address),
+            );
+            return;
+        }
+    }
+
+    {
+        let mut api = rtc.direct_api();
+        api.set_remote_ice_credentials(str0m::IceCreds {
+            ufrag: ice_ufrag.clone(),
+            pass: ice_ufrag,
+        });
+        api.set_ice_controlling(false);
+        // Because fingerprint verification is disabled, this value is never compared with the
+        // actual fingerprint, but it must be present.
+        api.set_remote_fingerprint(str0m::config::Fingerprint {
+            hash_func: "sha-256".to_owned(),
+            bytes: vec![0; 32],
+        });
+        if api.start_dtls(false).is_err() {
+            return;
+        }
+        api.start_sctp(false);
+    }
+
+    // The ICE and DTLS handshakes must finish within a certain time, in order to not accumulate
+    // connections that never finish their handshake. The Noise handshake that follows has its
+    // own timeout enforced by the connection task.
+    let handshake_timeout = time_source.now() + Duration::from_secs(10);
+
+    // Moment when the WebRTC state machine must be waken up.
+    let mut rtc_wake_up = time_source.now();
+
+    // Set to `Some` once the DTLS handshake has finished and the coordinator has been notified
+    // of the connection.
+    let mut connection: Option<(
+        service::ConnectionId,
+        channel::Receiver<service::CoordinatorToConnection<Instant>>,
+    )> = None;
+    let mut connection_task: Option<
+        service::MultiStreamConnectionTask<Instant, str0m::channel::ChannelId>,
+    > = None;
+
+    // State of the substreams, in other words of the data channels, that are open.
+    let mut substreams = hashbrown::HashMap::<
+        str0m::channel::ChannelId,
+        WebRtcSubstream,
+        fnv::FnvBuildHasher,
+    >::default();
+
+    // Data channels that are being opened and that the connection task isn't aware of yet.
+    let mut pending_opening_out_substreams =
+        hashbrown::HashSet::<str0m::channel::ChannelId, fnv::FnvBuildHasher>::default();
+
+    // `true` if the data channel dedicated to the Noise handshake has been created.
+    let mut handshake_substream_created = false;
+
+    // Future that sends a message to the coordinator. Only one message is sent to the coordinator
+    // at a time. `None` if no message is being sent.
+    let mut message_sending = None;
+
+    loop {
+        // Process everything that the WebRTC state machine has to report.
+        while rtc.is_alive() {
+            let event = match rtc.poll_output() {
+                Ok(str0m::Output::Timeout(when)) => {
+                    rtc_wake_up = when;
+                    break;
+                }
+                Ok(str0m::Output::Transmit(transmit)) => {
+                    // Errors are ignored, like a network would silently drop datagrams.
+                    let _ = socket
+                        .send_to(&transmit.contents, transmit.destination)
+                        .await;
+                    continue;
+                }
+                Ok(str0m::Output::Event(event)) => event,
+                Err(err) => {
+                    log_callback.log(
+                        LogLevel::Debug,
+                        format!("webrtc-error; address={}; error={}", address, err),
+                    );
+                    rtc.disconnect();
+                    break;
+                }
+            };
+
+            match (event, &mut connection_task) {
+                (str0m::Event::Connected, None) => {
+                    handshake_finished.store(true, Ordering::Relaxed);
+
+                    let api = rtc.direct_api();
+                    let local_tls_certificate_multihash =
+                        sha256_multihash(&api.local_dtls_fingerprint().bytes);
+                    let Some(remote_tls_certificate_multihash) = api
+                        .remote_dtls_fingerprint()
+                        .map(|fingerprint| sha256_multihash(&fingerprint.bytes))
+                    else {
+                        rtc.disconnect();
+                        break;
+                    };
+
+                    let multiaddr = address
+                        .iter()
+                        .chain(iter::once(ProtocolRef::Certhash(Cow::Borrowed(
+                            &remote_tls_certificate_multihash,
+                        ))))
+                        .collect::<Multiaddr>();
+
+                    log_callback.log(
+                        LogLevel::Debug,
+                        format!("incoming-connection; multiaddr={}", multiaddr),
+                    );
+
+                    let (result_tx, result_rx) = oneshot::channel();
+                    let _ = connection_to_coordinator
+                        .send(super::ToBackground::WebRtcConnectionEstablished {
+                            multiaddr,
+                            handshake_kind: service::MultiStreamHandshakeKind::WebRtc {
+                                local_tls_certificate_multihash,
+                                remote_tls_certificate_multihash,
+                            },
+                            when_connected: time_source.now(),
+                            result_tx,
+                        })
+                        .await;
+
+                    let Ok((id, task, coordinator_to_connection)) = result_rx.await else {
+                        return;
+                    };
+                    connection = Some((id, coordinator_to_connection));
+                    connection_task = Some(task);
+                }
+                (str0m::Event::ChannelOpen(channel_id, _), Some(connection_task)) => {
+                    let outbound = pending_opening_out_substreams.remove(&channel_id);
+                    connection_task.add_substream(channel_id, outbound);
+                    substreams.insert(
+                        channel_id,
+                        WebRtcSubstream {
+                            read_buffer: Vec::new(),
+                            needs_read_write: true,
+                            wake_up_after: None,
+                        },
+                    );
+                    if let Some(mut channel) = rtc.channel(channel_id) {
+                        channel.set_buffered_amount_low_threshold(WEBRTC_MAX_BUFFERED_BYTES / 2);
+                    }
+                }
+                (str0m::Event::ChannelData(data), _) => {
+                    if let Some(substream) = substreams.get_mut(&data.id) {
+                        substream.read_buffer.extend_from_slice(&data.data);
+                        substream.needs_read_write = true;
+                    }
+                }
+                (str0m::Event::ChannelBufferedAmountLow(channel_id), _) => {
+                    if let Some(substream) = substreams.get_mut(&channel_id) {
+                        substream.needs_read_write = true;
+                    }
+                }
+                (str0m::Event::ChannelClose(channel_id), Some(connection_task)) => {
+                    pending_opening_out_substreams.remove(&channel_id);
+                    if substreams.remove(&channel_id).is_some() {
+                        connection_task.reset_substream(&channel_id);
+                    }
+                }
+                _ => {}
+            }
+        }
+
+        if let Some(task) = connection_task.as_mut() {
+            if !rtc.is_alive() && !task.is_reset_called() {
+                log_callback.log(
+                    LogLevel::Trace,
+                    format!("connection-activity; address={}; reset", address),
+                );
+                task.reset();
+                substreams.clear();
+                pending_opening_out_substreams.clear();
+            }
+        } else if !rtc.is_alive() || time_source.now() >= handshake_timeout {
+            log_callback.log(
+                LogLevel::Debug,
+                format!("webrtc-handshake-failed; address={}", address),
+            );
+            return;
+        }
+
+        // Because only one message should be sent to the coordinator at a time, and that
+        // processing the substreams might generate a message, we only process the substreams if
+        // no message is currently being sent.
+        if let (Some(connection_id), true) = (
+            connection.as_ref().map(|(id, _)| *id),
+            message_sending.is_none(),
+        ) {
+            let mut task = connection_task.take().unwrap();
+            let now = time_source.now();
+
+            // Set to `true` if the WebRTC state machine might have data to send out.
+            let mut rtc_needs_polling = false;
+
+            // Start opening new outbound substreams, if needed. The first substream is the one
+            // dedicated to the Noise handshake, and is a negotiated data channel whose
+            // identifier is 0.
+            if rtc.is_alive() {
+                for _ in 0..usize::try_from(task.desired_outbound_substreams())
+                    .unwrap_or(usize::MAX)
+                    .saturating_sub(pending_opening_out_substreams.len())
+                {
+                    let channel_id =
+                        rtc.direct_api()
+                            .create_data_channel(str0m::channel::ChannelConfig {
+                                negotiated: if handshake_substream_created {
+                                    None
+                                } else {
+                                    Some(0)
+                                },
+                                ..Default::default()
+                            });
+                    handshake_substream_created = true;
+                    pending_opening_out_substreams.insert(channel_id);
+                    rtc_needs_polling = true;
+                }
+            }
+
+            let mut substreams_to_reset = Vec::new();
+
+            for (channel_id, substream) in &mut substreams {
+                let must_process = substream.needs_read_write
+                    || substream.wake_up_after.is_some_and(|when| when <= now);
+                if !must_process {
+                    continue;
+                }
+
+                let Some(mut channel) = rtc.channel(*channel_id) else {
+                    substreams_to_reset.push((*channel_id, true));
+                    continue;
+                };
+
+                let mut read_write = read_write::ReadWrite {
+                    now,
+                    incoming_buffer: mem::take(&mut substream.read_buffer),
+                    expected_incoming_bytes: Some(0),
+                    read_bytes: 0,
+                    write_buffers: Vec::new(),
+                    write_bytes_queued: 0,
+                    write_bytes_queueable: Some(
+                        WEBRTC_MAX_BUFFERED_BYTES.saturating_sub(channel.buffered_amount()),
+                    ),
+                    wake_up_after: None,
+                };
+
+                let substream_fate = task.substream_read_write(channel_id, &mut read_write);
+
+                if read_write.read_bytes != 0 || read_write.write_bytes_queued != 0 {
+                    log_callback.log(
+                        LogLevel::Trace,
+                        format!(
+                            "connection-activity; address={address}; substream={channel_id:?}; read={}; written={}; fate={substream_fate:?}",
+                            read_write.read_bytes, read_write.write_bytes_queued,
+                        ),
+                    );
+                }
+
+                substream.read_buffer = mem::take(&mut read_write.incoming_buffer);
+                substream.needs_read_write = false;
+                substream.wake_up_after = read_write.wake_up_after;
+                rtc_needs_polling |= !read_write.write_buffers.is_empty();
+
+                // The connection task always writes out entire length-prefixed frames, but
+                // potentially split between multiple buffers. Each frame must be sent as a
+                // separate data channel message.
+                let written = read_write.write_buffers.concat();
+                let write_success = webrtc_frames(&written)
+                    .all(|frame| frame.is_some_and(|f| matches!(channel.write(true, f), Ok(true))));
+
+                // If the state machine has reset the substream, its identifier is now invalid
+                // and must not be passed to `reset_substream`.
+                match substream_fate {
+                    SubstreamFate::Reset => substreams_to_reset.push((*channel_id, false)),
+                    SubstreamFate::Continue if !write_success => {
+                        substreams_to_reset.push((*channel_id, true))
+                    }
+                    SubstreamFate::Continue => {}
+                }
+            }
+
+            for (channel_id, notify_task) in substreams_to_reset {
+                if substreams.remove(&channel_id).is_some() {
+                    if notify_task {
+                        task.reset_substream(&channel_id);
+                    }
+                    rtc.direct_api().close_data_channel(channel_id);
+                    rtc_needs_polling = true;
+                }
+            }
+
+            // Try pull message to send to the coordinator.
+
+            // Calling this method takes ownership of the task and returns that task if it has
+            // more work to do. If `None` is returned, then the entire task is gone and the
+            // connection must be abruptly closed, which is what happens when we return from
+            // this function.
+            let (task_update, opaque_message) = task.pull_message_to_coordinator();
+            if let Some(task_update) = task_update {
+                connection_task = Some(task_update);
+                if let Some(opaque_message) = opaque_message {
+                    message_sending = Some(connection_to_coordinator.send(
+                        super::ToBackground::FromConnectionTask {
+                            connection_id,
+                            opaque_message: Some(opaque_message),
+                            connection_now_dead: false,
+                        },
+                    ));
+                }
+            } else {
+                let _ = connection_to_coordinator
+                    .send(super::ToBackground::FromConnectionTask {
+                        connection_id,
+                        opaque_message,
+                        connection_now_dead: true,
+                    })
+                    .await;
+                return;
+            }
+
+            // Processing the substreams might have generated data to send out.
+            if rtc_needs_polling && rtc.is_alive() {
+                continue;
+            }
+        }
+
+        // Now wait for something interesting to happen before looping again.
+
+        enum WhatHappened {
+            Datagram(Vec<u8>),
+            ListenerDead,
+            Timer,
+            CoordinatorMessage(service::CoordinatorToConnection<Instant>),
+            CoordinatorDead,
+            MessageSent,
+        }
+
+        let what_happened: WhatHappened = {
+            let datagram = async {
+                match datagrams_rx.next().await {
+                    Some(datagram) => WhatHappened::Datagram(datagram),
+                    None => WhatHappened::ListenerDead,
+                }
+            };
+
+            let timer = {
+                let wake_up = substreams
+                    .values()
+                    .filter_map(|substream| substream.wake_up_after)
+                    .chain(if rtc.is_alive() {
+                        Some(rtc_wake_up)
+                    } else {
+                        None
+                    })
+                    .chain(if connection.is_none() {
+                        Some(handshake_timeout)
+                    } else {
+                        None
+                    })
+                    .min();
+                let sleep = wake_up.map(|wake_up| time_source.sleep_until(wake_up));
+                async move {
+                    if let Some(sleep) = sleep {
+                        sleep.await;
+                        WhatHappened::Timer
+                    } else {
+                        future::pending().await
+                    }
+                }
+            };
+
+            let coordinator_message = async {
+                if let Some((_, coordinator_to_connection)) = connection.as_mut() {
+                    match coordinator_to_connection.next().await {
+                        Some(msg) => WhatHappened::CoordinatorMessage(msg),
+                        None => WhatHappened::CoordinatorDead,
+                    }
+                } else {
+                    future::pending().await
+                }
+            };
+
+            let message_sent = async {
+                let result = if let Some(message_sending) = message_sending.as_mut() {
+                    message_sending.await
+                } else {
+                    future::pending().await
+                };
+                message_sending = None;
+                if result.is_ok() {
+                    WhatHappened::MessageSent
+                } else {
+                    WhatHappened::CoordinatorDead
+                }
+            };
+
+            datagram
+                .or(timer)
+                .or(coordinator_message)
+                .or(message_sent)
+                .await
+        };
+
+        match what_happened {
+            WhatHappened::Datagram(datagram) => {
+                if rtc.is_alive() {
+                    if let Ok(receive) = str0m::net::Receive::new(
+                        str0m::net::Protocol::Udp,
+                        remote_addr,
+                        local_addr,
+                        &datagram,
+                    ) {
+                        // Errors are reported through `poll_output`.
+                        let _ = rtc.handle_input(str0m::Input::Receive(time_source.now(), receive));
+                    }
+                }
+            }
+            WhatHappened::ListenerDead => {
+                // The listener is only gone if the node is shutting down.
+                return;
+            }
+            WhatHappened::Timer => {
+                if rtc.is_alive() {
+                    let _ = rtc.handle_input(str0m::Input::Timeout(time_source.now()));
+                }
+            }
+            WhatHappened::CoordinatorMessage(message) => {
+                if let Some(task) = connection_task.as_mut() {
+                    task.inject_coordinator_message(message);
+                }
+                // The message might have queued data to send on any of the substreams.
+                for substream in substreams.values_mut() {
+                    substream.needs_read_write = true;
+                }
+            }
+            WhatHappened::CoordinatorDead => return,
+            WhatHappened::MessageSent => {}
+        }
+    }
+}
+
+/// Maximum number of bytes that are buffered within each WebRTC data channel.
+const WEBRTC_MAX_BUFFERED_BYTES: usize = 128 * 1024;
+
+/// State of a substream of a WebRTC connection.
+struct WebRtcSubstream {
+    /// Data received on the data channel and not processed yet.
+    read_buffer: Vec<u8>,
+    /// `true` if the substream must be processed by the connection task at the next iteration.
+    needs_read_write: bool,
+    /// If `Some`, the substream must be processed by the connection task once this moment is
+    /// reached.
+    wake_up_after: Option<Instant>,
+}
+
+/// Splits the given data into the length-prefixed frames it contains. Yields `None` if the
+/// data doesn't consist of entire frames.
+fn webrtc_frames(mut data: &[u8]) -> impl Iterator<Item = Option<&[u8]>> {
+    iter::from_fn(move || {
+        if data.is_empty() {
+            return None;
+        }
+
+        let mut frame_len = 0usize;
+        let mut prefix_len = 0;
+        loop {
+            let Some(byte) = data.get(prefix_len) else {
+                data = &[];
+                return Some(None);
+            };
+            frame_len |= usize::from(byte & 0x7f) << (7 * prefix_len);
+            prefix_len += 1;
+            if byte & 0x80 == 0 || prefix_len >= 3 {
+                break;
+            }
+        }
+
+        let Some(frame) = data.get(..prefix_len + frame_len) else {
+            data = &[];
+            return Some(None);
+        };
+        data = &data[frame.len()..];
+        Some(Some(frame))
     })
 }
+
+/// Turns the SHA-256 of a certificate into a multihash.
+pub(super) fn sha256_multihash(sha256: &[u8]) -> Vec<u8> {
+    [12u8, 32]
+        .into_iter()
+        .chain(sha256.iter().copied())
+        .collect()
+}
+
+/// Parses a multiaddress of the form `/ip4/.../udp/.../quic` or `/ip4/.../udp/.../quic-v1`, or
+/// their IPv6 equivalents, into the address to connect to and the version of QUIC to use.
+/// Returns `None` if the multiaddress isn't a QUIC address.
+pub(super) fn multiaddr_to_quic(addr: &Multiaddr) -> Option<(SocketAddr, u32)> {
+    let mut iter = addr.iter().fuse();
+    let proto1 = iter.next()?;
+    let proto2 = iter.next()?;
+    let proto3 = iter.next()?;
+
+    if iter.next().is_some() {
+        return None;
+    }
+
+    let ip = match proto1 {
+        ProtocolRef::Ip4(ip) => IpAddr::V4(ip.into()),
+        ProtocolRef::Ip6(ip) => IpAddr::V6(ip.into()),
+        _ => return None,
+    };
+
+    let ProtocolRef::Udp(port) = proto2 else {
+        return None;
+    };
+
+    let version = match proto3 {
+        ProtocolRef::Quic => quic::DRAFT_29_VERSION,
+        ProtocolRef::QuicV1 => quic::VERSION_1,
+        _ => return None,
+    };
+
+    Some((SocketAddr::new(ip, port), version))
+}
+
+/// Asynchronous task opening an outgoing QUIC connection, including the QUIC handshake.
+pub(super) async fn opening_quic_connection_task(
+    endpoint: quinn::Endpoint,
+    client_config: quinn::ClientConfig,
+    address: SocketAddr,
+    start_connect: service::StartConnect<Instant>,
+    log_callback: Arc<dyn LogCallback + Send + Sync>,
+    time_source: Arc<dyn TimeSource + Send + Sync>,
+) -> Result<quinn::Connection, ()> {
+    let result = match endpoint.connect_with(client_config, address, quic::SERVER_NAME) {
+        Ok(connecting) => {
+            async move { connecting.await.map_err(|err| err.to_string()) }
+                .or(async move {
+                    time_source.sleep_until(start_connect.timeout).await;
+                    Err("timeout".to_owned())
+                })
+                .await
+        }
+        Err(err) => Err(err.to_string()),
+    };
+
+    result.map_err(|err| {
+        log_callback.log(
+            LogLevel::Debug,
+            format!("quic-connect-error; address={}; error={}", address, err),
+        );
+    })
+}
+
+/// Asynchronous task managing a QUIC listener.
+///
+/// The QUIC handshakes of the incoming connections are performed within this task, and the
+/// coordinator is notified of each connection whose handshake has succeeded.
+pub(super) async fn quic_listener_task(
+    log_callback: Arc<dyn LogCallback + Send + Sync>,
+    time_source: Arc<dyn TimeSource + Send + Sync>,
+    endpoint: quinn::Endpoint,
+    mut on_shutdown: event_listener::EventListener,
+    to_background_tx: channel::Sender<super::ToBackground>,
+) {
+    // QUIC handshakes that are in progress. The duration of a handshake is bounded by the idle
+    // timeout of the QUIC configuration.
+    let mut handshakes = futures_util::stream::FuturesUnordered::new();
+
+    loop {
+        let Some(event) = future::or(
+            async {
+                (&mut on_shutdown).await;
+                None
+            },
+            future::or(async { endpoint.accept().await.map(either::Left) }, async {
+                if handshakes.is_empty() {
+                    future::pending::<()>().await;
+                }
+                Some(either::Right(handshakes.next().await.unwrap()))
+            }),
+        )
+        .await
+        else {
+            break;
+        };
+
+        match event {
+            either::Left(incoming) => {
+                let remote_addr = incoming.remote_address();
+                let multiaddr = [
+                    match remote_addr.ip() {
+                        IpAddr::V4(ip) => ProtocolRef::Ip4(ip.octets()),
+                        IpAddr::V6(ip) => ProtocolRef::Ip6(ip.octets()),
+                    },
+                    ProtocolRef::Udp(remote_addr.port()),
+                    ProtocolRef::QuicV1,
+                ]
+                .into_iter()
+                .collect::<Multiaddr>();
+
+                handshakes.push(async move { (multiaddr, incoming.await) });
+            }
+            either::Right((multiaddr, Ok(connection))) => {
+                log_callback.log(
+                    LogLevel::Debug,
+                    format!("incoming-connection; multiaddr={}", multiaddr),
+                );
+
+                let _ = to_background_tx
+                    .send(super::ToBackground::IncomingQuicConnection {
+                        connection,
+                        multiaddr,
+                        when_connected: time_source.now(),
+                    })
+                    .await;
+            }
+            either::Right((multiaddr, Err(err))) => {
+                log_callback.log(
+                    LogLevel::Debug,
+                    format!(
+                        "incoming-quic-handshake-error; multiaddr={}; error={}",
+                        multiaddr, err
+                    ),
+                );
+            }
+        }
+    }
+}
+
+/// Asynchronous task managing a listener of a [`memory::MemoryNetwork`].
+pub(super) async fn memory_listener_task(
+    log_callback: Arc<dyn LogCallback + Send + Sync>,
+    time_source: Arc<dyn TimeSource + Send + Sync>,
+    listener: memory::MemoryListener,
+    mut on_shutdown: event_listener::EventListener,
+    to_background_tx: channel::Sender<super::ToBackground>,
+) {
+    loop {
+        let Some((socket, remote_port)) = future::or(
+            async {
+                (&mut on_shutdown).await;
+                None
+            },
+            async { Some(listener.accept().await) },
+        )
+        .await
+        else {
+            break;
+        };
+
+        let multiaddr = iter::once(ProtocolRef::Memory(remote_port)).collect::<Multiaddr>();
+
+        log_callback.log(
+            LogLevel::Debug,
+            format!("incoming-connection; multiaddr={}", multiaddr),
+        );
+
+        let _ = to_background_tx
+            .send(super::ToBackground::IncomingConnection {
+                socket: Box::new(socket),
+                multiaddr,
+                when_connected: time_source.now(),
+            })
+            .await;
+    }
+}
+
+/// Asynchronous task managing a specific QUIC connection after its QUIC handshake has finished.
+#[allow(clippy::too_many_arguments)]
+pub(super) async fn quic_connection_task(
+    log_callback: Arc<dyn LogCallback + Send + Sync>,
+    time_source: Arc<dyn TimeSource + Send + Sync>,
+    address: String,
+    connection: quinn::Connection,
+    connection_id: service::ConnectionId,
+    mut connection_task: service::MultiStreamConnectionTask<Instant, quinn::StreamId>,
+    mut coordinator_to_connection: channel::Receiver<service::CoordinatorToConnection<Instant>>,
+    connection_to_coordinator: channel::Sender<super::ToBackground>,
+) {
+    // Substreams that are open. Each substream is wrapped around an object containing a read
+    // buffer and a write buffer.
+    let mut substreams = hashbrown::HashMap::<
+        quinn::StreamId,
+        pin::Pin<Box<with_buffers::WithBuffers<quic::Substream>>>,
+        fnv::FnvBuildHasher,
+    >::default();
+
+    // Outbound substreams that are being opened and that the connection task isn't aware of yet.
+    let mut opening_out_substreams = futures_util::stream::FuturesUnordered::new();
+
+    // Substreams that the connection task has destroyed after closing their writing side, and
+    // that are kept alive until the data remaining in their write buffer has been sent out.
+    let mut closing_substreams = futures_util::stream::FuturesUnordered::new();
+
+    // Future that sends a message to the coordinator. Only one message is sent to the coordinator
+    // at a time. `None` if no message is being sent.
+    let mut message_sending = None;
+
+    loop {
+        // Because only one message should be sent to the coordinator at a time, and that
+        // processing the substreams might generate a message, we only process the substreams if
+        // no message is currently being sent.
+        if message_sending.is_none() {
+            // Start opening new outbound substreams, if needed.
+            if !connection_task.is_reset_called() {
+                for _ in 0..usize::try_from(connection_task.desired_outbound_substreams())
+                    .unwrap_or(usize::MAX)
+                    .saturating_sub(opening_out_substreams.len())
+                {
+                    let connection = connection.clone();
+                    opening_out_substreams.push(async move { connection.open_bi().await });
+                }
+            }
+
+            let now = time_source.now();
+            let mut substreams_to_remove = Vec::new();
+
+            for (substream_id, substream) in &mut substreams {
+                let (substream_fate, write_closed) = if let Ok(mut read_write) =
+                    substream.as_mut().read_write_access(now)
+                {
+                    let read_bytes_before = read_write.read_bytes;
+                    let written_bytes_before = read_write.write_bytes_queued;
+                    let write_closed = read_write.write_bytes_queueable.is_none();
+
+                    let substream_fate =
+                        connection_task.substream_read_write(substream_id, &mut read_write);
+
+                    if read_write.read_bytes != read_bytes_before
+                        || read_write.write_bytes_queued != written_bytes_before
+                        || (!write_closed && read_write.write_bytes_queueable.is_none())
+                    {
+                        log_callback.log(
+                                LogLevel::Trace,
+                                format!(
+                                    "connection-activity; address={address}; substream={substream_id:?}; read={}; written={}; wake_up_after={:?}; write_close={:?}; fate={substream_fate:?}",
+                                    read_write.read_bytes - read_bytes_before,
+                                    read_write.write_bytes_queued - written_bytes_before,
+                                    read_write.wake_up_after.map(|w| w
+                                        .checked_duration_since(read_write.now)
+                                        .unwrap_or(Duration::new(0, 0))),
+                                    read_write.write_bytes_queueable.is_none(),
+                                ),
+                            );
+                    }
+
+                    (substream_fate, read_write.write_bytes_queueable.is_none())
+                } else {
+                    // Error on the substream.
+                    log_callback.log(
+                            LogLevel::Trace,
+                            format!(
+                                "connection-activity; address={address}; substream={substream_id:?}; reset"
+                            ),
+                        );
+                    connection_task.reset_substream(substream_id);
+                    (SubstreamFate::Reset, false)
+                };
+
+                if let SubstreamFate::Reset = substream_fate {
+                    substreams_to_remove.push((*substream_id, write_closed));
+                }
+            }
+
+            for (substream_id, write_closed) in substreams_to_remove {
+                let substream = substreams.remove(&substream_id).unwrap();
+                // Substreams whose writing side is still open are reset when dropped.
+                if write_closed {
+                    closing_substreams.push(quic_drain_substream(time_source.clone(), substream));
+                }
+            }
+
+            // Try pull message to send to the coordinator.
+
+            // Calling this method takes ownership of the task and returns that task if it has
+            // more work to do. If `None` is returned, then the entire task is gone and the
+            // connection must be abruptly closed, which is what happens when we return from
+            // this function.
+            let (task_update, opaque_message) = connection_task.pull_message_to_coordinator();
+            if let Some(task_update) = task_update {
+                connection_task = task_update;
+                if let Some(opaque_message) = opaque_message {
+                    message_sending = Some(connection_to_coordinator.send(
+                        super::ToBackground::FromConnectionTask {
+                            connection_id,
+                            opaque_message: Some(opaque_message),
+                            connection_now_dead: false,
+                        },
+                    ));
+                }
+            } else {
+                let _ = connection_to_coordinator
+                    .send(super::ToBackground::FromConnectionTask {
+                        connection_id,
+                        opaque_message,
+                        connection_now_dead: true,
+                    })
+                    .await;
+                return;
+            }
+        }
+
+        // Now wait for something interesting to happen before looping again.
+
+        enum WhatHappened {
+            CoordinatorMessage(CoordinatorToConnection<Instant>),
+            CoordinatorDead,
+            SocketEvent,
+            MessageSent,
+            NewSubstream(quinn::SendStream, quinn::RecvStream, bool),
+            ConnectionReset,
+        }
+
+        let process_substreams = message_sending.is_none();
+        let is_reset_called = connection_task.is_reset_called();
+
+        let what_happened: WhatHappened = {
+            let coordinator_message = async {
+                match coordinator_to_connection.next().await {
+                    Some(msg) => WhatHappened::CoordinatorMessage(msg),
+                    None => WhatHappened::CoordinatorDead,
+                }
+            };
+
+            let socket_event = async {
+                // The futures returned by `wait_read_write_again` yield when `read_write_access`
+                // must be called. Because we only call `read_write_access` when `message_sending`
+                // is `None`, we also call `wait_read_write_again` only when `message_sending` is
+                // `None`.
+                if !process_substreams || substreams.is_empty() {
+                    future::pending::<()>().await;
+                }
+
+                substreams
+                    .values_mut()
+                    .map(|substream| {
+                        substream
+                            .as_mut()
+                            .wait_read_write_again(|when| time_source.sleep_until(when))
+                    })
+                    .collect::<futures_util::stream::FuturesUnordered<_>>()
+                    .next()
+                    .await;
+                WhatHappened::SocketEvent
+            };
+
+            let message_sent = async {
+                let result = if let Some(message_sending) = message_sending.as_mut() {
+                    message_sending.await
+                } else {
+                    future::pending().await
+                };
+                message_sending = None;
+                if result.is_ok() {
+                    WhatHappened::MessageSent
+                } else {
+                    WhatHappened::CoordinatorDead
+                }
+            };
+
+            let new_substream = async {
+                if is_reset_called {
+                    future::pending::<()>().await;
+                }
+
+                let inbound = async {
+                    match connection.accept_bi().await {
+                        Ok((send, recv)) => WhatHappened::NewSubstream(send, recv, false),
+                        Err(_) => WhatHappened::ConnectionReset,
+                    }
+                };
+
+                let outbound = async {
+                    if opening_out_substreams.is_empty() {
+                        future::pending::<()>().await;
+                    }
+                    match opening_out_substreams.next().await.unwrap() {
+                        Ok((send, recv)) => WhatHappened::NewSubstream(send, recv, true),
+                        Err(_) => WhatHappened::ConnectionReset,
+                    }
+                };
+
+                inbound.or(outbound).await
+            };
+
+            let substream_closed = async {
+                if closing_substreams.is_empty() {
+                    future::pending::<()>().await;
+                }
+                closing_substreams.next().await;
+                // Nothing more to do, as the substream is simply dropped.
+                future::pending().await
+            };
+
+            coordinator_message
+                .or(socket_event)
+                .or(message_sent)
+                .or(new_substream)
+                .or(substream_closed)
+                .await
+        };
+
+        match what_happened {
+            WhatHappened::CoordinatorMessage(message) => {
+                connection_task.inject_coordinator_message(message);
+            }
+            WhatHappened::CoordinatorDead => return,
+            WhatHappened::SocketEvent => {}
+            WhatHappened::MessageSent => {}
+            WhatHappened::NewSubstream(send, recv, outbound) => {
+                let substream_id = send.id();
+                log_callback.log(
+                    LogLevel::Trace,
+                    format!(
+                        "connection-activity; address={address}; substream={substream_id:?}; new-substream; outbound={outbound:?}"
+                    ),
+                );
+                connection_task.add_substream(substream_id, outbound);
+                substreams.insert(
+                    substream_id,
+                    Box::pin(with_buffers::WithBuffers::new(quic::Substream::new(
+                        send, recv,
+                    ))),
+                );
+            }
+            WhatHappened::ConnectionReset => {
+                debug_assert!(!connection_task.is_reset_called());
+                log_callback.log(
+                    LogLevel::Trace,
+                    format!("connection-activity; address={}; reset", address),
+                );
+                connection_task.reset();
+                substreams.clear();
+                opening_out_substreams.clear();
+            }
+        }
+    }
+}
+
+/// Keeps the given substream alive until the data remaining in its write buffer has been sent
+/// out and its writing side has been closed, or until no progress has been made for some time.
+async fn quic_drain_substream(
+    time_source: Arc<dyn TimeSource + Send + Sync>,
+    mut substream: pin::Pin<Box<with_buffers::WithBuffers<quic::Substream>>>,
+) {
+    loop {
+        let write_buffer_empty = match substream.as_mut().read_write_access(time_source.now()) {
+            Ok(read_write) => read_write.write_bytes_queued == 0,
+            Err(_) => return,
+        };
+
+        // Even if the write buffer is empty, `wait_read_write_again` still needs to be called
+        // once in order to close the writing side.
+        let progress = async {
+            substream
+                .as_mut()
+                .wait_read_write_again(|when| time_source.sleep_until(when))
+                .await;
+            true
+        }
+        .or(async {
+            time_source.sleep(Duration::from_secs(10)).await;
+            false
+        })
+        .await;
+
+        if write_buffer_empty || !progress {
+            return;
+        }
+    }
+}
@@ -0,0 +1,287 @@
+// Smoldot
+// Copyright (C) 2023  Pierre Krieger
+// SPDX-License-Identifier: GPL-3.0-or-later WITH Classpath-exception-2.0
+
+// This program is free software: you can redistribute it and/or modify
+// it under the terms of the GNU General Public License as published by
+// the Free Software Foundation, either version 3 of the License, or
+// (at your option) any later version.
+
+// This program is distributed in the hope that it will be useful,
+// but WITHOUT ANY WARRANTY; without even the implied warranty of
+// MERCHANTABILITY or FITNESS FOR A PARTICULAR PURPOSE.  See the
+// GNU General Public License for more details.
+
+// You should have received a copy of the GNU General Public License
+// along with this program.  If not, see <http://www.gnu.org/licenses/>.
+
+//! Background off-chain workers service.
+//!
+//! The [`OffchainWorkerService`] follows the blocks verified by the
+//! [`consensus_service::ConsensusService`] and calls the `OffchainWorkerApi_offchain_worker`
+//! runtime function every time a new best block is imported.
+//!
+//! The off-chain storage accessed by the runtime is stored in the database, the transactions
+//! submitted by the runtime are passed to the [`transactions_service::TransactionsService`], and
+//! the HTTP requests started by the runtime are performed by a [`HttpClient`]. The keys that the
+//! runtime looks up, generates, or signs with are those of the [`keystore::Keystore`] of the
+//! node.
+//!
+//! If multiple best blocks are imported while an off-chain worker is running, only the latest
+//! of them is used for the next call.
+
+use crate::{
+    consensus_service, database_thread, runtime_call, transactions_service, HttpClient,
+    LogCallback, LogLevel, TimeSource,
+};
+
+use futures_lite::FutureExt as _;
+use smol::lock::Mutex;
+use smoldot::{header, identity::keystore, informant::HashDisplay};
+use std::{future::Future, iter, num::NonZeroUsize, pin::Pin, sync::Arc};
+
+/// Configuration for an [`OffchainWorkerService`].
+pub struct Config {
+    /// Function that can be used to spawn background tasks.
+    ///
+    /// The tasks passed as parameter must be executed until they shut down.
+    pub tasks_executor: Arc<dyn Fn(Pin<Box<dyn Future<Output = ()> + Send>>) + Send + Sync>,
+
+    /// Function called in order to notify of something.
+    pub log_callback: Arc<dyn LogCallback + Send + Sync>,
+
+    /// Consensus service of the chain. Used to follow the best block of the chain.
+    pub consensus_service: Arc<consensus_service::ConsensusService>,
+
+    /// Service where the transactions submitted by the off-chain workers are sent.
+    pub transactions_service: Arc<transactions_service::TransactionsService>,
+
+    /// Database to access the storage of the blocks and the off-chain storage.
+    pub database: Arc<database_thread::DatabaseThread>,
+
+    /// Keystore whose keys are made available to the off-chain workers. Keys generated by the
+    /// off-chain workers are saved in it.
+    pub keystore: Arc<keystore::Keystore>,
+
+    /// Number of bytes used to encode the block number in headers.
+    pub block_number_bytes: usize,
+
+    /// Client used to perform the HTTP requests started by the off-chain workers.
+    pub http_client: Arc<dyn HttpClient + Send + Sync>,
+
+    /// Source of the time reported to the off-chain workers and used for the deadlines of their
+    /// HTTP requests.
+    pub time_source: Arc<dyn TimeSource + Send + Sync>,
+}
+
+/// See [the module-level documentation](..).
+pub struct OffchainWorkerService {
+    /// The background task stops when this sender is dropped.
+    _keep_alive: async_channel::Sender<()>,
+}
+
+impl OffchainWorkerService {
+    /// Builds a new service.
+    pub async fn new(config: Config) -> Arc<Self> {
+        let (keep_alive, from_foreground) = async_channel::bounded(1);
+
+        let background = Background {
+            log_callback: config.log_callback,
+            consensus_service: config.consensus_service,
+            transactions_service: config.transactions_service,
+            database: config.database,
+            keystore: config.keystore,
+            block_number_bytes: config.block_number_bytes,
+            http_client: config.http_client,
+            time_source: config.time_source,
+            runtime_caches: Mutex::new(lru::LruCache::new(NonZeroUsize::new(1).unwrap())),
+            from_foreground,
+        };
+
+        (config.tasks_executor)(Box::pin(background.run()));
+
+        Arc::new(OffchainWorkerService {
+            _keep_alive: keep_alive,
+        })
+    }
+}
+
+struct Background {
+    log_callback: Arc<dyn LogCallback + Send + Sync>,
+    consensus_service: Arc<consensus_service::ConsensusService>,
+    transactions_service: Arc<transactions_service::TransactionsService>,
+    database: Arc<database_thread::DatabaseThread>,
+    keystore: Arc<keystore::Keystore>,
+    block_number_bytes: usize,
+    http_client: Arc<dyn HttpClient + Send + Sync>,
+    time_source: Arc<dyn TimeSource + Send + Sync>,
+
+    /// Runtimes compiled in order to run the off-chain workers.
+    runtime_caches: Mutex<runtime_call::RuntimeCaches>,
+
+    /// Channel whose sender is held by the [`OffchainWorkerService`]. Closed when the service is
+    /// destroyed.
+    from_foreground: async_channel::Receiver<()>,
+}
+
+impl Background {
+    async fn run(self) {
+        // Receiver of the notifications about the blocks of the chain, or `None` if we aren't
+        // subscribed.
+        let mut new_blocks: Option<async_channel::Receiver<consensus_service::Notification>> = None;
+
+        loop {
+            // Subscribe to the blocks of the chain. This is done again whenever the subscription
+            // is lost. Off-chain workers are only run for the best blocks that are imported
+            // after the subscription.
+            if new_blocks.is_none() {
+                let subscription = self.consensus_service.subscribe_all(32).await;
+                new_blocks = Some(subscription.new_blocks);
+            }
+
+            enum WhatHappened {
+                SubscriptionDead,
+                Notification(consensus_service::Notification),
+                ForegroundClosed,
+            }
+
+            let outcome: WhatHappened = {
+                let next_block = async {
+                    match new_blocks.as_ref().unwrap().recv().await {
+                        Ok(n) => WhatHappened::Notification(n),
+                        Err(_) => WhatHappened::SubscriptionDead,
+                    }
+                };
+
+                let foreground_closed = async {
+                    let _ = self.from_foreground.recv().await;
+                    WhatHappened::ForegroundClosed
+                };
+
+                next_block.or(foreground_closed).await
+            };
+
+            match outcome {
+                WhatHappened::ForegroundClosed => return,
+                WhatHappened::SubscriptionDead => {
+                    self.log_callback.log(
+                        LogLevel::Warn,
+                        "offchain-worker-service-blocks-subscription-lost".to_string(),
+                    );
+                    new_blocks = None;
+                }
+                WhatHappened::Notification(consensus_service::Notification::Block(block))
+                    if block.is_new_best =>
+                {
+                    // Skip over the best blocks that have been imported in the meanwhile, in
+                    // order to not fall behind the head of the chain.
+                    let mut best_block = (block.block_hash, block.scale_encoded_header);
+                    while let Ok(notification) = new_blocks.as_ref().unwrap().try_recv() {
+                        if let consensus_service::Notification::Block(block) = notification {
+                            if block.is_new_best {
+                                best_block = (block.block_hash, block.scale_encoded_header);
+                            }
+                        }
+                    }
+
+                    self.run_offchain_worker(best_block.0, &best_block.1).await;
+                }
+                WhatHappened::Notification(_) => {}
+            }
+        }
+    }
+
+    /// Calls `OffchainWorkerApi_offchain_worker` against the storage of the given block.
+    async fn run_offchain_worker(&self, block_hash: [u8; 32], block_scale_encoded_header: &[u8]) {
+        // Find the version of the `OffchainWorkerApi` supported by the runtime, if any.
+        let api_version =
+            match runtime_call::runtime_of_block(&self.database, &self.runtime_caches, block_hash)
+                .await
+            {
+                Ok((cache_key, runtime)) => {
+                    let api_version = runtime
+                        .runtime_version()
+                        .decode()
+                        .apis
+                        .find_version("OffchainWorkerApi");
+                    self.runtime_caches.lock().await.put(cache_key, runtime);
+                    api_version
+                }
+                Err(error) => {
+                    self.log_callback.log(
+                        LogLevel::Warn,
+                        format!(
+                            "offchain-worker-runtime-error; block_hash={}; error={}",
+                            HashDisplay(&block_hash),
+                            error
+                        ),
+                    );
+                    return;
+                }
+            };
+
+        let Some(api_version) = api_version else {
+            return;
+        };
+
+        // Versions 2 and above of the API accept the header of the block as parameter, while
+        // version 1 accepts the block number.
+        let parameter = if api_version >= 2 {
+            block_scale_encoded_header.to_vec()
+        } else {
+            // Blocks reported by the consensus service have already been verified and their
+            // header is thus always valid.
+            let block_number =
+                match header::decode(block_scale_encoded_header, self.block_number_bytes) {
+                    Ok(h) => h.number,
+                    Err(_) => unreachable!(),
+                };
+            block_number.to_le_bytes()[..self.block_number_bytes].to_vec()
+        };
+
+        self.log_callback.log(
+            LogLevel::Debug,
+            format!(
+                "offchain-worker-start; block_hash={}; api_version={}",
+                HashDisplay(&block_hash),
+                api_version
+            ),
+        );
+
+        match runtime_call::offchain_runtime_call(
+            &self.database,
+            &self.runtime_caches,
+            runtime_call::Offchain {
+                transactions_service: &self.transactions_service,
+                keystore: &self.keystore,
+                http_client: self.http_client.clone(),
+                time_source: self.time_source.clone(),
+            },
+            block_hash,
+            "OffchainWorkerApi_offchain_worker",
+            iter::once(parameter),
+        )
+        .await
+        {
+            Ok(_) => {
+                self.log_callback.log(
+                    LogLevel::Debug,
+                    format!(
+                        "offchain-worker-finished; block_hash={}",
+                        HashDisplay(&block_hash)
+                    ),
+                );
+            }
+            Err(error) => {
+                self.log_callback.log(
+                    LogLevel::Warn,
+                    format!(
+                        "offchain-worker-error; block_hash={}; error={}",
+                        HashDisplay(&block_hash),
+                        error
+                    ),
+                );
+            }
+        }
+    }
+}
@@ -0,0 +1,1269 @@
+// Smoldot
+// Copyright (C) 2023  Pierre Krieger
+// SPDX-License-Identifier: GPL-3.0-or-later WITH Classpath-exception-2.0
+
+// This program is free software: you can redistribute it and/or modify
+// it under the terms of the GNU General Public License as published by
+// the Free Software Foundation, either version 3 of the License, or
+// (at your option) any later version.
+
+// This program is distributed in the hope that it will be useful,
+// but WITHOUT ANY WARRANTY; without even the implied warranty of
+// MERCHANTABILITY or FITNESS FOR A PARTICULAR PURPOSE.  See the
+// GNU General Public License for more details.
+
+// You should have received a copy of the GNU General Public License
+// along with this program.  If not, see <http://www.gnu.org/licenses/>.
+
+//! Performing runtime calls against the storage of blocks found in the database.
+
+use crate::{database_thread, http_client, transactions_service, util, HttpClient, TimeSource};
+
+use smol::lock::Mutex;
+use smoldot::{
+    database::full_sqlite,
+    executor::{self, runtime_host, storage_diff},
+    header,
+    identity::keystore,
+    metadata, trie,
+};
+use std::{iter, sync::Arc, time::Instant};
+
+/// Runtimes that have been compiled in the past.
+///
+/// Indexed by the hash of the runtime code and the number of heap pages.
+pub type RuntimeCaches = lru::LruCache<RuntimeCacheKey, executor::host::HostVmPrototype>;
+
+/// Key of the [`RuntimeCaches`]. Contains the hash of the `:code` and the number of heap pages.
+pub type RuntimeCacheKey = ([u8; 32], executor::vm::HeapPages);
+
+/// Performs a runtime call against the storage of the given block.
+pub async fn runtime_call(
+    database: &database_thread::DatabaseThread,
+    runtime_caches: &Mutex<RuntimeCaches>,
+    block_hash: [u8; 32],
+    function_to_call: &str,
+    parameter: impl Iterator<Item = impl AsRef<[u8]>> + Clone,
+) -> Result<Vec<u8>, RuntimeCallError> {
+    runtime_call_inner(
+        database,
+        runtime_caches,
+        block_hash,
+        function_to_call,
+        parameter,
+        RuntimeCallOptions::default(),
+    )
+    .await
+}
+
+/// Performs a runtime call against the storage of the given block, and returns a trace of the
+/// execution alongside with the outcome of the call.
+///
+/// The trace is `None` if the call has failed before the runtime has started executing.
+pub async fn runtime_call_traced(
+    database: &database_thread::DatabaseThread,
+    runtime_caches: &Mutex<RuntimeCaches>,
+    block_hash: [u8; 32],
+    function_to_call: &str,
+    parameter: impl Iterator<Item = impl AsRef<[u8]>> + Clone,
+) -> (
+    Result<Vec<u8>, RuntimeCallError>,
+    Option<runtime_host::ExecutionTrace>,
+) {
+    let mut execution_trace = None;
+    let result = runtime_call_inner(
+        database,
+        runtime_caches,
+        block_hash,
+        function_to_call,
+        parameter,
+        RuntimeCallOptions {
+            execution_trace: Some(&mut execution_trace),
+            ..Default::default()
+        },
+    )
+    .await;
+    (result, execution_trace)
+}
+
+/// Performs a runtime call against the storage of the given block, and returns the changes to
+/// the storage that the call performs alongside with its output.
+///
+/// The changes aren't written anywhere.
+pub async fn runtime_call_storage_changes(
+    database: &database_thread::DatabaseThread,
+    runtime_caches: &Mutex<RuntimeCaches>,
+    block_hash: [u8; 32],
+    function_to_call: &str,
+    parameter: impl Iterator<Item = impl AsRef<[u8]>> + Clone,
+) -> Result<(Vec<u8>, runtime_host::StorageChanges), RuntimeCallError> {
+    let mut storage_changes = None;
+    let output = runtime_call_inner(
+        database,
+        runtime_caches,
+        block_hash,
+        function_to_call,
+        parameter,
+        RuntimeCallOptions {
+            storage_changes: Some(&mut storage_changes),
+            ..Default::default()
+        },
+    )
+    .await?;
+    Ok((output, storage_changes.unwrap()))
+}
+
+/// Performs a runtime call against the storage of the given block, but using `code` as the
+/// runtime instead of the one found in the storage of the block.
+///
+/// The `:code` storage item is overridden with `code` for the duration of the call, as if the
+/// runtime had been upgraded. Returns the output of the call and the changes to the storage that
+/// it performs, alongside with the logs of level info or lower that the runtime has emitted,
+/// including if the call has failed.
+///
+/// The changes aren't written anywhere.
+pub async fn runtime_call_with_code(
+    database: &database_thread::DatabaseThread,
+    runtime_caches: &Mutex<RuntimeCaches>,
+    block_hash: [u8; 32],
+    code: &[u8],
+    function_to_call: &str,
+    parameter: impl Iterator<Item = impl AsRef<[u8]>> + Clone,
+) -> (
+    Result<(Vec<u8>, runtime_host::StorageChanges), RuntimeCallError>,
+    String,
+) {
+    let mut storage_changes = None;
+    let mut logs = String::new();
+    let mut result = runtime_call_inner(
+        database,
+        runtime_caches,
+        block_hash,
+        function_to_call,
+        parameter.clone(),
+        RuntimeCallOptions {
+            storage_changes: Some(&mut storage_changes),
+            code_override: Some(code),
+            logs: Some(&mut logs),
+            ..Default::default()
+        },
+    )
+    .await;
+
+    // The execution is interrupted if the runtime emits more logs than the limit enforced by
+    // `runtime_host`. In that situation, the call is performed again without logs in order to
+    // obtain its actual outcome.
+    if let Err(RuntimeCallError::RuntimeError(runtime_host::ErrorDetail::LogsTooLong)) = result {
+        logs = "<logs discarded because their size exceeds the limit>\n".to_owned();
+        result = runtime_call_inner(
+            database,
+            runtime_caches,
+            block_hash,
+            function_to_call,
+            parameter,
+            RuntimeCallOptions {
+                storage_changes: Some(&mut storage_changes),
+                code_override: Some(code),
+                ..Default::default()
+            },
+        )
+        .await;
+    }
+
+    (
+        result.map(|output| (output, storage_changes.unwrap())),
+        logs,
+    )
+}
+
+/// Performs a runtime call against the storage of the given block, giving the runtime access to
+/// the keys of `keystore`.
+///
+/// Keys generated by the runtime are saved in the file system of the keystore.
+pub async fn runtime_call_with_keystore(
+    database: &database_thread::DatabaseThread,
+    runtime_caches: &Mutex<RuntimeCaches>,
+    keystore: &keystore::Keystore,
+    block_hash: [u8; 32],
+    function_to_call: &str,
+    parameter: impl Iterator<Item = impl AsRef<[u8]>> + Clone,
+) -> Result<Vec<u8>, RuntimeCallError> {
+    runtime_call_inner(
+        database,
+        runtime_caches,
+        block_hash,
+        function_to_call,
+        parameter,
+        RuntimeCallOptions {
+            keystore: Some(keystore),
+            ..Default::default()
+        },
+    )
+    .await
+}
+
+/// Performs a runtime call against the storage of the given block, giving the runtime access to
+/// the off-chain host functions.
+///
+/// Contrary to [`runtime_call`], the runtime can read and write the off-chain storage stored in
+/// the database, and uses the capabilities found in `offchain` for everything else.
+pub async fn offchain_runtime_call(
+    database: &database_thread::DatabaseThread,
+    runtime_caches: &Mutex<RuntimeCaches>,
+    offchain: Offchain<'_>,
+    block_hash: [u8; 32],
+    function_to_call: &str,
+    parameter: impl Iterator<Item = impl AsRef<[u8]>> + Clone,
+) -> Result<Vec<u8>, RuntimeCallError> {
+    runtime_call_inner(
+        database,
+        runtime_caches,
+        block_hash,
+        function_to_call,
+        parameter,
+        RuntimeCallOptions {
+            offchain: Some(OffchainState {
+                transactions_service: offchain.transactions_service,
+                http_requests: http_client::OffchainHttpRequests::new(
+                    offchain.http_client,
+                    offchain.time_source.clone(),
+                ),
+                time_source: offchain.time_source,
+            }),
+            keystore: Some(offchain.keystore),
+            ..Default::default()
+        },
+    )
+    .await
+}
+
+/// Off-chain capabilities given to the runtime by [`offchain_runtime_call`].
+pub struct Offchain<'a> {
+    /// Service where the transactions submitted by the runtime are sent.
+    pub transactions_service: &'a transactions_service::TransactionsService,
+    /// Keystore containing the keys that the runtime can use.
+    pub keystore: &'a keystore::Keystore,
+    /// Client that performs the HTTP requests started by the runtime.
+    pub http_client: Arc<dyn HttpClient + Send + Sync>,
+    /// Source of the time reported to the runtime.
+    pub time_source: Arc<dyn TimeSource + Send + Sync>,
+}
+
+/// State of the off-chain capabilities during a runtime call. See [`RuntimeCallOptions::offchain`].
+struct OffchainState<'a> {
+    /// Service where the transactions submitted by the runtime are sent.
+    transactions_service: &'a transactions_service::TransactionsService,
+    /// HTTP requests started by the runtime.
+    http_requests: http_client::OffchainHttpRequests,
+    /// See [`Offchain::time_source`].
+    time_source: Arc<dyn TimeSource + Send + Sync>,
+}
+
+/// Performs a runtime call against the storage of the given block, and returns a Merkle proof
+/// containing all the storage items that the call has accessed.
+///
+/// The proof also contains the `:code` and `:heappages` storage items, as they are necessary in
+/// order to perform the call.
+pub async fn runtime_call_proof(
+    database: &database_thread::DatabaseThread,
+    runtime_caches: &Mutex<RuntimeCaches>,
+    block_number_bytes: usize,
+    block_hash: [u8; 32],
+    function_to_call: &str,
+    parameter: impl Iterator<Item = impl AsRef<[u8]>> + Clone,
+) -> Result<Vec<u8>, RuntimeCallError> {
+    let mut accessed_keys = [&b":code"[..], &b":heappages"[..]]
+        .into_iter()
+        .map(|key| ProofKey {
+            child_trie: None,
+            key_nibbles: trie::bytes_to_nibbles(key.iter().copied())
+                .map(u8::from)
+                .collect(),
+        })
+        .collect::<Vec<_>>();
+
+    runtime_call_inner(
+        database,
+        runtime_caches,
+        block_hash,
+        function_to_call,
+        parameter,
+        RuntimeCallOptions {
+            accessed_keys: Some(&mut accessed_keys),
+            ..Default::default()
+        },
+    )
+    .await?;
+
+    storage_proof(database, block_number_bytes, block_hash, accessed_keys)
+        .await
+        .map_err(RuntimeCallError::StorageAccess)
+}
+
+/// Optional behaviours of a call to [`runtime_call_inner`].
+#[derive(Default)]
+struct RuntimeCallOptions<'a> {
+    /// If `Some`, each key looked up in the storage is pushed to it.
+    accessed_keys: Option<&'a mut Vec<ProofKey>>,
+    /// If `Some`, the call is performed as an off-chain worker and the off-chain host functions
+    /// are allowed.
+    offchain: Option<OffchainState<'a>>,
+    /// If `Some`, the execution is traced and the trace is written to it once the runtime call
+    /// is over, including if it has failed.
+    execution_trace: Option<&'a mut Option<runtime_host::ExecutionTrace>>,
+    /// If `Some`, the changes to the storage performed by the call are written to it if the call
+    /// succeeds.
+    storage_changes: Option<&'a mut Option<runtime_host::StorageChanges>>,
+    /// If `Some`, used as the runtime and as the value of `:code` instead of the `:code` found in
+    /// the storage of the block.
+    code_override: Option<&'a [u8]>,
+    /// If `Some`, the logs of level info or lower emitted by the runtime are written to it if the
+    /// call succeeds or if the execution of the runtime fails.
+    logs: Option<&'a mut String>,
+    /// If `Some`, the runtime can look up, generate, and sign with its keys.
+    keystore: Option<&'a keystore::Keystore>,
+}
+
+/// Performs a runtime call against the storage of the given block.
+///
+/// See [`RuntimeCallOptions`] for the optional behaviours of the call.
+async fn runtime_call_inner(
+    database: &database_thread::DatabaseThread,
+    runtime_caches: &Mutex<RuntimeCaches>,
+    block_hash: [u8; 32],
+    function_to_call: &str,
+    parameter: impl Iterator<Item = impl AsRef<[u8]>> + Clone,
+    options: RuntimeCallOptions<'_>,
+) -> Result<Vec<u8>, RuntimeCallError> {
+    let RuntimeCallOptions {
+        mut accessed_keys,
+        mut offchain,
+        execution_trace,
+        storage_changes,
+        code_override,
+        logs,
+        keystore,
+    } = options;
+
+    let (cache_key, runtime) =
+        runtime_of_call(database, runtime_caches, block_hash, code_override).await?;
+
+    let mut storage_main_trie_changes = storage_diff::TrieDiff::default();
+    if let Some(code) = code_override {
+        storage_main_trie_changes.diff_insert(&b":code"[..], code, ());
+    }
+
+    let execution_tracing = execution_trace.is_some().then(|| {
+        let start = Instant::now();
+        Box::new(move || start.elapsed()) as Box<dyn Fn() -> _ + Send + Sync>
+    });
+
+    let mut call = match runtime_host::run(runtime_host::Config {
+        virtual_machine: runtime,
+        function_to_call,
+        parameter,
+        storage_main_trie_changes,
+        max_log_level: if logs.is_some() { 3 } else { 0 },
+        calculate_trie_changes: false,
+        execution_tracing,
+    }) {
+        Ok(vm) => vm,
+        Err((error, runtime)) => {
+            runtime_caches.lock().await.put(cache_key, runtime);
+            return Err(RuntimeCallError::StartError(error));
+        }
+    };
+
+    loop {
+        match call {
+            runtime_host::RuntimeHostVm::Finished(Ok(success)) => {
+                if let Some(execution_trace) = execution_trace {
+                    *execution_trace = success.execution_trace;
+                }
+                if let Some(storage_changes) = storage_changes {
+                    *storage_changes = Some(success.storage_changes);
+                }
+                if let Some(logs) = logs {
+                    *logs = success.logs;
+                }
+                let output = success.virtual_machine.value().as_ref().to_vec();
+                runtime_caches
+                    .lock()
+                    .await
+                    .put(cache_key, success.virtual_machine.into_prototype());
+                break Ok(output);
+            }
+            runtime_host::RuntimeHostVm::Finished(Err(error)) => {
+                if let Some(execution_trace) = execution_trace {
+                    *execution_trace = error.execution_trace;
+                }
+                if let (Some(logs), runtime_host::ErrorDetail::WasmVm { logs: vm_logs, .. }) =
+                    (logs, &error.detail)
+                {
+                    logs.clone_from(vm_logs);
+                }
+                runtime_caches.lock().await.put(cache_key, error.prototype);
+                break Err(RuntimeCallError::RuntimeError(error.detail));
+            }
+            runtime_host::RuntimeHostVm::StorageGet(req) => {
+                let child_trie = req.child_trie().map(|t| t.as_ref().to_vec());
+                let key = req.key().as_ref().to_vec();
+                if let Some(accessed_keys) = accessed_keys.as_deref_mut() {
+                    accessed_keys.push(ProofKey {
+                        child_trie: child_trie.clone(),
+                        key_nibbles: trie::bytes_to_nibbles(key.iter().copied())
+                            .map(u8::from)
+                            .collect(),
+                    });
+                }
+                let value =
+                    match storage_get(database, block_hash, child_trie.as_deref(), &key).await {
+                        Ok(v) => v,
+                        Err(error) => {
+                            runtime_caches.lock().await.put(
+                                cache_key,
+                                runtime_host::RuntimeHostVm::StorageGet(req).into_prototype(),
+                            );
+                            break Err(error);
+                        }
+                    };
+                call = req.inject_value(
+                    value
+                        .as_ref()
+                        .map(|(val, vers)| (iter::once(&val[..]), *vers)),
+                );
+            }
+            runtime_host::RuntimeHostVm::ClosestDescendantMerkleValue(req) => {
+                let child_trie = req.child_trie().map(|t| t.as_ref().to_vec());
+                let key_nibbles = req.key().map(u8::from).collect::<Vec<_>>();
+                if let Some(accessed_keys) = accessed_keys.as_deref_mut() {
+                    accessed_keys.push(ProofKey {
+                        child_trie: child_trie.clone(),
+                        key_nibbles: key_nibbles.clone(),
+                    });
+                }
+                let merkle_value = match storage_closest_descendant_merkle_value(
+                    database,
+                    block_hash,
+                    child_trie.as_deref(),
+                    key_nibbles,
+                )
+                .await
+                {
+                    Ok(v) => v,
+                    Err(error) => {
+                        runtime_caches.lock().await.put(
+                            cache_key,
+                            runtime_host::RuntimeHostVm::ClosestDescendantMerkleValue(req)
+                                .into_prototype(),
+                        );
+                        break Err(error);
+                    }
+                };
+                call = req.inject_merkle_value(merkle_value.as_ref().map(|v| &v[..]));
+            }
+            runtime_host::RuntimeHostVm::NextKey(req) => {
+                let child_trie = req.child_trie().map(|t| t.as_ref().to_vec());
+                let prefix_nibbles = req.prefix().map(u8::from).collect::<Vec<_>>();
+                if let Some(accessed_keys) = accessed_keys.as_deref_mut() {
+                    for key_nibbles in [req.key().map(u8::from).collect(), prefix_nibbles.clone()] {
+                        accessed_keys.push(ProofKey {
+                            child_trie: child_trie.clone(),
+                            key_nibbles,
+                        });
+                    }
+                }
+                let next_key = match storage_next_key(
+                    database,
+                    block_hash,
+                    child_trie.as_deref(),
+                    req.key().map(u8::from).collect(),
+                    req.or_equal(),
+                    prefix_nibbles,
+                    req.branch_nodes(),
+                )
+                .await
+                {
+                    Ok(v) => v,
+                    Err(error) => {
+                        runtime_caches.lock().await.put(
+                            cache_key,
+                            runtime_host::RuntimeHostVm::NextKey(req).into_prototype(),
+                        );
+                        break Err(error);
+                    }
+                };
+                if let (Some(accessed_keys), Some(next_key)) =
+                    (accessed_keys.as_deref_mut(), next_key.as_ref())
+                {
+                    accessed_keys.push(ProofKey {
+                        child_trie,
+                        key_nibbles: next_key.clone(),
+                    });
+                }
+                call = req.inject_key(
+                    next_key.map(|k| k.into_iter().map(|b| trie::Nibble::try_from(b).unwrap())),
+                );
+            }
+            runtime_host::RuntimeHostVm::SignatureVerification(req) => {
+                call = req.verify_and_resume();
+            }
+            runtime_host::RuntimeHostVm::OffchainStorageSet(req) if offchain.is_none() => {
+                // Offchain storage writes are ignored outside of off-chain workers.
+                call = req.resume();
+            }
+            runtime_host::RuntimeHostVm::OffchainStorageSet(req) => {
+                let key = req.key().as_ref().to_vec();
+                let value = req.value().map(|v| v.as_ref().to_vec());
+                let result = database
+                    .with_database(move |db| db.offchain_storage_set(&key, value.as_deref()))
+                    .await;
+                if let Err(error) = result {
+                    runtime_caches.lock().await.put(
+                        cache_key,
+                        runtime_host::RuntimeHostVm::OffchainStorageSet(req).into_prototype(),
+                    );
+                    break Err(RuntimeCallError::OffchainStorageAccess(error));
+                }
+                call = req.resume();
+            }
+            req @ (runtime_host::RuntimeHostVm::KeystorePublicKeys(_)
+            | runtime_host::RuntimeHostVm::KeystoreGenerate(_)
+            | runtime_host::RuntimeHostVm::KeystoreSign(_))
+                if keystore.is_none() =>
+            {
+                runtime_caches
+                    .lock()
+                    .await
+                    .put(cache_key, req.into_prototype());
+                break Err(RuntimeCallError::ForbiddenHostCall);
+            }
+            runtime_host::RuntimeHostVm::KeystorePublicKeys(req) => {
+                let keystore = keystore.unwrap();
+                call = req.resume_with_keystore(keystore).await;
+            }
+            runtime_host::RuntimeHostVm::KeystoreGenerate(req) => {
+                let keystore = keystore.unwrap();
+                call = req.resume_with_keystore(keystore, true).await;
+            }
+            runtime_host::RuntimeHostVm::KeystoreSign(req) => {
+                let keystore = keystore.unwrap();
+                call = req.resume_with_keystore(keystore).await;
+            }
+            runtime_host::RuntimeHostVm::Offchain(ctx) => {
+                let Some(offchain) = offchain.as_mut() else {
+                    runtime_caches.lock().await.put(
+                        cache_key,
+                        runtime_host::RuntimeHostVm::Offchain(ctx).into_prototype(),
+                    );
+                    break Err(RuntimeCallError::ForbiddenHostCall);
+                };
+
+                match ctx {
+                    runtime_host::OffchainContext::StorageGet(req) => {
+                        let key = req.key().as_ref().to_vec();
+                        let value = database
+                            .with_database(move |db| db.offchain_storage_get(&key))
+                            .await;
+                        match value {
+                            Ok(value) => call = req.inject_value(value),
+                            Err(error) => {
+                                runtime_caches.lock().await.put(
+                                    cache_key,
+                                    runtime_host::OffchainContext::StorageGet(req).into_prototype(),
+                                );
+                                break Err(RuntimeCallError::OffchainStorageAccess(error));
+                            }
+                        }
+                    }
+                    runtime_host::OffchainContext::StorageSet(req) => {
+                        let key = req.key().as_ref().to_vec();
+                        let value = req.value().map(|v| v.as_ref().to_vec());
+                        // The old value, if any, is a SCALE-encoded `Option<Vec<u8>>`.
+                        let old_value = match req
+                            .old_value()
+                            .map(|v| {
+                                util::decode_scale_option_bytes(v.as_ref())
+                                    .map(|v| v.map(|v| v.to_vec()))
+                            })
+                            .transpose()
+                        {
+                            Ok(v) => v,
+                            Err(()) => {
+                                runtime_caches.lock().await.put(
+                                    cache_key,
+                                    runtime_host::OffchainContext::StorageSet(req).into_prototype(),
+                                );
+                                break Err(RuntimeCallError::InvalidOffchainStorageOldValue);
+                            }
+                        };
+                        let result = database
+                            .with_database(move |db| match old_value {
+                                Some(old_value) => db.offchain_storage_compare_and_set(
+                                    &key,
+                                    old_value.as_deref(),
+                                    value.as_deref(),
+                                ),
+                                None => db
+                                    .offchain_storage_set(&key, value.as_deref())
+                                    .map(|()| true),
+                            })
+                            .await;
+                        match result {
+                            Ok(replaced) => call = req.resume(replaced),
+                            Err(error) => {
+                                runtime_caches.lock().await.put(
+                                    cache_key,
+                                    runtime_host::OffchainContext::StorageSet(req).into_prototype(),
+                                );
+                                break Err(RuntimeCallError::OffchainStorageAccess(error));
+                            }
+                        }
+                    }
+                    runtime_host::OffchainContext::Timestamp(req) => {
+                        let timestamp =
+                            u64::try_from(offchain.time_source.now_from_unix_epoch().as_millis())
+                                .unwrap_or(u64::MAX);
+                        call = req.inject_timestamp(timestamp);
+                    }
+                    runtime_host::OffchainContext::RandomSeed(req) => {
+                        call = req.inject_random_seed(rand::random());
+                    }
+                    runtime_host::OffchainContext::SubmitTransaction(req) => {
+                        offchain
+                            .transactions_service
+                            .submit_transaction(req.transaction().as_ref().to_vec())
+                            .await;
+                        call = req.resume(true);
+                    }
+                    runtime_host::OffchainContext::HttpRequestStart(req) => {
+                        let result = offchain
+                            .http_requests
+                            .start(req.method().as_ref(), req.uri().as_ref());
+                        call = req.resume(result);
+                    }
+                    runtime_host::OffchainContext::HttpRequestAddHeader(req) => {
+                        let result = offchain.http_requests.add_header(
+                            req.request_id(),
+                            req.name().as_ref(),
+                            req.value().as_ref(),
+                        );
+                        call = req.resume(result);
+                    }
+                    runtime_host::OffchainContext::HttpRequestWriteBody(req) => {
+                        let result = offchain
+                            .http_requests
+                            .write_body(req.request_id(), req.chunk().as_ref());
+                        call = req.resume(result);
+                    }
+                    runtime_host::OffchainContext::HttpResponseWait(req) => {
+                        let statuses = offchain
+                            .http_requests
+                            .wait(req.request_ids(), req.deadline())
+                            .await;
+                        call = req.resume(statuses.into_iter());
+                    }
+                    runtime_host::OffchainContext::HttpResponseHeaders(req) => {
+                        let headers = offchain.http_requests.response_headers(req.request_id());
+                        call = req.resume(
+                            headers
+                                .iter()
+                                .map(|(name, value)| (name.as_bytes(), value.as_bytes())),
+                        );
+                    }
+                    runtime_host::OffchainContext::HttpResponseReadBody(req) => {
+                        let result = offchain
+                            .http_requests
+                            .read_body(req.request_id(), req.max_size(), req.deadline())
+                            .await;
+                        call = req.resume(result.as_deref().map_err(|err| *err));
+                    }
+                }
+            }
+        }
+    }
+}
+
+/// Obtains the SCALE-encoded metadata of the runtime of the given block, preferably in the
+/// given version of the format. See [`metadata::query`].
+pub async fn runtime_metadata(
+    database: &database_thread::DatabaseThread,
+    runtime_caches: &Mutex<RuntimeCaches>,
+    block_hash: [u8; 32],
+    version: u32,
+) -> Result<Vec<u8>, RuntimeMetadataError> {
+    runtime_metadata_inner(database, runtime_caches, block_hash, None, version).await
+}
+
+/// Obtains the SCALE-encoded metadata of `code`, preferably in the given version of the format,
+/// as if it was the runtime of the given block. See [`runtime_call_with_code`].
+pub async fn runtime_metadata_with_code(
+    database: &database_thread::DatabaseThread,
+    runtime_caches: &Mutex<RuntimeCaches>,
+    block_hash: [u8; 32],
+    code: &[u8],
+    version: u32,
+) -> Result<Vec<u8>, RuntimeMetadataError> {
+    runtime_metadata_inner(database, runtime_caches, block_hash, Some(code), version).await
+}
+
+/// Obtains the SCALE-encoded metadata of the runtime of the given block, or of `code_override`
+/// if it is `Some`.
+async fn runtime_metadata_inner(
+    database: &database_thread::DatabaseThread,
+    runtime_caches: &Mutex<RuntimeCaches>,
+    block_hash: [u8; 32],
+    code_override: Option<&[u8]>,
+    version: u32,
+) -> Result<Vec<u8>, RuntimeMetadataError> {
+    let (cache_key, runtime) = runtime_of_call(database, runtime_caches, block_hash, code_override)
+        .await
+        .map_err(RuntimeMetadataError::RuntimeCall)?;
+
+    let mut query = metadata::query::query_metadata(metadata::query::Config {
+        runtime,
+        version,
+        max_log_level: 0,
+    });
+
+    loop {
+        let storage_result = match query {
+            metadata::query::Query::Finished {
+                result,
+                virtual_machine,
+            } => {
+                runtime_caches.lock().await.put(cache_key, virtual_machine);
+                return result.map_err(RuntimeMetadataError::Query);
+            }
+            metadata::query::Query::StorageGet(req) => {
+                let child_trie = req.child_trie().map(|t| t.as_ref().to_vec());
+                let key = req.key().as_ref().to_vec();
+                if let (Some(code), None, b":code") = (code_override, &child_trie, &key[..]) {
+                    query = req.inject_value(Some((iter::once(code), trie::TrieEntryVersion::V0)));
+                    continue;
+                }
+                match storage_get(database, block_hash, child_trie.as_deref(), &key).await {
+                    Ok(value) => {
+                        query = req.inject_value(
+                            value
+                                .as_ref()
+                                .map(|(val, vers)| (iter::once(&val[..]), *vers)),
+                        );
+                        continue;
+                    }
+                    Err(error) => (error, metadata::query::Query::StorageGet(req)),
+                }
+            }
+            metadata::query::Query::ClosestDescendantMerkleValue(req) => {
+                let child_trie = req.child_trie().map(|t| t.as_ref().to_vec());
+                match storage_closest_descendant_merkle_value(
+                    database,
+                    block_hash,
+                    child_trie.as_deref(),
+                    req.key().map(u8::from).collect(),
+                )
+                .await
+                {
+                    Ok(merkle_value) => {
+                        query = req.inject_merkle_value(merkle_value.as_deref());
+                        continue;
+                    }
+                    Err(error) => (
+                        error,
+                        metadata::query::Query::ClosestDescendantMerkleValue(req),
+                    ),
+                }
+            }
+            metadata::query::Query::NextKey(req) => {
+                let child_trie = req.child_trie().map(|t| t.as_ref().to_vec());
+                match storage_next_key(
+                    database,
+                    block_hash,
+                    child_trie.as_deref(),
+                    req.key().map(u8::from).collect(),
+                    req.or_equal(),
+                    req.prefix().map(u8::from).collect(),
+                    req.branch_nodes(),
+                )
+                .await
+                {
+                    Ok(next_key) => {
+                        query = req
+                            .inject_key(next_key.map(|k| {
+                                k.into_iter().map(|b| trie::Nibble::try_from(b).unwrap())
+                            }));
+                        continue;
+                    }
+                    Err(error) => (error, metadata::query::Query::NextKey(req)),
+                }
+            }
+        };
+
+        let (error, query) = storage_result;
+        runtime_caches
+            .lock()
+            .await
+            .put(cache_key, query.into_prototype());
+        return Err(RuntimeMetadataError::RuntimeCall(error));
+    }
+}
+
+/// Builds the parameter to pass to `Core_execute_block` in order to re-execute the given block.
+///
+/// The block must be executed by calling `Core_execute_block` against the storage of its parent,
+/// in the same way as when it has been verified. The seal at the end of the digest logs is
+/// removed from the header, as the runtime expects.
+///
+/// Returns the hash of the parent of the block and the parameter of the call.
+pub fn execute_block_parameter(
+    scale_encoded_header: &[u8],
+    block_number_bytes: usize,
+    body: &[impl AsRef<[u8]>],
+) -> Result<([u8; 32], Vec<u8>), header::Error> {
+    let mut decoded = header::decode(scale_encoded_header, block_number_bytes)?;
+    let _seal = decoded.digest.pop_seal();
+    let mut parameter = decoded.scale_encoding_vec(block_number_bytes);
+    parameter.extend_from_slice(&util::encode_scale_compact_usize(body.len()));
+    for extrinsic in body {
+        parameter.extend_from_slice(extrinsic.as_ref());
+    }
+    Ok((*decoded.parent_hash, parameter))
+}
+
+/// Returns the runtime to use in order to perform a call against the storage of the given block.
+///
+/// If `code_override` is `Some`, it is used as the runtime instead of the `:code` found in the
+/// storage of the block, with the `:heappages` found in the storage of the block.
+async fn runtime_of_call(
+    database: &database_thread::DatabaseThread,
+    runtime_caches: &Mutex<RuntimeCaches>,
+    block_hash: [u8; 32],
+    code_override: Option<&[u8]>,
+) -> Result<(RuntimeCacheKey, executor::host::HostVmPrototype), RuntimeCallError> {
+    match code_override {
+        Some(code) => {
+            let heap_pages = block_storage_value(database, block_hash, b":heappages").await?;
+            let heap_pages = executor::storage_heap_pages_to_value(heap_pages.as_deref())
+                .map_err(RuntimeCallError::InvalidHeapPages)?;
+            runtime_of_code(database, runtime_caches, code, heap_pages).await
+        }
+        None => runtime_of_block(database, runtime_caches, block_hash).await,
+    }
+}
+
+/// Returns the runtime of the given block, either from `runtime_caches` or by
+/// compiling it.
+///
+/// The runtime is removed from the cache and should be put back after it has been used.
+pub async fn runtime_of_block(
+    database: &database_thread::DatabaseThread,
+    runtime_caches: &Mutex<RuntimeCaches>,
+    block_hash: [u8; 32],
+) -> Result<(RuntimeCacheKey, executor::host::HostVmPrototype), RuntimeCallError> {
+    let code = block_storage_value(database, block_hash, b":code")
+        .await?
+        .ok_or(RuntimeCallError::CodeNotFound)?;
+    let heap_pages = block_storage_value(database, block_hash, b":heappages").await?;
+    let heap_pages = executor::storage_heap_pages_to_value(heap_pages.as_deref())
+        .map_err(RuntimeCallError::InvalidHeapPages)?;
+
+    runtime_of_code(database, runtime_caches, &code, heap_pages).await
+}
+
+/// Returns the runtime corresponding to the given code and number of heap pages, either from
+/// `runtime_caches` or by compiling it.
+///
+/// The runtime is removed from the cache and should be put back after it has been used.
+async fn runtime_of_code(
+    database: &database_thread::DatabaseThread,
+    runtime_caches: &Mutex<RuntimeCaches>,
+    code: &[u8],
+    heap_pages: executor::vm::HeapPages,
+) -> Result<(RuntimeCacheKey, executor::host::HostVmPrototype), RuntimeCallError> {
+    let cache_key = (
+        <[u8; 32]>::try_from(blake2_rfc::blake2b::blake2b(32, &[], code).as_bytes()).unwrap(),
+        heap_pages,
+    );
+
+    if let Some(runtime) = runtime_caches.lock().await.pop(&cache_key) {
+        return Ok((cache_key, runtime));
+    }
+
+    let runtime = executor::host::HostVmPrototype::new(executor::host::Config {
+        module: code,
+        heap_pages,
+        exec_hint: executor::vm::ExecHint::CompileAheadOfTime,
+        allow_unresolved_imports: true,
+        compiled_module_cache: database.compiled_runtimes_cache(),
+        resource_limits: Default::default(),
+        allocation_strategy: Default::default(),
+    })
+    .map_err(RuntimeCallError::Compilation)?;
+
+    Ok((cache_key, runtime))
+}
+
+/// Returns the value of the given key of the main trie of the storage of the given block.
+async fn block_storage_value(
+    database: &database_thread::DatabaseThread,
+    block_hash: [u8; 32],
+    key: &'static [u8],
+) -> Result<Option<Vec<u8>>, RuntimeCallError> {
+    database
+        .with_database(move |database| {
+            database.block_storage_get(
+                &block_hash,
+                iter::empty::<iter::Empty<u8>>(),
+                trie::bytes_to_nibbles(key.iter().copied()).map(u8::from),
+            )
+        })
+        .await
+        .map(|value| value.map(|(value, _)| value))
+        .map_err(RuntimeCallError::StorageAccess)
+}
+
+/// Returns the value of the given key of the storage of the given block, and the version of the
+/// trie entry.
+async fn storage_get(
+    database: &database_thread::DatabaseThread,
+    block_hash: [u8; 32],
+    child_trie: Option<&[u8]>,
+    key: &[u8],
+) -> Result<Option<(Vec<u8>, runtime_host::TrieEntryVersion)>, RuntimeCallError> {
+    let parent_paths = child_trie.map(child_trie_parent_path);
+    let key = trie::bytes_to_nibbles(key.iter().copied())
+        .map(u8::from)
+        .collect::<Vec<_>>();
+    let value = database
+        .with_database(move |db| {
+            db.block_storage_get(
+                &block_hash,
+                parent_paths.into_iter().map(|p| p.into_iter()),
+                key.iter().copied(),
+            )
+        })
+        .await
+        .map_err(RuntimeCallError::StorageAccess)?;
+    value
+        .map(|(val, vers)| {
+            runtime_host::TrieEntryVersion::try_from(vers)
+                .map(|vers| (val, vers))
+                .map_err(|_| RuntimeCallError::CorruptedDatabase)
+        })
+        .transpose()
+}
+
+/// Returns the Merkle value of the closest descendant of the given key in the storage of the
+/// given block.
+async fn storage_closest_descendant_merkle_value(
+    database: &database_thread::DatabaseThread,
+    block_hash: [u8; 32],
+    child_trie: Option<&[u8]>,
+    key_nibbles: Vec<u8>,
+) -> Result<Option<Vec<u8>>, RuntimeCallError> {
+    let parent_paths = child_trie.map(child_trie_parent_path);
+    database
+        .with_database(move |db| {
+            db.block_storage_closest_descendant_merkle_value(
+                &block_hash,
+                parent_paths.into_iter().map(|p| p.into_iter()),
+                key_nibbles.iter().copied(),
+            )
+        })
+        .await
+        .map_err(RuntimeCallError::StorageAccess)
+}
+
+/// Returns the key, as a list of nibbles, that follows the given one in the storage of the given
+/// block.
+async fn storage_next_key(
+    database: &database_thread::DatabaseThread,
+    block_hash: [u8; 32],
+    child_trie: Option<&[u8]>,
+    key_nibbles: Vec<u8>,
+    or_equal: bool,
+    prefix_nibbles: Vec<u8>,
+    branch_nodes: bool,
+) -> Result<Option<Vec<u8>>, RuntimeCallError> {
+    let parent_paths = child_trie.map(child_trie_parent_path);
+    let key_nibbles = key_nibbles
+        .into_iter()
+        .chain(if or_equal { None } else { Some(0u8) })
+        .collect::<Vec<_>>();
+    database
+        .with_database(move |db| {
+            db.block_storage_next_key(
+                &block_hash,
+                parent_paths.into_iter().map(|p| p.into_iter()),
+                key_nibbles.iter().copied(),
+                prefix_nibbles.iter().copied(),
+                branch_nodes,
+            )
+        })
+        .await
+        .map_err(RuntimeCallError::StorageAccess)
+}
+
+/// Returns the path, as a list of nibbles, of the node of the main trie that contains the root
+/// of the given default child trie.
+fn child_trie_parent_path(child_trie: &[u8]) -> Vec<u8> {
+    trie::bytes_to_nibbles(b":child_storage:default:".iter().copied())
+        .chain(trie::bytes_to_nibbles(child_trie.iter().copied()))
+        .map(u8::from)
+        .collect()
+}
+
+/// Key whose lookup must be included in a storage proof.
+#[derive(Debug, Clone, PartialEq, Eq, Hash)]
+pub struct ProofKey {
+    /// Name of the default child trie the key belongs to, or `None` for the main trie.
+    pub child_trie: Option<Vec<u8>>,
+    /// Key to look up, as a list of nibbles. Each byte must be strictly inferior to 16.
+    pub key_nibbles: Vec<u8>,
+}
+
+/// Builds a storage proof containing all the trie nodes that are traversed when looking up the
+/// given keys in the storage of the given block.
+pub async fn storage_proof(
+    database: &database_thread::DatabaseThread,
+    block_number_bytes: usize,
+    block_hash: [u8; 32],
+    keys: Vec<ProofKey>,
+) -> Result<Vec<u8>, full_sqlite::StorageAccessError> {
+    database
+        .with_database(move |database| {
+            let state_root = block_state_root(database, block_number_bytes, &block_hash)?;
+            let mut proof = StorageProofBuilder::new();
+            for key in &keys {
+                proof.add_lookup(database, &state_root, key)?;
+            }
+            Ok(proof.build())
+        })
+        .await
+}
+
+/// Returns the state root found in the header of the given block.
+pub fn block_state_root(
+    database: &full_sqlite::SqliteFullDatabase,
+    block_number_bytes: usize,
+    block_hash: &[u8; 32],
+) -> Result<[u8; 32], full_sqlite::StorageAccessError> {
+    let header = database
+        .block_scale_encoded_header(block_hash)?
+        .ok_or(full_sqlite::StorageAccessError::UnknownBlock)?;
+    let decoded = header::decode(&header, block_number_bytes).map_err(|err| {
+        full_sqlite::AccessError::Corrupted(full_sqlite::CorruptedError::BlockHeaderCorrupted(err))
+    })?;
+    Ok(*decoded.state_root)
+}
+
+/// Storage proof whose building is in progress.
+///
+/// Contains one [`trie::proof_encode::ProofBuilder`] for the main trie and one for each default
+/// child trie that has been looked up.
+pub struct StorageProofBuilder {
+    main_trie: trie::proof_encode::ProofBuilder,
+    child_tries: hashbrown::HashMap<Vec<u8>, trie::proof_encode::ProofBuilder, fnv::FnvBuildHasher>,
+
+    /// Merkle values of the trie nodes that have been added to the builders so far, in order to
+    /// not count them multiple times in [`StorageProofBuilder::size`].
+    visited_nodes: hashbrown::HashSet<Vec<u8>, fnv::FnvBuildHasher>,
+
+    /// Approximate size in bytes of the proof.
+    size: usize,
+}
+
+impl StorageProofBuilder {
+    /// Initializes a new empty proof.
+    pub fn new() -> Self {
+        StorageProofBuilder {
+            main_trie: trie::proof_encode::ProofBuilder::new(),
+            child_tries: hashbrown::HashMap::with_capacity_and_hasher(0, Default::default()),
+            visited_nodes: hashbrown::HashSet::with_capacity_and_hasher(32, Default::default()),
+            size: 0,
+        }
+    }
+
+    /// Adds to the proof the trie nodes that are traversed when looking up the given key in the
+    /// storage whose main trie root is `state_root`, and returns the storage value found at this
+    /// key, if any.
+    ///
+    /// If the key belongs to a child trie, the lookup of this child trie in the main trie is
+    /// included in the proof as well.
+    ///
+    /// Returns [`full_sqlite::StorageAccessError::Pruned`] if the trie nodes can't be found in
+    /// the database.
+    pub fn add_lookup(
+        &mut self,
+        database: &full_sqlite::SqliteFullDatabase,
+        state_root: &[u8; 32],
+        key: &ProofKey,
+    ) -> Result<Option<(Vec<u8>, u8)>, full_sqlite::StorageAccessError> {
+        let Some(child_trie) = &key.child_trie else {
+            return add_trie_lookup(
+                &mut self.main_trie,
+                &mut self.visited_nodes,
+                &mut self.size,
+                database,
+                &state_root[..],
+                &key.key_nibbles,
+            );
+        };
+
+        let child_trie_key_nibbles = trie::bytes_to_nibbles(
+            b":child_storage:default:"
+                .iter()
+                .chain(child_trie.iter())
+                .copied(),
+        )
+        .map(u8::from)
+        .collect::<Vec<_>>();
+
+        let Some((child_trie_root, _)) = add_trie_lookup(
+            &mut self.main_trie,
+            &mut self.visited_nodes,
+            &mut self.size,
+            database,
+            &state_root[..],
+            &child_trie_key_nibbles,
+        )?
+        else {
+            return Ok(None);
+        };
+
+        add_trie_lookup(
+            self.child_tries.entry(child_trie.clone()).or_default(),
+            &mut self.visited_nodes,
+            &mut self.size,
+            database,
+            &child_trie_root,
+            &key.key_nibbles,
+        )
+    }
+
+    /// Returns the approximate size in bytes of the proof built so far.
+    pub fn size(&self) -> usize {
+        self.size
+    }
+
+    /// Returns the SCALE-encoded proof, containing the nodes of all the tries.
+    pub fn build(self) -> Vec<u8> {
+        let mut entries =
+            hashbrown::HashSet::<Vec<u8>, fnv::FnvBuildHasher>::with_capacity_and_hasher(
+                self.visited_nodes.len(),
+                Default::default(),
+            );
+        for builder in iter::once(self.main_trie).chain(self.child_tries.into_values()) {
+            let proof = builder.build_to_vec();
+            // The proof builder always produces a valid list.
+            let trie_entries = util::decode_scale_bytes_list(&proof).unwrap();
+            entries.extend(trie_entries.into_iter().map(|entry| entry.to_vec()));
+        }
+
+        let mut out = util::encode_scale_compact_usize(entries.len());
+        for entry in entries {
+            out.extend_from_slice(&util::encode_scale_compact_usize(entry.len()));
+            out.extend_from_slice(&entry);
+        }
+        out
+    }
+}
+
+/// Walks the trie whose root is `root_merkle_value` down to the given key, and adds the nodes
+/// that are traversed to `builder`.
+fn add_trie_lookup(
+    builder: &mut trie::proof_encode::ProofBuilder,
+    visited_nodes: &mut hashbrown::HashSet<Vec<u8>, fnv::FnvBuildHasher>,
+    size: &mut usize,
+    database: &full_sqlite::SqliteFullDatabase,
+    root_merkle_value: &[u8],
+    key_nibbles: &[u8],
+) -> Result<Option<(Vec<u8>, u8)>, full_sqlite::StorageAccessError> {
+    let mut merkle_value = root_merkle_value.to_vec();
+    let mut node_key = Vec::with_capacity(key_nibbles.len());
+
+    loop {
+        let node = database
+            .trie_node(&merkle_value)?
+            .ok_or(full_sqlite::StorageAccessError::Pruned)?;
+
+        node_key.extend(
+            node.partial_key_nibbles
+                .iter()
+                .map(|n| trie::Nibble::try_from(*n).unwrap()),
+        );
+
+        if visited_nodes.insert(merkle_value) {
+            let (node_value, unhashed_storage_value) = node.node_value();
+            *size += node_value.len() + unhashed_storage_value.map_or(0, |v| v.len());
+            builder.set_node_value(&node_key, &node_value, unhashed_storage_value);
+        }
+
+        let remaining_key = &key_nibbles[node_key.len().min(key_nibbles.len())..];
+        if node_key.len() > key_nibbles.len()
+            || node_key
+                .iter()
+                .zip(key_nibbles)
+                .any(|(a, b)| u8::from(*a) != *b)
+        {
+            return Ok(None);
+        }
+
+        let Some(child_index) = remaining_key.first() else {
+            return Ok(node.storage_value);
+        };
+
+        match &node.children_merkle_values[usize::from(*child_index)] {
+            Some(child) => merkle_value = child.clone(),
+            None => return Ok(None),
+        }
+        node_key.push(trie::Nibble::try_from(*child_index).unwrap());
+    }
+}
+
+/// Error potentially returned by [`runtime_metadata`].
+#[derive(Debug, derive_more::Display)]
+pub enum RuntimeMetadataError {
+    /// Error while obtaining the runtime or accessing the storage of the block.
+    #[display(fmt = "{_0}")]
+    RuntimeCall(RuntimeCallError),
+    /// Error while calling the runtime or decoding its output.
+    #[display(fmt = "{_0}")]
+    Query(metadata::query::Error),
+}
+
+/// Error potentially returned by [`runtime_call`].
+#[derive(Debug, derive_more::Display)]
+pub enum RuntimeCallError {
+    /// Error while accessing the storage of the block in the database.
+    #[display(fmt = "Failed to access the block storage: {_0}")]
+    StorageAccess(full_sqlite::StorageAccessError),
+    /// The database contains entries that can't be decoded.
+    #[display(fmt = "Corrupted database")]
+    CorruptedDatabase,
+    /// The block doesn't have any `:code` in its storage.
+    #[display(fmt = "No runtime code found in the storage of the block")]
+    CodeNotFound,
+    /// Value of `:heappages` in the storage of the block is invalid.
+    #[display(fmt = "Invalid heap pages value: {_0}")]
+    InvalidHeapPages(executor::InvalidHeapPagesError),
+    /// Failed to compile the runtime of the block.
+    #[display(fmt = "Failed to compile the runtime: {_0}")]
+    Compilation(executor::host::NewErr),
+    /// Failed to start the runtime call.
+    #[display(fmt = "{_0}")]
+    StartError(executor::host::StartErr),
+    /// Error while executing the runtime.
+    #[display(fmt = "{_0}")]
+    RuntimeError(runtime_host::ErrorDetail),
+    /// The runtime called a host function that isn't available outside of offchain workers.
+    #[display(fmt = "Runtime called a forbidden host function")]
+    ForbiddenHostCall,
+    /// Error while accessing the off-chain storage in the database.
+    #[display(fmt = "Failed to access the off-chain storage: {_0}")]
+    OffchainStorageAccess(full_sqlite::AccessError),
+    /// The runtime has passed an invalid value to compare against when setting an off-chain
+    /// storage item.
+    #[display(fmt = "Invalid off-chain storage value to compare against")]
+    InvalidOffchainStorageOldValue,
+}
@@ -0,0 +1,399 @@
+// Smoldot
+// Copyright (C) 2023  Pierre Krieger
+// SPDX-License-Identifier: GPL-3.0-or-later WITH Classpath-exception-2.0
+
+// This program is free software: you can redistribute it and/or modify
+// it under the terms of the GNU General Public License as published by
+// the Free Software Foundation, either version 3 of the License, or
+// (at your option) any later version.
+
+// This program is distributed in the hope that it will be useful,
+// but WITHOUT ANY WARRANTY; without even the implied warranty of
+// MERCHANTABILITY or FITNESS FOR A PARTICULAR PURPOSE.  See the
+// GNU General Public License for more details.
+
+// You should have received a copy of the GNU General Public License
+// along with this program.  If not, see <http://www.gnu.org/licenses/>.
+
+//! Simulation of a runtime upgrade against the storage of the latest finalized block found in
+//! the database.
+//!
+//! The new runtime must have been compiled with support for the `TryRuntime` runtime API, which
+//! runs the storage migrations of all the pallets and optionally the checks that come with them.
+
+use crate::{database_thread, runtime_call, util};
+
+use smol::lock::Mutex;
+use smoldot::{database::full_sqlite, executor, header, metadata};
+use std::iter;
+
+/// Which checks the runtime should perform alongside with the storage migrations.
+///
+/// Only [`UpgradeChecks::None`] is supported by runtimes whose version of the `TryRuntime` API
+/// is 1. Only [`UpgradeChecks::None`] and [`UpgradeChecks::All`] are supported by runtimes whose
+/// version of the `TryRuntime` API accepts a boolean rather than a list of checks. Other checks
+/// make [`crate::simulate_runtime_upgrade`] return [`RuntimeUpgradeError::UnsupportedChecks`].
+#[derive(Debug, Copy, Clone, PartialEq, Eq)]
+pub enum UpgradeChecks {
+    /// Only run the migrations.
+    None,
+    /// Run the pre-upgrade and post-upgrade checks of the migrations, and the `try_state`
+    /// checks of the pallets.
+    All,
+    /// Run the pre-upgrade and post-upgrade checks of the migrations.
+    PreAndPost,
+    /// Run the `try_state` checks of the pallets.
+    TryState,
+}
+
+/// Weight of a call, as reported by the runtime.
+#[derive(Debug, Copy, Clone, PartialEq, Eq)]
+pub struct Weight {
+    /// Execution time, in picoseconds.
+    pub ref_time: u64,
+    /// Size, in bytes, of the storage proof the call would generate. Always 0 for runtimes that
+    /// predate this dimension of the weight.
+    pub proof_size: u64,
+}
+
+/// Outcome of a successful [`crate::simulate_runtime_upgrade`].
+#[derive(Debug, Clone)]
+pub struct RuntimeUpgradeReport {
+    /// Hash of the block against which the upgrade has been simulated.
+    pub block_hash: [u8; 32],
+    /// Number of the block against which the upgrade has been simulated.
+    pub block_number: u64,
+    /// Name of the specification of the runtime of the block.
+    pub old_spec_name: String,
+    /// Version of the specification of the runtime of the block.
+    pub old_spec_version: u32,
+    /// Name of the specification of the new runtime.
+    pub new_spec_name: String,
+    /// Version of the specification of the new runtime.
+    pub new_spec_version: u32,
+    /// Weight consumed by the migrations.
+    pub consumed_weight: Weight,
+    /// Maximum weight of a block according to the new runtime.
+    pub max_block_weight: Weight,
+    /// Number of entries of the storage modified by the migrations, including `:code`.
+    pub num_storage_changes: usize,
+    /// Logs emitted by the runtime during the migrations.
+    pub logs: String,
+}
+
+impl RuntimeUpgradeReport {
+    /// Returns `true` if the migrations consume more than the maximum weight of a block, in which
+    /// case the block that enacts the upgrade can't be produced.
+    pub fn is_weight_overrun(&self) -> bool {
+        self.consumed_weight.ref_time > self.max_block_weight.ref_time
+            || self.consumed_weight.proof_size > self.max_block_weight.proof_size
+    }
+}
+
+/// Error potentially returned by [`crate::simulate_runtime_upgrade`].
+#[derive(Debug, derive_more::Display)]
+pub enum RuntimeUpgradeError {
+    /// Error while opening the database.
+    #[display(fmt = "Failed to open the database: {_0}")]
+    DatabaseOpen(full_sqlite::InternalError),
+    /// The database doesn't exist or is empty.
+    #[display(fmt = "The database is empty")]
+    EmptyDatabase,
+    /// Error while accessing the database.
+    #[display(fmt = "Failed to access the database: {_0}")]
+    DatabaseAccess(full_sqlite::AccessError),
+    /// The header of the finalized block can't be decoded.
+    #[display(fmt = "Failed to decode the finalized block header: {_0}")]
+    InvalidHeader(header::Error),
+    /// Error while obtaining the version of the runtime of the finalized block.
+    #[display(fmt = "Failed to obtain the version of the current runtime: {_0}")]
+    OldRuntimeVersion(runtime_call::RuntimeCallError),
+    /// Error while obtaining the version of the new runtime.
+    #[display(fmt = "Failed to obtain the version of the new runtime: {_0}")]
+    NewRuntimeVersion(runtime_call::RuntimeCallError),
+    /// A runtime has returned a version that can't be decoded.
+    #[display(fmt = "Failed to decode the runtime version")]
+    InvalidRuntimeVersion,
+    /// The new runtime doesn't support the `TryRuntime` runtime API. It must be compiled with
+    /// the `try-runtime` feature.
+    #[display(fmt = "The new runtime doesn't support the TryRuntime API")]
+    TryRuntimeUnsupported,
+    /// The new runtime supports a version of the `TryRuntime` runtime API that isn't supported.
+    #[display(fmt = "Unsupported version of the TryRuntime API: {_0}")]
+    UnsupportedTryRuntimeVersion(u32),
+    /// The new runtime doesn't support selecting these checks.
+    #[display(fmt = "The new runtime doesn't support the {_0:?} checks")]
+    UnsupportedChecks(UpgradeChecks),
+    /// Error while obtaining the metadata of the new runtime.
+    #[display(fmt = "Failed to obtain the metadata of the new runtime: {_0}")]
+    NewRuntimeMetadata(runtime_call::RuntimeMetadataError),
+    /// The metadata of the new runtime can't be decoded.
+    #[display(fmt = "Failed to decode the metadata of the new runtime: {_0}")]
+    InvalidMetadata(metadata::DecodeError),
+    /// The migrations or the checks have failed.
+    #[display(fmt = "Runtime upgrade failed: {error}")]
+    UpgradeFailed {
+        /// Error that happened.
+        error: runtime_call::RuntimeCallError,
+        /// Logs emitted by the runtime during the migrations, before the failure.
+        logs: String,
+    },
+    /// The output of `TryRuntime_on_runtime_upgrade` can't be decoded.
+    #[display(fmt = "Failed to decode the weight returned by the runtime")]
+    InvalidWeight,
+}
+
+/// Executes the storage migrations of `new_code` against the storage of the latest finalized
+/// block, as if the runtime had been upgraded to `new_code` right after this block.
+///
+/// Nothing is written to the database.
+pub async fn simulate_runtime_upgrade(
+    database: &database_thread::DatabaseThread,
+    runtime_caches: &Mutex<runtime_call::RuntimeCaches>,
+    block_number_bytes: usize,
+    new_code: &[u8],
+    checks: UpgradeChecks,
+) -> Result<RuntimeUpgradeReport, RuntimeUpgradeError> {
+    let (block_hash, header) = database
+        .with_database(|database| {
+            let block_hash = database.finalized_block_hash()?;
+            let header = database.block_scale_encoded_header(&block_hash)?;
+            Ok((block_hash, header))
+        })
+        .await
+        .map_err(RuntimeUpgradeError::DatabaseAccess)?;
+    // The finalized block is always present in the database.
+    let header = header.unwrap();
+    let block_number = header::decode(&header, block_number_bytes)
+        .map_err(RuntimeUpgradeError::InvalidHeader)?
+        .number;
+
+    let old_version = runtime_call::runtime_call(
+        database,
+        runtime_caches,
+        block_hash,
+        "Core_version",
+        iter::empty::<Vec<u8>>(),
+    )
+    .await
+    .map_err(RuntimeUpgradeError::OldRuntimeVersion)?;
+    let old_version = executor::host::CoreVersion::from_slice(old_version)
+        .map_err(|_| RuntimeUpgradeError::InvalidRuntimeVersion)?;
+
+    let (new_version, _) = runtime_call::runtime_call_with_code(
+        database,
+        runtime_caches,
+        block_hash,
+        new_code,
+        "Core_version",
+        iter::empty::<Vec<u8>>(),
+    )
+    .await
+    .0
+    .map_err(RuntimeUpgradeError::NewRuntimeVersion)?;
+    let new_version = executor::host::CoreVersion::from_slice(new_version)
+        .map_err(|_| RuntimeUpgradeError::InvalidRuntimeVersion)?;
+
+    // Version 1 of the API doesn't accept any parameter, doesn't run any check, and returns
+    // weights made of a single `u64`. Version 2 accepts either a boolean indicating whether to
+    // run the checks or an enum indicating which checks to run, both of which have the same
+    // encoding for `false`/`None` and `true`/`All`. The two can only be told apart by looking
+    // at the metadata of the runtime.
+    let api_version = new_version
+        .decode()
+        .apis
+        .find_version("TryRuntime")
+        .ok_or(RuntimeUpgradeError::TryRuntimeUnsupported)?;
+    let parameter = match (api_version, checks) {
+        (1, UpgradeChecks::None) => Vec::new(),
+        (2, UpgradeChecks::None) => vec![0],
+        (2, UpgradeChecks::All) => vec![1],
+        (2, UpgradeChecks::PreAndPost | UpgradeChecks::TryState)
+            if accepts_checks_selection(database, runtime_caches, block_hash, new_code).await? =>
+        {
+            vec![if checks == UpgradeChecks::PreAndPost {
+                2
+            } else {
+                3
+            }]
+        }
+        (1 | 2, _) => return Err(RuntimeUpgradeError::UnsupportedChecks(checks)),
+        (v, _) => return Err(RuntimeUpgradeError::UnsupportedTryRuntimeVersion(v)),
+    };
+
+    let (result, logs) = runtime_call::runtime_call_with_code(
+        database,
+        runtime_caches,
+        block_hash,
+        new_code,
+        "TryRuntime_on_runtime_upgrade",
+        iter::once(&parameter),
+    )
+    .await;
+    let (output, storage_changes) = match result {
+        Ok(outcome) => outcome,
+        Err(error) => return Err(RuntimeUpgradeError::UpgradeFailed { error, logs }),
+    };
+
+    // The runtime returns a tuple of the weight consumed by the migrations and the maximum
+    // weight of a block.
+    let (consumed_weight, max_block_weight) = if api_version == 1 {
+        decode_weights_v1(&output)
+    } else {
+        decode_weights_v2(&output)
+    }
+    .ok_or(RuntimeUpgradeError::InvalidWeight)?;
+
+    let old_version = old_version.decode();
+    let new_version = new_version.decode();
+    Ok(RuntimeUpgradeReport {
+        block_hash,
+        block_number,
+        old_spec_name: old_version.spec_name.to_owned(),
+        old_spec_version: old_version.spec_version,
+        new_spec_name: new_version.spec_name.to_owned(),
+        new_spec_version: new_version.spec_version,
+        consumed_weight,
+        max_block_weight,
+        num_storage_changes: storage_changes
+            .main_trie_storage_changes_iter_unordered()
+            .count(),
+        logs,
+    })
+}
+
+/// Returns `true` if the `TryRuntime_on_runtime_upgrade` function of `new_code` accepts an enum
+/// indicating which checks to run, rather than a boolean.
+///
+/// Returns `false` if the metadata of `new_code` doesn't describe its runtime APIs, which is the
+/// case before version 15 of the metadata format.
+async fn accepts_checks_selection(
+    database: &database_thread::DatabaseThread,
+    runtime_caches: &Mutex<runtime_call::RuntimeCaches>,
+    block_hash: [u8; 32],
+    new_code: &[u8],
+) -> Result<bool, RuntimeUpgradeError> {
+    let metadata = runtime_call::runtime_metadata_with_code(
+        database,
+        runtime_caches,
+        block_hash,
+        new_code,
+        15,
+    )
+    .await
+    .map_err(RuntimeUpgradeError::NewRuntimeMetadata)?;
+    let metadata = metadata::decode(&metadata).map_err(RuntimeUpgradeError::InvalidMetadata)?;
+
+    let parameter_ty = metadata
+        .apis
+        .iter()
+        .find(|api| api.name == "TryRuntime")
+        .and_then(|api| {
+            api.methods
+                .iter()
+                .find(|method| method.name == "on_runtime_upgrade")
+        })
+        .and_then(|method| method.inputs.first())
+        .and_then(|(_, ty)| metadata.type_by_id(*ty));
+    Ok(matches!(
+        parameter_ty,
+        Some(metadata::TypeRef {
+            definition: metadata::TypeDefinition::Variant { .. },
+            ..
+        })
+    ))
+}
+
+/// Decodes two weights each made of a single `u64`.
+fn decode_weights_v1(output: &[u8]) -> Option<(Weight, Weight)> {
+    let output = <&[u8; 16]>::try_from(output).ok()?;
+    let weight = |bytes: &[u8]| Weight {
+        ref_time: u64::from_le_bytes(<[u8; 8]>::try_from(bytes).unwrap()),
+        proof_size: 0,
+    };
+    Some((weight(&output[..8]), weight(&output[8..])))
+}
+
+/// Decodes two weights each made of two SCALE-compact `u64`s.
+fn decode_weights_v2(output: &[u8]) -> Option<(Weight, Weight)> {
+    let mut numbers = [0; 4];
+    let mut rest = output;
+    for number in &mut numbers {
+        (*number, rest) = util::decode_scale_compact_u64(rest).ok()?;
+    }
+    if !rest.is_empty() {
+        return None;
+    }
+
+    Some((
+        Weight {
+            ref_time: numbers[0],
+            proof_size: numbers[1],
+        },
+        Weight {
+            ref_time: numbers[2],
+            proof_size: numbers[3],
+        },
+    ))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::Weight;
+
+    #[test]
+    fn decode_weights_v1() {
+        let output = [
+            0x40, 0x42, 0x0f, 0, 0, 0, 0, 0, 0x00, 0x20, 0x4a, 0xa9, 0xd1, 0x01, 0, 0,
+        ];
+        assert_eq!(
+            super::decode_weights_v1(&output),
+            Some((
+                Weight {
+                    ref_time: 1_000_000,
+                    proof_size: 0
+                },
+                Weight {
+                    ref_time: 2_000_000_000_000,
+                    proof_size: 0
+                }
+            ))
+        );
+    }
+
+    #[test]
+    fn decode_weights_v1_wrong_length() {
+        assert!(super::decode_weights_v1(&[0; 15]).is_none());
+        assert!(super::decode_weights_v1(&[0; 17]).is_none());
+        assert!(super::decode_weights_v1(&[]).is_none());
+    }
+
+    #[test]
+    fn decode_weights_v2() {
+        // `Compact(5)`, `Compact(300)`, `Compact(2_000_000_000_000)`, `Compact(5_242_880)`.
+        let output = [
+            0x14, 0xb1, 0x04, 0x0b, 0x00, 0x20, 0x4a, 0xa9, 0xd1, 0x01, 0x02, 0x00, 0x40, 0x01,
+        ];
+        assert_eq!(
+            super::decode_weights_v2(&output),
+            Some((
+                Weight {
+                    ref_time: 5,
+                    proof_size: 300
+                },
+                Weight {
+                    ref_time: 2_000_000_000_000,
+                    proof_size: 5_242_880
+                }
+            ))
+        );
+    }
+
+    #[test]
+    fn decode_weights_v2_invalid() {
+        // Missing number.
+        assert!(super::decode_weights_v2(&[0x14, 0xb1, 0x04, 0x04]).is_none());
+        // Trailing data.
+        assert!(super::decode_weights_v2(&[0x04, 0x04, 0x04, 0x04, 0x00]).is_none());
+    }
+}
@@ -0,0 +1,136 @@
+// Smoldot
+// Copyright (C) 2023  Pierre Krieger
+// SPDX-License-Identifier: GPL-3.0-or-later WITH Classpath-exception-2.0
+
+// This program is free software: you can redistribute it and/or modify
+// it under the terms of the GNU General Public License as published by
+// the Free Software Foundation, either version 3 of the License, or
+// (at your option) any later version.
+
+// This program is distributed in the hope that it will be useful,
+// but WITHOUT ANY WARRANTY; without even the implied warranty of
+// MERCHANTABILITY or FITNESS FOR A PARTICULAR PURPOSE.  See the
+// GNU General Public License for more details.
+
+// You should have received a copy of the GNU General Public License
+// along with this program.  If not, see <http://www.gnu.org/licenses/>.
+
+//! Re-execution of blocks found in the database in order to display the changes they perform to
+//! the storage.
+
+use crate::{database_thread, runtime_call};
+
+use smol::lock::Mutex;
+use smoldot::{database::full_sqlite, header, metadata};
+use std::iter;
+
+/// Change to the storage performed by a block. See [`crate::block_storage_diff`].
+#[derive(Debug, Clone)]
+pub struct StorageDiffEntry {
+    /// Key of the storage item.
+    pub key: Vec<u8>,
+    /// New value of the storage item, or `None` if the item has been removed.
+    pub new_value: Option<Vec<u8>>,
+    /// Human-readable version of [`StorageDiffEntry::key`], in the form `Pallet::Entry(keys)`.
+    /// `None` if the key couldn't be decoded, for example if it isn't described by the metadata.
+    pub decoded_key: Option<String>,
+    /// Human-readable version of [`StorageDiffEntry::new_value`]. `None` if the key or the value
+    /// couldn't be decoded, or if the item has been removed.
+    pub decoded_new_value: Option<String>,
+}
+
+/// Error potentially returned by [`crate::block_storage_diff`].
+#[derive(Debug, derive_more::Display)]
+pub enum BlockStorageDiffError {
+    /// Error while opening the database.
+    #[display(fmt = "Failed to open the database: {_0}")]
+    DatabaseOpen(full_sqlite::InternalError),
+    /// The database doesn't exist or is empty.
+    #[display(fmt = "The database is empty")]
+    EmptyDatabase,
+    /// Error while accessing the database.
+    #[display(fmt = "Failed to access the database: {_0}")]
+    DatabaseAccess(full_sqlite::AccessError),
+    /// The block can't be found in the database.
+    #[display(fmt = "Unknown block")]
+    UnknownBlock,
+    /// The header of the block can't be decoded.
+    #[display(fmt = "Failed to decode the block header: {_0}")]
+    InvalidHeader(header::Error),
+    /// Error while executing the block.
+    #[display(fmt = "Failed to execute the block: {_0}")]
+    Execution(runtime_call::RuntimeCallError),
+    /// Error while obtaining the metadata of the runtime of the parent of the block.
+    #[display(fmt = "Failed to obtain the metadata: {_0}")]
+    Metadata(runtime_call::RuntimeMetadataError),
+    /// The metadata returned by the runtime couldn't be decoded.
+    #[display(fmt = "Failed to decode the metadata: {_0}")]
+    MetadataDecode(metadata::DecodeError),
+}
+
+/// Re-executes the given block on top of the storage of its parent, and returns the changes that
+/// it performs to the main trie of the storage, ordered by key.
+///
+/// Keys and values are decoded using the metadata of the runtime of the parent of the block.
+pub async fn block_storage_diff(
+    database: &database_thread::DatabaseThread,
+    runtime_caches: &Mutex<runtime_call::RuntimeCaches>,
+    block_number_bytes: usize,
+    block_hash: [u8; 32],
+) -> Result<Vec<StorageDiffEntry>, BlockStorageDiffError> {
+    let (header, body) = database
+        .with_database(move |database| {
+            let Some(header) = database.block_scale_encoded_header(&block_hash)? else {
+                return Ok(None);
+            };
+            let Some(body) = database.block_extrinsics(&block_hash)? else {
+                return Ok(None);
+            };
+            Ok(Some((header, body.collect::<Vec<_>>())))
+        })
+        .await
+        .map_err(BlockStorageDiffError::DatabaseAccess)?
+        .ok_or(BlockStorageDiffError::UnknownBlock)?;
+
+    let (parent_hash, execute_block_parameter) =
+        runtime_call::execute_block_parameter(&header, block_number_bytes, &body)
+            .map_err(BlockStorageDiffError::InvalidHeader)?;
+
+    let (_, storage_changes) = runtime_call::runtime_call_storage_changes(
+        database,
+        runtime_caches,
+        parent_hash,
+        "Core_execute_block",
+        iter::once(&execute_block_parameter),
+    )
+    .await
+    .map_err(BlockStorageDiffError::Execution)?;
+
+    let metadata = runtime_call::runtime_metadata(database, runtime_caches, parent_hash, 15)
+        .await
+        .map_err(BlockStorageDiffError::Metadata)?;
+    let metadata = metadata::decode(&metadata).map_err(BlockStorageDiffError::MetadataDecode)?;
+
+    let mut diff = storage_changes
+        .main_trie_storage_changes_iter_unordered()
+        .map(|(key, new_value)| {
+            let decoded_key = metadata.decode_storage_key(key).ok();
+            let decoded_new_value = match (&decoded_key, new_value) {
+                (Some(decoded_key), Some(new_value)) => metadata
+                    .decode_value(decoded_key.value_ty, new_value)
+                    .ok()
+                    .map(|value| value.to_string()),
+                _ => None,
+            };
+
+            StorageDiffEntry {
+                key: key.to_vec(),
+                new_value: new_value.map(|v| v.to_vec()),
+                decoded_key: decoded_key.map(|key| key.to_string()),
+                decoded_new_value,
+            }
+        })
+        .collect::<Vec<_>>();
+    diff.sort_unstable_by(|a, b| a.key.cmp(&b.key));
+    Ok(diff)
+}
@@ -0,0 +1,1161 @@
+// Smoldot
+// Copyright (C) 2023  Pierre Krieger
+// SPDX-License-Identifier: GPL-3.0-or-later WITH Classpath-exception-2.0
+
+// This program is free software: you can redistribute it and/or modify
+// it under the terms of the GNU General Public License as published by
+// the Free Software Foundation, either version 3 of the License, or
+// (at your option) any later version.
+
+// This program is distributed in the hope that it will be useful,
+// but WITHOUT ANY WARRANTY; without even the implied warranty of
+// MERCHANTABILITY or FITNESS FOR A PARTICULAR PURPOSE.  See the
+// GNU General Public License for more details.
+
+// You should have received a copy of the GNU General Public License
+// along with this program.  If not, see <http://www.gnu.org/licenses/>.
+
+//! Background transactions service.
+//!
+//! The [`TransactionsService`] holds a pool of transactions that have been submitted locally or
+//! received from the peers of the network. The transactions of the pool are validated against
+//! the blocks found in the database, sent out to the peers of the network, and provided to the
+//! [`consensus_service::ConsensusService`] when it authors a new block.
+//!
+//! The service follows the blocks verified by the [`consensus_service::ConsensusService`] (see
+//! [`consensus_service::ConsensusService::subscribe_all`]) and reads from the database the body
+//! of all the blocks of the best chain, in order to find out whether the transactions of the pool
+//! have been included. Each transaction can be watched by the API user, in which case the
+//! updates about its status are reported through a channel.
+//!
+//! If the channel of blocks notifications coming from the consensus service is closed, which
+//! happens if the transactions service isn't processing notifications quickly enough, all the
+//! transactions are dropped.
+
+use crate::{
+    consensus_service, database_thread, network_service, runtime_call, LogCallback, LogLevel,
+};
+
+use futures_lite::FutureExt as _;
+use futures_util::{stream, StreamExt as _};
+use smol::lock::Mutex;
+use smoldot::{
+    database::full_sqlite,
+    header,
+    informant::HashDisplay,
+    libp2p::PeerId,
+    transactions::{pool, validate},
+    trie,
+};
+use std::{
+    collections::{HashMap, HashSet},
+    future::Future,
+    iter, mem,
+    num::{NonZeroU32, NonZeroUsize},
+    pin::Pin,
+    sync::Arc,
+};
+
+/// Configuration for a [`TransactionsService`].
+pub struct Config {
+    /// Function that can be used to spawn background tasks.
+    ///
+    /// The tasks passed as parameter must be executed until they shut down.
+    pub tasks_executor: Arc<dyn Fn(Pin<Box<dyn Future<Output = ()> + Send>>) + Send + Sync>,
+
+    /// Function called in order to notify of something.
+    pub log_callback: Arc<dyn LogCallback + Send + Sync>,
+
+    /// Consensus service of the chain. Used to follow the blocks of the chain.
+    pub consensus_service: Arc<consensus_service::ConsensusService>,
+
+    /// Access to the network, and index of the chain to use to gossip transactions from the point
+    /// of view of the network service.
+    pub network_service: (Arc<network_service::NetworkService>, usize),
+
+    /// Receiver for events coming from the network, as returned by
+    /// [`network_service::NetworkService::new`]. Used to receive the transactions gossiped by
+    /// the peers.
+    pub network_events_receiver: stream::BoxStream<'static, network_service::Event>,
+
+    /// Database to access blocks.
+    pub database: Arc<database_thread::DatabaseThread>,
+
+    /// Number of bytes used to encode the block number in headers.
+    pub block_number_bytes: usize,
+
+    /// Receiving side of the channel passed to the consensus service through
+    /// [`consensus_service::Config::block_author_transactions`].
+    pub block_author_transactions:
+        async_channel::Receiver<consensus_service::BlockAuthorTransactionsRequest>,
+
+    /// Maximum number of pending transactions allowed in the service.
+    ///
+    /// Any extra transaction will lead to [`DropReason::MaxPendingTransactionsReached`].
+    pub max_pending_transactions: NonZeroU32,
+
+    /// Maximum number of transaction validations that can be performed in parallel.
+    pub max_concurrent_validations: NonZeroU32,
+}
+
+/// See [the module-level documentation](..).
+pub struct TransactionsService {
+    /// Sending messages to the background task.
+    to_background: async_channel::Sender<ToBackground>,
+}
+
+impl TransactionsService {
+    /// Builds a new service.
+    pub async fn new(config: Config) -> Arc<Self> {
+        let (to_background, from_foreground) = async_channel::bounded(8);
+        let (validations_results_tx, validations_results_rx) = async_channel::unbounded();
+        let (broadcasts_results_tx, broadcasts_results_rx) = async_channel::unbounded();
+
+        let background = Background {
+            tasks_executor: config.tasks_executor.clone(),
+            log_callback: config.log_callback,
+            consensus_service: config.consensus_service,
+            network_service: config.network_service.0,
+            network_chain_index: config.network_service.1,
+            network_events_receiver: config.network_events_receiver,
+            database: config.database,
+            block_number_bytes: config.block_number_bytes,
+            runtime_caches: Arc::new(Mutex::new(lru::LruCache::new(
+                NonZeroUsize::new(2).unwrap(),
+            ))),
+            from_foreground,
+            block_author_transactions: config.block_author_transactions,
+            pool: pool::Pool::new(pool::Config {
+                capacity: 0,
+                finalized_block_height: 0,
+                randomness_seed: rand::random(),
+            }),
+            next_local_id: 0,
+            max_pending_transactions: usize::try_from(config.max_pending_transactions.get())
+                .unwrap_or(usize::max_value()),
+            max_concurrent_validations: usize::try_from(config.max_concurrent_validations.get())
+                .unwrap_or(usize::max_value()),
+            num_validations_in_progress: 0,
+            finalized_block: ([0; 32], 0, Vec::new()),
+            non_finalized_blocks: HashMap::new(),
+            best_chain: Vec::new(),
+            validations_results_tx,
+            validations_results_rx,
+            broadcasts_results_tx,
+            broadcasts_results_rx,
+        };
+
+        (config.tasks_executor)(Box::pin(background.run()));
+
+        Arc::new(TransactionsService { to_background })
+    }
+
+    /// Adds a transaction to the service. The service will try to send it out as soon as
+    /// possible.
+    ///
+    /// Must pass as parameter the SCALE-encoded transaction.
+    ///
+    /// The return value of this method is a channel which will receive updates on the state
+    /// of the transaction. The channel is closed when no new update is expected or if it becomes
+    /// full.
+    ///
+    /// > **Note**: Dropping the value returned does not cancel sending out the transaction.
+    ///
+    /// If this exact same transaction has already been submitted before and is still pending, the
+    /// transaction isn't added a second time. Instead, the channel is attached to the
+    /// already-existing transaction.
+    pub async fn submit_and_watch_transaction(
+        &self,
+        transaction_bytes: Vec<u8>,
+        channel_size: usize,
+    ) -> async_channel::Receiver<TransactionStatus> {
+        let (updates_report, rx) = async_channel::bounded(channel_size);
+
+        self.to_background
+            .send(ToBackground::SubmitTransaction {
+                transaction_bytes,
+                updates_report: Some(updates_report),
+            })
+            .await
+            .unwrap();
+
+        rx
+    }
+
+    /// Similar to [`TransactionsService::submit_and_watch_transaction`], but doesn't return any
+    /// channel.
+    pub async fn submit_transaction(&self, transaction_bytes: Vec<u8>) {
+        self.to_background
+            .send(ToBackground::SubmitTransaction {
+                transaction_bytes,
+                updates_report: None,
+            })
+            .await
+            .unwrap();
+    }
+}
+
+/// Update on the state of a transaction in the service.
+#[derive(Debug, Clone)]
+pub enum TransactionStatus {
+    /// Transaction has been broadcasted to the given peers.
+    Broadcast(Vec<PeerId>),
+
+    /// Transaction is now known to be valid. If it ever becomes invalid in the future, a
+    /// [`TransactionStatus::Dropped`] will be generated.
+    Validated,
+
+    /// The block in which a block is included has changed.
+    IncludedBlockUpdate {
+        /// If `Some`, the transaction is included in the block of the best chain with the given
+        /// hash and at the given index. If `None`, the transaction isn't present in the best
+        /// chain.
+        block_hash: Option<([u8; 32], u32)>,
+    },
+
+    /// Transaction has been removed from the pool.
+    ///
+    /// This is always the last message sent back by the channel reporting the status.
+    Dropped(DropReason),
+}
+
+/// See [`TransactionStatus::Dropped`].
+#[derive(Debug, Clone)]
+pub enum DropReason {
+    /// Transaction has been included in a finalized block.
+    ///
+    /// This is a success path.
+    Finalized { block_hash: [u8; 32], index: u32 },
+
+    /// Transaction has been dropped because the service has lost track of the chain of blocks.
+    GapInChain,
+
+    /// Transaction has been dropped because the maximum number of transactions in the pool has
+    /// been reached.
+    MaxPendingTransactionsReached,
+
+    /// Transaction has been dropped because it is invalid.
+    Invalid(validate::TransactionValidityError),
+
+    /// Transaction has been dropped because we have failed to validate it.
+    ValidateError(Arc<ValidateTransactionError>),
+}
+
+/// Failed to check the validity of a transaction.
+#[derive(Debug, derive_more::Display)]
+pub enum ValidateTransactionError {
+    /// Failed to obtain the runtime of the block to validate against.
+    #[display(fmt = "{_0}")]
+    Runtime(runtime_call::RuntimeCallError),
+    /// Error while accessing the storage of the block in the database.
+    #[display(fmt = "Failed to access the block storage: {_0}")]
+    StorageAccess(full_sqlite::StorageAccessError),
+    /// The database contains entries that can't be decoded.
+    #[display(fmt = "Corrupted database")]
+    CorruptedDatabase,
+    /// Error during the validation runtime call.
+    #[display(fmt = "{_0}")]
+    Validation(validate::Error),
+}
+
+/// Message sent from the foreground service to the background.
+enum ToBackground {
+    SubmitTransaction {
+        transaction_bytes: Vec<u8>,
+        updates_report: Option<async_channel::Sender<TransactionStatus>>,
+    },
+}
+
+/// Outcome of a validation performed in a separate task.
+struct ValidationResult {
+    transaction_id: pool::TransactionId,
+    /// Must match [`PendingTransaction::local_id`], otherwise the result is obsolete.
+    local_id: u64,
+    block_height: u64,
+    result: Result<
+        Result<validate::ValidTransaction, validate::TransactionValidityError>,
+        ValidateTransactionError,
+    >,
+}
+
+/// Outcome of a gossiping performed in a separate task.
+struct BroadcastResult {
+    transaction_id: pool::TransactionId,
+    /// Must match [`PendingTransaction::local_id`], otherwise the result is obsolete.
+    local_id: u64,
+    peers: Vec<PeerId>,
+}
+
+/// Entry in [`Background::pool`].
+struct PendingTransaction {
+    /// Identifier of the transaction that is never re-used by the service, contrary to
+    /// [`pool::TransactionId`].
+    local_id: u64,
+
+    /// List of channels that should receive updates about the state of this transaction.
+    watchers: Vec<async_channel::Sender<TransactionStatus>>,
+
+    /// If `Some`, the transaction is included in the block of the best chain with the given
+    /// hash and at the given index.
+    included_block: Option<([u8; 32], u32)>,
+
+    /// `true` if a validation of this transaction is currently in progress.
+    validation_in_progress: bool,
+
+    /// `true` if [`TransactionStatus::Validated`] has already been sent.
+    validated_reported: bool,
+
+    /// `true` if the transaction has already been sent out to the network.
+    broadcasted: bool,
+}
+
+impl PendingTransaction {
+    /// Sends the given status update to all the watchers. Watchers whose channel is full or
+    /// closed are removed.
+    fn update_status(&mut self, status: TransactionStatus) {
+        self.watchers
+            .retain(|watcher| watcher.try_send(status.clone()).is_ok());
+    }
+}
+
+struct Background {
+    /// See [`Config::tasks_executor`].
+    tasks_executor: Arc<dyn Fn(Pin<Box<dyn Future<Output = ()> + Send>>) + Send + Sync>,
+
+    /// See [`Config::log_callback`].
+    log_callback: Arc<dyn LogCallback + Send + Sync>,
+
+    /// See [`Config::consensus_service`].
+    consensus_service: Arc<consensus_service::ConsensusService>,
+
+    /// See [`Config::network_service`].
+    network_service: Arc<network_service::NetworkService>,
+
+    /// See [`Config::network_service`].
+    network_chain_index: usize,
+
+    /// See [`Config::network_events_receiver`].
+    network_events_receiver: stream::BoxStream<'static, network_service::Event>,
+
+    /// See [`Config::database`].
+    database: Arc<database_thread::DatabaseThread>,
+
+    /// See [`Config::block_number_bytes`].
+    block_number_bytes: usize,
+
+    /// Runtimes that have been compiled in the past, used to validate transactions.
+    runtime_caches: Arc<Mutex<runtime_call::RuntimeCaches>>,
+
+    /// Receives messages from the [`TransactionsService`].
+    from_foreground: async_channel::Receiver<ToBackground>,
+
+    /// See [`Config::block_author_transactions`].
+    block_author_transactions:
+        async_channel::Receiver<consensus_service::BlockAuthorTransactionsRequest>,
+
+    /// List of pending transactions. Only contains the transactions that have been submitted
+    /// locally or received from the network. The transactions of the blocks that aren't in this
+    /// list are ignored.
+    ///
+    /// The best block height of the pool always corresponds to the height of the last block
+    /// of [`Background::best_chain`].
+    pool: pool::Pool<PendingTransaction>,
+
+    /// Value to use for the next [`PendingTransaction::local_id`].
+    next_local_id: u64,
+
+    /// See [`Config::max_pending_transactions`].
+    max_pending_transactions: usize,
+
+    /// See [`Config::max_concurrent_validations`].
+    max_concurrent_validations: usize,
+
+    /// Number of validations that have been started and whose result hasn't been received yet.
+    num_validations_in_progress: usize,
+
+    /// Hash, height, and SCALE-encoded header of the current finalized block.
+    finalized_block: ([u8; 32], u64, Vec<u8>),
+
+    /// List of all the non-finalized blocks that descend from the finalized block, indexed by
+    /// their hash.
+    non_finalized_blocks: HashMap<[u8; 32], Block>,
+
+    /// Hashes of the blocks of the best chain, from the child of the finalized block to the
+    /// best block.
+    best_chain: Vec<[u8; 32]>,
+
+    /// Sending side of [`Background::validations_results_rx`], cloned for each validation.
+    validations_results_tx: async_channel::Sender<ValidationResult>,
+
+    /// Receives the results of the validations that have been started.
+    validations_results_rx: async_channel::Receiver<ValidationResult>,
+
+    /// Sending side of [`Background::broadcasts_results_rx`], cloned for each gossiping.
+    broadcasts_results_tx: async_channel::Sender<BroadcastResult>,
+
+    /// Receives the results of the transactions gossiping that have been started.
+    broadcasts_results_rx: async_channel::Receiver<BroadcastResult>,
+}
+
+/// Entry in [`Background::non_finalized_blocks`].
+struct Block {
+    parent_hash: [u8; 32],
+    height: u64,
+    scale_encoded_header: Vec<u8>,
+}
+
+impl Background {
+    async fn run(mut self) {
+        // Receiver of the notifications about the blocks of the chain, or `None` if we aren't
+        // subscribed.
+        let mut new_blocks: Option<async_channel::Receiver<consensus_service::Notification>> = None;
+
+        // Subscription to the blocks of the chain currently in progress. The subscription is
+        // performed concurrently with processing the other events, as the consensus service
+        // might be waiting for us to answer a block authoring request.
+        let mut subscribe_all: Option<
+            Pin<Box<dyn Future<Output = consensus_service::SubscribeAll> + Send>>,
+        > = None;
+
+        loop {
+            // Subscribe to the blocks of the chain. This is done again whenever the subscription
+            // is lost.
+            if new_blocks.is_none() && subscribe_all.is_none() {
+                let consensus_service = self.consensus_service.clone();
+                subscribe_all = Some(Box::pin(async move {
+                    consensus_service.subscribe_all(32).await
+                }));
+            }
+
+            enum WhatHappened {
+                Subscribed(consensus_service::SubscribeAll),
+                SubscriptionDead,
+                Notification(consensus_service::Notification),
+                Foreground(ToBackground),
+                NetworkEvent(network_service::Event),
+                BlockAuthorRequest(consensus_service::BlockAuthorTransactionsRequest),
+                ValidationResult(ValidationResult),
+                BroadcastResult(BroadcastResult),
+                ForegroundClosed,
+            }
+
+            let outcome: WhatHappened = {
+                let next_block = async {
+                    if let Some(new_blocks) = &new_blocks {
+                        match new_blocks.recv().await {
+                            Ok(n) => WhatHappened::Notification(n),
+                            Err(_) => WhatHappened::SubscriptionDead,
+                        }
+                    } else if let Some(subscribe_all) = &mut subscribe_all {
+                        WhatHappened::Subscribed(subscribe_all.as_mut().await)
+                    } else {
+                        unreachable!()
+                    }
+                };
+
+                // The service keeps running as long as either the frontend or the consensus
+                // service is alive. In particular, the consensus service might be waiting for an
+                // answer to a block authoring request even if no transaction can be submitted
+                // anymore.
+                let message = async {
+                    match self.from_foreground.recv().await {
+                        Ok(msg) => WhatHappened::Foreground(msg),
+                        Err(_) if self.block_author_transactions.is_closed() => {
+                            WhatHappened::ForegroundClosed
+                        }
+                        Err(_) => smol::future::pending().await,
+                    }
+                };
+
+                let network_event = async {
+                    // We expect the network events channel to never shut down.
+                    WhatHappened::NetworkEvent(self.network_events_receiver.next().await.unwrap())
+                };
+
+                let block_author_request = async {
+                    match self.block_author_transactions.recv().await {
+                        Ok(rq) => WhatHappened::BlockAuthorRequest(rq),
+                        Err(_) if self.from_foreground.is_closed() => {
+                            WhatHappened::ForegroundClosed
+                        }
+                        Err(_) => smol::future::pending().await,
+                    }
+                };
+
+                let validation_result = async {
+                    // `self` holds a sender, thus the channel can never be closed.
+                    WhatHappened::ValidationResult(
+                        self.validations_results_rx.recv().await.unwrap(),
+                    )
+                };
+
+                let broadcast_result = async {
+                    // `self` holds a sender, thus the channel can never be closed.
+                    WhatHappened::BroadcastResult(self.broadcasts_results_rx.recv().await.unwrap())
+                };
+
+                next_block
+                    .or(message)
+                    .or(network_event)
+                    .or(block_author_request)
+                    .or(validation_result)
+                    .or(broadcast_result)
+                    .await
+            };
+
+            match outcome {
+                WhatHappened::ForegroundClosed => return,
+                WhatHappened::Subscribed(subscription) => {
+                    subscribe_all = None;
+                    new_blocks = Some(subscription.new_blocks);
+
+                    // Since the chain of blocks might have progressed while we weren't subscribed, we
+                    // don't know whether the pending transactions have been included. All the pending
+                    // transactions are thus dropped.
+                    self.reset(
+                        subscription.finalized_block_hash,
+                        subscription.finalized_block_scale_encoded_header,
+                    );
+
+                    for block in subscription.non_finalized_blocks_ancestry_order {
+                        self.on_new_block(block).await;
+                    }
+                }
+                WhatHappened::SubscriptionDead => {
+                    self.log_callback.log(
+                        LogLevel::Warn,
+                        "transactions-service-blocks-subscription-lost".to_string(),
+                    );
+                    new_blocks = None;
+                }
+                WhatHappened::Notification(consensus_service::Notification::Block(block)) => {
+                    self.on_new_block(block).await;
+                }
+                WhatHappened::Notification(consensus_service::Notification::Finalized {
+                    hash,
+                    best_block_hash,
+                }) => {
+                    self.set_best_block(best_block_hash).await;
+                    self.on_finalized(hash);
+                }
+                WhatHappened::Foreground(ToBackground::SubmitTransaction {
+                    transaction_bytes,
+                    updates_report,
+                }) => {
+                    self.on_submit(transaction_bytes, updates_report);
+                }
+                WhatHappened::NetworkEvent(network_service::Event::Transactions {
+                    chain_index,
+                    transactions,
+                    ..
+                }) if chain_index == self.network_chain_index => {
+                    // Transactions received from the network are added to the pool similarly to
+                    // the ones submitted locally, except that nobody watches them.
+                    for transaction_bytes in transactions.decode() {
+                        self.on_submit(transaction_bytes.to_vec(), None);
+                    }
+                }
+                WhatHappened::NetworkEvent(_) => {}
+                WhatHappened::BlockAuthorRequest(request) => {
+                    // The transactions are returned by decreasing priority, which is the
+                    // order in which they should be included.
+                    let transactions = self
+                        .pool
+                        .best_block_includable_transactions()
+                        .map(|(id, _)| self.pool.scale_encoding(id).unwrap().to_vec())
+                        .collect::<Vec<_>>();
+                    self.log_callback.log(
+                        LogLevel::Debug,
+                        format!(
+                            "transactions-service-block-author-request; parent_hash={}; \
+                            best_hash={}; num_transactions={}",
+                            HashDisplay(&request.parent_hash),
+                            HashDisplay(&self.best_block_hash()),
+                            transactions.len()
+                        ),
+                    );
+                    let _ = request.result_tx.send(transactions);
+                }
+                WhatHappened::ValidationResult(result) => self.on_validation_result(result),
+                WhatHappened::BroadcastResult(result) => {
+                    // The transaction might have been removed from the pool in the meanwhile.
+                    if self.is_current(result.transaction_id, result.local_id) {
+                        self.pool[result.transaction_id]
+                            .update_status(TransactionStatus::Broadcast(result.peers));
+                    }
+                }
+            }
+
+            self.start_validations();
+        }
+    }
+
+    /// Drops all the transactions of the pool and resets the chain to the given finalized block.
+    fn reset(&mut self, finalized_block_hash: [u8; 32], finalized_block_header: Vec<u8>) {
+        let finalized_block_height =
+            header::decode(&finalized_block_header, self.block_number_bytes)
+                .unwrap()
+                .number;
+
+        let mut pool = mem::replace(
+            &mut self.pool,
+            pool::Pool::new(pool::Config {
+                capacity: self.max_pending_transactions,
+                finalized_block_height,
+                randomness_seed: rand::random(),
+            }),
+        );
+
+        let transaction_ids = pool.iter().map(|(id, _)| id).collect::<Vec<_>>();
+        for transaction_id in transaction_ids {
+            let mut tx = pool.remove(transaction_id);
+            tx.update_status(TransactionStatus::Dropped(DropReason::GapInChain));
+        }
+
+        self.finalized_block = (
+            finalized_block_hash,
+            finalized_block_height,
+            finalized_block_header,
+        );
+        self.non_finalized_blocks.clear();
+        self.best_chain.clear();
+    }
+
+    /// Returns `true` if the given transaction is still in the pool and its local identifier
+    /// matches the given one.
+    fn is_current(&self, transaction_id: pool::TransactionId, local_id: u64) -> bool {
+        self.pool.scale_encoding(transaction_id).is_some()
+            && self.pool[transaction_id].local_id == local_id
+    }
+
+    /// Returns the hash of the current best block.
+    fn best_block_hash(&self) -> [u8; 32] {
+        self.best_chain
+            .last()
+            .copied()
+            .unwrap_or(self.finalized_block.0)
+    }
+
+    /// Adds a new transaction to the pool, or attaches the watcher to an existing identical
+    /// transaction.
+    fn on_submit(
+        &mut self,
+        transaction_bytes: Vec<u8>,
+        updates_report: Option<async_channel::Sender<TransactionStatus>>,
+    ) {
+        // If an identical transaction is already pending, attach the watcher to it.
+        let existing = self
+            .pool
+            .transactions_by_scale_encoding(&transaction_bytes)
+            .find(|id| self.pool.included_block_height(*id).is_none());
+        if let Some(existing) = existing {
+            if let Some(updates_report) = updates_report {
+                let tx = &mut self.pool[existing];
+                if tx.validated_reported {
+                    let _ = updates_report.try_send(TransactionStatus::Validated);
+                }
+                tx.watchers.push(updates_report);
+            }
+            return;
+        }
+
+        if self.pool.len() >= self.max_pending_transactions {
+            if let Some(updates_report) = updates_report {
+                let _ = updates_report.try_send(TransactionStatus::Dropped(
+                    DropReason::MaxPendingTransactionsReached,
+                ));
+            }
+            return;
+        }
+
+        self.log_callback.log(
+            LogLevel::Debug,
+            format!(
+                "transactions-service-new-transaction; hash={}",
+                HashDisplay(&blake2_hash(&transaction_bytes))
+            ),
+        );
+
+        let local_id = self.next_local_id;
+        self.next_local_id += 1;
+        self.pool.add_unvalidated(
+            transaction_bytes,
+            PendingTransaction {
+                local_id,
+                watchers: updates_report.into_iter().collect(),
+                included_block: None,
+                validation_in_progress: false,
+                validated_reported: false,
+                broadcasted: false,
+            },
+        );
+    }
+
+    /// Inserts a new block in [`Background::non_finalized_blocks`] and updates the best chain if
+    /// necessary.
+    async fn on_new_block(&mut self, block: consensus_service::BlockNotification) {
+        let height = header::decode(&block.scale_encoded_header, self.block_number_bytes)
+            .unwrap()
+            .number;
+        self.non_finalized_blocks.insert(
+            block.block_hash,
+            Block {
+                parent_hash: block.parent_hash,
+                height,
+                scale_encoded_header: block.scale_encoded_header,
+            },
+        );
+
+        if block.is_new_best {
+            self.set_best_block(block.block_hash).await;
+        }
+    }
+
+    /// Updates [`Background::best_chain`] and the pool so that the given block is the best
+    /// block.
+    async fn set_best_block(&mut self, new_best_block_hash: [u8; 32]) {
+        // Build the list of blocks between the finalized block and the new best block.
+        let new_best_chain = {
+            let mut chain = Vec::new();
+            let mut iter = new_best_block_hash;
+            while iter != self.finalized_block.0 {
+                chain.push(iter);
+                iter = self.non_finalized_blocks.get(&iter).unwrap().parent_hash;
+            }
+            chain.reverse();
+            chain
+        };
+
+        // Number of blocks that the old and new best chains have in common.
+        let common_len = self
+            .best_chain
+            .iter()
+            .zip(new_best_chain.iter())
+            .take_while(|(a, b)| a == b)
+            .count();
+
+        // Retract the blocks that are no longer part of the best chain.
+        let num_to_retract = u64::try_from(self.best_chain.len() - common_len).unwrap();
+        let retracted = self
+            .pool
+            .retract_blocks(num_to_retract)
+            .map(|(id, _)| id)
+            .collect::<Vec<_>>();
+        for transaction_id in retracted {
+            let tx = &mut self.pool[transaction_id];
+            tx.included_block = None;
+            tx.update_status(TransactionStatus::IncludedBlockUpdate { block_hash: None });
+        }
+        self.best_chain.truncate(common_len);
+
+        // Append the new blocks.
+        for block_hash in new_best_chain.into_iter().skip(common_len) {
+            self.pool.append_empty_block();
+            self.best_chain.push(block_hash);
+
+            let body = self
+                .database
+                .with_database(move |database| {
+                    database
+                        .block_extrinsics(&block_hash)
+                        .map(|body| body.map(|body| body.collect::<Vec<_>>()))
+                })
+                .await;
+            let body = match body {
+                Ok(Some(body)) => body,
+                Ok(None) | Err(_) => {
+                    self.log_callback.log(
+                        LogLevel::Warn,
+                        format!(
+                            "transactions-service-block-body-unavailable; hash={}",
+                            HashDisplay(&block_hash)
+                        ),
+                    );
+                    continue;
+                }
+            };
+
+            for (index, extrinsic) in body.iter().enumerate() {
+                let index = u32::try_from(index).unwrap();
+                if let pool::AppendBlockTransaction::NonIncludedUpdated { user_data, .. } = self
+                    .pool
+                    .best_block_add_transaction_by_scale_encoding(extrinsic)
+                {
+                    user_data.included_block = Some((block_hash, index));
+                    user_data.update_status(TransactionStatus::IncludedBlockUpdate {
+                        block_hash: Some((block_hash, index)),
+                    });
+                }
+            }
+        }
+    }
+
+    /// Removes from the pool the transactions that have been finalized and prunes the blocks that
+    /// are no longer relevant.
+    ///
+    /// Must be called after the best block has been updated to a descendant of the new finalized
+    /// block.
+    fn on_finalized(&mut self, new_finalized_hash: [u8; 32]) {
+        let new_finalized = self
+            .non_finalized_blocks
+            .remove(&new_finalized_hash)
+            .unwrap();
+        let num_finalized = usize::try_from(new_finalized.height - self.finalized_block.1).unwrap();
+        debug_assert_eq!(self.best_chain[num_finalized - 1], new_finalized_hash);
+
+        for (_, mut tx) in self.pool.remove_included(new_finalized.height) {
+            let (block_hash, index) = tx.included_block.unwrap();
+            tx.update_status(TransactionStatus::Dropped(DropReason::Finalized {
+                block_hash,
+                index,
+            }));
+        }
+
+        self.best_chain.drain(..num_finalized);
+        self.finalized_block = (
+            new_finalized_hash,
+            new_finalized.height,
+            new_finalized.scale_encoded_header,
+        );
+
+        // Only keep the blocks that descend from the new finalized block. Blocks are iterated in
+        // increasing height, so that parents are always checked before their children.
+        let mut blocks = self.non_finalized_blocks.drain().collect::<Vec<_>>();
+        blocks.sort_by_key(|(_, block)| block.height);
+        let mut kept = HashSet::new();
+        for (hash, block) in blocks {
+            if block.parent_hash == new_finalized_hash || kept.contains(&block.parent_hash) {
+                kept.insert(hash);
+                self.non_finalized_blocks.insert(hash, block);
+            }
+        }
+    }
+
+    /// Starts validating the transactions that aren't validated yet, within the limit of
+    /// [`Background::max_concurrent_validations`].
+    fn start_validations(&mut self) {
+        let to_validate = self
+            .pool
+            .unvalidated_transactions()
+            .filter(|(_, tx, _)| !tx.validation_in_progress)
+            .map(|(id, _, height)| (id, height))
+            .take(
+                self.max_concurrent_validations
+                    .saturating_sub(self.num_validations_in_progress),
+            )
+            .collect::<Vec<_>>();
+
+        for (transaction_id, block_height) in to_validate {
+            let (block_hash, block_header) = if block_height == self.finalized_block.1 {
+                (self.finalized_block.0, self.finalized_block.2.clone())
+            } else {
+                let hash = self.best_chain
+                    [usize::try_from(block_height - self.finalized_block.1 - 1).unwrap()];
+                (
+                    hash,
+                    self.non_finalized_blocks[&hash]
+                        .scale_encoded_header
+                        .clone(),
+                )
+            };
+
+            let source = if self.pool.included_block_height(transaction_id).is_some() {
+                validate::TransactionSource::InBlock
+            } else {
+                validate::TransactionSource::External
+            };
+
+            let transaction_bytes = self.pool.scale_encoding(transaction_id).unwrap().to_vec();
+            let tx = &mut self.pool[transaction_id];
+            tx.validation_in_progress = true;
+            let local_id = tx.local_id;
+            self.num_validations_in_progress += 1;
+
+            self.log_callback.log(
+                LogLevel::Debug,
+                format!(
+                    "transactions-service-validation-start; transaction={}; block={}; \
+                    block_height={}",
+                    HashDisplay(&blake2_hash(&transaction_bytes)),
+                    HashDisplay(&block_hash),
+                    block_height
+                ),
+            );
+
+            (self.tasks_executor)(Box::pin({
+                let database = self.database.clone();
+                let runtime_caches = self.runtime_caches.clone();
+                let block_number_bytes = self.block_number_bytes;
+                let validations_results_tx = self.validations_results_tx.clone();
+                async move {
+                    let result = validate_transaction(
+                        &database,
+                        &runtime_caches,
+                        block_hash,
+                        &block_header,
+                        block_number_bytes,
+                        &transaction_bytes,
+                        source,
+                    )
+                    .await;
+                    let _ = validations_results_tx
+                        .send(ValidationResult {
+                            transaction_id,
+                            local_id,
+                            block_height,
+                            result,
+                        })
+                        .await;
+                }
+            }));
+        }
+    }
+
+    /// Called when a validation started with [`Background::start_validations`] has finished.
+    fn on_validation_result(&mut self, result: ValidationResult) {
+        self.num_validations_in_progress -= 1;
+
+        // The transaction might have been removed from the pool in the meanwhile.
+        if !self.is_current(result.transaction_id, result.local_id) {
+            return;
+        }
+        let tx = &mut self.pool[result.transaction_id];
+        tx.validation_in_progress = false;
+
+        match result.result {
+            Ok(Ok(validity)) => {
+                if !tx.validated_reported {
+                    tx.validated_reported = true;
+                    tx.update_status(TransactionStatus::Validated);
+                }
+
+                // Gossip the transaction to the peers the first time it is validated.
+                // TODO: send again to peers that have connected since
+                if validity.propagate && !tx.broadcasted {
+                    tx.broadcasted = true;
+                    (self.tasks_executor)(Box::pin({
+                        let network_service = self.network_service.clone();
+                        let network_chain_index = self.network_chain_index;
+                        let transaction_bytes = self
+                            .pool
+                            .scale_encoding(result.transaction_id)
+                            .unwrap()
+                            .to_vec();
+                        let broadcasts_results_tx = self.broadcasts_results_tx.clone();
+                        let transaction_id = result.transaction_id;
+                        let local_id = result.local_id;
+                        async move {
+                            let peers = network_service
+                                .announce_transaction(network_chain_index, transaction_bytes)
+                                .await;
+                            let _ = broadcasts_results_tx
+                                .send(BroadcastResult {
+                                    transaction_id,
+                                    local_id,
+                                    peers,
+                                })
+                                .await;
+                        }
+                    }));
+                }
+
+                self.pool.set_validation_result(
+                    result.transaction_id,
+                    result.block_height,
+                    validity,
+                );
+            }
+            Ok(Err(invalid)) => {
+                self.log_callback.log(
+                    LogLevel::Debug,
+                    format!(
+                        "transactions-service-transaction-invalid; transaction={}; error={}",
+                        HashDisplay(&blake2_hash(
+                            self.pool.scale_encoding(result.transaction_id).unwrap()
+                        )),
+                        invalid
+                    ),
+                );
+                let mut tx = self.pool.remove(result.transaction_id);
+                tx.update_status(TransactionStatus::Dropped(DropReason::Invalid(invalid)));
+            }
+            Err(error) => {
+                self.log_callback.log(
+                    LogLevel::Warn,
+                    format!(
+                        "transactions-service-validation-error; transaction={}; error={}",
+                        HashDisplay(&blake2_hash(
+                            self.pool.scale_encoding(result.transaction_id).unwrap()
+                        )),
+                        error
+                    ),
+                );
+                let mut tx = self.pool.remove(result.transaction_id);
+                tx.update_status(TransactionStatus::Dropped(DropReason::ValidateError(
+                    Arc::new(error),
+                )));
+            }
+        }
+    }
+}
+
+/// Validates the given transaction against the storage of the given block.
+async fn validate_transaction(
+    database: &database_thread::DatabaseThread,
+    runtime_caches: &Mutex<runtime_call::RuntimeCaches>,
+    block_hash: [u8; 32],
+    block_scale_encoded_header: &[u8],
+    block_number_bytes: usize,
+    scale_encoded_transaction: &[u8],
+    source: validate::TransactionSource,
+) -> Result<
+    Result<validate::ValidTransaction, validate::TransactionValidityError>,
+    ValidateTransactionError,
+> {
+    let (cache_key, runtime) = runtime_call::runtime_of_block(database, runtime_caches, block_hash)
+        .await
+        .map_err(ValidateTransactionError::Runtime)?;
+
+    let mut validation = validate::validate_transaction(validate::Config {
+        runtime,
+        scale_encoded_header: block_scale_encoded_header,
+        block_number_bytes,
+        scale_encoded_transaction: iter::once(scale_encoded_transaction),
+        source,
+        max_log_level: 0,
+    });
+
+    loop {
+        match validation {
+            validate::Query::Finished {
+                result,
+                virtual_machine,
+            } => {
+                runtime_caches.lock().await.put(cache_key, virtual_machine);
+                break result.map_err(ValidateTransactionError::Validation);
+            }
+            validate::Query::StorageGet(req) => {
+                let parent_paths = req.child_trie().map(|child_trie| {
+                    trie::bytes_to_nibbles(b":child_storage:default:".iter().copied())
+                        .chain(trie::bytes_to_nibbles(child_trie.as_ref().iter().copied()))
+                        .map(u8::from)
+                        .collect::<Vec<_>>()
+                });
+                let key = trie::bytes_to_nibbles(req.key().as_ref().iter().copied())
+                    .map(u8::from)
+                    .collect::<Vec<_>>();
+                let value = database
+                    .with_database(move |db| {
+                        db.block_storage_get(
+                            &block_hash,
+                            parent_paths.into_iter().map(|p| p.into_iter()),
+                            key.iter().copied(),
+                        )
+                    })
+                    .await;
+                let value = match value {
+                    Ok(v) => v,
+                    Err(error) => {
+                        runtime_caches
+                            .lock()
+                            .await
+                            .put(cache_key, validate::Query::StorageGet(req).into_prototype());
+                        break Err(ValidateTransactionError::StorageAccess(error));
+                    }
+                };
+                let Some(value) = value
+                    .as_ref()
+                    .map(|(val, vers)| {
+                        validate::TrieEntryVersion::try_from(*vers)
+                            .map(|vers| (iter::once(&val[..]), vers))
+                    })
+                    .transpose()
+                    .ok()
+                else {
+                    runtime_caches
+                        .lock()
+                        .await
+                        .put(cache_key, validate::Query::StorageGet(req).into_prototype());
+                    break Err(ValidateTransactionError::CorruptedDatabase);
+                };
+                validation = req.inject_value(value);
+            }
+            validate::Query::ClosestDescendantMerkleValue(req) => {
+                let parent_paths = req.child_trie().map(|child_trie| {
+                    trie::bytes_to_nibbles(b":child_storage:default:".iter().copied())
+                        .chain(trie::bytes_to_nibbles(child_trie.as_ref().iter().copied()))
+                        .map(u8::from)
+                        .collect::<Vec<_>>()
+                });
+                let key_nibbles = req.key().map(u8::from).collect::<Vec<_>>();
+
+                let merkle_value = database
+                    .with_database(move |db| {
+                        db.block_storage_closest_descendant_merkle_value(
+                            &block_hash,
+                            parent_paths.into_iter().map(|p| p.into_iter()),
+                            key_nibbles.iter().copied(),
+                        )
+                    })
+                    .await;
+                let merkle_value = match merkle_value {
+                    Ok(v) => v,
+                    Err(error) => {
+                        runtime_caches.lock().await.put(
+                            cache_key,
+                            validate::Query::ClosestDescendantMerkleValue(req).into_prototype(),
+                        );
+                        break Err(ValidateTransactionError::StorageAccess(error));
+                    }
+                };
+                validation = req.inject_merkle_value(merkle_value.as_ref().map(|v| &v[..]));
+            }
+            validate::Query::NextKey(req) => {
+                let parent_paths = req.child_trie().map(|child_trie| {
+                    trie::bytes_to_nibbles(b":child_storage:default:".iter().copied())
+                        .chain(trie::bytes_to_nibbles(child_trie.as_ref().iter().copied()))
+                        .map(u8::from)
+                        .collect::<Vec<_>>()
+                });
+                let key_nibbles = req
+                    .key()
+                    .map(u8::from)
+                    .chain(if req.or_equal() { None } else { Some(0u8) })
+                    .collect::<Vec<_>>();
+                let prefix_nibbles = req.prefix().map(u8::from).collect::<Vec<_>>();
+                let branch_nodes = req.branch_nodes();
+
+                let next_key = database
+                    .with_database(move |db| {
+                        db.block_storage_next_key(
+                            &block_hash,
+                            parent_paths.into_iter().map(|p| p.into_iter()),
+                            key_nibbles.iter().copied(),
+                            prefix_nibbles.iter().copied(),
+                            branch_nodes,
+                        )
+                    })
+                    .await;
+                let next_key = match next_key {
+                    Ok(v) => v,
+                    Err(error) => {
+                        runtime_caches
+                            .lock()
+                            .await
+                            .put(cache_key, validate::Query::NextKey(req).into_prototype());
+                        break Err(ValidateTransactionError::StorageAccess(error));
+                    }
+                };
+                validation = req.inject_key(next_key.map(|k| {
+                    k.into_iter()
+                        .map(|b| validate::Nibble::try_from(b).unwrap())
+                }));
+            }
+        }
+    }
+}
+
+/// Utility. Calculates the BLAKE2 hash of the given bytes.
+fn blake2_hash(bytes: &[u8]) -> [u8; 32] {
+    <[u8; 32]>::try_from(blake2_rfc::blake2b::blake2b(32, &[], bytes).as_bytes()).unwrap()
+}
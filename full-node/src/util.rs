@@ -65,3 +65,104 @@ pub fn truncated_str<'a>(
 
     Iter(input, limit)
 }
+
+/// Encodes the given `usize` as a SCALE-compact number.
+pub fn encode_scale_compact_usize(value: usize) -> Vec<u8> {
+    if value < 1 << 6 {
+        vec![u8::try_from(value << 2).unwrap()]
+    } else if value < 1 << 14 {
+        u16::try_from((value << 2) | 0b01)
+            .unwrap()
+            .to_le_bytes()
+            .to_vec()
+    } else if value < 1 << 30 {
+        u32::try_from((value << 2) | 0b10)
+            .unwrap()
+            .to_le_bytes()
+            .to_vec()
+    } else {
+        let bytes = u64::try_from(value).unwrap().to_le_bytes();
+        let num_bytes = bytes.iter().rposition(|b| *b != 0).unwrap() + 1;
+        let mut out = Vec::with_capacity(1 + num_bytes);
+        out.push(u8::try_from(((num_bytes - 4) << 2) | 0b11).unwrap());
+        out.extend_from_slice(&bytes[..num_bytes]);
+        out
+    }
+}
+
+/// Decodes a SCALE-encoded `Option<Vec<u8>>`. Returns `Err` if the input is invalid.
+pub fn decode_scale_option_bytes(input: &[u8]) -> Result<Option<&[u8]>, ()> {
+    match input.split_first() {
+        Some((0, [])) => Ok(None),
+        Some((1, rest)) => {
+            let (length, rest) = decode_scale_compact_usize(rest)?;
+            if rest.len() != length {
+                return Err(());
+            }
+            Ok(Some(rest))
+        }
+        _ => Err(()),
+    }
+}
+
+/// Decodes a SCALE-encoded `Vec<u8>`. Returns `Err` if the input is invalid.
+pub fn decode_scale_bytes(input: &[u8]) -> Result<&[u8], ()> {
+    let (length, rest) = decode_scale_compact_usize(input)?;
+    if rest.len() != length {
+        return Err(());
+    }
+    Ok(rest)
+}
+
+/// Decodes a SCALE-encoded `Vec<Vec<u8>>`. Returns `Err` if the input is invalid.
+pub fn decode_scale_bytes_list(input: &[u8]) -> Result<Vec<&[u8]>, ()> {
+    let (num_elems, mut rest) = decode_scale_compact_usize(input)?;
+    let mut out = Vec::with_capacity(num_elems.min(rest.len()));
+    for _ in 0..num_elems {
+        let (length, after_length) = decode_scale_compact_usize(rest)?;
+        if after_length.len() < length {
+            return Err(());
+        }
+        out.push(&after_length[..length]);
+        rest = &after_length[length..];
+    }
+    if !rest.is_empty() {
+        return Err(());
+    }
+    Ok(out)
+}
+
+/// Decodes a SCALE-compact number at the start of the given slice. Returns the number and the
+/// rest of the slice.
+fn decode_scale_compact_usize(input: &[u8]) -> Result<(usize, &[u8]), ()> {
+    let (value, rest) = decode_scale_compact_u64(input)?;
+    Ok((usize::try_from(value).map_err(|_| ())?, rest))
+}
+
+/// Decodes a SCALE-compact `u64` at the start of the given slice. Returns the number and the
+/// rest of the slice.
+pub fn decode_scale_compact_u64(input: &[u8]) -> Result<(u64, &[u8]), ()> {
+    let first = *input.first().ok_or(())?;
+    let (value, num_bytes) = match first & 0b11 {
+        0b00 => (u64::from(first >> 2), 1),
+        0b01 => {
+            let bytes = <[u8; 2]>::try_from(input.get(..2).ok_or(())?).unwrap();
+            (u64::from(u16::from_le_bytes(bytes) >> 2), 2)
+        }
+        0b10 => {
+            let bytes = <[u8; 4]>::try_from(input.get(..4).ok_or(())?).unwrap();
+            (u64::from(u32::from_le_bytes(bytes) >> 2), 4)
+        }
+        _ => {
+            let num_bytes = usize::from(first >> 2) + 4;
+            if num_bytes > 8 {
+                return Err(());
+            }
+            let mut bytes = [0; 8];
+            bytes[..num_bytes].copy_from_slice(input.get(1..1 + num_bytes).ok_or(())?);
+            (u64::from_le_bytes(bytes), 1 + num_bytes)
+        }
+    };
+
+    Ok((value, &input[num_bytes..]))
+}
@@ -0,0 +1,513 @@
+// Smoldot
+// Copyright (C) 2023  Pierre Krieger
+// SPDX-License-Identifier: GPL-3.0-or-later WITH Classpath-exception-2.0
+
+// This program is free software: you can redistribute it and/or modify
+// it under the terms of the GNU General Public License as published by
+// the Free Software Foundation, either version 3 of the License, or
+// (at your option) any later version.
+
+// This program is distributed in the hope that it will be useful,
+// but WITHOUT ANY WARRANTY; without even the implied warranty of
+// MERCHANTABILITY or FITNESS FOR A PARTICULAR PURPOSE.  See the
+// GNU General Public License for more details.
+
+// You should have received a copy of the GNU General Public License
+// along with this program.  If not, see <http://www.gnu.org/licenses/>.
+
+//! Warp syncing of a database that only contains the genesis block.
+//!
+//! Rather than executing every block since the genesis, the node downloads a GrandPa warp sync
+//! proof from its peers in order to reach a recent finalized block, then downloads the entire
+//! storage of this block through state requests, and finally replaces the content of the
+//! database with this block. The consensus service can then continue syncing from there.
+//!
+//! Because the blocks between the genesis and the warp synced block are never downloaded, the
+//! database doesn't contain them.
+
+use crate::{database_thread, network_service, LogCallback, LogLevel, TimeSource};
+
+use futures_util::{stream, FutureExt as _, StreamExt as _};
+use smoldot::{
+    chain::chain_information,
+    database::full_sqlite,
+    executor,
+    informant::HashDisplay,
+    libp2p::PeerId,
+    network::protocol,
+    sync::{state_download, warp_sync},
+    trie::TrieEntryVersion,
+};
+use std::{borrow::Cow, cmp, future::Future, iter, pin::Pin, sync::Arc, time::Duration};
+
+/// Configuration for [`warp_sync`].
+pub struct Config {
+    /// Function called in order to notify of something.
+    pub log_callback: Arc<dyn LogCallback + Send + Sync>,
+
+    /// Source of the time used to wait between two failed requests.
+    pub time_source: Arc<dyn TimeSource + Send + Sync>,
+
+    /// Network service to download the warp sync proof and the storage from, and index of the
+    /// chain within this service.
+    ///
+    /// Must not be shared with anything else, as [`Config::network_events_receiver`] must be the
+    /// only receiver of events.
+    pub network_service: (Arc<network_service::NetworkService>, usize),
+
+    /// Receiver of events coming from the network service.
+    pub network_events_receiver: Pin<Box<dyn stream::Stream<Item = network_service::Event> + Send>>,
+
+    /// Database whose content is replaced at the end of the warp syncing. Must only contain the
+    /// genesis block.
+    pub database: Arc<database_thread::DatabaseThread>,
+
+    /// Chain information of the genesis block.
+    pub genesis_chain_information: chain_information::ValidChainInformation,
+
+    /// Number of bytes used to encode block numbers in headers.
+    pub block_number_bytes: usize,
+}
+
+/// Error potentially returned by [`warp_sync`].
+#[derive(Debug, derive_more::Display)]
+pub enum WarpSyncError {
+    /// The chain doesn't support warp syncing.
+    #[display(fmt = "Chain doesn't support warp syncing: {_0}")]
+    Unsupported(warp_sync::WarpSyncInitError),
+    /// The storage of the warp synced block contains both entries of version 0 and of version 1
+    /// of the trie, which the database doesn't support.
+    // TODO: support chains that are in the middle of a migration of their trie entries
+    #[display(fmt = "Storage of the warp synced block contains entries of mixed trie versions")]
+    MixedStateVersions,
+    /// Error while writing the warp synced block to the database.
+    #[display(fmt = "Failed to write the warp synced block to the database: {_0}")]
+    DatabaseAccess(full_sqlite::AccessError),
+}
+
+/// Warp syncs to a recent finalized block of the chain, downloads its storage, and resets the
+/// database to this block.
+///
+/// Only returns once finished or in case of unrecoverable error. Errors that concern a specific
+/// peer, such as an invalid response, lead to the same request being sent to a different peer.
+pub async fn warp_sync(mut config: Config) -> Result<(), WarpSyncError> {
+    let (network_service, network_chain_index) = config.network_service.clone();
+
+    let mut warp_sync = warp_sync::start_warp_sync::<PeerId, u64>(warp_sync::Config {
+        start_chain_information: config.genesis_chain_information,
+        block_number_bytes: config.block_number_bytes,
+        sources_capacity: 32,
+        requests_capacity: 16,
+        code_trie_node_hint: None,
+    })
+    .map_err(|(_, err)| WarpSyncError::Unsupported(err))?;
+
+    config
+        .log_callback
+        .log(LogLevel::Info, "warp-sync-started".to_string());
+
+    // Peers that are currently connected, and their corresponding source in the state machine.
+    let mut peers = hashbrown::HashMap::<PeerId, warp_sync::SourceId>::new();
+
+    // Requests that are in progress. Each request is identified by a unique number, as the
+    // request identifiers of the state machine can be reused after a source has been removed.
+    let mut next_request_number = 0u64;
+    let mut requests_in_progress = hashbrown::HashMap::<u64, warp_sync::RequestId>::new();
+    let mut requests_futures = stream::FuturesUnordered::<
+        Pin<Box<dyn Future<Output = (u64, RequestOutcome)> + Send>>,
+    >::new();
+
+    let success = 'warp_sync: loop {
+        // Verify what has been downloaded so far.
+        loop {
+            match warp_sync.process_one() {
+                warp_sync::ProcessOne::Idle(idle) => {
+                    warp_sync = idle;
+                    break;
+                }
+                warp_sync::ProcessOne::VerifyWarpSyncFragment(verify) => {
+                    let (next, error) = verify.verify(rand::random());
+                    if let Some(error) = error {
+                        config.log_callback.log(
+                            LogLevel::Warn,
+                            format!("warp-sync-fragment-verify-error; error={error}"),
+                        );
+                    }
+                    warp_sync = next;
+                }
+                warp_sync::ProcessOne::BuildRuntime(build) => {
+                    let (next, error) =
+                        build.build(executor::vm::ExecHint::CompileAheadOfTime, true);
+                    if let Some(error) = error {
+                        config.log_callback.log(
+                            LogLevel::Warn,
+                            format!("warp-sync-runtime-build-error; error={error}"),
+                        );
+                    }
+                    match next {
+                        warp_sync::WarpSync::InProgress(next) => warp_sync = next,
+                        warp_sync::WarpSync::Finished(success) => break 'warp_sync success,
+                    }
+                }
+                warp_sync::ProcessOne::BuildChainInformation(build) => {
+                    let (next, error) = build.build();
+                    if let Some(error) = error {
+                        config.log_callback.log(
+                            LogLevel::Warn,
+                            format!("warp-sync-chain-information-build-error; error={error}"),
+                        );
+                    }
+                    match next {
+                        warp_sync::WarpSync::InProgress(next) => warp_sync = next,
+                        warp_sync::WarpSync::Finished(success) => break 'warp_sync success,
+                    }
+                }
+            }
+        }
+
+        // Start the requests that the state machine would like to start. Adding a request to the
+        // state machine removes it from the list of desired requests.
+        loop {
+            let next_desired_request = warp_sync
+                .desired_requests()
+                .next()
+                .map(|(source_id, peer_id, rq)| (source_id, peer_id.clone(), rq));
+            let Some((source_id, peer_id, desired_request)) = next_desired_request else {
+                break;
+            };
+
+            let (request_detail, future) = match desired_request {
+                warp_sync::DesiredRequest::WarpSyncRequest { block_hash } => (
+                    warp_sync::RequestDetail::WarpSyncRequest { block_hash },
+                    network_service
+                        .clone()
+                        .grandpa_warp_sync_request(peer_id, network_chain_index, block_hash)
+                        .map(RequestOutcome::WarpSync)
+                        .boxed(),
+                ),
+                warp_sync::DesiredRequest::StorageGetMerkleProof {
+                    block_hash, keys, ..
+                } => (
+                    warp_sync::RequestDetail::StorageGetMerkleProof {
+                        block_hash,
+                        keys: keys.clone(),
+                    },
+                    network_service
+                        .clone()
+                        .storage_proof_request(
+                            peer_id,
+                            network_chain_index,
+                            protocol::StorageProofRequestConfig {
+                                block_hash,
+                                keys: keys.into_iter(),
+                            },
+                        )
+                        .map(RequestOutcome::StorageProof)
+                        .boxed(),
+                ),
+                warp_sync::DesiredRequest::RuntimeCallMerkleProof {
+                    block_hash,
+                    function_name,
+                    parameter_vectored,
+                } => (
+                    warp_sync::RequestDetail::RuntimeCallMerkleProof {
+                        block_hash,
+                        function_name: function_name.clone(),
+                        parameter_vectored: parameter_vectored.clone(),
+                    },
+                    network_service
+                        .clone()
+                        .call_proof_request(
+                            peer_id,
+                            network_chain_index,
+                            protocol::CallProofRequestConfig {
+                                block_hash,
+                                method: Cow::Owned(function_name.into_owned()),
+                                parameter_vectored: iter::once(parameter_vectored.into_owned()),
+                            },
+                        )
+                        .map(RequestOutcome::CallProof)
+                        .boxed(),
+                ),
+            };
+
+            let request_number = next_request_number;
+            next_request_number += 1;
+            let request_id = warp_sync.add_request(source_id, request_number, request_detail);
+            requests_in_progress.insert(request_number, request_id);
+            requests_futures.push(Box::pin(
+                future.map(move |outcome| (request_number, outcome)),
+            ));
+        }
+
+        futures_util::select! {
+            network_event = config.network_events_receiver.next().fuse() => {
+                // The network events channel never shuts down.
+                match network_event.unwrap() {
+                    network_service::Event::Connected { chain_index, peer_id, .. }
+                        if chain_index == network_chain_index =>
+                    {
+                        let source_id = warp_sync.add_source(peer_id.clone());
+                        peers.insert(peer_id, source_id);
+                    }
+                    network_service::Event::Disconnected { chain_index, peer_id }
+                        if chain_index == network_chain_index =>
+                    {
+                        // Peers that were connected before the warp syncing started aren't
+                        // tracked.
+                        let Some(source_id) = peers.remove(&peer_id) else {
+                            continue;
+                        };
+                        let (_, obsolete_requests) = warp_sync.remove_source(source_id);
+                        for (_, request_number) in obsolete_requests {
+                            requests_in_progress.remove(&request_number);
+                        }
+                    }
+                    _ => {}
+                }
+            }
+
+            request_result = requests_futures.select_next_some() => {
+                let (request_number, outcome) = request_result;
+
+                // The request is ignored if its source has been removed in the meanwhile.
+                let Some(request_id) = requests_in_progress.remove(&request_number) else {
+                    continue;
+                };
+
+                match outcome {
+                    RequestOutcome::WarpSync(Ok(response)) => {
+                        let response = response.decode();
+                        let fragments = response
+                            .fragments
+                            .into_iter()
+                            .map(|f| warp_sync::WarpSyncFragment {
+                                scale_encoded_header: f.scale_encoded_header.to_vec(),
+                                scale_encoded_justification: f.scale_encoded_justification.to_vec(),
+                            })
+                            .collect();
+                        warp_sync.warp_sync_request_success(
+                            request_id,
+                            fragments,
+                            response.is_finished,
+                        );
+                    }
+                    RequestOutcome::StorageProof(Ok(response)) => {
+                        warp_sync.storage_get_success(request_id, response.decode().to_vec());
+                    }
+                    RequestOutcome::CallProof(Ok(response)) => {
+                        warp_sync
+                            .runtime_call_merkle_proof_success(request_id, response.decode().to_vec());
+                    }
+                    RequestOutcome::WarpSync(Err(_))
+                    | RequestOutcome::StorageProof(Err(_))
+                    | RequestOutcome::CallProof(Err(_)) => {
+                        // The error has already been logged by the network service.
+                        warp_sync.fail_request(request_id);
+                    }
+                }
+            }
+        }
+    };
+
+    let finalized_block_header = success
+        .chain_information
+        .as_ref()
+        .finalized_block_header
+        .clone();
+    let finalized_block_hash = finalized_block_header.hash(config.block_number_bytes);
+    config.log_callback.log(
+        LogLevel::Info,
+        format!(
+            "warp-sync-finished; finalized_block_hash={}; finalized_block_number={}",
+            HashDisplay(&finalized_block_hash),
+            finalized_block_header.number
+        ),
+    );
+
+    let state_version = success
+        .finalized_runtime
+        .runtime_version()
+        .decode()
+        .state_version
+        .unwrap_or(TrieEntryVersion::V0);
+
+    // Download the storage of the finalized block. Requests are sent one by one, each to a
+    // different peer than the previous one.
+    let mut peers = success
+        .sources_ordered
+        .into_iter()
+        .map(|(_, peer_id)| peer_id)
+        .collect::<Vec<_>>();
+    let mut next_peer_index = 0;
+    // Delay to wait for before sending the next request. Increased after every failed request,
+    // and reset after every successful one.
+    let mut retry_delay = None;
+    let mut state_download = state_download::StateDownload::new(state_download::Config {
+        block_hash: finalized_block_hash,
+        state_trie_root: *finalized_block_header.state_root,
+    });
+
+    while let Some(request) = state_download.next_request() {
+        if let Some(retry_delay) = retry_delay {
+            config.time_source.sleep(retry_delay).await;
+        }
+
+        // Process the network events that have happened in the meanwhile, and wait for a peer
+        // to be connected if necessary.
+        loop {
+            let network_event = if peers.is_empty() {
+                Some(config.network_events_receiver.next().await.unwrap())
+            } else {
+                config
+                    .network_events_receiver
+                    .next()
+                    .now_or_never()
+                    .map(Option::unwrap)
+            };
+
+            match network_event {
+                None => break,
+                Some(network_service::Event::Connected {
+                    chain_index,
+                    peer_id,
+                    ..
+                }) if chain_index == network_chain_index => peers.push(peer_id),
+                Some(network_service::Event::Disconnected {
+                    chain_index,
+                    peer_id,
+                }) if chain_index == network_chain_index => peers.retain(|p| *p != peer_id),
+                Some(_) => {}
+            }
+        }
+
+        next_peer_index = (next_peer_index + 1) % peers.len();
+        let peer_id = peers[next_peer_index].clone();
+
+        let response = network_service
+            .clone()
+            .state_request(
+                peer_id.clone(),
+                network_chain_index,
+                *request.block_hash,
+                match request.child_trie {
+                    Some(child_trie) => protocol::StateRequestStart::ChildTrieDefault {
+                        child_trie,
+                        key: request.start_key,
+                    },
+                    None => protocol::StateRequestStart::MainTrie(request.start_key),
+                },
+            )
+            .await;
+
+        let Ok(response) = response else {
+            // The error has already been logged by the network service.
+            retry_delay = Some(next_retry_delay(retry_delay));
+            continue;
+        };
+
+        if let Err(error) = state_download.inject_response(response.decode()) {
+            config.log_callback.log(
+                LogLevel::Warn,
+                format!("state-download-bad-response; peer_id={peer_id}; error={error}"),
+            );
+            retry_delay = Some(next_retry_delay(retry_delay));
+            continue;
+        }
+
+        retry_delay = None;
+
+        config.log_callback.log(
+            LogLevel::Debug,
+            format!(
+                "state-download-progress; num_storage_entries={}; num_trie_nodes={}",
+                state_download.num_storage_entries(),
+                state_download.num_trie_nodes()
+            ),
+        );
+    }
+
+    config.log_callback.log(
+        LogLevel::Info,
+        format!(
+            "state-download-finished; block_hash={}; num_storage_entries={}",
+            HashDisplay(&finalized_block_hash),
+            state_download.num_storage_entries()
+        ),
+    );
+
+    // The database stores a single trie entry version for the whole storage. Storage values
+    // shorter than 33 bytes are encoded the same way in both versions, but longer ones must
+    // match the version of the runtime.
+    let trie_nodes = state_download.into_trie_nodes().collect::<Vec<_>>();
+    if trie_nodes.iter().any(|node| {
+        matches!(&node.storage_value, Some((value, version))
+                if value.len() >= 33 && *version != state_version)
+    }) {
+        return Err(WarpSyncError::MixedStateVersions);
+    }
+
+    let chain_information = success.chain_information;
+    config
+        .database
+        .with_database(move |database| {
+            database.reset(
+                chain_information.as_ref(),
+                iter::empty(),
+                None,
+                trie_nodes.iter().map(|node| full_sqlite::InsertTrieNode {
+                    merkle_value: Cow::Borrowed(&node.merkle_value),
+                    partial_key_nibbles: Cow::Owned(
+                        node.partial_key.iter().map(|n| u8::from(*n)).collect(),
+                    ),
+                    children_merkle_values: node
+                        .children_merkle_values
+                        .each_ref()
+                        .map(|child| child.as_deref().map(Cow::Borrowed)),
+                    storage_value: match &node.storage_value {
+                        Some((value, _)) => full_sqlite::InsertTrieNodeStorageValue::Value {
+                            value: Cow::Borrowed(value),
+                            references_merkle_value: node.storage_value_is_child_trie_root,
+                        },
+                        None => full_sqlite::InsertTrieNodeStorageValue::NoValue,
+                    },
+                }),
+                u8::from(state_version),
+            )
+        })
+        .await
+        .map_err(WarpSyncError::DatabaseAccess)?;
+
+    Ok(())
+}
+
+/// Returns the delay to wait for before retrying a state request after a failure, given the
+/// delay that was waited for before the request that has failed.
+fn next_retry_delay(previous: Option<Duration>) -> Duration {
+    match previous {
+        None => Duration::from_millis(500),
+        Some(previous) => cmp::min(previous * 2, Duration::from_secs(16)),
+    }
+}
+
+/// Outcome of a request started during the warp syncing.
+enum RequestOutcome {
+    WarpSync(
+        Result<
+            smoldot::network::service::EncodedGrandpaWarpSyncResponse,
+            network_service::GrandpaWarpSyncRequestError,
+        >,
+    ),
+    StorageProof(
+        Result<
+            smoldot::network::service::EncodedMerkleProof,
+            network_service::StorageProofRequestError,
+        >,
+    ),
+    CallProof(
+        Result<
+            smoldot::network::service::EncodedMerkleProof,
+            network_service::CallProofRequestError,
+        >,
+    ),
+}
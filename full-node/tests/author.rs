@@ -15,45 +15,627 @@
 // You should have received a copy of the GNU General Public License
 // along with this program.  If not, see <http://www.gnu.org/licenses/>.
 
-use std::{sync::Arc, time::Duration};
+// Apart from `block_propagated_over_quic`, the nodes of the tests below are connected through a
+// `MemoryNetwork` and their time is driven by a `Clock` that only moves forward when the test
+// advances it. The author holds one of the two Aura authorities of the test chain, and authors a
+// block every time the clock enters one of its slots.
+
+use futures_lite::future;
+use smoldot_light::platform::memory::{Clock, MemoryNetwork};
+use std::{
+    iter,
+    num::NonZeroU32,
+    sync::Arc,
+    time::{Duration, Instant},
+};
+
+/// Time between two slots claimed by the author. The test chain has 6 seconds slots and two Aura
+/// authorities, and the author is the one that can claim the even slots.
+const AUTHORING_INTERVAL: Duration = Duration::from_secs(12);
+
+/// UNIX time the clock of the tests starts at. One second after the start of an even slot.
+const CLOCK_START: Duration = Duration::from_secs(1_700_000_005);
 
 #[test]
 fn basic_block_generated() {
     smol::block_on(async move {
-        let client = smoldot_full_node::start(smoldot_full_node::Config {
-            chain: smoldot_full_node::ChainConfig {
-                chain_spec: SUBSTRATE_NODE_TEMPLATE_CHAIN_SPEC.into(),
-                additional_bootnodes: Vec::new(),
-                keystore_memory: vec![smoldot::identity::seed_phrase::decode_sr25519_private_key(
-                    "//Alice",
-                )
-                .unwrap()],
-                sqlite_database_path: None,
-                sqlite_cache_size: 256 * 1024 * 1024,
-                keystore_path: None,
-            },
-            relay_chain: None,
-            libp2p_key: Box::new([0; 32]),
-            listen_addresses: Vec::new(),
-            json_rpc: None,
-            tasks_executor: Arc::new(|task| smol::spawn(task).detach()),
-            log_callback: Arc::new(move |_, _| {}),
-            jaeger_agent: None,
-        })
+        let clock = Clock::new(CLOCK_START);
+        let client = smoldot_full_node::start(node_config(
+            [0; 32],
+            true,
+            Vec::new(),
+            Vec::new(),
+            None,
+            Some(&clock),
+        ))
+        .await;
+
+        // The slot the clock starts in is claimed immediately.
+        client.wait_sync_state(|s| s.best_block_number >= 1).await;
+
+        // No other block is authored until the clock enters the next slot of the author.
+        for block_number in 2..=3 {
+            clock.advance(AUTHORING_INTERVAL);
+            let state = client
+                .wait_sync_state(|s| s.best_block_number >= block_number)
+                .await;
+            assert_eq!(state.best_block_number, block_number);
+        }
+    });
+}
+
+#[test]
+fn block_propagated_over_quic() {
+    smol::block_on(async move {
+        // Find a UDP port that is very likely to be available.
+        let port = std::net::UdpSocket::bind("127.0.0.1:0")
+            .unwrap()
+            .local_addr()
+            .unwrap()
+            .port();
+        let author_address: smoldot::libp2p::Multiaddr =
+            format!("/ip4/127.0.0.1/udp/{port}/quic-v1")
+                .parse()
+                .unwrap();
+
+        let author_libp2p_key = [1; 32];
+        let author_peer_id = peer_id(&author_libp2p_key);
+
+        // The QUIC connections go through the operating system and use the system clock.
+        let _author = smoldot_full_node::start(node_config(
+            author_libp2p_key,
+            true,
+            vec![author_address.clone()],
+            Vec::new(),
+            None,
+            None,
+        ))
+        .await;
+
+        // This node doesn't author blocks and can only learn about new blocks from the author.
+        let client = smoldot_full_node::start(node_config(
+            [2; 32],
+            false,
+            Vec::new(),
+            vec![(author_peer_id, author_address)],
+            None,
+            None,
+        ))
+        .await;
+
+        client.wait_sync_state(|s| s.best_block_number >= 1).await;
+    });
+}
+
+#[test]
+fn block_propagated_over_memory() {
+    smol::block_on(async move {
+        let memory_network = MemoryNetwork::new();
+        let clock = Clock::new(CLOCK_START);
+        let author_address: smoldot::libp2p::Multiaddr = "/memory/1".parse().unwrap();
+
+        let author_libp2p_key = [1; 32];
+        let author_peer_id = peer_id(&author_libp2p_key);
+
+        let author = smoldot_full_node::start(node_config(
+            author_libp2p_key,
+            true,
+            vec![author_address.clone()],
+            Vec::new(),
+            Some(&memory_network),
+            Some(&clock),
+        ))
+        .await;
+
+        // This node doesn't author blocks and can only learn about new blocks from the author.
+        let client = smoldot_full_node::start(node_config(
+            [2; 32],
+            false,
+            Vec::new(),
+            vec![(author_peer_id, author_address)],
+            Some(&memory_network),
+            Some(&clock),
+        ))
+        .await;
+
+        client.wait_sync_state(|s| s.best_block_number >= 1).await;
+
+        advance_slot(&clock, &author).await;
+        let state = client.wait_sync_state(|s| s.best_block_number >= 2).await;
+        assert_eq!(
+            state.best_block_hash,
+            author.sync_state().await.best_block_hash
+        );
+    });
+}
+
+#[test]
+fn late_node_syncs_through_other_node() {
+    smol::block_on(async move {
+        let memory_network = MemoryNetwork::new();
+        let clock = Clock::new(CLOCK_START);
+        let author_address: smoldot::libp2p::Multiaddr = "/memory/1".parse().unwrap();
+        let relay_address: smoldot::libp2p::Multiaddr = "/memory/2".parse().unwrap();
+
+        let author_libp2p_key = [1; 32];
+        let relay_libp2p_key = [2; 32];
+
+        let author = smoldot_full_node::start(node_config(
+            author_libp2p_key,
+            true,
+            vec![author_address.clone()],
+            Vec::new(),
+            Some(&memory_network),
+            Some(&clock),
+        ))
+        .await;
+
+        // The author builds a few blocks before any other node exists.
+        author.wait_sync_state(|s| s.best_block_number >= 1).await;
+        for _ in 0..3 {
+            advance_slot(&clock, &author).await;
+        }
+        let author_state = author.sync_state().await;
+        assert_eq!(author_state.best_block_number, 4);
+
+        let relay = smoldot_full_node::start(node_config(
+            relay_libp2p_key,
+            false,
+            vec![relay_address.clone()],
+            vec![(peer_id(&author_libp2p_key), author_address)],
+            Some(&memory_network),
+            Some(&clock),
+        ))
+        .await;
+        relay
+            .wait_sync_state(|s| s.best_block_hash == author_state.best_block_hash)
+            .await;
+
+        // The last node only knows about the address of the node that has synced from the
+        // author.
+        let client = smoldot_full_node::start(node_config(
+            [3; 32],
+            false,
+            Vec::new(),
+            vec![(peer_id(&relay_libp2p_key), relay_address)],
+            Some(&memory_network),
+            Some(&clock),
+        ))
+        .await;
+        client
+            .wait_sync_state(|s| s.best_block_hash == author_state.best_block_hash)
+            .await;
+
+        // Blocks authored afterwards reach the last node as well.
+        advance_slot(&clock, &author).await;
+        let author_state = author.sync_state().await;
+        client
+            .wait_sync_state(|s| s.best_block_hash == author_state.best_block_hash)
+            .await;
+    });
+}
+
+#[test]
+fn grandpa_finalizes_blocks() {
+    smol::block_on(async move {
+        let memory_network = MemoryNetwork::new();
+        let clock = Clock::new(CLOCK_START);
+        let author_address: smoldot::libp2p::Multiaddr = "/memory/1".parse().unwrap();
+
+        let author_libp2p_key = [1; 32];
+        let author_peer_id = peer_id(&author_libp2p_key);
+
+        // The author is also the only GrandPa authority of the chain. GrandPa keys are Ed25519
+        // keys, which can only be passed to the node through its keystore directory.
+        let keystore_path = std::env::temp_dir().join(format!(
+            "smoldot-full-node-test-keystore-{}",
+            hex::encode(rand::random::<[u8; 8]>())
+        ));
+        let grandpa_key = {
+            let keystore =
+                smoldot::identity::keystore::Keystore::new(Some(keystore_path.clone()), [0; 32])
+                    .await
+                    .unwrap();
+            keystore
+                .generate_ed25519(smoldot::identity::keystore::KeyNamespace::Grandpa, true)
+                .await
+                .unwrap()
+        };
+        let chain_spec = {
+            let mut chain_spec =
+                serde_json::from_slice::<serde_json::Value>(SUBSTRATE_NODE_TEMPLATE_CHAIN_SPEC)
+                    .unwrap();
+            // SCALE-encoded list containing one authority with a weight of 1, prefixed with the
+            // version of the format.
+            chain_spec["genesis"]["raw"]["top"][GRANDPA_AUTHORITIES_KEY] = serde_json::json!(
+                format!("0x0104{}0100000000000000", hex::encode(grandpa_key))
+            );
+            chain_spec.to_string().into_bytes()
+        };
+
+        let mut author_config = node_config(
+            author_libp2p_key,
+            true,
+            vec![author_address.clone()],
+            Vec::new(),
+            Some(&memory_network),
+            Some(&clock),
+        );
+        author_config.chain.chain_spec = chain_spec.clone().into();
+        author_config.chain.keystore_path = Some(keystore_path.clone());
+        let author = smoldot_full_node::start(author_config).await;
+
+        // This node isn't an authority, and learns about the finality from the author.
+        let mut client_config = node_config(
+            [2; 32],
+            false,
+            Vec::new(),
+            vec![(author_peer_id, author_address)],
+            Some(&memory_network),
+            Some(&clock),
+        );
+        client_config.chain.chain_spec = chain_spec.into();
+        let client = smoldot_full_node::start(client_config).await;
+
+        // The GrandPa rounds only progress when the clock moves forward.
+        author.wait_sync_state(|s| s.best_block_number >= 1).await;
+        while author.sync_state().await.finalized_block_number == 0 {
+            advance_slot(&clock, &author).await;
+        }
+
+        client
+            .wait_sync_state(|s| s.finalized_block_number >= 1)
+            .await;
+
+        let _ = std::fs::remove_dir_all(&keystore_path);
+    });
+}
+
+#[test]
+fn light_client_over_memory() {
+    smol::block_on(async move {
+        let memory_network = MemoryNetwork::new();
+        let clock = Clock::new(CLOCK_START);
+        let author_address: smoldot::libp2p::Multiaddr = "/memory/1".parse().unwrap();
+
+        let author_libp2p_key = [1; 32];
+        let author_peer_id = peer_id(&author_libp2p_key);
+
+        let author = smoldot_full_node::start(node_config(
+            author_libp2p_key,
+            true,
+            vec![author_address.clone()],
+            Vec::new(),
+            Some(&memory_network),
+            Some(&clock),
+        ))
         .await;
 
+        // The light client only learns about new blocks from the author.
+        let (mut light_client, chain_id, mut json_rpc_responses) =
+            light_client(&memory_network, &clock, (&author_peer_id, &author_address));
+        light_client
+            .json_rpc_request(
+                r#"{"id":1,"jsonrpc":"2.0","method":"chain_subscribeNewHeads","params":[]}"#,
+                chain_id,
+            )
+            .unwrap();
+
+        wait_json_rpc_response(&mut json_rpc_responses, r#""number":"0x1""#).await;
+
+        advance_slot(&clock, &author).await;
+        wait_json_rpc_response(&mut json_rpc_responses, r#""number":"0x2""#).await;
+    });
+}
+
+#[test]
+fn transaction_propagated_to_author() {
+    smol::block_on(async move {
+        let memory_network = MemoryNetwork::new();
+        let clock = Clock::new(CLOCK_START);
+        let relay_address: smoldot::libp2p::Multiaddr = "/memory/1".parse().unwrap();
+
+        let relay_libp2p_key = [1; 32];
+        let relay_peer_id = peer_id(&relay_libp2p_key);
+
+        // The author doesn't listen on any address, meaning that the light client can only send
+        // its transaction to the other node, which must then propagate it to the author.
+        let relay = smoldot_full_node::start(node_config(
+            relay_libp2p_key,
+            false,
+            vec![relay_address.clone()],
+            Vec::new(),
+            Some(&memory_network),
+            Some(&clock),
+        ))
+        .await;
+        let author = smoldot_full_node::start(node_config(
+            [2; 32],
+            true,
+            Vec::new(),
+            vec![(relay_peer_id.clone(), relay_address.clone())],
+            Some(&memory_network),
+            Some(&clock),
+        ))
+        .await;
+        relay.wait_sync_state(|s| s.best_block_number >= 1).await;
+
+        let (mut light_client, chain_id, mut json_rpc_responses) =
+            light_client(&memory_network, &clock, (&relay_peer_id, &relay_address));
+
+        // Wait for the light client to be synced before submitting the transaction.
+        light_client
+            .json_rpc_request(
+                r#"{"id":1,"jsonrpc":"2.0","method":"chain_subscribeNewHeads","params":[]}"#,
+                chain_id,
+            )
+            .unwrap();
+        wait_json_rpc_response(&mut json_rpc_responses, r#""number":"0x1""#).await;
+
+        let transaction = remark_transaction(
+            &author.sync_state().await.finalized_block_hash,
+            b"hello world",
+        )
+        .await;
+        light_client
+            .json_rpc_request(
+                format!(
+                    r#"{{"id":2,"jsonrpc":"2.0","method":"author_submitAndWatchExtrinsic","params":["0x{}"]}}"#,
+                    hex::encode(&transaction)
+                ),
+                chain_id,
+            )
+            .unwrap();
+
+        // The light client validates the transaction by querying the other node, and the clock
+        // must not be advanced while this query is in progress, as it would otherwise time out.
+        wait_json_rpc_response(&mut json_rpc_responses, r#""broadcast""#).await;
+
+        // Move from slot to slot until the transaction is included in a block.
+        let mut best_block_number = author.sync_state().await.best_block_number;
         loop {
-            smol::Timer::after(Duration::from_secs(1)).await;
+            advance_slot(&clock, &author).await;
+            best_block_number += 1;
 
-            let client_state = client.sync_state().await;
-            if client_state.best_block_number >= 1 {
-                // Success!
+            let included = future::or(
+                async {
+                    wait_json_rpc_response(&mut json_rpc_responses, r#""inBlock""#).await;
+                    true
+                },
+                async {
+                    relay
+                        .wait_sync_state(|s| s.best_block_number >= best_block_number)
+                        .await;
+                    false
+                },
+            )
+            .await;
+            if included {
                 break;
             }
         }
     });
 }
 
+/// Moves the clock to the next slot of `author`, and waits for it to author the block of this slot.
+async fn advance_slot(clock: &Clock, author: &smoldot_full_node::Client) {
+    let best_block_number = author.sync_state().await.best_block_number;
+    clock.advance(AUTHORING_INTERVAL);
+    author
+        .wait_sync_state(|s| s.best_block_number > best_block_number)
+        .await;
+}
+
+/// Waits until the light client sends a JSON-RPC response or notification that contains the
+/// given string, and returns it.
+async fn wait_json_rpc_response(
+    json_rpc_responses: &mut smoldot_light::JsonRpcResponses,
+    pattern: &str,
+) -> String {
+    loop {
+        let response = json_rpc_responses.next().await.unwrap();
+        if response.contains(pattern) {
+            return response;
+        }
+    }
+}
+
+/// Starts a light client of the test chain whose only bootnode is the given full node.
+fn light_client(
+    memory_network: &MemoryNetwork,
+    clock: &Clock,
+    bootnode: (&smoldot::libp2p::PeerId, &smoldot::libp2p::Multiaddr),
+) -> (
+    smoldot_light::Client<Arc<smoldot_light::platform::memory::MemoryPlatform>>,
+    smoldot_light::ChainId,
+    smoldot_light::JsonRpcResponses,
+) {
+    let chain_spec = {
+        let mut chain_spec =
+            serde_json::from_slice::<serde_json::Value>(SUBSTRATE_NODE_TEMPLATE_CHAIN_SPEC)
+                .unwrap();
+        chain_spec["bootNodes"] = serde_json::json!([format!("{}/p2p/{}", bootnode.1, bootnode.0)]);
+        chain_spec.to_string()
+    };
+
+    let mut client =
+        smoldot_light::Client::new(smoldot_light::platform::memory::MemoryPlatform::new(
+            "test".into(),
+            "0.0.0".into(),
+            memory_network.clone(),
+            clock.clone(),
+            [0; 32],
+        ));
+    let smoldot_light::AddChainSuccess {
+        chain_id,
+        json_rpc_responses,
+    } = client
+        .add_chain(smoldot_light::AddChainConfig {
+            specification: &chain_spec,
+            json_rpc: smoldot_light::AddChainConfigJsonRpc::Enabled {
+                max_pending_requests: NonZeroU32::new(16).unwrap(),
+                max_subscriptions: 16,
+            },
+            potential_relay_chains: iter::empty(),
+            database_content: "",
+            user_data: (),
+        })
+        .unwrap();
+
+    (client, chain_id, json_rpc_responses.unwrap())
+}
+
+/// Builds a transaction of the test chain, signed by the account of Alice, that calls
+/// `System::remark` with the given bytes. The transaction is immortal and uses a nonce of 0.
+///
+/// The returned transaction is SCALE-encoded, meaning that it is prefixed with its length.
+async fn remark_transaction(genesis_hash: &[u8; 32], remark: &[u8]) -> Vec<u8> {
+    // Index of the `System` pallet and of its `remark` function.
+    let mut call = vec![0, 1];
+    call.extend_from_slice(&encode_compact(remark.len()));
+    call.extend_from_slice(remark);
+
+    // Immortal era, nonce, and tip.
+    let extra = [0, 0, 0];
+
+    let mut signed_payload = call.clone();
+    signed_payload.extend_from_slice(&extra);
+    // Spec version and transaction version of the runtime.
+    signed_payload.extend_from_slice(&100u32.to_le_bytes());
+    signed_payload.extend_from_slice(&1u32.to_le_bytes());
+    // Genesis hash, and hash of the block the era starts at.
+    signed_payload.extend_from_slice(genesis_hash);
+    signed_payload.extend_from_slice(genesis_hash);
+    // Payloads longer than 256 bytes are hashed before being signed.
+    assert!(signed_payload.len() <= 256);
+
+    let mut keystore = smoldot::identity::keystore::Keystore::new(None, [0; 32])
+        .await
+        .unwrap();
+    let alice = keystore.insert_sr25519_memory(
+        iter::once(smoldot::identity::keystore::KeyNamespace::Aura),
+        &smoldot::identity::seed_phrase::decode_sr25519_private_key("//Alice").unwrap(),
+    );
+    let signature = keystore
+        .sign(
+            smoldot::identity::keystore::KeyNamespace::Aura,
+            &alice,
+            &signed_payload,
+        )
+        .await
+        .unwrap();
+
+    // Version 4 of the format, signed.
+    let mut transaction = vec![0x84];
+    // `MultiAddress::Id`.
+    transaction.push(0);
+    transaction.extend_from_slice(&alice);
+    // `MultiSignature::Sr25519`.
+    transaction.push(1);
+    transaction.extend_from_slice(&signature);
+    transaction.extend_from_slice(&extra);
+    transaction.extend_from_slice(&call);
+
+    let mut encoded = encode_compact(transaction.len());
+    encoded.extend_from_slice(&transaction);
+    encoded
+}
+
+/// Returns the SCALE compact encoding of the given number, which must be inferior to `2^14`.
+fn encode_compact(value: usize) -> Vec<u8> {
+    if value < 1 << 6 {
+        vec![u8::try_from(value << 2).unwrap()]
+    } else {
+        u16::try_from((value << 2) | 0b01)
+            .unwrap()
+            .to_le_bytes()
+            .to_vec()
+    }
+}
+
+/// Builds the configuration of a full node of the test chain.
+///
+/// If `authoring` is `true`, the node has the key of the only authority of the chain in its
+/// keystore and thus authors blocks. If `clock` is `None`, the node uses the system clock.
+fn node_config(
+    libp2p_key: [u8; 32],
+    authoring: bool,
+    listen_addresses: Vec<smoldot::libp2p::Multiaddr>,
+    additional_bootnodes: Vec<(smoldot::libp2p::PeerId, smoldot::libp2p::Multiaddr)>,
+    memory_network: Option<&MemoryNetwork>,
+    clock: Option<&Clock>,
+) -> smoldot_full_node::Config<'static> {
+    smoldot_full_node::Config {
+        chain: smoldot_full_node::ChainConfig {
+            chain_spec: SUBSTRATE_NODE_TEMPLATE_CHAIN_SPEC.into(),
+            additional_bootnodes,
+            keystore_memory: if authoring {
+                vec![smoldot::identity::seed_phrase::decode_sr25519_private_key("//Alice").unwrap()]
+            } else {
+                Vec::new()
+            },
+            sqlite_database_path: None,
+            sqlite_cache_size: 256 * 1024 * 1024,
+            sqlite_pruning: smoldot::database::full_sqlite::PruningMode::Archive,
+            keystore_path: None,
+        },
+        relay_chain: None,
+        libp2p_key: Box::new(libp2p_key),
+        listen_addresses,
+        memory_network: memory_network.cloned(),
+        time_source: clock.map(|clock| {
+            Arc::new(ClockTimeSource {
+                clock: clock.clone(),
+                origin: Instant::now(),
+            }) as Arc<_>
+        }),
+        webrtc_certificate: smoldot_full_node::WebRtcCertificate::generate(),
+        json_rpc: None,
+        tasks_executor: Arc::new(|task| smol::spawn(task).detach()),
+        log_callback: Arc::new(move |_, _| {}),
+        jaeger_agent: None,
+        offchain_http_client: None,
+        sync_mode: smoldot_full_node::SyncMode::Full,
+    }
+}
+
+/// Implementation of [`smoldot_full_node::TimeSource`] driven by the same [`Clock`] as the light
+/// clients of the test.
+struct ClockTimeSource {
+    clock: Clock,
+    /// Value of [`smoldot_full_node::TimeSource::now`] when the [`Clock`] was created.
+    origin: Instant,
+}
+
+impl smoldot_full_node::TimeSource for ClockTimeSource {
+    fn now_from_unix_epoch(&self) -> Duration {
+        self.clock.now_from_unix_epoch()
+    }
+
+    fn now(&self) -> Instant {
+        self.origin + self.clock.now()
+    }
+
+    fn sleep_until(&self, when: Instant) -> futures_util::future::BoxFuture<'static, ()> {
+        Box::pin(
+            self.clock
+                .sleep_until(when.saturating_duration_since(self.origin)),
+        )
+    }
+}
+
+/// Returns the [`smoldot::libp2p::PeerId`] of a node using the given libp2p key.
+fn peer_id(libp2p_key: &[u8; 32]) -> smoldot::libp2p::PeerId {
+    smoldot::libp2p::peer_id::PublicKey::Ed25519(
+        *smoldot::libp2p::connection::NoiseKey::new(libp2p_key, &[0; 32])
+            .libp2p_public_ed25519_key(),
+    )
+    .into_peer_id()
+}
+
+/// Key of the genesis storage containing the list of GrandPa authorities.
+const GRANDPA_AUTHORITIES_KEY: &str = "0x3a6772616e6470615f617574686f726974696573";
+
 const SUBSTRATE_NODE_TEMPLATE_CHAIN_SPEC: &[u8] = br#"
 {
     "name": "Local Testnet",
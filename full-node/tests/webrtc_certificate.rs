@@ -0,0 +1,38 @@
+// Smoldot
+// Copyright (C) 2023  Pierre Krieger
+// SPDX-License-Identifier: GPL-3.0-or-later WITH Classpath-exception-2.0
+
+// This program is free software: you can redistribute it and/or modify
+// it under the terms of the GNU General Public License as published by
+// the Free Software Foundation, either version 3 of the License, or
+// (at your option) any later version.
+
+// This program is distributed in the hope that it will be useful,
+// but WITHOUT ANY WARRANTY; without even the implied warranty of
+// MERCHANTABILITY or FITNESS FOR A PARTICULAR PURPOSE.  See the
+// GNU General Public License for more details.
+
+// You should have received a copy of the GNU General Public License
+// along with this program.  If not, see <http://www.gnu.org/licenses/>.
+
+use smoldot_full_node::WebRtcCertificate;
+
+#[test]
+fn der_round_trip() {
+    let certificate = WebRtcCertificate::generate();
+    let decoded = WebRtcCertificate::from_der(
+        certificate.certificate_der().to_vec(),
+        certificate.private_key_der().to_vec(),
+    )
+    .unwrap();
+    assert_eq!(certificate.certificate_der(), decoded.certificate_der());
+    assert_eq!(certificate.private_key_der(), decoded.private_key_der());
+}
+
+#[test]
+fn invalid_der_rejected() {
+    let certificate = WebRtcCertificate::generate();
+    assert!(
+        WebRtcCertificate::from_der(certificate.certificate_der().to_vec(), vec![1, 2, 3]).is_err()
+    );
+}
@@ -23,5 +23,8 @@ libfuzzer_sys::fuzz_target!(|data: &[u8]| {
         heap_pages: smoldot::executor::DEFAULT_HEAP_PAGES,
         exec_hint: smoldot::executor::vm::ExecHint::ForceWasmi,
         allow_unresolved_imports: true,
+        compiled_module_cache: None,
+        resource_limits: Default::default(),
+        allocation_strategy: Default::default(),
     });
 });
@@ -97,7 +97,7 @@ pub fn next_slot_claim<'a>(
         let slot_start_from_unix_epoch =
             Duration::from_millis(slot_number.checked_mul(config.slot_duration.get()).unwrap());
         let slot_end_from_unix_epoch =
-            slot_start_from_unix_epoch + Duration::from_secs(config.slot_duration.get());
+            slot_start_from_unix_epoch + Duration::from_millis(config.slot_duration.get());
         debug_assert!(slot_end_from_unix_epoch > config.now_from_unix_epoch);
 
         Some(SlotClaim {
@@ -0,0 +1,842 @@
+// Smoldot
+// Copyright (C) 2023  Pierre Krieger
+// SPDX-License-Identifier: GPL-3.0-or-later WITH Classpath-exception-2.0
+
+// This program is free software: you can redistribute it and/or modify
+// it under the terms of the GNU General Public License as published by
+// the Free Software Foundation, either version 3 of the License, or
+// (at your option) any later version.
+
+// This program is distributed in the hope that it will be useful,
+// but WITHOUT ANY WARRANTY; without even the implied warranty of
+// MERCHANTABILITY or FITNESS FOR A PARTICULAR PURPOSE.  See the
+// GNU General Public License for more details.
+
+// You should have received a copy of the GNU General Public License
+// along with this program.  If not, see <http://www.gnu.org/licenses/>.
+
+//! Determining whether one of the local authorities is allowed to claim a Babe slot.
+//!
+//! Contrary to Aura, the Babe slots are attributed in a way that can't be determined in advance.
+//! Each authority can claim a *primary slot* if the output of a VRF signature, which can only be
+//! calculated by the owner of the private key, is below a certain threshold. In addition, each
+//! slot is attributed to one authority in a deterministic way, in which case it is called a
+//! *secondary slot*. See also the documentation of [`crate::verify::babe`].
+//!
+//! The [`next_slot_claim`] function returns a [`ClaimProgress`]. As long as
+//! [`ClaimProgress::VrfSign`] is returned, a VRF signature must be generated using the private
+//! key of one of the local authorities. The slot is eventually either claimed, or not claimable
+//! by any of the local authorities.
+
+use crate::{chain::chain_information, header, verify};
+
+use alloc::vec::Vec;
+use core::{num::NonZeroU64, time::Duration};
+
+/// Configuration for [`next_slot_claim`].
+pub struct Config<'a, TLocAuth> {
+    /// Time elapsed since [the Unix Epoch](https://en.wikipedia.org/wiki/Unix_time) (i.e.
+    /// 00:00:00 UTC on 1 January 1970), ignoring leap seconds.
+    pub now_from_unix_epoch: Duration,
+
+    /// Duration, in milliseconds, of a Babe slot.
+    pub slot_duration: NonZeroU64,
+
+    /// Number of slots per epoch in the Babe configuration.
+    pub slots_per_epoch: NonZeroU64,
+
+    /// Slot number of the parent of the block to author. Must be `None` if and only if the
+    /// parent is the genesis block.
+    pub parent_slot_number: Option<u64>,
+
+    /// Epoch the parent of the block to author belongs to. Must be `None` if and only if the
+    /// parent is the genesis block.
+    pub parent_block_epoch: Option<chain_information::BabeEpochInformationRef<'a>>,
+
+    /// Epoch that follows the epoch the parent of the block to author belongs to.
+    pub parent_block_next_epoch: chain_information::BabeEpochInformationRef<'a>,
+
+    /// Iterator to the list of Sr25519 public keys available locally.
+    ///
+    /// Must implement `Iterator<Item = &[u8; 32]>`.
+    pub local_authorities: TLocAuth,
+}
+
+/// Starts determining whether one of the authorities in [`Config::local_authorities`] is allowed
+/// to produce a block in the earliest slot that is available for the new block.
+///
+/// The slot in question is either the current slot or, if the parent block is in the current
+/// slot or in a future slot, the slot right after the one of the parent block.
+pub fn next_slot_claim<'a>(
+    config: Config<'a, impl Iterator<Item = &'a [u8; 32]>>,
+) -> ClaimProgress {
+    // Note that this calculation (and some other calculations down below) can overflow in the
+    // very distant future. This is considered acceptable.
+    let current_slot = u64::try_from(
+        config.now_from_unix_epoch.as_millis() / u128::from(config.slot_duration.get()),
+    )
+    .unwrap();
+
+    let slot_number = match config.parent_slot_number {
+        Some(parent_slot) => core::cmp::max(current_slot, parent_slot + 1),
+        None => current_slot,
+    };
+
+    // Determine the epoch the new block belongs to.
+    let epoch = match (&config.parent_block_epoch, &config.parent_block_next_epoch) {
+        (None, next_epoch) => next_epoch,
+        (Some(_), next_epoch)
+            if next_epoch
+                .start_slot_number
+                .map_or(true, |start| start <= slot_number) =>
+        {
+            // If the new block would skip an entire epoch, we can't author it, as the information
+            // about the epoch in question isn't known.
+            if next_epoch.start_slot_number.map_or(false, |start| {
+                slot_number >= start.saturating_add(config.slots_per_epoch.get())
+            }) {
+                return ClaimProgress::NoClaim;
+            }
+
+            next_epoch
+        }
+        (Some(current_epoch), _) => current_epoch,
+    };
+
+    let num_authorities = epoch.authorities.len();
+    if num_authorities == 0 {
+        return ClaimProgress::NoClaim;
+    }
+
+    // Each slot is attributed to one specific authority, called the secondary slot author.
+    let secondary_slot_author = match epoch.allowed_slots {
+        header::BabeAllowedSlots::PrimarySlots => None,
+        header::BabeAllowedSlots::PrimaryAndSecondaryPlainSlots
+        | header::BabeAllowedSlots::PrimaryAndSecondaryVrfSlots => Some(
+            verify::babe::secondary_slot_author(epoch.randomness, slot_number, num_authorities),
+        ),
+    };
+
+    // Build the list of local authorities that belong to the epoch.
+    let candidates = config
+        .local_authorities
+        .enumerate()
+        .filter_map(|(local_authorities_index, local_pub_key)| {
+            // TODO: O(n) complexity
+            let (authority_index, authority) = epoch
+                .authorities
+                .clone()
+                .enumerate()
+                .find(|(_, a)| a.public_key == local_pub_key)?;
+
+            // An authority with a weight of 0 is never allowed to claim a primary slot.
+            let primary_threshold = if authority.weight != 0 {
+                verify::babe::calculate_primary_threshold(
+                    epoch.c,
+                    epoch.authorities.clone().map(|a| a.weight),
+                    authority.weight,
+                )
+            } else {
+                0
+            };
+
+            Some(Candidate {
+                local_authorities_index,
+                authority_index: u32::try_from(authority_index).unwrap(),
+                public_key: *local_pub_key,
+                primary_threshold,
+            })
+        })
+        .collect::<Vec<_>>();
+
+    if candidates.is_empty() {
+        return ClaimProgress::NoClaim;
+    }
+
+    let slot_start_from_unix_epoch =
+        Duration::from_millis(slot_number.checked_mul(config.slot_duration.get()).unwrap());
+    let slot_end_from_unix_epoch =
+        slot_start_from_unix_epoch + Duration::from_millis(config.slot_duration.get());
+    debug_assert!(slot_end_from_unix_epoch > config.now_from_unix_epoch);
+
+    ClaimProgress::VrfSign(VrfSign {
+        slot_number,
+        slot_start_from_unix_epoch,
+        slot_end_from_unix_epoch,
+        epoch_index: epoch.epoch_index,
+        epoch_randomness: *epoch.randomness,
+        allowed_slots: epoch.allowed_slots,
+        secondary_slot_author,
+        candidates,
+        next_candidate: 0,
+        secondary_vrf: None,
+    })
+}
+
+/// Progress in determining whether a slot can be claimed.
+#[must_use]
+#[derive(Debug)]
+pub enum ClaimProgress {
+    /// A VRF signature must be generated in order to continue.
+    VrfSign(VrfSign),
+
+    /// One of the local authorities is allowed to produce a block in the given slot.
+    Claimed(SlotClaim),
+
+    /// None of the local authorities is allowed to produce a block in the slot.
+    NoClaim,
+}
+
+/// A VRF signature must be generated by one of the local authorities in order to determine
+/// whether it can claim the slot.
+#[must_use]
+#[derive(Debug)]
+pub struct VrfSign {
+    slot_number: u64,
+    slot_start_from_unix_epoch: Duration,
+    slot_end_from_unix_epoch: Duration,
+    epoch_index: u64,
+    epoch_randomness: [u8; 32],
+    allowed_slots: header::BabeAllowedSlots,
+
+    /// Index within the list of authorities of the epoch of the author of the secondary slot.
+    /// `None` if secondary slots aren't allowed.
+    secondary_slot_author: Option<u32>,
+
+    /// List of local authorities that belong to the epoch.
+    candidates: Vec<Candidate>,
+
+    /// Index within [`VrfSign::candidates`] of the authority that must generate the VRF
+    /// signature.
+    next_candidate: usize,
+
+    /// If the secondary slot author is one of the local authorities and the slot requires a VRF
+    /// output and proof, contains the VRF output and proof generated earlier.
+    secondary_vrf: Option<(usize, [u8; 32], [u8; 64])>,
+}
+
+#[derive(Debug)]
+struct Candidate {
+    local_authorities_index: usize,
+    authority_index: u32,
+    public_key: [u8; 32],
+    primary_threshold: u128,
+}
+
+impl VrfSign {
+    /// Returns the slot that is attempted to be claimed.
+    pub fn slot_number(&self) -> u64 {
+        self.slot_number
+    }
+
+    /// Returns the index within [`Config::local_authorities`] of the authority that must
+    /// generate the VRF signature.
+    pub fn local_authorities_index(&self) -> usize {
+        self.candidates[self.next_candidate].local_authorities_index
+    }
+
+    /// Returns the Sr25519 public key of the authority that must generate the VRF signature.
+    pub fn public_key(&self) -> &[u8; 32] {
+        &self.candidates[self.next_candidate].public_key
+    }
+
+    /// Returns the label of the transcript to sign.
+    pub fn transcript_label(&self) -> &'static [u8] {
+        b"BABE"
+    }
+
+    /// Returns the items of the transcript to sign.
+    pub fn transcript_items(
+        &'_ self,
+    ) -> impl Iterator<Item = (&'static [u8], either::Either<&'_ [u8], u64>)> + '_ {
+        [
+            (&b"slot number"[..], either::Right(self.slot_number)),
+            (&b"current epoch"[..], either::Right(self.epoch_index)),
+            (
+                &b"chain randomness"[..],
+                either::Left(&self.epoch_randomness[..]),
+            ),
+        ]
+        .into_iter()
+    }
+
+    /// Injects the VRF output (also called "pre-output") and proof generated by the authority.
+    ///
+    /// If the VRF output or proof is invalid, the authority is considered as not being able to
+    /// claim the slot.
+    pub fn inject_vrf_output(mut self, vrf_output: [u8; 32], vrf_proof: [u8; 64]) -> ClaimProgress {
+        let candidate = &self.candidates[self.next_candidate];
+
+        let transcript = {
+            let mut transcript = merlin::Transcript::new(self.transcript_label());
+            for (label, value) in self.transcript_items() {
+                match value {
+                    either::Left(bytes) => transcript.append_message(label, bytes),
+                    either::Right(value) => transcript.append_u64(label, value),
+                }
+            }
+            transcript
+        };
+
+        // Verifying the signature is necessary in order to obtain the VRF output, and also
+        // guarantees that we don't author a block that would be considered as invalid.
+        let vrf_in_out = match (
+            schnorrkel::PublicKey::from_bytes(&candidate.public_key),
+            schnorrkel::vrf::VRFPreOut::from_bytes(&vrf_output[..]),
+            schnorrkel::vrf::VRFProof::from_bytes(&vrf_proof[..]),
+        ) {
+            (Ok(public_key), Ok(pre_output), Ok(proof)) => public_key
+                .vrf_verify(transcript, &pre_output, &proof)
+                .ok()
+                .map(|(in_out, _)| in_out),
+            _ => None,
+        };
+
+        if let Some(vrf_in_out) = vrf_in_out {
+            if u128::from_le_bytes(vrf_in_out.make_bytes::<[u8; 16]>(b"substrate-babe-vrf"))
+                < candidate.primary_threshold
+            {
+                return ClaimProgress::Claimed(SlotClaim {
+                    slot_start_from_unix_epoch: self.slot_start_from_unix_epoch,
+                    slot_end_from_unix_epoch: self.slot_end_from_unix_epoch,
+                    slot_number: self.slot_number,
+                    local_authorities_index: candidate.local_authorities_index,
+                    pre_digest: header::BabePreDigest::Primary(header::BabePrimaryPreDigest {
+                        authority_index: candidate.authority_index,
+                        slot_number: self.slot_number,
+                        vrf_output,
+                        vrf_proof,
+                    }),
+                });
+            }
+
+            if matches!(
+                self.allowed_slots,
+                header::BabeAllowedSlots::PrimaryAndSecondaryVrfSlots
+            ) && self.secondary_slot_author == Some(candidate.authority_index)
+            {
+                self.secondary_vrf = Some((self.next_candidate, vrf_output, vrf_proof));
+            }
+        }
+
+        self.skip()
+    }
+
+    /// Indicates that the authority couldn't generate a VRF signature, for example because its
+    /// private key is no longer available.
+    pub fn skip(mut self) -> ClaimProgress {
+        self.next_candidate += 1;
+        if self.next_candidate < self.candidates.len() {
+            return ClaimProgress::VrfSign(self);
+        }
+
+        // None of the local authorities can claim a primary slot. Try a secondary slot.
+        let pre_digest_and_candidate = match self.allowed_slots {
+            header::BabeAllowedSlots::PrimarySlots => None,
+            header::BabeAllowedSlots::PrimaryAndSecondaryPlainSlots => self
+                .candidates
+                .iter()
+                .find(|c| Some(c.authority_index) == self.secondary_slot_author)
+                .map(|candidate| {
+                    (
+                        header::BabePreDigest::SecondaryPlain(
+                            header::BabeSecondaryPlainPreDigest {
+                                authority_index: candidate.authority_index,
+                                slot_number: self.slot_number,
+                            },
+                        ),
+                        candidate,
+                    )
+                }),
+            header::BabeAllowedSlots::PrimaryAndSecondaryVrfSlots => {
+                self.secondary_vrf
+                    .map(|(candidate_index, vrf_output, vrf_proof)| {
+                        let candidate = &self.candidates[candidate_index];
+                        (
+                            header::BabePreDigest::SecondaryVRF(
+                                header::BabeSecondaryVRFPreDigest {
+                                    authority_index: candidate.authority_index,
+                                    slot_number: self.slot_number,
+                                    vrf_output,
+                                    vrf_proof,
+                                },
+                            ),
+                            candidate,
+                        )
+                    })
+            }
+        };
+
+        match pre_digest_and_candidate {
+            Some((pre_digest, candidate)) => ClaimProgress::Claimed(SlotClaim {
+                slot_start_from_unix_epoch: self.slot_start_from_unix_epoch,
+                slot_end_from_unix_epoch: self.slot_end_from_unix_epoch,
+                slot_number: self.slot_number,
+                local_authorities_index: candidate.local_authorities_index,
+                pre_digest,
+            }),
+            None => ClaimProgress::NoClaim,
+        }
+    }
+}
+
+/// Slot happening now or in the future and that can be attributed to one of the authorities in
+/// [`Config::local_authorities`].
+#[derive(Debug, Clone)]
+pub struct SlotClaim {
+    /// UNIX time when the slot starts. Can be inferior to the value passed to
+    /// [`Config::now_from_unix_epoch`] if the slot has already started.
+    pub slot_start_from_unix_epoch: Duration,
+    /// UNIX time when the slot ends. Always superior to the value passed to
+    /// [`Config::now_from_unix_epoch`].
+    pub slot_end_from_unix_epoch: Duration,
+    /// Slot number of the claim. Used when building the block.
+    pub slot_number: u64,
+    /// Index within [`Config::local_authorities`] of the authority that can produce the block.
+    pub local_authorities_index: usize,
+    /// Pre-runtime digest item to include in the header of the new block.
+    pub pre_digest: header::BabePreDigest,
+}
+
+#[cfg(test)]
+mod tests {
+    use super::{ClaimProgress, Config, SlotClaim, VrfSign};
+    use crate::{chain::chain_information, header, verify};
+
+    use core::{num::NonZeroU64, time::Duration};
+
+    const SLOT_DURATION: u64 = 6000;
+    const SLOTS_PER_EPOCH: u64 = 100;
+
+    fn keypair(seed: u8) -> schnorrkel::Keypair {
+        schnorrkel::MiniSecretKey::from_bytes(&[seed; 32])
+            .unwrap()
+            .expand_to_keypair(schnorrkel::ExpansionMode::Ed25519)
+    }
+
+    fn epoch(
+        epoch_index: u64,
+        authorities: &[&schnorrkel::Keypair],
+        c: (u64, u64),
+        allowed_slots: header::BabeAllowedSlots,
+    ) -> chain_information::BabeEpochInformation {
+        chain_information::BabeEpochInformation {
+            epoch_index,
+            start_slot_number: Some(1000 + epoch_index * SLOTS_PER_EPOCH),
+            authorities: authorities
+                .iter()
+                .map(|keypair| header::BabeAuthority {
+                    public_key: keypair.public.to_bytes(),
+                    weight: 1,
+                })
+                .collect(),
+            randomness: [u8::try_from(epoch_index).unwrap(); 32],
+            c,
+            allowed_slots,
+        }
+    }
+
+    /// Calls [`super::next_slot_claim`] with a parent block in slot 1000 of epoch 0.
+    fn start_claim(
+        current_slot: u64,
+        parent_block_epoch: &chain_information::BabeEpochInformation,
+        parent_block_next_epoch: &chain_information::BabeEpochInformation,
+        local_authorities: &[&schnorrkel::Keypair],
+    ) -> ClaimProgress {
+        let local_authorities = local_authorities
+            .iter()
+            .map(|keypair| keypair.public.to_bytes())
+            .collect::<Vec<_>>();
+
+        super::next_slot_claim(Config {
+            now_from_unix_epoch: Duration::from_millis(current_slot * SLOT_DURATION + 1),
+            slot_duration: NonZeroU64::new(SLOT_DURATION).unwrap(),
+            slots_per_epoch: NonZeroU64::new(SLOTS_PER_EPOCH).unwrap(),
+            parent_slot_number: Some(1000),
+            parent_block_epoch: Some(parent_block_epoch.into()),
+            parent_block_next_epoch: parent_block_next_epoch.into(),
+            local_authorities: local_authorities.iter(),
+        })
+    }
+
+    /// Generates the VRF output and proof requested by the [`VrfSign`].
+    fn vrf_sign(keypair: &schnorrkel::Keypair, sign: &VrfSign) -> ([u8; 32], [u8; 64]) {
+        assert_eq!(*sign.public_key(), keypair.public.to_bytes());
+        let mut transcript = merlin::Transcript::new(sign.transcript_label());
+        for (label, value) in sign.transcript_items() {
+            match value {
+                either::Left(bytes) => transcript.append_message(label, bytes),
+                either::Right(value) => transcript.append_u64(label, value),
+            }
+        }
+        let (in_out, proof, _) = keypair.vrf_sign(transcript);
+        (in_out.to_preout().to_bytes(), proof.to_bytes())
+    }
+
+    /// Returns the value of the `current epoch` item of the transcript to sign.
+    fn transcript_epoch_index(sign: &VrfSign) -> u64 {
+        sign.transcript_items()
+            .find_map(|(label, value)| match (label, value) {
+                (b"current epoch", either::Right(epoch_index)) => Some(epoch_index),
+                _ => None,
+            })
+            .unwrap()
+    }
+
+    /// Builds a block containing the given claim, signed by `keypair`, and verifies it with
+    /// [`verify::babe::verify_header`].
+    fn verify_claim(
+        claim: &SlotClaim,
+        keypair: &schnorrkel::Keypair,
+        parent_block_epoch: &chain_information::BabeEpochInformation,
+        parent_block_next_epoch: &chain_information::BabeEpochInformation,
+    ) -> Result<verify::babe::VerifySuccess, verify::babe::VerifyError> {
+        let parent_digest = [header::DigestItem::BabePreDigest(
+            header::BabePreDigest::SecondaryPlain(header::BabeSecondaryPlainPreDigest {
+                authority_index: 0,
+                slot_number: 1000,
+            }),
+        )];
+        let parent_block_header = header::HeaderRef {
+            parent_hash: &[0; 32],
+            number: 1,
+            state_root: &[0; 32],
+            extrinsics_root: &[0; 32],
+            digest: header::DigestRef::from_slice(&parent_digest).unwrap(),
+        };
+        let parent_hash = parent_block_header.hash(4);
+
+        // The first block of an epoch must announce the epoch after.
+        let mut digest = vec![header::DigestItem::BabePreDigest(claim.pre_digest.clone())];
+        if claim.slot_number >= parent_block_next_epoch.start_slot_number.unwrap() {
+            digest.push(header::DigestItem::BabeConsensus(
+                header::BabeConsensusLog::NextEpochData(header::BabeNextEpoch {
+                    authorities: parent_block_next_epoch.authorities.clone(),
+                    randomness: [0xff; 32],
+                }),
+            ));
+        }
+
+        let unsealed_header_hash = header::HeaderRef {
+            parent_hash: &parent_hash,
+            number: 2,
+            state_root: &[0; 32],
+            extrinsics_root: &[0; 32],
+            digest: header::DigestRef::from_slice(&digest).unwrap(),
+        }
+        .hash(4);
+        let signature = keypair
+            .sign_simple(b"substrate", &unsealed_header_hash)
+            .to_bytes();
+        digest.push(header::DigestItem::BabeSeal(signature));
+        let header = header::HeaderRef {
+            parent_hash: &parent_hash,
+            number: 2,
+            state_root: &[0; 32],
+            extrinsics_root: &[0; 32],
+            digest: header::DigestRef::from_slice(&digest).unwrap(),
+        };
+
+        verify::babe::verify_header(verify::babe::VerifyConfig {
+            header,
+            block_number_bytes: 4,
+            parent_block_header,
+            now_from_unix_epoch: claim.slot_start_from_unix_epoch,
+            slots_per_epoch: NonZeroU64::new(SLOTS_PER_EPOCH).unwrap(),
+            parent_block_epoch: Some(parent_block_epoch.into()),
+            parent_block_next_epoch: parent_block_next_epoch.into(),
+        })
+    }
+
+    #[test]
+    fn current_epoch_selected() {
+        let alice = keypair(1);
+        let current = epoch(0, &[&alice], (1, 4), header::BabeAllowedSlots::PrimarySlots);
+        let next = epoch(1, &[&alice], (1, 4), header::BabeAllowedSlots::PrimarySlots);
+
+        let ClaimProgress::VrfSign(sign) = start_claim(1050, &current, &next, &[&alice]) else {
+            panic!()
+        };
+        assert_eq!(sign.slot_number(), 1050);
+        assert_eq!(transcript_epoch_index(&sign), 0);
+    }
+
+    #[test]
+    fn next_epoch_selected() {
+        let alice = keypair(1);
+        let bob = keypair(2);
+        let current = epoch(0, &[&alice], (1, 4), header::BabeAllowedSlots::PrimarySlots);
+        let next = epoch(1, &[&bob], (1, 4), header::BabeAllowedSlots::PrimarySlots);
+
+        // Alice is only an authority of the current epoch.
+        assert!(matches!(
+            start_claim(1150, &current, &next, &[&alice]),
+            ClaimProgress::NoClaim
+        ));
+
+        let ClaimProgress::VrfSign(sign) = start_claim(1150, &current, &next, &[&alice, &bob])
+        else {
+            panic!()
+        };
+        assert_eq!(sign.slot_number(), 1150);
+        assert_eq!(sign.local_authorities_index(), 1);
+        assert_eq!(transcript_epoch_index(&sign), 1);
+    }
+
+    #[test]
+    fn no_claim_if_epoch_skipped() {
+        let alice = keypair(1);
+        let current = epoch(0, &[&alice], (1, 4), header::BabeAllowedSlots::PrimarySlots);
+        let next = epoch(1, &[&alice], (1, 4), header::BabeAllowedSlots::PrimarySlots);
+
+        // Slot 1199 is the last slot of the next epoch. Slot 1200 belongs to the epoch after,
+        // which isn't known.
+        assert!(matches!(
+            start_claim(1199, &current, &next, &[&alice]),
+            ClaimProgress::VrfSign(_)
+        ));
+        assert!(matches!(
+            start_claim(1200, &current, &next, &[&alice]),
+            ClaimProgress::NoClaim
+        ));
+    }
+
+    #[test]
+    fn primary_claim_below_threshold() {
+        let alice = keypair(1);
+        let current = epoch(
+            0,
+            &[&alice],
+            (999, 1000),
+            header::BabeAllowedSlots::PrimarySlots,
+        );
+        let next = epoch(
+            1,
+            &[&alice],
+            (999, 1000),
+            header::BabeAllowedSlots::PrimarySlots,
+        );
+
+        for slot in [1050, 1150] {
+            let ClaimProgress::VrfSign(sign) = start_claim(slot, &current, &next, &[&alice]) else {
+                panic!()
+            };
+            let (vrf_output, vrf_proof) = vrf_sign(&alice, &sign);
+            let ClaimProgress::Claimed(claim) = sign.inject_vrf_output(vrf_output, vrf_proof)
+            else {
+                panic!()
+            };
+
+            assert_eq!(claim.slot_number, slot);
+            assert_eq!(claim.local_authorities_index, 0);
+            assert_eq!(
+                claim.slot_start_from_unix_epoch,
+                Duration::from_millis(slot * SLOT_DURATION)
+            );
+            assert!(matches!(
+                claim.pre_digest,
+                header::BabePreDigest::Primary(header::BabePrimaryPreDigest {
+                    authority_index: 0,
+                    ..
+                })
+            ));
+
+            let success = verify_claim(&claim, &alice, &current, &next).unwrap();
+            assert!(success.is_primary_slot);
+            assert_eq!(success.slot_number, slot);
+        }
+    }
+
+    #[test]
+    fn no_primary_claim_above_threshold() {
+        let alice = keypair(1);
+        let current = epoch(0, &[&alice], (0, 1), header::BabeAllowedSlots::PrimarySlots);
+        let next = epoch(1, &[&alice], (0, 1), header::BabeAllowedSlots::PrimarySlots);
+
+        let ClaimProgress::VrfSign(sign) = start_claim(1050, &current, &next, &[&alice]) else {
+            panic!()
+        };
+        let (vrf_output, vrf_proof) = vrf_sign(&alice, &sign);
+        assert!(matches!(
+            sign.inject_vrf_output(vrf_output, vrf_proof),
+            ClaimProgress::NoClaim
+        ));
+    }
+
+    #[test]
+    fn invalid_vrf_output_not_claimed() {
+        let alice = keypair(1);
+        let current = epoch(
+            0,
+            &[&alice],
+            (999, 1000),
+            header::BabeAllowedSlots::PrimarySlots,
+        );
+        let next = epoch(
+            1,
+            &[&alice],
+            (999, 1000),
+            header::BabeAllowedSlots::PrimarySlots,
+        );
+
+        let ClaimProgress::VrfSign(sign) = start_claim(1050, &current, &next, &[&alice]) else {
+            panic!()
+        };
+        let (vrf_output, _) = vrf_sign(&alice, &sign);
+        assert!(matches!(
+            sign.inject_vrf_output(vrf_output, [0; 64]),
+            ClaimProgress::NoClaim
+        ));
+    }
+
+    #[test]
+    fn secondary_plain_claim() {
+        let alice = keypair(1);
+        let current = epoch(
+            0,
+            &[&alice],
+            (0, 1),
+            header::BabeAllowedSlots::PrimaryAndSecondaryPlainSlots,
+        );
+        let next = epoch(
+            1,
+            &[&alice],
+            (0, 1),
+            header::BabeAllowedSlots::PrimaryAndSecondaryPlainSlots,
+        );
+
+        for slot in [1050, 1150] {
+            let ClaimProgress::VrfSign(sign) = start_claim(slot, &current, &next, &[&alice]) else {
+                panic!()
+            };
+            let (vrf_output, vrf_proof) = vrf_sign(&alice, &sign);
+            let ClaimProgress::Claimed(claim) = sign.inject_vrf_output(vrf_output, vrf_proof)
+            else {
+                panic!()
+            };
+
+            assert!(matches!(
+                claim.pre_digest,
+                header::BabePreDigest::SecondaryPlain(header::BabeSecondaryPlainPreDigest {
+                    authority_index: 0,
+                    slot_number,
+                }) if slot_number == slot
+            ));
+
+            let success = verify_claim(&claim, &alice, &current, &next).unwrap();
+            assert!(!success.is_primary_slot);
+        }
+    }
+
+    #[test]
+    fn secondary_vrf_claim() {
+        let alice = keypair(1);
+        let current = epoch(
+            0,
+            &[&alice],
+            (0, 1),
+            header::BabeAllowedSlots::PrimaryAndSecondaryVrfSlots,
+        );
+        let next = epoch(
+            1,
+            &[&alice],
+            (0, 1),
+            header::BabeAllowedSlots::PrimaryAndSecondaryVrfSlots,
+        );
+
+        for slot in [1050, 1150] {
+            let ClaimProgress::VrfSign(sign) = start_claim(slot, &current, &next, &[&alice]) else {
+                panic!()
+            };
+            let (vrf_output, vrf_proof) = vrf_sign(&alice, &sign);
+            let ClaimProgress::Claimed(claim) = sign.inject_vrf_output(vrf_output, vrf_proof)
+            else {
+                panic!()
+            };
+
+            match &claim.pre_digest {
+                header::BabePreDigest::SecondaryVRF(digest) => {
+                    assert_eq!(digest.authority_index, 0);
+                    assert_eq!(digest.slot_number, slot);
+                    assert_eq!(digest.vrf_output, vrf_output);
+                    assert_eq!(digest.vrf_proof, vrf_proof);
+                }
+                _ => panic!(),
+            }
+
+            let success = verify_claim(&claim, &alice, &current, &next).unwrap();
+            assert!(!success.is_primary_slot);
+        }
+    }
+
+    #[test]
+    fn secondary_vrf_requires_vrf_signature() {
+        let alice = keypair(1);
+        let current = epoch(
+            0,
+            &[&alice],
+            (0, 1),
+            header::BabeAllowedSlots::PrimaryAndSecondaryVrfSlots,
+        );
+        let next = epoch(
+            1,
+            &[&alice],
+            (0, 1),
+            header::BabeAllowedSlots::PrimaryAndSecondaryVrfSlots,
+        );
+
+        let ClaimProgress::VrfSign(sign) = start_claim(1050, &current, &next, &[&alice]) else {
+            panic!()
+        };
+        assert!(matches!(sign.skip(), ClaimProgress::NoClaim));
+    }
+
+    #[test]
+    fn secondary_claim_only_by_secondary_author() {
+        let alice = keypair(1);
+        let bob = keypair(2);
+        let allowed_slots = header::BabeAllowedSlots::PrimaryAndSecondaryPlainSlots;
+        let current = epoch(0, &[&alice, &bob], (0, 1), allowed_slots);
+        let next = epoch(1, &[&alice, &bob], (0, 1), allowed_slots);
+
+        let slot = 1050;
+        let secondary_author = verify::babe::secondary_slot_author(&current.randomness, slot, 2);
+        let (author, other) = if secondary_author == 0 {
+            (&alice, &bob)
+        } else {
+            (&bob, &alice)
+        };
+
+        let ClaimProgress::VrfSign(sign) = start_claim(slot, &current, &next, &[other]) else {
+            panic!()
+        };
+        let (vrf_output, vrf_proof) = vrf_sign(other, &sign);
+        assert!(matches!(
+            sign.inject_vrf_output(vrf_output, vrf_proof),
+            ClaimProgress::NoClaim
+        ));
+
+        let ClaimProgress::VrfSign(sign) = start_claim(slot, &current, &next, &[other, author])
+        else {
+            panic!()
+        };
+        let (vrf_output, vrf_proof) = vrf_sign(other, &sign);
+        let ClaimProgress::VrfSign(sign) = sign.inject_vrf_output(vrf_output, vrf_proof) else {
+            panic!()
+        };
+        let (vrf_output, vrf_proof) = vrf_sign(author, &sign);
+        let ClaimProgress::Claimed(claim) = sign.inject_vrf_output(vrf_output, vrf_proof) else {
+            panic!()
+        };
+        assert_eq!(claim.local_authorities_index, 1);
+        assert!(matches!(
+            claim.pre_digest,
+            header::BabePreDigest::SecondaryPlain(header::BabeSecondaryPlainPreDigest {
+                authority_index,
+                ..
+            }) if authority_index == secondary_author
+        ));
+
+        verify_claim(&claim, author, &current, &next).unwrap();
+    }
+}
@@ -18,7 +18,8 @@
 // TODO: docs
 
 use crate::{
-    author::{aura, runtime},
+    author::{aura, babe, runtime},
+    chain::chain_information,
     executor::host,
     header,
     verify::inherents,
@@ -56,7 +57,35 @@ pub enum ConfigConsensus<'a, TLocAuth> {
         /// Must implement `Iterator<Item = &[u8; 32]>`.
         local_authorities: TLocAuth,
     },
-    // TODO: Babe isn't supported yet
+
+    /// Chain is using the Babe consensus algorithm.
+    Babe {
+        /// Time elapsed since [the Unix Epoch](https://en.wikipedia.org/wiki/Unix_time) (i.e.
+        /// 00:00:00 UTC on 1 January 1970), ignoring leap seconds.
+        now_from_unix_epoch: Duration,
+
+        /// Duration, in milliseconds, of a Babe slot.
+        slot_duration: NonZeroU64,
+
+        /// Number of slots per epoch in the Babe configuration.
+        slots_per_epoch: NonZeroU64,
+
+        /// Slot number of the current best block. Must be `None` if and only if the current best
+        /// block is the genesis block.
+        parent_slot_number: Option<u64>,
+
+        /// Epoch the current best block belongs to. Must be `None` if and only if the current
+        /// best block is the genesis block.
+        parent_block_epoch: Option<chain_information::BabeEpochInformationRef<'a>>,
+
+        /// Epoch that follows the epoch the current best block belongs to.
+        parent_block_next_epoch: chain_information::BabeEpochInformationRef<'a>,
+
+        /// Iterator to the list of Sr25519 public keys available locally.
+        ///
+        /// Must implement `Iterator<Item = &[u8; 32]>`.
+        local_authorities: TLocAuth,
+    },
 }
 
 /// Current state of the block building process.
@@ -70,6 +99,10 @@ pub enum Builder {
 
     /// Block production is ready to start.
     Ready(AuthoringStart),
+
+    /// A VRF signature must be generated in order to determine whether one of the local
+    /// authorities is allowed to produce a block. Only happens if the chain is using Babe.
+    VrfSign(VrfSign),
 }
 
 impl Builder {
@@ -79,7 +112,7 @@ impl Builder {
     ///
     /// Keep in mind that the builder should be reconstructed every time the best block changes.
     pub fn new<'a>(config: Config<'a, impl Iterator<Item = &'a [u8; 32]>>) -> Self {
-        let (slot, ready): (WaitSlotConsensus, bool) = match config.consensus {
+        match config.consensus {
             ConfigConsensus::Aura {
                 current_authorities,
                 local_authorities,
@@ -99,16 +132,105 @@ impl Builder {
                 debug_assert!(now_from_unix_epoch < consensus.slot_end_from_unix_epoch);
                 let ready = now_from_unix_epoch >= consensus.slot_start_from_unix_epoch;
 
-                (WaitSlotConsensus::Aura(consensus), ready)
+                Builder::from_slot(WaitSlotConsensus::Aura(consensus), ready)
             }
-        };
+            ConfigConsensus::Babe {
+                now_from_unix_epoch,
+                slot_duration,
+                slots_per_epoch,
+                parent_slot_number,
+                parent_block_epoch,
+                parent_block_next_epoch,
+                local_authorities,
+            } => Builder::from_babe_progress(
+                babe::next_slot_claim(babe::Config {
+                    now_from_unix_epoch,
+                    slot_duration,
+                    slots_per_epoch,
+                    parent_slot_number,
+                    parent_block_epoch,
+                    parent_block_next_epoch,
+                    local_authorities,
+                }),
+                now_from_unix_epoch,
+            ),
+        }
+    }
 
+    fn from_slot(slot: WaitSlotConsensus, ready: bool) -> Self {
         if ready {
             Builder::Ready(AuthoringStart { consensus: slot })
         } else {
             Builder::WaitSlot(WaitSlot { consensus: slot })
         }
     }
+
+    fn from_babe_progress(progress: babe::ClaimProgress, now_from_unix_epoch: Duration) -> Self {
+        match progress {
+            babe::ClaimProgress::VrfSign(inner) => Builder::VrfSign(VrfSign {
+                inner,
+                now_from_unix_epoch,
+            }),
+            babe::ClaimProgress::Claimed(claim) => {
+                debug_assert!(now_from_unix_epoch < claim.slot_end_from_unix_epoch);
+                let ready = now_from_unix_epoch >= claim.slot_start_from_unix_epoch;
+                Builder::from_slot(WaitSlotConsensus::Babe(claim), ready)
+            }
+            babe::ClaimProgress::NoClaim => Builder::Idle,
+        }
+    }
+}
+
+/// A VRF signature must be generated in order to determine whether one of the local authorities
+/// is allowed to produce a block.
+///
+/// The VRF signature must be generated using the Sr25519 private key whose public key is
+/// [`VrfSign::public_key`], and a transcript whose label is [`VrfSign::transcript_label`] and
+/// whose items are [`VrfSign::transcript_items`].
+#[must_use]
+#[derive(Debug)]
+pub struct VrfSign {
+    inner: babe::VrfSign,
+    now_from_unix_epoch: Duration,
+}
+
+impl VrfSign {
+    /// Returns the index within [`ConfigConsensus::Babe::local_authorities`] of the authority
+    /// that must generate the VRF signature.
+    pub fn local_authorities_index(&self) -> usize {
+        self.inner.local_authorities_index()
+    }
+
+    /// Returns the Sr25519 public key of the authority that must generate the VRF signature.
+    pub fn public_key(&self) -> &[u8; 32] {
+        self.inner.public_key()
+    }
+
+    /// Returns the label of the transcript to sign.
+    pub fn transcript_label(&self) -> &'static [u8] {
+        self.inner.transcript_label()
+    }
+
+    /// Returns the items of the transcript to sign.
+    pub fn transcript_items(
+        &'_ self,
+    ) -> impl Iterator<Item = (&'static [u8], either::Either<&'_ [u8], u64>)> + '_ {
+        self.inner.transcript_items()
+    }
+
+    /// Injects the VRF output (also called "pre-output") and proof generated by the authority.
+    pub fn inject_vrf_output(self, vrf_output: [u8; 32], vrf_proof: [u8; 64]) -> Builder {
+        Builder::from_babe_progress(
+            self.inner.inject_vrf_output(vrf_output, vrf_proof),
+            self.now_from_unix_epoch,
+        )
+    }
+
+    /// Indicates that the authority couldn't generate a VRF signature, for example because its
+    /// private key is no longer available.
+    pub fn skip(self) -> Builder {
+        Builder::from_babe_progress(self.inner.skip(), self.now_from_unix_epoch)
+    }
 }
 
 /// Current state of the block building process.
@@ -169,6 +291,7 @@ pub struct WaitSlot {
 #[derive(Debug)]
 enum WaitSlotConsensus {
     Aura(aura::SlotClaim),
+    Babe(babe::SlotClaim),
 }
 
 impl WaitSlot {
@@ -176,8 +299,9 @@ impl WaitSlot {
     /// the UNIX epoch, ignoring leap seconds).
     pub fn when(&self) -> Duration {
         // TODO: we can actually start building the block before our slot in some situations?
-        match self.consensus {
+        match &self.consensus {
             WaitSlotConsensus::Aura(claim) => claim.slot_start_from_unix_epoch,
+            WaitSlotConsensus::Babe(claim) => claim.slot_start_from_unix_epoch,
         }
     }
 
@@ -202,8 +326,9 @@ impl AuthoringStart {
     /// Returns when the authoring slot start, as a UNIX timestamp (i.e. number of seconds since
     /// the UNIX epoch, ignoring leap seconds).
     pub fn slot_start_from_unix_epoch(&self) -> Duration {
-        match self.consensus {
+        match &self.consensus {
             WaitSlotConsensus::Aura(claim) => claim.slot_start_from_unix_epoch,
+            WaitSlotConsensus::Babe(claim) => claim.slot_start_from_unix_epoch,
         }
     }
 
@@ -215,8 +340,9 @@ impl AuthoringStart {
     /// authored **and** propagated throughout the entire peer-to-peer network before the slot
     /// ends.
     pub fn slot_end_from_unix_epoch(&self) -> Duration {
-        match self.consensus {
+        match &self.consensus {
             WaitSlotConsensus::Aura(claim) => claim.slot_end_from_unix_epoch,
+            WaitSlotConsensus::Babe(claim) => claim.slot_end_from_unix_epoch,
         }
     }
 
@@ -228,12 +354,15 @@ impl AuthoringStart {
             parent_number: config.parent_number,
             parent_runtime: config.parent_runtime,
             block_body_capacity: config.block_body_capacity,
-            consensus_digest_log_item: match self.consensus {
+            consensus_digest_log_item: match &self.consensus {
                 WaitSlotConsensus::Aura(slot) => {
                     runtime::ConfigPreRuntime::Aura(header::AuraPreDigest {
                         slot_number: slot.slot_number,
                     })
                 }
+                WaitSlotConsensus::Babe(slot) => {
+                    runtime::ConfigPreRuntime::Babe((&slot.pre_digest).into())
+                }
             },
             max_log_level: config.max_log_level,
             calculate_trie_changes: config.calculate_trie_changes,
@@ -463,10 +592,12 @@ impl Seal {
     /// Returns the index within the list of authorities of the authority that must sign the
     /// block.
     ///
-    /// See [`ConfigConsensus::Aura::local_authorities`].
+    /// See [`ConfigConsensus::Aura::local_authorities`] and
+    /// [`ConfigConsensus::Babe::local_authorities`].
     pub fn authority_index(&self) -> usize {
-        match self.shared.slot_claim {
+        match &self.shared.slot_claim {
             WaitSlotConsensus::Aura(slot) => slot.local_authorities_index,
+            WaitSlotConsensus::Babe(slot) => slot.local_authorities_index,
         }
     }
 
@@ -484,7 +615,10 @@ impl Seal {
         self.block.scale_encoded_header = header
             .scale_encoding_with_extra_digest_item(
                 self.shared.block_number_bytes,
-                header::DigestItemRef::AuraSeal(&signature),
+                match self.shared.slot_claim {
+                    WaitSlotConsensus::Aura(_) => header::DigestItemRef::AuraSeal(&signature),
+                    WaitSlotConsensus::Babe(_) => header::DigestItemRef::BabeSeal(&signature),
+                },
             )
             .fold(Vec::with_capacity(8192), |mut a, b| {
                 a.extend_from_slice(b.as_ref());
@@ -566,8 +700,11 @@ impl Shared {
                     // Injecting the inherent is guaranteed to be done only once per block.
                     inner = a.inject_inherents(self.inherent_data.take().unwrap());
                 }
-                runtime::BlockBuild::ApplyExtrinsic(a) => {
-                    inner = a.finish();
+                runtime::BlockBuild::ApplyExtrinsic(inner) => {
+                    break BuilderAuthoring::ApplyExtrinsic(ApplyExtrinsic {
+                        inner,
+                        shared: self,
+                    })
                 }
                 runtime::BlockBuild::ApplyExtrinsicResult { result, resume } => {
                     break BuilderAuthoring::ApplyExtrinsicResult {
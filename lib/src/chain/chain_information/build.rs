@@ -108,6 +108,8 @@ pub enum Error {
     },
     /// Runtime has called an offchain worker host function.
     OffchainWorkerHostFunction,
+    /// Runtime has called a keystore host function.
+    KeystoreHostFunction,
     /// Failed to decode the output of the `AuraApi_slot_duration` runtime call.
     AuraSlotDurationOutputDecode,
     /// Failed to decode the output of the `AuraApi_authorities` runtime call.
@@ -507,6 +509,7 @@ impl ChainInformationBuild {
                 max_log_level: 0,
                 storage_main_trie_changes: Default::default(),
                 calculate_trie_changes: false,
+                execution_tracing: None,
             });
 
             let vm = match vm_start_result {
@@ -845,6 +848,14 @@ impl ChainInformationBuild {
                         virtual_machine,
                     };
                 }
+                req @ (runtime_host::RuntimeHostVm::KeystorePublicKeys(_)
+                | runtime_host::RuntimeHostVm::KeystoreGenerate(_)
+                | runtime_host::RuntimeHostVm::KeystoreSign(_)) => {
+                    break ChainInformationBuild::Finished {
+                        result: Err(Error::KeystoreHostFunction),
+                        virtual_machine: req.into_prototype(),
+                    };
+                }
             }
         }
     }
@@ -44,6 +44,7 @@ use crate::{
 
 use alloc::{
     boxed::Box,
+    collections::BTreeMap,
     string::{String, ToString as _},
     vec::Vec,
 };
@@ -65,11 +66,16 @@ impl ChainSpec {
             .map_err(ParseErrorInner::Serde)
             .map_err(ParseError)?;
 
-        // TODO: we don't support child tries in the genesis block
-        assert!(match &client_spec.genesis {
-            structs::Genesis::Raw(genesis) => genesis.children_default.is_empty(),
-            structs::Genesis::StateRootHash(_) => true,
-        });
+        // Child tries must be in `childrenDefault` rather than in the main trie.
+        if let structs::Genesis::Raw(genesis) = &client_spec.genesis {
+            if genesis
+                .top
+                .keys()
+                .any(|key| key.0.starts_with(CHILD_STORAGE_PREFIX))
+            {
+                return Err(ParseError(ParseErrorInner::Other));
+            }
+        }
 
         if client_spec.relay_chain.is_some() != client_spec.para_id.is_some() {
             return Err(ParseError(ParseErrorInner::Other));
@@ -111,6 +117,9 @@ impl ChainSpec {
             heap_pages,
             exec_hint: executor::vm::ExecHint::Oneshot,
             allow_unresolved_imports: true,
+            compiled_module_cache: None,
+            resource_limits: Default::default(),
+            allocation_strategy: Default::default(),
         })
         .map_err(FromGenesisStorageError::VmInitialization)?;
 
@@ -120,58 +129,65 @@ impl ChainSpec {
             .state_version
             .unwrap_or(trie::TrieEntryVersion::V0);
 
+        let child_tries_roots = child_tries_roots(&genesis_storage, state_version);
+        let main_trie = [&genesis_storage.raw.top, &child_tries_roots];
+
         let mut chain_information_build = build::ChainInformationBuild::new(build::Config {
             finalized_block_header: build::ConfigFinalizedBlockHeader::Genesis {
-                state_trie_root_hash: {
-                    match self.genesis_storage() {
-                        GenesisStorage::TrieRootHash(hash) => *hash,
-                        GenesisStorage::Items(genesis_storage) => {
-                            let mut calculation =
-                                trie::calculate_root::root_merkle_value(trie::HashFunction::Blake2);
-
-                            loop {
-                                match calculation {
-                                    trie::calculate_root::RootMerkleValueCalculation::Finished {
-                                        hash,
-                                        ..
-                                    } => break hash,
-                                    trie::calculate_root::RootMerkleValueCalculation::NextKey(next_key) => {
-                                        // TODO: borrowchecker erroneously thinks that `outcome` borrows `next_key`
-                                        let outcome = genesis_storage.next_key(next_key.key_before(), next_key.or_equal(), next_key.prefix()).map(|k| k.collect::<Vec<_>>().into_iter());
-                                        calculation = next_key.inject_key(outcome);
-                                    }
-                                    trie::calculate_root::RootMerkleValueCalculation::StorageValue(
-                                        val,
-                                    ) => {
-                                        let key: alloc::vec::Vec<u8> = val.key().collect();
-                                        let value = genesis_storage.value(&key[..]);
-                                        calculation = val.inject(value.map(move |v| (v, state_version)));
-                                    }
-                                }
-                            }
-                        }
-                    }
-                },
+                state_trie_root_hash: trie_root_hash(&main_trie, state_version),
             },
             block_number_bytes: usize::from(self.block_number_bytes()),
             runtime: vm_prototype,
         });
 
+        // Returns the list of entries of the trie designated by `child_trie`.
+        let trie_entries = |child_trie: Option<&[u8]>| match child_trie {
+            None => main_trie.to_vec(),
+            Some(child_trie) => genesis_storage
+                .raw
+                .children_default
+                .get(child_trie)
+                .into_iter()
+                .collect::<Vec<_>>(),
+        };
+
         let (chain_info, vm_prototype) = loop {
             match chain_information_build {
                 build::ChainInformationBuild::InProgress(build::InProgress::StorageGet(get)) => {
-                    // TODO: child tries not supported
-                    let value = genesis_storage.value(get.key().as_ref());
+                    let entries = trie_entries(get.child_trie().as_ref().map(|c| c.as_ref()));
+                    let value = trie_value(&entries, get.key().as_ref());
                     chain_information_build =
                         get.inject_value(value.map(|v| (iter::once(v), state_version)));
                 }
-                build::ChainInformationBuild::InProgress(build::InProgress::NextKey(_nk)) => {
-                    todo!() // TODO:
+                build::ChainInformationBuild::InProgress(build::InProgress::NextKey(nk)) => {
+                    let entries = trie_entries(nk.child_trie().as_ref().map(|c| c.as_ref()));
+
+                    let mut search =
+                        trie::branch_search::start_branch_search(trie::branch_search::Config {
+                            key_before: nk.key(),
+                            or_equal: nk.or_equal(),
+                            prefix: nk.prefix(),
+                            no_branch_search: !nk.branch_nodes(),
+                        });
+
+                    let next_key = loop {
+                        let key_before = search.key_before().collect::<Vec<_>>();
+                        let prefix = search.prefix().collect::<Vec<_>>();
+                        let next = trie_next_key(&entries, &key_before, search.or_equal(), &prefix);
+                        match search.inject(next.map(|k| k.iter().copied())) {
+                            trie::branch_search::BranchSearch::Found {
+                                branch_trie_node_key,
+                            } => break branch_trie_node_key,
+                            trie::branch_search::BranchSearch::NextKey(next) => search = next,
+                        }
+                    };
+
+                    chain_information_build = nk.inject_key(next_key);
                 }
                 build::ChainInformationBuild::InProgress(
-                    build::InProgress::ClosestDescendantMerkleValue(_mv),
+                    build::InProgress::ClosestDescendantMerkleValue(mv),
                 ) => {
-                    todo!() // TODO:
+                    chain_information_build = mv.resume_unknown();
                 }
                 build::ChainInformationBuild::Finished {
                     result: Err(err), ..
@@ -391,12 +407,16 @@ pub struct GenesisStorageItems<'a> {
 }
 
 impl<'a> GenesisStorageItems<'a> {
-    /// Returns the list of storage keys and values of the genesis block.
+    /// Returns the list of storage keys and values of the main trie of the genesis block.
+    ///
+    /// The entries of the main trie that point to the child tries aren't included. Use
+    /// [`GenesisStorageItems::child_tries`] to access the child tries.
     pub fn iter(&self) -> impl ExactSizeIterator<Item = (&[u8], &[u8])> + Clone {
         self.raw.top.iter().map(|(k, v)| (&k.0[..], &v.0[..]))
     }
 
-    /// Find the storage key that immediately follows `key_before` in the list of storage items.
+    /// Find the storage key that immediately follows `key_before` in the list of storage items
+    /// of the main trie.
     ///
     /// If `or_equal` is `true`, then `key_before` is returned if it corresponds to a key in the
     /// storage.
@@ -409,25 +429,169 @@ impl<'a> GenesisStorageItems<'a> {
         or_equal: bool,
         prefix: impl Iterator<Item = u8>,
     ) -> Option<impl Iterator<Item = u8> + 'a> {
-        let lower_bound = if or_equal {
-            Bound::Included(structs::HexString(key_before.collect::<Vec<_>>()))
-        } else {
-            Bound::Excluded(structs::HexString(key_before.collect::<Vec<_>>()))
-        };
+        trie_next_key(
+            &[&self.raw.top],
+            &key_before.collect::<Vec<_>>(),
+            or_equal,
+            &prefix.collect::<Vec<_>>(),
+        )
+        .map(|k| k.iter().copied())
+    }
 
+    /// Returns the genesis storage value for a specific key of the main trie.
+    ///
+    /// Returns `None` if there is no value corresponding to that key.
+    pub fn value(&self, key: &[u8]) -> Option<&[u8]> {
+        trie_value(&[&self.raw.top], key)
+    }
+
+    /// Returns the entries of the main trie of the genesis block that point to its non-empty
+    /// child tries.
+    ///
+    /// Each entry consists of the key in the main trie, which is `:child_storage:default:`
+    /// followed with the key of the child trie, and of the hash of the root of the child trie.
+    /// Combined with [`GenesisStorageItems::iter`], this yields the full content of the main
+    /// trie.
+    ///
+    /// The hashes of the roots depend on the `state_version` of the genesis runtime.
+    pub fn child_tries_roots(
+        &self,
+        state_version: trie::TrieEntryVersion,
+    ) -> impl ExactSizeIterator<Item = (Vec<u8>, [u8; 32])> {
+        child_tries_roots(self, state_version)
+            .into_iter()
+            .map(|(key, root_hash)| (key.0, <[u8; 32]>::try_from(&root_hash.0[..]).unwrap()))
+    }
+
+    /// Returns the list of child tries of the genesis block.
+    pub fn child_tries(&self) -> impl ExactSizeIterator<Item = GenesisStorageChildTrie<'a>> + 'a {
         self.raw
-            .top
-            .range((lower_bound, Bound::Unbounded))
-            .next()
-            .filter(|(k, _)| k.0.iter().copied().zip(prefix).all(|(a, b)| a == b))
-            .map(|(k, _)| k.0.iter().copied())
+            .children_default
+            .iter()
+            .map(|(child_trie, entries)| GenesisStorageChildTrie {
+                child_trie: &child_trie.0,
+                entries,
+            })
     }
+}
 
-    /// Returns the genesis storage value for a specific key.
+/// See [`GenesisStorageItems::child_tries`].
+#[derive(Clone)]
+pub struct GenesisStorageChildTrie<'a> {
+    child_trie: &'a [u8],
+    entries: &'a BTreeMap<structs::HexString, structs::HexString>,
+}
+
+impl<'a> GenesisStorageChildTrie<'a> {
+    /// Returns the key of this child trie, without the `:child_storage:default:` prefix.
+    pub fn child_trie(&self) -> &'a [u8] {
+        self.child_trie
+    }
+
+    /// Returns the list of storage keys and values of this child trie.
+    pub fn iter(&self) -> impl ExactSizeIterator<Item = (&'a [u8], &'a [u8])> + Clone {
+        self.entries.iter().map(|(k, v)| (&k.0[..], &v.0[..]))
+    }
+
+    /// Returns the genesis storage value for a specific key of this child trie.
     ///
     /// Returns `None` if there is no value corresponding to that key.
-    pub fn value(&self, key: &[u8]) -> Option<&[u8]> {
-        self.raw.top.get(key).map(|value| &value.0[..])
+    pub fn value(&self, key: &[u8]) -> Option<&'a [u8]> {
+        trie_value(&[self.entries], key)
+    }
+}
+
+/// Prefix of all the keys of the main trie that correspond to child tries.
+const CHILD_STORAGE_PREFIX: &[u8] = b":child_storage:";
+
+/// Prefix of all the keys of the main trie that correspond to default child tries.
+const DEFAULT_CHILD_STORAGE_PREFIX: &[u8] = b":child_storage:default:";
+
+/// Find the storage key that immediately follows `key_before` in the union of the given lists
+/// of entries.
+fn trie_next_key<'a>(
+    entries: &[&'a BTreeMap<structs::HexString, structs::HexString>],
+    key_before: &[u8],
+    or_equal: bool,
+    prefix: &[u8],
+) -> Option<&'a [u8]> {
+    let lower_bound = if or_equal {
+        Bound::Included(key_before)
+    } else {
+        Bound::Excluded(key_before)
+    };
+
+    entries
+        .iter()
+        .filter_map(|entries| {
+            entries
+                .range::<[u8], _>((lower_bound, Bound::Unbounded))
+                .next()
+        })
+        .map(|(k, _)| &k.0[..])
+        .min()
+        .filter(|k| k.starts_with(prefix))
+}
+
+/// Returns the entries of the main trie that refer to the child tries of the given genesis
+/// storage.
+///
+/// Child tries are found in the main trie under the key `:child_storage:default:` followed with
+/// the key of the child trie, and whose value is the hash of the root of the child trie. Empty
+/// child tries aren't included in the main trie.
+fn child_tries_roots(
+    genesis_storage: &GenesisStorageItems,
+    state_version: trie::TrieEntryVersion,
+) -> BTreeMap<structs::HexString, structs::HexString> {
+    genesis_storage
+        .child_tries()
+        .filter(|child_trie| child_trie.iter().len() != 0)
+        .map(|child_trie| {
+            let mut key = DEFAULT_CHILD_STORAGE_PREFIX.to_vec();
+            key.extend_from_slice(child_trie.child_trie());
+            let root_hash = trie_root_hash(&[child_trie.entries], state_version);
+            (
+                structs::HexString(key),
+                structs::HexString(root_hash.to_vec()),
+            )
+        })
+        .collect()
+}
+
+/// Returns the storage value associated to the given key in the union of the given lists of
+/// entries.
+fn trie_value<'a>(
+    entries: &[&'a BTreeMap<structs::HexString, structs::HexString>],
+    key: &[u8],
+) -> Option<&'a [u8]> {
+    entries
+        .iter()
+        .find_map(|entries| entries.get(key))
+        .map(|value| &value.0[..])
+}
+
+/// Calculates the hash of the root of the trie made of the union of the given lists of entries.
+fn trie_root_hash(
+    entries: &[&BTreeMap<structs::HexString, structs::HexString>],
+    state_version: trie::TrieEntryVersion,
+) -> [u8; 32] {
+    let mut calculation = trie::calculate_root::root_merkle_value(trie::HashFunction::Blake2);
+
+    loop {
+        match calculation {
+            trie::calculate_root::RootMerkleValueCalculation::Finished { hash, .. } => break hash,
+            trie::calculate_root::RootMerkleValueCalculation::NextKey(next_key) => {
+                let key_before = next_key.key_before().collect::<Vec<_>>();
+                let prefix = next_key.prefix().collect::<Vec<_>>();
+                let outcome = trie_next_key(entries, &key_before, next_key.or_equal(), &prefix);
+                calculation = next_key.inject_key(outcome.map(|k| k.iter().copied()));
+            }
+            trie::calculate_root::RootMerkleValueCalculation::StorageValue(val) => {
+                let key = val.key().collect::<Vec<_>>();
+                let value = trie_value(entries, &key);
+                calculation = val.inject(value.map(move |v| (v, state_version)));
+            }
+        }
     }
 }
 
@@ -569,7 +733,10 @@ pub enum CheckpointToChainInformationError {
 
 #[cfg(test)]
 mod tests {
-    use super::{Bootnode, ChainSpec, CheckpointToChainInformationError};
+    use super::{
+        child_tries_roots, trie, trie_root_hash, Bootnode, ChainSpec,
+        CheckpointToChainInformationError, GenesisStorage,
+    };
 
     #[test]
     fn can_decode_polkadot_genesis() {
@@ -695,6 +862,68 @@ mod tests {
         .is_err());
     }
 
+    #[test]
+    fn genesis_child_tries() {
+        let chain_spec = ChainSpec::from_json_bytes(
+            r#"{
+            "name": "Test",
+            "id": "test",
+            "bootNodes": [],
+            "genesis": {
+              "raw": {
+                "top": {
+                  "0x01": "0x02"
+                },
+                "childrenDefault": {
+                  "0xaabb": {
+                    "0x03": "0x04",
+                    "0x05": "0x06"
+                  }
+                }
+              }
+            }
+          }
+          "#,
+        )
+        .unwrap();
+
+        let genesis_storage = chain_spec.genesis_storage().into_genesis_items().unwrap();
+        assert_eq!(genesis_storage.iter().len(), 1);
+        assert_eq!(genesis_storage.value(&[0x01]), Some(&[0x02][..]));
+
+        let child_tries = genesis_storage.child_tries().collect::<Vec<_>>();
+        assert_eq!(child_tries.len(), 1);
+        assert_eq!(child_tries[0].child_trie(), &[0xaa, 0xbb][..]);
+        assert_eq!(
+            child_tries[0].iter().collect::<Vec<_>>(),
+            vec![(&[0x03][..], &[0x04][..]), (&[0x05][..], &[0x06][..])]
+        );
+        assert_eq!(child_tries[0].value(&[0x05]), Some(&[0x06][..]));
+        assert_eq!(child_tries[0].value(&[0x01]), None);
+    }
+
+    #[test]
+    fn child_trie_in_main_trie_forbidden() {
+        // `0x3a6368696c645f73746f726167653a64656661756c743a` is `:child_storage:default:`.
+        assert!(ChainSpec::from_json_bytes(
+            r#"{
+            "name": "Test",
+            "id": "test",
+            "bootNodes": [],
+            "genesis": {
+              "raw": {
+                "top": {
+                  "0x3a6368696c645f73746f726167653a64656661756c743aaabb": "0x00"
+                },
+                "childrenDefault": {}
+              }
+            }
+          }
+          "#,
+        )
+        .is_err());
+    }
+
     #[test]
     fn issue_598() {
         // Regression test for a panic.
@@ -892,4 +1121,71 @@ mod tests {
             Err(CheckpointToChainInformationError::GenesisBlockCheckpoint)
         ));
     }
+
+    #[test]
+    fn genesis_state_root_with_child_trie() {
+        let specs = ChainSpec::from_json_bytes(
+            r#"{
+            "name": "Test",
+            "id": "test",
+            "bootNodes": [],
+            "genesis": {
+              "raw": {
+                "top": {
+                  "0x3a666f6f": "0x01"
+                },
+                "childrenDefault": {
+                  "0x626172": {
+                    "0x0102": "0x03",
+                    "0x0103": "0x04"
+                  },
+                  "0x656d707479": {}
+                }
+              }
+            }
+          }
+          "#,
+        )
+        .unwrap();
+
+        let GenesisStorage::Items(genesis_storage) = specs.genesis_storage() else {
+            panic!()
+        };
+
+        // The expected values have been calculated by hand by encoding the trie nodes according
+        // to the layout of Substrate's version 0 trie, then hashing them.
+        let child_tries_roots = child_tries_roots(&genesis_storage, trie::TrieEntryVersion::V0);
+        assert_eq!(
+            child_tries_roots
+                .iter()
+                .map(|(k, v)| (&k.0[..], &v.0[..]))
+                .collect::<Vec<_>>(),
+            vec![(
+                &b":child_storage:default:bar"[..],
+                &[
+                    0x0c, 0xee, 0xe1, 0x10, 0xb4, 0xc2, 0x5c, 0xf3, 0xd2, 0xc6, 0x4e, 0x0f, 0x01,
+                    0xd6, 0xd3, 0x56, 0xf1, 0xa6, 0x3c, 0x47, 0x31, 0x59, 0xca, 0x8d, 0x96, 0xea,
+                    0xf1, 0x0f, 0x86, 0xfb, 0x00, 0x65
+                ][..]
+            )]
+        );
+        assert!(genesis_storage
+            .child_tries_roots(trie::TrieEntryVersion::V0)
+            .map(|(k, v)| (k, v.to_vec()))
+            .eq(child_tries_roots
+                .iter()
+                .map(|(k, v)| (k.0.clone(), v.0.clone()))));
+
+        assert_eq!(
+            trie_root_hash(
+                &[&genesis_storage.raw.top, &child_tries_roots],
+                trie::TrieEntryVersion::V0
+            ),
+            [
+                0x1e, 0x6d, 0x4e, 0x9a, 0xc9, 0x07, 0x01, 0x42, 0xd1, 0xd5, 0x7a, 0x49, 0x2c, 0x7b,
+                0x27, 0x1b, 0x30, 0x59, 0xd8, 0xf1, 0x23, 0xef, 0x99, 0x8b, 0x00, 0xb2, 0x48, 0xd8,
+                0x99, 0xf7, 0xe4, 0xe2
+            ]
+        );
+    }
 }
@@ -105,7 +105,9 @@ pub(super) enum Genesis {
 #[serde(deny_unknown_fields)]
 pub(super) struct RawGenesis {
     pub(super) top: BTreeMap<HexString, HexString>,
-    pub(super) children_default: BTreeMap<HexString, ChildRawStorage>,
+    /// Content of the child tries, indexed by the key of the child trie (i.e. without the
+    /// `:child_storage:default:` prefix).
+    pub(super) children_default: BTreeMap<HexString, BTreeMap<HexString, HexString>>,
 }
 
 #[derive(Debug, Clone, PartialEq, Eq, PartialOrd, Ord, Hash)]
@@ -144,14 +146,6 @@ impl<'a> serde::Deserialize<'a> for HexString {
     }
 }
 
-#[derive(Debug, Clone, Serialize, Deserialize)]
-#[serde(rename_all = "camelCase")]
-#[serde(deny_unknown_fields)]
-pub(super) struct ChildRawStorage {
-    pub(super) child_info: Vec<u8>,
-    pub(super) child_type: u32,
-}
-
 #[derive(Debug, Clone, PartialEq, Eq, Hash)]
 pub(super) struct HashHexString(pub(super) [u8; 32]);
 
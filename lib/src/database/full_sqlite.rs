@@ -35,6 +35,9 @@
 //! Any block that isn't an ancestor or descendant will be removed. Reverting finalization is
 //! not supported.
 //!
+//! Use [`SqliteFullDatabase::reset`] to replace the entire content of the database with a new
+//! finalized block, for example after a warp sync.
+//!
 //! In order to minimize disk usage, it is not possible to efficiently retrieve the storage items
 //! of blocks that are ancestors of the finalized block. When a block is finalized, the storage of
 //! its ancestors is lost, and the only way to reconstruct it is to execute all blocks starting
@@ -70,14 +73,14 @@
 #![cfg(feature = "database-sqlite")]
 #![cfg_attr(docsrs, doc(cfg(feature = "database-sqlite")))]
 
-use crate::{chain::chain_information, header, util};
+use crate::{chain::chain_information, header, trie, util};
 
 use alloc::borrow::Cow;
 use core::{fmt, iter, num::NonZeroU64};
 use parking_lot::Mutex;
 use rusqlite::OptionalExtension as _;
 
-pub use open::{open, Config, ConfigTy, DatabaseEmpty, DatabaseOpen};
+pub use open::{open, Config, ConfigTy, DatabaseEmpty, DatabaseOpen, PruningMode};
 
 mod open;
 mod tests;
@@ -100,6 +103,9 @@ pub struct SqliteFullDatabase {
 
     /// Number of bytes used to encode the block number.
     block_number_bytes: usize,
+
+    /// Which storage to keep in the database.
+    pruning: PruningMode,
 }
 
 impl SqliteFullDatabase {
@@ -396,6 +402,69 @@ impl SqliteFullDatabase {
         Ok(())
     }
 
+    /// Removes all the blocks and all the storage from the database, then inserts the given
+    /// finalized block in the same way as [`DatabaseEmpty::initialize`] would.
+    ///
+    /// The offchain storage is left untouched.
+    ///
+    /// This is useful in situations where the finalized block has been obtained in a way other
+    /// than by executing all its ancestors, for example by warp syncing.
+    // TODO: Passing SameAsParent is invalid, document and error
+    pub fn reset<'a>(
+        &self,
+        chain_information: impl Into<chain_information::ChainInformationRef<'a>>,
+        finalized_block_body: impl ExactSizeIterator<Item = &'a [u8]>,
+        finalized_block_justification: Option<Vec<u8>>,
+        finalized_block_storage_entries: impl Iterator<Item = InsertTrieNode<'a>>,
+        finalized_block_state_version: u8,
+    ) -> Result<(), AccessError> {
+        let mut database = self.database.lock();
+
+        // Start a transaction in order to not leave the database in an empty state if something
+        // goes wrong.
+        let transaction = database
+            .transaction()
+            .map_err(|err| AccessError::Corrupted(CorruptedError::Internal(InternalError(err))))?;
+
+        // The rows are deleted in no particular order.
+        // Note that this is immediately disabled again when we `COMMIT` later down below.
+        transaction
+            .execute("PRAGMA defer_foreign_keys = ON", ())
+            .map_err(|err| AccessError::Corrupted(CorruptedError::Internal(InternalError(err))))?;
+
+        transaction
+            .execute_batch(
+                r#"
+            DELETE FROM blocks_body;
+            DELETE FROM blocks;
+            DELETE FROM trie_node_child;
+            DELETE FROM trie_node_storage;
+            DELETE FROM trie_node;
+            DELETE FROM grandpa_triggered_authorities;
+            DELETE FROM grandpa_scheduled_authorities;
+            DELETE FROM aura_finalized_authorities;
+            DELETE FROM meta;
+        "#,
+            )
+            .map_err(|err| AccessError::Corrupted(CorruptedError::Internal(InternalError(err))))?;
+
+        open::insert_finalized_block(
+            &transaction,
+            self.block_number_bytes,
+            chain_information.into(),
+            finalized_block_body,
+            finalized_block_justification,
+            finalized_block_storage_entries,
+            finalized_block_state_version,
+        )?;
+
+        transaction
+            .commit()
+            .map_err(|err| AccessError::Corrupted(CorruptedError::Internal(InternalError(err))))?;
+
+        Ok(())
+    }
+
     /// Changes the finalized block to the given one.
     ///
     /// The block must have been previously inserted using [`SqliteFullDatabase::insert`], otherwise
@@ -526,8 +595,27 @@ impl SqliteFullDatabase {
             allowed_parents = next_iter_allowed_parents;
         }
 
+        // Remove the storage of the finalized blocks that are now too old to be kept.
+        if let PruningMode::Pruned {
+            num_finalized_blocks,
+        } = self.pruning
+        {
+            // Blocks whose height is inferior or equal to `current_finalized - num_finalized_blocks`
+            // have already been pruned in the past.
+            let first_height = (current_finalized + 1).saturating_sub(num_finalized_blocks.get());
+            if let Some(last_height) = new_finalized_header
+                .number
+                .checked_sub(num_finalized_blocks.get())
+            {
+                for height in first_height..=last_height {
+                    for block_hash in block_hashes_by_number(&transaction, height)? {
+                        purge_block_storage(&transaction, &block_hash)?;
+                    }
+                }
+            }
+        }
+
         // Now update the finalized block storage.
-        // TODO: prune the storage of old blocks?
         for height in current_finalized + 1..=new_finalized_header.number {
             let block_hash =
                 {
@@ -648,6 +736,153 @@ impl SqliteFullDatabase {
         Ok(())
     }
 
+    /// Stores the SCALE-encoded GrandPa justification of the given block, overwriting any
+    /// previously-stored justification.
+    ///
+    /// The justification is later returned by
+    /// [`SqliteFullDatabase::finalized_blocks_with_justification`] once the block is finalized.
+    ///
+    /// The validity of the justification isn't verified.
+    pub fn set_block_justification(
+        &self,
+        block_hash: &[u8; 32],
+        scale_encoded_justification: &[u8],
+    ) -> Result<(), SetJustificationError> {
+        let connection = self.database.lock();
+
+        let num_updated = connection
+            .prepare_cached(r#"UPDATE blocks SET justification = ? WHERE hash = ?"#)
+            .map_err(|err| CorruptedError::Internal(InternalError(err)))
+            .map_err(AccessError::Corrupted)?
+            .execute((scale_encoded_justification, &block_hash[..]))
+            .map_err(|err| CorruptedError::Internal(InternalError(err)))
+            .map_err(AccessError::Corrupted)?;
+
+        if num_updated == 0 {
+            return Err(SetJustificationError::UnknownBlock);
+        }
+
+        Ok(())
+    }
+
+    /// Returns the SCALE-encoded headers and GrandPa justifications of the blocks of the
+    /// finalized chain whose number is superior or equal to `first_block_number` and that have a
+    /// justification stored in the database, ordered by increasing block number.
+    ///
+    /// At most `max_blocks` blocks are returned.
+    ///
+    /// See also [`SqliteFullDatabase::set_block_justification`].
+    pub fn finalized_blocks_with_justification(
+        &self,
+        first_block_number: u64,
+        max_blocks: usize,
+    ) -> Result<Vec<(Vec<u8>, Vec<u8>)>, AccessError> {
+        let connection = self.database.lock();
+
+        let finalized_number = finalized_num(&connection)?;
+        let first_block_number = match i64::try_from(first_block_number) {
+            Ok(n) => n,
+            Err(_) => return Ok(Vec::new()),
+        };
+
+        let result = connection
+            .prepare_cached(
+                r#"SELECT header, justification FROM blocks WHERE number >= ? AND number <= ? AND justification IS NOT NULL ORDER BY number ASC LIMIT ?"#,
+            )
+            .map_err(|err| CorruptedError::Internal(InternalError(err)))?
+            .query_map(
+                (
+                    first_block_number,
+                    i64::try_from(finalized_number).unwrap(),
+                    i64::try_from(max_blocks).unwrap_or(i64::max_value()),
+                ),
+                |row| Ok((row.get::<_, Vec<u8>>(0)?, row.get::<_, Vec<u8>>(1)?)),
+            )
+            .map_err(|err| CorruptedError::Internal(InternalError(err)))?
+            .collect::<Result<Vec<_>, _>>()
+            .map_err(|err| CorruptedError::Internal(InternalError(err)))?;
+
+        Ok(result)
+    }
+
+    /// Returns the partial key, children and storage value of the trie node whose Merkle value
+    /// is given as parameter, or `None` if the database doesn't contain this node.
+    ///
+    /// The Merkle value of the root node of the storage of a block can be found in the header of
+    /// this block. If the database doesn't contain the root node, then the storage of this block
+    /// has been pruned or was never stored.
+    ///
+    /// > **Note**: If this method is called twice times in a row with the same Merkle value, it
+    /// >           is possible for the first time to return `Some` and the second time to return
+    /// >           `None`, in case the node has since been removed from the database.
+    pub fn trie_node(&self, merkle_value: &[u8]) -> Result<Option<TrieNode>, AccessError> {
+        let connection = self.database.lock();
+
+        let node = connection
+            .prepare_cached(
+                r#"
+            SELECT trie_node.partial_key, COALESCE(trie_node_storage.value, trie_node_storage.trie_root_ref), trie_node_storage.trie_entry_version
+            FROM trie_node
+            LEFT JOIN trie_node_storage ON trie_node_storage.node_hash = trie_node.hash
+            WHERE trie_node.hash = ?"#,
+            )
+            .map_err(|err| CorruptedError::Internal(InternalError(err)))?
+            .query_row((merkle_value,), |row| {
+                let partial_key = row.get::<_, Vec<u8>>(0)?;
+                let value = row.get::<_, Option<Vec<u8>>>(1)?;
+                let trie_entry_version = row.get::<_, Option<i64>>(2)?;
+                Ok((partial_key, value, trie_entry_version))
+            })
+            .optional()
+            .map_err(|err| CorruptedError::Internal(InternalError(err)))?;
+
+        let Some((partial_key_nibbles, storage_value, trie_entry_version)) = node else {
+            return Ok(None);
+        };
+
+        if partial_key_nibbles.iter().any(|n| *n >= 16) {
+            return Err(AccessError::Corrupted(CorruptedError::InvalidNibble));
+        }
+
+        let storage_value = match (storage_value, trie_entry_version) {
+            (Some(value), Some(version)) => Some((
+                value,
+                u8::try_from(version).map_err(|_| CorruptedError::InvalidTrieEntryVersion)?,
+            )),
+            (None, _) => None,
+            (Some(_), None) => {
+                return Err(AccessError::Corrupted(
+                    CorruptedError::InvalidTrieEntryVersion,
+                ))
+            }
+        };
+
+        let mut children_merkle_values: [Option<Vec<u8>>; 16] = Default::default();
+        let children = connection
+            .prepare_cached(r#"SELECT child_num, child_hash FROM trie_node_child WHERE hash = ?"#)
+            .map_err(|err| CorruptedError::Internal(InternalError(err)))?
+            .query_map((merkle_value,), |row| {
+                Ok((row.get::<_, Vec<u8>>(0)?, row.get::<_, Vec<u8>>(1)?))
+            })
+            .map_err(|err| CorruptedError::Internal(InternalError(err)))?
+            .collect::<Result<Vec<_>, _>>()
+            .map_err(|err| CorruptedError::Internal(InternalError(err)))?;
+        for (child_num, child_merkle_value) in children {
+            match &child_num[..] {
+                [n] if *n < 16 => {
+                    children_merkle_values[usize::from(*n)] = Some(child_merkle_value)
+                }
+                _ => return Err(AccessError::Corrupted(CorruptedError::InvalidNibble)),
+            }
+        }
+
+        Ok(Some(TrieNode {
+            partial_key_nibbles,
+            children_merkle_values,
+            storage_value,
+        }))
+    }
+
     /// Returns the value associated with a node of the trie of the given block.
     ///
     /// `parent_tries_paths_nibbles` is a list of keys to follow in order to find the root of the
@@ -1014,6 +1249,109 @@ impl SqliteFullDatabase {
 
         Ok(merkle_value)
     }
+
+    /// Returns the identifier of the authorities set and the number of the latest GrandPa round
+    /// during which the local node has voted, as set with
+    /// [`SqliteFullDatabase::set_grandpa_last_voted_round`].
+    ///
+    /// Returns `None` if the local node has never voted.
+    pub fn grandpa_last_voted_round(&self) -> Result<Option<(u64, u64)>, AccessError> {
+        let connection = self.database.lock();
+        let set_id = meta_get_number(&connection, "grandpa_last_voted_set_id")?;
+        let round_number = meta_get_number(&connection, "grandpa_last_voted_round")?;
+        Ok(set_id.zip(round_number))
+    }
+
+    /// Stores the identifier of the authorities set and the number of the GrandPa round during
+    /// which the local node is about to vote.
+    ///
+    /// This must be called before a vote is sent out, in order for the node to not vote a second
+    /// time during the same round after it restarts, which would be an equivocation.
+    pub fn set_grandpa_last_voted_round(
+        &self,
+        set_id: u64,
+        round_number: u64,
+    ) -> Result<(), AccessError> {
+        let mut database = self.database.lock();
+
+        let transaction = database
+            .transaction()
+            .map_err(|err| AccessError::Corrupted(CorruptedError::Internal(InternalError(err))))?;
+        meta_set_number(&transaction, "grandpa_last_voted_set_id", set_id)?;
+        meta_set_number(&transaction, "grandpa_last_voted_round", round_number)?;
+        transaction
+            .commit()
+            .map_err(|err| AccessError::Corrupted(CorruptedError::Internal(InternalError(err))))?;
+
+        Ok(())
+    }
+
+    /// Returns the value associated to the given key in the off-chain storage, or `None` if
+    /// there is no such value.
+    ///
+    /// Contrary to the storage of the blocks, the off-chain storage isn't tied to any specific
+    /// block and is never pruned.
+    pub fn offchain_storage_get(&self, key: &[u8]) -> Result<Option<Vec<u8>>, AccessError> {
+        let connection = self.database.lock();
+
+        let value = connection
+            .prepare_cached(r#"SELECT value FROM offchain_storage WHERE key = ?"#)
+            .map_err(|err| CorruptedError::Internal(InternalError(err)))?
+            .query_row((key,), |row| row.get::<_, Vec<u8>>(0))
+            .optional()
+            .map_err(|err| CorruptedError::Internal(InternalError(err)))?;
+
+        Ok(value)
+    }
+
+    /// Sets the value associated to the given key in the off-chain storage. If `value` is
+    /// `None`, the key is removed from the off-chain storage.
+    pub fn offchain_storage_set(
+        &self,
+        key: &[u8],
+        value: Option<&[u8]>,
+    ) -> Result<(), AccessError> {
+        let connection = self.database.lock();
+        offchain_storage_set(&connection, key, value)
+    }
+
+    /// Sets the value associated to the given key in the off-chain storage, but only if the
+    /// current value is equal to `expected_value`. If `expected_value` is `None`, the value is
+    /// set only if the key is absent from the off-chain storage. If `value` is `None`, the key
+    /// is removed from the off-chain storage.
+    ///
+    /// Returns `true` if the value has been set.
+    pub fn offchain_storage_compare_and_set(
+        &self,
+        key: &[u8],
+        expected_value: Option<&[u8]>,
+        value: Option<&[u8]>,
+    ) -> Result<bool, AccessError> {
+        let mut database = self.database.lock();
+
+        let transaction = database
+            .transaction()
+            .map_err(|err| CorruptedError::Internal(InternalError(err)))?;
+
+        let current_value = transaction
+            .prepare_cached(r#"SELECT value FROM offchain_storage WHERE key = ?"#)
+            .map_err(|err| CorruptedError::Internal(InternalError(err)))?
+            .query_row((key,), |row| row.get::<_, Vec<u8>>(0))
+            .optional()
+            .map_err(|err| CorruptedError::Internal(InternalError(err)))?;
+
+        if current_value.as_deref() != expected_value {
+            return Ok(false);
+        }
+
+        offchain_storage_set(&transaction, key, value)?;
+
+        transaction
+            .commit()
+            .map_err(|err| CorruptedError::Internal(InternalError(err)))?;
+
+        Ok(true)
+    }
 }
 
 impl fmt::Debug for SqliteFullDatabase {
@@ -1040,6 +1378,58 @@ pub struct InsertTrieNode<'a> {
     pub storage_value: InsertTrieNodeStorageValue<'a>,
 }
 
+/// Trie node found in the database. See [`SqliteFullDatabase::trie_node`].
+#[derive(Debug, Clone)]
+pub struct TrieNode {
+    /// Partial key of the node. Each byte is a nibble, in other words all bytes are inferior
+    /// to 16.
+    pub partial_key_nibbles: Vec<u8>,
+    /// Merkle values of the children of the node.
+    pub children_merkle_values: [Option<Vec<u8>>; 16],
+    /// Storage value of the node, if any, and version of the trie entry.
+    pub storage_value: Option<(Vec<u8>, u8)>,
+}
+
+impl TrieNode {
+    /// Builds the node value of this trie node, in other words the value whose hash is the
+    /// Merkle value of the node.
+    ///
+    /// If the storage value is included in the node value in the form of a hash, the second
+    /// returned value contains the unhashed storage value.
+    pub fn node_value(&self) -> (Vec<u8>, Option<&[u8]>) {
+        let storage_value_hash = match &self.storage_value {
+            Some((value, 1)) if value.len() >= 33 => {
+                Some(blake2_rfc::blake2b::blake2b(32, &[], value))
+            }
+            _ => None,
+        };
+
+        let node_value = trie::trie_node::encode_to_vec(trie::trie_node::Decoded {
+            children: core::array::from_fn(|n| self.children_merkle_values[n].as_ref()),
+            partial_key: self
+                .partial_key_nibbles
+                .iter()
+                .map(|n| trie::Nibble::try_from(*n).unwrap()),
+            storage_value: match (&self.storage_value, &storage_value_hash) {
+                (_, Some(hash)) => trie::trie_node::StorageValue::Hashed(
+                    <&[u8; 32]>::try_from(hash.as_bytes()).unwrap(),
+                ),
+                (Some((value, _)), None) => trie::trie_node::StorageValue::Unhashed(value),
+                (None, None) => trie::trie_node::StorageValue::None,
+            },
+        })
+        .unwrap();
+
+        let unhashed_storage_value = if storage_value_hash.is_some() {
+            self.storage_value.as_ref().map(|(v, _)| &v[..])
+        } else {
+            None
+        };
+
+        (node_value, unhashed_storage_value)
+    }
+}
+
 pub enum InsertTrieNodeStorageValue<'a> {
     NoValue,
     Value {
@@ -1090,6 +1480,15 @@ pub enum SetFinalizedError {
     RevertForbidden,
 }
 
+/// Error while calling [`SqliteFullDatabase::set_block_justification`].
+#[derive(Debug, derive_more::Display, derive_more::From)]
+pub enum SetJustificationError {
+    /// Error accessing the database.
+    Access(AccessError),
+    /// Block isn't in the database.
+    UnknownBlock,
+}
+
 /// Error while accessing the storage of the finalized block.
 #[derive(Debug, derive_more::Display, derive_more::From)]
 pub enum StorageAccessError {
@@ -1131,6 +1530,8 @@ pub enum CorruptedError {
     InvalidBabeEpochInformation,
     /// The version information about a storage entry has failed to decode.
     InvalidTrieEntryVersion,
+    /// The partial key or the children of a trie node contain a nibble superior or equal to 16.
+    InvalidNibble,
     #[display(fmt = "Internal error: {_0}")]
     Internal(InternalError),
 }
@@ -1139,6 +1540,28 @@ pub enum CorruptedError {
 #[derive(Debug, derive_more::Display)]
 pub struct InternalError(rusqlite::Error);
 
+fn offchain_storage_set(
+    database: &rusqlite::Connection,
+    key: &[u8],
+    value: Option<&[u8]>,
+) -> Result<(), AccessError> {
+    if let Some(value) = value {
+        database
+            .prepare_cached(r#"INSERT OR REPLACE INTO offchain_storage(key, value) VALUES (?, ?)"#)
+            .map_err(|err| AccessError::Corrupted(CorruptedError::Internal(InternalError(err))))?
+            .execute((key, value))
+            .map_err(|err| AccessError::Corrupted(CorruptedError::Internal(InternalError(err))))?;
+    } else {
+        database
+            .prepare_cached(r#"DELETE FROM offchain_storage WHERE key = ?"#)
+            .map_err(|err| AccessError::Corrupted(CorruptedError::Internal(InternalError(err))))?
+            .execute((key,))
+            .map_err(|err| AccessError::Corrupted(CorruptedError::Internal(InternalError(err))))?;
+    }
+
+    Ok(())
+}
+
 fn meta_get_blob(
     database: &rusqlite::Connection,
     key: &str,
@@ -1426,13 +1849,13 @@ fn purge_block_storage(
     database: &rusqlite::Connection,
     hash: &[u8; 32],
 ) -> Result<(), AccessError> {
-    // TODO: untested
-
     let state_trie_root_hash = database
         .prepare_cached(r#"SELECT state_trie_root_hash FROM blocks WHERE hash = ?"#)
         .map_err(|err| AccessError::Corrupted(CorruptedError::Internal(InternalError(err))))?
-        .query_row((hash,), |row| row.get::<_, Vec<u8>>(0))
-        .map_err(|err| AccessError::Corrupted(CorruptedError::Internal(InternalError(err))))?;
+        .query_row((&hash[..],), |row| row.get::<_, Option<Vec<u8>>>(0))
+        .optional()
+        .map_err(|err| AccessError::Corrupted(CorruptedError::Internal(InternalError(err))))?
+        .flatten();
 
     database
         .prepare_cached(
@@ -1447,34 +1870,63 @@ fn purge_block_storage(
         })
         .map_err(|err| AccessError::Corrupted(CorruptedError::Internal(InternalError(err))))?;
 
-    // TODO: doesn't delete everything in the situation where a single node with a merkle value is referenced multiple times from the same trie
-    // TODO: currently doesn't follow `trie_root_ref`
-    database
-        .prepare_cached(r#"
-            WITH RECURSIVE
-                to_delete(node_hash) AS (
-                    SELECT trie_node.hash
-                        FROM trie_node
-                        LEFT JOIN blocks ON blocks.hash != :block_hash AND blocks.state_trie_root_hash = trie_node.state_trie_root_hash
-                        LEFT JOIN trie_node_storage ON trie_node_storage.trie_root_ref = trie_node.node_hash
-                        WHERE trie_node.hash = :state_trie_root_hash AND blocks.hash IS NULL AND trie_node_storage.node_hash IS NULL
-                    UNION ALL
-                    SELECT trie_node_child.child_hash
-                        FROM to_delete
-                        JOIN trie_node_child ON trie_node_child.hash = to_delete.node_hash
-                        LEFT JOIN blocks ON blocks.state_trie_root_hash = trie_node_child.child_hash
-                        LEFT JOIN trie_node_storage ON trie_node_storage.trie_root_ref = child_hash.node_hash
-                        WHERE blocks.hash IS NULL AND trie_node_storage.node_hash IS NULL
-                )
-            DELETE FROM trie_node
-            SELECT node_hash FROM to_delete
-        "#)
-        .map_err(|err| AccessError::Corrupted(CorruptedError::Internal(InternalError(err))))?
-        .execute(rusqlite::named_params! {
-            ":state_trie_root_hash": &state_trie_root_hash,
-            ":block_hash": hash,
-        })
+    // Trie nodes are shared between the storage of multiple blocks. A trie node is removed from
+    // the database only if it isn't referenced anymore, either as the root of the storage of a
+    // block, as the child of another node, or as the root of a child trie. Removing a node can
+    // lead to its children no longer being referenced, in which case they are removed as well.
+    let mut is_referenced_statement = database
+        .prepare_cached(
+            r#"
+            SELECT
+                EXISTS(SELECT 1 FROM blocks WHERE state_trie_root_hash = :node_hash)
+                OR EXISTS(SELECT 1 FROM trie_node_child WHERE child_hash = :node_hash)
+                OR EXISTS(SELECT 1 FROM trie_node_storage WHERE trie_root_ref = :node_hash)
+        "#,
+        )
         .map_err(|err| AccessError::Corrupted(CorruptedError::Internal(InternalError(err))))?;
+    let mut children_statement = database
+        .prepare_cached(
+            r#"
+            SELECT child_hash FROM trie_node_child WHERE hash = :node_hash
+            UNION ALL
+            SELECT trie_root_ref FROM trie_node_storage WHERE node_hash = :node_hash AND trie_root_ref IS NOT NULL
+        "#,
+        )
+        .map_err(|err| AccessError::Corrupted(CorruptedError::Internal(InternalError(err))))?;
+    let mut delete_statement = database
+        .prepare_cached(r#"DELETE FROM trie_node WHERE hash = ?"#)
+        .map_err(|err| AccessError::Corrupted(CorruptedError::Internal(InternalError(err))))?;
+
+    let mut to_check = state_trie_root_hash.into_iter().collect::<Vec<_>>();
+    while let Some(node_hash) = to_check.pop() {
+        let is_referenced = is_referenced_statement
+            .query_row(
+                rusqlite::named_params! { ":node_hash": &node_hash },
+                |row| row.get::<_, bool>(0),
+            )
+            .map_err(|err| AccessError::Corrupted(CorruptedError::Internal(InternalError(err))))?;
+        if is_referenced {
+            continue;
+        }
+
+        let children = children_statement
+            .query_map(
+                rusqlite::named_params! { ":node_hash": &node_hash },
+                |row| row.get::<_, Vec<u8>>(0),
+            )
+            .map_err(|err| AccessError::Corrupted(CorruptedError::Internal(InternalError(err))))?
+            .collect::<Result<Vec<_>, _>>()
+            .map_err(|err| AccessError::Corrupted(CorruptedError::Internal(InternalError(err))))?;
+
+        // The entries in `trie_node_child` and `trie_node_storage` associated to this node are
+        // automatically removed as well thanks to `ON DELETE CASCADE`.
+        delete_statement
+            .execute((&node_hash,))
+            .map_err(|err| AccessError::Corrupted(CorruptedError::Internal(InternalError(err))))?;
+
+        to_check.extend(children);
+    }
+
     Ok(())
 }
 
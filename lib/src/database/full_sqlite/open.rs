@@ -27,6 +27,7 @@ use super::{
 };
 use crate::chain::chain_information;
 
+use core::num::NonZeroU64;
 use std::path::Path;
 
 /// Opens the database using the given [`Config`].
@@ -197,7 +198,7 @@ List of all known blocks, indexed by their hash or number.
 */
 CREATE TABLE blocks(
     hash BLOB NOT NULL PRIMARY KEY,
-    parent_hash BLOB,  -- NULL only for the genesis block
+    parent_hash BLOB,  -- NULL only for the first block inserted in the database, normally the genesis block
     state_trie_root_hash BLOB,  -- NULL if and only if the trie is empty or if the trie storage has been pruned from the database
     number INTEGER NOT NULL,
     header BLOB NOT NULL,
@@ -264,6 +265,26 @@ PRAGMA user_version = 1;
             .map_err(InternalError)?
     }
 
+    if user_version <= 1 {
+        database
+            .execute_batch(
+                r#"
+/*
+Off-chain storage, which can be read and written by the runtime when it runs off-chain workers.
+Contrary to the storage of the blocks, the off-chain storage isn't tied to any specific block.
+*/
+CREATE TABLE offchain_storage(
+    key BLOB NOT NULL PRIMARY KEY,
+    value BLOB NOT NULL
+);
+
+PRAGMA user_version = 2;
+
+        "#,
+            )
+            .map_err(InternalError)?
+    }
+
     let is_empty = database
         .prepare_cached("SELECT COUNT(*) FROM meta WHERE key = ?")
         .map_err(InternalError)?
@@ -275,11 +296,13 @@ PRAGMA user_version = 1;
         DatabaseOpen::Open(SqliteFullDatabase {
             database: parking_lot::Mutex::new(database),
             block_number_bytes: config.block_number_bytes, // TODO: consider storing this value in the DB and check it when opening
+            pruning: config.pruning,
         })
     } else {
         DatabaseOpen::Empty(DatabaseEmpty {
             database,
             block_number_bytes: config.block_number_bytes,
+            pruning: config.pruning,
         })
     })
 }
@@ -295,6 +318,9 @@ pub struct Config<'a> {
 
     /// Maximum allowed size, in bytes, of the SQLite cache.
     pub cache_size: usize,
+
+    /// Which storage to keep in the database.
+    pub pruning: PruningMode,
 }
 
 /// Type of database.
@@ -312,6 +338,22 @@ pub enum ConfigTy<'a> {
     Memory,
 }
 
+/// See [`Config::pruning`].
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum PruningMode {
+    /// The storage of all the finalized blocks is kept forever.
+    Archive,
+    /// Only the storage of the latest `num_finalized_blocks` finalized blocks (including the
+    /// finalized block itself) and of the non-finalized blocks is kept. The storage of older
+    /// blocks is removed from the database when blocks get finalized.
+    ///
+    /// The headers and bodies of the finalized blocks are always kept.
+    Pruned {
+        /// Number of finalized blocks whose storage is kept.
+        num_finalized_blocks: NonZeroU64,
+    },
+}
+
 /// Either existing database or database prototype.
 pub enum DatabaseOpen {
     /// A database already existed and has now been opened.
@@ -332,6 +374,9 @@ pub struct DatabaseEmpty {
 
     /// See the similar field in [`SqliteFullDatabase`].
     block_number_bytes: usize,
+
+    /// See the similar field in [`SqliteFullDatabase`].
+    pruning: PruningMode,
 }
 
 impl DatabaseEmpty {
@@ -361,83 +406,133 @@ impl DatabaseEmpty {
             .execute("PRAGMA defer_foreign_keys = ON", ())
             .map_err(|err| AccessError::Corrupted(CorruptedError::Internal(InternalError(err))))?;
 
-        let chain_information = chain_information.into();
-
-        let finalized_block_hash = chain_information
-            .finalized_block_header
-            .hash(self.block_number_bytes);
-
-        let scale_encoded_finalized_block_header = chain_information
-            .finalized_block_header
-            .scale_encoding(self.block_number_bytes)
-            .fold(Vec::new(), |mut a, b| {
-                a.extend_from_slice(b.as_ref());
-                a
-            });
-
-        insert_storage(
+        insert_finalized_block(
             &transaction,
-            None,
+            self.block_number_bytes,
+            chain_information.into(),
+            finalized_block_body,
+            finalized_block_justification,
             finalized_block_storage_entries,
             finalized_block_state_version,
         )?;
 
         transaction
-            .prepare_cached(
-                "INSERT INTO blocks(hash, parent_hash, state_trie_root_hash, number, header, justification) VALUES(?, ?, ?, ?, ?, ?)",
-            )
-            .unwrap()
-            .execute((
-                &finalized_block_hash[..],
-                if chain_information.finalized_block_header.number != 0 {
-                    Some(&chain_information.finalized_block_header.parent_hash[..])
-                } else { None },
-                &chain_information.finalized_block_header.state_root[..],
-                i64::try_from(chain_information.finalized_block_header.number).unwrap(),
-                &scale_encoded_finalized_block_header[..],
-                finalized_block_justification.as_deref(),
-            ))
+            .commit()
+            .map_err(|err| AccessError::Corrupted(CorruptedError::Internal(InternalError(err))))?;
+
+        Ok(SqliteFullDatabase {
+            database: parking_lot::Mutex::new(self.database),
+            block_number_bytes: self.block_number_bytes,
+            pruning: self.pruning,
+        })
+    }
+}
+
+/// Inserts the given finalized block, its storage, and the consensus-related information in an
+/// empty database.
+///
+/// Shared between [`DatabaseEmpty::initialize`] and [`SqliteFullDatabase::reset`]. Foreign key
+/// checks must have been deferred.
+pub(super) fn insert_finalized_block<'a>(
+    transaction: &rusqlite::Connection,
+    block_number_bytes: usize,
+    chain_information: chain_information::ChainInformationRef<'a>,
+    finalized_block_body: impl ExactSizeIterator<Item = &'a [u8]>,
+    finalized_block_justification: Option<Vec<u8>>,
+    finalized_block_storage_entries: impl Iterator<Item = InsertTrieNode<'a>>,
+    finalized_block_state_version: u8,
+) -> Result<(), AccessError> {
+    let finalized_block_hash = chain_information
+        .finalized_block_header
+        .hash(block_number_bytes);
+
+    let scale_encoded_finalized_block_header = chain_information
+        .finalized_block_header
+        .scale_encoding(block_number_bytes)
+        .fold(Vec::new(), |mut a, b| {
+            a.extend_from_slice(b.as_ref());
+            a
+        });
+
+    insert_storage(
+        transaction,
+        None,
+        finalized_block_storage_entries,
+        finalized_block_state_version,
+    )?;
+
+    transaction
+        .prepare_cached(
+            "INSERT INTO blocks(hash, parent_hash, state_trie_root_hash, number, header, justification) VALUES(?, ?, ?, ?, ?, ?)",
+        )
+        .unwrap()
+        .execute((
+            &finalized_block_hash[..],
+            // The parent of the block isn't in the database, and referencing it would violate
+            // the foreign key constraint.
+            Option::<&[u8]>::None,
+            &chain_information.finalized_block_header.state_root[..],
+            i64::try_from(chain_information.finalized_block_header.number).unwrap(),
+            &scale_encoded_finalized_block_header[..],
+            finalized_block_justification.as_deref(),
+        ))
+        .unwrap();
+
+    {
+        let mut statement = transaction
+            .prepare_cached("INSERT INTO blocks_body(hash, idx, extrinsic) VALUES(?, ?, ?)")
             .unwrap();
+        for (index, item) in finalized_block_body.enumerate() {
+            statement
+                .execute((
+                    &finalized_block_hash[..],
+                    i64::try_from(index).unwrap(),
+                    item,
+                ))
+                .unwrap();
+        }
+    }
+
+    super::meta_set_blob(transaction, "best", &finalized_block_hash[..]).unwrap();
+    super::meta_set_number(
+        transaction,
+        "finalized",
+        chain_information.finalized_block_header.number,
+    )?;
+
+    match &chain_information.finality {
+        chain_information::ChainInformationFinalityRef::Outsourced => {}
+        chain_information::ChainInformationFinalityRef::Grandpa {
+            finalized_triggered_authorities,
+            after_finalized_block_authorities_set_id,
+            finalized_scheduled_change,
+        } => {
+            super::meta_set_number(
+                transaction,
+                "grandpa_authorities_set_id",
+                *after_finalized_block_authorities_set_id,
+            )?;
 
-        {
             let mut statement = transaction
-                .prepare_cached("INSERT INTO blocks_body(hash, idx, extrinsic) VALUES(?, ?, ?)")
+                .prepare_cached("INSERT INTO grandpa_triggered_authorities(idx, public_key, weight) VALUES(?, ?, ?)")
                 .unwrap();
-            for (index, item) in finalized_block_body.enumerate() {
+            for (index, item) in finalized_triggered_authorities.iter().enumerate() {
                 statement
                     .execute((
-                        &finalized_block_hash[..],
                         i64::try_from(index).unwrap(),
-                        item,
+                        &item.public_key[..],
+                        i64::from_ne_bytes(item.weight.get().to_ne_bytes()),
                     ))
                     .unwrap();
             }
-        }
-
-        super::meta_set_blob(&transaction, "best", &finalized_block_hash[..]).unwrap();
-        super::meta_set_number(
-            &transaction,
-            "finalized",
-            chain_information.finalized_block_header.number,
-        )?;
 
-        match &chain_information.finality {
-            chain_information::ChainInformationFinalityRef::Outsourced => {}
-            chain_information::ChainInformationFinalityRef::Grandpa {
-                finalized_triggered_authorities,
-                after_finalized_block_authorities_set_id,
-                finalized_scheduled_change,
-            } => {
-                super::meta_set_number(
-                    &transaction,
-                    "grandpa_authorities_set_id",
-                    *after_finalized_block_authorities_set_id,
-                )?;
+            if let Some((height, list)) = finalized_scheduled_change {
+                super::meta_set_number(transaction, "grandpa_scheduled_target", *height)?;
 
                 let mut statement = transaction
-                    .prepare_cached("INSERT INTO grandpa_triggered_authorities(idx, public_key, weight) VALUES(?, ?, ?)")
+                    .prepare_cached("INSERT INTO grandpa_scheduled_authorities(idx, public_key, weight) VALUES(?, ?, ?)")
                     .unwrap();
-                for (index, item) in finalized_triggered_authorities.iter().enumerate() {
+                for (index, item) in list.iter().enumerate() {
                     statement
                         .execute((
                             i64::try_from(index).unwrap(),
@@ -446,75 +541,53 @@ impl DatabaseEmpty {
                         ))
                         .unwrap();
                 }
-
-                if let Some((height, list)) = finalized_scheduled_change {
-                    super::meta_set_number(&transaction, "grandpa_scheduled_target", *height)?;
-
-                    let mut statement = transaction
-                        .prepare_cached("INSERT INTO grandpa_scheduled_authorities(idx, public_key, weight) VALUES(?, ?, ?)")
-                        .unwrap();
-                    for (index, item) in list.iter().enumerate() {
-                        statement
-                            .execute((
-                                i64::try_from(index).unwrap(),
-                                &item.public_key[..],
-                                i64::from_ne_bytes(item.weight.get().to_ne_bytes()),
-                            ))
-                            .unwrap();
-                    }
-                }
             }
         }
+    }
 
-        match &chain_information.consensus {
-            chain_information::ChainInformationConsensusRef::Unknown => {}
-            chain_information::ChainInformationConsensusRef::Aura {
-                finalized_authorities_list,
-                slot_duration,
-            } => {
-                super::meta_set_number(&transaction, "aura_slot_duration", slot_duration.get())
-                    .unwrap();
+    match &chain_information.consensus {
+        chain_information::ChainInformationConsensusRef::Unknown => {}
+        chain_information::ChainInformationConsensusRef::Aura {
+            finalized_authorities_list,
+            slot_duration,
+        } => {
+            super::meta_set_number(transaction, "aura_slot_duration", slot_duration.get()).unwrap();
 
-                let mut statement = transaction
-                    .prepare_cached(
-                        "INSERT INTO aura_finalized_authorities(idx, public_key) VALUES(?, ?)",
-                    )
+            let mut statement = transaction
+                .prepare_cached(
+                    "INSERT INTO aura_finalized_authorities(idx, public_key) VALUES(?, ?)",
+                )
+                .unwrap();
+            for (index, item) in finalized_authorities_list.clone().enumerate() {
+                statement
+                    .execute((i64::try_from(index).unwrap(), &item.public_key[..]))
                     .unwrap();
-                for (index, item) in finalized_authorities_list.clone().enumerate() {
-                    statement
-                        .execute((i64::try_from(index).unwrap(), &item.public_key[..]))
-                        .unwrap();
-                }
             }
-            chain_information::ChainInformationConsensusRef::Babe {
-                slots_per_epoch,
-                finalized_next_epoch_transition,
-                finalized_block_epoch_information,
-            } => {
-                super::meta_set_number(&transaction, "babe_slots_per_epoch", slots_per_epoch.get())
-                    .unwrap();
+        }
+        chain_information::ChainInformationConsensusRef::Babe {
+            slots_per_epoch,
+            finalized_next_epoch_transition,
+            finalized_block_epoch_information,
+        } => {
+            super::meta_set_number(transaction, "babe_slots_per_epoch", slots_per_epoch.get())
+                .unwrap();
+            super::meta_set_blob(
+                transaction,
+                "babe_finalized_next_epoch",
+                &encode_babe_epoch_information(finalized_next_epoch_transition.clone())[..],
+            )
+            .unwrap();
+
+            if let Some(finalized_block_epoch_information) = finalized_block_epoch_information {
                 super::meta_set_blob(
-                    &transaction,
-                    "babe_finalized_next_epoch",
-                    &encode_babe_epoch_information(finalized_next_epoch_transition.clone())[..],
+                    transaction,
+                    "babe_finalized_epoch",
+                    &encode_babe_epoch_information(finalized_block_epoch_information.clone())[..],
                 )
                 .unwrap();
-
-                if let Some(finalized_block_epoch_information) = finalized_block_epoch_information {
-                    super::meta_set_blob(&transaction, "babe_finalized_epoch", &encode_babe_epoch_information(
-            finalized_block_epoch_information.clone(),
-        )[..]).unwrap();
-                }
             }
         }
-
-        transaction
-            .commit()
-            .map_err(|err| AccessError::Corrupted(CorruptedError::Internal(InternalError(err))))?;
-
-        Ok(SqliteFullDatabase {
-            database: parking_lot::Mutex::new(self.database),
-            block_number_bytes: self.block_number_bytes,
-        })
     }
+
+    Ok(())
 }
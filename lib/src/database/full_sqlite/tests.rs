@@ -17,11 +17,14 @@
 
 #![cfg(test)]
 
-use super::{open, Config, ConfigTy, DatabaseOpen, InsertTrieNode, InsertTrieNodeStorageValue};
+use super::{
+    open, Config, ConfigTy, DatabaseOpen, InsertTrieNode, InsertTrieNodeStorageValue, PruningMode,
+    StorageAccessError,
+};
 use crate::{chain::chain_information, header, trie};
 
 use alloc::borrow::Cow;
-use core::{array, iter};
+use core::{array, iter, num::NonZeroU64};
 use rand::distributions::{Distribution as _, Uniform};
 
 #[test]
@@ -31,6 +34,7 @@ fn empty_database_fill_then_query() {
         let DatabaseOpen::Empty(empty_db) = open(Config {
             block_number_bytes: 4,
             cache_size: 2 * 1024 * 1024,
+            pruning: PruningMode::Archive,
             ty: ConfigTy::Memory,
         })
         .unwrap() else {
@@ -181,6 +185,24 @@ fn empty_database_fill_then_query() {
 
         let block0_hash = open_db.finalized_block_hash().unwrap();
 
+        // Check that the node value of each trie node can be rebuilt from the database.
+        for node_index in trie.iter_unordered() {
+            let merkle_value = trie[node_index].1.as_ref().unwrap().as_ref();
+            let (node_value, _) = open_db
+                .trie_node(merkle_value)
+                .unwrap()
+                .unwrap()
+                .node_value();
+            if merkle_value.len() == 32 {
+                assert_eq!(
+                    blake2_rfc::blake2b::blake2b(32, &[], &node_value).as_bytes(),
+                    merkle_value
+                );
+            } else {
+                assert_eq!(node_value, merkle_value);
+            }
+        }
+
         // Ask random keys.
         for _ in 0..1024 {
             let key = (0..uniform_sample(0, 4))
@@ -272,3 +294,355 @@ fn empty_database_fill_then_query() {
         }
     }
 }
+
+#[test]
+fn finalized_storage_pruned() {
+    for pruning in [
+        PruningMode::Archive,
+        PruningMode::Pruned {
+            num_finalized_blocks: NonZeroU64::new(2).unwrap(),
+        },
+    ] {
+        let DatabaseOpen::Empty(empty_db) = open(Config {
+            block_number_bytes: 4,
+            cache_size: 2 * 1024 * 1024,
+            pruning,
+            ty: ConfigTy::Memory,
+        })
+        .unwrap() else {
+            panic!()
+        };
+
+        // Each block has a storage made of one single trie node whose Merkle value is
+        // `[block_number; 32]` and whose storage value is `[block_number]`. Blocks #3 and #4 share
+        // the same storage.
+        let storage_of = |num: u8| {
+            iter::once(InsertTrieNode {
+                storage_value: InsertTrieNodeStorageValue::Value {
+                    value: Cow::Owned(vec![num]),
+                    references_merkle_value: false,
+                },
+                merkle_value: Cow::Owned(vec![num; 32]),
+                children_merkle_values: array::from_fn(|_| None),
+                partial_key_nibbles: Cow::Owned(Vec::new()),
+            })
+        };
+        let state_root_of = |num: u64| [u8::try_from(num.min(3)).unwrap(); 32];
+
+        let open_db = empty_db
+            .initialize(
+                chain_information::ChainInformationRef {
+                    finalized_block_header: header::HeaderRef {
+                        number: 0,
+                        extrinsics_root: &[0; 32],
+                        parent_hash: &[0; 32],
+                        state_root: &state_root_of(0),
+                        digest: header::DigestRef::empty(),
+                    },
+                    consensus: chain_information::ChainInformationConsensusRef::Unknown,
+                    finality: chain_information::ChainInformationFinalityRef::Outsourced,
+                },
+                iter::empty(),
+                None,
+                storage_of(0),
+                0,
+            )
+            .unwrap();
+
+        let mut block_hashes = vec![open_db.finalized_block_hash().unwrap()];
+        for number in 1..=4u64 {
+            let header = header::HeaderRef {
+                number,
+                extrinsics_root: &[0; 32],
+                parent_hash: block_hashes.last().unwrap(),
+                state_root: &state_root_of(number),
+                digest: header::DigestRef::empty(),
+            }
+            .scale_encoding_vec(4);
+            open_db
+                .insert(
+                    &header,
+                    true,
+                    iter::empty::<Vec<u8>>(),
+                    storage_of(u8::try_from(number.min(3)).unwrap()),
+                    0,
+                )
+                .unwrap();
+            block_hashes.push(header::hash_from_scale_encoded_header(&header));
+        }
+
+        open_db.set_finalized(&block_hashes[2]).unwrap();
+        open_db.set_finalized(&block_hashes[4]).unwrap();
+
+        for (number, block_hash) in block_hashes.iter().enumerate() {
+            let storage_value = open_db.block_storage_get(
+                block_hash,
+                iter::empty::<iter::Empty<_>>(),
+                iter::empty(),
+            );
+            let trie_node = open_db
+                .trie_node(&[u8::try_from(number).unwrap(); 32])
+                .unwrap();
+
+            if matches!(pruning, PruningMode::Pruned { .. }) && number < 3 {
+                assert!(matches!(storage_value, Err(StorageAccessError::Pruned)));
+                assert!(trie_node.is_none());
+            } else {
+                let expected = u8::try_from(number.min(3)).unwrap();
+                assert_eq!(storage_value.unwrap(), Some((vec![expected], 0)));
+                if number <= 3 {
+                    assert!(trie_node.is_some());
+                }
+            }
+
+            // Headers are never pruned.
+            assert!(open_db
+                .block_scale_encoded_header(block_hash)
+                .unwrap()
+                .is_some());
+        }
+    }
+}
+
+#[test]
+fn offchain_storage() {
+    let DatabaseOpen::Empty(empty_db) = open(Config {
+        block_number_bytes: 4,
+        cache_size: 2 * 1024 * 1024,
+        pruning: PruningMode::Archive,
+        ty: ConfigTy::Memory,
+    })
+    .unwrap() else {
+        panic!()
+    };
+
+    let open_db = empty_db
+        .initialize(
+            chain_information::ChainInformationRef {
+                finalized_block_header: header::HeaderRef {
+                    number: 0,
+                    extrinsics_root: &[0; 32],
+                    parent_hash: &[0; 32],
+                    state_root: &[0; 32],
+                    digest: header::DigestRef::empty(),
+                },
+                consensus: chain_information::ChainInformationConsensusRef::Unknown,
+                finality: chain_information::ChainInformationFinalityRef::Outsourced,
+            },
+            iter::empty(),
+            None,
+            iter::once(InsertTrieNode {
+                storage_value: InsertTrieNodeStorageValue::NoValue,
+                merkle_value: Cow::Owned(vec![0; 32]),
+                children_merkle_values: array::from_fn(|_| None),
+                partial_key_nibbles: Cow::Owned(Vec::new()),
+            }),
+            0,
+        )
+        .unwrap();
+
+    assert_eq!(open_db.offchain_storage_get(b"foo").unwrap(), None);
+
+    open_db.offchain_storage_set(b"foo", Some(b"bar")).unwrap();
+    assert_eq!(
+        open_db.offchain_storage_get(b"foo").unwrap().as_deref(),
+        Some(&b"bar"[..])
+    );
+
+    assert!(!open_db
+        .offchain_storage_compare_and_set(b"foo", None, Some(b"baz"))
+        .unwrap());
+    assert!(!open_db
+        .offchain_storage_compare_and_set(b"foo", Some(b"baz"), Some(b"baz"))
+        .unwrap());
+    assert!(open_db
+        .offchain_storage_compare_and_set(b"foo", Some(b"bar"), Some(b"baz"))
+        .unwrap());
+    assert_eq!(
+        open_db.offchain_storage_get(b"foo").unwrap().as_deref(),
+        Some(&b"baz"[..])
+    );
+
+    assert!(open_db
+        .offchain_storage_compare_and_set(b"foo", Some(b"baz"), None)
+        .unwrap());
+    assert_eq!(open_db.offchain_storage_get(b"foo").unwrap(), None);
+    assert!(open_db
+        .offchain_storage_compare_and_set(b"foo", None, Some(b"qux"))
+        .unwrap());
+
+    open_db.offchain_storage_set(b"foo", None).unwrap();
+    assert_eq!(open_db.offchain_storage_get(b"foo").unwrap(), None);
+}
+
+#[test]
+fn grandpa_last_voted_round() {
+    let DatabaseOpen::Empty(empty_db) = open(Config {
+        block_number_bytes: 4,
+        cache_size: 2 * 1024 * 1024,
+        pruning: PruningMode::Archive,
+        ty: ConfigTy::Memory,
+    })
+    .unwrap() else {
+        panic!()
+    };
+
+    let open_db = empty_db
+        .initialize(
+            chain_information::ChainInformationRef {
+                finalized_block_header: header::HeaderRef {
+                    number: 0,
+                    extrinsics_root: &[0; 32],
+                    parent_hash: &[0; 32],
+                    state_root: &[0; 32],
+                    digest: header::DigestRef::empty(),
+                },
+                consensus: chain_information::ChainInformationConsensusRef::Unknown,
+                finality: chain_information::ChainInformationFinalityRef::Outsourced,
+            },
+            iter::empty(),
+            None,
+            iter::once(InsertTrieNode {
+                storage_value: InsertTrieNodeStorageValue::NoValue,
+                merkle_value: Cow::Owned(vec![0; 32]),
+                children_merkle_values: array::from_fn(|_| None),
+                partial_key_nibbles: Cow::Owned(Vec::new()),
+            }),
+            0,
+        )
+        .unwrap();
+
+    assert_eq!(open_db.grandpa_last_voted_round().unwrap(), None);
+
+    open_db.set_grandpa_last_voted_round(3, 12).unwrap();
+    assert_eq!(open_db.grandpa_last_voted_round().unwrap(), Some((3, 12)));
+
+    open_db.set_grandpa_last_voted_round(4, 1).unwrap();
+    assert_eq!(open_db.grandpa_last_voted_round().unwrap(), Some((4, 1)));
+}
+
+#[test]
+fn reset_to_later_block() {
+    let DatabaseOpen::Empty(empty_db) = open(Config {
+        block_number_bytes: 4,
+        cache_size: 2 * 1024 * 1024,
+        pruning: PruningMode::Archive,
+        ty: ConfigTy::Memory,
+    })
+    .unwrap() else {
+        panic!()
+    };
+
+    // Same storage layout as in `finalized_storage_pruned`.
+    let storage_of = |num: u8| {
+        iter::once(InsertTrieNode {
+            storage_value: InsertTrieNodeStorageValue::Value {
+                value: Cow::Owned(vec![num]),
+                references_merkle_value: false,
+            },
+            merkle_value: Cow::Owned(vec![num; 32]),
+            children_merkle_values: array::from_fn(|_| None),
+            partial_key_nibbles: Cow::Owned(Vec::new()),
+        })
+    };
+
+    let open_db = empty_db
+        .initialize(
+            chain_information::ChainInformationRef {
+                finalized_block_header: header::HeaderRef {
+                    number: 0,
+                    extrinsics_root: &[0; 32],
+                    parent_hash: &[0; 32],
+                    state_root: &[0; 32],
+                    digest: header::DigestRef::empty(),
+                },
+                consensus: chain_information::ChainInformationConsensusRef::Unknown,
+                finality: chain_information::ChainInformationFinalityRef::Outsourced,
+            },
+            iter::empty(),
+            None,
+            storage_of(0),
+            0,
+        )
+        .unwrap();
+    let genesis_hash = open_db.finalized_block_hash().unwrap();
+    open_db.offchain_storage_set(b"foo", Some(b"bar")).unwrap();
+
+    // The parent of the new finalized block is unknown to the database.
+    let new_finalized_header = header::HeaderRef {
+        number: 1000,
+        extrinsics_root: &[0; 32],
+        parent_hash: &[0xff; 32],
+        state_root: &[5; 32],
+        digest: header::DigestRef::empty(),
+    };
+    open_db
+        .reset(
+            chain_information::ChainInformationRef {
+                finalized_block_header: new_finalized_header.clone(),
+                consensus: chain_information::ChainInformationConsensusRef::Unknown,
+                finality: chain_information::ChainInformationFinalityRef::Outsourced,
+            },
+            iter::once(&b"extrinsic"[..]),
+            None,
+            storage_of(5),
+            1,
+        )
+        .unwrap();
+
+    let new_finalized_hash = new_finalized_header.hash(4);
+    assert_eq!(open_db.finalized_block_hash().unwrap(), new_finalized_hash);
+    assert_eq!(open_db.best_block_hash().unwrap(), new_finalized_hash);
+    assert_eq!(open_db.block_hash_by_number(0).unwrap().count(), 0);
+    assert!(open_db
+        .block_scale_encoded_header(&genesis_hash)
+        .unwrap()
+        .is_none());
+    assert!(open_db.trie_node(&[0; 32]).unwrap().is_none());
+    assert_eq!(
+        open_db
+            .block_storage_get(
+                &new_finalized_hash,
+                iter::empty::<iter::Empty<_>>(),
+                iter::empty(),
+            )
+            .unwrap(),
+        Some((vec![5], 1))
+    );
+    assert_eq!(
+        open_db
+            .block_extrinsics(&new_finalized_hash)
+            .unwrap()
+            .unwrap()
+            .collect::<Vec<_>>(),
+        vec![b"extrinsic".to_vec()]
+    );
+
+    // The offchain storage isn't affected.
+    assert_eq!(
+        open_db.offchain_storage_get(b"foo").unwrap().as_deref(),
+        Some(&b"bar"[..])
+    );
+
+    // New blocks can be inserted on top of the new finalized block.
+    let child_header = header::HeaderRef {
+        number: 1001,
+        extrinsics_root: &[0; 32],
+        parent_hash: &new_finalized_hash,
+        state_root: &[6; 32],
+        digest: header::DigestRef::empty(),
+    }
+    .scale_encoding_vec(4);
+    open_db
+        .insert(
+            &child_header,
+            true,
+            iter::empty::<Vec<u8>>(),
+            storage_of(6),
+            1,
+        )
+        .unwrap();
+    open_db
+        .set_finalized(&header::hash_from_scale_encoded_header(&child_header))
+        .unwrap();
+}
@@ -66,9 +66,27 @@
 //!   be around `75%` (`(3N + ε) / 2 / 2N`) meaning that around `25%` of the space in allocation will be
 //!   wasted. This is more pronounced (in terms of absolute heap amounts) with larger allocation
 //!   sizes.
+//!
+//! # Reclaiming strategy
+//!
+//! The first shortcoming mentioned above can be avoided by creating the allocator with
+//! [`AllocationStrategy::Reclaiming`]. In this mode, the allocator doesn't use the per-order
+//! linked lists. Instead, it keeps track, outside of the linear memory, of the regions of the
+//! heap that have been freed. Upon deallocation, the freed chunk is merged with the free
+//! regions that surround it, and a free region that ends at the bumper is given back to the bump
+//! allocator. Allocations are served from the smallest free region that is large enough, and
+//! from the bump allocator if there isn't any.
+//!
+//! Sizes of allocations are still rounded up to the nearest order, and the headers are identical
+//! in both modes.
+//!
+//! Note that the strategy affects whether an allocation succeeds. Runtimes are written with the
+//! assumption that [`AllocationStrategy::FreeingBump`] is used, and a runtime call that fails
+//! with this strategy is expected to also fail on the other implementations of the host.
 
 #![allow(clippy::all)] // TODO: since this code has been copy-pasted from Substrate, we simply silence clippy warnings
 
+use alloc::collections::{BTreeMap, BTreeSet};
 use core::{
     mem,
     ops::{Index, IndexMut, Range},
@@ -331,13 +349,167 @@ impl IndexMut<Order> for FreeLists {
     }
 }
 
+/// Free regions of the heap tracked when using [`AllocationStrategy::Reclaiming`].
+///
+/// The regions never overlap, never touch each other, and are always entirely below the bumper.
+/// A region that ends at the bumper is immediately given back to the bump allocator.
+struct FreeRegions {
+    /// Length in bytes of each free region, indexed by the position of its start.
+    by_start: BTreeMap<u32, u32>,
+    /// Same regions as [`FreeRegions::by_start`], indexed by `(length, start)`.
+    by_length: BTreeSet<(u32, u32)>,
+}
+
+impl FreeRegions {
+    /// Creates an empty list of regions.
+    fn new() -> Self {
+        FreeRegions {
+            by_start: BTreeMap::new(),
+            by_length: BTreeSet::new(),
+        }
+    }
+
+    /// Removes `length` bytes from the smallest free region that is large enough, and returns
+    /// the position of these bytes. Returns `None` if no region is large enough.
+    fn take(&mut self, length: u32) -> Option<u32> {
+        let (region_length, region_start) = *self.by_length.range((length, 0)..).next()?;
+        self.remove(region_start, region_length);
+        if region_length > length {
+            self.insert(region_start + length, region_length - length);
+        }
+        Some(region_start)
+    }
+
+    /// Marks the `length` bytes starting at `start` as free, merging them with the adjacent free
+    /// regions. If the resulting region ends at `bumper`, it is removed and `bumper` is lowered
+    /// instead.
+    ///
+    /// Returns an error if the bytes aren't entirely between `heap_base` and `bumper`, or if they
+    /// overlap with a free region.
+    fn release(
+        &mut self,
+        start: u32,
+        length: u32,
+        heap_base: u32,
+        bumper: &mut u32,
+    ) -> Result<(), Error> {
+        let end = start
+            .checked_add(length)
+            .ok_or_else(|| error("Invalid pointer for deallocation"))?;
+        if start < heap_base || end > *bumper {
+            return Err(error("Invalid pointer for deallocation"));
+        }
+
+        let mut start = start;
+        let mut end = end;
+
+        if let Some((&prev_start, &prev_length)) = self.by_start.range(..=start).next_back() {
+            if prev_start + prev_length > start {
+                return Err(error("the allocation overlaps with a free region"));
+            }
+            if prev_start + prev_length == start {
+                self.remove(prev_start, prev_length);
+                start = prev_start;
+            }
+        }
+
+        if let Some((&next_start, &next_length)) = self.by_start.range(start..).next() {
+            if next_start < end {
+                return Err(error("the allocation overlaps with a free region"));
+            }
+            if next_start == end {
+                self.remove(next_start, next_length);
+                end += next_length;
+            }
+        }
+
+        if end == *bumper {
+            *bumper = start;
+        } else {
+            self.insert(start, end - start);
+        }
+
+        Ok(())
+    }
+
+    fn insert(&mut self, start: u32, length: u32) {
+        self.by_start.insert(start, length);
+        self.by_length.insert((length, start));
+    }
+
+    fn remove(&mut self, start: u32, length: u32) {
+        self.by_start.remove(&start);
+        self.by_length.remove(&(length, start));
+    }
+}
+
+/// Strategy used by a [`FreeingBumpHeapAllocator`] in order to reuse the memory that has been
+/// deallocated.
+///
+/// See [the module-level documentation](..) for more information.
+#[derive(Debug, Copy, Clone, PartialEq, Eq)]
+pub enum AllocationStrategy {
+    /// Deallocated chunks are only reused by later allocations of the same order, and the bump
+    /// space is never reclaimed. This is the strategy used by Substrate.
+    FreeingBump,
+    /// Deallocated chunks are merged together and given back to the bump allocator when
+    /// possible, and can be reused by allocations of any size.
+    Reclaiming,
+}
+
+impl Default for AllocationStrategy {
+    fn default() -> Self {
+        AllocationStrategy::FreeingBump
+    }
+}
+
+/// Statistics about the memory usage of a [`FreeingBumpHeapAllocator`].
+#[derive(Debug, Copy, Clone, PartialEq, Eq)]
+pub struct AllocationStats {
+    /// Number of bytes currently allocated, including the headers of the allocations.
+    pub bytes_allocated: u32,
+
+    /// Highest value that [`AllocationStats::bytes_allocated`] has reached.
+    pub bytes_allocated_peak: u32,
+
+    /// Sum of the sizes of all the allocations that have been performed, including the headers
+    /// of the allocations, ignoring the deallocations.
+    pub bytes_allocated_sum: u64,
+
+    /// Number of bytes of the heap that have been reserved by the bump allocator. This is the
+    /// amount of memory that the heap effectively occupies.
+    pub address_space_used: u32,
+
+    /// Highest value that [`AllocationStats::address_space_used`] has reached. Since the bump
+    /// space is never reclaimed when using [`AllocationStrategy::FreeingBump`], this is always
+    /// equal to [`AllocationStats::address_space_used`] when using this strategy.
+    pub address_space_used_peak: u32,
+
+    /// Number of bytes within [`AllocationStats::address_space_used`] that aren't currently
+    /// allocated. These bytes can only be used by allocations of the same order when using
+    /// [`AllocationStrategy::FreeingBump`], or by allocations that fit in them when using
+    /// [`AllocationStrategy::Reclaiming`]. A high value indicates a fragmented heap.
+    pub fragmented_bytes: u32,
+
+    /// Number of allocations that have been performed, ignoring the deallocations, grouped by
+    /// order. The element at index `n` corresponds to allocations whose size, once rounded up,
+    /// is `8 << n` bytes.
+    pub allocations_per_order: [u64; N_ORDERS],
+}
+
 /// An implementation of freeing bump allocator.
 ///
 /// Refer to the module-level documentation for further details.
 pub struct FreeingBumpHeapAllocator {
+    original_heap_base: u32,
     bumper: u32,
     free_lists: FreeLists,
+    /// `Some` if and only if [`AllocationStrategy::Reclaiming`] is used, in which case
+    /// [`FreeingBumpHeapAllocator::free_lists`] is always empty.
+    free_regions: Option<FreeRegions>,
+    allocations_per_order: [u64; N_ORDERS],
     total_size: u32,
+    total_size_sum: u64,
     poisoned: bool,
     max_total_size: u32,
     max_bumper: u32,
@@ -345,18 +517,26 @@ pub struct FreeingBumpHeapAllocator {
 }
 
 impl FreeingBumpHeapAllocator {
-    /// Creates a new allocation heap which follows a freeing-bump strategy.
+    /// Creates a new allocation heap.
     ///
     /// # Arguments
     ///
     /// - `heap_base` - the offset from the beginning of the linear memory where the heap starts.
-    pub fn new(heap_base: u32) -> Self {
+    /// - `strategy` - the way the deallocated memory is reused.
+    pub fn new(heap_base: u32, strategy: AllocationStrategy) -> Self {
         let aligned_heap_base = (heap_base + ALIGNMENT - 1) / ALIGNMENT * ALIGNMENT;
 
         FreeingBumpHeapAllocator {
+            original_heap_base: aligned_heap_base,
             bumper: aligned_heap_base,
             free_lists: FreeLists::new(),
+            free_regions: match strategy {
+                AllocationStrategy::FreeingBump => None,
+                AllocationStrategy::Reclaiming => Some(FreeRegions::new()),
+            },
+            allocations_per_order: [0; N_ORDERS],
             total_size: 0,
+            total_size_sum: 0,
             poisoned: false,
             max_total_size: 0,
             max_bumper: aligned_heap_base,
@@ -364,6 +544,19 @@ impl FreeingBumpHeapAllocator {
         }
     }
 
+    /// Returns statistics about the memory usage of the allocator.
+    pub fn stats(&self) -> AllocationStats {
+        AllocationStats {
+            bytes_allocated: self.total_size,
+            bytes_allocated_peak: self.max_total_size,
+            bytes_allocated_sum: self.total_size_sum,
+            address_space_used: self.bumper - self.original_heap_base,
+            address_space_used_peak: self.max_bumper - self.original_heap_base,
+            fragmented_bytes: self.bumper - self.original_heap_base - self.total_size,
+            allocations_per_order: self.allocations_per_order,
+        }
+    }
+
     /// Gets requested number of bytes to allocate and returns a pointer.
     /// The maximum size which can be allocated at once is 32 MiB.
     /// There is no minimum size, but whatever size is passed into
@@ -391,8 +584,12 @@ impl FreeingBumpHeapAllocator {
         Self::observe_memory_size(&mut self.last_observed_memory_size, mem)?;
         let order = Order::from_size(size)?;
 
-        let header_ptr: u32 = match self.free_lists[order] {
-            Link::Ptr(header_ptr) => {
+        let header_ptr: u32 = match (&mut self.free_regions, self.free_lists[order]) {
+            (Some(free_regions), _) => match free_regions.take(order.size() + HEADER_SIZE) {
+                Some(header_ptr) => header_ptr,
+                None => Self::bump(&mut self.bumper, order.size() + HEADER_SIZE, mem.size())?,
+            },
+            (None, Link::Ptr(header_ptr)) => {
                 assert!(
                     header_ptr + order.size() + HEADER_SIZE <= mem.size(),
                     "Pointer is looked up in list of free entries, into which
@@ -407,7 +604,7 @@ impl FreeingBumpHeapAllocator {
 
                 header_ptr
             }
-            Link::Nil => {
+            (None, Link::Nil) => {
                 // Corresponding free list is empty. Allocate a new item.
                 Self::bump(&mut self.bumper, order.size() + HEADER_SIZE, mem.size())?
             }
@@ -417,6 +614,8 @@ impl FreeingBumpHeapAllocator {
         Header::Occupied(order).write_into(mem, header_ptr)?;
 
         self.total_size += order.size() + HEADER_SIZE;
+        self.total_size_sum += u64::from(order.size() + HEADER_SIZE);
+        self.allocations_per_order[order.0 as usize] += 1;
 
         // update trackers if needed.
         if self.total_size > self.max_total_size {
@@ -460,9 +659,21 @@ impl FreeingBumpHeapAllocator {
             .into_occupied()
             .ok_or_else(|| error("the allocation points to an empty header"))?;
 
-        // Update the just freed header and knit it back to the free list.
-        let prev_head = self.free_lists.replace(order, Link::Ptr(header_ptr));
-        Header::Free(prev_head).write_into(mem, header_ptr)?;
+        if let Some(free_regions) = &mut self.free_regions {
+            // Mark the header as free in order to detect double frees, then merge the chunk with
+            // the other free regions.
+            Header::Free(Link::Nil).write_into(mem, header_ptr)?;
+            free_regions.release(
+                header_ptr,
+                order.size() + HEADER_SIZE,
+                self.original_heap_base,
+                &mut self.bumper,
+            )?;
+        } else {
+            // Update the just freed header and knit it back to the free list.
+            let prev_head = self.free_lists.replace(order, Link::Ptr(header_ptr));
+            Header::Free(prev_head).write_into(mem, header_ptr)?;
+        }
 
         // Do the total_size book keeping.
         self.total_size = self
@@ -581,7 +792,7 @@ mod tests {
     fn should_allocate_properly() {
         // given
         let mut mem = [0u8; PAGE_SIZE as usize];
-        let mut heap = FreeingBumpHeapAllocator::new(0);
+        let mut heap = FreeingBumpHeapAllocator::new(0, AllocationStrategy::FreeingBump);
 
         // when
         let ptr = heap.allocate(&mut mem[..], 1).unwrap();
@@ -595,7 +806,7 @@ mod tests {
     fn should_always_align_pointers_to_multiples_of_8() {
         // given
         let mut mem = [0u8; PAGE_SIZE as usize];
-        let mut heap = FreeingBumpHeapAllocator::new(13);
+        let mut heap = FreeingBumpHeapAllocator::new(13, AllocationStrategy::FreeingBump);
 
         // when
         let ptr = heap.allocate(&mut mem[..], 1).unwrap();
@@ -610,7 +821,7 @@ mod tests {
     fn should_increment_pointers_properly() {
         // given
         let mut mem = [0u8; PAGE_SIZE as usize];
-        let mut heap = FreeingBumpHeapAllocator::new(0);
+        let mut heap = FreeingBumpHeapAllocator::new(0, AllocationStrategy::FreeingBump);
 
         // when
         let ptr1 = heap.allocate(&mut mem[..], 1).unwrap();
@@ -633,7 +844,7 @@ mod tests {
     fn should_free_properly() {
         // given
         let mut mem = [0u8; PAGE_SIZE as usize];
-        let mut heap = FreeingBumpHeapAllocator::new(0);
+        let mut heap = FreeingBumpHeapAllocator::new(0, AllocationStrategy::FreeingBump);
         let ptr1 = heap.allocate(&mut mem[..], 1).unwrap();
         // the prefix of 8 bytes is prepended to the pointer
         assert_eq!(ptr1, to_pointer(HEADER_SIZE));
@@ -659,7 +870,7 @@ mod tests {
         // given
         let mut mem = [0u8; PAGE_SIZE as usize];
         let padded_offset = 16;
-        let mut heap = FreeingBumpHeapAllocator::new(13);
+        let mut heap = FreeingBumpHeapAllocator::new(13, AllocationStrategy::FreeingBump);
 
         let ptr1 = heap.allocate(&mut mem[..], 1).unwrap();
         // the prefix of 8 bytes is prepended to the pointer
@@ -685,7 +896,7 @@ mod tests {
     fn should_build_linked_list_of_free_areas_properly() {
         // given
         let mut mem = [0u8; PAGE_SIZE as usize];
-        let mut heap = FreeingBumpHeapAllocator::new(0);
+        let mut heap = FreeingBumpHeapAllocator::new(0, AllocationStrategy::FreeingBump);
 
         let ptr1 = heap.allocate(&mut mem[..], 8).unwrap();
         let ptr2 = heap.allocate(&mut mem[..], 8).unwrap();
@@ -715,7 +926,7 @@ mod tests {
     fn should_not_allocate_if_too_large() {
         // given
         let mut mem = [0u8; PAGE_SIZE as usize];
-        let mut heap = FreeingBumpHeapAllocator::new(13);
+        let mut heap = FreeingBumpHeapAllocator::new(13, AllocationStrategy::FreeingBump);
 
         // when
         let ptr = heap.allocate(&mut mem[..], PAGE_SIZE - 13);
@@ -731,7 +942,7 @@ mod tests {
     fn should_not_allocate_if_full() {
         // given
         let mut mem = [0u8; PAGE_SIZE as usize];
-        let mut heap = FreeingBumpHeapAllocator::new(0);
+        let mut heap = FreeingBumpHeapAllocator::new(0, AllocationStrategy::FreeingBump);
         let ptr1 = heap
             .allocate(&mut mem[..], (PAGE_SIZE / 2) - HEADER_SIZE)
             .unwrap();
@@ -752,7 +963,7 @@ mod tests {
     fn should_allocate_max_possible_allocation_size() {
         // given
         let mut mem = vec![0u8; (MAX_POSSIBLE_ALLOCATION + PAGE_SIZE) as usize];
-        let mut heap = FreeingBumpHeapAllocator::new(0);
+        let mut heap = FreeingBumpHeapAllocator::new(0, AllocationStrategy::FreeingBump);
 
         // when
         let ptr = heap
@@ -767,7 +978,7 @@ mod tests {
     fn should_not_allocate_if_requested_size_too_large() {
         // given
         let mut mem = [0u8; PAGE_SIZE as usize];
-        let mut heap = FreeingBumpHeapAllocator::new(0);
+        let mut heap = FreeingBumpHeapAllocator::new(0, AllocationStrategy::FreeingBump);
 
         // when
         let ptr = heap.allocate(&mut mem[..], MAX_POSSIBLE_ALLOCATION + 1);
@@ -783,7 +994,7 @@ mod tests {
     fn should_return_error_when_bumper_greater_than_heap_size() {
         // given
         let mut mem = [0u8; 64];
-        let mut heap = FreeingBumpHeapAllocator::new(0);
+        let mut heap = FreeingBumpHeapAllocator::new(0, AllocationStrategy::FreeingBump);
 
         let ptr1 = heap.allocate(&mut mem[..], 32).unwrap();
         assert_eq!(ptr1, to_pointer(HEADER_SIZE));
@@ -817,7 +1028,7 @@ mod tests {
     fn should_include_prefixes_in_total_heap_size() {
         // given
         let mut mem = [0u8; PAGE_SIZE as usize];
-        let mut heap = FreeingBumpHeapAllocator::new(1);
+        let mut heap = FreeingBumpHeapAllocator::new(1, AllocationStrategy::FreeingBump);
 
         // when
         // an item size of 16 must be used then
@@ -831,7 +1042,7 @@ mod tests {
     fn should_calculate_total_heap_size_to_zero() {
         // given
         let mut mem = [0u8; PAGE_SIZE as usize];
-        let mut heap = FreeingBumpHeapAllocator::new(13);
+        let mut heap = FreeingBumpHeapAllocator::new(13, AllocationStrategy::FreeingBump);
 
         // when
         let ptr = heap.allocate(&mut mem[..], 42).unwrap();
@@ -846,7 +1057,7 @@ mod tests {
     fn should_calculate_total_size_of_zero() {
         // given
         let mut mem = [0u8; PAGE_SIZE as usize];
-        let mut heap = FreeingBumpHeapAllocator::new(19);
+        let mut heap = FreeingBumpHeapAllocator::new(19, AllocationStrategy::FreeingBump);
 
         // when
         for _ in 1..10 {
@@ -898,7 +1109,7 @@ mod tests {
     #[test]
     fn deallocate_needs_to_maintain_linked_list() {
         let mut mem = [0u8; 8 * 2 * 4 + ALIGNMENT as usize];
-        let mut heap = FreeingBumpHeapAllocator::new(0);
+        let mut heap = FreeingBumpHeapAllocator::new(0, AllocationStrategy::FreeingBump);
 
         // Allocate and free some pointers
         let ptrs = (0..4)
@@ -935,7 +1146,7 @@ mod tests {
         // given
         // a heap of 32 bytes. Should be enough for two allocations.
         let mut mem = [0u8; 32];
-        let mut heap = FreeingBumpHeapAllocator::new(0);
+        let mut heap = FreeingBumpHeapAllocator::new(0, AllocationStrategy::FreeingBump);
 
         // when
         assert!(heap.allocate(mem.as_mut(), 8).is_ok());
@@ -962,7 +1173,7 @@ mod tests {
         const ITEM_ON_HEAP_SIZE: usize = 16 + HEADER_SIZE as usize;
 
         let mut mem = vec![0u8; ITEM_ON_HEAP_SIZE * 2];
-        let mut heap = FreeingBumpHeapAllocator::new(0);
+        let mut heap = FreeingBumpHeapAllocator::new(0, AllocationStrategy::FreeingBump);
 
         let _ = heap.allocate(&mut mem[..], ITEM_SIZE).unwrap();
         let _ = heap.allocate(&mut mem[..], ITEM_SIZE).unwrap();
@@ -979,7 +1190,7 @@ mod tests {
 
         let initial_size = ITEM_ON_HEAP_SIZE * 3;
         let mut mem = vec![0u8; initial_size];
-        let mut heap = FreeingBumpHeapAllocator::new(0);
+        let mut heap = FreeingBumpHeapAllocator::new(0, AllocationStrategy::FreeingBump);
 
         let _ = heap.allocate(&mut mem[..], ITEM_SIZE).unwrap();
 
@@ -990,4 +1201,86 @@ mod tests {
             _ => panic!(),
         }
     }
+
+    #[test]
+    fn reclaiming_gives_back_bump_space() {
+        // given
+        let mut mem = [0u8; 32 + HEADER_SIZE as usize];
+        let mut freeing_bump = FreeingBumpHeapAllocator::new(0, AllocationStrategy::FreeingBump);
+        let mut reclaiming = FreeingBumpHeapAllocator::new(0, AllocationStrategy::Reclaiming);
+
+        // when
+        let ptr = freeing_bump.allocate(&mut mem[..], 32).unwrap();
+        freeing_bump.deallocate(&mut mem[..], ptr).unwrap();
+        let ptr = reclaiming.allocate(&mut mem[..], 32).unwrap();
+        reclaiming.deallocate(&mut mem[..], ptr).unwrap();
+
+        // then
+        assert!(freeing_bump.allocate(&mut mem[..], 8).is_err());
+        assert_eq!(reclaiming.stats().address_space_used, 0);
+        assert_eq!(
+            reclaiming.allocate(&mut mem[..], 8).unwrap(),
+            to_pointer(HEADER_SIZE)
+        );
+    }
+
+    #[test]
+    fn reclaiming_merges_free_regions() {
+        // given
+        let mut mem = [0u8; PAGE_SIZE as usize];
+        let mut heap = FreeingBumpHeapAllocator::new(0, AllocationStrategy::Reclaiming);
+        let ptr1 = heap.allocate(&mut mem[..], 8).unwrap();
+        let ptr2 = heap.allocate(&mut mem[..], 8).unwrap();
+        let _ptr3 = heap.allocate(&mut mem[..], 8).unwrap();
+
+        // when
+        heap.deallocate(&mut mem[..], ptr1).unwrap();
+        heap.deallocate(&mut mem[..], ptr2).unwrap();
+        let ptr = heap.allocate(&mut mem[..], 16).unwrap();
+
+        // then
+        // the two freed chunks of 16 bytes have been merged and the allocation of 24 bytes was
+        // put in the resulting region
+        assert_eq!(ptr, to_pointer(HEADER_SIZE));
+        let stats = heap.stats();
+        assert_eq!(stats.address_space_used, 48);
+        assert_eq!(stats.fragmented_bytes, 8);
+    }
+
+    #[test]
+    fn reclaiming_detects_double_free() {
+        // given
+        let mut mem = [0u8; PAGE_SIZE as usize];
+        let mut heap = FreeingBumpHeapAllocator::new(0, AllocationStrategy::Reclaiming);
+        let ptr = heap.allocate(&mut mem[..], 8).unwrap();
+        let _ = heap.allocate(&mut mem[..], 8).unwrap();
+        heap.deallocate(&mut mem[..], ptr).unwrap();
+
+        // when
+        let result = heap.deallocate(&mut mem[..], ptr);
+
+        // then
+        assert!(result.is_err());
+    }
+
+    #[test]
+    fn stats_count_allocations_per_order() {
+        // given
+        let mut mem = [0u8; PAGE_SIZE as usize];
+        let mut heap = FreeingBumpHeapAllocator::new(0, AllocationStrategy::FreeingBump);
+
+        // when
+        let ptr = heap.allocate(&mut mem[..], 1).unwrap();
+        heap.deallocate(&mut mem[..], ptr).unwrap();
+        let _ = heap.allocate(&mut mem[..], 8).unwrap();
+        let _ = heap.allocate(&mut mem[..], 100).unwrap();
+
+        // then
+        let stats = heap.stats();
+        assert_eq!(stats.allocations_per_order[0], 2);
+        assert_eq!(stats.allocations_per_order[4], 1);
+        assert_eq!(stats.allocations_per_order.iter().sum::<u64>(), 3);
+        assert_eq!(stats.address_space_used_peak, stats.address_space_used);
+        assert_eq!(stats.fragmented_bytes, 0);
+    }
 }
@@ -137,6 +137,13 @@
 //! [`HostVm::ExternalStorageGet`], you must load a value from the storage and pass it back by
 //! calling [`ExternalStorageGet::resume`].
 //!
+//! ## Tracing
+//!
+//! Calling [`ReadyToRun::enable_tracing`] makes the virtual machine record every host function
+//! that the runtime calls, alongside with its parameters and how long it took to handle it.
+//! Once the execution is over, this [`ExecutionTrace`] can be retrieved with
+//! [`Finished::take_execution_trace`] or [`HostVmPrototype::take_execution_trace`].
+//!
 //! The Wasm execution is fully deterministic, and the outcome of the execution only depends on
 //! the inputs. There is, for example, no implicit injection of randomness or of the current time.
 //!
@@ -153,7 +160,10 @@
 //!         module: &wasm_binary_code,
 //!         heap_pages: HeapPages::from(2048),
 //!         exec_hint: smoldot::executor::vm::ExecHint::Oneshot,
-//!         allow_unresolved_imports: false
+//!         allow_unresolved_imports: false,
+//!         compiled_module_cache: None,
+//!         resource_limits: Default::default(),
+//!         allocation_strategy: Default::default(),
 //!     }).unwrap();
 //!     prototype.run_no_param("Core_version").unwrap().into()
 //! };
@@ -198,13 +208,15 @@ use alloc::{
     borrow::ToOwned as _,
     boxed::Box,
     string::{String, ToString as _},
+    sync::Arc,
     vec,
     vec::Vec,
 };
-use core::{fmt, hash::Hasher as _, iter, str};
+use core::{fmt, hash::Hasher as _, iter, str, time::Duration};
 
 pub mod runtime_version;
 
+pub use allocator::{AllocationStats, AllocationStrategy};
 pub use runtime_version::{
     CoreVersion, CoreVersionApisFromSliceErr, CoreVersionError, CoreVersionRef,
     FindEncodedEmbeddedRuntimeVersionApisError,
@@ -236,6 +248,30 @@ pub struct Config<TModule> {
     /// a [`Error::UnresolvedFunctionCalled`] error will be generated if the module tries to call
     /// an unresolved function.
     pub allow_unresolved_imports: bool,
+
+    /// Cache of compiled modules, used in order to avoid compiling the same runtime again when
+    /// [`vm::ExecHint::CompileAheadOfTime`] is used. Entries are keyed by the hash of the
+    /// runtime code and by `heap_pages`, amongst others.
+    ///
+    /// See [`vm::CompiledModuleCache`].
+    pub compiled_module_cache: Option<Arc<dyn vm::CompiledModuleCache>>,
+
+    /// Limits on the resources that each call to the runtime can use. A call that consumes all
+    /// its fuel fails with [`Error::OutOfFuel`].
+    ///
+    /// [`vm::ResourceLimits::max_memory_pages`] includes the memory reserved for `heap_pages`.
+    /// If it is too low to fit `heap_pages`, [`NewErr::MemoryMaxSizeTooLow`] is returned.
+    ///
+    /// See [`vm::ResourceLimits`].
+    pub resource_limits: vm::ResourceLimits,
+
+    /// Strategy used by the implementation of `ext_allocator_malloc_version_1` and
+    /// `ext_allocator_free_version_1` in order to reuse the memory that the runtime has freed.
+    ///
+    /// [`AllocationStrategy::FreeingBump`] should be used when executing blocks, as it is the
+    /// strategy that the runtimes expect. [`AllocationStrategy::Reclaiming`] can run calls that
+    /// would otherwise run out of memory.
+    pub allocation_strategy: AllocationStrategy,
 }
 
 /// Prototype for an [`HostVm`].
@@ -249,6 +285,13 @@ pub struct HostVmPrototype {
 
     /// Inner virtual machine prototype.
     vm_proto: vm::VirtualMachinePrototype,
+
+    /// Trace of the latest execution. See [`HostVmPrototype::take_execution_trace`].
+    execution_trace: Option<Box<ExecutionTrace>>,
+
+    /// Statistics about the memory allocator at the end of the latest execution. See
+    /// [`HostVmPrototype::allocation_stats`].
+    allocation_stats: Option<Box<AllocationStats>>,
 }
 
 /// Fields that are kept as is even during the execution.
@@ -276,6 +319,9 @@ struct VmCommon {
     /// Total number of pages of Wasm memory. This is equal to `heap_base / 64k` (rounded up) plus
     /// `heap_pages`.
     memory_total_pages: HeapPages,
+
+    /// Value of [`Config::allocation_strategy`].
+    allocation_strategy: AllocationStrategy,
 }
 
 impl HostVmPrototype {
@@ -328,6 +374,13 @@ impl HostVmPrototype {
             let vm_proto = vm::VirtualMachinePrototype::new(vm::Config {
                 module_bytes: &module_bytes[..],
                 exec_hint: config.exec_hint,
+                compiled_module_cache: config.compiled_module_cache.as_deref().map(|cache| {
+                    vm::CompiledModuleCacheConfig {
+                        cache,
+                        heap_pages: config.heap_pages,
+                    }
+                }),
+                resource_limits: config.resource_limits,
                 // This closure is called back for each function that the runtime imports.
                 symbols: &mut |mod_name, f_name, signature| {
                     if mod_name != "env" {
@@ -373,6 +426,8 @@ impl HostVmPrototype {
         }
 
         let mut host_vm_prototype = HostVmPrototype {
+            execution_trace: None,
+            allocation_stats: None,
             vm_proto,
             common: Box::new(VmCommon {
                 runtime_version,
@@ -380,6 +435,7 @@ impl HostVmPrototype {
                 registered_functions,
                 heap_pages: config.heap_pages,
                 memory_total_pages,
+                allocation_strategy: config.allocation_strategy,
             }),
         };
 
@@ -439,6 +495,22 @@ impl HostVmPrototype {
         self.common.runtime_version.as_ref().unwrap()
     }
 
+    /// Returns the trace of the latest execution that has been turned back into this prototype,
+    /// provided that [`ReadyToRun::enable_tracing`] was called during this execution.
+    ///
+    /// Returns `None` if the trace has already been taken out.
+    pub fn take_execution_trace(&mut self) -> Option<ExecutionTrace> {
+        self.execution_trace.take().map(|trace| *trace)
+    }
+
+    /// Returns statistics about the memory allocator at the end of the latest execution that
+    /// has been turned back into this prototype, including executions that have failed.
+    ///
+    /// Returns `None` if this prototype has never been executed.
+    pub fn allocation_stats(&self) -> Option<&AllocationStats> {
+        self.allocation_stats.as_deref()
+    }
+
     /// Starts the VM, calling the function passed as parameter.
     pub fn run(self, function_to_call: &str, data: &[u8]) -> Result<ReadyToRun, (StartErr, Self)> {
         self.run_vectored(function_to_call, iter::once(data))
@@ -472,7 +544,10 @@ impl HostVmPrototype {
         // Initialize the state of the memory allocator. This is the allocator that is used in
         // order to allocate space for the input data, and also later used when the Wasm code
         // requests variable-length data.
-        let mut allocator = allocator::FreeingBumpHeapAllocator::new(self.common.heap_base);
+        let mut allocator = allocator::FreeingBumpHeapAllocator::new(
+            self.common.heap_base,
+            self.common.allocation_strategy,
+        );
 
         // Prepare the virtual machine for execution.
         let mut vm = self.vm_proto.prepare();
@@ -535,6 +610,7 @@ impl HostVmPrototype {
                 storage_transaction_depth: 0,
                 signatures_batch_verification: None,
                 allocator,
+                tracer: None,
             }),
         })
     }
@@ -604,9 +680,36 @@ pub enum HostVm {
     /// Submit a transaction from offchain worker.
     #[from]
     OffchainSubmitTransaction(OffchainSubmitTransaction),
+    /// Must start an HTTP request.
+    #[from]
+    OffchainHttpRequestStart(OffchainHttpRequestStart),
+    /// Must add a header to an HTTP request that hasn't been sent yet.
+    #[from]
+    OffchainHttpRequestAddHeader(OffchainHttpRequestAddHeader),
+    /// Must write a chunk of the body of an HTTP request.
+    #[from]
+    OffchainHttpRequestWriteBody(OffchainHttpRequestWriteBody),
+    /// Must wait for the responses to some HTTP requests.
+    #[from]
+    OffchainHttpResponseWait(OffchainHttpResponseWait),
+    /// Must provide the headers of the response to an HTTP request.
+    #[from]
+    OffchainHttpResponseHeaders(OffchainHttpResponseHeaders),
+    /// Must read a chunk of the body of the response to an HTTP request.
+    #[from]
+    OffchainHttpResponseReadBody(OffchainHttpResponseReadBody),
     /// Need to verify whether a signature is valid.
     #[from]
     SignatureVerification(SignatureVerification),
+    /// Must provide the public keys of the keystore of a certain key type and algorithm.
+    #[from]
+    KeystorePublicKeys(KeystorePublicKeys),
+    /// Must generate a new key in the keystore.
+    #[from]
+    KeystoreGenerate(KeystoreGenerate),
+    /// Must sign a message using a key of the keystore.
+    #[from]
+    KeystoreSign(KeystoreSign),
     /// Need to call `Core_version` on the given Wasm code and return the raw output (i.e.
     /// still SCALE-encoded), or an error if the call has failed.
     #[from]
@@ -654,7 +757,16 @@ impl HostVm {
             HostVm::OffchainTimestamp(inner) => inner.inner.into_prototype(),
             HostVm::OffchainRandomSeed(inner) => inner.inner.into_prototype(),
             HostVm::OffchainSubmitTransaction(inner) => inner.inner.into_prototype(),
+            HostVm::OffchainHttpRequestStart(inner) => inner.inner.into_prototype(),
+            HostVm::OffchainHttpRequestAddHeader(inner) => inner.inner.into_prototype(),
+            HostVm::OffchainHttpRequestWriteBody(inner) => inner.inner.into_prototype(),
+            HostVm::OffchainHttpResponseWait(inner) => inner.inner.into_prototype(),
+            HostVm::OffchainHttpResponseHeaders(inner) => inner.inner.into_prototype(),
+            HostVm::OffchainHttpResponseReadBody(inner) => inner.inner.into_prototype(),
             HostVm::SignatureVerification(inner) => inner.inner.into_prototype(),
+            HostVm::KeystorePublicKeys(inner) => inner.inner.into_prototype(),
+            HostVm::KeystoreGenerate(inner) => inner.inner.into_prototype(),
+            HostVm::KeystoreSign(inner) => inner.inner.into_prototype(),
             HostVm::CallRuntimeVersion(inner) => inner.inner.into_prototype(),
             HostVm::StartStorageTransaction(inner) => inner.inner.into_prototype(),
             HostVm::EndStorageTransaction { resume, .. } => resume.inner.into_prototype(),
@@ -662,6 +774,44 @@ impl HostVm {
             HostVm::LogEmit(inner) => inner.inner.into_prototype(),
         }
     }
+
+    /// Returns statistics about the memory allocator of the current execution.
+    pub fn allocation_stats(&self) -> AllocationStats {
+        match self {
+            HostVm::ReadyToRun(inner) => inner.inner.allocator.stats(),
+            HostVm::Finished(inner) => inner.inner.allocator.stats(),
+            // An error is always generated by an execution, and the prototype thus always
+            // contains the statistics of this execution.
+            HostVm::Error { prototype, .. } => *prototype.allocation_stats().unwrap(),
+            HostVm::ExternalStorageGet(inner) => inner.inner.allocator.stats(),
+            HostVm::ExternalStorageSet(inner) => inner.inner.allocator.stats(),
+            HostVm::ExternalStorageAppend(inner) => inner.inner.allocator.stats(),
+            HostVm::ExternalStorageClearPrefix(inner) => inner.inner.allocator.stats(),
+            HostVm::ExternalStorageRoot(inner) => inner.inner.allocator.stats(),
+            HostVm::ExternalStorageNextKey(inner) => inner.inner.allocator.stats(),
+            HostVm::ExternalOffchainIndexSet(inner) => inner.inner.allocator.stats(),
+            HostVm::ExternalOffchainStorageGet(inner) => inner.inner.allocator.stats(),
+            HostVm::ExternalOffchainStorageSet(inner) => inner.inner.allocator.stats(),
+            HostVm::OffchainTimestamp(inner) => inner.inner.allocator.stats(),
+            HostVm::OffchainRandomSeed(inner) => inner.inner.allocator.stats(),
+            HostVm::OffchainSubmitTransaction(inner) => inner.inner.allocator.stats(),
+            HostVm::OffchainHttpRequestStart(inner) => inner.inner.allocator.stats(),
+            HostVm::OffchainHttpRequestAddHeader(inner) => inner.inner.allocator.stats(),
+            HostVm::OffchainHttpRequestWriteBody(inner) => inner.inner.allocator.stats(),
+            HostVm::OffchainHttpResponseWait(inner) => inner.inner.allocator.stats(),
+            HostVm::OffchainHttpResponseHeaders(inner) => inner.inner.allocator.stats(),
+            HostVm::OffchainHttpResponseReadBody(inner) => inner.inner.allocator.stats(),
+            HostVm::SignatureVerification(inner) => inner.inner.allocator.stats(),
+            HostVm::KeystorePublicKeys(inner) => inner.inner.allocator.stats(),
+            HostVm::KeystoreGenerate(inner) => inner.inner.allocator.stats(),
+            HostVm::KeystoreSign(inner) => inner.inner.allocator.stats(),
+            HostVm::CallRuntimeVersion(inner) => inner.inner.allocator.stats(),
+            HostVm::StartStorageTransaction(inner) => inner.inner.allocator.stats(),
+            HostVm::EndStorageTransaction { resume, .. } => resume.inner.allocator.stats(),
+            HostVm::GetMaxLogLevel(inner) => inner.inner.allocator.stats(),
+            HostVm::LogEmit(inner) => inner.inner.allocator.stats(),
+        }
+    }
 }
 
 /// Virtual machine is ready to run.
@@ -671,6 +821,23 @@ pub struct ReadyToRun {
 }
 
 impl ReadyToRun {
+    /// Starts recording the host function calls performed by the runtime from now on.
+    ///
+    /// The `clock` must return the time elapsed since an arbitrary fixed moment, and is used in
+    /// order to measure how long each host function call took. The time it takes for the user
+    /// of this module to handle a host function call is included in this measurement.
+    ///
+    /// Has no effect if tracing was already enabled.
+    pub fn enable_tracing(&mut self, clock: Box<dyn Fn() -> Duration + Send + Sync>) {
+        if self.inner.tracer.is_none() {
+            self.inner.tracer = Some(Box::new(Tracer {
+                clock,
+                host_function_calls: Vec::new(),
+                current_call_start: None,
+            }));
+        }
+    }
+
     /// Runs the virtual machine until something important happens.
     ///
     /// > **Note**: This is when the actual CPU-heavy computation happens.
@@ -684,6 +851,12 @@ impl ReadyToRun {
     }
 
     fn run_once(mut self) -> HostVm {
+        // If the execution is being traced, the host function whose value is being resumed is
+        // now over.
+        if let Some(tracer) = &mut self.inner.tracer {
+            tracer.finish_host_function_call();
+        }
+
         // `vm::ExecOutcome::Interrupted` is by far the variant that requires the most
         // handling code. As such, special-case all other variants before.
         let (id, params) = match self.inner.vm.run(self.resume_value) {
@@ -753,6 +926,13 @@ impl ReadyToRun {
                 }
             }
 
+            Ok(vm::ExecOutcome::OutOfFuel) => {
+                return HostVm::Error {
+                    error: Error::OutOfFuel,
+                    prototype: self.inner.into_prototype(),
+                }
+            }
+
             Err(vm::RunErr::BadValueTy { .. }) => {
                 // Tried to inject back the value returned by a host function, but it doesn't
                 // match what the Wasm code expects. Given that we check the host function
@@ -782,6 +962,10 @@ impl ReadyToRun {
             None => unreachable!(),
         };
 
+        if let Some(tracer) = &mut self.inner.tracer {
+            tracer.start_host_function_call(host_fn.name(), &params);
+        }
+
         // Passed a parameter index. Produces an `impl AsRef<[u8]>`.
         macro_rules! expect_pointer_size {
             ($num:expr) => {{
@@ -927,6 +1111,87 @@ impl ReadyToRun {
         }
 
         // TODO: implement all functions and remove this macro
+        macro_rules! expect_http_request_id {
+            ($num:expr) => {{
+                match u16::try_from(expect_u32!($num)) {
+                    Ok(id) => id,
+                    Err(_) => {
+                        return HostVm::Error {
+                            error: Error::ParamDecodeError,
+                            prototype: self.inner.into_prototype(),
+                        }
+                    }
+                }
+            }};
+        }
+
+        // Deadlines of HTTP requests are passed as a SCALE-encoded `Option<u64>`.
+        macro_rules! expect_http_deadline {
+            ($num:expr) => {{
+                let deadline = {
+                    let input = expect_pointer_size!($num);
+                    let parsing_result: Result<_, nom::Err<(&[u8], nom::error::ErrorKind)>> =
+                        nom::combinator::all_consuming(util::nom_option_decode(
+                            nom::number::streaming::le_u64,
+                        ))(input.as_ref())
+                        .map(|(_, parse_result)| parse_result);
+
+                    match parsing_result {
+                        Ok(val) => Ok(val),
+                        Err(_) => Err(()),
+                    }
+                };
+
+                match deadline {
+                    Ok(deadline) => deadline,
+                    Err(()) => {
+                        return HostVm::Error {
+                            error: Error::ParamDecodeError,
+                            prototype: self.inner.into_prototype(),
+                        }
+                    }
+                }
+            }};
+        }
+
+        // Seeds passed when generating keys are a SCALE-encoded `Option<Vec<u8>>`. Produces the
+        // pointer and size of the seed itself, if any.
+        macro_rules! expect_keystore_seed {
+            ($num:expr) => {{
+                let (ptr, _) = expect_pointer_size_raw!($num);
+                let seed = {
+                    let input = expect_pointer_size!($num);
+                    let parsing_result: Result<_, nom::Err<(&[u8], nom::error::ErrorKind)>> =
+                        nom::combinator::all_consuming(util::nom_option_decode(
+                            util::nom_bytes_decode,
+                        ))(input.as_ref())
+                        .map(|(_, parse_result)| {
+                            // The seed, if any, is found at the end of the parameter.
+                            parse_result.map(|seed| (input.as_ref().len() - seed.len(), seed.len()))
+                        });
+
+                    match parsing_result {
+                        Ok(val) => Ok(val),
+                        Err(_) => Err(()),
+                    }
+                };
+
+                match seed {
+                    Ok(Some((offset, size))) => Some((
+                        ptr + u32::try_from(offset).unwrap(),
+                        u32::try_from(size).unwrap(),
+                    )),
+                    Ok(None) => None,
+                    Err(()) => {
+                        return HostVm::Error {
+                            error: Error::ParamDecodeError,
+                            prototype: self.inner.into_prototype(),
+                        }
+                    }
+                }
+            }};
+        }
+
         macro_rules! host_fn_not_implemented {
             () => {{
                 return HostVm::Error {
@@ -1370,9 +1635,34 @@ impl ReadyToRun {
                     child_trie_ptr_size: Some((child_trie_ptr, child_trie_size)),
                 })
             }
-            HostFunction::ext_crypto_ed25519_public_keys_version_1 => host_fn_not_implemented!(),
-            HostFunction::ext_crypto_ed25519_generate_version_1 => host_fn_not_implemented!(),
-            HostFunction::ext_crypto_ed25519_sign_version_1 => host_fn_not_implemented!(),
+            HostFunction::ext_crypto_ed25519_public_keys_version_1 => {
+                HostVm::KeystorePublicKeys(KeystorePublicKeys {
+                    algorithm: KeyAlgorithm::Ed25519,
+                    key_type_id: expect_pointer_constant_size!(0, 4),
+                    inner: self.inner,
+                })
+            }
+            HostFunction::ext_crypto_ed25519_generate_version_1 => {
+                let key_type_id = expect_pointer_constant_size!(0, 4);
+                let seed = expect_keystore_seed!(1);
+                HostVm::KeystoreGenerate(KeystoreGenerate {
+                    algorithm: KeyAlgorithm::Ed25519,
+                    key_type_id,
+                    seed,
+                    inner: self.inner,
+                })
+            }
+            HostFunction::ext_crypto_ed25519_sign_version_1 => {
+                let (message_ptr, message_size) = expect_pointer_size_raw!(2);
+                HostVm::KeystoreSign(KeystoreSign {
+                    algorithm: KeyAlgorithm::Ed25519,
+                    key_type_id: expect_pointer_constant_size!(0, 4),
+                    public_key_ptr: expect_pointer_constant_size_raw!(1, 32),
+                    message_ptr,
+                    message_size,
+                    inner: self.inner,
+                })
+            }
             HostFunction::ext_crypto_ed25519_verify_version_1
             | HostFunction::ext_crypto_ed25519_batch_verify_version_1 => {
                 let is_batch_verification = matches!(
@@ -1398,9 +1688,34 @@ impl ReadyToRun {
                     is_batch_verification,
                 })
             }
-            HostFunction::ext_crypto_sr25519_public_keys_version_1 => host_fn_not_implemented!(),
-            HostFunction::ext_crypto_sr25519_generate_version_1 => host_fn_not_implemented!(),
-            HostFunction::ext_crypto_sr25519_sign_version_1 => host_fn_not_implemented!(),
+            HostFunction::ext_crypto_sr25519_public_keys_version_1 => {
+                HostVm::KeystorePublicKeys(KeystorePublicKeys {
+                    algorithm: KeyAlgorithm::Sr25519,
+                    key_type_id: expect_pointer_constant_size!(0, 4),
+                    inner: self.inner,
+                })
+            }
+            HostFunction::ext_crypto_sr25519_generate_version_1 => {
+                let key_type_id = expect_pointer_constant_size!(0, 4);
+                let seed = expect_keystore_seed!(1);
+                HostVm::KeystoreGenerate(KeystoreGenerate {
+                    algorithm: KeyAlgorithm::Sr25519,
+                    key_type_id,
+                    seed,
+                    inner: self.inner,
+                })
+            }
+            HostFunction::ext_crypto_sr25519_sign_version_1 => {
+                let (message_ptr, message_size) = expect_pointer_size_raw!(2);
+                HostVm::KeystoreSign(KeystoreSign {
+                    algorithm: KeyAlgorithm::Sr25519,
+                    key_type_id: expect_pointer_constant_size!(0, 4),
+                    public_key_ptr: expect_pointer_constant_size_raw!(1, 32),
+                    message_ptr,
+                    message_size,
+                    inner: self.inner,
+                })
+            }
             HostFunction::ext_crypto_sr25519_verify_version_1
             | HostFunction::ext_crypto_sr25519_batch_verify_version_1 => {
                 let is_batch_verification = matches!(
@@ -1438,34 +1753,34 @@ impl ReadyToRun {
                     is_batch_verification: false,
                 })
             }
-            HostFunction::ext_crypto_ecdsa_generate_version_1 => host_fn_not_implemented!(),
+            HostFunction::ext_crypto_ecdsa_generate_version_1 => {
+                let key_type_id = expect_pointer_constant_size!(0, 4);
+                let seed = expect_keystore_seed!(1);
+                HostVm::KeystoreGenerate(KeystoreGenerate {
+                    algorithm: KeyAlgorithm::Ecdsa,
+                    key_type_id,
+                    seed,
+                    inner: self.inner,
+                })
+            }
             HostFunction::ext_crypto_ecdsa_sign_version_1 => {
-                // NOTE: safe to unwrap here because we supply the nn to blake2b fn
-                let data = <[u8; 32]>::try_from(
-                    blake2_rfc::blake2b::blake2b(32, &[], expect_pointer_size!(0).as_ref())
-                        .as_bytes(),
-                )
-                .unwrap();
-                let message = libsecp256k1::Message::parse(&data);
-
-                if let Ok(sc) =
-                    libsecp256k1::SecretKey::parse(&expect_pointer_constant_size!(1, 32))
-                {
-                    let (sig, ri) = libsecp256k1::sign(&message, &sc);
-
-                    // NOTE: the function returns 2 slices: signature (64 bytes) and recovery ID (1 byte; AS A SLICE)
-                    self.inner.alloc_write_and_return_pointer(
-                        host_fn.name(),
-                        [&sig.serialize()[..], &[ri.serialize()]].into_iter(),
-                    )
-                } else {
-                    HostVm::Error {
-                        error: Error::ParamDecodeError,
-                        prototype: self.inner.into_prototype(),
-                    }
-                }
+                let (message_ptr, message_size) = expect_pointer_size_raw!(2);
+                HostVm::KeystoreSign(KeystoreSign {
+                    algorithm: KeyAlgorithm::Ecdsa,
+                    key_type_id: expect_pointer_constant_size!(0, 4),
+                    public_key_ptr: expect_pointer_constant_size_raw!(1, 33),
+                    message_ptr,
+                    message_size,
+                    inner: self.inner,
+                })
+            }
+            HostFunction::ext_crypto_ecdsa_public_keys_version_1 => {
+                HostVm::KeystorePublicKeys(KeystorePublicKeys {
+                    algorithm: KeyAlgorithm::Ecdsa,
+                    key_type_id: expect_pointer_constant_size!(0, 4),
+                    inner: self.inner,
+                })
             }
-            HostFunction::ext_crypto_ecdsa_public_keys_version_1 => host_fn_not_implemented!(),
             HostFunction::ext_crypto_ecdsa_verify_version_1
             | HostFunction::ext_crypto_ecdsa_batch_verify_version_1 => {
                 let is_batch_verification = matches!(
@@ -1639,12 +1954,11 @@ impl ReadyToRun {
                 })
             }
             HostFunction::ext_crypto_finish_batch_verify_version_1 => {
-                let Some(outcome) = self.inner.signatures_batch_verification.take()
-                else {
+                let Some(outcome) = self.inner.signatures_batch_verification.take() else {
                     return HostVm::Error {
                         error: Error::NoBatchVerify,
                         prototype: self.inner.into_prototype(),
-                    }
+                    };
                 };
 
                 HostVm::ReadyToRun(ReadyToRun {
@@ -1835,19 +2149,130 @@ impl ReadyToRun {
                     inner: self.inner,
                 })
             }
-            HostFunction::ext_offchain_http_request_start_version_1 => host_fn_not_implemented!(),
+            HostFunction::ext_offchain_http_request_start_version_1 => {
+                let (method_ptr, method_size) = expect_pointer_size_raw!(0);
+                let (uri_ptr, uri_size) = expect_pointer_size_raw!(1);
+                // The third parameter is reserved for future use and is ignored.
+
+                for (param_num, ptr, size) in [(0, method_ptr, method_size), (1, uri_ptr, uri_size)]
+                {
+                    let utf8_check =
+                        str::from_utf8(self.inner.vm.read_memory(ptr, size).unwrap().as_ref())
+                            .map(|_| ());
+                    if let Err(error) = utf8_check {
+                        return HostVm::Error {
+                            error: Error::Utf8Error {
+                                function: host_fn.name(),
+                                param_num,
+                                error,
+                            },
+                            prototype: self.inner.into_prototype(),
+                        };
+                    }
+                }
+
+                HostVm::OffchainHttpRequestStart(OffchainHttpRequestStart {
+                    inner: self.inner,
+                    method_ptr,
+                    method_size,
+                    uri_ptr,
+                    uri_size,
+                })
+            }
             HostFunction::ext_offchain_http_request_add_header_version_1 => {
-                host_fn_not_implemented!()
+                let request_id = expect_http_request_id!(0);
+                let (name_ptr, name_size) = expect_pointer_size_raw!(1);
+                let (value_ptr, value_size) = expect_pointer_size_raw!(2);
+
+                for (param_num, ptr, size) in [(1, name_ptr, name_size), (2, value_ptr, value_size)]
+                {
+                    let utf8_check =
+                        str::from_utf8(self.inner.vm.read_memory(ptr, size).unwrap().as_ref())
+                            .map(|_| ());
+                    if let Err(error) = utf8_check {
+                        return HostVm::Error {
+                            error: Error::Utf8Error {
+                                function: host_fn.name(),
+                                param_num,
+                                error,
+                            },
+                            prototype: self.inner.into_prototype(),
+                        };
+                    }
+                }
+
+                HostVm::OffchainHttpRequestAddHeader(OffchainHttpRequestAddHeader {
+                    inner: self.inner,
+                    request_id,
+                    name_ptr,
+                    name_size,
+                    value_ptr,
+                    value_size,
+                })
             }
             HostFunction::ext_offchain_http_request_write_body_version_1 => {
-                host_fn_not_implemented!()
+                let request_id = expect_http_request_id!(0);
+                let (chunk_ptr, chunk_size) = expect_pointer_size_raw!(1);
+                let deadline = expect_http_deadline!(2);
+                HostVm::OffchainHttpRequestWriteBody(OffchainHttpRequestWriteBody {
+                    inner: self.inner,
+                    request_id,
+                    chunk_ptr,
+                    chunk_size,
+                    deadline,
+                })
+            }
+            HostFunction::ext_offchain_http_response_wait_version_1 => {
+                let request_ids = {
+                    let input = expect_pointer_size!(0);
+                    let parsing_result: Result<_, nom::Err<(&[u8], nom::error::ErrorKind)>> =
+                        nom::combinator::all_consuming(nom::multi::length_count(
+                            util::nom_scale_compact_usize,
+                            nom::number::streaming::le_u16,
+                        ))(input.as_ref())
+                        .map(|(_, parse_result)| parse_result);
+
+                    match parsing_result {
+                        Ok(val) => Ok(val),
+                        Err(_) => Err(()),
+                    }
+                };
+
+                let request_ids = match request_ids {
+                    Ok(ids) => ids,
+                    Err(()) => {
+                        return HostVm::Error {
+                            error: Error::ParamDecodeError,
+                            prototype: self.inner.into_prototype(),
+                        };
+                    }
+                };
+
+                let deadline = expect_http_deadline!(1);
+                HostVm::OffchainHttpResponseWait(OffchainHttpResponseWait {
+                    inner: self.inner,
+                    request_ids,
+                    deadline,
+                })
             }
-            HostFunction::ext_offchain_http_response_wait_version_1 => host_fn_not_implemented!(),
             HostFunction::ext_offchain_http_response_headers_version_1 => {
-                host_fn_not_implemented!()
+                let request_id = expect_http_request_id!(0);
+                HostVm::OffchainHttpResponseHeaders(OffchainHttpResponseHeaders {
+                    inner: self.inner,
+                    request_id,
+                })
             }
             HostFunction::ext_offchain_http_response_read_body_version_1 => {
-                host_fn_not_implemented!()
+                let request_id = expect_http_request_id!(0);
+                let (buffer_ptr, buffer_size) = expect_pointer_size_raw!(1);
+                let deadline = expect_http_deadline!(2);
+                HostVm::OffchainHttpResponseReadBody(OffchainHttpResponseReadBody {
+                    inner: self.inner,
+                    request_id,
+                    buffer_ptr,
+                    buffer_size,
+                    deadline,
+                })
             }
             HostFunction::ext_trie_blake2_256_root_version_1
             | HostFunction::ext_trie_blake2_256_root_version_2
@@ -1975,10 +2400,49 @@ impl ReadyToRun {
                     },
                 }
             }
-            HostFunction::ext_trie_blake2_256_verify_proof_version_1 => host_fn_not_implemented!(),
-            HostFunction::ext_trie_blake2_256_verify_proof_version_2 => host_fn_not_implemented!(),
-            HostFunction::ext_trie_keccak_256_verify_proof_version_1 => host_fn_not_implemented!(),
-            HostFunction::ext_trie_keccak_256_verify_proof_version_2 => host_fn_not_implemented!(),
+            HostFunction::ext_trie_blake2_256_verify_proof_version_1
+            | HostFunction::ext_trie_blake2_256_verify_proof_version_2
+            | HostFunction::ext_trie_keccak_256_verify_proof_version_1
+            | HostFunction::ext_trie_keccak_256_verify_proof_version_2 => {
+                let state_version = if matches!(
+                    host_fn,
+                    HostFunction::ext_trie_blake2_256_verify_proof_version_2
+                        | HostFunction::ext_trie_keccak_256_verify_proof_version_2
+                ) {
+                    expect_state_version!(4)
+                } else {
+                    TrieEntryVersion::V0
+                };
+
+                let hash_function = if matches!(
+                    host_fn,
+                    HostFunction::ext_trie_blake2_256_verify_proof_version_1
+                        | HostFunction::ext_trie_blake2_256_verify_proof_version_2
+                ) {
+                    trie::HashFunction::Blake2
+                } else {
+                    trie::HashFunction::Keccak256
+                };
+
+                let trie_root_hash = expect_pointer_constant_size!(0, 32);
+                let proof = expect_pointer_size!(1).as_ref().to_vec();
+                let key = expect_pointer_size!(2).as_ref().to_vec();
+                let value = expect_pointer_size!(3).as_ref().to_vec();
+
+                let is_valid = trie::ordered_proof::verify(trie::ordered_proof::Config {
+                    proof: &proof,
+                    trie_root_hash: &trie_root_hash,
+                    entries: iter::once((&key[..], Some(&value[..]))),
+                    hash_function,
+                    trie_entries_version: state_version,
+                })
+                .is_ok();
+
+                HostVm::ReadyToRun(ReadyToRun {
+                    inner: self.inner,
+                    resume_value: Some(vm::WasmValue::I32(if is_valid { 1 } else { 0 })),
+                })
+            }
             HostFunction::ext_misc_print_num_version_1 => {
                 let num = match params[0] {
                     vm::WasmValue::I64(v) => u64::from_ne_bytes(v.to_ne_bytes()),
@@ -2169,6 +2633,14 @@ impl Finished {
             .unwrap()
     }
 
+    /// Returns the trace of the execution, provided that [`ReadyToRun::enable_tracing`] has
+    /// been called.
+    ///
+    /// Returns `None` if the trace has already been taken out.
+    pub fn take_execution_trace(&mut self) -> Option<ExecutionTrace> {
+        self.inner.take_execution_trace()
+    }
+
     /// Turns the virtual machine back into a prototype.
     pub fn into_prototype(self) -> HostVmPrototype {
         self.inner.into_prototype()
@@ -3001,10 +3473,244 @@ impl fmt::Debug for SignatureVerification {
     }
 }
 
-/// Must provide the runtime version obtained by calling the `Core_version` entry point of a Wasm
-/// blob.
-pub struct CallRuntimeVersion {
-    inner: Box<Inner>,
+/// Cryptographic algorithm of a key of the keystore.
+#[derive(Debug, Copy, Clone, PartialEq, Eq, Hash)]
+pub enum KeyAlgorithm {
+    /// Ed25519 key. Public keys are 32 bytes and signatures 64 bytes.
+    Ed25519,
+    /// Sr25519 key. Public keys are 32 bytes and signatures 64 bytes.
+    Sr25519,
+    /// ECDSA key over the secp256k1 curve. Public keys are 33 bytes (compressed) and signatures
+    /// 65 bytes (including the recovery identifier).
+    Ecdsa,
+}
+
+impl KeyAlgorithm {
+    /// Returns the size in bytes of a public key of this algorithm.
+    pub fn public_key_size(&self) -> usize {
+        match self {
+            KeyAlgorithm::Ed25519 | KeyAlgorithm::Sr25519 => 32,
+            KeyAlgorithm::Ecdsa => 33,
+        }
+    }
+
+    /// Returns the size in bytes of a signature of this algorithm.
+    pub fn signature_size(&self) -> usize {
+        match self {
+            KeyAlgorithm::Ed25519 | KeyAlgorithm::Sr25519 => 64,
+            KeyAlgorithm::Ecdsa => 65,
+        }
+    }
+}
+
+/// Must provide the list of public keys of the keystore that belong to a certain key type and
+/// algorithm.
+pub struct KeystorePublicKeys {
+    inner: Box<Inner>,
+    /// Algorithm of the keys.
+    algorithm: KeyAlgorithm,
+    /// Identifier of the type of keys, such as `b"babe"` or `b"gran"`.
+    key_type_id: [u8; 4],
+}
+
+impl KeystorePublicKeys {
+    /// Returns the algorithm of the keys to return.
+    pub fn algorithm(&self) -> KeyAlgorithm {
+        self.algorithm
+    }
+
+    /// Returns the identifier of the type of keys to return, such as `b"babe"` or `b"gran"`.
+    pub fn key_type_id(&self) -> &[u8; 4] {
+        &self.key_type_id
+    }
+
+    /// Resumes execution by providing the list of public keys.
+    ///
+    /// # Panic
+    ///
+    /// Panics if the size of one of the public keys isn't equal to
+    /// [`KeyAlgorithm::public_key_size`].
+    ///
+    pub fn resume(self, public_keys: impl ExactSizeIterator<Item = impl AsRef<[u8]>>) -> HostVm {
+        let host_fn = match self.algorithm {
+            KeyAlgorithm::Ed25519 => HostFunction::ext_crypto_ed25519_public_keys_version_1,
+            KeyAlgorithm::Sr25519 => HostFunction::ext_crypto_sr25519_public_keys_version_1,
+            KeyAlgorithm::Ecdsa => HostFunction::ext_crypto_ecdsa_public_keys_version_1,
+        };
+
+        let mut encoded = util::encode_scale_compact_usize(public_keys.len())
+            .as_ref()
+            .to_vec();
+        for public_key in public_keys {
+            assert_eq!(public_key.as_ref().len(), self.algorithm.public_key_size());
+            encoded.extend_from_slice(public_key.as_ref());
+        }
+
+        self.inner
+            .alloc_write_and_return_pointer_size(host_fn.name(), iter::once(&encoded))
+    }
+}
+
+impl fmt::Debug for KeystorePublicKeys {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        f.debug_struct("KeystorePublicKeys")
+            .field("algorithm", &self.algorithm)
+            .field("key_type_id", &self.key_type_id)
+            .finish()
+    }
+}
+
+/// Must generate a new key in the keystore.
+pub struct KeystoreGenerate {
+    inner: Box<Inner>,
+    /// Algorithm of the key to generate.
+    algorithm: KeyAlgorithm,
+    /// Identifier of the type of key, such as `b"babe"` or `b"gran"`.
+    key_type_id: [u8; 4],
+    /// Pointer and size of the seed to derive the key from, if any. Guaranteed to be in range.
+    seed: Option<(u32, u32)>,
+}
+
+impl KeystoreGenerate {
+    /// Returns the algorithm of the key to generate.
+    pub fn algorithm(&self) -> KeyAlgorithm {
+        self.algorithm
+    }
+
+    /// Returns the identifier of the type of key to generate, such as `b"babe"` or `b"gran"`.
+    pub fn key_type_id(&self) -> &[u8; 4] {
+        &self.key_type_id
+    }
+
+    /// Returns the seed that the key must be derived from, if any.
+    ///
+    /// If `Some`, this is normally a UTF-8 seed phrase, such as `//Alice`. If `None`, the key
+    /// must be randomly generated.
+    pub fn seed(&'_ self) -> Option<impl AsRef<[u8]> + '_> {
+        self.seed
+            .map(|(ptr, size)| self.inner.vm.read_memory(ptr, size).unwrap())
+    }
+
+    /// Resumes execution by providing the public key of the newly-generated key.
+    ///
+    /// # Panic
+    ///
+    /// Panics if the size of the public key isn't equal to [`KeyAlgorithm::public_key_size`].
+    ///
+    pub fn resume(self, public_key: &[u8]) -> HostVm {
+        assert_eq!(public_key.len(), self.algorithm.public_key_size());
+
+        let host_fn = match self.algorithm {
+            KeyAlgorithm::Ed25519 => HostFunction::ext_crypto_ed25519_generate_version_1,
+            KeyAlgorithm::Sr25519 => HostFunction::ext_crypto_sr25519_generate_version_1,
+            KeyAlgorithm::Ecdsa => HostFunction::ext_crypto_ecdsa_generate_version_1,
+        };
+
+        self.inner
+            .alloc_write_and_return_pointer(host_fn.name(), iter::once(public_key))
+    }
+}
+
+impl fmt::Debug for KeystoreGenerate {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        f.debug_struct("KeystoreGenerate")
+            .field("algorithm", &self.algorithm)
+            .field("key_type_id", &self.key_type_id)
+            .field("has_seed", &self.seed.is_some())
+            .finish()
+    }
+}
+
+/// Must sign a message using a key of the keystore.
+pub struct KeystoreSign {
+    inner: Box<Inner>,
+    /// Algorithm of the key to sign with.
+    algorithm: KeyAlgorithm,
+    /// Identifier of the type of key, such as `b"babe"` or `b"gran"`.
+    key_type_id: [u8; 4],
+    /// Pointer to the public key. The size of the public key depends on the algorithm.
+    /// Guaranteed to be in range.
+    public_key_ptr: u32,
+    /// Pointer to the message. Guaranteed to be in range.
+    message_ptr: u32,
+    /// Size of the message. Guaranteed to be in range.
+    message_size: u32,
+}
+
+impl KeystoreSign {
+    /// Returns the algorithm of the key to sign with.
+    pub fn algorithm(&self) -> KeyAlgorithm {
+        self.algorithm
+    }
+
+    /// Returns the identifier of the type of key to sign with, such as `b"babe"` or `b"gran"`.
+    pub fn key_type_id(&self) -> &[u8; 4] {
+        &self.key_type_id
+    }
+
+    /// Returns the public key of the key to sign with.
+    pub fn public_key(&'_ self) -> impl AsRef<[u8]> + '_ {
+        self.inner
+            .vm
+            .read_memory(
+                self.public_key_ptr,
+                u32::try_from(self.algorithm.public_key_size()).unwrap(),
+            )
+            .unwrap()
+    }
+
+    /// Returns the message to sign.
+    pub fn message(&'_ self) -> impl AsRef<[u8]> + '_ {
+        self.inner
+            .vm
+            .read_memory(self.message_ptr, self.message_size)
+            .unwrap()
+    }
+
+    /// Resumes execution by providing the signature, or `None` if the keystore doesn't contain
+    /// the requested key.
+    ///
+    /// # Panic
+    ///
+    /// Panics if the size of the signature isn't equal to [`KeyAlgorithm::signature_size`].
+    ///
+    pub fn resume(self, signature: Option<&[u8]>) -> HostVm {
+        let host_fn = match self.algorithm {
+            KeyAlgorithm::Ed25519 => HostFunction::ext_crypto_ed25519_sign_version_1,
+            KeyAlgorithm::Sr25519 => HostFunction::ext_crypto_sr25519_sign_version_1,
+            KeyAlgorithm::Ecdsa => HostFunction::ext_crypto_ecdsa_sign_version_1,
+        };
+
+        match signature {
+            Some(signature) => {
+                assert_eq!(signature.len(), self.algorithm.signature_size());
+                self.inner.alloc_write_and_return_pointer_size(
+                    host_fn.name(),
+                    [&[1][..], signature].into_iter(),
+                )
+            }
+            None => self
+                .inner
+                .alloc_write_and_return_pointer_size(host_fn.name(), iter::once(&[0])),
+        }
+    }
+}
+
+impl fmt::Debug for KeystoreSign {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        f.debug_struct("KeystoreSign")
+            .field("algorithm", &self.algorithm)
+            .field("key_type_id", &self.key_type_id)
+            .field("public_key", &self.public_key().as_ref())
+            .field("message", &self.message().as_ref())
+            .finish()
+    }
+}
+
+/// Must provide the runtime version obtained by calling the `Core_version` entry point of a Wasm
+/// blob.
+pub struct CallRuntimeVersion {
+    inner: Box<Inner>,
 
     /// Pointer to the wasm code whose runtime version must be provided. Guaranteed to be in range.
     wasm_blob_ptr: u32,
@@ -3316,6 +4022,434 @@ impl fmt::Debug for OffchainSubmitTransaction {
     }
 }
 
+/// Error that can happen while performing an HTTP request on behalf of the runtime.
+#[derive(Debug, Copy, Clone, PartialEq, Eq, Hash)]
+pub enum HttpError {
+    /// The deadline has been reached before the operation could finish.
+    DeadlineReached,
+    /// There was an I/O error while processing the request.
+    IoError,
+    /// The request identifier is invalid, or the operation isn't valid in the current state of
+    /// the request.
+    Invalid,
+}
+
+impl HttpError {
+    /// Returns the SCALE encoding of this error, as expected by the runtime.
+    fn scale_encoding(&self) -> u8 {
+        match self {
+            HttpError::DeadlineReached => 1,
+            HttpError::IoError => 2,
+            HttpError::Invalid => 3,
+        }
+    }
+}
+
+/// Status of an HTTP request, as reported to the runtime in response to a
+/// [`OffchainHttpResponseWait`].
+#[derive(Debug, Copy, Clone, PartialEq, Eq, Hash)]
+pub enum HttpRequestStatus {
+    /// The deadline has been reached before the response has been received.
+    DeadlineReached,
+    /// There was an I/O error while processing the request.
+    IoError,
+    /// The request identifier is unknown.
+    Invalid,
+    /// The response has been received. Contains the HTTP status code of the response.
+    Finished(u16),
+}
+
+/// Must start an HTTP request.
+pub struct OffchainHttpRequestStart {
+    inner: Box<Inner>,
+
+    /// Pointer to the HTTP method. Guaranteed to be in range and to be valid UTF-8.
+    method_ptr: u32,
+    /// Size of the HTTP method. Guaranteed to be in range and to be valid UTF-8.
+    method_size: u32,
+    /// Pointer to the URI. Guaranteed to be in range and to be valid UTF-8.
+    uri_ptr: u32,
+    /// Size of the URI. Guaranteed to be in range and to be valid UTF-8.
+    uri_size: u32,
+}
+
+impl OffchainHttpRequestStart {
+    /// Returns the HTTP method of the request, such as `GET` or `POST`.
+    pub fn method(&'_ self) -> impl AsRef<str> + '_ {
+        let data = self
+            .inner
+            .vm
+            .read_memory(self.method_ptr, self.method_size)
+            .unwrap();
+        Utf8Str(data)
+    }
+
+    /// Returns the URI of the request.
+    pub fn uri(&'_ self) -> impl AsRef<str> + '_ {
+        let data = self
+            .inner
+            .vm
+            .read_memory(self.uri_ptr, self.uri_size)
+            .unwrap();
+        Utf8Str(data)
+    }
+
+    /// Resumes execution after having started the request. Must provide the identifier of the
+    /// newly-started request, or `Err` if the request couldn't be started.
+    ///
+    /// The request isn't supposed to be sent to the server before its headers have been added
+    /// and its body has been written.
+    pub fn resume(self, request_id: Result<u16, ()>) -> HostVm {
+        let host_fn = HostFunction::ext_offchain_http_request_start_version_1;
+        match request_id {
+            Ok(request_id) => {
+                let request_id = request_id.to_le_bytes();
+                self.inner.alloc_write_and_return_pointer_size(
+                    host_fn.name(),
+                    [&[0][..], &request_id[..]].into_iter(),
+                )
+            }
+            Err(()) => self
+                .inner
+                .alloc_write_and_return_pointer_size(host_fn.name(), iter::once(&[1])),
+        }
+    }
+}
+
+impl fmt::Debug for OffchainHttpRequestStart {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        f.debug_struct("OffchainHttpRequestStart")
+            .field("method", &self.method().as_ref())
+            .field("uri", &self.uri().as_ref())
+            .finish()
+    }
+}
+
+/// Must add a header to an HTTP request that hasn't been sent yet.
+pub struct OffchainHttpRequestAddHeader {
+    inner: Box<Inner>,
+
+    /// Identifier of the request, as provided to [`OffchainHttpRequestStart::resume`].
+    request_id: u16,
+
+    /// Pointer to the name of the header. Guaranteed to be in range and to be valid UTF-8.
+    name_ptr: u32,
+    /// Size of the name of the header. Guaranteed to be in range and to be valid UTF-8.
+    name_size: u32,
+    /// Pointer to the value of the header. Guaranteed to be in range and to be valid UTF-8.
+    value_ptr: u32,
+    /// Size of the value of the header. Guaranteed to be in range and to be valid UTF-8.
+    value_size: u32,
+}
+
+impl OffchainHttpRequestAddHeader {
+    /// Returns the identifier of the request, as provided to [`OffchainHttpRequestStart::resume`].
+    pub fn request_id(&self) -> u16 {
+        self.request_id
+    }
+
+    /// Returns the name of the header to add.
+    pub fn name(&'_ self) -> impl AsRef<str> + '_ {
+        let data = self
+            .inner
+            .vm
+            .read_memory(self.name_ptr, self.name_size)
+            .unwrap();
+        Utf8Str(data)
+    }
+
+    /// Returns the value of the header to add.
+    pub fn value(&'_ self) -> impl AsRef<str> + '_ {
+        let data = self
+            .inner
+            .vm
+            .read_memory(self.value_ptr, self.value_size)
+            .unwrap();
+        Utf8Str(data)
+    }
+
+    /// Resumes execution after having added the header. Must return `Err` if the request
+    /// identifier is invalid or if the request has already been sent.
+    pub fn resume(self, result: Result<(), ()>) -> HostVm {
+        self.inner.alloc_write_and_return_pointer_size(
+            HostFunction::ext_offchain_http_request_add_header_version_1.name(),
+            iter::once(if result.is_ok() { &[0] } else { &[1] }),
+        )
+    }
+}
+
+impl fmt::Debug for OffchainHttpRequestAddHeader {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        f.debug_struct("OffchainHttpRequestAddHeader")
+            .field("request_id", &self.request_id)
+            .field("name", &self.name().as_ref())
+            .field("value", &self.value().as_ref())
+            .finish()
+    }
+}
+
+/// Must write a chunk of the body of an HTTP request.
+pub struct OffchainHttpRequestWriteBody {
+    inner: Box<Inner>,
+
+    /// Identifier of the request, as provided to [`OffchainHttpRequestStart::resume`].
+    request_id: u16,
+
+    /// Pointer to the chunk to write. Guaranteed to be in range.
+    chunk_ptr: u32,
+    /// Size of the chunk to write. Guaranteed to be in range.
+    chunk_size: u32,
+
+    /// UNIX timestamp, in milliseconds, after which the operation must be aborted.
+    deadline: Option<u64>,
+}
+
+impl OffchainHttpRequestWriteBody {
+    /// Returns the identifier of the request, as provided to [`OffchainHttpRequestStart::resume`].
+    pub fn request_id(&self) -> u16 {
+        self.request_id
+    }
+
+    /// Returns the chunk of body to write.
+    ///
+    /// An empty chunk indicates that the body is complete. The request can then be sent.
+    pub fn chunk(&'_ self) -> impl AsRef<[u8]> + '_ {
+        self.inner
+            .vm
+            .read_memory(self.chunk_ptr, self.chunk_size)
+            .unwrap()
+    }
+
+    /// Returns the UNIX timestamp, in milliseconds, after which the operation must be aborted
+    /// with [`HttpError::DeadlineReached`]. `None` if there is no deadline.
+    pub fn deadline(&self) -> Option<u64> {
+        self.deadline
+    }
+
+    /// Resumes execution after having written the chunk.
+    pub fn resume(self, result: Result<(), HttpError>) -> HostVm {
+        let host_fn = HostFunction::ext_offchain_http_request_write_body_version_1;
+        match result {
+            Ok(()) => self
+                .inner
+                .alloc_write_and_return_pointer_size(host_fn.name(), iter::once(&[0])),
+            Err(error) => self.inner.alloc_write_and_return_pointer_size(
+                host_fn.name(),
+                iter::once(&[1, error.scale_encoding()]),
+            ),
+        }
+    }
+}
+
+impl fmt::Debug for OffchainHttpRequestWriteBody {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        f.debug_struct("OffchainHttpRequestWriteBody")
+            .field("request_id", &self.request_id)
+            .field("deadline", &self.deadline)
+            .finish()
+    }
+}
+
+/// Must wait for the responses to some HTTP requests.
+pub struct OffchainHttpResponseWait {
+    inner: Box<Inner>,
+
+    /// Identifiers of the requests to wait for.
+    request_ids: Vec<u16>,
+
+    /// UNIX timestamp, in milliseconds, after which the operation must be aborted.
+    deadline: Option<u64>,
+}
+
+impl OffchainHttpResponseWait {
+    /// Returns the identifiers of the requests whose response must be waited for.
+    pub fn request_ids(&'_ self) -> impl ExactSizeIterator<Item = u16> + '_ {
+        self.request_ids.iter().copied()
+    }
+
+    /// Returns the UNIX timestamp, in milliseconds, after which waiting must stop. The requests
+    /// whose response hasn't been received by then must be reported as
+    /// [`HttpRequestStatus::DeadlineReached`]. `None` if there is no deadline.
+    pub fn deadline(&self) -> Option<u64> {
+        self.deadline
+    }
+
+    /// Resumes execution after having waited. Must provide the status of each request, in the
+    /// same order as [`OffchainHttpResponseWait::request_ids`].
+    ///
+    /// # Panic
+    ///
+    /// Panics if the number of statuses doesn't match the number of request identifiers.
+    ///
+    pub fn resume(self, statuses: impl ExactSizeIterator<Item = HttpRequestStatus>) -> HostVm {
+        assert_eq!(statuses.len(), self.request_ids.len());
+
+        let mut encoded = util::encode_scale_compact_usize(statuses.len())
+            .as_ref()
+            .to_vec();
+        for status in statuses {
+            match status {
+                HttpRequestStatus::DeadlineReached => encoded.push(0),
+                HttpRequestStatus::IoError => encoded.push(1),
+                HttpRequestStatus::Invalid => encoded.push(2),
+                HttpRequestStatus::Finished(code) => {
+                    encoded.push(3);
+                    encoded.extend_from_slice(&code.to_le_bytes());
+                }
+            }
+        }
+
+        self.inner.alloc_write_and_return_pointer_size(
+            HostFunction::ext_offchain_http_response_wait_version_1.name(),
+            iter::once(encoded),
+        )
+    }
+}
+
+impl fmt::Debug for OffchainHttpResponseWait {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        f.debug_struct("OffchainHttpResponseWait")
+            .field("request_ids", &self.request_ids)
+            .field("deadline", &self.deadline)
+            .finish()
+    }
+}
+
+/// Must provide the headers of the response to an HTTP request.
+pub struct OffchainHttpResponseHeaders {
+    inner: Box<Inner>,
+
+    /// Identifier of the request, as provided to [`OffchainHttpRequestStart::resume`].
+    request_id: u16,
+}
+
+impl OffchainHttpResponseHeaders {
+    /// Returns the identifier of the request, as provided to [`OffchainHttpRequestStart::resume`].
+    pub fn request_id(&self) -> u16 {
+        self.request_id
+    }
+
+    /// Resumes execution by providing the list of names and values of the headers of the
+    /// response.
+    ///
+    /// An empty list must be provided if the request identifier is invalid or if the response
+    /// hasn't been received yet.
+    pub fn resume(
+        self,
+        headers: impl Iterator<Item = (impl AsRef<[u8]>, impl AsRef<[u8]>)>,
+    ) -> HostVm {
+        let mut num_headers = 0;
+        let mut encoded_headers = Vec::new();
+        for (name, value) in headers {
+            num_headers += 1;
+            for item in [name.as_ref(), value.as_ref()] {
+                encoded_headers
+                    .extend_from_slice(util::encode_scale_compact_usize(item.len()).as_ref());
+                encoded_headers.extend_from_slice(item);
+            }
+        }
+
+        self.inner.alloc_write_and_return_pointer_size(
+            HostFunction::ext_offchain_http_response_headers_version_1.name(),
+            [
+                either::Left(util::encode_scale_compact_usize(num_headers)),
+                either::Right(encoded_headers),
+            ]
+            .into_iter(),
+        )
+    }
+}
+
+impl fmt::Debug for OffchainHttpResponseHeaders {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        f.debug_struct("OffchainHttpResponseHeaders")
+            .field("request_id", &self.request_id)
+            .finish()
+    }
+}
+
+/// Must read a chunk of the body of the response to an HTTP request.
+pub struct OffchainHttpResponseReadBody {
+    inner: Box<Inner>,
+
+    /// Identifier of the request, as provided to [`OffchainHttpRequestStart::resume`].
+    request_id: u16,
+
+    /// Pointer to the buffer where to write the body. Guaranteed to be in range.
+    buffer_ptr: u32,
+    /// Size of the buffer where to write the body. Guaranteed to be in range.
+    buffer_size: u32,
+
+    /// UNIX timestamp, in milliseconds, after which the operation must be aborted.
+    deadline: Option<u64>,
+}
+
+impl OffchainHttpResponseReadBody {
+    /// Returns the identifier of the request, as provided to [`OffchainHttpRequestStart::resume`].
+    pub fn request_id(&self) -> u16 {
+        self.request_id
+    }
+
+    /// Returns the maximum number of bytes that can be passed to
+    /// [`OffchainHttpResponseReadBody::resume`].
+    pub fn max_size(&self) -> usize {
+        usize::try_from(self.buffer_size).unwrap()
+    }
+
+    /// Returns the UNIX timestamp, in milliseconds, after which the operation must be aborted
+    /// with [`HttpError::DeadlineReached`]. `None` if there is no deadline.
+    pub fn deadline(&self) -> Option<u64> {
+        self.deadline
+    }
+
+    /// Resumes execution by providing the next chunk of the body of the response.
+    ///
+    /// An empty chunk indicates that the body has been entirely read.
+    ///
+    /// # Panic
+    ///
+    /// Panics if the chunk is larger than [`OffchainHttpResponseReadBody::max_size`].
+    ///
+    pub fn resume(mut self, result: Result<&[u8], HttpError>) -> HostVm {
+        let host_fn = HostFunction::ext_offchain_http_response_read_body_version_1;
+        match result {
+            Ok(chunk) => {
+                assert!(chunk.len() <= self.max_size());
+                self.inner.vm.write_memory(self.buffer_ptr, chunk).unwrap();
+                let chunk_len = u32::try_from(chunk.len()).unwrap().to_le_bytes();
+                self.inner.alloc_write_and_return_pointer_size(
+                    host_fn.name(),
+                    [&[0][..], &chunk_len[..]].into_iter(),
+                )
+            }
+            Err(error) => self.inner.alloc_write_and_return_pointer_size(
+                host_fn.name(),
+                iter::once(&[1, error.scale_encoding()]),
+            ),
+        }
+    }
+}
+
+impl fmt::Debug for OffchainHttpResponseReadBody {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        f.debug_struct("OffchainHttpResponseReadBody")
+            .field("request_id", &self.request_id)
+            .field("max_size", &self.buffer_size)
+            .field("deadline", &self.deadline)
+            .finish()
+    }
+}
+
+/// Buffer whose content has been verified to be valid UTF-8.
+struct Utf8Str<T>(T);
+
+impl<T: AsRef<[u8]>> AsRef<str> for Utf8Str<T> {
+    fn as_ref(&self) -> &str {
+        // The validity of the UTF-8 content is checked when the host function is called.
+        str::from_utf8(self.0.as_ref()).unwrap()
+    }
+}
+
 /// Report about a log entry being emitted.
 ///
 /// Use the implementation of [`fmt::Display`] to obtain the log entry. For example, you can
@@ -3500,6 +4634,10 @@ struct Inner {
 
     /// Fields that are kept as is even during the execution.
     common: Box<VmCommon>,
+
+    /// If `Some`, the host function calls are being recorded. See
+    /// [`ReadyToRun::enable_tracing`].
+    tracer: Option<Box<Tracer>>,
 }
 
 impl Inner {
@@ -3654,15 +4792,87 @@ impl Inner {
         Ok(dest_ptr)
     }
 
+    /// Extracts the trace of the execution from the tracer, if any.
+    fn take_execution_trace(&mut self) -> Option<ExecutionTrace> {
+        let mut tracer = self.tracer.take()?;
+        tracer.finish_host_function_call();
+        Some(ExecutionTrace {
+            host_function_calls: tracer.host_function_calls,
+            allocator_stats: self.allocator.stats(),
+        })
+    }
+
     /// Turns the virtual machine back into a prototype.
-    fn into_prototype(self) -> HostVmPrototype {
+    fn into_prototype(mut self) -> HostVmPrototype {
+        let execution_trace = self.take_execution_trace().map(Box::new);
         HostVmPrototype {
             vm_proto: self.vm.into_prototype(),
             common: self.common,
+            execution_trace,
+            allocation_stats: Some(Box::new(self.allocator.stats())),
         }
     }
 }
 
+/// See [`Inner::tracer`].
+struct Tracer {
+    /// Clock passed to [`ReadyToRun::enable_tracing`].
+    clock: Box<dyn Fn() -> Duration + Send + Sync>,
+
+    /// Host function calls recorded so far.
+    host_function_calls: Vec<HostFunctionCall>,
+
+    /// If `Some`, the last entry of [`Tracer::host_function_calls`] is still being handled, and
+    /// has started at the given moment.
+    current_call_start: Option<Duration>,
+}
+
+impl Tracer {
+    /// Records the beginning of a host function call.
+    fn start_host_function_call(&mut self, name: &'static str, parameters: &[vm::WasmValue]) {
+        debug_assert!(self.current_call_start.is_none());
+        self.current_call_start = Some((self.clock)());
+        self.host_function_calls.push(HostFunctionCall {
+            name,
+            parameters: parameters.to_vec(),
+            duration: Duration::new(0, 0),
+        });
+    }
+
+    /// Records the end of the host function call in progress, if any.
+    fn finish_host_function_call(&mut self) {
+        if let Some(start) = self.current_call_start.take() {
+            let call = self.host_function_calls.last_mut().unwrap();
+            call.duration = (self.clock)().saturating_sub(start);
+        }
+    }
+}
+
+/// Trace of an execution. See [`ReadyToRun::enable_tracing`].
+#[derive(Debug, Clone)]
+pub struct ExecutionTrace {
+    /// List of all the host functions that the runtime has called since tracing was enabled, in
+    /// the order in which they have been called.
+    pub host_function_calls: Vec<HostFunctionCall>,
+
+    /// Statistics about the memory allocator at the end of the execution.
+    pub allocator_stats: AllocationStats,
+}
+
+/// Host function call found in an [`ExecutionTrace`].
+#[derive(Debug, Clone)]
+pub struct HostFunctionCall {
+    /// Name of the host function, for example `ext_storage_get_version_1`.
+    pub name: &'static str,
+
+    /// Parameters that the runtime has passed to the host function.
+    pub parameters: Vec<vm::WasmValue>,
+
+    /// Time between the moment when the runtime has called the host function and the moment
+    /// when the execution has resumed.
+    pub duration: Duration,
+}
+
 /// Error that can happen when initializing a VM.
 #[derive(Debug, derive_more::From, derive_more::Display, Clone)]
 pub enum NewErr {
@@ -3714,6 +4924,10 @@ pub enum Error {
     /// Error in the Wasm code execution.
     #[display(fmt = "{_0}")]
     Trap(vm::Trap),
+    /// The runtime has consumed all the fuel it was allowed to consume.
+    /// See [`Config::resource_limits`].
+    #[display(fmt = "The runtime has consumed all of its fuel")]
+    OutOfFuel,
     /// A non-`i64` value has been returned by the Wasm entry point.
     #[display(fmt = "A non-I64 value has been returned: {actual:?}")]
     BadReturnValue {
@@ -4082,7 +5296,7 @@ impl HostFunction {
                 crate::signature!((vm::ValueType::I32, vm::ValueType::I32, vm::ValueType::I64) => vm::ValueType::I64)
             }
             HostFunction::ext_crypto_ecdsa_public_keys_version_1 => {
-                crate::signature!((vm::ValueType::I64) => vm::ValueType::I64)
+                crate::signature!((vm::ValueType::I32) => vm::ValueType::I64)
             }
             HostFunction::ext_crypto_ecdsa_verify_version_1 => {
                 crate::signature!((vm::ValueType::I32, vm::ValueType::I64, vm::ValueType::I32) => vm::ValueType::I32)
@@ -4188,7 +5402,7 @@ impl HostFunction {
                 crate::signature!((vm::ValueType::I64, vm::ValueType::I64) => vm::ValueType::I64)
             }
             HostFunction::ext_offchain_http_response_headers_version_1 => {
-                crate::signature!((vm::ValueType::I64) => vm::ValueType::I64)
+                crate::signature!((vm::ValueType::I32) => vm::ValueType::I64)
             }
             HostFunction::ext_offchain_http_response_read_body_version_1 => {
                 crate::signature!((vm::ValueType::I32, vm::ValueType::I64, vm::ValueType::I64) => vm::ValueType::I64)
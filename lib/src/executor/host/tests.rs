@@ -22,6 +22,7 @@ use super::{vm::ExecHint, Config, HeapPages, HostVm, HostVmPrototype};
 mod hash_algorithms;
 mod initialization;
 mod run;
+mod trie_verify_proof;
 
 /*
 
@@ -94,6 +95,9 @@ fn basic_core_version() {
             heap_pages: HeapPages::new(2048),
             exec_hint,
             allow_unresolved_imports: true,
+            compiled_module_cache: None,
+            resource_limits: Default::default(),
+            allocation_strategy: Default::default(),
         })
         .unwrap();
 
@@ -132,6 +136,9 @@ fn min_requirements() {
     for exec_hint in ExecHint::available_engines() {
         HostVmPrototype::new(Config {
             allow_unresolved_imports: false,
+            compiled_module_cache: None,
+            resource_limits: Default::default(),
+            allocation_strategy: Default::default(),
             exec_hint,
             heap_pages: HeapPages::new(1024),
             module: &module_bytes,
@@ -215,6 +215,9 @@ macro_rules! gen_test {
             for exec_hint in ExecHint::available_engines() {
                 let proto = HostVmPrototype::new(Config {
                     allow_unresolved_imports: false,
+                    compiled_module_cache: None,
+                    resource_limits: Default::default(),
+                    allocation_strategy: Default::default(),
                     exec_hint,
                     heap_pages: HeapPages::new(1024),
                     module: &module_bytes,
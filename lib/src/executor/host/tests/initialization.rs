@@ -27,6 +27,9 @@ fn invalid_wasm() {
     for exec_hint in ExecHint::available_engines() {
         match HostVmPrototype::new(Config {
             allow_unresolved_imports: false,
+            compiled_module_cache: None,
+            resource_limits: Default::default(),
+            allocation_strategy: Default::default(),
             exec_hint,
             heap_pages: HeapPages::new(1024),
             module: &module_bytes,
@@ -45,6 +48,9 @@ fn invalid_zstd() {
     for exec_hint in ExecHint::available_engines() {
         match HostVmPrototype::new(Config {
             allow_unresolved_imports: false,
+            compiled_module_cache: None,
+            resource_limits: Default::default(),
+            allocation_strategy: Default::default(),
             exec_hint,
             heap_pages: HeapPages::new(1024),
             module: &module_bytes,
@@ -71,6 +77,9 @@ fn valid_zstd() {
     for exec_hint in ExecHint::available_engines() {
         HostVmPrototype::new(Config {
             allow_unresolved_imports: false,
+            compiled_module_cache: None,
+            resource_limits: Default::default(),
+            allocation_strategy: Default::default(),
             exec_hint,
             heap_pages: HeapPages::new(1024),
             module: &module_bytes,
@@ -95,6 +104,9 @@ fn no_heap_base() {
     for exec_hint in ExecHint::available_engines() {
         match HostVmPrototype::new(Config {
             allow_unresolved_imports: false,
+            compiled_module_cache: None,
+            resource_limits: Default::default(),
+            allocation_strategy: Default::default(),
             exec_hint,
             heap_pages: HeapPages::new(1024),
             module: &module_bytes,
@@ -122,6 +134,9 @@ fn memory_max_size_too_low() {
     for exec_hint in ExecHint::available_engines() {
         match HostVmPrototype::new(Config {
             allow_unresolved_imports: false,
+            compiled_module_cache: None,
+            resource_limits: Default::default(),
+            allocation_strategy: Default::default(),
             exec_hint,
             heap_pages: HeapPages::new(1024),
             module: &module_bytes,
@@ -150,6 +165,9 @@ fn unresolved_host_functions_setting() {
     for exec_hint in ExecHint::available_engines() {
         match HostVmPrototype::new(Config {
             allow_unresolved_imports: true,
+            compiled_module_cache: None,
+            resource_limits: Default::default(),
+            allocation_strategy: Default::default(),
             exec_hint,
             heap_pages: HeapPages::new(1024),
             module: &module_bytes,
@@ -160,6 +178,9 @@ fn unresolved_host_functions_setting() {
 
         match HostVmPrototype::new(Config {
             allow_unresolved_imports: false,
+            compiled_module_cache: None,
+            resource_limits: Default::default(),
+            allocation_strategy: Default::default(),
             exec_hint,
             heap_pages: HeapPages::new(1024),
             module: &module_bytes,
@@ -190,6 +211,9 @@ fn host_function_bad_signature() {
     for exec_hint in ExecHint::available_engines() {
         match HostVmPrototype::new(Config {
             allow_unresolved_imports: true,
+            compiled_module_cache: None,
+            resource_limits: Default::default(),
+            allocation_strategy: Default::default(),
             exec_hint,
             heap_pages: HeapPages::new(1024),
             module: &module_bytes,
@@ -200,6 +224,9 @@ fn host_function_bad_signature() {
 
         match HostVmPrototype::new(Config {
             allow_unresolved_imports: false,
+            compiled_module_cache: None,
+            resource_limits: Default::default(),
+            allocation_strategy: Default::default(),
             exec_hint,
             heap_pages: HeapPages::new(1024),
             module: &module_bytes,
@@ -219,6 +246,9 @@ fn rococo_genesis_works() {
     for exec_hint in ExecHint::available_engines() {
         assert!(HostVmPrototype::new(Config {
             allow_unresolved_imports: true,
+            compiled_module_cache: None,
+            resource_limits: Default::default(),
+            allocation_strategy: Default::default(),
             exec_hint,
             heap_pages: HeapPages::new(1024),
             module: &module_bytes,
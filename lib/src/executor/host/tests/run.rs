@@ -16,10 +16,17 @@
 // along with this program.  If not, see <http://www.gnu.org/licenses/>.
 
 use super::super::{
-    vm, vm::ExecHint, Config, Error, HeapPages, HostVm, HostVmPrototype, NewErr, StartErr,
+    vm, vm::ExecHint, AllocationStrategy, Config, Error, HeapPages, HostVm, HostVmPrototype,
+    KeyAlgorithm, NewErr, StartErr,
 };
 use super::with_core_version_custom_sections;
 
+use alloc::sync::Arc;
+use core::{
+    sync::atomic::{AtomicU64, Ordering},
+    time::Duration,
+};
+
 #[test]
 fn function_to_run_doesnt_exist() {
     let module_bytes = with_core_version_custom_sections(
@@ -37,6 +44,9 @@ fn function_to_run_doesnt_exist() {
     for exec_hint in ExecHint::available_engines() {
         let host_vm = HostVmPrototype::new(Config {
             allow_unresolved_imports: false,
+            compiled_module_cache: None,
+            resource_limits: Default::default(),
+            allocation_strategy: Default::default(),
             exec_hint,
             heap_pages: HeapPages::new(1024),
             module: &module_bytes,
@@ -68,6 +78,9 @@ fn function_to_run_invalid_params() {
     for exec_hint in ExecHint::available_engines() {
         let host_vm = HostVmPrototype::new(Config {
             allow_unresolved_imports: false,
+            compiled_module_cache: None,
+            resource_limits: Default::default(),
+            allocation_strategy: Default::default(),
             exec_hint,
             heap_pages: HeapPages::new(1024),
             module: &module_bytes,
@@ -197,6 +210,9 @@ fn input_provided_correctly() {
     for exec_hint in ExecHint::available_engines() {
         let proto = HostVmPrototype::new(Config {
             allow_unresolved_imports: false,
+            compiled_module_cache: None,
+            resource_limits: Default::default(),
+            allocation_strategy: Default::default(),
             exec_hint,
             heap_pages: HeapPages::new(1024),
             module: &module_bytes,
@@ -219,6 +235,9 @@ fn input_provided_correctly() {
     for exec_hint in ExecHint::available_engines() {
         let proto = HostVmPrototype::new(Config {
             allow_unresolved_imports: false,
+            compiled_module_cache: None,
+            resource_limits: Default::default(),
+            allocation_strategy: Default::default(),
             exec_hint,
             heap_pages: HeapPages::new(1024),
             module: &module_bytes,
@@ -246,6 +265,9 @@ fn input_provided_correctly() {
     for exec_hint in ExecHint::available_engines() {
         let proto = HostVmPrototype::new(Config {
             allow_unresolved_imports: false,
+            compiled_module_cache: None,
+            resource_limits: Default::default(),
+            allocation_strategy: Default::default(),
             exec_hint,
             heap_pages: HeapPages::new(1024),
             module: &module_bytes,
@@ -352,6 +374,9 @@ fn large_input_provided_correctly() {
     for exec_hint in ExecHint::available_engines() {
         let proto = HostVmPrototype::new(Config {
             allow_unresolved_imports: false,
+            compiled_module_cache: None,
+            resource_limits: Default::default(),
+            allocation_strategy: Default::default(),
             exec_hint,
             heap_pages: HeapPages::new(1024),
             module: &module_bytes,
@@ -415,6 +440,9 @@ fn return_value_works() {
     for exec_hint in ExecHint::available_engines() {
         let proto = HostVmPrototype::new(Config {
             allow_unresolved_imports: false,
+            compiled_module_cache: None,
+            resource_limits: Default::default(),
+            allocation_strategy: Default::default(),
             exec_hint,
             heap_pages: HeapPages::new(1024),
             module: &module_bytes,
@@ -469,6 +497,9 @@ fn bad_return_value() {
     for exec_hint in ExecHint::available_engines() {
         let proto = HostVmPrototype::new(Config {
             allow_unresolved_imports: false,
+            compiled_module_cache: None,
+            resource_limits: Default::default(),
+            allocation_strategy: Default::default(),
             exec_hint,
             heap_pages: HeapPages::new(1024),
             module: &module_bytes,
@@ -528,6 +559,9 @@ fn returned_ptr_out_of_range() {
     for exec_hint in ExecHint::available_engines() {
         let proto = HostVmPrototype::new(Config {
             allow_unresolved_imports: false,
+            compiled_module_cache: None,
+            resource_limits: Default::default(),
+            allocation_strategy: Default::default(),
             exec_hint,
             heap_pages: HeapPages::new(1024),
             module: &module_bytes,
@@ -587,6 +621,9 @@ fn returned_size_out_of_range() {
     for exec_hint in ExecHint::available_engines() {
         let proto = HostVmPrototype::new(Config {
             allow_unresolved_imports: false,
+            compiled_module_cache: None,
+            resource_limits: Default::default(),
+            allocation_strategy: Default::default(),
             exec_hint,
             heap_pages: HeapPages::new(1024),
             module: &module_bytes,
@@ -653,6 +690,9 @@ fn unresolved_host_function_called() {
     for exec_hint in ExecHint::available_engines() {
         match HostVmPrototype::new(Config {
             allow_unresolved_imports: false,
+            compiled_module_cache: None,
+            resource_limits: Default::default(),
+            allocation_strategy: Default::default(),
             exec_hint,
             heap_pages: HeapPages::new(1024),
             module: &module_bytes,
@@ -663,6 +703,9 @@ fn unresolved_host_function_called() {
 
         let proto = HostVmPrototype::new(Config {
             allow_unresolved_imports: true,
+            compiled_module_cache: None,
+            resource_limits: Default::default(),
+            allocation_strategy: Default::default(),
             exec_hint,
             heap_pages: HeapPages::new(1024),
             module: &module_bytes,
@@ -685,4 +728,310 @@ fn unresolved_host_function_called() {
     }
 }
 
+#[test]
+fn offchain_http_request_start() {
+    // Calls `ext_offchain_http_request_start_version_1` with a method of `GET` and a URI of
+    // `http://example.com`, and returns the output of the host function.
+    let module_bytes = with_core_version_custom_sections(
+        wat::parse_str(
+            r#"
+    (module
+        (type (;0;) (func (param i64 i64 i64) (result i64)))
+        (type (;1;) (func (param i32 i32) (result i64)))
+        (import "env" "ext_offchain_http_request_start_version_1" (func (;0;) (type 0)))
+        (func (;1;) (type 1) (param i32 i32) (result i64)
+          i64.const 12885950464
+          i64.const 77310459912
+          i64.const 0
+          call 0)
+        (table (;0;) 1 1 funcref)
+        (memory (;0;) 17)
+        (global (;0;) (mut i32) (i32.const 1048576))
+        (global (;1;) i32 (i32.const 1048602))
+        (global (;2;) i32 (i32.const 1048608))
+        (export "memory" (memory 0))
+        (export "test" (func 1))
+        (export "__data_end" (global 1))
+        (export "__heap_base" (global 2))
+        (data (;0;) (i32.const 1048576) "GET")
+        (data (;1;) (i32.const 1048584) "http://example.com")
+    )
+    "#,
+        )
+        .unwrap(),
+    );
+
+    for exec_hint in ExecHint::available_engines() {
+        let proto = HostVmPrototype::new(Config {
+            allow_unresolved_imports: false,
+            compiled_module_cache: None,
+            resource_limits: Default::default(),
+            allocation_strategy: Default::default(),
+            exec_hint,
+            heap_pages: HeapPages::new(1024),
+            module: &module_bytes,
+        })
+        .unwrap();
+
+        let mut vm = HostVm::from(proto.run("test", &[]).unwrap());
+        loop {
+            match vm {
+                HostVm::ReadyToRun(r) => vm = r.run(),
+                HostVm::OffchainHttpRequestStart(req) => {
+                    assert_eq!(req.method().as_ref(), "GET");
+                    assert_eq!(req.uri().as_ref(), "http://example.com");
+                    vm = req.resume(Ok(5));
+                }
+                HostVm::Finished(out) => {
+                    // SCALE-encoded `Ok(5u16)`.
+                    assert_eq!(out.value().as_ref(), &[0, 5, 0]);
+                    break;
+                }
+                _ => unreachable!(),
+            }
+        }
+    }
+}
+
+#[test]
+fn keystore_generate_with_seed() {
+    // Calls `ext_crypto_sr25519_generate_version_1` with a key type of `babe` and a seed of
+    // `//Alice`, and returns the 32 bytes of the public key returned by the host function.
+    let module_bytes = with_core_version_custom_sections(
+        wat::parse_str(
+            r#"
+    (module
+        (type (;0;) (func (param i32 i64) (result i32)))
+        (type (;1;) (func (param i32 i32) (result i64)))
+        (import "env" "ext_crypto_sr25519_generate_version_1" (func (;0;) (type 0)))
+        (func (;1;) (type 1) (param i32 i32) (result i64)
+          i32.const 1048576
+          i64.const 38655754248
+          call 0
+          i64.extend_i32_u
+          i64.const 137438953472
+          i64.or)
+        (table (;0;) 1 1 funcref)
+        (memory (;0;) 17)
+        (global (;0;) (mut i32) (i32.const 1048576))
+        (global (;1;) i32 (i32.const 1048593))
+        (global (;2;) i32 (i32.const 1048608))
+        (export "memory" (memory 0))
+        (export "test" (func 1))
+        (export "__data_end" (global 1))
+        (export "__heap_base" (global 2))
+        (data (;0;) (i32.const 1048576) "babe")
+        (data (;1;) (i32.const 1048584) "\01\1c//Alice")
+    )
+    "#,
+        )
+        .unwrap(),
+    );
+
+    for exec_hint in ExecHint::available_engines() {
+        let proto = HostVmPrototype::new(Config {
+            allow_unresolved_imports: false,
+            compiled_module_cache: None,
+            resource_limits: Default::default(),
+            allocation_strategy: Default::default(),
+            exec_hint,
+            heap_pages: HeapPages::new(1024),
+            module: &module_bytes,
+        })
+        .unwrap();
+
+        let mut vm = HostVm::from(proto.run("test", &[]).unwrap());
+        loop {
+            match vm {
+                HostVm::ReadyToRun(r) => vm = r.run(),
+                HostVm::KeystoreGenerate(req) => {
+                    assert_eq!(req.algorithm(), KeyAlgorithm::Sr25519);
+                    assert_eq!(req.key_type_id(), b"babe");
+                    assert_eq!(req.seed().unwrap().as_ref(), b"//Alice");
+                    vm = req.resume(&[0xaa; 32]);
+                }
+                HostVm::Finished(out) => {
+                    assert_eq!(out.value().as_ref(), &[0xaa; 32]);
+                    break;
+                }
+                _ => unreachable!(),
+            }
+        }
+    }
+}
+
+#[test]
+fn execution_trace_records_host_function_calls() {
+    // Same module as in `offchain_http_request_start`.
+    let module_bytes = with_core_version_custom_sections(
+        wat::parse_str(
+            r#"
+    (module
+        (type (;0;) (func (param i64 i64 i64) (result i64)))
+        (type (;1;) (func (param i32 i32) (result i64)))
+        (import "env" "ext_offchain_http_request_start_version_1" (func (;0;) (type 0)))
+        (func (;1;) (type 1) (param i32 i32) (result i64)
+          i64.const 12885950464
+          i64.const 77310459912
+          i64.const 0
+          call 0)
+        (table (;0;) 1 1 funcref)
+        (memory (;0;) 17)
+        (global (;0;) (mut i32) (i32.const 1048576))
+        (global (;1;) i32 (i32.const 1048602))
+        (global (;2;) i32 (i32.const 1048608))
+        (export "memory" (memory 0))
+        (export "test" (func 1))
+        (export "__data_end" (global 1))
+        (export "__heap_base" (global 2))
+        (data (;0;) (i32.const 1048576) "GET")
+        (data (;1;) (i32.const 1048584) "http://example.com")
+    )
+    "#,
+        )
+        .unwrap(),
+    );
+
+    for exec_hint in ExecHint::available_engines() {
+        let proto = HostVmPrototype::new(Config {
+            allow_unresolved_imports: false,
+            compiled_module_cache: None,
+            resource_limits: Default::default(),
+            allocation_strategy: Default::default(),
+            exec_hint,
+            heap_pages: HeapPages::new(1024),
+            module: &module_bytes,
+        })
+        .unwrap();
+
+        // Clock that advances by one second every time it is read.
+        let clock = Arc::new(AtomicU64::new(0));
+        let mut ready_to_run = proto.run("test", &[]).unwrap();
+        ready_to_run.enable_tracing(Box::new(move || {
+            Duration::from_secs(clock.fetch_add(1, Ordering::Relaxed))
+        }));
+
+        let mut vm = HostVm::from(ready_to_run);
+        loop {
+            match vm {
+                HostVm::ReadyToRun(r) => vm = r.run(),
+                HostVm::OffchainHttpRequestStart(req) => vm = req.resume(Ok(5)),
+                HostVm::Finished(mut out) => {
+                    let trace = out.take_execution_trace().unwrap();
+                    assert_eq!(trace.host_function_calls.len(), 1);
+                    let call = &trace.host_function_calls[0];
+                    assert_eq!(call.name, "ext_offchain_http_request_start_version_1");
+                    assert_eq!(
+                        call.parameters,
+                        [
+                            vm::WasmValue::I64(12885950464),
+                            vm::WasmValue::I64(77310459912),
+                            vm::WasmValue::I64(0)
+                        ]
+                    );
+                    assert_eq!(call.duration, Duration::from_secs(1));
+
+                    // The input data and the output of the host function have been allocated,
+                    // and nothing has been freed.
+                    assert!(trace.allocator_stats.bytes_allocated > 0);
+                    assert_eq!(
+                        trace.allocator_stats.bytes_allocated,
+                        trace.allocator_stats.bytes_allocated_peak
+                    );
+                    assert_eq!(
+                        u64::from(trace.allocator_stats.bytes_allocated),
+                        trace.allocator_stats.bytes_allocated_sum
+                    );
+
+                    assert!(out.take_execution_trace().is_none());
+                    assert!(out.into_prototype().take_execution_trace().is_none());
+                    break;
+                }
+                _ => unreachable!(),
+            }
+        }
+    }
+}
+
+#[test]
+fn allocation_strategy_reclaims_memory() {
+    // Allocates and frees 64 kiB, then allocates 32 kiB twice. The heap isn't large enough to
+    // fit the three allocations at the same time.
+    let module_bytes = with_core_version_custom_sections(
+        wat::parse_str(
+            r#"
+    (module
+        (import "env" "memory" (memory 0))
+        (import "env" "ext_allocator_malloc_version_1" (func $malloc (param i32) (result i32)))
+        (import "env" "ext_allocator_free_version_1" (func $free (param i32)))
+        (global (export "__heap_base") i32 (i32.const 0))
+        (func (export "test") (param i32 i32) (result i64)
+            (call $free (call $malloc (i32.const 65536)))
+            (drop (call $malloc (i32.const 32768)))
+            (drop (call $malloc (i32.const 32768)))
+            (i64.const 0))
+    )
+    "#,
+        )
+        .unwrap(),
+    );
+
+    for exec_hint in ExecHint::available_engines() {
+        for allocation_strategy in [
+            AllocationStrategy::FreeingBump,
+            AllocationStrategy::Reclaiming,
+        ] {
+            let proto = HostVmPrototype::new(Config {
+                allow_unresolved_imports: false,
+                compiled_module_cache: None,
+                resource_limits: Default::default(),
+                allocation_strategy,
+                exec_hint,
+                heap_pages: HeapPages::new(2),
+                module: &module_bytes,
+            })
+            .unwrap();
+            assert!(proto.allocation_stats().is_none());
+
+            let mut vm = HostVm::from(proto.run("test", &[]).unwrap());
+            let prototype = loop {
+                match vm {
+                    HostVm::ReadyToRun(r) => vm = r.run(),
+                    vm @ HostVm::Finished(_) => {
+                        assert_eq!(allocation_strategy, AllocationStrategy::Reclaiming);
+                        let stats = vm.allocation_stats();
+                        let prototype = vm.into_prototype();
+                        assert_eq!(prototype.allocation_stats(), Some(&stats));
+                        break prototype;
+                    }
+                    HostVm::Error {
+                        error: Error::OutOfMemory { .. },
+                        prototype,
+                    } => {
+                        assert_eq!(allocation_strategy, AllocationStrategy::FreeingBump);
+                        break prototype;
+                    }
+                    _ => unreachable!(),
+                }
+            };
+
+            // The input data and the 64 kiB allocation always succeed. The bump space of the
+            // 64 kiB allocation is reused only when reclaiming.
+            let stats = prototype.allocation_stats().unwrap();
+            assert_eq!(stats.allocations_per_order[0], 1);
+            assert_eq!(stats.allocations_per_order[13], 1);
+            match allocation_strategy {
+                AllocationStrategy::FreeingBump => {
+                    assert_eq!(stats.allocations_per_order[12], 1);
+                    assert_eq!(stats.address_space_used_peak, 16 + 65544 + 32776);
+                }
+                AllocationStrategy::Reclaiming => {
+                    assert_eq!(stats.allocations_per_order[12], 2);
+                    assert_eq!(stats.address_space_used_peak, 16 + 32776 * 2);
+                }
+            }
+        }
+    }
+}
+
 // TODO: consider more tests for the other errors here, or add them on a host-function case-by-case basis
@@ -0,0 +1,467 @@
+// Smoldot
+// Copyright (C) 2023  Pierre Krieger
+// SPDX-License-Identifier: GPL-3.0-or-later WITH Classpath-exception-2.0
+
+// This program is free software: you can redistribute it and/or modify
+// it under the terms of the GNU General Public License as published by
+// the Free Software Foundation, either version 3 of the License, or
+// (at your option) any later version.
+
+// This program is distributed in the hope that it will be useful,
+// but WITHOUT ANY WARRANTY; without even the implied warranty of
+// MERCHANTABILITY or FITNESS FOR A PARTICULAR PURPOSE.  See the
+// GNU General Public License for more details.
+
+// You should have received a copy of the GNU General Public License
+// along with this program.  If not, see <http://www.gnu.org/licenses/>.
+
+//! Tests for the `ext_trie_*_verify_proof_*` host functions.
+//!
+//! The proofs passed to these host functions are in the format of Substrate's
+//! `generate_trie_proof` function: node values are ordered depth-first, children included in the
+//! proof are replaced with an empty Merkle value, and the storage value of the key being proven
+//! is omitted.
+//!
+//! The tries used in these tests contain the keys `a` and `ab`. The node of `ab` is a child of
+//! the node of `a`. The trie root hashes and the proofs have been generated with the
+//! `sp_trie::TrieDBMutBuilder` and `sp_trie::generate_trie_proof` functions of Substrate, using
+//! `LayoutV0` and `LayoutV1` and both the Blake2 and Keccak hashers.
+
+use super::super::{vm::ExecHint, Config, HeapPages, HostVm, HostVmPrototype};
+use super::with_core_version_custom_sections;
+use crate::{trie, util};
+
+use core::fmt::Write as _;
+
+/// Calls the given host function with the given parameters and returns its output.
+fn verify_proof(
+    host_fn_name: &str,
+    state_version: Option<u8>,
+    trie_root_hash: &[u8; 32],
+    proof_entries: &[&[u8]],
+    key: &[u8],
+    value: &[u8],
+) -> bool {
+    let mut proof = util::encode_scale_compact_usize(proof_entries.len())
+        .as_ref()
+        .to_vec();
+    for entry in proof_entries {
+        proof.extend_from_slice(util::encode_scale_compact_usize(entry.len()).as_ref());
+        proof.extend_from_slice(entry);
+    }
+
+    // The root hash, proof, key, and value are written one after the other in memory, starting
+    // at `DATA_START`.
+    const DATA_START: u64 = 1048576;
+    let data = [&trie_root_hash[..], &proof, key, value].concat();
+    let pointer_size = |offset: usize, len: usize| {
+        (u64::try_from(len).unwrap() << 32) | (DATA_START + u64::try_from(offset).unwrap())
+    };
+
+    let mut data_escaped = String::new();
+    for byte in &data {
+        write!(data_escaped, "\\{byte:02x}").unwrap();
+    }
+
+    let data_end = DATA_START + u64::try_from(data.len()).unwrap();
+    let module = format!(
+        r#"
+    (module
+        (type (;0;) (func (param i32 i64 i64 i64 {version_param_ty}) (result i32)))
+        (type (;1;) (func (param i32 i32) (result i64)))
+        (import "env" "{host_fn_name}" (func (;0;) (type 0)))
+        (func (;1;) (type 1) (param i32 i32) (result i64)
+          i32.const {DATA_START}
+          i64.const {proof_ptr_size}
+          i64.const {key_ptr_size}
+          i64.const {value_ptr_size}
+          {version_param}
+          call 0
+          i64.extend_i32_u
+          i64.const 4294967296
+          i64.or)
+        (memory (;0;) 17)
+        (global (;0;) (mut i32) (i32.const {DATA_START}))
+        (global (;1;) i32 (i32.const {data_end}))
+        (global (;2;) i32 (i32.const {heap_base}))
+        (export "memory" (memory 0))
+        (export "test" (func 1))
+        (export "__data_end" (global 1))
+        (export "__heap_base" (global 2))
+        (data (;0;) (i32.const 0) "\00\01")
+        (data (;1;) (i32.const {DATA_START}) "{data_escaped}")
+    )
+    "#,
+        version_param_ty = if state_version.is_some() { "i32" } else { "" },
+        proof_ptr_size = pointer_size(32, proof.len()),
+        key_ptr_size = pointer_size(32 + proof.len(), key.len()),
+        value_ptr_size = pointer_size(32 + proof.len() + key.len(), value.len()),
+        version_param = state_version
+            .map(|v| format!("i32.const {v}"))
+            .unwrap_or_default(),
+        heap_base = (data_end + 15) & !15,
+    );
+
+    let module_bytes = with_core_version_custom_sections(wat::parse_str(module).unwrap());
+
+    let mut outputs = ExecHint::available_engines().map(|exec_hint| {
+        let proto = HostVmPrototype::new(Config {
+            allow_unresolved_imports: false,
+            compiled_module_cache: None,
+            resource_limits: Default::default(),
+            allocation_strategy: Default::default(),
+            exec_hint,
+            heap_pages: HeapPages::new(1024),
+            module: &module_bytes,
+        })
+        .unwrap();
+
+        // The test function returns a pointer to a single byte, at address 0 if the host
+        // function has returned `0` and at address 1 if it has returned `1`.
+        let mut vm = HostVm::from(proto.run("test", &[]).unwrap());
+        loop {
+            match vm {
+                HostVm::ReadyToRun(r) => vm = r.run(),
+                HostVm::Finished(out) => match out.value().as_ref() {
+                    [0] => break false,
+                    [1] => break true,
+                    _ => unreachable!(),
+                },
+                _ => unreachable!(),
+            }
+        }
+    });
+
+    let output = outputs.next().unwrap();
+    assert!(outputs.all(|o| o == output));
+    output
+}
+
+fn hash(hash_function: trie::HashFunction, data: &[u8]) -> [u8; 32] {
+    match hash_function {
+        trie::HashFunction::Blake2 => {
+            <[u8; 32]>::try_from(blake2_rfc::blake2b::blake2b(32, &[], data).as_bytes()).unwrap()
+        }
+        trie::HashFunction::Keccak256 => <sha3::Keccak256 as sha3::Digest>::digest(data).into(),
+    }
+}
+
+/// Storage value of the key `a` in the tries of these tests.
+const VALUE_A: &[u8] = b"hello";
+
+/// Proof of `a` in the trie where `ab` is associated to `x`. The root node is the branch node
+/// `a` whose storage value is omitted, and the leaf node `ab` is inlined in it.
+const PROOF_SMALL_A: &str = "826140001041020478";
+
+/// Proof of `ab` in the trie where `ab` is associated to `x`. The leaf node `ab` is inlined in
+/// the root node, and its storage value is omitted.
+const PROOF_SMALL_AB: &str = concat!("c261400014", "68656c6c6f", "0c410200");
+
+/// Proof of `ab` in the trie where `ab` is associated to a large value. The root node contains
+/// an empty Merkle value for its child, and is followed with the leaf node `ab` whose storage
+/// value is omitted. This is the same for both state versions.
+const PROOF_LARGE_AB: [&str; 2] = ["c26140001468656c6c6f00", "410200"];
+
+/// Storage value of the key `ab` in the trie where it is large.
+const LARGE_VALUE: &[u8] = &[0xaa; 40];
+
+/// Root hash and proofs that depend on the hash function and the state version.
+struct Vectors {
+    /// Root hash of the trie where `ab` is associated to `x`. Since none of the storage values
+    /// are hashed, it is the same for both state versions.
+    small_root: &'static str,
+    /// Root hash of the trie where `ab` is associated to [`LARGE_VALUE`].
+    large_root: &'static str,
+    /// Proof of `a` in the trie where `ab` is associated to [`LARGE_VALUE`]. The root node
+    /// contains the hash of the node of `ab`.
+    large_a_proof: &'static str,
+}
+
+fn vectors(hash_function: trie::HashFunction, trie_version: trie::TrieEntryVersion) -> Vectors {
+    match (hash_function, trie_version) {
+        (trie::HashFunction::Blake2, trie::TrieEntryVersion::V0) => Vectors {
+            small_root: "1e74c11052a20f6b55580ce6eddd6a9c98b007d61110f082376b9fd70e58e0d1",
+            large_root: "768ccac460b5845fa038863777edd268e2825da0d495a1ec1e11f484e1b36b79",
+            large_a_proof:
+                "8261400080234fd3cc95c254d762e5f790a9209549970f189f8910c3c3a3482ec04fb3ff19",
+        },
+        (trie::HashFunction::Blake2, trie::TrieEntryVersion::V1) => Vectors {
+            small_root: "1e74c11052a20f6b55580ce6eddd6a9c98b007d61110f082376b9fd70e58e0d1",
+            large_root: "dee104fed54c36e5eb0bab4481667a1d2d82fc8244d42c44bd424914c8354d5c",
+            large_a_proof:
+                "8261400080a21495b4424dc2eb0fe5c8d8897d0ea00f3ef1c8c315f8c4a39eb058513926f3",
+        },
+        (trie::HashFunction::Keccak256, trie::TrieEntryVersion::V0) => Vectors {
+            small_root: "7e7f88348add752ed87a6f3bdebb36c355cf7db00582c0cf0138824f6fd41b1a",
+            large_root: "2fbdf3f984db5adc278d922c48c67509d54fb27572209df2d7e4a2cf7f26a3fd",
+            large_a_proof:
+                "8261400080c30ca44f77240acbae9179c2e007ef0460e2c64fbfae46039e76dcd238c3be72",
+        },
+        (trie::HashFunction::Keccak256, trie::TrieEntryVersion::V1) => Vectors {
+            small_root: "7e7f88348add752ed87a6f3bdebb36c355cf7db00582c0cf0138824f6fd41b1a",
+            large_root: "52cd1bc282bab7a17f7842a2aa5d5716b478c35028c5ef5ba518f08e87ea4915",
+            large_a_proof:
+                "826140008099c4ad9b38eb4c7d147a5cec9879eeacdebd018677ffd8409a18bd78eda4866f",
+        },
+    }
+}
+
+fn decode_hash(hex: &str) -> [u8; 32] {
+    <[u8; 32]>::try_from(hex::decode(hex).unwrap()).unwrap()
+}
+
+fn hash_function(host_fn_name: &str) -> trie::HashFunction {
+    if host_fn_name.contains("blake2") {
+        trie::HashFunction::Blake2
+    } else {
+        trie::HashFunction::Keccak256
+    }
+}
+
+fn check_common(host_fn_name: &str, state_version: Option<u8>) {
+    let hash_function = hash_function(host_fn_name);
+    let trie_version = match state_version {
+        Some(1) => trie::TrieEntryVersion::V1,
+        _ => trie::TrieEntryVersion::V0,
+    };
+
+    let trie_root_hash = decode_hash(vectors(hash_function, trie_version).small_root);
+
+    let proof_a = hex::decode(PROOF_SMALL_A).unwrap();
+    let proof_ab = hex::decode(PROOF_SMALL_AB).unwrap();
+
+    assert!(verify_proof(
+        host_fn_name,
+        state_version,
+        &trie_root_hash,
+        &[&proof_a],
+        b"a",
+        VALUE_A
+    ));
+    assert!(verify_proof(
+        host_fn_name,
+        state_version,
+        &trie_root_hash,
+        &[&proof_ab],
+        b"ab",
+        b"x"
+    ));
+
+    // Wrong value.
+    assert!(!verify_proof(
+        host_fn_name,
+        state_version,
+        &trie_root_hash,
+        &[&proof_a],
+        b"a",
+        b"world"
+    ));
+    // Proof of a different key.
+    assert!(!verify_proof(
+        host_fn_name,
+        state_version,
+        &trie_root_hash,
+        &[&proof_a],
+        b"ab",
+        b"x"
+    ));
+    // Key not in the trie.
+    assert!(!verify_proof(
+        host_fn_name,
+        state_version,
+        &trie_root_hash,
+        &[&proof_a],
+        b"b",
+        VALUE_A
+    ));
+    // Wrong trie root.
+    assert!(!verify_proof(
+        host_fn_name,
+        state_version,
+        &[0; 32],
+        &[&proof_a],
+        b"a",
+        VALUE_A
+    ));
+    // Empty proof.
+    assert!(!verify_proof(
+        host_fn_name,
+        state_version,
+        &trie_root_hash,
+        &[],
+        b"a",
+        VALUE_A
+    ));
+    // Unused node value.
+    assert!(!verify_proof(
+        host_fn_name,
+        state_version,
+        &trie_root_hash,
+        &[&proof_a, b"unused entry"],
+        b"a",
+        VALUE_A
+    ));
+    // Unused valid trie node, here the leaf node of `ab` in the trie where its storage value is
+    // large.
+    assert!(!verify_proof(
+        host_fn_name,
+        state_version,
+        &trie_root_hash,
+        &[&proof_ab, &hex::decode(PROOF_LARGE_AB[1]).unwrap()],
+        b"ab",
+        b"x"
+    ));
+
+    // A regular proof, where the storage value isn't omitted, is rejected.
+    let regular_root_node = {
+        let mut node = hex::decode("c261400014").unwrap();
+        node.extend_from_slice(VALUE_A);
+        node.extend_from_slice(&hex::decode("1041020478").unwrap());
+        node
+    };
+    assert_eq!(hash(hash_function, &regular_root_node), trie_root_hash);
+    assert!(!verify_proof(
+        host_fn_name,
+        state_version,
+        &trie_root_hash,
+        &[&regular_root_node],
+        b"a",
+        VALUE_A
+    ));
+}
+
+fn check_large_value(host_fn_name: &str, state_version: Option<u8>) {
+    let hash_function = hash_function(host_fn_name);
+    let trie_version = match state_version {
+        Some(1) => trie::TrieEntryVersion::V1,
+        _ => trie::TrieEntryVersion::V0,
+    };
+
+    let vectors = vectors(hash_function, trie_version);
+    let trie_root_hash = decode_hash(vectors.large_root);
+
+    let proof_root = hex::decode(PROOF_LARGE_AB[0]).unwrap();
+    let proof_leaf = hex::decode(PROOF_LARGE_AB[1]).unwrap();
+    let proof_a = hex::decode(vectors.large_a_proof).unwrap();
+
+    // Proof of `a`, where the root node contains the hash of its child.
+    assert!(verify_proof(
+        host_fn_name,
+        state_version,
+        &trie_root_hash,
+        &[&proof_a],
+        b"a",
+        VALUE_A
+    ));
+    // The node of `ab` is a valid trie node, but isn't needed to prove `a`.
+    assert!(!verify_proof(
+        host_fn_name,
+        state_version,
+        &trie_root_hash,
+        &[&proof_a, &proof_leaf],
+        b"a",
+        VALUE_A
+    ));
+    // Proof of `a` that is valid in the trie with the other storage value of `ab`.
+    assert!(!verify_proof(
+        host_fn_name,
+        state_version,
+        &trie_root_hash,
+        &[&hex::decode(PROOF_SMALL_A).unwrap()],
+        b"a",
+        VALUE_A
+    ));
+
+    assert!(verify_proof(
+        host_fn_name,
+        state_version,
+        &trie_root_hash,
+        &[&proof_root, &proof_leaf],
+        b"ab",
+        LARGE_VALUE
+    ));
+    assert!(!verify_proof(
+        host_fn_name,
+        state_version,
+        &trie_root_hash,
+        &[&proof_root, &proof_leaf],
+        b"ab",
+        &[0xbb; 40]
+    ));
+    // Missing child node.
+    assert!(!verify_proof(
+        host_fn_name,
+        state_version,
+        &trie_root_hash,
+        &[&proof_root],
+        b"ab",
+        LARGE_VALUE
+    ));
+
+    // The proof is identical for both state versions, but the storage value is hashed only in
+    // the version 1. The proof thus isn't valid with the other version.
+    let other_version = if state_version == Some(1) { 0 } else { 1 };
+    if state_version.is_some() {
+        assert!(!verify_proof(
+            host_fn_name,
+            Some(other_version),
+            &trie_root_hash,
+            &[&proof_root, &proof_leaf],
+            b"ab",
+            LARGE_VALUE
+        ));
+    }
+
+    // A proof containing the Merkle value of the child instead of the child is rejected.
+    let child_node_value = match trie_version {
+        trie::TrieEntryVersion::V0 => {
+            let mut node = hex::decode("4102a0").unwrap();
+            node.extend_from_slice(LARGE_VALUE);
+            node
+        }
+        trie::TrieEntryVersion::V1 => {
+            let mut node = hex::decode("2102").unwrap();
+            node.extend_from_slice(&hash(hash_function, LARGE_VALUE));
+            node
+        }
+    };
+    let mut root_with_child_hash = proof_root[..proof_root.len() - 1].to_vec();
+    root_with_child_hash.push(32 << 2);
+    root_with_child_hash.extend_from_slice(&hash(hash_function, &child_node_value));
+    assert!(!verify_proof(
+        host_fn_name,
+        state_version,
+        &trie_root_hash,
+        &[&root_with_child_hash, &proof_leaf],
+        b"ab",
+        LARGE_VALUE
+    ));
+}
+
+#[test]
+fn blake2_256_verify_proof_version_1() {
+    check_common("ext_trie_blake2_256_verify_proof_version_1", None);
+    check_large_value("ext_trie_blake2_256_verify_proof_version_1", None);
+}
+
+#[test]
+fn blake2_256_verify_proof_version_2() {
+    for version in [0, 1] {
+        check_common("ext_trie_blake2_256_verify_proof_version_2", Some(version));
+        check_large_value("ext_trie_blake2_256_verify_proof_version_2", Some(version));
+    }
+}
+
+#[test]
+fn keccak_256_verify_proof_version_1() {
+    check_common("ext_trie_keccak_256_verify_proof_version_1", None);
+    check_large_value("ext_trie_keccak_256_verify_proof_version_1", None);
+}
+
+#[test]
+fn keccak_256_verify_proof_version_2() {
+    for version in [0, 1] {
+        check_common("ext_trie_keccak_256_verify_proof_version_2", Some(version));
+        check_large_value("ext_trie_keccak_256_verify_proof_version_2", Some(version));
+    }
+}
@@ -31,6 +31,8 @@
 //! - Keeps track of the logs generated by the call and concatenates them into a [`String`].
 //! - Automatically handles some externalities, such as calculating the Merkle root or storage
 //!   transactions.
+//! - Optionally records an [`ExecutionTrace`] containing the host function calls and the storage
+//!   accesses performed by the runtime. See [`Config::execution_tracing`].
 //!
 //! These additional features considerably reduces the number of externals concepts to plug to
 //! the virtual machine.
@@ -42,6 +44,9 @@ use crate::{
     trie, util,
 };
 
+#[cfg(feature = "std")]
+use crate::identity::keystore;
+
 use alloc::{
     borrow::ToOwned as _,
     boxed::Box,
@@ -50,8 +55,9 @@ use alloc::{
     string::{String, ToString as _},
     vec::Vec,
 };
-use core::{fmt, iter, ops};
+use core::{fmt, iter, ops, time::Duration};
 
+pub use host::{AllocationStats, HostFunctionCall, HttpError, HttpRequestStatus, KeyAlgorithm};
 pub use trie::{Nibble, TrieEntryVersion};
 
 mod tests;
@@ -85,6 +91,15 @@ pub struct Config<'a, TParams> {
     /// If `true`, then [`StorageChanges::trie_changes_iter_ordered`] will return `Some`.
     /// Passing `None` requires fewer calculation and fewer storage accesses.
     pub calculate_trie_changes: bool,
+
+    /// If `Some`, the execution is traced, and [`Success::execution_trace`] and
+    /// [`Error::execution_trace`] will contain `Some`.
+    ///
+    /// The function must return the time elapsed since an arbitrary fixed moment. It is used in
+    /// order to measure the duration of each host function call.
+    ///
+    /// Tracing slows down the execution and should only be enabled for debugging purposes.
+    pub execution_tracing: Option<Box<dyn Fn() -> Duration + Send + Sync>>,
 }
 
 /// Start running the WebAssembly virtual machine.
@@ -98,11 +113,18 @@ pub fn run(
         .state_version
         .unwrap_or(TrieEntryVersion::V0);
 
+    let mut vm = config
+        .virtual_machine
+        .run_vectored(config.function_to_call, config.parameter)?;
+    let storage_accesses = if let Some(clock) = config.execution_tracing {
+        vm.enable_tracing(clock);
+        Some(Vec::new())
+    } else {
+        None
+    };
+
     Ok(Inner {
-        vm: config
-            .virtual_machine
-            .run_vectored(config.function_to_call, config.parameter)?
-            .into(),
+        vm: vm.into(),
         pending_storage_changes: PendingStorageChanges {
             trie_diffs: {
                 let mut hm = hashbrown::HashMap::with_capacity_and_hasher(4, Default::default());
@@ -123,6 +145,7 @@ pub fn run(
         logs: String::new(),
         max_log_level: config.max_log_level,
         calculate_trie_changes: config.calculate_trie_changes,
+        storage_accesses,
     }
     .run())
 }
@@ -140,6 +163,8 @@ pub struct Success {
     pub state_trie_version: TrieEntryVersion,
     /// Concatenation of all the log messages printed by the runtime.
     pub logs: String,
+    /// Trace of the execution. `Some` if and only if [`Config::execution_tracing`] was `Some`.
+    pub execution_trace: Option<ExecutionTrace>,
 }
 
 /// See [`Success::storage_changes`].
@@ -428,6 +453,9 @@ pub struct Error {
     pub detail: ErrorDetail,
     /// Prototype of the virtual machine that was passed through [`Config::virtual_machine`].
     pub prototype: host::HostVmPrototype,
+    /// Trace of the execution up until the error. `Some` if and only if
+    /// [`Config::execution_tracing`] was `Some`.
+    pub execution_trace: Option<ExecutionTrace>,
 }
 
 /// See [`Error::detail`].
@@ -443,6 +471,79 @@ pub enum ErrorDetail {
     },
     /// Size of the logs generated by the runtime exceeds the limit.
     LogsTooLong,
+    /// The runtime has requested the generation of a key that couldn't be generated.
+    /// See [`KeystoreGenerate::resume_failed`].
+    #[display(fmt = "Failed to generate a key in the keystore")]
+    KeyGenerationFailed,
+}
+
+/// Trace of an execution. See [`Config::execution_tracing`].
+#[derive(Debug, Clone)]
+pub struct ExecutionTrace {
+    /// List of all the host functions that the runtime has called, in the order in which they
+    /// have been called.
+    ///
+    /// This includes the host functions that are handled internally, such as the ones
+    /// accessing the storage.
+    pub host_function_calls: Vec<HostFunctionCall>,
+
+    /// List of all the accesses to the storage that the runtime has performed, in the order in
+    /// which they have been performed.
+    ///
+    /// Accesses that have later been reverted by a storage transaction rollback are included.
+    pub storage_accesses: Vec<StorageAccess>,
+
+    /// Statistics about the memory allocator at the end of the execution.
+    pub allocator_stats: AllocationStats,
+}
+
+impl ExecutionTrace {
+    /// Builds an [`ExecutionTrace`] out of the trace of the virtual machine and the storage
+    /// accesses recorded in [`Inner::storage_accesses`]. Returns `None` if tracing is disabled.
+    fn from_parts(
+        host_trace: Option<host::ExecutionTrace>,
+        storage_accesses: Option<Vec<StorageAccess>>,
+    ) -> Option<Self> {
+        let host_trace = host_trace?;
+        Some(ExecutionTrace {
+            host_function_calls: host_trace.host_function_calls,
+            storage_accesses: storage_accesses.unwrap_or_default(),
+            allocator_stats: host_trace.allocator_stats,
+        })
+    }
+}
+
+/// Storage access found in an [`ExecutionTrace`].
+#[derive(Debug, Clone)]
+pub struct StorageAccess {
+    /// Child trie the access concerns, or `None` for the main trie.
+    pub child_trie: Option<Vec<u8>>,
+    /// Key that has been accessed, or prefix of the keys for [`StorageAccessKind::ClearPrefix`].
+    pub key: Vec<u8>,
+    /// Type of access.
+    pub kind: StorageAccessKind,
+}
+
+/// See [`StorageAccess::kind`].
+#[derive(Debug, Clone)]
+pub enum StorageAccessKind {
+    /// The storage value has been read.
+    Read {
+        /// Size of the storage value, or `None` if there is no storage value.
+        value_size: Option<usize>,
+    },
+    /// The storage value has been written.
+    Write {
+        /// Size of the new storage value, or `None` if the storage value has been removed.
+        value_size: Option<usize>,
+    },
+    /// Some data has been appended to the storage value.
+    Append {
+        /// Size of the data that has been appended.
+        value_size: usize,
+    },
+    /// All the storage values whose key starts with [`StorageAccess::key`] have been removed.
+    ClearPrefix,
 }
 
 /// Current state of the execution.
@@ -459,6 +560,12 @@ pub enum RuntimeHostVm {
     NextKey(NextKey),
     /// Verifying whether a signature is correct is required in order to continue.
     SignatureVerification(SignatureVerification),
+    /// Obtaining the public keys of the keystore is required in order to continue.
+    KeystorePublicKeys(KeystorePublicKeys),
+    /// Generating a new key in the keystore is required in order to continue.
+    KeystoreGenerate(KeystoreGenerate),
+    /// Signing a message with a key of the keystore is required in order to continue.
+    KeystoreSign(KeystoreSign),
     /// Setting an offchain storage value is required in order to continue.
     ///
     /// Contrary to [`OffchainContext::StorageSet`], this variant is allowed to happen
@@ -478,6 +585,9 @@ impl RuntimeHostVm {
             RuntimeHostVm::ClosestDescendantMerkleValue(inner) => inner.inner.vm.into_prototype(),
             RuntimeHostVm::NextKey(inner) => inner.inner.vm.into_prototype(),
             RuntimeHostVm::SignatureVerification(inner) => inner.inner.vm.into_prototype(),
+            RuntimeHostVm::KeystorePublicKeys(inner) => inner.inner.vm.into_prototype(),
+            RuntimeHostVm::KeystoreGenerate(inner) => inner.inner.vm.into_prototype(),
+            RuntimeHostVm::KeystoreSign(inner) => inner.inner.vm.into_prototype(),
             RuntimeHostVm::OffchainStorageSet(inner) => inner.inner.vm.into_prototype(),
             RuntimeHostVm::Offchain(inner) => inner.into_prototype(),
         }
@@ -498,6 +608,18 @@ pub enum OffchainContext {
     RandomSeed(OffchainRandomSeed),
     /// Submit transaction from offchain worker.
     SubmitTransaction(OffchainSubmitTransaction),
+    /// Start an HTTP request from offchain worker.
+    HttpRequestStart(OffchainHttpRequestStart),
+    /// Add a header to an HTTP request from offchain worker.
+    HttpRequestAddHeader(OffchainHttpRequestAddHeader),
+    /// Write the body of an HTTP request from offchain worker.
+    HttpRequestWriteBody(OffchainHttpRequestWriteBody),
+    /// Wait for the responses to HTTP requests from offchain worker.
+    HttpResponseWait(OffchainHttpResponseWait),
+    /// Obtain the headers of the response to an HTTP request from offchain worker.
+    HttpResponseHeaders(OffchainHttpResponseHeaders),
+    /// Read the body of the response to an HTTP request from offchain worker.
+    HttpResponseReadBody(OffchainHttpResponseReadBody),
 }
 
 impl OffchainContext {
@@ -508,6 +630,12 @@ impl OffchainContext {
             OffchainContext::Timestamp(inner) => inner.inner.vm.into_prototype(),
             OffchainContext::RandomSeed(inner) => inner.inner.vm.into_prototype(),
             OffchainContext::SubmitTransaction(inner) => inner.inner.vm.into_prototype(),
+            OffchainContext::HttpRequestStart(inner) => inner.inner.vm.into_prototype(),
+            OffchainContext::HttpRequestAddHeader(inner) => inner.inner.vm.into_prototype(),
+            OffchainContext::HttpRequestWriteBody(inner) => inner.inner.vm.into_prototype(),
+            OffchainContext::HttpResponseWait(inner) => inner.inner.vm.into_prototype(),
+            OffchainContext::HttpResponseHeaders(inner) => inner.inner.vm.into_prototype(),
+            OffchainContext::HttpResponseReadBody(inner) => inner.inner.vm.into_prototype(),
         }
     }
 }
@@ -603,6 +731,16 @@ impl StorageGet {
 
         match (self.inner.vm, self.inner.root_calculation.take()) {
             (host::HostVm::ExternalStorageGet(req), None) => {
+                if let Some(storage_accesses) = &mut self.inner.storage_accesses {
+                    storage_accesses.push(StorageAccess {
+                        child_trie: req.child_trie().map(|ct| ct.as_ref().to_vec()),
+                        key: req.key().as_ref().to_vec(),
+                        kind: StorageAccessKind::Read {
+                            value_size: value.as_ref().map(|(v, _)| v.len()),
+                        },
+                    });
+                }
+
                 // TODO: should actually report the offset and max_size in the API
                 self.inner.vm = req.resume_full_value(value.as_ref().map(|(v, _)| &v[..]));
             }
@@ -976,6 +1114,295 @@ impl SignatureVerification {
     }
 }
 
+/// Obtaining the public keys of the keystore that belong to a certain key type and algorithm is
+/// required in order to continue.
+#[must_use]
+pub struct KeystorePublicKeys {
+    inner: Inner,
+}
+
+impl KeystorePublicKeys {
+    /// Returns the algorithm of the keys to return.
+    pub fn algorithm(&self) -> KeyAlgorithm {
+        match &self.inner.vm {
+            host::HostVm::KeystorePublicKeys(req) => req.algorithm(),
+            // We only create a `KeystorePublicKeys` if the state is one of the above.
+            _ => unreachable!(),
+        }
+    }
+
+    /// Returns the identifier of the type of keys to return, such as `b"babe"` or `b"gran"`.
+    pub fn key_type_id(&self) -> [u8; 4] {
+        match &self.inner.vm {
+            host::HostVm::KeystorePublicKeys(req) => *req.key_type_id(),
+            // We only create a `KeystorePublicKeys` if the state is one of the above.
+            _ => unreachable!(),
+        }
+    }
+
+    /// Resume execution by providing the list of public keys.
+    ///
+    /// # Panic
+    ///
+    /// Panics if the size of one of the public keys isn't equal to
+    /// [`KeyAlgorithm::public_key_size`].
+    ///
+    pub fn resume(
+        mut self,
+        public_keys: impl ExactSizeIterator<Item = impl AsRef<[u8]>>,
+    ) -> RuntimeHostVm {
+        match self.inner.vm {
+            host::HostVm::KeystorePublicKeys(req) => {
+                self.inner.vm = req.resume(public_keys);
+            }
+            // We only create a `KeystorePublicKeys` if the state is one of the above.
+            _ => unreachable!(),
+        };
+
+        self.inner.run()
+    }
+
+    /// Resume execution by providing the public keys found in the given keystore.
+    #[cfg(feature = "std")]
+    pub async fn resume_with_keystore(self, keystore: &keystore::Keystore) -> RuntimeHostVm {
+        let algorithm = self.algorithm();
+        let Some(namespace) = keystore::KeyNamespace::from_key_type_id(&self.key_type_id()) else {
+            return self.resume(iter::empty::<[u8; 32]>());
+        };
+
+        let public_keys = keystore
+            .keys_with_algorithm()
+            .await
+            .filter(|(n, a, _)| *n == namespace && *a == algorithm)
+            .map(|(_, _, public_key)| public_key)
+            .collect::<Vec<_>>();
+        self.resume(public_keys.into_iter())
+    }
+}
+
+/// Generating a new key in the keystore is required in order to continue.
+#[must_use]
+pub struct KeystoreGenerate {
+    inner: Inner,
+}
+
+impl KeystoreGenerate {
+    /// Returns the algorithm of the key to generate.
+    pub fn algorithm(&self) -> KeyAlgorithm {
+        match &self.inner.vm {
+            host::HostVm::KeystoreGenerate(req) => req.algorithm(),
+            // We only create a `KeystoreGenerate` if the state is one of the above.
+            _ => unreachable!(),
+        }
+    }
+
+    /// Returns the identifier of the type of key to generate, such as `b"babe"` or `b"gran"`.
+    pub fn key_type_id(&self) -> [u8; 4] {
+        match &self.inner.vm {
+            host::HostVm::KeystoreGenerate(req) => *req.key_type_id(),
+            // We only create a `KeystoreGenerate` if the state is one of the above.
+            _ => unreachable!(),
+        }
+    }
+
+    /// Returns the seed that the key must be derived from, if any. If `None`, the key must be
+    /// randomly generated.
+    pub fn seed(&'_ self) -> Option<impl AsRef<[u8]> + '_> {
+        match &self.inner.vm {
+            host::HostVm::KeystoreGenerate(req) => req.seed(),
+            // We only create a `KeystoreGenerate` if the state is one of the above.
+            _ => unreachable!(),
+        }
+    }
+
+    /// Resume execution by providing the public key of the newly-generated key.
+    ///
+    /// # Panic
+    ///
+    /// Panics if the size of the public key isn't equal to [`KeyAlgorithm::public_key_size`].
+    ///
+    pub fn resume(mut self, public_key: &[u8]) -> RuntimeHostVm {
+        match self.inner.vm {
+            host::HostVm::KeystoreGenerate(req) => {
+                self.inner.vm = req.resume(public_key);
+            }
+            // We only create a `KeystoreGenerate` if the state is one of the above.
+            _ => unreachable!(),
+        };
+
+        self.inner.run()
+    }
+
+    /// Stops execution with an [`ErrorDetail::KeyGenerationFailed`] error.
+    ///
+    /// The runtime has no way to recover from a failure to generate a key, and the execution
+    /// must thus be aborted.
+    pub fn resume_failed(self) -> RuntimeHostVm {
+        let mut prototype = self.inner.vm.into_prototype();
+        RuntimeHostVm::Finished(Err(Error {
+            detail: ErrorDetail::KeyGenerationFailed,
+            execution_trace: ExecutionTrace::from_parts(
+                prototype.take_execution_trace(),
+                self.inner.storage_accesses,
+            ),
+            prototype,
+        }))
+    }
+
+    /// Resume execution by generating the key in the given keystore.
+    ///
+    /// If `save` is `true`, the generated key is saved in the file system of the keystore. See
+    /// [`keystore::Keystore::generate_ed25519`].
+    ///
+    /// The keystore doesn't support keys derived from a seed, or key types that don't correspond
+    /// to a [`keystore::KeyNamespace`]. The execution fails with
+    /// [`ErrorDetail::KeyGenerationFailed`] if the runtime requests one of them.
+    #[cfg(feature = "std")]
+    pub async fn resume_with_keystore(
+        self,
+        keystore: &keystore::Keystore,
+        save: bool,
+    ) -> RuntimeHostVm {
+        let Some(namespace) = keystore::KeyNamespace::from_key_type_id(&self.key_type_id()) else {
+            return self.resume_failed();
+        };
+
+        if self.seed().is_some() {
+            return self.resume_failed();
+        }
+
+        let result = match self.algorithm() {
+            KeyAlgorithm::Ed25519 => keystore
+                .generate_ed25519(namespace, save)
+                .await
+                .map(|key| key.to_vec()),
+            KeyAlgorithm::Sr25519 => keystore
+                .generate_sr25519(namespace, save)
+                .await
+                .map(|key| key.to_vec()),
+            KeyAlgorithm::Ecdsa => keystore
+                .generate_ecdsa(namespace, save)
+                .await
+                .map(|key| key.to_vec()),
+        };
+
+        match result {
+            Ok(public_key) => self.resume(&public_key),
+            Err(_) => self.resume_failed(),
+        }
+    }
+}
+
+/// Signing a message with a key of the keystore is required in order to continue.
+#[must_use]
+pub struct KeystoreSign {
+    inner: Inner,
+}
+
+impl KeystoreSign {
+    /// Returns the algorithm of the key to sign with.
+    pub fn algorithm(&self) -> KeyAlgorithm {
+        match &self.inner.vm {
+            host::HostVm::KeystoreSign(req) => req.algorithm(),
+            // We only create a `KeystoreSign` if the state is one of the above.
+            _ => unreachable!(),
+        }
+    }
+
+    /// Returns the identifier of the type of key to sign with, such as `b"babe"` or `b"gran"`.
+    pub fn key_type_id(&self) -> [u8; 4] {
+        match &self.inner.vm {
+            host::HostVm::KeystoreSign(req) => *req.key_type_id(),
+            // We only create a `KeystoreSign` if the state is one of the above.
+            _ => unreachable!(),
+        }
+    }
+
+    /// Returns the public key of the key to sign with.
+    pub fn public_key(&'_ self) -> impl AsRef<[u8]> + '_ {
+        match &self.inner.vm {
+            host::HostVm::KeystoreSign(req) => req.public_key(),
+            // We only create a `KeystoreSign` if the state is one of the above.
+            _ => unreachable!(),
+        }
+    }
+
+    /// Returns the message to sign.
+    pub fn message(&'_ self) -> impl AsRef<[u8]> + '_ {
+        match &self.inner.vm {
+            host::HostVm::KeystoreSign(req) => req.message(),
+            // We only create a `KeystoreSign` if the state is one of the above.
+            _ => unreachable!(),
+        }
+    }
+
+    /// Resume execution by providing the signature, or `None` if the requested key isn't
+    /// available.
+    ///
+    /// # Panic
+    ///
+    /// Panics if the size of the signature isn't equal to [`KeyAlgorithm::signature_size`].
+    ///
+    pub fn resume(mut self, signature: Option<&[u8]>) -> RuntimeHostVm {
+        match self.inner.vm {
+            host::HostVm::KeystoreSign(req) => {
+                self.inner.vm = req.resume(signature);
+            }
+            // We only create a `KeystoreSign` if the state is one of the above.
+            _ => unreachable!(),
+        };
+
+        self.inner.run()
+    }
+
+    /// Resume execution by signing the message with the given keystore.
+    ///
+    /// The runtime is informed that the key isn't available if the keystore doesn't contain
+    /// it, if it was generated with a different algorithm, or if it couldn't be loaded.
+    #[cfg(feature = "std")]
+    pub async fn resume_with_keystore(self, keystore: &keystore::Keystore) -> RuntimeHostVm {
+        let algorithm = self.algorithm();
+        let Some(namespace) = keystore::KeyNamespace::from_key_type_id(&self.key_type_id()) else {
+            return self.resume(None);
+        };
+
+        if algorithm == KeyAlgorithm::Ecdsa {
+            let Ok(public_key) = <[u8; 33]>::try_from(self.public_key().as_ref()) else {
+                return self.resume(None);
+            };
+            let result = keystore
+                .sign_ecdsa(namespace, &public_key, self.message().as_ref())
+                .await;
+            return match result {
+                Ok(signature) => self.resume(Some(&signature)),
+                Err(_) => self.resume(None),
+            };
+        }
+
+        let Ok(public_key) = <[u8; 32]>::try_from(self.public_key().as_ref()) else {
+            return self.resume(None);
+        };
+
+        // The keystore signs with whatever algorithm the key was generated with. Make sure
+        // that this matches what the runtime expects.
+        if !keystore
+            .keys_with_algorithm()
+            .await
+            .any(|(n, a, k)| n == namespace && a == algorithm && k == public_key)
+        {
+            return self.resume(None);
+        }
+
+        let result = keystore
+            .sign(namespace, &public_key, self.message().as_ref())
+            .await;
+        match result {
+            Ok(signature) => self.resume(Some(&signature)),
+            Err(_) => self.resume(None),
+        }
+    }
+}
+
 /// Loading an offchain storage value is required in order to continue.
 #[must_use]
 pub struct OffchainStorageGet {
@@ -1200,6 +1627,280 @@ impl OffchainSubmitTransaction {
     }
 }
 
+/// The runtime requests starting an HTTP request.
+#[must_use]
+pub struct OffchainHttpRequestStart {
+    inner: Inner,
+}
+
+impl OffchainHttpRequestStart {
+    /// Returns the HTTP method of the request, such as `GET` or `POST`.
+    pub fn method(&'_ self) -> impl AsRef<str> + '_ {
+        match &self.inner.vm {
+            host::HostVm::OffchainHttpRequestStart(req) => req.method(),
+            // We only create a `OffchainHttpRequestStart` if the state is one of the above.
+            _ => unreachable!(),
+        }
+    }
+
+    /// Returns the URI of the request.
+    pub fn uri(&'_ self) -> impl AsRef<str> + '_ {
+        match &self.inner.vm {
+            host::HostVm::OffchainHttpRequestStart(req) => req.uri(),
+            // We only create a `OffchainHttpRequestStart` if the state is one of the above.
+            _ => unreachable!(),
+        }
+    }
+
+    /// Resume execution. Must provide the identifier of the newly-started request, or `Err` if
+    /// the request couldn't be started.
+    ///
+    /// See [`host::OffchainHttpRequestStart::resume`].
+    pub fn resume(mut self, request_id: Result<u16, ()>) -> RuntimeHostVm {
+        match self.inner.vm {
+            host::HostVm::OffchainHttpRequestStart(req) => {
+                self.inner.vm = req.resume(request_id);
+            }
+            // We only create a `OffchainHttpRequestStart` if the state is one of the above.
+            _ => unreachable!(),
+        };
+
+        self.inner.run()
+    }
+}
+
+/// The runtime requests adding a header to an HTTP request.
+#[must_use]
+pub struct OffchainHttpRequestAddHeader {
+    inner: Inner,
+}
+
+impl OffchainHttpRequestAddHeader {
+    /// Returns the identifier of the request.
+    pub fn request_id(&self) -> u16 {
+        match &self.inner.vm {
+            host::HostVm::OffchainHttpRequestAddHeader(req) => req.request_id(),
+            // We only create a `OffchainHttpRequestAddHeader` if the state is one of the above.
+            _ => unreachable!(),
+        }
+    }
+
+    /// Returns the name of the header to add.
+    pub fn name(&'_ self) -> impl AsRef<str> + '_ {
+        match &self.inner.vm {
+            host::HostVm::OffchainHttpRequestAddHeader(req) => req.name(),
+            // We only create a `OffchainHttpRequestAddHeader` if the state is one of the above.
+            _ => unreachable!(),
+        }
+    }
+
+    /// Returns the value of the header to add.
+    pub fn value(&'_ self) -> impl AsRef<str> + '_ {
+        match &self.inner.vm {
+            host::HostVm::OffchainHttpRequestAddHeader(req) => req.value(),
+            // We only create a `OffchainHttpRequestAddHeader` if the state is one of the above.
+            _ => unreachable!(),
+        }
+    }
+
+    /// Resume execution. Must indicate whether the header has been added.
+    pub fn resume(mut self, result: Result<(), ()>) -> RuntimeHostVm {
+        match self.inner.vm {
+            host::HostVm::OffchainHttpRequestAddHeader(req) => {
+                self.inner.vm = req.resume(result);
+            }
+            // We only create a `OffchainHttpRequestAddHeader` if the state is one of the above.
+            _ => unreachable!(),
+        };
+
+        self.inner.run()
+    }
+}
+
+/// The runtime requests writing a chunk of the body of an HTTP request.
+#[must_use]
+pub struct OffchainHttpRequestWriteBody {
+    inner: Inner,
+}
+
+impl OffchainHttpRequestWriteBody {
+    /// Returns the identifier of the request.
+    pub fn request_id(&self) -> u16 {
+        match &self.inner.vm {
+            host::HostVm::OffchainHttpRequestWriteBody(req) => req.request_id(),
+            // We only create a `OffchainHttpRequestWriteBody` if the state is one of the above.
+            _ => unreachable!(),
+        }
+    }
+
+    /// Returns the chunk of body to write. An empty chunk indicates that the body is complete.
+    pub fn chunk(&'_ self) -> impl AsRef<[u8]> + '_ {
+        match &self.inner.vm {
+            host::HostVm::OffchainHttpRequestWriteBody(req) => req.chunk(),
+            // We only create a `OffchainHttpRequestWriteBody` if the state is one of the above.
+            _ => unreachable!(),
+        }
+    }
+
+    /// Returns the UNIX timestamp, in milliseconds, after which the operation must be aborted.
+    pub fn deadline(&self) -> Option<u64> {
+        match &self.inner.vm {
+            host::HostVm::OffchainHttpRequestWriteBody(req) => req.deadline(),
+            // We only create a `OffchainHttpRequestWriteBody` if the state is one of the above.
+            _ => unreachable!(),
+        }
+    }
+
+    /// Resume execution after having written the chunk.
+    pub fn resume(mut self, result: Result<(), HttpError>) -> RuntimeHostVm {
+        match self.inner.vm {
+            host::HostVm::OffchainHttpRequestWriteBody(req) => {
+                self.inner.vm = req.resume(result);
+            }
+            // We only create a `OffchainHttpRequestWriteBody` if the state is one of the above.
+            _ => unreachable!(),
+        };
+
+        self.inner.run()
+    }
+}
+
+/// The runtime requests waiting for the responses to some HTTP requests.
+#[must_use]
+pub struct OffchainHttpResponseWait {
+    inner: Inner,
+}
+
+impl OffchainHttpResponseWait {
+    /// Returns the identifiers of the requests whose response must be waited for.
+    pub fn request_ids(&'_ self) -> impl ExactSizeIterator<Item = u16> + '_ {
+        match &self.inner.vm {
+            host::HostVm::OffchainHttpResponseWait(req) => req.request_ids(),
+            // We only create a `OffchainHttpResponseWait` if the state is one of the above.
+            _ => unreachable!(),
+        }
+    }
+
+    /// Returns the UNIX timestamp, in milliseconds, after which waiting must stop.
+    pub fn deadline(&self) -> Option<u64> {
+        match &self.inner.vm {
+            host::HostVm::OffchainHttpResponseWait(req) => req.deadline(),
+            // We only create a `OffchainHttpResponseWait` if the state is one of the above.
+            _ => unreachable!(),
+        }
+    }
+
+    /// Resume execution. Must provide the status of each request, in the same order as
+    /// [`OffchainHttpResponseWait::request_ids`].
+    ///
+    /// # Panic
+    ///
+    /// Panics if the number of statuses doesn't match the number of request identifiers.
+    ///
+    pub fn resume(
+        mut self,
+        statuses: impl ExactSizeIterator<Item = HttpRequestStatus>,
+    ) -> RuntimeHostVm {
+        match self.inner.vm {
+            host::HostVm::OffchainHttpResponseWait(req) => {
+                self.inner.vm = req.resume(statuses);
+            }
+            // We only create a `OffchainHttpResponseWait` if the state is one of the above.
+            _ => unreachable!(),
+        };
+
+        self.inner.run()
+    }
+}
+
+/// The runtime requests the headers of the response to an HTTP request.
+#[must_use]
+pub struct OffchainHttpResponseHeaders {
+    inner: Inner,
+}
+
+impl OffchainHttpResponseHeaders {
+    /// Returns the identifier of the request.
+    pub fn request_id(&self) -> u16 {
+        match &self.inner.vm {
+            host::HostVm::OffchainHttpResponseHeaders(req) => req.request_id(),
+            // We only create a `OffchainHttpResponseHeaders` if the state is one of the above.
+            _ => unreachable!(),
+        }
+    }
+
+    /// Resume execution by providing the names and values of the headers of the response.
+    pub fn resume(
+        mut self,
+        headers: impl Iterator<Item = (impl AsRef<[u8]>, impl AsRef<[u8]>)>,
+    ) -> RuntimeHostVm {
+        match self.inner.vm {
+            host::HostVm::OffchainHttpResponseHeaders(req) => {
+                self.inner.vm = req.resume(headers);
+            }
+            // We only create a `OffchainHttpResponseHeaders` if the state is one of the above.
+            _ => unreachable!(),
+        };
+
+        self.inner.run()
+    }
+}
+
+/// The runtime requests reading a chunk of the body of the response to an HTTP request.
+#[must_use]
+pub struct OffchainHttpResponseReadBody {
+    inner: Inner,
+}
+
+impl OffchainHttpResponseReadBody {
+    /// Returns the identifier of the request.
+    pub fn request_id(&self) -> u16 {
+        match &self.inner.vm {
+            host::HostVm::OffchainHttpResponseReadBody(req) => req.request_id(),
+            // We only create a `OffchainHttpResponseReadBody` if the state is one of the above.
+            _ => unreachable!(),
+        }
+    }
+
+    /// Returns the maximum number of bytes that can be passed to
+    /// [`OffchainHttpResponseReadBody::resume`].
+    pub fn max_size(&self) -> usize {
+        match &self.inner.vm {
+            host::HostVm::OffchainHttpResponseReadBody(req) => req.max_size(),
+            // We only create a `OffchainHttpResponseReadBody` if the state is one of the above.
+            _ => unreachable!(),
+        }
+    }
+
+    /// Returns the UNIX timestamp, in milliseconds, after which the operation must be aborted.
+    pub fn deadline(&self) -> Option<u64> {
+        match &self.inner.vm {
+            host::HostVm::OffchainHttpResponseReadBody(req) => req.deadline(),
+            // We only create a `OffchainHttpResponseReadBody` if the state is one of the above.
+            _ => unreachable!(),
+        }
+    }
+
+    /// Resume execution by providing the next chunk of the body. An empty chunk indicates that
+    /// the body has been entirely read.
+    ///
+    /// # Panic
+    ///
+    /// Panics if the chunk is larger than [`OffchainHttpResponseReadBody::max_size`].
+    ///
+    pub fn resume(mut self, result: Result<&[u8], HttpError>) -> RuntimeHostVm {
+        match self.inner.vm {
+            host::HostVm::OffchainHttpResponseReadBody(req) => {
+                self.inner.vm = req.resume(result);
+            }
+            // We only create a `OffchainHttpResponseReadBody` if the state is one of the above.
+            _ => unreachable!(),
+        };
+
+        self.inner.run()
+    }
+}
+
 /// Implementation detail of the execution. Shared by all the variants of [`RuntimeHostVm`]
 /// other than [`RuntimeHostVm::Finished`].
 struct Inner {
@@ -1235,6 +1936,10 @@ struct Inner {
 
     /// See [`Config::calculate_trie_changes`].
     calculate_trie_changes: bool,
+
+    /// Storage accesses performed by the runtime so far. `Some` if and only if the execution is
+    /// being traced. See [`Config::execution_tracing`].
+    storage_accesses: Option<Vec<StorageAccess>>,
 }
 
 /// See [`Inner::pending_storage_changes`].
@@ -1506,17 +2211,24 @@ impl Inner {
             match self.vm {
                 host::HostVm::ReadyToRun(r) => self.vm = r.run(),
 
-                host::HostVm::Error { error, prototype } => {
+                host::HostVm::Error {
+                    error,
+                    mut prototype,
+                } => {
                     return RuntimeHostVm::Finished(Err(Error {
                         detail: ErrorDetail::WasmVm {
                             error,
                             logs: self.logs,
                         },
+                        execution_trace: ExecutionTrace::from_parts(
+                            prototype.take_execution_trace(),
+                            self.storage_accesses,
+                        ),
                         prototype,
                     }));
                 }
 
-                host::HostVm::Finished(finished) => {
+                host::HostVm::Finished(mut finished) => {
                     debug_assert!(self.transactions_stack.is_empty()); // Guaranteed by `host`.
                     debug_assert!(
                         self.pending_storage_changes
@@ -1535,6 +2247,11 @@ impl Inner {
                     );
                     debug_assert!(self.offchain_storage_changes.is_empty());
 
+                    let execution_trace = ExecutionTrace::from_parts(
+                        finished.take_execution_trace(),
+                        self.storage_accesses,
+                    );
+
                     return RuntimeHostVm::Finished(Ok(Success {
                         virtual_machine: SuccessVirtualMachine(finished),
                         storage_changes: StorageChanges {
@@ -1543,6 +2260,7 @@ impl Inner {
                         },
                         state_trie_version: self.state_trie_version,
                         logs: self.logs,
+                        execution_trace,
                     }));
                 }
 
@@ -1554,6 +2272,15 @@ impl Inner {
                         .and_then(|diff| diff.diff_get(req.key().as_ref()));
 
                     if let Some((value_in_diff, _)) = diff_search {
+                        if let Some(storage_accesses) = &mut self.storage_accesses {
+                            storage_accesses.push(StorageAccess {
+                                child_trie: req.child_trie().map(|ct| ct.as_ref().to_vec()),
+                                key: req.key().as_ref().to_vec(),
+                                kind: StorageAccessKind::Read {
+                                    value_size: value_in_diff.map(|v| v.len()),
+                                },
+                            });
+                        }
                         self.vm = req.resume_full_value(value_in_diff);
                     } else {
                         self.vm = req.into();
@@ -1571,6 +2298,16 @@ impl Inner {
                         continue;
                     }
 
+                    if let Some(storage_accesses) = &mut self.storage_accesses {
+                        storage_accesses.push(StorageAccess {
+                            child_trie: req.child_trie().map(|ct| ct.as_ref().to_vec()),
+                            key: req.key().as_ref().to_vec(),
+                            kind: StorageAccessKind::Write {
+                                value_size: req.value().map(|v| v.as_ref().len()),
+                            },
+                        });
+                    }
+
                     // TOOD: to_owned overhead
                     self.pending_storage_changes
                         .stale_child_tries_root_hashes
@@ -1601,6 +2338,16 @@ impl Inner {
                         continue;
                     }
 
+                    if let Some(storage_accesses) = &mut self.storage_accesses {
+                        storage_accesses.push(StorageAccess {
+                            child_trie: req.child_trie().map(|ct| ct.as_ref().to_vec()),
+                            key: req.key().as_ref().to_vec(),
+                            kind: StorageAccessKind::Append {
+                                value_size: req.value().as_ref().len(),
+                            },
+                        });
+                    }
+
                     // TOOD: to_owned overhead
                     self.pending_storage_changes
                         .stale_child_tries_root_hashes
@@ -1635,6 +2382,14 @@ impl Inner {
                         continue;
                     }
 
+                    if let Some(storage_accesses) = &mut self.storage_accesses {
+                        storage_accesses.push(StorageAccess {
+                            child_trie: req.child_trie().map(|ct| ct.as_ref().to_vec()),
+                            key: req.prefix().as_ref().to_vec(),
+                            kind: StorageAccessKind::ClearPrefix,
+                        });
+                    }
+
                     // TODO: consider doing this only if at least one key was actually removed
                     // TOOD: to_owned overhead
                     self.pending_storage_changes
@@ -1702,6 +2457,21 @@ impl Inner {
                     });
                 }
 
+                host::HostVm::KeystorePublicKeys(req) => {
+                    self.vm = req.into();
+                    return RuntimeHostVm::KeystorePublicKeys(KeystorePublicKeys { inner: self });
+                }
+
+                host::HostVm::KeystoreGenerate(req) => {
+                    self.vm = req.into();
+                    return RuntimeHostVm::KeystoreGenerate(KeystoreGenerate { inner: self });
+                }
+
+                host::HostVm::KeystoreSign(req) => {
+                    self.vm = req.into();
+                    return RuntimeHostVm::KeystoreSign(KeystoreSign { inner: self });
+                }
+
                 host::HostVm::CallRuntimeVersion(req) => {
                     // TODO: make the user execute this ; see https://github.com/paritytech/smoldot/issues/144
                     // The code below compiles the provided WebAssembly runtime code, which is a
@@ -1716,6 +2486,9 @@ impl Inner {
                         heap_pages: executor::DEFAULT_HEAP_PAGES,
                         exec_hint: vm::ExecHint::Oneshot,
                         allow_unresolved_imports: false, // TODO: what is a correct value here?
+                        compiled_module_cache: None,
+                        resource_limits: Default::default(),
+                        allocation_strategy: Default::default(),
                     }) {
                         Ok(w) => w,
                         Err(_) => {
@@ -1777,9 +2550,14 @@ impl Inner {
                     match fmt::write(&mut WriterWithMax(&mut self.logs), format_args!("{req}")) {
                         Ok(()) => {}
                         Err(fmt::Error) => {
+                            let mut prototype = host::HostVm::LogEmit(req).into_prototype();
                             return RuntimeHostVm::Finished(Err(Error {
                                 detail: ErrorDetail::LogsTooLong,
-                                prototype: host::HostVm::LogEmit(req).into_prototype(),
+                                execution_trace: ExecutionTrace::from_parts(
+                                    prototype.take_execution_trace(),
+                                    self.storage_accesses,
+                                ),
+                                prototype,
                             }));
                         }
                     }
@@ -1803,6 +2581,42 @@ impl Inner {
                         OffchainSubmitTransaction { inner: self },
                     ));
                 }
+                host::HostVm::OffchainHttpRequestStart(req) => {
+                    self.vm = req.into();
+                    return RuntimeHostVm::Offchain(OffchainContext::HttpRequestStart(
+                        OffchainHttpRequestStart { inner: self },
+                    ));
+                }
+                host::HostVm::OffchainHttpRequestAddHeader(req) => {
+                    self.vm = req.into();
+                    return RuntimeHostVm::Offchain(OffchainContext::HttpRequestAddHeader(
+                        OffchainHttpRequestAddHeader { inner: self },
+                    ));
+                }
+                host::HostVm::OffchainHttpRequestWriteBody(req) => {
+                    self.vm = req.into();
+                    return RuntimeHostVm::Offchain(OffchainContext::HttpRequestWriteBody(
+                        OffchainHttpRequestWriteBody { inner: self },
+                    ));
+                }
+                host::HostVm::OffchainHttpResponseWait(req) => {
+                    self.vm = req.into();
+                    return RuntimeHostVm::Offchain(OffchainContext::HttpResponseWait(
+                        OffchainHttpResponseWait { inner: self },
+                    ));
+                }
+                host::HostVm::OffchainHttpResponseHeaders(req) => {
+                    self.vm = req.into();
+                    return RuntimeHostVm::Offchain(OffchainContext::HttpResponseHeaders(
+                        OffchainHttpResponseHeaders { inner: self },
+                    ));
+                }
+                host::HostVm::OffchainHttpResponseReadBody(req) => {
+                    self.vm = req.into();
+                    return RuntimeHostVm::Offchain(OffchainContext::HttpResponseReadBody(
+                        OffchainHttpResponseReadBody { inner: self },
+                    ));
+                }
             }
         }
     }
@@ -76,6 +76,9 @@ fn execute_blocks() {
                 heap_pages,
                 exec_hint: crate::executor::vm::ExecHint::Oneshot,
                 allow_unresolved_imports: false,
+                compiled_module_cache: None,
+                resource_limits: Default::default(),
+                allocation_strategy: Default::default(),
             })
             .unwrap()
         };
@@ -97,6 +100,7 @@ fn execute_blocks() {
             max_log_level: 3,
             storage_main_trie_changes: Default::default(),
             calculate_trie_changes: false,
+            execution_tracing: None,
             parameter: {
                 // Block header + number of extrinsics + extrinsics
                 let encoded_body_len =
@@ -179,7 +183,11 @@ fn execute_blocks() {
 
                     execution = req.inject_key(next_key.map(|nk| nk.into_iter()));
                 }
-                RuntimeHostVm::OffchainStorageSet(_) | RuntimeHostVm::Offchain(_) => {
+                RuntimeHostVm::OffchainStorageSet(_)
+                | RuntimeHostVm::Offchain(_)
+                | RuntimeHostVm::KeystorePublicKeys(_)
+                | RuntimeHostVm::KeystoreGenerate(_)
+                | RuntimeHostVm::KeystoreSign(_) => {
                     unimplemented!()
                 }
             }
@@ -62,6 +62,30 @@
 //! The code in this module, however, doesn't allow any of the feature that were added post-MVP.
 //! Trying to use WebAssembly code that uses one of these features will result in an error.
 //!
+//! # Compiled modules cache
+//!
+//! Compiling WebAssembly code ahead of time can take a long time. In order to avoid compiling
+//! the same code again, for example after a restart, a [`CompiledModuleCache`] can be passed
+//! through [`Config::compiled_module_cache`]. The compiled code is then loaded from that cache
+//! if possible, and stored in it after having been compiled.
+//!
+//! The data loaded from the cache is turned into machine code and executed without being
+//! validated. A checksum only detects accidental corruption, such as a truncated entry, and the
+//! code is compiled again if the data is missing, corrupted in such a way, or was produced by an
+//! incompatible compiler. Because data forged by an attacker leads to undefined behavior,
+//! [`CompiledModuleCache`] is an `unsafe` trait.
+//!
+//! # Resource limits
+//!
+//! By default, the WebAssembly code can run for as long as it wants and grow its memory up to
+//! the maximum that it declares. When executing untrusted code, [`Config::resource_limits`] can
+//! be used in order to bound the CPU time and memory that each function call can use.
+//!
+//! The CPU time is measured in "fuel". Executing a WebAssembly instruction consumes fuel, and
+//! the execution stops with [`ExecOutcome::OutOfFuel`] once the budget has been consumed. The
+//! amount of fuel consumed by a given execution is deterministic, but isn't the same between
+//! the different execution engines.
+//!
 
 mod interpreter;
 
@@ -97,6 +121,84 @@ pub struct Config<'a> {
     /// return an error if the import can't be resolved. When the VM calls one of these functions,
     /// this number will be returned back in order for the user to know how to handle the call.
     pub symbols: &'a mut dyn FnMut(&str, &str, &Signature) -> Result<usize, ()>,
+
+    /// Cache where the compiled code is loaded from and stored to. Ignored if the module isn't
+    /// compiled ahead of time.
+    ///
+    /// See [the module-level documentation](..) for more information.
+    pub compiled_module_cache: Option<CompiledModuleCacheConfig<'a>>,
+
+    /// Limits on the resources that each function call can use.
+    ///
+    /// See [the module-level documentation](..) for more information.
+    pub resource_limits: ResourceLimits,
+}
+
+/// See [`Config::resource_limits`].
+#[derive(Debug, Copy, Clone, Default, PartialEq, Eq)]
+pub struct ResourceLimits {
+    /// Maximum amount of fuel that each function call can consume. The execution stops with
+    /// [`ExecOutcome::OutOfFuel`] if it consumes more than this amount.
+    ///
+    /// `None` if there is no limit. Fuel metering slows down the execution, and is only
+    /// enabled if this field is `Some`.
+    pub fuel: Option<u64>,
+
+    /// Maximum number of pages that the memory can have. Attempts by the WebAssembly code to
+    /// grow its memory beyond this size fail.
+    ///
+    /// `None` if there is no limit other than the maximum that the module declares.
+    pub max_memory_pages: Option<HeapPages>,
+}
+
+/// See [`Config::compiled_module_cache`].
+#[derive(Copy, Clone)]
+pub struct CompiledModuleCacheConfig<'a> {
+    /// Storage of the compiled modules.
+    pub cache: &'a dyn CompiledModuleCache,
+
+    /// Number of heap pages the module is going to be used with. Part of the
+    /// [`CompiledModuleKey`].
+    pub heap_pages: HeapPages,
+}
+
+/// Storage for compiled WebAssembly modules, typically on disk.
+///
+/// See [the module-level documentation](..).
+///
+/// # Safety
+///
+/// The data returned by [`CompiledModuleCache::load`] is deserialized into machine code that is
+/// then executed, without any validation. Implementations must only ever return data that has
+/// been produced by wasmtime and passed to [`CompiledModuleCache::store`] by smoldot. A storage
+/// that can be modified by anything else, such as a directory that untrusted users can write
+/// to, doesn't uphold this requirement. Returning any other data is undefined behavior.
+pub unsafe trait CompiledModuleCache: Send + Sync {
+    /// Returns the data that was last passed to [`CompiledModuleCache::store`] with the given
+    /// key, or `None` if there is no such entry.
+    ///
+    /// See the safety section of the documentation of the trait.
+    fn load(&self, key: &CompiledModuleKey) -> Option<Vec<u8>>;
+
+    /// Stores the given data, overwriting the previous entry with the same key, if any.
+    ///
+    /// Failing to store the data isn't considered as an error, and implementations are
+    /// expected to silently ignore failures.
+    fn store(&self, key: &CompiledModuleKey, data: &[u8]);
+}
+
+/// Key of an entry of a [`CompiledModuleCache`].
+#[derive(Debug, Copy, Clone, PartialEq, Eq, Hash)]
+pub struct CompiledModuleKey {
+    /// BLAKE2 hash of the WebAssembly bytecode.
+    pub code_hash: [u8; 32],
+
+    /// Hash of the version and configuration of the compiler, and of the target it compiles
+    /// for.
+    pub engine_hash: u64,
+
+    /// See [`CompiledModuleCacheConfig::heap_pages`].
+    pub heap_pages: HeapPages,
 }
 
 /// Virtual machine ready to start executing a function.
@@ -145,9 +247,14 @@ impl VirtualMachinePrototype {
                     ),
                     feature = "wasmtime"
                 ))]
-                ExecHint::CompileAheadOfTime => VirtualMachinePrototypeInner::Jit(
-                    jit::JitPrototype::new(config.module_bytes, config.symbols)?,
-                ),
+                ExecHint::CompileAheadOfTime => {
+                    VirtualMachinePrototypeInner::Jit(jit::JitPrototype::new(
+                        config.module_bytes,
+                        config.symbols,
+                        config.compiled_module_cache,
+                        config.resource_limits,
+                    )?)
+                }
                 #[cfg(not(all(
                     any(
                         all(
@@ -160,13 +267,18 @@ impl VirtualMachinePrototype {
                     feature = "wasmtime"
                 )))]
                 ExecHint::CompileAheadOfTime => VirtualMachinePrototypeInner::Interpreter(
-                    interpreter::InterpreterPrototype::new(config.module_bytes, config.symbols)?,
+                    interpreter::InterpreterPrototype::new(
+                        config.module_bytes,
+                        config.symbols,
+                        config.resource_limits,
+                    )?,
                 ),
                 ExecHint::Oneshot | ExecHint::Untrusted | ExecHint::ForceWasmi => {
                     VirtualMachinePrototypeInner::Interpreter(
                         interpreter::InterpreterPrototype::new(
                             config.module_bytes,
                             config.symbols,
+                            config.resource_limits,
                         )?,
                     )
                 }
@@ -182,9 +294,14 @@ impl VirtualMachinePrototype {
                     ),
                     feature = "wasmtime"
                 ))]
-                ExecHint::ForceWasmtime => VirtualMachinePrototypeInner::Jit(
-                    jit::JitPrototype::new(config.module_bytes, config.symbols)?,
-                ),
+                ExecHint::ForceWasmtime => {
+                    VirtualMachinePrototypeInner::Jit(jit::JitPrototype::new(
+                        config.module_bytes,
+                        config.symbols,
+                        config.compiled_module_cache,
+                        config.resource_limits,
+                    )?)
+                }
             },
         })
     }
@@ -211,7 +328,8 @@ impl VirtualMachinePrototype {
         }
     }
 
-    /// Returns the maximum number of pages that the memory can have.
+    /// Returns the maximum number of pages that the memory can have, taking into account
+    /// [`ResourceLimits::max_memory_pages`].
     ///
     /// `None` if there is no limit.
     pub fn memory_max_pages(&self) -> Option<HeapPages> {
@@ -1156,6 +1274,13 @@ pub enum ExecOutcome {
         /// Parameters of the function call.
         params: Vec<WasmValue>,
     },
+
+    /// The execution has consumed all the fuel it was allowed to consume.
+    /// See [`ResourceLimits::fuel`].
+    ///
+    /// The state machine is now in a poisoned state, and calling [`run`](VirtualMachine::run)
+    /// will return [`RunErr::Poisoned`].
+    OutOfFuel,
 }
 
 /// Opaque error that happened during execution, such as an `unreachable` instruction.
@@ -18,8 +18,8 @@
 //! Implements the API documented [in the parent module](..).
 
 use super::{
-    ExecOutcome, GlobalValueErr, HeapPages, NewErr, OutOfBoundsError, RunErr, Signature, StartErr,
-    Trap, ValueType, WasmValue,
+    ExecOutcome, GlobalValueErr, HeapPages, NewErr, OutOfBoundsError, ResourceLimits, RunErr,
+    Signature, StartErr, Trap, ValueType, WasmValue,
 };
 
 use alloc::{borrow::ToOwned as _, string::ToString as _, sync::Arc, vec::Vec};
@@ -31,7 +31,7 @@ pub struct InterpreterPrototype {
     base_components: BaseComponents,
 
     // TODO: doc
-    store: wasmi::Store<()>,
+    store: wasmi::Store<wasmi::StoreLimits>,
 
     /// An instance of the module.
     instance: wasmi::Instance,
@@ -46,6 +46,9 @@ struct BaseComponents {
     /// For each import of the module, either `None` if not a function, or `Some` containing the
     /// `usize` of that function.
     resolved_imports: Vec<Option<usize>>,
+
+    /// Limits passed at initialization. Applied to each new [`wasmi::Store`].
+    resource_limits: ResourceLimits,
 }
 
 impl InterpreterPrototype {
@@ -53,6 +56,7 @@ impl InterpreterPrototype {
     pub fn new(
         module_bytes: &[u8],
         symbols: &mut dyn FnMut(&str, &str, &Signature) -> Result<usize, ()>,
+        resource_limits: ResourceLimits,
     ) -> Result<Self, NewErr> {
         let engine = {
             let mut config = wasmi::Config::default();
@@ -67,6 +71,8 @@ impl InterpreterPrototype {
             config.wasm_saturating_float_to_int(false);
             config.wasm_tail_call(false);
 
+            config.consume_fuel(resource_limits.fuel.is_some());
+
             wasmi::Engine::new(&config)
         };
 
@@ -107,13 +113,30 @@ impl InterpreterPrototype {
         Self::from_base_components(BaseComponents {
             module: Arc::new(module),
             resolved_imports,
+            resource_limits,
         })
     }
 
     fn from_base_components(base_components: BaseComponents) -> Result<Self, NewErr> {
-        let mut store = wasmi::Store::new(base_components.module.engine(), ());
+        let mut store = wasmi::Store::new(base_components.module.engine(), {
+            let mut limits = wasmi::StoreLimitsBuilder::new();
+            if let Some(max_memory_pages) = base_components.resource_limits.max_memory_pages {
+                let max_memory_bytes = u64::from(u32::from(max_memory_pages)) * 64 * 1024;
+                limits =
+                    limits.memory_size(usize::try_from(max_memory_bytes).unwrap_or(usize::MAX));
+            }
+            limits.build()
+        });
+        store.limiter(|limits| limits);
+
+        // Each store is used for at most one function call. The fuel of the store is thus the
+        // fuel of the call.
+        if let Some(fuel) = base_components.resource_limits.fuel {
+            // Can only fail if fuel metering is disabled, which isn't the case.
+            store.add_fuel(fuel).unwrap();
+        }
 
-        let mut linker = wasmi::Linker::<()>::new(base_components.module.engine());
+        let mut linker = wasmi::Linker::<wasmi::StoreLimits>::new(base_components.module.engine());
         let mut import_memory = None;
 
         for (module_import, resolved_function) in base_components
@@ -220,10 +243,18 @@ impl InterpreterPrototype {
 
     /// See [`super::VirtualMachinePrototype::memory_max_pages`].
     pub fn memory_max_pages(&self) -> Option<HeapPages> {
-        self.memory
+        let declared = self
+            .memory
             .ty(&self.store)
             .maximum_pages()
-            .map(|p| HeapPages(u32::from(p)))
+            .map(|p| HeapPages(u32::from(p)));
+        match (
+            declared,
+            self.base_components.resource_limits.max_memory_pages,
+        ) {
+            (Some(a), Some(b)) => Some(a.min(b)),
+            (a, b) => a.or(b),
+        }
     }
 
     /// See [`super::VirtualMachinePrototype::prepare`].
@@ -242,6 +273,7 @@ impl Clone for InterpreterPrototype {
         InterpreterPrototype::from_base_components(BaseComponents {
             module: self.base_components.module.clone(),
             resolved_imports: self.base_components.resolved_imports.clone(),
+            resource_limits: self.base_components.resource_limits,
         })
         .unwrap()
     }
@@ -433,7 +465,7 @@ pub struct Interpreter {
     base_components: BaseComponents,
 
     // TODO: doc
-    store: wasmi::Store<()>,
+    store: wasmi::Store<wasmi::StoreLimits>,
 
     /// Memory of the module instantiation.
     memory: wasmi::Memory,
@@ -526,6 +558,11 @@ impl Interpreter {
                 self.execution = Some(Execution::Started(next));
                 Ok(outcome)
             }
+            Err(wasmi::Error::Trap(trap))
+                if matches!(trap.trap_code(), Some(wasmi::core::TrapCode::OutOfFuel)) =>
+            {
+                Ok(ExecOutcome::OutOfFuel)
+            }
             Err(err) => Ok(ExecOutcome::Finished {
                 return_value: Err(Trap(err.to_string())),
             }),
@@ -18,13 +18,15 @@
 //! Implements the API documented [in the parent module](..).
 
 use super::{
-    ExecOutcome, GlobalValueErr, HeapPages, NewErr, OutOfBoundsError, RunErr, Signature, StartErr,
-    Trap, ValueType, WasmValue,
+    CompiledModuleCacheConfig, CompiledModuleKey, ExecOutcome, GlobalValueErr, HeapPages, NewErr,
+    OutOfBoundsError, ResourceLimits, RunErr, Signature, StartErr, Trap, ValueType, WasmValue,
 };
 
 use alloc::{boxed::Box, sync::Arc, vec::Vec};
 use core::{
-    fmt, future, mem,
+    fmt, future,
+    hash::{Hash as _, Hasher as _},
+    mem,
     pin::Pin,
     slice,
     task::{Context, Poll, Waker},
@@ -38,7 +40,7 @@ pub struct JitPrototype {
     /// Base components that can be used to recreate a prototype later if desired.
     base_components: BaseComponents,
 
-    store: wasmtime::Store<()>,
+    store: wasmtime::Store<wasmtime::StoreLimits>,
 
     /// Instantiated Wasm VM.
     instance: wasmtime::Instance,
@@ -59,6 +61,9 @@ struct BaseComponents {
     /// For each import of the module, either `None` if not a function, or `Some` containing the
     /// `usize` of that function.
     resolved_imports: Vec<Option<usize>>,
+
+    /// Limits passed at initialization. Applied to each new [`wasmtime::Store`].
+    resource_limits: ResourceLimits,
 }
 
 impl JitPrototype {
@@ -66,6 +71,8 @@ impl JitPrototype {
     pub fn new(
         module_bytes: &[u8],
         symbols: &mut dyn FnMut(&str, &str, &Signature) -> Result<usize, ()>,
+        compiled_module_cache: Option<CompiledModuleCacheConfig>,
+        resource_limits: ResourceLimits,
     ) -> Result<Self, NewErr> {
         let mut config = wasmtime::Config::new();
         config.cranelift_nan_canonicalization(true);
@@ -89,11 +96,16 @@ impl JitPrototype {
         config.wasm_multi_memory(false);
         config.wasm_memory64(false);
 
+        config.consume_fuel(resource_limits.fuel.is_some());
+
         let engine =
             wasmtime::Engine::new(&config).map_err(|err| NewErr::InvalidWasm(err.to_string()))?;
 
-        let module = wasmtime::Module::from_binary(&engine, module_bytes)
-            .map_err(|err| NewErr::InvalidWasm(err.to_string()))?;
+        let module = match compiled_module_cache {
+            Some(cache_config) => compile_with_cache(&engine, module_bytes, cache_config)?,
+            None => wasmtime::Module::from_binary(&engine, module_bytes)
+                .map_err(|err| NewErr::InvalidWasm(err.to_string()))?,
+        };
 
         // Building the list of imports that the Wasm VM is able to use.
         let resolved_imports = {
@@ -135,11 +147,28 @@ impl JitPrototype {
         Self::from_base_components(BaseComponents {
             module,
             resolved_imports,
+            resource_limits,
         })
     }
 
     fn from_base_components(base_components: BaseComponents) -> Result<Self, NewErr> {
-        let mut store = wasmtime::Store::new(base_components.module.engine(), ());
+        let mut store = wasmtime::Store::new(base_components.module.engine(), {
+            let mut limits = wasmtime::StoreLimitsBuilder::new();
+            if let Some(max_memory_pages) = base_components.resource_limits.max_memory_pages {
+                let max_memory_bytes = u64::from(u32::from(max_memory_pages)) * 64 * 1024;
+                limits =
+                    limits.memory_size(usize::try_from(max_memory_bytes).unwrap_or(usize::MAX));
+            }
+            limits.build()
+        });
+        store.limiter(|limits| limits);
+
+        // Each store is used for at most one function call. The fuel of the store is thus the
+        // fuel of the call.
+        if let Some(fuel) = base_components.resource_limits.fuel {
+            // Can only fail if fuel metering is disabled, which isn't the case.
+            store.add_fuel(fuel).unwrap();
+        }
 
         let mut imported_memory = None;
         let shared = Arc::new(Mutex::new(Shared::ExecutingStart));
@@ -345,11 +374,19 @@ impl JitPrototype {
 
     /// See [`super::VirtualMachinePrototype::memory_max_pages`].
     pub fn memory_max_pages(&self) -> Option<HeapPages> {
-        let num = self.memory.ty(&self.store).maximum()?;
-        match u32::try_from(num) {
-            Ok(n) => Some(HeapPages::new(n)),
-            // If `num` doesn't fit in a `u32`, we return `None` to mean "infinite".
-            Err(_) => None,
+        // If the declared maximum doesn't fit in a `u32`, it is treated as "infinite".
+        let declared = self
+            .memory
+            .ty(&self.store)
+            .maximum()
+            .and_then(|num| u32::try_from(num).ok())
+            .map(HeapPages::new);
+        match (
+            declared,
+            self.base_components.resource_limits.max_memory_pages,
+        ) {
+            (Some(a), Some(b)) => Some(a.min(b)),
+            (a, b) => a.or(b),
         }
     }
 
@@ -369,6 +406,7 @@ impl Clone for JitPrototype {
         JitPrototype::from_base_components(BaseComponents {
             module: self.base_components.module.clone(),
             resolved_imports: self.base_components.resolved_imports.clone(),
+            resource_limits: self.base_components.resource_limits,
         })
         .unwrap()
     }
@@ -380,6 +418,56 @@ impl fmt::Debug for JitPrototype {
     }
 }
 
+/// Loads the compiled module from the cache, or compiles it and stores the outcome in the cache
+/// if the cache doesn't contain a valid entry.
+///
+/// The entries of the cache consist of a BLAKE2 checksum followed with the output of
+/// [`wasmtime::Module::serialize`].
+fn compile_with_cache(
+    engine: &wasmtime::Engine,
+    module_bytes: &[u8],
+    cache_config: CompiledModuleCacheConfig,
+) -> Result<wasmtime::Module, NewErr> {
+    let key = CompiledModuleKey {
+        code_hash: blake2_256(module_bytes),
+        engine_hash: {
+            let mut hasher = fnv::FnvHasher::default();
+            engine.precompile_compatibility_hash().hash(&mut hasher);
+            hasher.finish()
+        },
+        heap_pages: cache_config.heap_pages,
+    };
+
+    if let Some(entry) = cache_config.cache.load(&key) {
+        if entry.len() >= 32 && entry[..32] == blake2_256(&entry[32..]) {
+            // SAFETY: `deserialize` is unsafe because it trusts the data to have been produced
+            // by `serialize`. `CompiledModuleCache` is an unsafe trait whose implementations
+            // guarantee that they only return entries written by this function. The checksum
+            // merely detects accidental corruption, and `deserialize` itself returns an error if
+            // the data was produced by an incompatible engine.
+            if let Ok(module) = unsafe { wasmtime::Module::deserialize(engine, &entry[32..]) } {
+                return Ok(module);
+            }
+        }
+    }
+
+    let module = wasmtime::Module::from_binary(engine, module_bytes)
+        .map_err(|err| NewErr::InvalidWasm(err.to_string()))?;
+
+    if let Ok(serialized) = module.serialize() {
+        let mut entry = Vec::with_capacity(32 + serialized.len());
+        entry.extend_from_slice(&blake2_256(&serialized));
+        entry.extend_from_slice(&serialized);
+        cache_config.cache.store(&key, &entry);
+    }
+
+    Ok(module)
+}
+
+fn blake2_256(data: &[u8]) -> [u8; 32] {
+    <[u8; 32]>::try_from(blake2_rfc::blake2b::blake2b(32, &[], data).as_bytes()).unwrap()
+}
+
 /// See [`super::Prepare`].
 pub struct Prepare {
     inner: JitPrototype,
@@ -602,14 +690,14 @@ enum JitInner {
 
     /// Execution has not started yet.
     NotStarted {
-        store: wasmtime::Store<()>,
+        store: wasmtime::Store<wasmtime::StoreLimits>,
         function_to_call: wasmtime::Func,
         params: Vec<wasmtime::Val>,
     },
     /// `Future` that drives the execution. Contains an invocation of `wasmtime::Func::call_async`.
-    Executing(BoxFuture<(wasmtime::Store<()>, ExecOutcomeValue)>),
+    Executing(BoxFuture<(wasmtime::Store<wasmtime::StoreLimits>, ExecOutcomeValue)>),
     /// Execution has finished because the future has returned `Poll::Ready` in the past.
-    Done(wasmtime::Store<()>),
+    Done(wasmtime::Store<wasmtime::StoreLimits>),
 }
 
 type BoxFuture<T> = Pin<Box<dyn future::Future<Output = T> + Send>>;
@@ -738,6 +826,12 @@ impl Jit {
                     return_value: Ok(val),
                 })
             }
+            Poll::Ready((store, Err(err)))
+                if err.downcast_ref::<wasmtime::Trap>() == Some(&wasmtime::Trap::OutOfFuel) =>
+            {
+                self.inner = JitInner::Done(store);
+                Ok(ExecOutcome::OutOfFuel)
+            }
             Poll::Ready((store, Err(err))) => {
                 self.inner = JitInner::Done(store);
                 Ok(ExecOutcome::Finished {
@@ -887,10 +981,17 @@ impl Jit {
                         } else {
                             1 + u64::try_from((memory.1 - 1) / (64 * 1024)).unwrap()
                         };
+                        let max_pages = self
+                            .base_components
+                            .resource_limits
+                            .max_memory_pages
+                            .map(|max| u64::from(u32::from(max)));
                         if self
                             .memory_type
                             .maximum()
-                            .map_or(false, |max| current_pages + additional > max)
+                            .into_iter()
+                            .chain(max_pages)
+                            .any(|max| current_pages + additional > max)
                         {
                             // Put everything back as it was.
                             *shared_lock = Shared::WithinFunctionCall {
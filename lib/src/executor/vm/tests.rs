@@ -34,6 +34,8 @@ fn basic_seems_to_work() {
         let prototype = super::VirtualMachinePrototype::new(super::Config {
             module_bytes: &include_bytes!("./test-polkadot-runtime-v9160.wasm")[..],
             exec_hint,
+            compiled_module_cache: None,
+            resource_limits: Default::default(),
             symbols: &mut |_, _, _| Ok(0),
         })
         .unwrap();
@@ -59,6 +61,7 @@ fn basic_seems_to_work() {
                 }) => panic!(),
                 Ok(super::ExecOutcome::Interrupted { id: 0, .. }) => break,
                 Ok(super::ExecOutcome::Interrupted { .. }) => panic!(),
+                Ok(super::ExecOutcome::OutOfFuel) => panic!(),
                 Err(_) => panic!(),
             }
         }
@@ -72,6 +75,8 @@ fn wat_not_accepted() {
             super::VirtualMachinePrototype::new(super::Config {
                 module_bytes: b"(module)",
                 exec_hint,
+                compiled_module_cache: None,
+                resource_limits: Default::default(),
                 symbols: &mut |_, _, _| Ok(0)
             }),
             Err(super::NewErr::InvalidWasm(_))
@@ -90,6 +95,8 @@ fn out_of_memory_access() {
         assert!(super::VirtualMachinePrototype::new(super::Config {
             module_bytes: &module_bytes[..],
             exec_hint,
+            compiled_module_cache: None,
+            resource_limits: Default::default(),
             symbols: &mut |_, _, _| Ok(0)
         })
         .is_err());
@@ -107,6 +114,8 @@ fn has_start_function() {
         assert!(super::VirtualMachinePrototype::new(super::Config {
             module_bytes: &module_bytes[..],
             exec_hint,
+            compiled_module_cache: None,
+            resource_limits: Default::default(),
             symbols: &mut |_, _, _| Ok(0)
         })
         .is_err());
@@ -125,6 +134,8 @@ fn unsupported_type() {
         assert!(super::VirtualMachinePrototype::new(super::Config {
             module_bytes: &module_bytes[..],
             exec_hint,
+            compiled_module_cache: None,
+            resource_limits: Default::default(),
             symbols: &mut |_, _, _| Ok(0)
         })
         .is_err());
@@ -152,6 +163,8 @@ fn basic_host_function_return_value_works() {
         let prototype = super::VirtualMachinePrototype::new(super::Config {
             module_bytes: &module_bytes,
             exec_hint,
+            compiled_module_cache: None,
+            resource_limits: Default::default(),
             symbols: &mut |_, _, _| Ok(0),
         })
         .unwrap();
@@ -175,6 +188,7 @@ fn basic_host_function_return_value_works() {
                     resume_value = Some(super::WasmValue::I32(3));
                 }
                 Ok(super::ExecOutcome::Interrupted { .. }) => panic!(),
+                Ok(super::ExecOutcome::OutOfFuel) => panic!(),
                 Err(_) => panic!(),
             }
         }
@@ -200,6 +214,8 @@ fn no_memory() {
             super::VirtualMachinePrototype::new(super::Config {
                 module_bytes: &module_bytes,
                 exec_hint,
+                compiled_module_cache: None,
+                resource_limits: Default::default(),
                 symbols: &mut |_, _, _| Ok(0)
             }),
             Err(super::NewErr::NoMemory)
@@ -227,6 +243,8 @@ fn memory_misnamed() {
             super::VirtualMachinePrototype::new(super::Config {
                 module_bytes: &module_bytes,
                 exec_hint,
+                compiled_module_cache: None,
+                resource_limits: Default::default(),
                 symbols: &mut |_, _, _| Ok(0)
             }),
             Err(super::NewErr::MemoryNotNamedMemory)
@@ -250,6 +268,8 @@ fn memory_isnt_memory() {
             super::VirtualMachinePrototype::new(super::Config {
                 module_bytes: &module_bytes,
                 exec_hint,
+                compiled_module_cache: None,
+                resource_limits: Default::default(),
                 symbols: &mut |_, _, _| Ok(0)
             }),
             Err(super::NewErr::MemoryIsntMemory)
@@ -281,6 +301,8 @@ fn two_memories() {
             super::VirtualMachinePrototype::new(super::Config {
                 module_bytes: &module_bytes,
                 exec_hint,
+                compiled_module_cache: None,
+                resource_limits: Default::default(),
                 symbols: &mut |_, _, _| Ok(0)
             }),
             Err(super::NewErr::InvalidWasm(_) | super::NewErr::TwoMemories)
@@ -307,6 +329,8 @@ fn exported_memory_works() {
         super::VirtualMachinePrototype::new(super::Config {
             module_bytes: &module_bytes,
             exec_hint,
+            compiled_module_cache: None,
+            resource_limits: Default::default(),
             symbols: &mut |_, _, _| Ok(0),
         })
         .unwrap();
@@ -333,6 +357,8 @@ fn unresolved_function() {
             super::VirtualMachinePrototype::new(super::Config {
                 module_bytes: &module_bytes,
                 exec_hint,
+                compiled_module_cache: None,
+                resource_limits: Default::default(),
                 symbols: &mut |_, _, _| Err(())
             }),
             Err(super::NewErr::UnresolvedFunctionImport { .. })
@@ -358,6 +384,8 @@ fn unsupported_signature() {
             super::VirtualMachinePrototype::new(super::Config {
                 module_bytes: &module_bytes,
                 exec_hint,
+                compiled_module_cache: None,
+                resource_limits: Default::default(),
                 symbols: &mut |_, _, _| Ok(0)
             }),
             Err(super::NewErr::UnresolvedFunctionImport { .. })
@@ -383,6 +411,8 @@ fn unsupported_import_type() {
             super::VirtualMachinePrototype::new(super::Config {
                 module_bytes: &module_bytes,
                 exec_hint,
+                compiled_module_cache: None,
+                resource_limits: Default::default(),
                 symbols: &mut |_, _, _| Ok(0)
             }),
             Err(super::NewErr::ImportTypeNotSupported)
@@ -409,6 +439,8 @@ fn start_function_forbidden() {
             super::VirtualMachinePrototype::new(super::Config {
                 module_bytes: &module_bytes,
                 exec_hint,
+                compiled_module_cache: None,
+                resource_limits: Default::default(),
                 symbols: &mut |_, _, _| Ok(0)
             }),
             Err(super::NewErr::StartFunctionNotSupported) | Ok(_)
@@ -432,6 +464,8 @@ fn max_memory_pages() {
         let prototype = super::VirtualMachinePrototype::new(super::Config {
             module_bytes: &module_bytes,
             exec_hint,
+            compiled_module_cache: None,
+            resource_limits: Default::default(),
             symbols: &mut |_, _, _| Ok(0),
         })
         .unwrap();
@@ -458,6 +492,8 @@ fn get_global() {
         let mut prototype = super::VirtualMachinePrototype::new(super::Config {
             module_bytes: &module_bytes,
             exec_hint,
+            compiled_module_cache: None,
+            resource_limits: Default::default(),
             symbols: &mut |_, _, _| Ok(0),
         })
         .unwrap();
@@ -480,6 +516,8 @@ fn global_doesntexist() {
         let mut prototype = super::VirtualMachinePrototype::new(super::Config {
             module_bytes: &module_bytes,
             exec_hint,
+            compiled_module_cache: None,
+            resource_limits: Default::default(),
             symbols: &mut |_, _, _| Ok(0),
         })
         .unwrap();
@@ -506,6 +544,8 @@ fn global_wrong_type() {
         let mut prototype = super::VirtualMachinePrototype::new(super::Config {
             module_bytes: &module_bytes,
             exec_hint,
+            compiled_module_cache: None,
+            resource_limits: Default::default(),
             symbols: &mut |_, _, _| Ok(0),
         })
         .unwrap();
@@ -532,6 +572,8 @@ fn global_isnt_global() {
         let mut prototype = super::VirtualMachinePrototype::new(super::Config {
             module_bytes: &module_bytes,
             exec_hint,
+            compiled_module_cache: None,
+            resource_limits: Default::default(),
             symbols: &mut |_, _, _| Ok(0),
         })
         .unwrap();
@@ -558,6 +600,8 @@ fn global_negative_value_works() {
         let mut prototype = super::VirtualMachinePrototype::new(super::Config {
             module_bytes: &module_bytes,
             exec_hint,
+            compiled_module_cache: None,
+            resource_limits: Default::default(),
             symbols: &mut |_, _, _| Ok(0),
         })
         .unwrap();
@@ -581,6 +625,8 @@ fn call_non_existing_function() {
         let prototype = super::VirtualMachinePrototype::new(super::Config {
             module_bytes: &module_bytes,
             exec_hint,
+            compiled_module_cache: None,
+            resource_limits: Default::default(),
             symbols: &mut |_, _, _| Ok(0),
         })
         .unwrap();
@@ -607,6 +653,8 @@ fn call_signature_not_supported() {
         let prototype = super::VirtualMachinePrototype::new(super::Config {
             module_bytes: &module_bytes,
             exec_hint,
+            compiled_module_cache: None,
+            resource_limits: Default::default(),
             symbols: &mut |_, _, _| Ok(0),
         })
         .unwrap();
@@ -633,6 +681,8 @@ fn bad_params_types() {
         let prototype = super::VirtualMachinePrototype::new(super::Config {
             module_bytes: &module_bytes,
             exec_hint,
+            compiled_module_cache: None,
+            resource_limits: Default::default(),
             symbols: &mut |_, _, _| Ok(0),
         })
         .unwrap();
@@ -659,6 +709,8 @@ fn try_to_call_global() {
         let prototype = super::VirtualMachinePrototype::new(super::Config {
             module_bytes: &module_bytes,
             exec_hint,
+            compiled_module_cache: None,
+            resource_limits: Default::default(),
             symbols: &mut |_, _, _| Ok(0),
         })
         .unwrap();
@@ -688,6 +740,8 @@ fn wrong_type_provided_initially() {
         let prototype = super::VirtualMachinePrototype::new(super::Config {
             module_bytes: &module_bytes,
             exec_hint,
+            compiled_module_cache: None,
+            resource_limits: Default::default(),
             symbols: &mut |_, _, _| Ok(0),
         })
         .unwrap();
@@ -720,6 +774,8 @@ fn wrong_type_returned_by_host_function_call() {
         let prototype = super::VirtualMachinePrototype::new(super::Config {
             module_bytes: &module_bytes,
             exec_hint,
+            compiled_module_cache: None,
+            resource_limits: Default::default(),
             symbols: &mut |_, _, _| Ok(0),
         })
         .unwrap();
@@ -752,6 +808,8 @@ fn memory_min_specified_in_wasm() {
         let prototype = super::VirtualMachinePrototype::new(super::Config {
             module_bytes: &module_bytes,
             exec_hint,
+            compiled_module_cache: None,
+            resource_limits: Default::default(),
             symbols: &mut |_, _, _| Ok(0),
         })
         .unwrap();
@@ -776,6 +834,8 @@ fn memory_grow_works() {
         let prototype = super::VirtualMachinePrototype::new(super::Config {
             module_bytes: &module_bytes,
             exec_hint,
+            compiled_module_cache: None,
+            resource_limits: Default::default(),
             symbols: &mut |_, _, _| Ok(0),
         })
         .unwrap();
@@ -802,6 +862,8 @@ fn memory_grow_detects_limit() {
         let prototype = super::VirtualMachinePrototype::new(super::Config {
             module_bytes: &module_bytes,
             exec_hint,
+            compiled_module_cache: None,
+            resource_limits: Default::default(),
             symbols: &mut |_, _, _| Ok(0),
         })
         .unwrap();
@@ -830,6 +892,8 @@ fn memory_grow_detects_limit_within_host_function() {
         let prototype = super::VirtualMachinePrototype::new(super::Config {
             module_bytes: &module_bytes,
             exec_hint,
+            compiled_module_cache: None,
+            resource_limits: Default::default(),
             symbols: &mut |_, _, _| Ok(0),
         })
         .unwrap();
@@ -854,6 +918,7 @@ fn memory_grow_detects_limit_within_host_function() {
                     resume_value = Some(super::WasmValue::I32(3));
                 }
                 Ok(super::ExecOutcome::Interrupted { .. }) => panic!(),
+                Ok(super::ExecOutcome::OutOfFuel) => panic!(),
                 Err(_) => panic!(),
             }
         }
@@ -882,6 +947,8 @@ fn globals_reinitialized_after_reset() {
         let mut prototype = super::VirtualMachinePrototype::new(super::Config {
             module_bytes: &module_bytes,
             exec_hint,
+            compiled_module_cache: None,
+            resource_limits: Default::default(),
             symbols: &mut |_, _, _| Ok(0),
         })
         .unwrap();
@@ -916,6 +983,8 @@ fn memory_zeroed_after_reset() {
         let prototype = super::VirtualMachinePrototype::new(super::Config {
             module_bytes: &module_bytes,
             exec_hint,
+            compiled_module_cache: None,
+            resource_limits: Default::default(),
             symbols: &mut |_, _, _| Ok(0),
         })
         .unwrap();
@@ -964,6 +1033,8 @@ fn memory_zeroed_after_prepare() {
         let prototype = super::VirtualMachinePrototype::new(super::Config {
             module_bytes: &module_bytes,
             exec_hint,
+            compiled_module_cache: None,
+            resource_limits: Default::default(),
             symbols: &mut |_, _, _| Ok(0),
         })
         .unwrap();
@@ -1002,6 +1073,8 @@ fn feature_disabled_signext() {
         assert!(super::VirtualMachinePrototype::new(super::Config {
             module_bytes: &module_bytes,
             exec_hint,
+            compiled_module_cache: None,
+            resource_limits: Default::default(),
             symbols: &mut |_, _, _| Ok(0),
         })
         .is_err());
@@ -1033,6 +1106,8 @@ fn feature_disabled_saturated_float_to_int() {
         assert!(super::VirtualMachinePrototype::new(super::Config {
             module_bytes: &module_bytes,
             exec_hint,
+            compiled_module_cache: None,
+            resource_limits: Default::default(),
             symbols: &mut |_, _, _| Ok(0),
         })
         .is_err());
@@ -1058,6 +1133,8 @@ fn feature_disabled_threads() {
         assert!(super::VirtualMachinePrototype::new(super::Config {
             module_bytes: &module_bytes,
             exec_hint,
+            compiled_module_cache: None,
+            resource_limits: Default::default(),
             symbols: &mut |_, _, _| Ok(0),
         })
         .is_err());
@@ -1080,6 +1157,8 @@ fn feature_disabled_reference_type() {
         assert!(super::VirtualMachinePrototype::new(super::Config {
             module_bytes: &module_bytes,
             exec_hint,
+            compiled_module_cache: None,
+            resource_limits: Default::default(),
             symbols: &mut |_, _, _| Ok(0),
         })
         .is_err());
@@ -1105,6 +1184,8 @@ fn feature_disabled_bulk_memory() {
         assert!(super::VirtualMachinePrototype::new(super::Config {
             module_bytes: &module_bytes,
             exec_hint,
+            compiled_module_cache: None,
+            resource_limits: Default::default(),
             symbols: &mut |_, _, _| Ok(0),
         })
         .is_err());
@@ -1129,6 +1210,8 @@ fn feature_disabled_multi_value() {
         assert!(super::VirtualMachinePrototype::new(super::Config {
             module_bytes: &module_bytes,
             exec_hint,
+            compiled_module_cache: None,
+            resource_limits: Default::default(),
             symbols: &mut |_, _, _| Ok(0),
         })
         .is_err());
@@ -1150,6 +1233,8 @@ fn feature_disabled_memory64() {
         assert!(super::VirtualMachinePrototype::new(super::Config {
             module_bytes: &module_bytes,
             exec_hint,
+            compiled_module_cache: None,
+            resource_limits: Default::default(),
             symbols: &mut |_, _, _| Ok(0),
         })
         .is_err());
@@ -1178,6 +1263,8 @@ fn feature_disabled_mutable_globals() {
         assert!(super::VirtualMachinePrototype::new(super::Config {
             module_bytes: &module_bytes,
             exec_hint,
+            compiled_module_cache: None,
+            resource_limits: Default::default(),
             symbols: &mut |_, _, _| Ok(0),
         })
         .is_err());
@@ -1213,6 +1300,8 @@ fn feature_disabled_tail_call() {
         assert!(super::VirtualMachinePrototype::new(super::Config {
             module_bytes: &module_bytes,
             exec_hint,
+            compiled_module_cache: None,
+            resource_limits: Default::default(),
             symbols: &mut |_, _, _| Ok(0),
         })
         .is_err());
@@ -1272,12 +1361,199 @@ fn feature_disabled_simd128() {
         assert!(super::VirtualMachinePrototype::new(super::Config {
             module_bytes: &module_bytes,
             exec_hint,
+            compiled_module_cache: None,
+            resource_limits: Default::default(),
             symbols: &mut |_, _, _| Ok(0),
         })
         .is_err());
     }
 }
 
+#[test]
+fn out_of_fuel() {
+    let module_bytes = wat::parse_str(
+        r#"
+    (module
+        (import "env" "memory" (memory $mem 0 4096))
+        (func (export "hello") (result i32)
+            (loop $infinite
+                (br $infinite))
+            (i32.const 5)
+        )
+    )
+    "#,
+    )
+    .unwrap();
+
+    for exec_hint in super::ExecHint::available_engines() {
+        let prototype = super::VirtualMachinePrototype::new(super::Config {
+            module_bytes: &module_bytes,
+            exec_hint,
+            compiled_module_cache: None,
+            resource_limits: super::ResourceLimits {
+                fuel: Some(100_000),
+                max_memory_pages: None,
+            },
+            symbols: &mut |_, _, _| Ok(0),
+        })
+        .unwrap();
+
+        let mut vm = prototype.prepare().start("hello", &[]).unwrap();
+        assert!(matches!(vm.run(None), Ok(super::ExecOutcome::OutOfFuel)));
+        assert!(matches!(vm.run(None), Err(super::RunErr::Poisoned)));
+    }
+}
+
+#[test]
+fn max_memory_pages_resource_limit() {
+    let module_bytes = wat::parse_str(
+        r#"
+    (module
+        (import "env" "memory" (memory $mem 1 4096))
+        (func (export "grow") (param i32) (result i32)
+            (drop (memory.grow (local.get 0)))
+            (memory.grow (i32.const 1))
+        )
+    )
+    "#,
+    )
+    .unwrap();
+
+    for exec_hint in super::ExecHint::available_engines() {
+        let prototype = super::VirtualMachinePrototype::new(super::Config {
+            module_bytes: &module_bytes,
+            exec_hint,
+            compiled_module_cache: None,
+            resource_limits: super::ResourceLimits {
+                fuel: None,
+                max_memory_pages: Some(super::HeapPages::new(16)),
+            },
+            symbols: &mut |_, _, _| Ok(0),
+        })
+        .unwrap();
+        assert_eq!(
+            prototype.memory_max_pages(),
+            Some(super::HeapPages::new(16))
+        );
+
+        // Growing by one page after having grown by the given number of pages succeeds only if
+        // the limit isn't exceeded.
+        for (grow, expected) in [(14, 15), (15, -1)] {
+            let mut vm = prototype
+                .clone()
+                .prepare()
+                .start("grow", &[super::WasmValue::I32(grow)])
+                .unwrap();
+            match vm.run(None) {
+                Ok(super::ExecOutcome::Finished {
+                    return_value: Ok(value),
+                }) => assert_eq!(value, Some(super::WasmValue::I32(expected))),
+                _ => panic!(),
+            }
+        }
+    }
+}
+
+#[test]
+fn compiled_module_cache() {
+    // In-memory cache that keeps track of the number of entries that have been stored.
+    #[derive(Default)]
+    struct Cache {
+        entry: std::sync::Mutex<Option<(super::CompiledModuleKey, Vec<u8>)>>,
+        num_stores: std::sync::atomic::AtomicUsize,
+    }
+
+    // SAFETY: The cache only returns data that was passed to `store`. The test corrupts an entry
+    // below, but in a way that the checksum detects.
+    unsafe impl super::CompiledModuleCache for Cache {
+        fn load(&self, key: &super::CompiledModuleKey) -> Option<Vec<u8>> {
+            match &*self.entry.lock().unwrap() {
+                Some((k, data)) if k == key => Some(data.clone()),
+                _ => None,
+            }
+        }
+
+        fn store(&self, key: &super::CompiledModuleKey, data: &[u8]) {
+            *self.entry.lock().unwrap() = Some((*key, data.to_vec()));
+            self.num_stores
+                .fetch_add(1, std::sync::atomic::Ordering::SeqCst);
+        }
+    }
+
+    let Some(exec_hint) = super::ExecHint::force_wasmtime_if_available() else {
+        return;
+    };
+
+    let module_bytes = wat::parse_str(
+        r#"
+    (module
+        (import "env" "memory" (memory $mem 0 4096))
+        (func (export "hello") (result i32)
+            (i32.const 5)
+        )
+    )
+    "#,
+    )
+    .unwrap();
+
+    let cache = Cache::default();
+    let instantiate_and_run = || {
+        let prototype = super::VirtualMachinePrototype::new(super::Config {
+            module_bytes: &module_bytes,
+            exec_hint,
+            compiled_module_cache: Some(super::CompiledModuleCacheConfig {
+                cache: &cache,
+                heap_pages: super::HeapPages::new(1024),
+            }),
+            resource_limits: Default::default(),
+            symbols: &mut |_, _, _| Ok(0),
+        })
+        .unwrap();
+
+        let mut vm = prototype.prepare().start("hello", &[]).unwrap();
+        match vm.run(None) {
+            Ok(super::ExecOutcome::Finished {
+                return_value: Ok(value),
+            }) => assert_eq!(value, Some(super::WasmValue::I32(5))),
+            _ => panic!(),
+        }
+    };
+
+    // The first compilation populates the cache.
+    instantiate_and_run();
+    assert_eq!(
+        cache.num_stores.load(std::sync::atomic::Ordering::SeqCst),
+        1
+    );
+    let (key, _) = cache.entry.lock().unwrap().clone().unwrap();
+    assert_eq!(key.heap_pages, super::HeapPages::new(1024));
+
+    // The second one loads the module from the cache.
+    instantiate_and_run();
+    assert_eq!(
+        cache.num_stores.load(std::sync::atomic::Ordering::SeqCst),
+        1
+    );
+
+    // A corrupted entry is ignored, and the module is compiled and stored again.
+    {
+        let mut entry = cache.entry.lock().unwrap();
+        let data = &mut entry.as_mut().unwrap().1;
+        let last = data.len() - 1;
+        data[last] ^= 0xff;
+    }
+    instantiate_and_run();
+    assert_eq!(
+        cache.num_stores.load(std::sync::atomic::Ordering::SeqCst),
+        2
+    );
+    instantiate_and_run();
+    assert_eq!(
+        cache.num_stores.load(std::sync::atomic::Ordering::SeqCst),
+        2
+    );
+}
+
 // TODO: check that the extended-const feature is disabled: https://github.com/WebAssembly/extended-const/blob/master/proposals/extended-const/Overview.md
 
 // TODO: test for memory reads and writes, including within host functions
@@ -16,3 +16,4 @@
 // along with this program.  If not, see <http://www.gnu.org/licenses/>.
 
 pub mod commit;
+pub mod voter;
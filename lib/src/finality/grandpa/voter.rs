@@ -0,0 +1,1509 @@
+// Smoldot
+// Copyright (C) 2023  Pierre Krieger
+// SPDX-License-Identifier: GPL-3.0-or-later WITH Classpath-exception-2.0
+
+// This program is free software: you can redistribute it and/or modify
+// it under the terms of the GNU General Public License as published by
+// the Free Software Foundation, either version 3 of the License, or
+// (at your option) any later version.
+
+// This program is distributed in the hope that it will be useful,
+// but WITHOUT ANY WARRANTY; without even the implied warranty of
+// MERCHANTABILITY or FITNESS FOR A PARTICULAR PURPOSE.  See the
+// GNU General Public License for more details.
+
+// You should have received a copy of the GNU General Public License
+// along with this program.  If not, see <http://www.gnu.org/licenses/>.
+
+//! GrandPa voter.
+//!
+//! The GrandPa algorithm is divided in rounds. During each round, the authorities of the current
+//! authorities set first emit a *prevote* for the block they consider as the best, then a
+//! *precommit* for the highest block that has been prevoted for by a super-majority of the
+//! authorities. Once a super-majority of the authorities have precommitted a block (or one of its
+//! descendants), this block is finalized and the next round starts.
+//!
+//! The [`GrandpaVoter`] tracks the votes of the authorities of a single authorities set, detects
+//! authorities that vote twice in the same round (which is called an *equivocation*), and
+//! produces a [`Commit`] every time a block gets finalized. If the local node is itself one of
+//! the authorities, the [`GrandpaVoter`] also indicates when and what to vote.
+//!
+//! # Usage
+//!
+//! The [`GrandpaVoter`] is a pure state machine and doesn't perform any networking, signing, or
+//! timing by itself.
+//!
+//! - Call [`GrandpaVoter::add_block`] for every new non-finalized block, and
+//!   [`GrandpaVoter::set_best_block`] whenever the best block changes.
+//! - Call [`GrandpaVoter::set_finalized_block`] when a block gets finalized, for example after the
+//!   justification found in a [`Commit`] has been verified.
+//! - Call [`GrandpaVoter::inject_vote`] for every vote received from the network.
+//! - Call [`GrandpaVoter::inject_completed_round`] with the precommits found in commit messages
+//!   and catch up responses received from the network.
+//! - Call [`GrandpaVoter::catch_up`] when another authority sends a catch up request, and send
+//!   back the returned [`CatchUp`].
+//! - Call [`GrandpaVoter::next_action`] after any of the above and whenever the moment returned
+//!   by [`GrandpaVoter::wake_up_after`] is reached.
+//!
+//! When the authorities set changes, a new [`GrandpaVoter`] must be built.
+//!
+//! # Catching up
+//!
+//! A voter that is behind the other authorities, for example after a restart, can skip directly
+//! to the round the other authorities are in by calling [`GrandpaVoter::inject_completed_round`].
+//! The precommits passed to this function are verified, and the local authority never votes in
+//! the rounds that are skipped.
+//!
+//! In order to not equivocate after a restart, the API user is expected to persist the round
+//! returned by [`SignVote::round_number`] before the vote is sent out, and to pass a round number
+//! strictly superior to it in [`Config::round_number`].
+//!
+//! # Limitations
+//!
+//! Primary proposals aren't supported.
+
+use crate::header;
+
+use alloc::{collections::BTreeMap, vec::Vec};
+use core::{cmp, iter, mem, ops, time::Duration};
+use hashbrown::{HashMap, HashSet};
+
+/// Configuration for a [`GrandpaVoter`].
+#[derive(Debug, Clone)]
+pub struct Config<TNow> {
+    /// Number of bytes used when encoding/decoding the block number. Influences how various data
+    /// structures should be parsed.
+    pub block_number_bytes: usize,
+
+    /// Identifier of the authorities set.
+    pub set_id: u64,
+
+    /// List of authorities of the authorities set.
+    pub authorities: Vec<header::GrandpaAuthority>,
+
+    /// Ed25519 public key of the local authority, if any. If `None`, or if this key isn't found
+    /// in [`Config::authorities`], the voter only observes the votes of the other authorities.
+    pub local_authority: Option<[u8; 32]>,
+
+    /// Hash of the latest finalized block.
+    pub finalized_block_hash: [u8; 32],
+
+    /// Height of the latest finalized block.
+    pub finalized_block_number: u64,
+
+    /// Number of the round to start with.
+    ///
+    /// If the local authority has already voted in the past for this authorities set, this must
+    /// be strictly superior to the latest round it has voted in. Voting again in the same round
+    /// would otherwise lead to an equivocation.
+    pub round_number: u64,
+
+    /// Expected maximum duration for a message to reach all the other authorities.
+    ///
+    /// Prevotes are emitted twice this duration after the start of a round, and precommits four
+    /// times this duration after the start of a round.
+    pub gossip_duration: Duration,
+
+    /// Time at which the first round starts.
+    pub now: TNow,
+}
+
+/// Kind of a GrandPa vote.
+#[derive(Debug, Copy, Clone, PartialEq, Eq, Hash)]
+pub enum VoteKind {
+    Prevote,
+    Precommit,
+}
+
+/// Vote and its signature.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct SignedVote {
+    /// Round the vote belongs to.
+    pub round_number: u64,
+    /// Identifier of the authorities set the vote belongs to.
+    pub set_id: u64,
+    /// Whether this is a prevote or a precommit.
+    pub kind: VoteKind,
+    /// Hash of the block being voted for.
+    pub target_hash: [u8; 32],
+    /// Height of the block being voted for.
+    pub target_number: u64,
+    /// Ed25519 signature of the payload returned by [`SignVote::to_sign`].
+    pub signature: [u8; 64],
+    /// Authority that signed the vote.
+    pub authority_public_key: [u8; 32],
+}
+
+/// Precommit found in a [`Commit`].
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct SignedPrecommit {
+    /// Hash of the block being precommitted. Either the target of the commit or one of its
+    /// descendants.
+    pub target_hash: [u8; 32],
+    /// Height of the block being precommitted.
+    pub target_number: u64,
+    /// Ed25519 signature of the precommit.
+    pub signature: [u8; 64],
+    /// Authority that signed the precommit.
+    pub authority_public_key: [u8; 32],
+}
+
+/// Prevote found in a [`CatchUp`].
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct SignedPrevote {
+    /// Hash of the block being prevoted.
+    pub target_hash: [u8; 32],
+    /// Height of the block being prevoted.
+    pub target_number: u64,
+    /// Ed25519 signature of the prevote.
+    pub signature: [u8; 64],
+    /// Authority that signed the prevote.
+    pub authority_public_key: [u8; 32],
+}
+
+/// Votes of a completed round, which lets an authority that is behind skip directly to the
+/// round that follows. See [`GrandpaVoter::catch_up`].
+#[derive(Debug, Clone)]
+pub struct CatchUp {
+    /// Identifier of the authorities set the round belongs to.
+    pub set_id: u64,
+    /// Number of the completed round.
+    pub round_number: u64,
+    /// Prevotes of the round. Equivocators appear twice.
+    pub prevotes: Vec<SignedPrevote>,
+    /// Precommits of the round. Equivocators appear twice.
+    pub precommits: Vec<SignedPrecommit>,
+    /// Hash of the block that was finalized when the round started. All the votes of the round
+    /// target this block or one of its descendants.
+    pub base_hash: [u8; 32],
+    /// Height of the block that was finalized when the round started.
+    pub base_number: u64,
+}
+
+/// Proof that a block has been finalized by the authorities.
+#[derive(Debug, Clone)]
+pub struct Commit {
+    /// Round during which the block has been finalized.
+    pub round_number: u64,
+    /// Identifier of the authorities set that has finalized the block.
+    pub set_id: u64,
+    /// Hash of the finalized block.
+    pub target_hash: [u8; 32],
+    /// Height of the finalized block.
+    pub target_number: u64,
+    /// Precommits of the authorities.
+    pub precommits: Vec<SignedPrecommit>,
+    /// SCALE-encoded headers of the blocks between [`Commit::target_hash`] (excluded) and the
+    /// targets of the precommits (included).
+    pub votes_ancestries: Vec<Vec<u8>>,
+}
+
+impl Commit {
+    /// Returns the SCALE encoding of the justification corresponding to this commit. This
+    /// justification can be decoded with
+    /// [`decode_grandpa`](crate::finality::justification::decode::decode_grandpa).
+    pub fn scale_encoded_justification(&self, block_number_bytes: usize) -> Vec<u8> {
+        let mut out = Vec::new();
+        out.extend_from_slice(&self.round_number.to_le_bytes());
+        out.extend_from_slice(&self.target_hash);
+        encode_block_number(&mut out, self.target_number, block_number_bytes);
+        out.extend_from_slice(
+            crate::util::encode_scale_compact_usize(self.precommits.len()).as_ref(),
+        );
+        for precommit in &self.precommits {
+            out.extend_from_slice(&precommit.target_hash);
+            encode_block_number(&mut out, precommit.target_number, block_number_bytes);
+            out.extend_from_slice(&precommit.signature);
+            out.extend_from_slice(&precommit.authority_public_key);
+        }
+        out.extend_from_slice(
+            crate::util::encode_scale_compact_usize(self.votes_ancestries.len()).as_ref(),
+        );
+        for header in &self.votes_ancestries {
+            out.extend_from_slice(header);
+        }
+        out
+    }
+}
+
+/// GrandPa voter state machine. See the module-level documentation for more info.
+pub struct GrandpaVoter<TNow> {
+    /// See [`Config::block_number_bytes`].
+    block_number_bytes: usize,
+
+    /// See [`Config::set_id`].
+    set_id: u64,
+
+    /// See [`Config::authorities`].
+    authorities: Vec<header::GrandpaAuthority>,
+
+    /// Minimum sum of weights necessary for a block to be considered as voted for by a
+    /// super-majority.
+    threshold: u64,
+
+    /// Index within [`GrandpaVoter::authorities`] of the local authority, if any.
+    local_authority_index: Option<usize>,
+
+    /// See [`Config::gossip_duration`].
+    gossip_duration: Duration,
+
+    /// Hash and height of the latest finalized block.
+    finalized_block: ([u8; 32], u64),
+
+    /// Hash and height of the current best block. Can be equal to
+    /// [`GrandpaVoter::finalized_block`].
+    best_block: ([u8; 32], u64),
+
+    /// Non-finalized blocks, indexed by their hash.
+    blocks: HashMap<[u8; 32], Block, fnv::FnvBuildHasher>,
+
+    /// Estimate of the previous round, in other words the block that the previous round has
+    /// voted for. Prevotes of the current round must target this block or one of its
+    /// descendants.
+    previous_round_estimate: ([u8; 32], u64),
+
+    /// Number of the current round.
+    round_number: u64,
+
+    /// Moment when the current round has started.
+    round_start: TNow,
+
+    /// Latest finalized block at the time when the current round has started.
+    round_base: ([u8; 32], u64),
+
+    /// `true` if a [`Action::SignVote`] has been emitted for the prevote of the local authority
+    /// during the current round.
+    local_prevote_emitted: bool,
+
+    /// `true` if a [`Action::SignVote`] has been emitted for the precommit of the local authority
+    /// during the current round.
+    local_precommit_emitted: bool,
+
+    /// Votes of the current round and of the next round. Votes of the next round are kept in
+    /// order to not lose them if the other authorities are slightly ahead.
+    rounds_votes: BTreeMap<u64, RoundVotes>,
+
+    /// Latest round that has been completed through [`GrandpaVoter::next_action`], kept in order
+    /// to answer catch up requests. Rounds skipped through
+    /// [`GrandpaVoter::inject_completed_round`] aren't stored, as their prevotes aren't known.
+    last_completed_round: Option<CompletedRound>,
+}
+
+/// See [`GrandpaVoter::last_completed_round`].
+struct CompletedRound {
+    round_number: u64,
+    /// Value of [`GrandpaVoter::round_base`] when the round was the current round.
+    base: ([u8; 32], u64),
+    votes: RoundVotes,
+}
+
+/// See [`GrandpaVoter::blocks`].
+struct Block {
+    number: u64,
+    parent_hash: [u8; 32],
+    scale_encoded_header: Vec<u8>,
+}
+
+/// Votes of a round.
+struct RoundVotes {
+    /// Prevotes. Indices match the ones of [`GrandpaVoter::authorities`].
+    prevotes: Vec<VoteSlot>,
+    /// Precommits. Indices match the ones of [`GrandpaVoter::authorities`].
+    precommits: Vec<VoteSlot>,
+}
+
+/// Votes of a specific authority for a specific round and kind of vote.
+#[derive(Clone)]
+enum VoteSlot {
+    None,
+    Single(Vote),
+    /// The authority has voted twice. Equivocators are considered as voting for all blocks.
+    Equivocated(Vote, Vote),
+}
+
+impl VoteSlot {
+    /// Returns the list of votes in this slot.
+    fn votes(&self) -> impl Iterator<Item = &Vote> {
+        let (first, second) = match self {
+            VoteSlot::None => (None, None),
+            VoteSlot::Single(vote) => (Some(vote), None),
+            VoteSlot::Equivocated(first, second) => (Some(first), Some(second)),
+        };
+        first.into_iter().chain(second)
+    }
+}
+
+#[derive(Clone, PartialEq, Eq)]
+struct Vote {
+    target_hash: [u8; 32],
+    target_number: u64,
+    signature: [u8; 64],
+}
+
+impl<TNow> GrandpaVoter<TNow>
+where
+    TNow: Clone + ops::Add<Duration, Output = TNow> + Ord,
+{
+    /// Initializes a new [`GrandpaVoter`].
+    ///
+    /// # Panic
+    ///
+    /// Panics if [`Config::authorities`] is empty.
+    ///
+    pub fn new(config: Config<TNow>) -> Self {
+        assert!(!config.authorities.is_empty());
+
+        let total_weight = config
+            .authorities
+            .iter()
+            .fold(0u64, |sum, a| sum.saturating_add(a.weight.get()));
+
+        let local_authority_index = config.local_authority.and_then(|local| {
+            config
+                .authorities
+                .iter()
+                .position(|a| a.public_key == local)
+        });
+
+        let finalized_block = (config.finalized_block_hash, config.finalized_block_number);
+
+        let mut voter = GrandpaVoter {
+            block_number_bytes: config.block_number_bytes,
+            set_id: config.set_id,
+            threshold: total_weight - (total_weight - 1) / 3,
+            authorities: config.authorities,
+            local_authority_index,
+            gossip_duration: config.gossip_duration,
+            finalized_block,
+            best_block: finalized_block,
+            blocks: HashMap::with_capacity_and_hasher(64, Default::default()),
+            previous_round_estimate: finalized_block,
+            round_number: config.round_number,
+            round_start: config.now,
+            round_base: finalized_block,
+            local_prevote_emitted: false,
+            local_precommit_emitted: false,
+            rounds_votes: BTreeMap::new(),
+            last_completed_round: None,
+        };
+
+        voter.ensure_round_votes(config.round_number);
+        voter.ensure_round_votes(config.round_number + 1);
+        voter
+    }
+
+    /// Returns the identifier of the authorities set this voter is part of.
+    pub fn set_id(&self) -> u64 {
+        self.set_id
+    }
+
+    /// Returns the number of the current round.
+    pub fn round_number(&self) -> u64 {
+        self.round_number
+    }
+
+    /// Returns the height of the latest finalized block, as passed to
+    /// [`GrandpaVoter::set_finalized_block`] or at initialization.
+    pub fn finalized_block_number(&self) -> u64 {
+        self.finalized_block.1
+    }
+
+    /// Returns `true` if the local node is one of the authorities of the set.
+    pub fn is_authority(&self) -> bool {
+        self.local_authority_index.is_some()
+    }
+
+    /// Adds a new non-finalized block.
+    ///
+    /// Blocks whose height is inferior or equal to the latest finalized block are ignored, as
+    /// are blocks that are already known.
+    pub fn add_block(&mut self, scale_encoded_header: &[u8]) -> Result<(), header::Error> {
+        let decoded = header::decode(scale_encoded_header, self.block_number_bytes)?;
+        if decoded.number <= self.finalized_block.1 {
+            return Ok(());
+        }
+
+        let hash = header::hash_from_scale_encoded_header(scale_encoded_header);
+        self.blocks.entry(hash).or_insert_with(|| Block {
+            number: decoded.number,
+            parent_hash: *decoded.parent_hash,
+            scale_encoded_header: scale_encoded_header.to_vec(),
+        });
+        Ok(())
+    }
+
+    /// Sets the current best block. Prevotes of the local authority target this block.
+    ///
+    /// # Panic
+    ///
+    /// Panics if the block is neither the finalized block nor a block previously passed to
+    /// [`GrandpaVoter::add_block`].
+    ///
+    pub fn set_best_block(&mut self, hash: &[u8; 32]) {
+        self.best_block = if *hash == self.finalized_block.0 {
+            self.finalized_block
+        } else {
+            (*hash, self.blocks.get(hash).unwrap().number)
+        };
+    }
+
+    /// Sets the latest finalized block. Blocks that don't descend from it are discarded.
+    ///
+    /// Returns an error if the block is neither the current finalized block nor a block
+    /// previously passed to [`GrandpaVoter::add_block`], in which case the voter is left
+    /// unchanged.
+    pub fn set_finalized_block(&mut self, hash: &[u8; 32]) -> Result<(), SetFinalizedBlockError> {
+        if *hash == self.finalized_block.0 {
+            return Ok(());
+        }
+
+        let new_finalized = match self.blocks.get(hash) {
+            Some(block) => (*hash, block.number),
+            None => return Err(SetFinalizedBlockError::UnknownBlock),
+        };
+
+        let to_remove = self
+            .blocks
+            .keys()
+            .filter(|h| !self.is_descendant_or_equal(h, &new_finalized) || **h == *hash)
+            .copied()
+            .collect::<Vec<_>>();
+
+        let best_is_descendant = self.is_descendant_or_equal(&self.best_block.0, &new_finalized);
+        let estimate_is_descendant =
+            self.is_descendant_or_equal(&self.previous_round_estimate.0, &new_finalized);
+
+        for block in to_remove {
+            self.blocks.remove(&block);
+        }
+
+        self.finalized_block = new_finalized;
+        if !best_is_descendant {
+            self.best_block = new_finalized;
+        }
+        if !estimate_is_descendant {
+            self.previous_round_estimate = new_finalized;
+        }
+        Ok(())
+    }
+
+    /// Injects a vote received from the network.
+    ///
+    /// The signature of the vote is verified. Only votes of the current round and of the next
+    /// round are accepted.
+    pub fn inject_vote(&mut self, vote: &SignedVote) -> Result<InjectVoteOutcome, InjectVoteError> {
+        if vote.set_id != self.set_id {
+            return Err(InjectVoteError::BadSetId);
+        }
+
+        if vote.round_number != self.round_number && vote.round_number != self.round_number + 1 {
+            return Err(InjectVoteError::RoundNotActive);
+        }
+
+        let authority_index = self
+            .authorities
+            .iter()
+            .position(|a| a.public_key == vote.authority_public_key)
+            .ok_or(InjectVoteError::NotAuthority)?;
+
+        let new_vote = Vote {
+            target_hash: vote.target_hash,
+            target_number: vote.target_number,
+            signature: vote.signature,
+        };
+
+        // Check for duplicates before verifying the signature, as this is a cheaper operation.
+        match self.vote_slot_mut(vote.round_number, vote.kind, authority_index) {
+            VoteSlot::Single(existing) if *existing == new_vote => {
+                return Ok(InjectVoteOutcome::Duplicate)
+            }
+            VoteSlot::Equivocated(..) => return Ok(InjectVoteOutcome::Duplicate),
+            _ => {}
+        }
+
+        // The signature is verified before the vote is stored, otherwise it would be possible
+        // to forge equivocations.
+        let payload = signed_payload(
+            vote.kind,
+            &vote.target_hash,
+            vote.target_number,
+            vote.round_number,
+            vote.set_id,
+            self.block_number_bytes,
+        );
+        let signature_valid = ed25519_zebra::VerificationKey::try_from(vote.authority_public_key)
+            .map_or(false, |key| {
+                key.verify(&ed25519_zebra::Signature::from(vote.signature), &payload)
+                    .is_ok()
+            });
+        if !signature_valid {
+            return Err(InjectVoteError::BadSignature);
+        }
+
+        let slot = self.vote_slot_mut(vote.round_number, vote.kind, authority_index);
+        match mem::replace(slot, VoteSlot::None) {
+            VoteSlot::None => {
+                *slot = VoteSlot::Single(new_vote);
+                Ok(InjectVoteOutcome::Accepted)
+            }
+            VoteSlot::Single(existing) => {
+                let first_target = (existing.target_hash, existing.target_number);
+                *slot = VoteSlot::Equivocated(existing, new_vote);
+                Ok(InjectVoteOutcome::Equivocation {
+                    first_target,
+                    second_target: (vote.target_hash, vote.target_number),
+                })
+            }
+            VoteSlot::Equivocated(..) => unreachable!(),
+        }
+    }
+
+    /// Returns the moment when [`GrandpaVoter::next_action`] should be called again, in addition
+    /// to after every modification made to the voter. Returns `None` if only modifications to
+    /// the voter can lead to new actions.
+    pub fn wake_up_after(&self) -> Option<TNow> {
+        self.local_authority_index?;
+
+        if !self.local_prevote_emitted {
+            Some(self.round_start.clone() + self.gossip_duration * 2)
+        } else if !self.local_precommit_emitted {
+            Some(self.round_start.clone() + self.gossip_duration * 4)
+        } else {
+            None
+        }
+    }
+
+    /// Returns the next action to perform, or `None` if there is nothing to do at the moment.
+    ///
+    /// This function should be called repeatedly until it returns `None`.
+    pub fn next_action(&mut self, now: &TNow) -> Option<Action> {
+        loop {
+            if let Some(local_authority_index) = self.local_authority_index {
+                // Emitting the prevote of the local authority.
+                if !self.local_prevote_emitted
+                    && *now >= self.round_start.clone() + self.gossip_duration * 2
+                {
+                    self.local_prevote_emitted = true;
+
+                    // Prevote for the best block, provided it is a descendant of the estimate
+                    // of the previous round.
+                    let target = if self
+                        .is_descendant_or_equal(&self.best_block.0, &self.previous_round_estimate)
+                    {
+                        self.best_block
+                    } else {
+                        self.previous_round_estimate
+                    };
+
+                    return Some(Action::SignVote(self.sign_vote(
+                        local_authority_index,
+                        VoteKind::Prevote,
+                        target,
+                    )));
+                }
+
+                // Emitting the precommit of the local authority.
+                if self.local_prevote_emitted && !self.local_precommit_emitted {
+                    let prevotes = &self.rounds_votes[&self.round_number].prevotes;
+                    let all_prevoted = prevotes.iter().all(|slot| !matches!(slot, VoteSlot::None));
+
+                    if *now >= self.round_start.clone() + self.gossip_duration * 4 || all_prevoted {
+                        if let Some(prevote_ghost) = self.ghost(prevotes) {
+                            self.local_precommit_emitted = true;
+                            return Some(Action::SignVote(self.sign_vote(
+                                local_authority_index,
+                                VoteKind::Precommit,
+                                prevote_ghost,
+                            )));
+                        }
+                    }
+                }
+
+                // The round can't be completed before the local authority has precommitted.
+                if !self.local_precommit_emitted {
+                    return None;
+                }
+            }
+
+            // Check whether the current round is completed.
+            let round_votes = &self.rounds_votes[&self.round_number];
+            let precommit_ghost = self.ghost(&round_votes.precommits)?;
+            let commit = if precommit_ghost.1 > self.finalized_block.1 {
+                self.build_commit(self.round_number, precommit_ghost)
+            } else {
+                None
+            };
+
+            // The estimate of the round is the prevote GHOST, if it is a descendant of the
+            // precommit GHOST.
+            self.previous_round_estimate = match self.ghost(&round_votes.prevotes) {
+                Some(prevote_ghost)
+                    if self.is_descendant_or_equal(&prevote_ghost.0, &precommit_ghost) =>
+                {
+                    prevote_ghost
+                }
+                _ => precommit_ghost,
+            };
+
+            // Start the next round, keeping the votes of the completed round in order to answer
+            // catch up requests.
+            let completed_round = self.round_number;
+            self.last_completed_round = Some(CompletedRound {
+                round_number: completed_round,
+                base: self.round_base,
+                votes: self.rounds_votes.remove(&completed_round).unwrap(),
+            });
+            self.start_round(completed_round + 1, now.clone());
+
+            if let Some(commit) = commit {
+                return Some(Action::Finalized {
+                    completed_round,
+                    commit,
+                });
+            }
+        }
+    }
+
+    /// Injects the precommits of a round that the authorities have completed, for example the
+    /// precommits found in a commit message or in a catch up response.
+    ///
+    /// If `round_number` is superior or equal to the current round, the signatures of the
+    /// precommits are verified and, if they represent a super-majority of the authorities, the
+    /// voter skips directly to the round following `round_number`. The local authority doesn't
+    /// vote in the rounds that are skipped.
+    ///
+    /// Returns `true` if the current round has changed. If `true` is returned, the voter might
+    /// have new actions to perform, and [`GrandpaVoter::next_action`] should be called.
+    ///
+    /// > **Note**: This function doesn't finalize any block. Commits must also be verified and
+    /// >           passed to [`GrandpaVoter::set_finalized_block`] as usual.
+    pub fn inject_completed_round(
+        &mut self,
+        round_number: u64,
+        set_id: u64,
+        precommits: impl Iterator<Item = SignedPrecommit>,
+        now: TNow,
+    ) -> Result<bool, InjectCompletedRoundError> {
+        if set_id != self.set_id {
+            return Err(InjectCompletedRoundError::BadSetId);
+        }
+
+        // Rounds that are already over are ignored without verifying anything.
+        if round_number < self.round_number {
+            return Ok(false);
+        }
+
+        let mut slots = iter::repeat(VoteSlot::None)
+            .take(self.authorities.len())
+            .collect::<Vec<_>>();
+        let mut total_weight = 0u64;
+
+        for precommit in precommits {
+            let authority_index = self
+                .authorities
+                .iter()
+                .position(|a| a.public_key == precommit.authority_public_key)
+                .ok_or(InjectCompletedRoundError::NotAuthority)?;
+
+            // The same authority is only counted once.
+            if !matches!(slots[authority_index], VoteSlot::None) {
+                continue;
+            }
+
+            let payload = signed_payload(
+                VoteKind::Precommit,
+                &precommit.target_hash,
+                precommit.target_number,
+                round_number,
+                set_id,
+                self.block_number_bytes,
+            );
+            let signature_valid = ed25519_zebra::VerificationKey::try_from(
+                precommit.authority_public_key,
+            )
+            .map_or(false, |key| {
+                key.verify(
+                    &ed25519_zebra::Signature::from(precommit.signature),
+                    &payload,
+                )
+                .is_ok()
+            });
+            if !signature_valid {
+                return Err(InjectCompletedRoundError::BadSignature);
+            }
+
+            total_weight =
+                total_weight.saturating_add(self.authorities[authority_index].weight.get());
+            slots[authority_index] = VoteSlot::Single(Vote {
+                target_hash: precommit.target_hash,
+                target_number: precommit.target_number,
+                signature: precommit.signature,
+            });
+        }
+
+        if total_weight < self.threshold {
+            return Err(InjectCompletedRoundError::NotEnoughPrecommits);
+        }
+
+        // The estimate of the skipped round isn't known, as it would require knowing its
+        // prevotes. The precommit GHOST is an ancestor of this estimate and is used instead.
+        if let Some(precommit_ghost) = self.ghost(&slots) {
+            if precommit_ghost.1 >= self.previous_round_estimate.1 {
+                self.previous_round_estimate = precommit_ghost;
+            }
+        }
+
+        self.start_round(round_number + 1, now);
+        Ok(true)
+    }
+
+    /// Builds the answer to a catch up request received from another authority.
+    ///
+    /// Returns the votes of the latest round that the voter has completed. Returns `None` if
+    /// `set_id` doesn't match, or if `round_number` is superior to the latest completed round, in
+    /// which case there is nothing to answer.
+    pub fn catch_up(&self, round_number: u64, set_id: u64) -> Option<CatchUp> {
+        if set_id != self.set_id {
+            return None;
+        }
+
+        let completed_round = self.last_completed_round.as_ref()?;
+        if round_number > completed_round.round_number {
+            return None;
+        }
+
+        let mut prevotes = Vec::new();
+        let mut precommits = Vec::new();
+        for (authority_index, authority) in self.authorities.iter().enumerate() {
+            for vote in completed_round.votes.prevotes[authority_index].votes() {
+                prevotes.push(SignedPrevote {
+                    target_hash: vote.target_hash,
+                    target_number: vote.target_number,
+                    signature: vote.signature,
+                    authority_public_key: authority.public_key,
+                });
+            }
+            for vote in completed_round.votes.precommits[authority_index].votes() {
+                precommits.push(SignedPrecommit {
+                    target_hash: vote.target_hash,
+                    target_number: vote.target_number,
+                    signature: vote.signature,
+                    authority_public_key: authority.public_key,
+                });
+            }
+        }
+
+        Some(CatchUp {
+            set_id: self.set_id,
+            round_number: completed_round.round_number,
+            prevotes,
+            precommits,
+            base_hash: completed_round.base.0,
+            base_number: completed_round.base.1,
+        })
+    }
+
+    /// Injects the signature of a vote requested through [`Action::SignVote`].
+    ///
+    /// Returns the signed vote, which should then be sent to the other authorities.
+    pub fn inject_local_signature(
+        &mut self,
+        sign_vote: SignVote,
+        signature: [u8; 64],
+    ) -> SignedVote {
+        // The round might have ended in the meantime, in which case the vote is still returned
+        // but not stored.
+        if self.rounds_votes.contains_key(&sign_vote.round_number) {
+            *self.vote_slot_mut(
+                sign_vote.round_number,
+                sign_vote.kind,
+                sign_vote.authority_index,
+            ) = VoteSlot::Single(Vote {
+                target_hash: sign_vote.target_hash,
+                target_number: sign_vote.target_number,
+                signature,
+            });
+        }
+
+        SignedVote {
+            round_number: sign_vote.round_number,
+            set_id: sign_vote.set_id,
+            kind: sign_vote.kind,
+            target_hash: sign_vote.target_hash,
+            target_number: sign_vote.target_number,
+            signature,
+            authority_public_key: self.authorities[sign_vote.authority_index].public_key,
+        }
+    }
+
+    fn sign_vote(
+        &self,
+        authority_index: usize,
+        kind: VoteKind,
+        (target_hash, target_number): ([u8; 32], u64),
+    ) -> SignVote {
+        SignVote {
+            round_number: self.round_number,
+            set_id: self.set_id,
+            kind,
+            target_hash,
+            target_number,
+            authority_index,
+            authority_public_key: self.authorities[authority_index].public_key,
+            payload: signed_payload(
+                kind,
+                &target_hash,
+                target_number,
+                self.round_number,
+                self.set_id,
+                self.block_number_bytes,
+            ),
+        }
+    }
+
+    /// Starts the given round, discarding the votes of the rounds before it.
+    fn start_round(&mut self, round_number: u64, now: TNow) {
+        debug_assert!(round_number > self.round_number);
+        self.round_number = round_number;
+        self.round_start = now;
+        self.round_base = self.finalized_block;
+        self.local_prevote_emitted = false;
+        self.local_precommit_emitted = false;
+        self.rounds_votes = self.rounds_votes.split_off(&self.round_number);
+        self.ensure_round_votes(self.round_number);
+        self.ensure_round_votes(self.round_number + 1);
+    }
+
+    fn ensure_round_votes(&mut self, round_number: u64) {
+        let num_authorities = self.authorities.len();
+        self.rounds_votes
+            .entry(round_number)
+            .or_insert_with(|| RoundVotes {
+                prevotes: iter::repeat(VoteSlot::None).take(num_authorities).collect(),
+                precommits: iter::repeat(VoteSlot::None).take(num_authorities).collect(),
+            });
+    }
+
+    fn vote_slot_mut(
+        &mut self,
+        round_number: u64,
+        kind: VoteKind,
+        authority_index: usize,
+    ) -> &mut VoteSlot {
+        let round = self.rounds_votes.get_mut(&round_number).unwrap();
+        match kind {
+            VoteKind::Prevote => &mut round.prevotes[authority_index],
+            VoteKind::Precommit => &mut round.precommits[authority_index],
+        }
+    }
+
+    /// Returns `true` if `hash` is equal to `ancestor` or is a known descendant of it.
+    fn is_descendant_or_equal(&self, hash: &[u8; 32], ancestor: &([u8; 32], u64)) -> bool {
+        let mut current = *hash;
+        loop {
+            if current == ancestor.0 {
+                return true;
+            }
+
+            match self.blocks.get(&current) {
+                Some(block) if block.number > ancestor.1 => current = block.parent_hash,
+                _ => return false,
+            }
+        }
+    }
+
+    /// Returns the list of blocks that `hash` and its ancestors, up to the finalized block
+    /// (excluded). Returns `None` if the block isn't known or doesn't descend from the finalized
+    /// block.
+    fn ancestry(&self, hash: &[u8; 32]) -> Option<Vec<[u8; 32]>> {
+        let mut ancestry = Vec::new();
+        let mut current = *hash;
+        while current != self.finalized_block.0 {
+            let block = self.blocks.get(&current)?;
+            ancestry.push(current);
+            current = block.parent_hash;
+        }
+        Some(ancestry)
+    }
+
+    /// Returns the highest block that has been voted for by a super-majority, where a vote for a
+    /// block also counts as a vote for all its ancestors.
+    fn ghost(&self, votes: &[VoteSlot]) -> Option<([u8; 32], u64)> {
+        // Equivocators are considered as voting for every block.
+        let mut equivocators_weight = 0u64;
+        let mut finalized_weight = 0u64;
+        let mut weights = HashMap::<[u8; 32], u64, fnv::FnvBuildHasher>::with_capacity_and_hasher(
+            self.blocks.len(),
+            Default::default(),
+        );
+
+        for (authority, slot) in self.authorities.iter().zip(votes.iter()) {
+            let weight = authority.weight.get();
+            let vote = match slot {
+                VoteSlot::None => continue,
+                VoteSlot::Equivocated(..) => {
+                    equivocators_weight = equivocators_weight.saturating_add(weight);
+                    continue;
+                }
+                VoteSlot::Single(vote) => vote,
+            };
+
+            // Votes for unknown blocks are ignored.
+            let Some(ancestry) = self.ancestry(&vote.target_hash) else {
+                continue;
+            };
+            finalized_weight = finalized_weight.saturating_add(weight);
+            for block in ancestry {
+                let entry = weights.entry(block).or_insert(0);
+                *entry = entry.saturating_add(weight);
+            }
+        }
+
+        weights
+            .into_iter()
+            .filter(|(_, weight)| weight.saturating_add(equivocators_weight) >= self.threshold)
+            .map(|(hash, _)| (hash, self.blocks[&hash].number))
+            .max_by_key(|(hash, number)| (*number, *hash))
+            .or_else(|| {
+                if finalized_weight.saturating_add(equivocators_weight) >= self.threshold {
+                    Some(self.finalized_block)
+                } else {
+                    None
+                }
+            })
+    }
+
+    /// Builds a [`Commit`] for the given target using the precommits of the given round.
+    ///
+    /// Returns `None` if the precommits that can be included aren't enough to reach a
+    /// super-majority, which can happen because of equivocations.
+    fn build_commit(&self, round_number: u64, target: ([u8; 32], u64)) -> Option<Commit> {
+        let mut precommits = Vec::new();
+        let mut votes_ancestries = Vec::new();
+        let mut ancestries_included =
+            HashSet::<[u8; 32], fnv::FnvBuildHasher>::with_capacity_and_hasher(
+                0,
+                Default::default(),
+            );
+        let mut included_weight = 0u64;
+
+        for (authority, slot) in self
+            .authorities
+            .iter()
+            .zip(self.rounds_votes[&round_number].precommits.iter())
+        {
+            // Only one of the precommits of an equivocator can be included, as a justification
+            // can't contain the same authority twice.
+            let vote = match slot {
+                VoteSlot::None => continue,
+                VoteSlot::Single(vote) => vote,
+                VoteSlot::Equivocated(first, second) => {
+                    if self.is_descendant_or_equal(&first.target_hash, &target) {
+                        first
+                    } else {
+                        second
+                    }
+                }
+            };
+
+            if !self.is_descendant_or_equal(&vote.target_hash, &target) {
+                continue;
+            }
+
+            let mut current = vote.target_hash;
+            while current != target.0 {
+                let block = &self.blocks[&current];
+                if ancestries_included.insert(current) {
+                    votes_ancestries.push(block.scale_encoded_header.clone());
+                }
+                current = block.parent_hash;
+            }
+
+            included_weight = included_weight.saturating_add(authority.weight.get());
+            precommits.push(SignedPrecommit {
+                target_hash: vote.target_hash,
+                target_number: vote.target_number,
+                signature: vote.signature,
+                authority_public_key: authority.public_key,
+            });
+        }
+
+        if included_weight < self.threshold {
+            return None;
+        }
+
+        Some(Commit {
+            round_number,
+            set_id: self.set_id,
+            target_hash: target.0,
+            target_number: target.1,
+            precommits,
+            votes_ancestries,
+        })
+    }
+}
+
+/// Action to perform, returned by [`GrandpaVoter::next_action`].
+#[derive(Debug)]
+pub enum Action {
+    /// The local authority must sign a vote. The signature must then be passed to
+    /// [`GrandpaVoter::inject_local_signature`]. If the vote can't be signed, the [`SignVote`]
+    /// can simply be dropped.
+    SignVote(SignVote),
+
+    /// A round has been completed and a block has been finalized by the authorities.
+    ///
+    /// The [`GrandpaVoter`] doesn't consider the block as finalized until
+    /// [`GrandpaVoter::set_finalized_block`] is called.
+    Finalized {
+        /// Number of the round that has been completed.
+        completed_round: u64,
+        /// Proof of the finality.
+        commit: Commit,
+    },
+}
+
+/// Vote of the local authority that needs to be signed.
+#[derive(Debug)]
+pub struct SignVote {
+    round_number: u64,
+    set_id: u64,
+    kind: VoteKind,
+    target_hash: [u8; 32],
+    target_number: u64,
+    authority_index: usize,
+    authority_public_key: [u8; 32],
+    payload: Vec<u8>,
+}
+
+impl SignVote {
+    /// Returns the Ed25519 public key of the local authority that must sign the vote.
+    pub fn authority_public_key(&self) -> &[u8; 32] {
+        &self.authority_public_key
+    }
+
+    /// Returns the payload to sign.
+    pub fn to_sign(&self) -> &[u8] {
+        &self.payload
+    }
+
+    /// Returns the kind of the vote.
+    pub fn kind(&self) -> VoteKind {
+        self.kind
+    }
+
+    /// Returns the round of the vote.
+    pub fn round_number(&self) -> u64 {
+        self.round_number
+    }
+
+    /// Returns the hash and height of the block being voted for.
+    pub fn target(&self) -> (&[u8; 32], u64) {
+        (&self.target_hash, self.target_number)
+    }
+}
+
+/// Outcome of [`GrandpaVoter::inject_vote`].
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum InjectVoteOutcome {
+    /// The vote has been stored.
+    Accepted,
+    /// The vote was already known, or the authority has already been detected as an
+    /// equivocator during this round.
+    Duplicate,
+    /// The authority has already emitted a different vote of the same kind during the same
+    /// round. The authority is from now on considered as voting for every block during this
+    /// round.
+    Equivocation {
+        /// Hash and height of the block targeted by the previous vote of this authority.
+        first_target: ([u8; 32], u64),
+        /// Hash and height of the block targeted by the vote that has just been injected.
+        second_target: ([u8; 32], u64),
+    },
+}
+
+/// Error potentially returned by [`GrandpaVoter::inject_vote`].
+#[derive(Debug, derive_more::Display, Clone, PartialEq, Eq)]
+pub enum InjectVoteError {
+    /// Vote concerns a different authorities set.
+    #[display(fmt = "Vote concerns a different authorities set")]
+    BadSetId,
+    /// Vote concerns a round that is neither the current round nor the next one.
+    #[display(fmt = "Vote concerns a round that isn't active")]
+    RoundNotActive,
+    /// Signer of the vote isn't part of the authorities set.
+    #[display(fmt = "Signer of the vote isn't part of the authorities set")]
+    NotAuthority,
+    /// Signature of the vote is invalid.
+    #[display(fmt = "Invalid vote signature")]
+    BadSignature,
+}
+
+/// Error potentially returned by [`GrandpaVoter::set_finalized_block`].
+#[derive(Debug, derive_more::Display, Clone, PartialEq, Eq)]
+pub enum SetFinalizedBlockError {
+    /// Block isn't known by the voter.
+    #[display(fmt = "Unknown block")]
+    UnknownBlock,
+}
+
+/// Error potentially returned by [`GrandpaVoter::inject_completed_round`].
+#[derive(Debug, derive_more::Display, Clone, PartialEq, Eq)]
+pub enum InjectCompletedRoundError {
+    /// Round concerns a different authorities set.
+    #[display(fmt = "Round concerns a different authorities set")]
+    BadSetId,
+    /// Signer of a precommit isn't part of the authorities set.
+    #[display(fmt = "Signer of a precommit isn't part of the authorities set")]
+    NotAuthority,
+    /// Signature of a precommit is invalid.
+    #[display(fmt = "Invalid precommit signature")]
+    BadSignature,
+    /// The precommits don't represent a super-majority of the authorities.
+    #[display(fmt = "Not enough precommits to prove the completion of the round")]
+    NotEnoughPrecommits,
+}
+
+/// Builds the payload that authorities sign when voting.
+fn signed_payload(
+    kind: VoteKind,
+    target_hash: &[u8; 32],
+    target_number: u64,
+    round_number: u64,
+    set_id: u64,
+    block_number_bytes: usize,
+) -> Vec<u8> {
+    let mut msg = Vec::with_capacity(1 + 32 + block_number_bytes + 8 + 8);
+    msg.push(match kind {
+        VoteKind::Prevote => 0u8,
+        VoteKind::Precommit => 1u8,
+    });
+    msg.extend_from_slice(target_hash);
+    encode_block_number(&mut msg, target_number, block_number_bytes);
+    msg.extend_from_slice(&round_number.to_le_bytes());
+    msg.extend_from_slice(&set_id.to_le_bytes());
+    msg
+}
+
+/// Appends to `out` the little endian encoding of `number` over `block_number_bytes` bytes.
+fn encode_block_number(out: &mut Vec<u8>, number: u64, block_number_bytes: usize) {
+    let bytes = number.to_le_bytes();
+    out.extend_from_slice(&bytes[..cmp::min(bytes.len(), block_number_bytes)]);
+    out.extend(iter::repeat(0).take(block_number_bytes.saturating_sub(bytes.len())));
+}
+
+#[cfg(test)]
+mod tests {
+    use super::{
+        Action, Config, GrandpaVoter, InjectCompletedRoundError, InjectVoteOutcome,
+        SetFinalizedBlockError, SignedPrecommit, SignedVote, VoteKind,
+    };
+    use crate::{finality::justification, header};
+    use core::{num::NonZeroU64, time::Duration};
+
+    fn block_header(parent_hash: [u8; 32], number: u64) -> Vec<u8> {
+        header::HeaderRef {
+            parent_hash: &parent_hash,
+            number,
+            state_root: &[0; 32],
+            extrinsics_root: &[0; 32],
+            digest: header::DigestRef::empty(),
+        }
+        .scale_encoding_vec(4)
+    }
+
+    #[test]
+    fn single_authority_finalizes() {
+        let key = ed25519_zebra::SigningKey::from([1; 32]);
+        let public_key: [u8; 32] = ed25519_zebra::VerificationKey::from(&key).into();
+        let authorities = vec![header::GrandpaAuthority {
+            public_key,
+            weight: NonZeroU64::new(1).unwrap(),
+        }];
+
+        let mut voter = GrandpaVoter::new(Config {
+            block_number_bytes: 4,
+            set_id: 0,
+            authorities: authorities.clone(),
+            local_authority: Some(public_key),
+            finalized_block_hash: [0; 32],
+            finalized_block_number: 0,
+            round_number: 1,
+            gossip_duration: Duration::from_secs(1),
+            now: Duration::new(0, 0),
+        });
+
+        let block1 = block_header([0; 32], 1);
+        let block1_hash = header::hash_from_scale_encoded_header(&block1);
+        voter.add_block(&block1).unwrap();
+        voter.set_best_block(&block1_hash);
+
+        assert!(voter.next_action(&Duration::new(0, 0)).is_none());
+        assert_eq!(voter.wake_up_after(), Some(Duration::from_secs(2)));
+
+        let now = Duration::from_secs(2);
+        for expected_kind in [VoteKind::Prevote, VoteKind::Precommit] {
+            let Some(Action::SignVote(sign)) = voter.next_action(&now) else {
+                panic!()
+            };
+            assert_eq!(sign.kind(), expected_kind);
+            assert_eq!(sign.target(), (&block1_hash, 1));
+            let signature = key.sign(sign.to_sign()).into();
+            voter.inject_local_signature(sign, signature);
+        }
+
+        let Some(Action::Finalized {
+            completed_round,
+            commit,
+        }) = voter.next_action(&now)
+        else {
+            panic!()
+        };
+        assert_eq!(completed_round, 1);
+        assert_eq!(voter.round_number(), 2);
+        assert_eq!(commit.target_hash, block1_hash);
+
+        let encoded = commit.scale_encoded_justification(4);
+        justification::verify::verify(justification::verify::Config {
+            justification: justification::decode::decode_grandpa(&encoded, 4).unwrap(),
+            block_number_bytes: 4,
+            authorities_set_id: 0,
+            authorities_list: authorities.iter().map(|a| a.public_key),
+            randomness_seed: [0; 32],
+        })
+        .unwrap();
+
+        assert_eq!(
+            voter.set_finalized_block(&[0xff; 32]),
+            Err(SetFinalizedBlockError::UnknownBlock)
+        );
+        assert_eq!(voter.set_finalized_block(&block1_hash), Ok(()));
+        assert_eq!(voter.finalized_block_number(), 1);
+    }
+
+    #[test]
+    fn catch_up_answered() {
+        let key = ed25519_zebra::SigningKey::from([1; 32]);
+        let public_key: [u8; 32] = ed25519_zebra::VerificationKey::from(&key).into();
+        let config = Config {
+            block_number_bytes: 4,
+            set_id: 0,
+            authorities: vec![header::GrandpaAuthority {
+                public_key,
+                weight: NonZeroU64::new(1).unwrap(),
+            }],
+            local_authority: Some(public_key),
+            finalized_block_hash: [0; 32],
+            finalized_block_number: 0,
+            round_number: 1,
+            gossip_duration: Duration::from_secs(1),
+            now: Duration::new(0, 0),
+        };
+        let mut voter = GrandpaVoter::new(config.clone());
+
+        let block1 = block_header([0; 32], 1);
+        let block1_hash = header::hash_from_scale_encoded_header(&block1);
+        voter.add_block(&block1).unwrap();
+        voter.set_best_block(&block1_hash);
+
+        // Nothing to answer before a round has been completed.
+        assert!(voter.catch_up(1, 0).is_none());
+
+        let now = Duration::from_secs(2);
+        while let Some(action) = voter.next_action(&now) {
+            if let Action::SignVote(sign) = action {
+                let signature = key.sign(sign.to_sign()).into();
+                voter.inject_local_signature(sign, signature);
+            }
+        }
+        assert_eq!(voter.round_number(), 2);
+
+        assert!(voter.catch_up(1, 1).is_none());
+        assert!(voter.catch_up(2, 0).is_none());
+        let catch_up = voter.catch_up(1, 0).unwrap();
+        assert_eq!(catch_up.round_number, 1);
+        assert_eq!((catch_up.base_hash, catch_up.base_number), ([0; 32], 0));
+        assert_eq!(catch_up.prevotes.len(), 1);
+        assert_eq!(catch_up.prevotes[0].target_hash, block1_hash);
+        assert_eq!(catch_up.precommits.len(), 1);
+
+        // The precommits of the catch up let another voter skip the round.
+        let mut other_voter = GrandpaVoter::new(Config {
+            local_authority: None,
+            ..config
+        });
+        other_voter.add_block(&block1).unwrap();
+        assert_eq!(
+            other_voter.inject_completed_round(
+                catch_up.round_number,
+                catch_up.set_id,
+                catch_up.precommits.into_iter(),
+                now
+            ),
+            Ok(true)
+        );
+        assert_eq!(other_voter.round_number(), 2);
+    }
+
+    #[test]
+    fn equivocation_detected() {
+        let keys = [[1; 32], [2; 32]].map(ed25519_zebra::SigningKey::from);
+        let public_keys: [[u8; 32]; 2] = [
+            ed25519_zebra::VerificationKey::from(&keys[0]).into(),
+            ed25519_zebra::VerificationKey::from(&keys[1]).into(),
+        ];
+
+        let mut voter = GrandpaVoter::new(Config {
+            block_number_bytes: 4,
+            set_id: 0,
+            authorities: public_keys
+                .iter()
+                .map(|public_key| header::GrandpaAuthority {
+                    public_key: *public_key,
+                    weight: NonZeroU64::new(1).unwrap(),
+                })
+                .collect(),
+            local_authority: None,
+            finalized_block_hash: [0; 32],
+            finalized_block_number: 0,
+            round_number: 1,
+            gossip_duration: Duration::from_secs(1),
+            now: Duration::new(0, 0),
+        });
+
+        let vote = |target_hash: [u8; 32]| {
+            let payload = super::signed_payload(VoteKind::Prevote, &target_hash, 1, 1, 0, 4);
+            SignedVote {
+                round_number: 1,
+                set_id: 0,
+                kind: VoteKind::Prevote,
+                target_hash,
+                target_number: 1,
+                signature: keys[0].sign(&payload).into(),
+                authority_public_key: public_keys[0],
+            }
+        };
+
+        assert_eq!(
+            voter.inject_vote(&vote([1; 32])).unwrap(),
+            InjectVoteOutcome::Accepted
+        );
+        assert_eq!(
+            voter.inject_vote(&vote([1; 32])).unwrap(),
+            InjectVoteOutcome::Duplicate
+        );
+        assert_eq!(
+            voter.inject_vote(&vote([2; 32])).unwrap(),
+            InjectVoteOutcome::Equivocation {
+                first_target: ([1; 32], 1),
+                second_target: ([2; 32], 1)
+            }
+        );
+
+        let mut forged = vote([3; 32]);
+        forged.authority_public_key = public_keys[1];
+        assert!(voter.inject_vote(&forged).is_err());
+    }
+
+    #[test]
+    fn completed_round_skips_rounds() {
+        let keys = [[1; 32], [2; 32], [3; 32], [4; 32]].map(ed25519_zebra::SigningKey::from);
+        let public_keys = keys
+            .each_ref()
+            .map(|key| <[u8; 32]>::from(ed25519_zebra::VerificationKey::from(key)));
+
+        let mut voter = GrandpaVoter::new(Config {
+            block_number_bytes: 4,
+            set_id: 0,
+            authorities: public_keys
+                .iter()
+                .map(|public_key| header::GrandpaAuthority {
+                    public_key: *public_key,
+                    weight: NonZeroU64::new(1).unwrap(),
+                })
+                .collect(),
+            local_authority: Some(public_keys[0]),
+            finalized_block_hash: [0; 32],
+            finalized_block_number: 0,
+            round_number: 1,
+            gossip_duration: Duration::from_secs(1),
+            now: Duration::new(0, 0),
+        });
+
+        let block1 = block_header([0; 32], 1);
+        let block1_hash = header::hash_from_scale_encoded_header(&block1);
+        voter.add_block(&block1).unwrap();
+        voter.set_best_block(&block1_hash);
+
+        let precommit = |authority: usize, round_number: u64| {
+            let payload =
+                super::signed_payload(VoteKind::Precommit, &block1_hash, 1, round_number, 0, 4);
+            SignedPrecommit {
+                target_hash: block1_hash,
+                target_number: 1,
+                signature: keys[authority].sign(&payload).into(),
+                authority_public_key: public_keys[authority],
+            }
+        };
+
+        // Not enough precommits, even when the same authority is repeated.
+        assert_eq!(
+            voter.inject_completed_round(
+                5,
+                0,
+                [precommit(1, 5), precommit(2, 5), precommit(2, 5)].into_iter(),
+                Duration::new(0, 0)
+            ),
+            Err(InjectCompletedRoundError::NotEnoughPrecommits)
+        );
+
+        // Precommits signed for a different round.
+        assert_eq!(
+            voter.inject_completed_round(
+                5,
+                0,
+                [precommit(1, 4), precommit(2, 4), precommit(3, 4)].into_iter(),
+                Duration::new(0, 0)
+            ),
+            Err(InjectCompletedRoundError::BadSignature)
+        );
+
+        assert_eq!(
+            voter.inject_completed_round(
+                5,
+                1,
+                [precommit(1, 5), precommit(2, 5), precommit(3, 5)].into_iter(),
+                Duration::new(0, 0)
+            ),
+            Err(InjectCompletedRoundError::BadSetId)
+        );
+        assert_eq!(voter.round_number(), 1);
+
+        assert_eq!(
+            voter.inject_completed_round(
+                5,
+                0,
+                [precommit(1, 5), precommit(2, 5), precommit(3, 5)].into_iter(),
+                Duration::from_secs(10)
+            ),
+            Ok(true)
+        );
+        assert_eq!(voter.round_number(), 6);
+
+        // Rounds that are already over are ignored.
+        assert_eq!(
+            voter.inject_completed_round(5, 0, [].into_iter(), Duration::from_secs(10)),
+            Ok(false)
+        );
+
+        // The local authority votes in the new round only, once the new round has lasted long
+        // enough.
+        assert!(voter.next_action(&Duration::from_secs(11)).is_none());
+        let Some(Action::SignVote(sign)) = voter.next_action(&Duration::from_secs(12)) else {
+            panic!()
+        };
+        assert_eq!(sign.kind(), VoteKind::Prevote);
+        assert_eq!(sign.round_number(), 6);
+        assert_eq!(sign.target(), (&block1_hash, 1));
+    }
+}
@@ -22,7 +22,8 @@
 //! cryptographic key pairs (i.e. both the public and secret keys).
 //!
 //! Each key pair contained within the keystore is identified as a `(KeyNamespace, [u8; 32])`
-//! tuple, where the `[u8; 32]` is the public key. See [`KeyNamespace`].
+//! tuple, where the `[u8; 32]` is the public key. See [`KeyNamespace`]. ECDSA key pairs, whose
+//! public keys are 33 bytes, are instead identified as a `(KeyNamespace, [u8; 33])` tuple.
 //!
 //! A keystore is optionally associated with a directory of the file system into which it will
 //! store secret keys permanently. Keys present in this directory are considered to be the content
@@ -45,6 +46,8 @@
 
 use crate::{identity::seed_phrase, util::SipHasherBuild};
 
+pub use crate::executor::host::KeyAlgorithm;
+
 use async_lock::Mutex;
 use rand_chacha::rand_core::{RngCore as _, SeedableRng as _};
 use std::{borrow::Cow, fs, io, path, str};
@@ -56,6 +59,7 @@ pub enum KeyNamespace {
     Aura,
     AuthorityDiscovery,
     Babe,
+    Beefy,
     Grandpa,
     ImOnline,
     // TODO: there exists other variants in Substrate but it's unclear whether they're in use (see https://github.com/paritytech/substrate/blob/cafe12e7785bf92e5dc04780c10e7f8330a15a4c/primitives/core/src/crypto.rs)
@@ -68,17 +72,32 @@ impl KeyNamespace {
             KeyNamespace::Aura,
             KeyNamespace::AuthorityDiscovery,
             KeyNamespace::Babe,
+            KeyNamespace::Beefy,
             KeyNamespace::Grandpa,
             KeyNamespace::ImOnline,
         ]
         .into_iter()
     }
 
+    /// Returns the [`KeyNamespace`] corresponding to the given key type identifier, as used by
+    /// the runtime (for example `b"babe"`). Returns `None` if the identifier is unknown.
+    pub fn from_key_type_id(key_type_id: &[u8; 4]) -> Option<Self> {
+        str::from_utf8(key_type_id)
+            .ok()
+            .and_then(KeyNamespace::from_string)
+    }
+
+    /// Returns the key type identifier, as used by the runtime, corresponding to this namespace.
+    pub fn key_type_id(&self) -> [u8; 4] {
+        <[u8; 4]>::try_from(self.as_string().as_bytes()).unwrap()
+    }
+
     fn from_string(str: &str) -> Option<Self> {
         match str {
             "aura" => Some(KeyNamespace::Aura),
             "audi" => Some(KeyNamespace::AuthorityDiscovery),
             "babe" => Some(KeyNamespace::Babe),
+            "beef" => Some(KeyNamespace::Beefy),
             "gran" => Some(KeyNamespace::Grandpa),
             "imon" => Some(KeyNamespace::ImOnline),
             _ => None,
@@ -90,6 +109,7 @@ impl KeyNamespace {
             KeyNamespace::Aura => "aura",
             KeyNamespace::AuthorityDiscovery => "audi",
             KeyNamespace::Babe => "babe",
+            KeyNamespace::Beefy => "beef",
             KeyNamespace::Grandpa => "gran",
             KeyNamespace::ImOnline => "imon",
         }
@@ -130,6 +150,14 @@ impl Keystore {
             })
         });
 
+        let mut ecdsa_keys = hashbrown::HashMap::with_capacity_and_hasher(0, {
+            SipHasherBuild::new({
+                let mut seed = [0; 16];
+                gen_rng.fill_bytes(&mut seed);
+                seed
+            })
+        });
+
         // Load the keys from the disk.
         // TODO: return some diagnostic about invalid files?
         if let Some(keys_directory) = &keys_directory {
@@ -157,22 +185,25 @@ impl Keystore {
                                 KeyNamespace::from_string,
                             ),
                             nom::bytes::streaming::tag("-"),
-                            nom::combinator::map_opt(
-                                nom::bytes::streaming::take(7u32),
-                                |b| match b {
-                                    "ed25519" => Some(PrivateKey::FileEd25519),
-                                    "sr25519" => Some(PrivateKey::FileSr25519),
-                                    _ => None,
-                                },
-                            ),
+                            nom::branch::alt((
+                                nom::combinator::map(nom::bytes::streaming::tag("ed25519"), |_| {
+                                    KeyAlgorithm::Ed25519
+                                }),
+                                nom::combinator::map(nom::bytes::streaming::tag("sr25519"), |_| {
+                                    KeyAlgorithm::Sr25519
+                                }),
+                                nom::combinator::map(nom::bytes::streaming::tag("ecdsa"), |_| {
+                                    KeyAlgorithm::Ecdsa
+                                }),
+                            )),
                             nom::bytes::streaming::tag("-"),
                             nom::combinator::map_opt(
                                 nom::bytes::complete::take_while(|c: char| {
                                     c.is_ascii_digit() || ('a'..='f').contains(&c)
                                 }),
                                 |k: &str| {
-                                    if k.len() == 64 {
-                                        Some(<[u8; 32]>::try_from(hex::decode(k).unwrap()).unwrap())
+                                    if k.len() % 2 == 0 {
+                                        Some(hex::decode(k).unwrap())
                                     } else {
                                         None
                                     }
@@ -189,7 +220,10 @@ impl Keystore {
                 // Make sure that the content of the file is valid and that it corresponds to
                 // the public key advertised in the file name.
                 match algorithm {
-                    PrivateKey::FileEd25519 => {
+                    KeyAlgorithm::Ed25519 => {
+                        let Ok(public_key) = <[u8; 32]>::try_from(&public_key[..]) else {
+                            continue;
+                        };
                         match Self::load_ed25519_from_file(keys_directory.join(entry.path())).await
                         {
                             Ok(kp) => {
@@ -200,8 +234,12 @@ impl Keystore {
                             }
                             Err(_) => continue,
                         }
+                        keys.insert((namespace, public_key), PrivateKey::FileEd25519);
                     }
-                    PrivateKey::FileSr25519 => {
+                    KeyAlgorithm::Sr25519 => {
+                        let Ok(public_key) = <[u8; 32]>::try_from(&public_key[..]) else {
+                            continue;
+                        };
                         match Self::load_sr25519_from_file(keys_directory.join(entry.path())).await
                         {
                             Ok(kp) => {
@@ -211,17 +249,33 @@ impl Keystore {
                             }
                             Err(err) => panic!("{err:?}"),
                         }
+                        keys.insert((namespace, public_key), PrivateKey::FileSr25519);
+                    }
+                    KeyAlgorithm::Ecdsa => {
+                        let Ok(public_key) = <[u8; 33]>::try_from(&public_key[..]) else {
+                            continue;
+                        };
+                        match Self::load_ecdsa_from_file(keys_directory.join(entry.path())).await {
+                            Ok(key) => {
+                                if ecdsa_public_key(&key) != public_key {
+                                    continue;
+                                }
+                            }
+                            Err(_) => continue,
+                        }
+                        ecdsa_keys.insert((namespace, public_key), EcdsaPrivateKey::File);
                     }
-                    _ => unreachable!(),
                 }
-
-                keys.insert((namespace, public_key), algorithm);
             }
         }
 
         Ok(Keystore {
             keys_directory,
-            guarded: Mutex::new(Guarded { gen_rng, keys }),
+            guarded: Mutex::new(Guarded {
+                gen_rng,
+                keys,
+                ecdsa_keys,
+            }),
             sr25519_signing_context: schnorrkel::signing_context(b"substrate"),
         })
     }
@@ -304,7 +358,9 @@ impl Keystore {
         Ok(public_key)
     }
 
-    /// Returns the list of all keys known to this keystore.
+    /// Returns the list of all Ed25519 and Sr25519 keys known to this keystore.
+    ///
+    /// ECDSA keys aren't included. Use [`Keystore::ecdsa_keys`] to obtain them.
     ///
     /// > **Note**: Keep in mind that this function is racy, as keys can be added and removed
     /// >           in parallel of this function being called.
@@ -313,6 +369,149 @@ impl Keystore {
         guarded.keys.keys().cloned().collect::<Vec<_>>().into_iter()
     }
 
+    /// Returns the list of all keys known to this keystore, alongside with their algorithm.
+    ///
+    /// > **Note**: Keep in mind that this function is racy, as keys can be added and removed
+    /// >           in parallel of this function being called.
+    ///
+    /// The size of each public key is equal to [`KeyAlgorithm::public_key_size`].
+    pub async fn keys_with_algorithm(
+        &self,
+    ) -> impl Iterator<Item = (KeyNamespace, KeyAlgorithm, Vec<u8>)> {
+        let guarded = self.guarded.lock().await;
+        guarded
+            .keys
+            .iter()
+            .map(|((namespace, public_key), key)| {
+                let algorithm = match key {
+                    PrivateKey::MemoryEd25519(_) | PrivateKey::FileEd25519 => KeyAlgorithm::Ed25519,
+                    PrivateKey::MemorySr25519(_) | PrivateKey::FileSr25519 => KeyAlgorithm::Sr25519,
+                };
+                (*namespace, algorithm, public_key.to_vec())
+            })
+            .chain(guarded.ecdsa_keys.keys().map(|(namespace, public_key)| {
+                (*namespace, KeyAlgorithm::Ecdsa, public_key.to_vec())
+            }))
+            .collect::<Vec<_>>()
+            .into_iter()
+    }
+
+    /// Returns the list of all ECDSA keys known to this keystore.
+    ///
+    /// > **Note**: Keep in mind that this function is racy, as keys can be added and removed
+    /// >           in parallel of this function being called.
+    pub async fn ecdsa_keys(&self) -> impl Iterator<Item = (KeyNamespace, [u8; 33])> {
+        let guarded = self.guarded.lock().await;
+        guarded
+            .ecdsa_keys
+            .keys()
+            .cloned()
+            .collect::<Vec<_>>()
+            .into_iter()
+    }
+
+    /// Generates a new ECDSA key over the secp256k1 curve and inserts it in the keystore.
+    ///
+    /// If `save` is `true`, the generated key is saved in the file system. This function returns
+    /// an error only if `save` is `true` and the key couldn't be written to the file system.
+    /// The value of `save` is silently ignored if no path was provided to [`Keystore::new`].
+    ///
+    /// Returns the corresponding public key, in its compressed form.
+    pub async fn generate_ecdsa(
+        &self,
+        namespace: KeyNamespace,
+        save: bool,
+    ) -> Result<[u8; 33], io::Error> {
+        let mut guarded = self.guarded.lock().await;
+
+        // A small fraction of the 32 bytes values aren't valid secp256k1 secret keys, in which
+        // case we simply try again.
+        let private_key = loop {
+            let mut bytes = zeroize::Zeroizing::new([0; 32]);
+            guarded.gen_rng.fill_bytes(&mut *bytes);
+            if libsecp256k1::SecretKey::parse(&bytes).is_ok() {
+                break bytes;
+            }
+        };
+        let public_key = ecdsa_public_key(&private_key);
+
+        let save_path = if save {
+            self.path_of_key(namespace, "ecdsa", &public_key)
+        } else {
+            None
+        };
+
+        if let Some(save_path) = save_path {
+            let mut phrase = zeroize::Zeroizing::new(vec![0; private_key.len() * 2]);
+            hex::encode_to_slice(&*private_key, &mut phrase).unwrap();
+            Self::write_to_file(&save_path, &phrase).await?;
+            guarded
+                .ecdsa_keys
+                .insert((namespace, public_key), EcdsaPrivateKey::File);
+        } else {
+            guarded.ecdsa_keys.insert(
+                (namespace, public_key),
+                EcdsaPrivateKey::Memory(private_key),
+            );
+        }
+
+        Ok(public_key)
+    }
+
+    /// Signs the given payload using the ECDSA private key associated to the public key passed
+    /// as parameter.
+    ///
+    /// The payload is hashed with BLAKE2 before being signed, and the signature contains the
+    /// recovery identifier as its last byte.
+    ///
+    /// An error is returned if the key-namespace combination is not in the keystore, or if the
+    /// key couldn't be loaded from disk. In the case when a key couldn't be loaded from disk, it
+    /// is automatically removed from the keystore.
+    pub async fn sign_ecdsa(
+        &self,
+        key_namespace: KeyNamespace,
+        public_key: &[u8; 33],
+        payload: &[u8],
+    ) -> Result<[u8; 65], SignError> {
+        let mut guarded = self.guarded.lock().await;
+        let key = guarded
+            .ecdsa_keys
+            .get(&(key_namespace, *public_key))
+            .ok_or(SignError::UnknownPublicKey)?;
+
+        let private_key = match key {
+            EcdsaPrivateKey::Memory(key) => key.clone(),
+            EcdsaPrivateKey::File => {
+                match Self::load_ecdsa_from_file(
+                    self.path_of_key(key_namespace, "ecdsa", public_key)
+                        .unwrap(),
+                )
+                .await
+                {
+                    Ok(key) => key,
+                    Err(err) => {
+                        guarded.ecdsa_keys.remove(&(key_namespace, *public_key));
+                        return Err(err.into());
+                    }
+                }
+            }
+        };
+        drop(guarded);
+
+        // The private key has been verified when it was inserted in the keystore.
+        let private_key = libsecp256k1::SecretKey::parse(&private_key).unwrap();
+        let message = libsecp256k1::Message::parse(
+            &<[u8; 32]>::try_from(blake2_rfc::blake2b::blake2b(32, &[], payload).as_bytes())
+                .unwrap(),
+        );
+        let (signature, recovery_id) = libsecp256k1::sign(&message, &private_key);
+
+        let mut out = [0; 65];
+        out[..64].copy_from_slice(&signature.serialize());
+        out[64] = recovery_id.serialize();
+        Ok(out)
+    }
+
     /// Generates a new Sr25519 key and inserts it in the keystore.
     ///
     /// If `save` is `true`, the generated key is saved in the file system. This function returns
@@ -475,9 +674,9 @@ impl Keystore {
                         }
                     }
 
-                    let (_in_out, proof, _) = key.vrf_sign(transcript);
+                    let (in_out, proof, _) = key.vrf_sign(transcript);
                     Ok(VrfSignature {
-                        // TODO: should probably output the `_in_out` as well
+                        output: in_out.to_preout().to_bytes(),
                         proof: proof.to_bytes(),
                     })
                 }
@@ -519,6 +718,22 @@ impl Keystore {
         Ok(schnorrkel_key)
     }
 
+    async fn load_ecdsa_from_file(
+        path: impl AsRef<path::Path>,
+    ) -> Result<zeroize::Zeroizing<[u8; 32]>, KeyLoadError> {
+        // TODO: read asynchronously?
+        let bytes = zeroize::Zeroizing::new(fs::read(path).map_err(KeyLoadError::Io)?);
+        let hex_key = bytes
+            .strip_prefix(b"0x")
+            .ok_or_else(|| KeyLoadError::BadFormat("Missing 0x prefix".to_owned()))?;
+        let mut private_key = zeroize::Zeroizing::new([0; 32]);
+        hex::decode_to_slice(hex_key, &mut *private_key)
+            .map_err(|err| KeyLoadError::BadFormat(err.to_string()))?;
+        libsecp256k1::SecretKey::parse(&private_key)
+            .map_err(|_| KeyLoadError::BadFormat("Invalid secp256k1 secret key".to_owned()))?;
+        Ok(private_key)
+    }
+
     async fn write_to_file_ed25519(
         path: impl AsRef<path::Path>,
         key: &ed25519_zebra::SigningKey,
@@ -574,7 +789,7 @@ impl Keystore {
         &self,
         key_namespace: KeyNamespace,
         key_algorithm: &str,
-        public_key: &[u8; 32],
+        public_key: &[u8],
     ) -> Option<path::PathBuf> {
         let keys_directory = match &self.keys_directory {
             Some(k) => k,
@@ -606,9 +821,14 @@ impl Keystore {
 struct Guarded {
     gen_rng: rand_chacha::ChaCha20Rng,
     keys: hashbrown::HashMap<(KeyNamespace, [u8; 32]), PrivateKey, SipHasherBuild>,
+    ecdsa_keys: hashbrown::HashMap<(KeyNamespace, [u8; 33]), EcdsaPrivateKey, SipHasherBuild>,
 }
 
+/// Successful outcome of [`Keystore::sign_sr25519_vrf`].
 pub struct VrfSignature {
+    /// VRF output, also called "pre-output".
+    pub output: [u8; 32],
+    /// Proof of the validity of the VRF output.
     pub proof: [u8; 64],
 }
 
@@ -650,6 +870,22 @@ enum PrivateKey {
     FileSr25519,
 }
 
+enum EcdsaPrivateKey {
+    Memory(zeroize::Zeroizing<[u8; 32]>),
+    File,
+}
+
+/// Returns the compressed public key corresponding to the given secp256k1 secret key.
+///
+/// # Panic
+///
+/// Panics if the secret key isn't valid.
+///
+fn ecdsa_public_key(private_key: &[u8; 32]) -> [u8; 33] {
+    let private_key = libsecp256k1::SecretKey::parse(private_key).unwrap();
+    libsecp256k1::PublicKey::from_secret_key(&private_key).serialize_compressed()
+}
+
 impl From<KeyLoadError> for SignError {
     fn from(err: KeyLoadError) -> SignError {
         SignError::KeyLoad(err)
@@ -664,7 +900,7 @@ impl From<KeyLoadError> for SignVrfError {
 
 #[cfg(test)]
 mod tests {
-    use super::{KeyNamespace, Keystore};
+    use super::{KeyAlgorithm, KeyNamespace, Keystore};
 
     #[test]
     fn disk_storage_works_ed25519() {
@@ -737,4 +973,97 @@ mod tests {
                 .is_ok());
         });
     }
+
+    #[test]
+    fn keys_with_algorithm() {
+        futures_executor::block_on(async move {
+            let keystore = Keystore::new(None, rand::random()).await.unwrap();
+            let ed25519_key = keystore
+                .generate_ed25519(KeyNamespace::Grandpa, false)
+                .await
+                .unwrap();
+            let sr25519_key = keystore
+                .generate_sr25519(KeyNamespace::Babe, false)
+                .await
+                .unwrap();
+            let ecdsa_key = keystore
+                .generate_ecdsa(KeyNamespace::Beefy, false)
+                .await
+                .unwrap();
+
+            let mut keys = keystore.keys_with_algorithm().await.collect::<Vec<_>>();
+            keys.sort_by_key(|(_, _, public_key)| public_key.clone());
+            let mut expected = vec![
+                (
+                    KeyNamespace::Grandpa,
+                    KeyAlgorithm::Ed25519,
+                    ed25519_key.to_vec(),
+                ),
+                (
+                    KeyNamespace::Babe,
+                    KeyAlgorithm::Sr25519,
+                    sr25519_key.to_vec(),
+                ),
+                (KeyNamespace::Beefy, KeyAlgorithm::Ecdsa, ecdsa_key.to_vec()),
+            ];
+            expected.sort_by_key(|(_, _, public_key)| public_key.clone());
+            assert_eq!(keys, expected);
+        });
+    }
+
+    #[test]
+    fn disk_storage_works_ecdsa() {
+        futures_executor::block_on(async move {
+            let path = tempfile::tempdir().unwrap();
+
+            let keystore1 = Keystore::new(Some(path.path().to_owned()), rand::random())
+                .await
+                .unwrap();
+            let public_key = keystore1
+                .generate_ecdsa(KeyNamespace::Beefy, true)
+                .await
+                .unwrap();
+            drop(keystore1);
+
+            let keystore2 = Keystore::new(Some(path.path().to_owned()), rand::random())
+                .await
+                .unwrap();
+            assert_eq!(keystore2.keys().await.next(), None);
+            assert_eq!(
+                keystore2.ecdsa_keys().await.next(),
+                Some((KeyNamespace::Beefy, public_key))
+            );
+
+            let signature = keystore2
+                .sign_ecdsa(KeyNamespace::Beefy, &public_key, b"hello world")
+                .await
+                .unwrap();
+
+            // The public key can be recovered from the signature of the hash of the message.
+            let message = libsecp256k1::Message::parse(
+                &<[u8; 32]>::try_from(
+                    blake2_rfc::blake2b::blake2b(32, &[], b"hello world").as_bytes(),
+                )
+                .unwrap(),
+            );
+            let recovered = libsecp256k1::recover(
+                &message,
+                &libsecp256k1::Signature::parse_standard_slice(&signature[..64]).unwrap(),
+                &libsecp256k1::RecoveryId::parse(signature[64]).unwrap(),
+            )
+            .unwrap();
+            assert_eq!(recovered.serialize_compressed(), public_key);
+        });
+    }
+
+    #[test]
+    fn key_type_id_round_trip() {
+        for namespace in KeyNamespace::all() {
+            assert_eq!(
+                KeyNamespace::from_key_type_id(&namespace.key_type_id()),
+                Some(namespace)
+            );
+        }
+        assert_eq!(KeyNamespace::from_key_type_id(b"xxxx"), None);
+    }
 }
@@ -61,6 +61,7 @@
 
 // TODO: write docs about usage ^
 
+pub mod dry_run;
 pub mod methods;
 pub mod parse;
 pub mod payment_info;
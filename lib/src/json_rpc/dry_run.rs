@@ -0,0 +1,319 @@
+// Smoldot
+// Copyright (C) 2023  Pierre Krieger
+// SPDX-License-Identifier: GPL-3.0-or-later WITH Classpath-exception-2.0
+
+// This program is free software: you can redistribute it and/or modify
+// it under the terms of the GNU General Public License as published by
+// the Free Software Foundation, either version 3 of the License, or
+// (at your option) any later version.
+
+// This program is distributed in the hope that it will be useful,
+// but WITHOUT ANY WARRANTY; without even the implied warranty of
+// MERCHANTABILITY or FITNESS FOR A PARTICULAR PURPOSE.  See the
+// GNU General Public License for more details.
+
+// You should have received a copy of the GNU General Public License
+// along with this program.  If not, see <http://www.gnu.org/licenses/>.
+
+//! Dry-running a transaction.
+//!
+//! Dry-running a transaction consists in calling [`APPLY_EXTRINSIC_FUNCTION_NAME`] on top of the
+//! storage of a block, without initializing a new block beforehand, and discarding the changes
+//! to the storage afterwards. The output of the call indicates whether the transaction would
+//! have been included in a block and whether its dispatch would have succeeded.
+//!
+//! The encoding of this output depends on the version of the `BlockBuilder` runtime API. Use
+//! [`decode_apply_extrinsic_result`] to decode it, and [`encode_apply_extrinsic_result`] to
+//! encode it in the format of the latest version of the API.
+
+use crate::transactions::validate::{
+    self, InvalidTransaction, TransactionValidityError, UnknownTransaction,
+};
+
+use alloc::vec::Vec;
+use core::ops;
+
+mod tests;
+
+/// Name of the runtime function to call in order to apply a transaction.
+pub const APPLY_EXTRINSIC_FUNCTION_NAME: &str = "BlockBuilder_apply_extrinsic";
+
+/// Versions of the `BlockBuilder` runtime API whose output of [`APPLY_EXTRINSIC_FUNCTION_NAME`]
+/// can be decoded with [`decode_apply_extrinsic_result`].
+pub const SUPPORTED_API_VERSIONS: ops::RangeInclusive<u32> = 4..=6;
+
+/// Outcome of applying a transaction.
+///
+/// The outer `Result` indicates whether the transaction could be included in a block, and the
+/// inner `Result` whether the dispatch of its call has succeeded.
+pub type ApplyExtrinsicResult = Result<Result<(), DispatchError>, TransactionValidityError>;
+
+/// Reason why the dispatch of a call has failed.
+#[derive(Debug, derive_more::Display, Clone, PartialEq, Eq)]
+pub enum DispatchError {
+    /// Some other error.
+    Other,
+    /// Failed to lookup some data.
+    CannotLookup,
+    /// A bad origin.
+    BadOrigin,
+    /// A custom error in a module.
+    #[display(fmt = "Error in module #{index}, error {error:?}")]
+    Module {
+        /// Module index, matching the metadata module index.
+        index: u8,
+        /// Module specific error value. Runtimes whose version of the `BlockBuilder` API is
+        /// lower than 6 only report the first byte.
+        error: [u8; 4],
+    },
+    /// At least one consumer is remaining so the account cannot be destroyed.
+    ConsumerRemaining,
+    /// There are no providers so the account cannot be created.
+    NoProviders,
+    /// There are too many consumers so the account cannot be created.
+    TooManyConsumers,
+    /// An error to do with tokens.
+    #[display(fmt = "Token error (code: {_0})")]
+    Token(u8),
+    /// An arithmetic error.
+    #[display(fmt = "Arithmetic error (code: {_0})")]
+    Arithmetic(u8),
+    /// An error to do with the transactional layers.
+    #[display(fmt = "Transactional error (code: {_0})")]
+    Transactional(u8),
+    /// Resources exhausted, for example attempt to read or write a value that is too big.
+    Exhausted,
+    /// The state is corrupt.
+    Corruption,
+    /// Some resource is unavailable.
+    Unavailable,
+    /// Root origin is not allowed.
+    RootNotAllowed,
+}
+
+/// Error potentially returned by [`decode_apply_extrinsic_result`].
+#[derive(Debug, derive_more::Display, Clone, PartialEq, Eq)]
+pub enum DecodeError {
+    /// The version of the `BlockBuilder` API isn't in [`SUPPORTED_API_VERSIONS`].
+    #[display(fmt = "Unsupported version of the BlockBuilder runtime API: {_0}")]
+    UnsupportedApiVersion(u32),
+    /// The output of the runtime can't be decoded.
+    #[display(fmt = "Failed to decode the output of the runtime")]
+    InvalidOutput,
+}
+
+/// Decodes the output of [`APPLY_EXTRINSIC_FUNCTION_NAME`], given the version of the
+/// `BlockBuilder` runtime API of the runtime that has produced it.
+pub fn decode_apply_extrinsic_result(
+    scale_encoded: &[u8],
+    api_version: u32,
+) -> Result<ApplyExtrinsicResult, DecodeError> {
+    let dispatch_error = match api_version {
+        // Versions 4 and 5 use what Substrate calls the "byte sized error", where the error of a
+        // module is a single byte and some variants don't exist yet.
+        4 | 5 => dispatch_error_v4,
+        6 => dispatch_error_v6,
+        v => return Err(DecodeError::UnsupportedApiVersion(v)),
+    };
+
+    let result: nom::IResult<_, _> = nom::combinator::all_consuming(nom::branch::alt((
+        nom::combinator::map(
+            nom::sequence::preceded(
+                nom::bytes::streaming::tag(&[0]),
+                nom::branch::alt((
+                    nom::combinator::map(nom::bytes::streaming::tag(&[0]), |_| Ok(())),
+                    nom::combinator::map(
+                        nom::sequence::preceded(nom::bytes::streaming::tag(&[1]), dispatch_error),
+                        Err,
+                    ),
+                )),
+            ),
+            Ok,
+        ),
+        nom::combinator::map(
+            nom::sequence::preceded(
+                nom::bytes::streaming::tag(&[1]),
+                validate::transaction_validity_error,
+            ),
+            Err,
+        ),
+    )))(scale_encoded);
+
+    match result {
+        Ok((_, result)) => Ok(result),
+        Err(_) => Err(DecodeError::InvalidOutput),
+    }
+}
+
+/// Encodes an [`ApplyExtrinsicResult`] in the format of the latest version of the `BlockBuilder`
+/// runtime API.
+pub fn encode_apply_extrinsic_result(result: &ApplyExtrinsicResult) -> Vec<u8> {
+    let mut out = Vec::with_capacity(7);
+
+    match result {
+        Ok(Ok(())) => out.extend_from_slice(&[0, 0]),
+        Ok(Err(error)) => {
+            out.extend_from_slice(&[0, 1]);
+            match error {
+                DispatchError::Other => out.push(0),
+                DispatchError::CannotLookup => out.push(1),
+                DispatchError::BadOrigin => out.push(2),
+                DispatchError::Module { index, error } => {
+                    out.extend_from_slice(&[3, *index]);
+                    out.extend_from_slice(error);
+                }
+                DispatchError::ConsumerRemaining => out.push(4),
+                DispatchError::NoProviders => out.push(5),
+                DispatchError::TooManyConsumers => out.push(6),
+                DispatchError::Token(code) => out.extend_from_slice(&[7, *code]),
+                DispatchError::Arithmetic(code) => out.extend_from_slice(&[8, *code]),
+                DispatchError::Transactional(code) => out.extend_from_slice(&[9, *code]),
+                DispatchError::Exhausted => out.push(10),
+                DispatchError::Corruption => out.push(11),
+                DispatchError::Unavailable => out.push(12),
+                DispatchError::RootNotAllowed => out.push(13),
+            }
+        }
+        Err(TransactionValidityError::Invalid(error)) => {
+            out.extend_from_slice(&[1, 0]);
+            match error {
+                InvalidTransaction::Call => out.push(0),
+                InvalidTransaction::Payment => out.push(1),
+                InvalidTransaction::Future => out.push(2),
+                InvalidTransaction::Stale => out.push(3),
+                InvalidTransaction::BadProof => out.push(4),
+                InvalidTransaction::AncientBirthBlock => out.push(5),
+                InvalidTransaction::ExhaustsResources => out.push(6),
+                InvalidTransaction::Custom(code) => out.extend_from_slice(&[7, *code]),
+                InvalidTransaction::BadMandatory => out.push(8),
+                InvalidTransaction::MandatoryDispatch => out.push(9),
+                InvalidTransaction::BadSigner => out.push(10),
+            }
+        }
+        Err(TransactionValidityError::Unknown(error)) => {
+            out.extend_from_slice(&[1, 1]);
+            match error {
+                UnknownTransaction::CannotLookup => out.push(0),
+                UnknownTransaction::NoUnsignedValidator => out.push(1),
+                UnknownTransaction::Custom(code) => out.extend_from_slice(&[2, *code]),
+            }
+        }
+    }
+
+    out
+}
+
+fn dispatch_error_v4(bytes: &[u8]) -> nom::IResult<&[u8], DispatchError> {
+    nom::error::context(
+        "dispatch error",
+        nom::branch::alt((
+            nom::combinator::map(nom::bytes::streaming::tag(&[0]), |_| DispatchError::Other),
+            nom::combinator::map(nom::bytes::streaming::tag(&[1]), |_| {
+                DispatchError::CannotLookup
+            }),
+            nom::combinator::map(nom::bytes::streaming::tag(&[2]), |_| {
+                DispatchError::BadOrigin
+            }),
+            nom::combinator::map(
+                nom::sequence::preceded(
+                    nom::bytes::streaming::tag(&[3]),
+                    nom::sequence::tuple((nom::number::streaming::u8, nom::number::streaming::u8)),
+                ),
+                |(index, error)| DispatchError::Module {
+                    index,
+                    error: [error, 0, 0, 0],
+                },
+            ),
+            nom::combinator::map(nom::bytes::streaming::tag(&[4]), |_| {
+                DispatchError::ConsumerRemaining
+            }),
+            nom::combinator::map(nom::bytes::streaming::tag(&[5]), |_| {
+                DispatchError::NoProviders
+            }),
+            nom::combinator::map(
+                nom::sequence::preceded(
+                    nom::bytes::streaming::tag(&[6]),
+                    nom::number::streaming::u8,
+                ),
+                DispatchError::Token,
+            ),
+            nom::combinator::map(
+                nom::sequence::preceded(
+                    nom::bytes::streaming::tag(&[7]),
+                    nom::number::streaming::u8,
+                ),
+                DispatchError::Arithmetic,
+            ),
+        )),
+    )(bytes)
+}
+
+fn dispatch_error_v6(bytes: &[u8]) -> nom::IResult<&[u8], DispatchError> {
+    nom::error::context(
+        "dispatch error",
+        nom::branch::alt((
+            nom::combinator::map(nom::bytes::streaming::tag(&[0]), |_| DispatchError::Other),
+            nom::combinator::map(nom::bytes::streaming::tag(&[1]), |_| {
+                DispatchError::CannotLookup
+            }),
+            nom::combinator::map(nom::bytes::streaming::tag(&[2]), |_| {
+                DispatchError::BadOrigin
+            }),
+            nom::combinator::map(
+                nom::sequence::preceded(
+                    nom::bytes::streaming::tag(&[3]),
+                    nom::sequence::tuple((
+                        nom::number::streaming::u8,
+                        nom::bytes::streaming::take(4u32),
+                    )),
+                ),
+                |(index, error): (_, &[u8])| DispatchError::Module {
+                    index,
+                    error: <[u8; 4]>::try_from(error).unwrap(),
+                },
+            ),
+            nom::combinator::map(nom::bytes::streaming::tag(&[4]), |_| {
+                DispatchError::ConsumerRemaining
+            }),
+            nom::combinator::map(nom::bytes::streaming::tag(&[5]), |_| {
+                DispatchError::NoProviders
+            }),
+            nom::combinator::map(nom::bytes::streaming::tag(&[6]), |_| {
+                DispatchError::TooManyConsumers
+            }),
+            nom::combinator::map(
+                nom::sequence::preceded(
+                    nom::bytes::streaming::tag(&[7]),
+                    nom::number::streaming::u8,
+                ),
+                DispatchError::Token,
+            ),
+            nom::combinator::map(
+                nom::sequence::preceded(
+                    nom::bytes::streaming::tag(&[8]),
+                    nom::number::streaming::u8,
+                ),
+                DispatchError::Arithmetic,
+            ),
+            nom::combinator::map(
+                nom::sequence::preceded(
+                    nom::bytes::streaming::tag(&[9]),
+                    nom::number::streaming::u8,
+                ),
+                DispatchError::Transactional,
+            ),
+            nom::combinator::map(nom::bytes::streaming::tag(&[10]), |_| {
+                DispatchError::Exhausted
+            }),
+            nom::combinator::map(nom::bytes::streaming::tag(&[11]), |_| {
+                DispatchError::Corruption
+            }),
+            nom::combinator::map(nom::bytes::streaming::tag(&[12]), |_| {
+                DispatchError::Unavailable
+            }),
+            nom::combinator::map(nom::bytes::streaming::tag(&[13]), |_| {
+                DispatchError::RootNotAllowed
+            }),
+        )),
+    )(bytes)
+}
@@ -0,0 +1,127 @@
+// Smoldot
+// Copyright (C) 2023  Pierre Krieger
+// SPDX-License-Identifier: GPL-3.0-or-later WITH Classpath-exception-2.0
+
+// This program is free software: you can redistribute it and/or modify
+// it under the terms of the GNU General Public License as published by
+// the Free Software Foundation, either version 3 of the License, or
+// (at your option) any later version.
+
+// This program is distributed in the hope that it will be useful,
+// but WITHOUT ANY WARRANTY; without even the implied warranty of
+// MERCHANTABILITY or FITNESS FOR A PARTICULAR PURPOSE.  See the
+// GNU General Public License for more details.
+
+// You should have received a copy of the GNU General Public License
+// along with this program.  If not, see <http://www.gnu.org/licenses/>.
+
+#![cfg(test)]
+
+use super::{
+    decode_apply_extrinsic_result, encode_apply_extrinsic_result, DecodeError, DispatchError,
+    SUPPORTED_API_VERSIONS,
+};
+use crate::transactions::validate::{
+    InvalidTransaction, TransactionValidityError, UnknownTransaction,
+};
+
+#[test]
+fn success() {
+    for api_version in SUPPORTED_API_VERSIONS {
+        let result = decode_apply_extrinsic_result(&[0, 0], api_version).unwrap();
+        assert_eq!(result, Ok(Ok(())));
+        assert_eq!(encode_apply_extrinsic_result(&result), [0, 0]);
+    }
+}
+
+#[test]
+fn dispatch_error_v6() {
+    let result = decode_apply_extrinsic_result(&[0, 1, 3, 5, 2, 0, 0, 1], 6).unwrap();
+    assert_eq!(
+        result,
+        Ok(Err(DispatchError::Module {
+            index: 5,
+            error: [2, 0, 0, 1]
+        }))
+    );
+    assert_eq!(
+        encode_apply_extrinsic_result(&result),
+        [0, 1, 3, 5, 2, 0, 0, 1]
+    );
+
+    let result = decode_apply_extrinsic_result(&[0, 1, 8, 1], 6).unwrap();
+    assert_eq!(result, Ok(Err(DispatchError::Arithmetic(1))));
+    assert_eq!(encode_apply_extrinsic_result(&result), [0, 1, 8, 1]);
+}
+
+#[test]
+fn dispatch_error_v4_converted_to_latest() {
+    // Versions 4 and 5 encode the error of a module as a single byte.
+    for api_version in [4, 5] {
+        let result = decode_apply_extrinsic_result(&[0, 1, 3, 5, 2], api_version).unwrap();
+        assert_eq!(
+            result,
+            Ok(Err(DispatchError::Module {
+                index: 5,
+                error: [2, 0, 0, 0]
+            }))
+        );
+        assert_eq!(
+            encode_apply_extrinsic_result(&result),
+            [0, 1, 3, 5, 2, 0, 0, 0]
+        );
+
+        // `TooManyConsumers` doesn't exist yet, and the variants after it are shifted by one.
+        let result = decode_apply_extrinsic_result(&[0, 1, 6, 2], api_version).unwrap();
+        assert_eq!(result, Ok(Err(DispatchError::Token(2))));
+        assert_eq!(encode_apply_extrinsic_result(&result), [0, 1, 7, 2]);
+    }
+}
+
+#[test]
+fn invalid_transaction() {
+    for api_version in SUPPORTED_API_VERSIONS {
+        let result = decode_apply_extrinsic_result(&[1, 0, 4], api_version).unwrap();
+        assert_eq!(
+            result,
+            Err(TransactionValidityError::Invalid(
+                InvalidTransaction::BadProof
+            ))
+        );
+        assert_eq!(encode_apply_extrinsic_result(&result), [1, 0, 4]);
+
+        let result = decode_apply_extrinsic_result(&[1, 1, 2, 42], api_version).unwrap();
+        assert_eq!(
+            result,
+            Err(TransactionValidityError::Unknown(
+                UnknownTransaction::Custom(42)
+            ))
+        );
+        assert_eq!(encode_apply_extrinsic_result(&result), [1, 1, 2, 42]);
+    }
+}
+
+#[test]
+fn unsupported_api_version() {
+    for api_version in [0, 3, 7] {
+        assert_eq!(
+            decode_apply_extrinsic_result(&[0, 0], api_version),
+            Err(DecodeError::UnsupportedApiVersion(api_version))
+        );
+    }
+}
+
+#[test]
+fn invalid_output() {
+    // Truncated, trailing data, and unknown variants.
+    for output in [&[][..], &[0], &[0, 0, 0], &[0, 1, 14], &[2]] {
+        assert_eq!(
+            decode_apply_extrinsic_result(output, 6),
+            Err(DecodeError::InvalidOutput)
+        );
+    }
+    assert_eq!(
+        decode_apply_extrinsic_result(&[0, 1, 8], 4),
+        Err(DecodeError::InvalidOutput)
+    );
+}
@@ -424,13 +424,18 @@ define_methods! {
     state_queryStorageAt(keys: Vec<HexString>, at: Option<HashHexString>) -> Vec<StorageChangeSet>, // TODO:
     state_subscribeRuntimeVersion() -> Cow<'a, str> [chain_subscribeRuntimeVersion],
     state_subscribeStorage(list: Vec<HexString>) -> Cow<'a, str>,
+    /// Executes again the given block against the storage of its parent and returns a trace of
+    /// this execution. The format of the returned value is specific to smoldot.
+    state_traceBlock(block: HashHexString) -> BlockTrace,
     state_unsubscribeRuntimeVersion(subscription: Cow<'a, str>) -> bool [chain_unsubscribeRuntimeVersion],
     state_unsubscribeStorage(subscription: Cow<'a, str>) -> bool,
     system_accountNextIndex(account: AccountId) -> u64,
     system_addReservedPeer() -> (), // TODO:
     system_chain() -> Cow<'a, str>,
     system_chainType() -> Cow<'a, str>,
-    system_dryRun() -> () [system_dryRunAt], // TODO:
+    /// Applies the given extrinsic on top of the storage of the given block, without storing the
+    /// result, and returns the SCALE-encoded `ApplyExtrinsicResult`.
+    system_dryRun(extrinsic: HexString, hash: Option<HashHexString>) -> HexString [system_dryRunAt],
     system_health() -> SystemHealth,
     system_localListenAddresses() -> Vec<String>,
     /// Returns the Base58 encoding of the network identity of the node on the peer-to-peer network.
@@ -731,7 +736,10 @@ pub enum FollowEvent<'a> {
         operation_id: Cow<'a, str>,
     },
     #[serde(rename = "operationWaitingForContinue")]
-    OperationWaitingForContinue,
+    OperationWaitingForContinue {
+        #[serde(rename = "operationId")]
+        operation_id: Cow<'a, str>,
+    },
     #[serde(rename = "operationError")]
     OperationError {
         #[serde(rename = "operationId")]
@@ -1087,6 +1095,70 @@ pub struct StorageChangeSet {
     pub changes: Vec<(HexString, Option<HexString>)>,
 }
 
+#[derive(Debug, Clone, serde::Serialize)]
+pub struct BlockTrace {
+    #[serde(rename = "blockHash")]
+    pub block_hash: HashHexString,
+    #[serde(rename = "hostFunctionCalls")]
+    pub host_function_calls: Vec<BlockTraceHostFunctionCall>,
+    #[serde(rename = "storageAccesses")]
+    pub storage_accesses: Vec<BlockTraceStorageAccess>,
+    pub allocator: BlockTraceAllocatorStats,
+    /// Error that happened during the execution, if any.
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub error: Option<String>,
+}
+
+#[derive(Debug, Clone, serde::Serialize)]
+pub struct BlockTraceHostFunctionCall {
+    pub name: String,
+    /// Parameters of the host function. 32 bits parameters are sign-extended.
+    pub parameters: Vec<i64>,
+    #[serde(rename = "durationNanos")]
+    pub duration_nanos: u64,
+}
+
+#[derive(Debug, Clone, serde::Serialize)]
+pub struct BlockTraceStorageAccess {
+    #[serde(rename = "type")]
+    pub ty: BlockTraceStorageAccessType,
+    #[serde(rename = "childTrie", skip_serializing_if = "Option::is_none")]
+    pub child_trie: Option<HexString>,
+    pub key: HexString,
+    #[serde(rename = "valueSize")]
+    pub value_size: Option<u64>,
+}
+
+#[derive(Debug, Clone, serde::Serialize)]
+pub enum BlockTraceStorageAccessType {
+    #[serde(rename = "read")]
+    Read,
+    #[serde(rename = "write")]
+    Write,
+    #[serde(rename = "append")]
+    Append,
+    #[serde(rename = "clearPrefix")]
+    ClearPrefix,
+}
+
+#[derive(Debug, Clone, serde::Serialize)]
+pub struct BlockTraceAllocatorStats {
+    #[serde(rename = "bytesAllocated")]
+    pub bytes_allocated: u32,
+    #[serde(rename = "bytesAllocatedPeak")]
+    pub bytes_allocated_peak: u32,
+    #[serde(rename = "bytesAllocatedSum")]
+    pub bytes_allocated_sum: u64,
+    #[serde(rename = "addressSpaceUsed")]
+    pub address_space_used: u32,
+    #[serde(rename = "addressSpaceUsedPeak")]
+    pub address_space_used_peak: u32,
+    #[serde(rename = "fragmentedBytes")]
+    pub fragmented_bytes: u32,
+    #[serde(rename = "allocationsPerOrder")]
+    pub allocations_per_order: Vec<u64>,
+}
+
 #[derive(Debug, Clone)]
 pub struct SystemHealth {
     pub is_syncing: bool,
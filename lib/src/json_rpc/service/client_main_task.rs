@@ -407,6 +407,7 @@ impl ClientMainTask {
                 | methods::MethodCall::state_getStorageSize { .. }
                 | methods::MethodCall::state_queryStorage { .. }
                 | methods::MethodCall::state_queryStorageAt { .. }
+                | methods::MethodCall::state_traceBlock { .. }
                 | methods::MethodCall::system_accountNextIndex { .. }
                 | methods::MethodCall::system_addReservedPeer { .. }
                 | methods::MethodCall::system_chain { .. }
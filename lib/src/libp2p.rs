@@ -93,10 +93,12 @@
 
 pub mod collection;
 pub mod connection;
+pub mod memory;
 pub mod multiaddr;
 pub mod multihash;
 pub mod peer_id;
 pub mod peers;
+pub mod quic;
 pub mod read_write;
 pub mod websocket;
 pub mod with_buffers;
@@ -47,7 +47,7 @@
 //! the calls to [`Network::inject_connection_message`].
 //!
 
-use crate::libp2p::connection::noise;
+use crate::libp2p::connection::{noise, tls};
 
 use super::connection::{established, single_stream_handshake};
 use alloc::{
@@ -104,6 +104,18 @@ pub enum MultiStreamHandshakeKind<'a> {
         /// Multihash encoding of the TLS certificate used by the remote node at the DTLS layer.
         remote_tls_certificate_multihash: Vec<u8>,
     },
+
+    /// The connection is a QUIC connection.
+    ///
+    /// The encryption and the authentication are performed by the TLS 1.3 handshake of QUIC.
+    /// See <https://github.com/libp2p/specs/blob/master/quic/README.md> for details.
+    ///
+    /// Substreams are QUIC streams, and can be closed by either side.
+    Quic {
+        /// DER-encoded X.509 certificate presented by the remote during the TLS handshake. Its
+        /// libp2p extension is used to determine the [`PeerId`] of the remote.
+        remote_tls_certificate: Vec<u8>,
+    },
 }
 
 /// Configuration for a [`Network`].
@@ -468,33 +480,6 @@ where
         let connection_id = self.next_connection_id;
         self.next_connection_id.0 += 1;
 
-        // In the WebRTC handshake, the Noise prologue must be set to `"libp2p-webrtc-noise:"`
-        // followed with the multihash-encoded fingerprints of the initiator's certificate
-        // and the receiver's certificate.
-        // See <https://github.com/libp2p/specs/pull/412>.
-        let (noise_key, noise_prologue) = {
-            let MultiStreamHandshakeKind::WebRtc {
-                noise_key,
-                local_tls_certificate_multihash,
-                remote_tls_certificate_multihash,
-            } = handshake_kind;
-            const PREFIX: &[u8] = b"libp2p-webrtc-noise:";
-            let mut out = Vec::with_capacity(
-                PREFIX.len()
-                    + local_tls_certificate_multihash.len()
-                    + remote_tls_certificate_multihash.len(),
-            );
-            out.extend_from_slice(PREFIX);
-            if is_initiator {
-                out.extend_from_slice(&local_tls_certificate_multihash);
-                out.extend_from_slice(&remote_tls_certificate_multihash);
-            } else {
-                out.extend_from_slice(&remote_tls_certificate_multihash);
-                out.extend_from_slice(&local_tls_certificate_multihash);
-            }
-            (noise_key, out)
-        };
-
         // TODO: could be precalculated
         let max_protocol_name_len = self
             .request_response_protocols
@@ -515,16 +500,47 @@ where
         let substreams_capacity =
             self.request_response_protocols.len() + self.notification_protocols.len() * 2 + 2;
 
-        let handshake = {
-            let mut noise_ephemeral_key = zeroize::Zeroizing::new([0; 32]);
-            self.randomness_seeds.fill_bytes(&mut *noise_ephemeral_key);
-            noise::HandshakeInProgress::new(noise::Config {
-                key: noise_key,
-                // It's the "server" that initiates the Noise handshake.
-                is_initiator: !is_initiator,
-                prologue: &noise_prologue,
-                ephemeral_secret_key: &noise_ephemeral_key,
-            })
+        let handshake = match handshake_kind {
+            MultiStreamHandshakeKind::WebRtc {
+                noise_key,
+                local_tls_certificate_multihash,
+                remote_tls_certificate_multihash,
+            } => {
+                // In the WebRTC handshake, the Noise prologue must be set to
+                // `"libp2p-webrtc-noise:"` followed with the multihash-encoded fingerprints of
+                // the initiator's certificate and the receiver's certificate.
+                // See <https://github.com/libp2p/specs/pull/412>.
+                let noise_prologue = {
+                    const PREFIX: &[u8] = b"libp2p-webrtc-noise:";
+                    let mut out = Vec::with_capacity(
+                        PREFIX.len()
+                            + local_tls_certificate_multihash.len()
+                            + remote_tls_certificate_multihash.len(),
+                    );
+                    out.extend_from_slice(PREFIX);
+                    if is_initiator {
+                        out.extend_from_slice(&local_tls_certificate_multihash);
+                        out.extend_from_slice(&remote_tls_certificate_multihash);
+                    } else {
+                        out.extend_from_slice(&remote_tls_certificate_multihash);
+                        out.extend_from_slice(&local_tls_certificate_multihash);
+                    }
+                    out
+                };
+
+                let mut noise_ephemeral_key = zeroize::Zeroizing::new([0; 32]);
+                self.randomness_seeds.fill_bytes(&mut *noise_ephemeral_key);
+                multi_stream::Handshake::WebRtc(noise::HandshakeInProgress::new(noise::Config {
+                    key: noise_key,
+                    // It's the "server" that initiates the Noise handshake.
+                    is_initiator: !is_initiator,
+                    prologue: &noise_prologue,
+                    ephemeral_secret_key: &noise_ephemeral_key,
+                }))
+            }
+            MultiStreamHandshakeKind::Quic {
+                remote_tls_certificate,
+            } => multi_stream::Handshake::Quic(tls::verify_certificate(&remote_tls_certificate)),
         };
 
         let connection_task = MultiStreamConnectionTask::new(
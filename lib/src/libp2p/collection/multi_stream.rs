@@ -19,12 +19,12 @@ use crate::util::{leb128, protobuf};
 
 use super::{
     super::{
-        connection::{established, noise},
+        connection::{established, noise, tls},
         read_write::ReadWrite,
     },
     ConnectionToCoordinator, ConnectionToCoordinatorInner, CoordinatorToConnection,
-    CoordinatorToConnectionInner, InboundTy, NotificationsOutErr, PeerId, ShutdownCause,
-    SubstreamFate, SubstreamId,
+    CoordinatorToConnectionInner, HandshakeError, InboundTy, NotificationsOutErr, PeerId,
+    ShutdownCause, SubstreamFate, SubstreamId,
 };
 
 use alloc::{collections::VecDeque, string::ToString as _, sync::Arc, vec::Vec};
@@ -127,12 +127,23 @@ enum MultiStreamConnectionTaskInner<TNow, TSubId> {
     },
 }
 
+/// Handshake to perform, passed to [`MultiStreamConnectionTask::new`].
+pub(super) enum Handshake {
+    /// Noise handshake performed on the first substream, as is the case for WebRTC.
+    WebRtc(noise::HandshakeInProgress),
+    /// The handshake has already been performed by the transport, as is the case for QUIC.
+    /// Contains the outcome of the verification of the TLS certificate of the remote.
+    Quic(Result<PeerId, tls::VerifyError>),
+}
+
 #[derive(Debug, Copy, Clone, PartialEq, Eq)]
 enum ShutdownInitiator {
     /// The coordinator sent a [`CoordinatorToConnectionInner::StartShutdown`] message.
     Coordinator,
     /// [`MultiStreamConnectionTask::reset`] has been called.
     Api,
+    /// The shutdown has been initiated due to a protocol error.
+    Remote,
 }
 
 impl<TNow, TSubId> MultiStreamConnectionTask<TNow, TSubId>
@@ -145,14 +156,25 @@ where
     pub(super) fn new(
         randomness_seed: [u8; 32],
         now: TNow,
-        handshake: noise::HandshakeInProgress,
+        handshake: Handshake,
         max_inbound_substreams: usize,
         substreams_capacity: usize,
         max_protocol_name_len: usize,
         ping_protocol: Arc<str>,
     ) -> Self {
-        MultiStreamConnectionTask {
-            connection: MultiStreamConnectionTaskInner::Handshake {
+        let config = established::Config {
+            max_inbound_substreams,
+            substreams_capacity,
+            max_protocol_name_len,
+            randomness_seed,
+            ping_protocol: ping_protocol.to_string(), // TODO: cloning :-/
+            ping_interval: Duration::from_secs(20),   // TODO: hardcoded
+            ping_timeout: Duration::from_secs(10),    // TODO: hardcoded
+            first_out_ping: now + Duration::from_secs(2), // TODO: hardcoded
+        };
+
+        let connection = match handshake {
+            Handshake::WebRtc(handshake) => MultiStreamConnectionTaskInner::Handshake {
                 handshake: Some(handshake),
                 opened_substream: None,
                 handshake_read_buffer: Vec::new(),
@@ -160,18 +182,31 @@ where
                     0,
                     Default::default(),
                 ),
-                established: Some(established::MultiStream::webrtc(established::Config {
-                    max_inbound_substreams,
-                    substreams_capacity,
-                    max_protocol_name_len,
-                    randomness_seed,
-                    ping_protocol: ping_protocol.to_string(), // TODO: cloning :-/
-                    ping_interval: Duration::from_secs(20),   // TODO: hardcoded
-                    ping_timeout: Duration::from_secs(10),    // TODO: hardcoded
-                    first_out_ping: now + Duration::from_secs(2), // TODO: hardcoded
-                })),
+                established: Some(established::MultiStream::webrtc(config)),
             },
-        }
+            Handshake::Quic(Ok(remote_peer_id)) => MultiStreamConnectionTaskInner::Established {
+                established: established::MultiStream::quic(config),
+                handshake_substream: None,
+                handshake_finished_message_to_send: Some(remote_peer_id),
+                outbound_substreams_map: hashbrown::HashMap::with_capacity_and_hasher(
+                    0,
+                    Default::default(),
+                ),
+                notifications_in_open_cancel_acknowledgments: VecDeque::with_capacity(4),
+                inbound_negotiated_cancel_acknowledgments:
+                    hashbrown::HashSet::with_capacity_and_hasher(2, Default::default()),
+                inbound_accept_cancel_events: VecDeque::with_capacity(2),
+            },
+            Handshake::Quic(Err(err)) => MultiStreamConnectionTaskInner::ShutdownWaitingAck {
+                initiator: ShutdownInitiator::Remote,
+                start_shutdown_message_to_send: Some(Some(ShutdownCause::HandshakeError(
+                    HandshakeError::TlsCertificate(err),
+                ))),
+                shutdown_finish_message_sent: false,
+            },
+        };
+
+        MultiStreamConnectionTask { connection }
     }
 
     /// Pulls a message to send back to the coordinator.
@@ -630,7 +665,7 @@ where
             | (
                 CoordinatorToConnectionInner::StartShutdown,
                 MultiStreamConnectionTaskInner::ShutdownWaitingAck {
-                    initiator: ShutdownInitiator::Api,
+                    initiator: ShutdownInitiator::Api | ShutdownInitiator::Remote,
                     ..
                 },
             ) => {
@@ -866,10 +901,14 @@ where
     ) -> SubstreamFate {
         // In WebRTC, the reading and writing sides are never closed.
         // Note that the `established::MultiStream` state machine also performs this check, but
-        // we do it here again because we're not necessarily in the ̀`established` state.
+        // we do it here again because we're not necessarily in the ̀`established` state. Only
+        // WebRTC connections go through the `Handshake` state.
         assert!(
-            read_write.expected_incoming_bytes.is_some()
-                && read_write.write_bytes_queueable.is_some()
+            !matches!(
+                self.connection,
+                MultiStreamConnectionTaskInner::Handshake { .. }
+            ) || (read_write.expected_incoming_bytes.is_some()
+                && read_write.write_bytes_queueable.is_some())
         );
 
         match &mut self.connection {
@@ -896,15 +935,14 @@ where
                 // TODO: this is very suboptimal; improve
                 // TODO: this doesn't properly back-pressure, because we read unconditionally
                 let (protobuf_frame_size, flags) = {
-                    let mut parser = nom::combinator::complete::<_, _, nom::error::Error<&[u8]>, _>(
-                        nom::combinator::map_parser(
+                    let mut parser =
+                        nom::combinator::map_parser::<_, _, _, nom::error::Error<&[u8]>, _, _>(
                             nom::multi::length_data(crate::util::leb128::nom_leb128_usize),
                             protobuf::message_decode! {
                                 #[optional] flags = 1 => protobuf::enum_tag_decode,
                                 #[optional] message = 2 => protobuf::bytes_tag_decode,
                             },
-                        ),
-                    );
+                        );
 
                     match parser(&read_write.incoming_buffer) {
                         Ok((rest, framed_message)) => {
@@ -912,18 +950,21 @@ where
                                 handshake_read_buffer.extend_from_slice(message);
                             }
 
-                            let protobuf_frame_size = handshake_read_buffer.len() - rest.len();
+                            let protobuf_frame_size = read_write.incoming_buffer.len() - rest.len();
                             (protobuf_frame_size, framed_message.flags)
                         }
                         Err(nom::Err::Incomplete(needed)) => {
                             read_write.expected_incoming_bytes = Some(
-                                handshake_read_buffer.len()
+                                read_write.incoming_buffer.len()
                                     + match needed {
                                         nom::Needed::Size(s) => s.get(),
                                         nom::Needed::Unknown => 1,
                                     },
                             );
-                            return SubstreamFate::Continue;
+                            // No full frame has been received, but the Noise handshake must
+                            // still be given the possibility to write out data, as the local
+                            // node might be the one sending the first handshake message.
+                            (0, None)
                         }
                         Err(_) => {
                             // Message decoding error.
@@ -974,16 +1015,22 @@ where
                     let tag = protobuf::tag_encode(2, 2).collect::<Vec<_>>();
                     let data_len = leb128::encode_usize(written_bytes).collect::<Vec<_>>();
                     let libp2p_prefix =
-                        leb128::encode_usize(tag.len() + data_len.len()).collect::<Vec<_>>();
+                        leb128::encode_usize(tag.len() + data_len.len() + written_bytes)
+                            .collect::<Vec<_>>();
 
                     // The spec mentions that a frame plus its length prefix shouldn't exceed
                     // 16kiB. This is normally ensured by forbidding the substream from writing
                     // more data than would fit in 16kiB.
-                    debug_assert!(libp2p_prefix.len() + tag.len() + data_len.len() <= 16384);
+                    debug_assert!(
+                        libp2p_prefix.len() + tag.len() + data_len.len() + written_bytes <= 16384
+                    );
 
                     read_write.write_out(libp2p_prefix);
                     read_write.write_out(tag);
                     read_write.write_out(data_len);
+                    for buffer in sub_read_write.write_buffers {
+                        read_write.write_out(buffer);
+                    }
                 }
 
                 match handshake_outcome {
@@ -991,7 +1038,16 @@ where
                         *handshake = Some(handshake_update);
                         SubstreamFate::Continue
                     }
-                    Err(_err) => todo!("{:?}", _err), // TODO: /!\
+                    Err(err) => {
+                        self.connection = MultiStreamConnectionTaskInner::ShutdownWaitingAck {
+                            initiator: ShutdownInitiator::Remote,
+                            start_shutdown_message_to_send: Some(Some(
+                                ShutdownCause::HandshakeError(HandshakeError::NoiseHandshake(err)),
+                            )),
+                            shutdown_finish_message_sent: false,
+                        };
+                        SubstreamFate::Reset
+                    }
                     Ok(noise::NoiseHandshake::Success {
                         cipher: _,
                         remote_peer_id,
@@ -85,6 +85,12 @@ pub struct MultiStream<TNow, TSubId, TSubUd> {
     ping_interval: Duration,
     /// See [`Config::ping_timeout`].
     ping_timeout: Duration,
+
+    /// If `true`, the data of each substream is wrapped within Protobuf frames, and the closing
+    /// of the writing side and resets are indicated through flags within these frames, as is
+    /// the case for WebRTC. If `false`, substreams directly contain the data and can be closed,
+    /// as is the case for QUIC.
+    webrtc_framing: bool,
 }
 
 struct Substream<TNow, TSubUd> {
@@ -109,8 +115,17 @@ where
     TNow: Clone + Add<Duration, Output = TNow> + Sub<TNow, Output = Duration> + Ord,
     TSubId: Clone + PartialEq + Eq + Hash,
 {
-    /// Creates a new connection from the given configuration.
+    /// Creates a new WebRTC connection from the given configuration.
     pub fn webrtc(config: Config<TNow>) -> MultiStream<TNow, TSubId, TSubUd> {
+        Self::new(config, true)
+    }
+
+    /// Creates a new QUIC connection from the given configuration.
+    pub fn quic(config: Config<TNow>) -> MultiStream<TNow, TSubId, TSubUd> {
+        Self::new(config, false)
+    }
+
+    fn new(config: Config<TNow>, webrtc_framing: bool) -> MultiStream<TNow, TSubId, TSubUd> {
         let mut randomness = rand_chacha::ChaCha20Rng::from_seed(config.randomness_seed);
 
         MultiStream {
@@ -146,6 +161,7 @@ where
             ping_protocol: config.ping_protocol,
             ping_interval: config.ping_interval,
             ping_timeout: config.ping_timeout,
+            webrtc_framing,
         }
     }
 
@@ -242,7 +258,7 @@ where
     pub fn reset_substream(&mut self, substream_id: &TSubId) {
         let mut substream = self.in_substreams.remove(substream_id).unwrap();
         let _was_in = self.out_in_substreams_map.remove(&substream.id);
-        debug_assert!(_was_in.is_none());
+        debug_assert!(_was_in.is_some());
 
         if Some(substream_id) == self.ping_substream.as_ref() {
             self.ping_substream = None;
@@ -286,8 +302,9 @@ where
 
         // In WebRTC, the reading and writing side is never closed.
         assert!(
-            read_write.expected_incoming_bytes.is_some()
-                && read_write.write_bytes_queueable.is_some()
+            !self.webrtc_framing
+                || (read_write.expected_incoming_bytes.is_some()
+                    && read_write.write_bytes_queueable.is_some())
         );
 
         // Reading/writing the ping substream is used to queue new outgoing pings.
@@ -306,6 +323,42 @@ where
             read_write.wake_up_after(&self.next_ping);
         }
 
+        // Without WebRTC framing, the substream state machine directly reads and writes the
+        // data of the substream.
+        if !self.webrtc_framing {
+            loop {
+                // Don't process any more data before events are pulled.
+                if self.pending_events.len() >= MAX_PENDING_EVENTS {
+                    return SubstreamFate::Continue;
+                }
+
+                let (substream_update, event) =
+                    substream.inner.take().unwrap().read_write(read_write);
+                substream.inner = substream_update;
+
+                let has_event = event.is_some();
+                if let Some(event) = event {
+                    Self::on_substream_event(
+                        &mut self.pending_events,
+                        substream.id,
+                        &mut substream.user_data,
+                        event,
+                    )
+                }
+
+                if substream.inner.is_none() {
+                    if Some(substream_id) == self.ping_substream.as_ref() {
+                        self.ping_substream = None;
+                    }
+                    self.out_in_substreams_map.remove(&substream.id);
+                    self.in_substreams.remove(substream_id);
+                    return SubstreamFate::Reset;
+                } else if !has_event {
+                    return SubstreamFate::Continue;
+                }
+            }
+        }
+
         loop {
             // Don't process any more data before events are pulled.
             if self.pending_events.len() >= MAX_PENDING_EVENTS {
@@ -452,17 +505,37 @@ where
                     // TODO: don't do the encoding manually but use the protobuf module?
                     let tag = protobuf::tag_encode(2, 2).collect::<Vec<_>>();
                     let data_len = leb128::encode_usize(written_bytes).collect::<Vec<_>>();
-                    let libp2p_prefix =
-                        leb128::encode_usize(tag.len() + data_len.len()).collect::<Vec<_>>();
+                    let flag = flag_to_write_out
+                        .map(|flag| {
+                            protobuf::enum_tag_encode(1, flag)
+                                .flat_map(|b| b.as_ref().to_vec())
+                                .collect::<Vec<_>>()
+                        })
+                        .unwrap_or_default();
+                    let libp2p_prefix = leb128::encode_usize(
+                        flag.len() + tag.len() + data_len.len() + written_bytes,
+                    )
+                    .collect::<Vec<_>>();
 
                     // The spec mentions that a frame plus its length prefix shouldn't exceed
                     // 16kiB. This is normally ensured by forbidding the substream from writing
                     // more data than would fit in 16kiB.
-                    debug_assert!(libp2p_prefix.len() + tag.len() + data_len.len() <= 16384);
+                    debug_assert!(
+                        libp2p_prefix.len()
+                            + flag.len()
+                            + tag.len()
+                            + data_len.len()
+                            + written_bytes
+                            <= 16384
+                    );
 
                     read_write.write_out(libp2p_prefix);
+                    read_write.write_out(flag);
                     read_write.write_out(tag);
                     read_write.write_out(data_len);
+                    for buffer in sub_read_write.write_buffers {
+                        read_write.write_out(buffer);
+                    }
 
                     // We continue looping because the substream might have more data to send.
                     continue_looping = true;
@@ -485,7 +558,7 @@ where
             }
 
             // WebRTC never closes the writing side.
-            debug_assert!(read_write.write_bytes_queueable.is_none());
+            debug_assert!(read_write.write_bytes_queueable.is_some());
 
             if substream.inner.is_none() {
                 if Some(substream_id) == self.ping_substream.as_ref() {
@@ -38,7 +38,7 @@ use super::{
     established::ConnectionPrototype,
     multistream_select,
     noise::{self, NoiseKey},
-    yamux,
+    tls, yamux,
 };
 
 use alloc::boxed::Box;
@@ -318,4 +318,7 @@ pub enum HandshakeError {
     /// Error in the noise cipher. Data has most likely been corrupted.
     #[display(fmt = "Noise cipher error: {_0}")]
     Noise(noise::CipherError),
+    /// Certificate presented by the remote during the TLS handshake is invalid.
+    #[display(fmt = "TLS certificate error: {_0}")]
+    TlsCertificate(tls::VerifyError),
 }
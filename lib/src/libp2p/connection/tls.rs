@@ -0,0 +1,463 @@
+// Smoldot
+// Copyright (C) 2023  Pierre Krieger
+// SPDX-License-Identifier: GPL-3.0-or-later WITH Classpath-exception-2.0
+
+// This program is free software: you can redistribute it and/or modify
+// it under the terms of the GNU General Public License as published by
+// the Free Software Foundation, either version 3 of the License, or
+// (at your option) any later version.
+
+// This program is distributed in the hope that it will be useful,
+// but WITHOUT ANY WARRANTY; without even the implied warranty of
+// MERCHANTABILITY or FITNESS FOR A PARTICULAR PURPOSE.  See the
+// GNU General Public License for more details.
+
+// You should have received a copy of the GNU General Public License
+// along with this program.  If not, see <http://www.gnu.org/licenses/>.
+
+//! Libp2p TLS certificates.
+//!
+//! When a connection is encrypted with TLS 1.3, which is notably the case of QUIC connections,
+//! libp2p doesn't use the Noise protocol in order to authenticate the remote. Instead, each side
+//! presents a self-signed X.509 certificate containing a specific extension. This extension
+//! contains the libp2p public key of the node, and a signature of the public key of the
+//! certificate made using the libp2p private key.
+//!
+//! See <https://github.com/libp2p/specs/blob/master/tls/tls.md> for details.
+//!
+//! # Usage
+//!
+//! Use [`generate_certificate`] in order to build the certificate and private key that the local
+//! node presents to the remote during the TLS handshake.
+//!
+//! Use [`verify_certificate`] in order to verify the certificate presented by the remote and
+//! determine its [`PeerId`].
+//!
+//! This module doesn't verify the signature of the certificate itself, as the TLS handshake
+//! already ensures that the remote owns the private key of the certificate it presents.
+
+use crate::libp2p::peer_id::{self, PeerId};
+
+use alloc::vec::Vec;
+use core::fmt;
+
+/// Identifier of the X.509 extension containing the libp2p public key and signature.
+///
+/// Corresponds to the object identifier `1.3.6.1.4.1.53594.1.1`.
+const LIBP2P_EXTENSION_OID: &[u8] = &[0x2b, 0x06, 0x01, 0x04, 0x01, 0x83, 0xa2, 0x5a, 0x01, 0x01];
+
+/// Identifier of the Ed25519 signature algorithm. Corresponds to `1.3.101.112`.
+const ED25519_OID: &[u8] = &[0x2b, 0x65, 0x70];
+
+/// Identifier of the "common name" attribute. Corresponds to `2.5.4.3`.
+const COMMON_NAME_OID: &[u8] = &[0x55, 0x04, 0x03];
+
+/// Prefix of the message signed with the libp2p private key.
+const SIGNATURE_PREFIX: &[u8] = b"libp2p-tls-handshake:";
+
+/// Protocol name to negotiate with ALPN during the TLS handshake.
+pub const ALPN_PROTOCOL: &[u8] = b"libp2p";
+
+/// Certificate and its private key, as generated by [`generate_certificate`].
+#[derive(Clone, PartialEq, Eq)]
+pub struct Certificate {
+    /// DER encoding of the X.509 certificate.
+    pub certificate_der: Vec<u8>,
+    /// DER encoding of the private key of the certificate, in the PKCS#8 format.
+    pub private_key_pkcs8_der: Vec<u8>,
+}
+
+impl fmt::Debug for Certificate {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        // The private key is intentionally not printed.
+        f.debug_struct("Certificate")
+            .field("certificate_der", &hex::encode(&self.certificate_der))
+            .finish_non_exhaustive()
+    }
+}
+
+/// Builds a self-signed certificate containing the libp2p extension.
+///
+/// The certificate uses an Ed25519 key, whose private key is passed as
+/// `certificate_ed25519_private_key`. This private key should be randomly generated and doesn't
+/// need to be persisted.
+pub fn generate_certificate(
+    libp2p_ed25519_private_key: &[u8; 32],
+    certificate_ed25519_private_key: &[u8; 32],
+) -> Certificate {
+    let certificate_signing_key = ed25519_zebra::SigningKey::from(*certificate_ed25519_private_key);
+    let certificate_public_key = <[u8; 32]>::from(ed25519_zebra::VerificationKey::from(
+        &certificate_signing_key,
+    ));
+
+    let algorithm_identifier = der_encode(0x30, &der_encode(0x06, ED25519_OID));
+
+    let subject_public_key_info = der_encode(
+        0x30,
+        &[
+            &algorithm_identifier[..],
+            &der_encode(0x03, &[&[0][..], &certificate_public_key[..]].concat()),
+        ]
+        .concat(),
+    );
+
+    let libp2p_extension = {
+        let libp2p_signing_key = ed25519_zebra::SigningKey::from(*libp2p_ed25519_private_key);
+        let libp2p_public_key = peer_id::PublicKey::Ed25519(<[u8; 32]>::from(
+            ed25519_zebra::VerificationKey::from(&libp2p_signing_key),
+        ));
+        let signature =
+            libp2p_signing_key.sign(&[SIGNATURE_PREFIX, &subject_public_key_info[..]].concat());
+
+        let signed_key = der_encode(
+            0x30,
+            &[
+                &der_encode(0x04, &libp2p_public_key.to_protobuf_encoding())[..],
+                &der_encode(0x04, &<[u8; 64]>::from(signature))[..],
+            ]
+            .concat(),
+        );
+
+        // The extension is marked as critical, as required by the specification.
+        der_encode(
+            0x30,
+            &[
+                &der_encode(0x06, LIBP2P_EXTENSION_OID)[..],
+                &der_encode(0x01, &[0xff])[..],
+                &der_encode(0x04, &signed_key)[..],
+            ]
+            .concat(),
+        )
+    };
+
+    // The specification doesn't put any requirement on the issuer and subject, but X.509
+    // requires the issuer to be non-empty.
+    let name = der_encode(
+        0x30,
+        &der_encode(
+            0x31,
+            &der_encode(
+                0x30,
+                &[
+                    &der_encode(0x06, COMMON_NAME_OID)[..],
+                    &der_encode(0x0c, b"libp2p")[..],
+                ]
+                .concat(),
+            ),
+        ),
+    );
+
+    let tbs_certificate = der_encode(
+        0x30,
+        &[
+            // Version 3 of X.509, which is necessary in order to have extensions.
+            &der_encode(0xa0, &der_encode(0x02, &[2]))[..],
+            // The serial number must be positive, hence the leading zero.
+            &der_encode(0x02, &[&[0][..], &certificate_public_key[..16]].concat())[..],
+            &algorithm_identifier[..],
+            &name[..],
+            // The certificate is valid from 1975 to 4096, as its validity period isn't
+            // meaningful.
+            &der_encode(
+                0x30,
+                &[
+                    &der_encode(0x17, b"750101000000Z")[..],
+                    &der_encode(0x18, b"40960101000000Z")[..],
+                ]
+                .concat(),
+            )[..],
+            &name[..],
+            &subject_public_key_info[..],
+            &der_encode(0xa3, &der_encode(0x30, &libp2p_extension))[..],
+        ]
+        .concat(),
+    );
+
+    let certificate_signature = certificate_signing_key.sign(&tbs_certificate);
+
+    let certificate_der = der_encode(
+        0x30,
+        &[
+            &tbs_certificate[..],
+            &algorithm_identifier[..],
+            &der_encode(
+                0x03,
+                &[&[0][..], &<[u8; 64]>::from(certificate_signature)[..]].concat(),
+            )[..],
+        ]
+        .concat(),
+    );
+
+    // See RFC 8410 for the format of Ed25519 private keys.
+    let private_key_pkcs8_der = der_encode(
+        0x30,
+        &[
+            &der_encode(0x02, &[0])[..],
+            &algorithm_identifier[..],
+            &der_encode(0x04, &der_encode(0x04, certificate_ed25519_private_key))[..],
+        ]
+        .concat(),
+    );
+
+    Certificate {
+        certificate_der,
+        private_key_pkcs8_der,
+    }
+}
+
+/// Verifies the libp2p extension of the given DER-encoded X.509 certificate, and returns the
+/// [`PeerId`] of the node it belongs to.
+pub fn verify_certificate(certificate_der: &[u8]) -> Result<PeerId, VerifyError> {
+    let ((_, subject_public_key_info), mut tbs_fields) =
+        decode_until_subject_public_key_info(certificate_der)?;
+
+    // Skip the optional issuer and subject unique identifiers.
+    while matches!(tbs_fields.first(), Some(0x81 | 0x82 | 0xa1 | 0xa2)) {
+        tbs_fields = der_decode(tbs_fields[0], tbs_fields)?.1;
+    }
+
+    let extensions = if tbs_fields.is_empty() {
+        return Err(VerifyError::MissingLibp2pExtension);
+    } else {
+        let (extensions, rest) = der_decode(0xa3, tbs_fields)?;
+        if !rest.is_empty() {
+            return Err(VerifyError::InvalidCertificate);
+        }
+        der_decode_exact(0x30, extensions.0)?.0
+    };
+
+    let mut libp2p_extension = None;
+    let mut extensions = extensions;
+    while !extensions.is_empty() {
+        let ((extension, _), rest) = der_decode(0x30, extensions)?;
+        extensions = rest;
+
+        let ((oid, _), mut extension) = der_decode(0x06, extension)?;
+        if extension.first() == Some(&0x01) {
+            extension = der_decode(0x01, extension)?.1; // Critical flag.
+        }
+        let (value, _) = der_decode_exact(0x04, extension)?;
+
+        if oid == LIBP2P_EXTENSION_OID {
+            if libp2p_extension.is_some() {
+                return Err(VerifyError::DuplicateLibp2pExtension);
+            }
+            libp2p_extension = Some(value);
+        }
+    }
+
+    let Some(libp2p_extension) = libp2p_extension else {
+        return Err(VerifyError::MissingLibp2pExtension);
+    };
+
+    let (public_key, signature) = (|| {
+        let (signed_key, _) = der_decode_exact(0x30, libp2p_extension)?;
+        let ((public_key, _), rest) = der_decode(0x04, signed_key)?;
+        let (signature, _) = der_decode_exact(0x04, rest)?;
+        Ok((public_key, signature))
+    })()
+    .map_err(|_: VerifyError| VerifyError::InvalidLibp2pExtension)?;
+
+    let public_key = peer_id::PublicKey::from_protobuf_encoding(public_key)
+        .map_err(VerifyError::InvalidPublicKey)?;
+    public_key
+        .verify(
+            &[SIGNATURE_PREFIX, subject_public_key_info].concat(),
+            signature,
+        )
+        .map_err(|_| VerifyError::BadSignature)?;
+
+    Ok(public_key.into_peer_id())
+}
+
+/// Returns the public key of the given DER-encoded X.509 certificate.
+///
+/// This is the key that the remote uses to sign the TLS handshake, and not its libp2p public
+/// key.
+pub fn certificate_public_key(
+    certificate_der: &[u8],
+) -> Result<CertificatePublicKey<'_>, VerifyError> {
+    let ((subject_public_key_info, _), _) = decode_until_subject_public_key_info(certificate_der)?;
+    let ((algorithm, _), rest) = der_decode(0x30, subject_public_key_info)?;
+    let (public_key, _) = der_decode_exact(0x03, rest)?;
+
+    // The first byte of a bit string indicates the number of unused bits, which must be 0 here.
+    match public_key.split_first() {
+        Some((0, public_key)) => Ok(CertificatePublicKey {
+            algorithm,
+            public_key,
+        }),
+        _ => Err(VerifyError::InvalidCertificate),
+    }
+}
+
+/// Public key of a certificate, as returned by [`certificate_public_key`].
+#[derive(Debug, Copy, Clone, PartialEq, Eq)]
+pub struct CertificatePublicKey<'a> {
+    /// DER-encoded content of the `AlgorithmIdentifier` of the key, without the header of the
+    /// `SEQUENCE`.
+    pub algorithm: &'a [u8],
+    /// Raw public key, whose format depends on the algorithm.
+    pub public_key: &'a [u8],
+}
+
+/// Error potentially returned by [`verify_certificate`] and [`certificate_public_key`].
+#[derive(Debug, derive_more::Display)]
+pub enum VerifyError {
+    /// Failed to decode the certificate.
+    InvalidCertificate,
+    /// Certificate doesn't contain the libp2p extension.
+    MissingLibp2pExtension,
+    /// Certificate contains the libp2p extension multiple times.
+    DuplicateLibp2pExtension,
+    /// Failed to decode the libp2p extension.
+    InvalidLibp2pExtension,
+    /// Failed to decode the libp2p public key found in the libp2p extension.
+    #[display(fmt = "Invalid libp2p public key: {_0}")]
+    InvalidPublicKey(peer_id::FromProtobufEncodingError),
+    /// Signature of the public key of the certificate doesn't match the libp2p public key.
+    BadSignature,
+}
+
+/// Decodes the given DER-encoded X.509 certificate up to its subject public key info.
+///
+/// Returns the subject public key info element (see [`der_decode`]) and the fields of the
+/// `TBSCertificate` that follow it.
+#[allow(clippy::type_complexity)]
+fn decode_until_subject_public_key_info(
+    certificate_der: &[u8],
+) -> Result<((&[u8], &[u8]), &[u8]), VerifyError> {
+    let (certificate, _) = der_decode_exact(0x30, certificate_der)?;
+    let (tbs_certificate, _) = der_decode(0x30, certificate)?.0;
+
+    // Skip the fields that precede the subject public key info.
+    let mut tbs_fields = tbs_certificate;
+    if tbs_fields.first() == Some(&0xa0) {
+        tbs_fields = der_decode(0xa0, tbs_fields)?.1;
+    }
+    tbs_fields = der_decode(0x02, tbs_fields)?.1; // Serial number.
+    tbs_fields = der_decode(0x30, tbs_fields)?.1; // Signature algorithm.
+    tbs_fields = der_decode(0x30, tbs_fields)?.1; // Issuer.
+    tbs_fields = der_decode(0x30, tbs_fields)?.1; // Validity.
+    tbs_fields = der_decode(0x30, tbs_fields)?.1; // Subject.
+
+    der_decode(0x30, tbs_fields)
+}
+
+/// Encodes the given DER tag and content.
+fn der_encode(tag: u8, content: &[u8]) -> Vec<u8> {
+    let mut out = Vec::with_capacity(content.len() + 6);
+    out.push(tag);
+    if content.len() < 0x80 {
+        out.push(u8::try_from(content.len()).unwrap());
+    } else {
+        let len_bytes = u32::try_from(content.len()).unwrap().to_be_bytes();
+        let num_leading_zeroes = len_bytes.iter().take_while(|b| **b == 0).count();
+        out.push(0x80 | u8::try_from(len_bytes.len() - num_leading_zeroes).unwrap());
+        out.extend_from_slice(&len_bytes[num_leading_zeroes..]);
+    }
+    out.extend_from_slice(content);
+    out
+}
+
+/// Decodes a DER element with the given tag at the start of `input`.
+///
+/// Returns the content of the element, the entire encoded element, and the data that follows
+/// the element.
+#[allow(clippy::type_complexity)]
+fn der_decode(tag: u8, input: &[u8]) -> Result<((&[u8], &[u8]), &[u8]), VerifyError> {
+    if input.first() != Some(&tag) {
+        return Err(VerifyError::InvalidCertificate);
+    }
+
+    let (length, header_len) = match *input.get(1).ok_or(VerifyError::InvalidCertificate)? {
+        len @ 0..=0x7f => (usize::from(len), 2),
+        len_len @ 0x81..=0x84 => {
+            let len_len = usize::from(len_len & 0x7f);
+            let len_bytes = input
+                .get(2..2 + len_len)
+                .ok_or(VerifyError::InvalidCertificate)?;
+            let length = len_bytes
+                .iter()
+                .fold(0usize, |acc, b| (acc << 8) | usize::from(*b));
+            (length, 2 + len_len)
+        }
+        _ => return Err(VerifyError::InvalidCertificate),
+    };
+
+    let total_len = header_len
+        .checked_add(length)
+        .ok_or(VerifyError::InvalidCertificate)?;
+    let element = input
+        .get(..total_len)
+        .ok_or(VerifyError::InvalidCertificate)?;
+    Ok(((&element[header_len..], element), &input[total_len..]))
+}
+
+/// Same as [`der_decode`], but returns an error if any data follows the element.
+fn der_decode_exact(tag: u8, input: &[u8]) -> Result<(&[u8], &[u8]), VerifyError> {
+    let (element, rest) = der_decode(tag, input)?;
+    if !rest.is_empty() {
+        return Err(VerifyError::InvalidCertificate);
+    }
+    Ok(element)
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::libp2p::peer_id::PublicKey;
+
+    #[test]
+    fn generate_and_verify() {
+        let libp2p_private_key = [7; 32];
+        let certificate = super::generate_certificate(&libp2p_private_key, &[9; 32]);
+
+        let expected_peer_id =
+            PublicKey::Ed25519(<[u8; 32]>::from(ed25519_zebra::VerificationKey::from(
+                &ed25519_zebra::SigningKey::from(libp2p_private_key),
+            )))
+            .into_peer_id();
+
+        assert_eq!(
+            super::verify_certificate(&certificate.certificate_der).unwrap(),
+            expected_peer_id
+        );
+    }
+
+    #[test]
+    fn certificate_public_key() {
+        let certificate = super::generate_certificate(&[1; 32], &[2; 32]);
+        let public_key = super::certificate_public_key(&certificate.certificate_der).unwrap();
+
+        assert_eq!(public_key.algorithm, &[0x06, 0x03, 0x2b, 0x65, 0x70]);
+        assert_eq!(
+            public_key.public_key,
+            <[u8; 32]>::from(ed25519_zebra::VerificationKey::from(
+                &ed25519_zebra::SigningKey::from([2; 32]),
+            ))
+        );
+    }
+
+    #[test]
+    fn tampered_certificate_rejected() {
+        let certificate = super::generate_certificate(&[1; 32], &[2; 32]);
+
+        // Modify one byte of the public key of the certificate, which is covered by the
+        // signature found in the libp2p extension.
+        let mut certificate_der = certificate.certificate_der;
+        let position = certificate_der
+            .windows(32)
+            .position(|w| {
+                w == <[u8; 32]>::from(ed25519_zebra::VerificationKey::from(
+                    &ed25519_zebra::SigningKey::from([2; 32]),
+                ))
+            })
+            .unwrap();
+        certificate_der[position] ^= 1;
+
+        assert!(matches!(
+            super::verify_certificate(&certificate_der),
+            Err(super::VerifyError::BadSignature)
+        ));
+    }
+}
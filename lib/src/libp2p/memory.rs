@@ -0,0 +1,439 @@
+// Smoldot
+// Copyright (C) 2023  Pierre Krieger
+// SPDX-License-Identifier: GPL-3.0-or-later WITH Classpath-exception-2.0
+
+// This program is free software: you can redistribute it and/or modify
+// it under the terms of the GNU General Public License as published by
+// the Free Software Foundation, either version 3 of the License, or
+// (at your option) any later version.
+
+// This program is distributed in the hope that it will be useful,
+// but WITHOUT ANY WARRANTY; without even the implied warranty of
+// MERCHANTABILITY or FITNESS FOR A PARTICULAR PURPOSE.  See the
+// GNU General Public License for more details.
+
+// You should have received a copy of the GNU General Public License
+// along with this program.  If not, see <http://www.gnu.org/licenses/>.
+
+//! In-process transport corresponding to `/memory/...` multiaddresses.
+//!
+//! A [`MemoryNetwork`] is a registry of listeners, each identified by a port number. Calling
+//! [`MemoryNetwork::connect`] with the port of a listener creates a pair of [`MemoryStream`]s,
+//! one of which is returned to the caller while the other is yielded by
+//! [`MemoryListener::accept`].
+//!
+//! [`MemoryStream`]s implement the `AsyncRead` and `AsyncWrite` traits, and behave similarly to
+//! TCP sockets: the writing side can be closed, and dropping a stream without closing its
+//! writing side is equivalent to resetting it.
+//!
+//! Multiple [`MemoryNetwork`]s are completely independent from each other. In particular, the
+//! same port can be listened on in multiple networks. This makes it possible to run multiple
+//! tests in parallel within the same process.
+
+#![cfg(feature = "std")]
+#![cfg_attr(docsrs, doc(cfg(feature = "std")))]
+
+use alloc::{collections::VecDeque, sync::Arc};
+use core::{
+    cmp, fmt, future,
+    pin::Pin,
+    task::{Context, Poll, Waker},
+};
+use futures_util::{AsyncRead, AsyncWrite};
+use std::{io, sync::Mutex};
+
+/// Maximum number of bytes that are buffered within each direction of a [`MemoryStream`].
+/// Writing more data than this is back-pressured until the remote reads it.
+const PIPE_CAPACITY: usize = 64 * 1024;
+
+/// Collection of listeners. See the module-level documentation.
+///
+/// Cloning a [`MemoryNetwork`] returns a handle to the same network.
+#[derive(Clone, Default)]
+pub struct MemoryNetwork {
+    inner: Arc<Mutex<NetworkInner>>,
+}
+
+#[derive(Default)]
+struct NetworkInner {
+    /// List of active listeners, indexed by port.
+    listeners: hashbrown::HashMap<u64, Arc<ListenerShared>, fnv::FnvBuildHasher>,
+    /// Port to assign to the dialing side of the next connection.
+    next_dialer_port: u64,
+}
+
+impl MemoryNetwork {
+    /// Creates a new empty network.
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    /// Starts listening on the given port.
+    ///
+    /// Returns an error of kind [`io::ErrorKind::AddrInUse`] if a listener for this port already
+    /// exists. The port is released when the [`MemoryListener`] is dropped.
+    pub fn listen(&self, port: u64) -> Result<MemoryListener, io::Error> {
+        let mut inner = self.inner.lock().unwrap();
+
+        let shared = match inner.listeners.entry(port) {
+            hashbrown::hash_map::Entry::Occupied(_) => {
+                return Err(io::Error::from(io::ErrorKind::AddrInUse))
+            }
+            hashbrown::hash_map::Entry::Vacant(entry) => entry
+                .insert(Arc::new(ListenerShared {
+                    pending: Mutex::new(ListenerPending {
+                        connections: VecDeque::new(),
+                        waker: None,
+                    }),
+                }))
+                .clone(),
+        };
+
+        Ok(MemoryListener {
+            network: self.clone(),
+            port,
+            shared,
+        })
+    }
+
+    /// Opens a connection to the listener of the given port.
+    ///
+    /// Returns an error of kind [`io::ErrorKind::ConnectionRefused`] if nothing listens on this
+    /// port.
+    pub fn connect(&self, port: u64) -> Result<MemoryStream, io::Error> {
+        let mut inner = self.inner.lock().unwrap();
+
+        let Some(listener) = inner.listeners.get(&port).cloned() else {
+            return Err(io::Error::from(io::ErrorKind::ConnectionRefused));
+        };
+
+        // Dialers are assigned ports in the upper half of the port space, similar to the
+        // ephemeral ports of TCP.
+        let dialer_port = (1 << 63) | inner.next_dialer_port;
+        inner.next_dialer_port = inner.next_dialer_port.wrapping_add(1) & !(1 << 63);
+
+        let (dialer, listener_side) = MemoryStream::pair();
+
+        let mut pending = listener.pending.lock().unwrap();
+        pending.connections.push_back((listener_side, dialer_port));
+        if let Some(waker) = pending.waker.take() {
+            waker.wake();
+        }
+
+        Ok(dialer)
+    }
+}
+
+impl fmt::Debug for MemoryNetwork {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        f.debug_set()
+            .entries(self.inner.lock().unwrap().listeners.keys())
+            .finish()
+    }
+}
+
+/// Listener registered within a [`MemoryNetwork`]. See [`MemoryNetwork::listen`].
+pub struct MemoryListener {
+    network: MemoryNetwork,
+    port: u64,
+    shared: Arc<ListenerShared>,
+}
+
+struct ListenerShared {
+    pending: Mutex<ListenerPending>,
+}
+
+struct ListenerPending {
+    /// Connections that haven't been yielded by [`MemoryListener::accept`] yet, and the port
+    /// assigned to their dialing side.
+    connections: VecDeque<(MemoryStream, u64)>,
+    /// Waker of the task currently calling [`MemoryListener::accept`].
+    waker: Option<Waker>,
+}
+
+impl MemoryListener {
+    /// Returns the port this listener is listening on.
+    pub fn port(&self) -> u64 {
+        self.port
+    }
+
+    /// Waits for a new incoming connection, and returns it alongside with the port assigned to
+    /// the dialing side.
+    pub async fn accept(&self) -> (MemoryStream, u64) {
+        future::poll_fn(|cx| {
+            let mut pending = self.shared.pending.lock().unwrap();
+            if let Some(connection) = pending.connections.pop_front() {
+                return Poll::Ready(connection);
+            }
+            pending.waker = Some(cx.waker().clone());
+            Poll::Pending
+        })
+        .await
+    }
+}
+
+impl fmt::Debug for MemoryListener {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        f.debug_tuple("MemoryListener").field(&self.port).finish()
+    }
+}
+
+impl Drop for MemoryListener {
+    fn drop(&mut self) {
+        let mut inner = self.network.inner.lock().unwrap();
+        let _was_in = inner.listeners.remove(&self.port);
+        debug_assert!(_was_in.is_some());
+    }
+}
+
+/// One side of a connection created through [`MemoryNetwork::connect`].
+pub struct MemoryStream {
+    /// Data sent by the remote to the local side.
+    incoming: Arc<Mutex<Pipe>>,
+    /// Data sent by the local side to the remote.
+    outgoing: Arc<Mutex<Pipe>>,
+}
+
+/// Data traveling in one direction.
+struct Pipe {
+    /// Data that has been written but not read yet.
+    buffer: VecDeque<u8>,
+    /// State of the writing side.
+    writer: WriterState,
+    /// `true` if the reading side has been dropped.
+    reader_dropped: bool,
+    /// Waker of the task currently waiting for data to read.
+    reader_waker: Option<Waker>,
+    /// Waker of the task currently waiting for space in [`Pipe::buffer`].
+    writer_waker: Option<Waker>,
+}
+
+#[derive(Debug, Copy, Clone, PartialEq, Eq)]
+enum WriterState {
+    Open,
+    Closed,
+    Reset,
+}
+
+impl MemoryStream {
+    /// Creates two streams connected to each other.
+    fn pair() -> (MemoryStream, MemoryStream) {
+        let new_pipe = || {
+            Arc::new(Mutex::new(Pipe {
+                buffer: VecDeque::new(),
+                writer: WriterState::Open,
+                reader_dropped: false,
+                reader_waker: None,
+                writer_waker: None,
+            }))
+        };
+
+        let a_to_b = new_pipe();
+        let b_to_a = new_pipe();
+
+        (
+            MemoryStream {
+                incoming: b_to_a.clone(),
+                outgoing: a_to_b.clone(),
+            },
+            MemoryStream {
+                incoming: a_to_b,
+                outgoing: b_to_a,
+            },
+        )
+    }
+}
+
+impl AsyncRead for MemoryStream {
+    fn poll_read(
+        self: Pin<&mut Self>,
+        cx: &mut Context<'_>,
+        buf: &mut [u8],
+    ) -> Poll<io::Result<usize>> {
+        let mut pipe = self.incoming.lock().unwrap();
+
+        if !pipe.buffer.is_empty() {
+            let num_read = cmp::min(buf.len(), pipe.buffer.len());
+            for (out, byte) in buf.iter_mut().zip(pipe.buffer.drain(..num_read)) {
+                *out = byte;
+            }
+            if let Some(waker) = pipe.writer_waker.take() {
+                waker.wake();
+            }
+            return Poll::Ready(Ok(num_read));
+        }
+
+        match pipe.writer {
+            WriterState::Open => {
+                pipe.reader_waker = Some(cx.waker().clone());
+                Poll::Pending
+            }
+            WriterState::Closed => Poll::Ready(Ok(0)),
+            WriterState::Reset => Poll::Ready(Err(io::Error::from(io::ErrorKind::ConnectionReset))),
+        }
+    }
+}
+
+impl AsyncWrite for MemoryStream {
+    fn poll_write(
+        self: Pin<&mut Self>,
+        cx: &mut Context<'_>,
+        buf: &[u8],
+    ) -> Poll<io::Result<usize>> {
+        let mut pipe = self.outgoing.lock().unwrap();
+
+        if pipe.reader_dropped {
+            return Poll::Ready(Err(io::Error::from(io::ErrorKind::BrokenPipe)));
+        }
+        if pipe.writer != WriterState::Open {
+            return Poll::Ready(Err(io::Error::from(io::ErrorKind::NotConnected)));
+        }
+        if buf.is_empty() {
+            return Poll::Ready(Ok(0));
+        }
+
+        let num_written = cmp::min(buf.len(), PIPE_CAPACITY.saturating_sub(pipe.buffer.len()));
+        if num_written == 0 {
+            pipe.writer_waker = Some(cx.waker().clone());
+            return Poll::Pending;
+        }
+
+        pipe.buffer.extend(&buf[..num_written]);
+        if let Some(waker) = pipe.reader_waker.take() {
+            waker.wake();
+        }
+        Poll::Ready(Ok(num_written))
+    }
+
+    fn poll_flush(self: Pin<&mut Self>, _: &mut Context<'_>) -> Poll<io::Result<()>> {
+        Poll::Ready(Ok(()))
+    }
+
+    fn poll_close(self: Pin<&mut Self>, _: &mut Context<'_>) -> Poll<io::Result<()>> {
+        let mut pipe = self.outgoing.lock().unwrap();
+        if pipe.writer == WriterState::Open {
+            pipe.writer = WriterState::Closed;
+            if let Some(waker) = pipe.reader_waker.take() {
+                waker.wake();
+            }
+        }
+        Poll::Ready(Ok(()))
+    }
+}
+
+impl fmt::Debug for MemoryStream {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        f.debug_tuple("MemoryStream").finish()
+    }
+}
+
+impl Drop for MemoryStream {
+    fn drop(&mut self) {
+        // Dropping the stream without closing its writing side resets it.
+        let mut outgoing = self.outgoing.lock().unwrap();
+        if outgoing.writer == WriterState::Open {
+            outgoing.writer = WriterState::Reset;
+            outgoing.buffer.clear();
+        }
+        if let Some(waker) = outgoing.reader_waker.take() {
+            waker.wake();
+        }
+        drop(outgoing);
+
+        let mut incoming = self.incoming.lock().unwrap();
+        incoming.reader_dropped = true;
+        incoming.buffer.clear();
+        if let Some(waker) = incoming.writer_waker.take() {
+            waker.wake();
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::MemoryNetwork;
+    use futures_util::{AsyncReadExt as _, AsyncWriteExt as _};
+    use std::io;
+
+    #[test]
+    fn connect_without_listener() {
+        let network = MemoryNetwork::new();
+        assert_eq!(
+            network.connect(5).unwrap_err().kind(),
+            io::ErrorKind::ConnectionRefused
+        );
+
+        let listener = network.listen(5).unwrap();
+        assert!(network.connect(5).is_ok());
+
+        drop(listener);
+        assert_eq!(
+            network.connect(5).unwrap_err().kind(),
+            io::ErrorKind::ConnectionRefused
+        );
+    }
+
+    #[test]
+    fn port_in_use() {
+        let network = MemoryNetwork::new();
+        let _listener = network.listen(5).unwrap();
+        assert_eq!(
+            network.listen(5).unwrap_err().kind(),
+            io::ErrorKind::AddrInUse
+        );
+
+        // Networks are independent from each other.
+        assert!(MemoryNetwork::new().listen(5).is_ok());
+    }
+
+    #[test]
+    fn send_and_close() {
+        futures_executor::block_on(async {
+            let network = MemoryNetwork::new();
+            let listener = network.listen(1).unwrap();
+
+            let mut dialer = network.connect(1).unwrap();
+            let (mut listener_side, _) = listener.accept().await;
+
+            // More data than fits in the pipe is sent in order to test the back-pressure.
+            let data = (0..super::PIPE_CAPACITY * 3)
+                .map(|n| n as u8)
+                .collect::<Vec<_>>();
+            let data_clone = data.clone();
+            let writer = async move {
+                dialer.write_all(&data_clone).await.unwrap();
+                dialer.close().await.unwrap();
+            };
+            let reader = async move {
+                let mut received = Vec::new();
+                listener_side.read_to_end(&mut received).await.unwrap();
+                received
+            };
+
+            let ((), received) = futures_util::future::join(writer, reader).await;
+            assert_eq!(received, data);
+        });
+    }
+
+    #[test]
+    fn drop_resets() {
+        futures_executor::block_on(async {
+            let network = MemoryNetwork::new();
+            let listener = network.listen(1).unwrap();
+
+            let mut dialer = network.connect(1).unwrap();
+            let (listener_side, _) = listener.accept().await;
+            drop(listener_side);
+
+            let mut buf = [0; 16];
+            assert_eq!(
+                dialer.read(&mut buf).await.unwrap_err().kind(),
+                io::ErrorKind::ConnectionReset
+            );
+            assert_eq!(
+                dialer.write(&buf).await.unwrap_err().kind(),
+                io::ErrorKind::BrokenPipe
+            );
+        });
+    }
+}
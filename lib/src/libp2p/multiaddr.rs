@@ -210,6 +210,7 @@ pub enum ProtocolRef<'a> {
     Ip6([u8; 16]),
     P2p(Cow<'a, [u8]>), // TODO: a bit hacky because there's no "owned" equivalent to MultihashRef
     Quic,
+    QuicV1,
     Tcp(u16),
     Tls,
     Udp(u16),
@@ -271,6 +272,8 @@ impl<'a> ProtocolRef<'a> {
                     port.parse().map_err(|_| ParseError::InvalidPort)?,
                 ))
             }
+            "quic" => Ok(ProtocolRef::Quic),
+            "quic-v1" => Ok(ProtocolRef::QuicV1),
             "tls" => Ok(ProtocolRef::Tls),
             "udp" => {
                 let port = iter.next().ok_or(ParseError::UnexpectedEof)?;
@@ -323,6 +326,7 @@ impl<'a> ProtocolRef<'a> {
             ProtocolRef::Ip6(_) => 41,
             ProtocolRef::P2p(_) => 421,
             ProtocolRef::Quic => 460,
+            ProtocolRef::QuicV1 => 461,
             ProtocolRef::Tcp(_) => 6,
             ProtocolRef::Tls => 448,
             ProtocolRef::Udp(_) => 273,
@@ -388,6 +392,7 @@ impl<'a> fmt::Display for ProtocolRef<'a> {
                 write!(f, "/p2p/{}", bs58::encode(multihash).into_string())
             }
             ProtocolRef::Quic => write!(f, "/quic"),
+            ProtocolRef::QuicV1 => write!(f, "/quic-v1"),
             ProtocolRef::Tcp(port) => write!(f, "/tcp/{port}"),
             ProtocolRef::Tls => write!(f, "/tls"),
             ProtocolRef::Udp(port) => write!(f, "/udp/{port}"),
@@ -540,6 +545,7 @@ fn protocol<'a, E: nom::error::ParseError<&'a [u8]>>(
             )(bytes),
             448 => Ok((bytes, ProtocolRef::Tls)),
             460 => Ok((bytes, ProtocolRef::Quic)),
+            461 => Ok((bytes, ProtocolRef::QuicV1)),
             477 => Ok((bytes, ProtocolRef::Ws)),
             478 => Ok((bytes, ProtocolRef::Wss)),
             // TODO: unclear what the /memory payload is, see https://github.com/multiformats/multiaddr/issues/127
@@ -596,6 +602,8 @@ mod tests {
         check_valid("/dnsaddr/./tcp/55");
         check_valid("/memory/1234567890");
         check_valid("/webrtc-direct");
+        check_valid("/ip4/1.2.3.4/udp/30333/quic");
+        check_valid("/ip6/::1/udp/30333/quic-v1");
         // TODO: example valid /certhash
 
         check_invalid("/");
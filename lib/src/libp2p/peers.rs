@@ -228,6 +228,16 @@ pub enum MultiStreamHandshakeKind {
         /// Multihash encoding of the TLS certificate used by the remote node at the DTLS layer.
         remote_tls_certificate_multihash: Vec<u8>,
     },
+
+    /// The connection is a QUIC connection.
+    ///
+    /// See <https://github.com/libp2p/specs/blob/master/quic/README.md> for details.
+    ///
+    /// Substreams are QUIC streams, and can be closed by either side.
+    Quic {
+        /// DER-encoded X.509 certificate presented by the remote during the TLS handshake.
+        remote_tls_certificate: Vec<u8>,
+    },
 }
 
 impl<TConn, TNow> Peers<TConn, TNow>
@@ -1051,6 +1061,11 @@ where
                     local_tls_certificate_multihash,
                     remote_tls_certificate_multihash,
                 },
+                MultiStreamHandshakeKind::Quic {
+                    remote_tls_certificate,
+                } => collection::MultiStreamHandshakeKind::Quic {
+                    remote_tls_certificate,
+                },
             },
             false,
             Connection {
@@ -1095,6 +1110,11 @@ where
                     local_tls_certificate_multihash,
                     remote_tls_certificate_multihash,
                 },
+                MultiStreamHandshakeKind::Quic {
+                    remote_tls_certificate,
+                } => collection::MultiStreamHandshakeKind::Quic {
+                    remote_tls_certificate,
+                },
             },
             true,
             Connection {
@@ -0,0 +1,408 @@
+// Smoldot
+// Copyright (C) 2023  Pierre Krieger
+// SPDX-License-Identifier: GPL-3.0-or-later WITH Classpath-exception-2.0
+
+// This program is free software: you can redistribute it and/or modify
+// it under the terms of the GNU General Public License as published by
+// the Free Software Foundation, either version 3 of the License, or
+// (at your option) any later version.
+
+// This program is distributed in the hope that it will be useful,
+// but WITHOUT ANY WARRANTY; without even the implied warranty of
+// MERCHANTABILITY or FITNESS FOR A PARTICULAR PURPOSE.  See the
+// GNU General Public License for more details.
+
+// You should have received a copy of the GNU General Public License
+// along with this program.  If not, see <http://www.gnu.org/licenses/>.
+
+//! Helpers for opening libp2p QUIC connections with the `quinn` library.
+//!
+//! The QUIC and TLS layers are entirely handled by `quinn`. This module provides the
+//! configurations that make `quinn` present and accept the certificates defined by the libp2p
+//! TLS specification (see the [`tls`] module), and a wrapper around QUIC streams that can be
+//! passed to [`super::with_buffers::WithBuffers`].
+//!
+//! Once a connection is established, the certificate of the remote (see [`remote_certificate`])
+//! must be passed to the connection state machine, which is responsible for deriving the
+//! `PeerId` of the remote from it.
+//!
+//! See also <https://github.com/libp2p/specs/blob/master/quic/README.md>.
+
+#![cfg(feature = "quic")]
+#![cfg_attr(docsrs, doc(cfg(feature = "quic")))]
+
+use crate::libp2p::connection::tls;
+
+use core::{
+    pin::Pin,
+    task::{Context, Poll},
+    time::Duration,
+};
+use futures_util::{AsyncRead, AsyncWrite};
+use rustls::{
+    client::danger::{HandshakeSignatureValid, ServerCertVerified, ServerCertVerifier},
+    crypto::CryptoProvider,
+    pki_types::{CertificateDer, PrivateKeyDer, PrivatePkcs8KeyDer, ServerName, UnixTime},
+    server::danger::{ClientCertVerified, ClientCertVerifier},
+    DigitallySignedStruct, DistinguishedName, SignatureScheme,
+};
+use std::{io, sync::Arc};
+
+/// Version number of the QUIC protocol to use in order to reach a `/quic-v1` multiaddress.
+pub const VERSION_1: u32 = 1;
+
+/// Version number of the QUIC protocol to use in order to reach a `/quic` multiaddress.
+pub const DRAFT_29_VERSION: u32 = 0xff00_001d;
+
+/// Server name to pass to `quinn` when connecting. Libp2p doesn't use the server name, but it
+/// must be a valid DNS name.
+pub const SERVER_NAME: &str = "l";
+
+/// Builds the `quinn` configuration of a QUIC server presenting the given certificate.
+///
+/// Remotes are required to present a valid libp2p certificate as well.
+///
+/// # Panic
+///
+/// Panics if the certificate or its private key is invalid. This can't happen for certificates
+/// generated with [`tls::generate_certificate`].
+///
+pub fn server_config(certificate: &tls::Certificate) -> quinn::ServerConfig {
+    let provider = Arc::new(rustls::crypto::ring::default_provider());
+
+    let mut crypto = rustls::ServerConfig::builder_with_provider(provider.clone())
+        .with_protocol_versions(&[&rustls::version::TLS13])
+        .unwrap()
+        .with_client_cert_verifier(Arc::new(Libp2pCertificateVerifier {
+            provider: provider.clone(),
+        }))
+        .with_cert_resolver(Arc::new(AlwaysResolvesCertificate::new(
+            &provider,
+            certificate,
+        )));
+    crypto.alpn_protocols = vec![tls::ALPN_PROTOCOL.to_vec()];
+
+    let mut config = quinn::ServerConfig::with_crypto(Arc::new(
+        quinn::crypto::rustls::QuicServerConfig::try_from(crypto).unwrap(),
+    ));
+    config.transport_config(transport_config());
+    config
+}
+
+/// Builds the `quinn` configuration of a QUIC client presenting the given certificate.
+///
+/// Remotes are required to present a valid libp2p certificate as well.
+///
+/// # Panic
+///
+/// Panics if the certificate or its private key is invalid. This can't happen for certificates
+/// generated with [`tls::generate_certificate`].
+///
+pub fn client_config(certificate: &tls::Certificate) -> quinn::ClientConfig {
+    let provider = Arc::new(rustls::crypto::ring::default_provider());
+
+    let mut crypto = rustls::ClientConfig::builder_with_provider(provider.clone())
+        .with_protocol_versions(&[&rustls::version::TLS13])
+        .unwrap()
+        .dangerous()
+        .with_custom_certificate_verifier(Arc::new(Libp2pCertificateVerifier {
+            provider: provider.clone(),
+        }))
+        .with_client_cert_resolver(Arc::new(AlwaysResolvesCertificate::new(
+            &provider,
+            certificate,
+        )));
+    crypto.alpn_protocols = vec![tls::ALPN_PROTOCOL.to_vec()];
+
+    let mut config = quinn::ClientConfig::new(Arc::new(
+        quinn::crypto::rustls::QuicClientConfig::try_from(crypto).unwrap(),
+    ));
+    config.transport_config(transport_config());
+    config
+}
+
+/// Returns the DER-encoded certificate that the remote has presented during the handshake of
+/// the given connection.
+///
+/// Returns `None` if the remote hasn't presented any certificate, which can't happen if the
+/// connection has been established with a configuration returned by [`server_config`] or
+/// [`client_config`].
+pub fn remote_certificate(connection: &quinn::Connection) -> Option<Vec<u8>> {
+    let identity = connection.peer_identity()?;
+    let certificates = identity.downcast::<Vec<CertificateDer<'static>>>().ok()?;
+    certificates.first().map(|cert| cert.as_ref().to_vec())
+}
+
+/// Transport parameters common to the client and the server.
+fn transport_config() -> Arc<quinn::TransportConfig> {
+    let mut config = quinn::TransportConfig::default();
+    // Libp2p doesn't use unidirectional streams.
+    config.max_concurrent_uni_streams(0u32.into());
+    config.keep_alive_interval(Some(Duration::from_secs(5)));
+    config.max_idle_timeout(Some(Duration::from_secs(10).try_into().unwrap()));
+    Arc::new(config)
+}
+
+/// Bidirectional QUIC stream implementing the `AsyncRead` and `AsyncWrite` traits.
+///
+/// If this object is destroyed while its writing side hasn't been closed, the stream is reset.
+pub struct Substream {
+    send: quinn::SendStream,
+    recv: quinn::RecvStream,
+    write_closed: bool,
+}
+
+impl Substream {
+    /// Builds a new [`Substream`] from the two halves returned by `quinn`.
+    pub fn new(send: quinn::SendStream, recv: quinn::RecvStream) -> Self {
+        Substream {
+            send,
+            recv,
+            write_closed: false,
+        }
+    }
+}
+
+impl AsyncRead for Substream {
+    fn poll_read(
+        mut self: Pin<&mut Self>,
+        cx: &mut Context<'_>,
+        buf: &mut [u8],
+    ) -> Poll<io::Result<usize>> {
+        AsyncRead::poll_read(Pin::new(&mut self.recv), cx, buf)
+    }
+}
+
+impl AsyncWrite for Substream {
+    fn poll_write(
+        mut self: Pin<&mut Self>,
+        cx: &mut Context<'_>,
+        buf: &[u8],
+    ) -> Poll<io::Result<usize>> {
+        AsyncWrite::poll_write(Pin::new(&mut self.send), cx, buf)
+    }
+
+    fn poll_flush(mut self: Pin<&mut Self>, cx: &mut Context<'_>) -> Poll<io::Result<()>> {
+        AsyncWrite::poll_flush(Pin::new(&mut self.send), cx)
+    }
+
+    fn poll_close(mut self: Pin<&mut Self>, cx: &mut Context<'_>) -> Poll<io::Result<()>> {
+        let result = AsyncWrite::poll_close(Pin::new(&mut self.send), cx);
+        if matches!(result, Poll::Ready(Ok(()))) {
+            self.write_closed = true;
+        }
+        result
+    }
+}
+
+impl Drop for Substream {
+    fn drop(&mut self) {
+        if !self.write_closed {
+            let _ = self.send.reset(0u32.into());
+        }
+    }
+}
+
+/// Implementation of the rustls certificate resolution traits that always presents the same
+/// certificate.
+///
+/// Contrary to `with_single_cert`, this doesn't make rustls parse the certificate, which it
+/// would refuse because of the libp2p extension.
+#[derive(Debug)]
+struct AlwaysResolvesCertificate(Arc<rustls::sign::CertifiedKey>);
+
+impl AlwaysResolvesCertificate {
+    fn new(provider: &CryptoProvider, certificate: &tls::Certificate) -> Self {
+        let private_key = provider
+            .key_provider
+            .load_private_key(PrivateKeyDer::Pkcs8(PrivatePkcs8KeyDer::from(
+                certificate.private_key_pkcs8_der.clone(),
+            )))
+            .unwrap();
+        AlwaysResolvesCertificate(Arc::new(rustls::sign::CertifiedKey::new(
+            vec![CertificateDer::from(certificate.certificate_der.clone())],
+            private_key,
+        )))
+    }
+}
+
+impl rustls::client::ResolvesClientCert for AlwaysResolvesCertificate {
+    fn resolve(
+        &self,
+        _: &[&[u8]],
+        _: &[SignatureScheme],
+    ) -> Option<Arc<rustls::sign::CertifiedKey>> {
+        Some(self.0.clone())
+    }
+
+    fn has_certs(&self) -> bool {
+        true
+    }
+}
+
+impl rustls::server::ResolvesServerCert for AlwaysResolvesCertificate {
+    fn resolve(
+        &self,
+        _: rustls::server::ClientHello<'_>,
+    ) -> Option<Arc<rustls::sign::CertifiedKey>> {
+        Some(self.0.clone())
+    }
+}
+
+/// Implementation of the rustls verification traits that accepts any certificate that conforms
+/// to the libp2p TLS specification.
+///
+/// Which `PeerId` the certificate corresponds to isn't checked here, as this is the role of the
+/// connection state machine.
+#[derive(Debug)]
+struct Libp2pCertificateVerifier {
+    provider: Arc<CryptoProvider>,
+}
+
+impl Libp2pCertificateVerifier {
+    fn verify_certificate(
+        &self,
+        end_entity: &CertificateDer<'_>,
+        intermediates: &[CertificateDer<'_>],
+    ) -> Result<(), rustls::Error> {
+        // The libp2p specification requires exactly one certificate.
+        if !intermediates.is_empty() {
+            return Err(rustls::Error::InvalidCertificate(
+                rustls::CertificateError::UnknownIssuer,
+            ));
+        }
+
+        match tls::verify_certificate(end_entity) {
+            Ok(_) => Ok(()),
+            Err(_) => Err(rustls::Error::InvalidCertificate(
+                rustls::CertificateError::ApplicationVerificationFailure,
+            )),
+        }
+    }
+
+    fn verify_tls13_signature(
+        &self,
+        message: &[u8],
+        cert: &CertificateDer<'_>,
+        dss: &DigitallySignedStruct,
+    ) -> Result<HandshakeSignatureValid, rustls::Error> {
+        // `rustls::crypto::verify_tls13_signature` can't be used, as it refuses certificates
+        // containing the libp2p extension, which is marked as critical.
+        let public_key = tls::certificate_public_key(cert).map_err(|_| {
+            rustls::Error::InvalidCertificate(rustls::CertificateError::BadEncoding)
+        })?;
+
+        let Some(algorithm) = self
+            .provider
+            .signature_verification_algorithms
+            .mapping
+            .iter()
+            .find(|(scheme, _)| *scheme == dss.scheme)
+            .and_then(|(_, algorithms)| algorithms.first())
+        else {
+            return Err(rustls::Error::PeerMisbehaved(
+                rustls::PeerMisbehaved::SignedHandshakeWithUnadvertisedSigScheme,
+            ));
+        };
+
+        if *algorithm.public_key_alg_id() != *public_key.algorithm {
+            return Err(rustls::Error::InvalidCertificate(
+                rustls::CertificateError::BadSignature,
+            ));
+        }
+
+        algorithm
+            .verify_signature(public_key.public_key, message, dss.signature())
+            .map_err(|_| {
+                rustls::Error::InvalidCertificate(rustls::CertificateError::BadSignature)
+            })?;
+
+        Ok(HandshakeSignatureValid::assertion())
+    }
+
+    fn supported_verify_schemes(&self) -> Vec<SignatureScheme> {
+        self.provider
+            .signature_verification_algorithms
+            .supported_schemes()
+    }
+}
+
+impl ServerCertVerifier for Libp2pCertificateVerifier {
+    fn verify_server_cert(
+        &self,
+        end_entity: &CertificateDer<'_>,
+        intermediates: &[CertificateDer<'_>],
+        _: &ServerName<'_>,
+        _: &[u8],
+        _: UnixTime,
+    ) -> Result<ServerCertVerified, rustls::Error> {
+        self.verify_certificate(end_entity, intermediates)?;
+        Ok(ServerCertVerified::assertion())
+    }
+
+    fn verify_tls12_signature(
+        &self,
+        _: &[u8],
+        _: &CertificateDer<'_>,
+        _: &DigitallySignedStruct,
+    ) -> Result<HandshakeSignatureValid, rustls::Error> {
+        // Only TLS 1.3 is enabled in the configuration.
+        Err(rustls::Error::PeerIncompatible(
+            rustls::PeerIncompatible::Tls13RequiredForQuic,
+        ))
+    }
+
+    fn verify_tls13_signature(
+        &self,
+        message: &[u8],
+        cert: &CertificateDer<'_>,
+        dss: &DigitallySignedStruct,
+    ) -> Result<HandshakeSignatureValid, rustls::Error> {
+        Libp2pCertificateVerifier::verify_tls13_signature(self, message, cert, dss)
+    }
+
+    fn supported_verify_schemes(&self) -> Vec<SignatureScheme> {
+        Libp2pCertificateVerifier::supported_verify_schemes(self)
+    }
+}
+
+impl ClientCertVerifier for Libp2pCertificateVerifier {
+    fn root_hint_subjects(&self) -> &[DistinguishedName] {
+        &[]
+    }
+
+    fn verify_client_cert(
+        &self,
+        end_entity: &CertificateDer<'_>,
+        intermediates: &[CertificateDer<'_>],
+        _: UnixTime,
+    ) -> Result<ClientCertVerified, rustls::Error> {
+        self.verify_certificate(end_entity, intermediates)?;
+        Ok(ClientCertVerified::assertion())
+    }
+
+    fn verify_tls12_signature(
+        &self,
+        _: &[u8],
+        _: &CertificateDer<'_>,
+        _: &DigitallySignedStruct,
+    ) -> Result<HandshakeSignatureValid, rustls::Error> {
+        // Only TLS 1.3 is enabled in the configuration.
+        Err(rustls::Error::PeerIncompatible(
+            rustls::PeerIncompatible::Tls13RequiredForQuic,
+        ))
+    }
+
+    fn verify_tls13_signature(
+        &self,
+        message: &[u8],
+        cert: &CertificateDer<'_>,
+        dss: &DigitallySignedStruct,
+    ) -> Result<HandshakeSignatureValid, rustls::Error> {
+        Libp2pCertificateVerifier::verify_tls13_signature(self, message, cert, dss)
+    }
+
+    fn supported_verify_schemes(&self) -> Vec<SignatureScheme> {
+        Libp2pCertificateVerifier::supported_verify_schemes(self)
+    }
+}
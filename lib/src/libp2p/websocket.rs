@@ -15,8 +15,8 @@
 // You should have received a copy of the GNU General Public License
 // along with this program.  If not, see <http://www.gnu.org/licenses/>.
 
-//! Implementation of a WebSocket client that wraps around an abstract representation of a TCP
-//! socket through the `AsyncRead` and `AsyncWrite` traits.
+//! Implementation of a WebSocket client and server that wraps around an abstract representation
+//! of a TCP socket through the `AsyncRead` and `AsyncWrite` traits.
 
 #![cfg(feature = "std")]
 #![cfg_attr(docsrs, doc(cfg(feature = "std")))]
@@ -75,6 +75,42 @@ pub async fn websocket_client_handshake<T: AsyncRead + AsyncWrite + Send + Unpin
     })
 }
 
+/// Negotiates the WebSocket protocol (including the HTTP-like request) on the given socket, as
+/// the server side, and returns an object that translates reads and writes into WebSocket binary
+/// frames.
+///
+/// The handshake is accepted no matter the URL requested by the client.
+pub async fn websocket_server_handshake<T: AsyncRead + AsyncWrite + Send + Unpin + 'static>(
+    tcp_socket: T,
+) -> Result<Connection<T>, io::Error> {
+    let mut server = soketto::handshake::Server::new(tcp_socket);
+
+    let key = match server.receive_request().await {
+        Ok(request) => request.key(),
+        Err(soketto::handshake::Error::Io(err)) => return Err(err),
+        Err(err) => return Err(io::Error::new(io::ErrorKind::InvalidData, err)),
+    };
+
+    match server
+        .send_response(&soketto::handshake::server::Response::Accept {
+            key,
+            protocol: None,
+        })
+        .await
+    {
+        Ok(()) => {}
+        Err(soketto::handshake::Error::Io(err)) => return Err(err),
+        Err(err) => return Err(io::Error::new(io::ErrorKind::Other, err)),
+    }
+
+    let (sender, receiver) = server.into_builder().finish();
+
+    Ok(Connection {
+        sender: Write::Idle(sender),
+        receiver: Read::Idle(receiver, Vec::with_capacity(1024), 0),
+    })
+}
+
 /// Negotiated WebSocket connection.
 ///
 /// Implements the `AsyncRead` and `AsyncWrite` traits.
@@ -374,7 +410,48 @@ fn convert_err(err: &soketto::connection::Error) -> io::Error {
 
 #[cfg(test)]
 mod tests {
-    use futures_util::{AsyncRead, AsyncWrite};
+    use futures_util::{
+        io::AllowStdIo, AsyncRead, AsyncReadExt as _, AsyncWrite, AsyncWriteExt as _,
+    };
+    use std::{net, thread};
+
+    #[test]
+    fn client_server_round_trip() {
+        let listener = net::TcpListener::bind("127.0.0.1:0").unwrap();
+        let addr = listener.local_addr().unwrap();
+
+        let server = thread::spawn(move || {
+            let (socket, _) = listener.accept().unwrap();
+            futures_executor::block_on(async move {
+                let mut connection = super::websocket_server_handshake(AllowStdIo::new(socket))
+                    .await
+                    .unwrap();
+                let mut buffer = [0; 5];
+                connection.read_exact(&mut buffer).await.unwrap();
+                assert_eq!(&buffer, b"hello");
+                connection.write_all(b"world").await.unwrap();
+                connection.flush().await.unwrap();
+            })
+        });
+
+        futures_executor::block_on(async move {
+            let socket = net::TcpStream::connect(addr).unwrap();
+            let mut connection = super::websocket_client_handshake(super::Config {
+                tcp_socket: AllowStdIo::new(socket),
+                host: &addr.to_string(),
+                url: "/",
+            })
+            .await
+            .unwrap();
+            connection.write_all(b"hello").await.unwrap();
+            connection.flush().await.unwrap();
+            let mut buffer = [0; 5];
+            connection.read_exact(&mut buffer).await.unwrap();
+            assert_eq!(&buffer, b"world");
+        });
+
+        server.join().unwrap();
+    }
 
     #[test]
     fn is_send() {
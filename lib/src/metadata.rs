@@ -0,0 +1,444 @@
+// Smoldot
+// Copyright (C) 2023  Pierre Krieger
+// SPDX-License-Identifier: GPL-3.0-or-later WITH Classpath-exception-2.0
+
+// This program is free software: you can redistribute it and/or modify
+// it under the terms of the GNU General Public License as published by
+// the Free Software Foundation, either version 3 of the License, or
+// (at your option) any later version.
+
+// This program is distributed in the hope that it will be useful,
+// but WITHOUT ANY WARRANTY; without even the implied warranty of
+// MERCHANTABILITY or FITNESS FOR A PARTICULAR PURPOSE.  See the
+// GNU General Public License for more details.
+
+// You should have received a copy of the GNU General Public License
+// along with this program.  If not, see <http://www.gnu.org/licenses/>.
+
+//! Runtime metadata retrieval and decoding.
+//!
+//! The *metadata* of a runtime is a data structure that describes the runtime: the list of its
+//! pallets, the storage entries, calls, events, errors and constants of each pallet, the format
+//! of the extrinsics, and a registry containing the definitions of all the types that the rest
+//! of the metadata refers to. It is what allows a UI or a library to interact with a chain
+//! without hardcoding knowledge about its runtime.
+//!
+//! # Retrieving the metadata
+//!
+//! The metadata is obtained by calling a function of the runtime:
+//!
+//! - `Metadata_metadata_at_version` (available if the version of the `Metadata` runtime API is
+//! at least 2) takes as parameter a version of the metadata format, and returns the metadata in
+//! this format, or nothing if the runtime doesn't support this format.
+//! - `Metadata_metadata` returns the metadata in a format chosen by the runtime. In practice,
+//! this is version 14 of the format.
+//!
+//! The [`query`] module contains a state machine that performs these calls. Alternatively, the
+//! call can be performed manually (for example through the network) using the constants and
+//! functions of this module, such as [`METADATA_AT_VERSION_FUNCTION_NAME`] and
+//! [`decode_metadata_at_version_return_value`].
+//!
+//! # Decoding the metadata
+//!
+//! The output of the runtime call can be decoded using [`decode`]. Versions 14 and 15 of the
+//! metadata format are supported.
+//!
+//! # Storage keys
+//!
+//! The key under which a storage entry is stored is built from the name of the pallet, the name
+//! of the entry, and, for maps, the hashes of the keys of the map. See [`storage_key`] and
+//! [`MetadataRef::storage_key`].
+//!
+//! Conversely, [`MetadataRef::decode_storage_key`] finds the storage entry that a key belongs to,
+//! and [`MetadataRef::decode_value`] decodes a storage value (or any other SCALE-encoded value)
+//! given the identifier of its type. Combined together, they make it possible to display the
+//! changes that a block performs to the storage in a human-readable way.
+
+use alloc::vec::Vec;
+use core::{fmt, iter};
+
+mod decode;
+mod tests;
+mod value;
+
+pub mod query;
+
+pub use decode::{
+    decode, CustomValueRef, DecodeError, ExtrinsicRef, ExtrinsicTypes, FieldRef, MetadataRef,
+    OuterEnums, PalletConstantRef, PalletRef, PalletStorageRef, Primitive, RuntimeApiMethodRef,
+    RuntimeApiRef, SignedExtensionRef, StorageEntryRef, StorageEntryType, StorageHasher,
+    TypeDefinition, TypeParamRef, TypeRef, VariantRef,
+};
+pub use value::{DecodeValueError, Value};
+
+/// Name of the runtime function to call in order to obtain the metadata in the format chosen
+/// by the runtime.
+pub const METADATA_FUNCTION_NAME: &str = "Metadata_metadata";
+
+/// Name of the runtime function to call in order to obtain the metadata in a specific format.
+///
+/// This function exists only if the version of the `Metadata` runtime API is at least 2.
+pub const METADATA_AT_VERSION_FUNCTION_NAME: &str = "Metadata_metadata_at_version";
+
+/// Produces the input to pass to the [`METADATA_AT_VERSION_FUNCTION_NAME`] runtime call.
+pub fn metadata_at_version_parameters(
+    version: u32,
+) -> impl Iterator<Item = impl AsRef<[u8]>> + Clone {
+    iter::once(version.to_le_bytes())
+}
+
+/// Attempt to decode the return value of the [`METADATA_FUNCTION_NAME`] runtime call.
+///
+/// On success, the returned slice can be passed to [`decode`].
+pub fn decode_metadata_return_value(scale_encoded: &[u8]) -> Result<&[u8], DecodeReturnValueError> {
+    match nom::combinator::all_consuming(crate::util::nom_bytes_decode::<nom::error::Error<&[u8]>>)(
+        scale_encoded,
+    ) {
+        Ok((_, metadata)) => Ok(metadata),
+        Err(_) => Err(DecodeReturnValueError()),
+    }
+}
+
+/// Attempt to decode the return value of the [`METADATA_AT_VERSION_FUNCTION_NAME`] runtime call.
+///
+/// Returns `Ok(None)` if the runtime doesn't support the requested version. On success, the
+/// returned slice can be passed to [`decode`].
+pub fn decode_metadata_at_version_return_value(
+    scale_encoded: &[u8],
+) -> Result<Option<&[u8]>, DecodeReturnValueError> {
+    match nom::combinator::all_consuming(crate::util::nom_option_decode::<
+        _,
+        nom::error::Error<&[u8]>,
+    >(crate::util::nom_bytes_decode))(scale_encoded)
+    {
+        Ok((_, metadata)) => Ok(metadata),
+        Err(_) => Err(DecodeReturnValueError()),
+    }
+}
+
+/// Error potentially returned by [`decode_metadata_return_value`] or
+/// [`decode_metadata_at_version_return_value`].
+#[derive(Debug, derive_more::Display, Clone)]
+#[display(fmt = "Failed to decode the return value of the metadata runtime call")]
+pub struct DecodeReturnValueError();
+
+/// Builds the key under which a storage entry, or an element of a storage map, is stored.
+///
+/// `pallet_prefix` is the [`PalletStorageRef::prefix`] of the pallet, and `entry_name` the
+/// [`StorageEntryRef::name`] of the entry. `keys` contains, for storage maps, the hasher and the
+/// SCALE-encoded value of each key of the map. Passing fewer keys than the map has hashers
+/// produces the prefix common to all the elements that share these keys.
+pub fn storage_key<'a>(
+    pallet_prefix: &str,
+    entry_name: &str,
+    keys: impl IntoIterator<Item = (StorageHasher, &'a [u8])>,
+) -> Vec<u8> {
+    let mut out = Vec::with_capacity(32);
+    out.extend_from_slice(&StorageHasher::Twox128.hash(pallet_prefix.as_bytes()));
+    out.extend_from_slice(&StorageHasher::Twox128.hash(entry_name.as_bytes()));
+    for (hasher, key) in keys {
+        out.extend_from_slice(&hasher.hash(key));
+    }
+    out
+}
+
+impl<'a> MetadataRef<'a> {
+    /// Builds the key under which a storage entry, or an element of a storage map, is stored.
+    ///
+    /// `scale_encoded_keys` contains the SCALE-encoded value of each key of the map, and must be
+    /// empty if the entry isn't a map. Passing fewer keys than the map has produces the prefix
+    /// common to all the elements that share these keys.
+    pub fn storage_key<'k>(
+        &self,
+        pallet_name: &str,
+        entry_name: &str,
+        scale_encoded_keys: impl ExactSizeIterator<Item = &'k [u8]>,
+    ) -> Result<Vec<u8>, StorageKeyError> {
+        let pallet = self
+            .pallet_by_name(pallet_name)
+            .ok_or(StorageKeyError::UnknownPallet)?;
+        let storage = pallet
+            .storage
+            .as_ref()
+            .ok_or(StorageKeyError::UnknownEntry)?;
+        let entry = pallet
+            .storage_entry_by_name(entry_name)
+            .ok_or(StorageKeyError::UnknownEntry)?;
+
+        let hashers = match &entry.ty {
+            StorageEntryType::Plain { .. } => &[][..],
+            StorageEntryType::Map { hashers, .. } => &hashers[..],
+        };
+
+        if scale_encoded_keys.len() > hashers.len() {
+            return Err(StorageKeyError::TooManyKeys);
+        }
+
+        Ok(storage_key(
+            storage.prefix,
+            entry.name,
+            hashers.iter().copied().zip(scale_encoded_keys),
+        ))
+    }
+
+    /// Finds the storage entry that the given key belongs to, and decodes the keys of the map
+    /// that are found in it.
+    ///
+    /// The key can be a prefix common to multiple elements of a map, in which case only the
+    /// keys of the map that are present are decoded.
+    ///
+    /// Returns [`DecodeStorageKeyError::UnknownEntry`] if the key doesn't belong to any storage
+    /// entry of the metadata, which is notably the case for well-known keys such as `:code`.
+    pub fn decode_storage_key(
+        &self,
+        key: &'a [u8],
+    ) -> Result<StorageKeyRef<'a>, DecodeStorageKeyError> {
+        if key.len() < 32 {
+            return Err(DecodeStorageKeyError::UnknownEntry);
+        }
+
+        let (pallet_name, storage) = self
+            .pallets
+            .iter()
+            .filter_map(|pallet| Some((pallet.name, pallet.storage.as_ref()?)))
+            .find(|(_, storage)| {
+                StorageHasher::Twox128.hash(storage.prefix.as_bytes()) == key[..16]
+            })
+            .ok_or(DecodeStorageKeyError::UnknownEntry)?;
+        let entry = storage
+            .entries
+            .iter()
+            .find(|entry| StorageHasher::Twox128.hash(entry.name.as_bytes()) == key[16..32])
+            .ok_or(DecodeStorageKeyError::UnknownEntry)?;
+
+        let mut remain = &key[32..];
+        let mut keys = Vec::new();
+
+        match &entry.ty {
+            StorageEntryType::Plain { value_ty } => {
+                if !remain.is_empty() {
+                    return Err(DecodeStorageKeyError::TrailingData);
+                }
+
+                Ok(StorageKeyRef {
+                    pallet_name,
+                    entry_name: entry.name,
+                    keys,
+                    value_ty: *value_ty,
+                })
+            }
+            StorageEntryType::Map {
+                hashers,
+                key_ty,
+                value_ty,
+            } => {
+                // If there are multiple hashers, the key type is a tuple containing one element
+                // per hasher.
+                let keys_tys = if hashers.len() == 1 {
+                    alloc::vec![*key_ty]
+                } else {
+                    match self.type_by_id(*key_ty).map(|ty| &ty.definition) {
+                        Some(TypeDefinition::Tuple { fields }) if fields.len() == hashers.len() => {
+                            fields.clone()
+                        }
+                        Some(_) => return Err(DecodeStorageKeyError::KeyTypeMismatch),
+                        None => {
+                            return Err(DecodeStorageKeyError::InvalidKey(
+                                DecodeValueError::UnknownType(*key_ty),
+                            ))
+                        }
+                    }
+                };
+
+                for (hasher, key_ty) in hashers.iter().zip(keys_tys) {
+                    if remain.is_empty() {
+                        break;
+                    }
+
+                    let hash_len = hasher.hash_len();
+                    if remain.len() < hash_len {
+                        return Err(DecodeStorageKeyError::InvalidKey(
+                            DecodeValueError::TooShort,
+                        ));
+                    }
+                    let (hash, after_hash) = remain.split_at(hash_len);
+                    remain = after_hash;
+
+                    let value = if hasher.is_transparent() {
+                        let (value, after_value) = self
+                            .decode_value_prefix(key_ty, remain)
+                            .map_err(DecodeStorageKeyError::InvalidKey)?;
+                        remain = after_value;
+                        Some(value)
+                    } else {
+                        None
+                    };
+
+                    keys.push(StorageMapKeyRef {
+                        hasher: *hasher,
+                        hash,
+                        value,
+                    });
+                }
+
+                if !remain.is_empty() {
+                    return Err(DecodeStorageKeyError::TrailingData);
+                }
+
+                Ok(StorageKeyRef {
+                    pallet_name,
+                    entry_name: entry.name,
+                    keys,
+                    value_ty: *value_ty,
+                })
+            }
+        }
+    }
+}
+
+/// Error potentially returned by [`MetadataRef::storage_key`].
+#[derive(Debug, derive_more::Display, Clone, PartialEq, Eq)]
+pub enum StorageKeyError {
+    /// No pallet with the given name exists.
+    UnknownPallet,
+    /// The pallet doesn't have any storage entry with the given name.
+    UnknownEntry,
+    /// More keys have been provided than the storage entry has.
+    TooManyKeys,
+}
+
+/// Storage key decoded by [`MetadataRef::decode_storage_key`].
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct StorageKeyRef<'a> {
+    /// Name of the pallet the storage entry belongs to, for example `System`.
+    pub pallet_name: &'a str,
+    /// Name of the storage entry, for example `Account`.
+    pub entry_name: &'a str,
+    /// Keys of the map found in the storage key. Always empty if the entry isn't a map.
+    pub keys: Vec<StorageMapKeyRef<'a>>,
+    /// Identifier of the type of the value stored under this key. Can be passed to
+    /// [`MetadataRef::decode_value`].
+    ///
+    /// > **Note**: If [`StorageKeyRef::keys`] doesn't contain all the keys of the map, the
+    /// >           storage key is a prefix and there is no value stored under it.
+    pub value_ty: u32,
+}
+
+impl<'a> fmt::Display for StorageKeyRef<'a> {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "{}::{}", self.pallet_name, self.entry_name)?;
+        if self.keys.is_empty() {
+            return Ok(());
+        }
+
+        f.write_str("(")?;
+        for (index, key) in self.keys.iter().enumerate() {
+            if index != 0 {
+                f.write_str(", ")?;
+            }
+            match &key.value {
+                Some(value) => fmt::Display::fmt(value, f)?,
+                None => {
+                    write!(f, "{:?}(0x", key.hasher)?;
+                    key.hash.iter().try_for_each(|b| write!(f, "{b:02x}"))?;
+                    f.write_str(")")?;
+                }
+            }
+        }
+        f.write_str(")")
+    }
+}
+
+/// Key of a storage map found in a [`StorageKeyRef`].
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct StorageMapKeyRef<'a> {
+    /// Hasher applied to the key.
+    pub hasher: StorageHasher,
+    /// Output of the hasher, not including the key itself in the case of
+    /// [`StorageHasher::Blake2_128Concat`] and [`StorageHasher::Twox64Concat`]. Empty in the case
+    /// of [`StorageHasher::Identity`].
+    pub hash: &'a [u8],
+    /// Decoded key. `None` if the hasher doesn't include the key in the storage key, in which
+    /// case only [`StorageMapKeyRef::hash`] is known.
+    pub value: Option<Value<'a>>,
+}
+
+/// Error potentially returned by [`MetadataRef::decode_storage_key`].
+#[derive(Debug, derive_more::Display, Clone, PartialEq, Eq)]
+pub enum DecodeStorageKeyError {
+    /// The key doesn't belong to any storage entry of the metadata.
+    UnknownEntry,
+    /// The type of the keys of the map doesn't match its number of hashers.
+    KeyTypeMismatch,
+    /// Failed to decode a key of the map.
+    #[display(fmt = "Failed to decode a key of the map: {_0}")]
+    InvalidKey(DecodeValueError),
+    /// The storage key contains more data than the storage entry accepts.
+    TrailingData,
+}
+
+impl StorageHasher {
+    /// Returns the number of bytes of hash that the hasher produces, not including the key
+    /// itself in the case of [`StorageHasher::Blake2_128Concat`] and
+    /// [`StorageHasher::Twox64Concat`].
+    pub fn hash_len(&self) -> usize {
+        match self {
+            StorageHasher::Blake2_128 | StorageHasher::Blake2_128Concat => 16,
+            StorageHasher::Blake2_256 => 32,
+            StorageHasher::Twox128 => 16,
+            StorageHasher::Twox256 => 32,
+            StorageHasher::Twox64Concat => 8,
+            StorageHasher::Identity => 0,
+        }
+    }
+
+    /// Returns `true` if the output of the hasher ends with the key itself, which makes it
+    /// possible to recover the key from the storage key.
+    pub fn is_transparent(&self) -> bool {
+        matches!(
+            self,
+            StorageHasher::Blake2_128Concat | StorageHasher::Twox64Concat | StorageHasher::Identity
+        )
+    }
+
+    /// Applies the hasher to the given SCALE-encoded key.
+    pub fn hash(&self, scale_encoded_key: &[u8]) -> Vec<u8> {
+        match self {
+            StorageHasher::Blake2_128 => blake2_rfc::blake2b::blake2b(16, &[], scale_encoded_key)
+                .as_bytes()
+                .to_vec(),
+            StorageHasher::Blake2_256 => blake2_rfc::blake2b::blake2b(32, &[], scale_encoded_key)
+                .as_bytes()
+                .to_vec(),
+            StorageHasher::Blake2_128Concat => {
+                let mut out = blake2_rfc::blake2b::blake2b(16, &[], scale_encoded_key)
+                    .as_bytes()
+                    .to_vec();
+                out.extend_from_slice(scale_encoded_key);
+                out
+            }
+            StorageHasher::Twox128 => twox(scale_encoded_key, 2),
+            StorageHasher::Twox256 => twox(scale_encoded_key, 4),
+            StorageHasher::Twox64Concat => {
+                let mut out = twox(scale_encoded_key, 1);
+                out.extend_from_slice(scale_encoded_key);
+                out
+            }
+            StorageHasher::Identity => scale_encoded_key.to_vec(),
+        }
+    }
+}
+
+/// Concatenates the little endian xxHash64 of `data` with the seeds from `0` to
+/// `num_hashes - 1`.
+fn twox(data: &[u8], num_hashes: u64) -> Vec<u8> {
+    use core::hash::Hasher as _;
+
+    let mut out = Vec::with_capacity(8 * usize::try_from(num_hashes).unwrap());
+    for seed in 0..num_hashes {
+        let mut hasher = twox_hash::XxHash::with_seed(seed);
+        hasher.write(data);
+        out.extend_from_slice(&hasher.finish().to_le_bytes());
+    }
+    out
+}
@@ -0,0 +1,844 @@
+// Smoldot
+// Copyright (C) 2023  Pierre Krieger
+// SPDX-License-Identifier: GPL-3.0-or-later WITH Classpath-exception-2.0
+
+// This program is free software: you can redistribute it and/or modify
+// it under the terms of the GNU General Public License as published by
+// the Free Software Foundation, either version 3 of the License, or
+// (at your option) any later version.
+
+// This program is distributed in the hope that it will be useful,
+// but WITHOUT ANY WARRANTY; without even the implied warranty of
+// MERCHANTABILITY or FITNESS FOR A PARTICULAR PURPOSE.  See the
+// GNU General Public License for more details.
+
+// You should have received a copy of the GNU General Public License
+// along with this program.  If not, see <http://www.gnu.org/licenses/>.
+
+//! Decoding of the metadata.
+//!
+//! See [the parent module](..) for more information.
+
+use crate::util;
+
+use alloc::vec::Vec;
+
+/// Attempts to decode the metadata, as returned by [`super::decode_metadata_return_value`] or
+/// [`super::decode_metadata_at_version_return_value`].
+///
+/// Only versions 14 and 15 of the metadata format are supported.
+pub fn decode(metadata: &[u8]) -> Result<MetadataRef<'_>, DecodeError> {
+    let (version, remain) = match nom::combinator::all_consuming(nom::sequence::preceded(
+        nom::bytes::complete::tag(b"meta"),
+        nom::sequence::pair(
+            nom::number::complete::u8,
+            nom::combinator::rest::<_, nom::error::Error<&[u8]>>,
+        ),
+    ))(metadata)
+    {
+        Ok((_, (version, remain))) => (version, remain),
+        Err(_) => return Err(DecodeError::InvalidPrefix),
+    };
+
+    let parser = match version {
+        14 => metadata_v14,
+        15 => metadata_v15,
+        version => return Err(DecodeError::UnsupportedVersion(version)),
+    };
+
+    match nom::combinator::all_consuming(parser)(remain) {
+        Ok((_, metadata)) => Ok(metadata),
+        Err(_) => Err(DecodeError::InvalidFormat),
+    }
+}
+
+/// Error potentially returned by [`decode`].
+#[derive(Debug, Clone, derive_more::Display)]
+pub enum DecodeError {
+    /// The metadata doesn't start with the `meta` magic number.
+    InvalidPrefix,
+    /// The version of the metadata format isn't supported.
+    #[display(fmt = "Unsupported metadata version: {_0}")]
+    UnsupportedVersion(u8),
+    /// Failed to decode the metadata.
+    InvalidFormat,
+}
+
+/// Decoded metadata.
+///
+/// Fields that only exist in version 15 of the metadata format are empty when decoding version
+/// 14.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct MetadataRef<'a> {
+    /// Version of the metadata format. Either 14 or 15.
+    pub version: u8,
+
+    /// Registry of all the types referred to by the rest of the metadata. Types are identified
+    /// by their [`TypeRef::id`]. Use [`MetadataRef::type_by_id`] to look up a type.
+    pub types: Vec<TypeRef<'a>>,
+
+    /// List of all the pallets of the runtime.
+    pub pallets: Vec<PalletRef<'a>>,
+
+    /// Information about the format of the extrinsics.
+    pub extrinsic: ExtrinsicRef<'a>,
+
+    /// Type identifier of the `Runtime` type.
+    pub runtime_ty: u32,
+
+    /// List of the runtime APIs exposed by the runtime. Always empty in version 14.
+    pub apis: Vec<RuntimeApiRef<'a>>,
+
+    /// Identifiers of the types of the enums that aggregate the calls, events, and errors of all
+    /// the pallets. Always `None` in version 14.
+    pub outer_enums: Option<OuterEnums>,
+
+    /// Chain-specific values, indexed by name. Always empty in version 14.
+    pub custom: Vec<(&'a str, CustomValueRef<'a>)>,
+}
+
+impl<'a> MetadataRef<'a> {
+    /// Returns the type with the given identifier, if any.
+    pub fn type_by_id(&self, id: u32) -> Option<&TypeRef<'a>> {
+        // Type identifiers are in practice always equal to the position of the type in the list.
+        match usize::try_from(id).ok().and_then(|idx| self.types.get(idx)) {
+            Some(ty) if ty.id == id => Some(ty),
+            _ => self.types.iter().find(|ty| ty.id == id),
+        }
+    }
+
+    /// Returns the pallet with the given name, if any.
+    pub fn pallet_by_name(&self, name: &str) -> Option<&PalletRef<'a>> {
+        self.pallets.iter().find(|pallet| pallet.name == name)
+    }
+}
+
+/// Type found in the types registry of the metadata.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct TypeRef<'a> {
+    /// Identifier of this type, used to refer to it from the rest of the metadata.
+    pub id: u32,
+    /// Path of the type in the source code of the runtime, for example
+    /// `["sp_runtime", "generic", "digest", "Digest"]`. Empty for primitive and anonymous types.
+    pub path: Vec<&'a str>,
+    /// Generic parameters of the type.
+    pub type_params: Vec<TypeParamRef<'a>>,
+    /// Definition of the type.
+    pub definition: TypeDefinition<'a>,
+    /// Documentation of the type.
+    pub docs: Vec<&'a str>,
+}
+
+/// Generic parameter of a [`TypeRef`].
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct TypeParamRef<'a> {
+    /// Name of the parameter.
+    pub name: &'a str,
+    /// Identifier of the type the parameter is instantiated with, if known.
+    pub ty: Option<u32>,
+}
+
+/// See [`TypeRef::definition`].
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum TypeDefinition<'a> {
+    /// Struct or tuple struct.
+    Composite {
+        /// Fields of the structure.
+        fields: Vec<FieldRef<'a>>,
+    },
+    /// Enum.
+    Variant {
+        /// Variants of the enum.
+        variants: Vec<VariantRef<'a>>,
+    },
+    /// Variable-length list of elements.
+    Sequence {
+        /// Identifier of the type of the elements.
+        ty: u32,
+    },
+    /// Fixed-length list of elements.
+    Array {
+        /// Number of elements.
+        len: u32,
+        /// Identifier of the type of the elements.
+        ty: u32,
+    },
+    /// Tuple.
+    Tuple {
+        /// Identifiers of the types of the elements.
+        fields: Vec<u32>,
+    },
+    /// Primitive type.
+    Primitive(Primitive),
+    /// Number encoded using the SCALE compact encoding.
+    Compact {
+        /// Identifier of the type of the number.
+        ty: u32,
+    },
+    /// Sequence of bits.
+    BitSequence {
+        /// Identifier of the type used to store the bits.
+        bit_store_ty: u32,
+        /// Identifier of the type indicating the order of the bits.
+        bit_order_ty: u32,
+    },
+}
+
+/// See [`TypeDefinition::Primitive`].
+#[derive(Debug, Copy, Clone, PartialEq, Eq, Hash)]
+pub enum Primitive {
+    Bool,
+    Char,
+    Str,
+    U8,
+    U16,
+    U32,
+    U64,
+    U128,
+    U256,
+    I8,
+    I16,
+    I32,
+    I64,
+    I128,
+    I256,
+}
+
+/// Field of a [`TypeDefinition::Composite`] or of a [`VariantRef`].
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct FieldRef<'a> {
+    /// Name of the field. `None` for tuple structs.
+    pub name: Option<&'a str>,
+    /// Identifier of the type of the field.
+    pub ty: u32,
+    /// Name of the type of the field as written in the source code of the runtime.
+    pub type_name: Option<&'a str>,
+    /// Documentation of the field.
+    pub docs: Vec<&'a str>,
+}
+
+/// Variant of a [`TypeDefinition::Variant`].
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct VariantRef<'a> {
+    /// Name of the variant.
+    pub name: &'a str,
+    /// Fields of the variant.
+    pub fields: Vec<FieldRef<'a>>,
+    /// Index of the variant, used when encoding a value of this type.
+    pub index: u8,
+    /// Documentation of the variant.
+    pub docs: Vec<&'a str>,
+}
+
+/// Pallet found in the metadata.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct PalletRef<'a> {
+    /// Name of the pallet, for example `System`.
+    pub name: &'a str,
+    /// Storage entries of the pallet, if it has any.
+    pub storage: Option<PalletStorageRef<'a>>,
+    /// Identifier of the type (always a [`TypeDefinition::Variant`]) of the calls of the
+    /// pallet, if it has any.
+    pub calls_ty: Option<u32>,
+    /// Identifier of the type (always a [`TypeDefinition::Variant`]) of the events of the
+    /// pallet, if it has any.
+    pub event_ty: Option<u32>,
+    /// Constants of the pallet.
+    pub constants: Vec<PalletConstantRef<'a>>,
+    /// Identifier of the type (always a [`TypeDefinition::Variant`]) of the errors of the
+    /// pallet, if it has any.
+    pub error_ty: Option<u32>,
+    /// Index of the pallet, used when encoding a call, event or error.
+    pub index: u8,
+    /// Documentation of the pallet. Always empty in version 14.
+    pub docs: Vec<&'a str>,
+}
+
+impl<'a> PalletRef<'a> {
+    /// Returns the storage entry of the pallet with the given name, if any.
+    pub fn storage_entry_by_name(&self, name: &str) -> Option<&StorageEntryRef<'a>> {
+        self.storage
+            .as_ref()?
+            .entries
+            .iter()
+            .find(|entry| entry.name == name)
+    }
+}
+
+/// See [`PalletRef::storage`].
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct PalletStorageRef<'a> {
+    /// Prefix of the keys of all the entries of the pallet. Generally identical to
+    /// [`PalletRef::name`].
+    pub prefix: &'a str,
+    /// List of storage entries.
+    pub entries: Vec<StorageEntryRef<'a>>,
+}
+
+/// Storage entry of a pallet.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct StorageEntryRef<'a> {
+    /// Name of the entry, for example `Account`.
+    pub name: &'a str,
+    /// If `true`, [`StorageEntryRef::default`] must be used when the storage doesn't contain
+    /// the entry. If `false`, the entry is considered as absent.
+    pub has_default: bool,
+    /// Type of the entry.
+    pub ty: StorageEntryType,
+    /// SCALE-encoded default value of the entry.
+    pub default: &'a [u8],
+    /// Documentation of the entry.
+    pub docs: Vec<&'a str>,
+}
+
+/// See [`StorageEntryRef::ty`].
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum StorageEntryType {
+    /// The entry consists in a single value.
+    Plain {
+        /// Identifier of the type of the value.
+        value_ty: u32,
+    },
+    /// The entry consists in a map of values.
+    Map {
+        /// Hashers applied to each key of the map. If there is more than one hasher, the key
+        /// type is a tuple whose length is equal to the number of hashers.
+        hashers: Vec<StorageHasher>,
+        /// Identifier of the type of the keys.
+        key_ty: u32,
+        /// Identifier of the type of the values.
+        value_ty: u32,
+    },
+}
+
+/// Hashing algorithm applied to a key of a [`StorageEntryType::Map`] in order to build the
+/// storage key of the value.
+#[derive(Debug, Copy, Clone, PartialEq, Eq, Hash)]
+pub enum StorageHasher {
+    Blake2_128,
+    Blake2_256,
+    Blake2_128Concat,
+    Twox128,
+    Twox256,
+    Twox64Concat,
+    Identity,
+}
+
+/// Constant of a pallet.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct PalletConstantRef<'a> {
+    /// Name of the constant.
+    pub name: &'a str,
+    /// Identifier of the type of the constant.
+    pub ty: u32,
+    /// SCALE-encoded value of the constant.
+    pub value: &'a [u8],
+    /// Documentation of the constant.
+    pub docs: Vec<&'a str>,
+}
+
+/// See [`MetadataRef::extrinsic`].
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct ExtrinsicRef<'a> {
+    /// Version of the format of the extrinsics.
+    pub version: u8,
+    /// Identifier of the type of the extrinsics. Only present in version 14.
+    pub ty: Option<u32>,
+    /// Identifiers of the types of the address, call, signature and extra of the extrinsics.
+    /// Only present in version 15.
+    pub types: Option<ExtrinsicTypes>,
+    /// Signed extensions of the extrinsics, in the order in which they are encoded.
+    pub signed_extensions: Vec<SignedExtensionRef<'a>>,
+}
+
+/// See [`ExtrinsicRef::types`].
+#[derive(Debug, Copy, Clone, PartialEq, Eq)]
+pub struct ExtrinsicTypes {
+    /// Identifier of the type of the address of the sender.
+    pub address_ty: u32,
+    /// Identifier of the type of the call.
+    pub call_ty: u32,
+    /// Identifier of the type of the signature.
+    pub signature_ty: u32,
+    /// Identifier of the type of the signed extensions data.
+    pub extra_ty: u32,
+}
+
+/// Signed extension of the extrinsics.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct SignedExtensionRef<'a> {
+    /// Name of the signed extension, for example `CheckNonce`.
+    pub identifier: &'a str,
+    /// Identifier of the type of the data included in the extrinsic.
+    pub ty: u32,
+    /// Identifier of the type of the data included in the signed payload but not in the
+    /// extrinsic.
+    pub additional_signed_ty: u32,
+}
+
+/// Runtime API found in the metadata.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct RuntimeApiRef<'a> {
+    /// Name of the runtime API, for example `Core`.
+    pub name: &'a str,
+    /// Functions of the runtime API.
+    pub methods: Vec<RuntimeApiMethodRef<'a>>,
+    /// Documentation of the runtime API.
+    pub docs: Vec<&'a str>,
+}
+
+/// Function of a [`RuntimeApiRef`].
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct RuntimeApiMethodRef<'a> {
+    /// Name of the function, for example `version`.
+    pub name: &'a str,
+    /// Names and identifiers of the types of the parameters of the function.
+    pub inputs: Vec<(&'a str, u32)>,
+    /// Identifier of the type of the return value.
+    pub output_ty: u32,
+    /// Documentation of the function.
+    pub docs: Vec<&'a str>,
+}
+
+/// See [`MetadataRef::outer_enums`].
+#[derive(Debug, Copy, Clone, PartialEq, Eq)]
+pub struct OuterEnums {
+    /// Identifier of the type of the enum of all the calls.
+    pub call_enum_ty: u32,
+    /// Identifier of the type of the enum of all the events.
+    pub event_enum_ty: u32,
+    /// Identifier of the type of the enum of all the errors.
+    pub error_enum_ty: u32,
+}
+
+/// See [`MetadataRef::custom`].
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct CustomValueRef<'a> {
+    /// Identifier of the type of the value.
+    pub ty: u32,
+    /// SCALE-encoded value.
+    pub value: &'a [u8],
+}
+
+// `nom` parser functions can be found below.
+
+fn metadata_v14(bytes: &[u8]) -> nom::IResult<&[u8], MetadataRef<'_>> {
+    nom::error::context(
+        "metadata v14",
+        nom::combinator::map(
+            nom::sequence::tuple((
+                vec_of(portable_type),
+                vec_of(pallet_v14),
+                extrinsic_v14,
+                type_id,
+            )),
+            |(types, pallets, extrinsic, runtime_ty)| MetadataRef {
+                version: 14,
+                types,
+                pallets,
+                extrinsic,
+                runtime_ty,
+                apis: Vec::new(),
+                outer_enums: None,
+                custom: Vec::new(),
+            },
+        ),
+    )(bytes)
+}
+
+fn metadata_v15(bytes: &[u8]) -> nom::IResult<&[u8], MetadataRef<'_>> {
+    nom::error::context(
+        "metadata v15",
+        nom::combinator::map(
+            nom::sequence::tuple((
+                vec_of(portable_type),
+                vec_of(pallet_v15),
+                extrinsic_v15,
+                type_id,
+                vec_of(runtime_api),
+                outer_enums,
+                vec_of(|b| nom::sequence::pair(util::nom_string_decode, custom_value)(b)),
+            )),
+            |(types, pallets, extrinsic, runtime_ty, apis, outer_enums, custom)| MetadataRef {
+                version: 15,
+                types,
+                pallets,
+                extrinsic,
+                runtime_ty,
+                apis,
+                outer_enums: Some(outer_enums),
+                custom,
+            },
+        ),
+    )(bytes)
+}
+
+fn portable_type(bytes: &[u8]) -> nom::IResult<&[u8], TypeRef<'_>> {
+    nom::error::context(
+        "type",
+        nom::combinator::map(
+            nom::sequence::tuple((
+                type_id,
+                vec_of(util::nom_string_decode),
+                vec_of(type_param),
+                type_definition,
+                docs,
+            )),
+            |(id, path, type_params, definition, docs)| TypeRef {
+                id,
+                path,
+                type_params,
+                definition,
+                docs,
+            },
+        ),
+    )(bytes)
+}
+
+fn type_param(bytes: &[u8]) -> nom::IResult<&[u8], TypeParamRef<'_>> {
+    nom::combinator::map(
+        nom::sequence::pair(util::nom_string_decode, util::nom_option_decode(type_id)),
+        |(name, ty)| TypeParamRef { name, ty },
+    )(bytes)
+}
+
+fn type_definition(bytes: &[u8]) -> nom::IResult<&[u8], TypeDefinition<'_>> {
+    nom::error::context(
+        "type definition",
+        nom::branch::alt((
+            nom::combinator::map(
+                nom::sequence::preceded(nom::bytes::complete::tag(&[0]), vec_of(field)),
+                |fields| TypeDefinition::Composite { fields },
+            ),
+            nom::combinator::map(
+                nom::sequence::preceded(nom::bytes::complete::tag(&[1]), vec_of(variant)),
+                |variants| TypeDefinition::Variant { variants },
+            ),
+            nom::combinator::map(
+                nom::sequence::preceded(nom::bytes::complete::tag(&[2]), type_id),
+                |ty| TypeDefinition::Sequence { ty },
+            ),
+            nom::combinator::map(
+                nom::sequence::preceded(
+                    nom::bytes::complete::tag(&[3]),
+                    nom::sequence::pair(nom::number::complete::le_u32, type_id),
+                ),
+                |(len, ty)| TypeDefinition::Array { len, ty },
+            ),
+            nom::combinator::map(
+                nom::sequence::preceded(nom::bytes::complete::tag(&[4]), vec_of(type_id)),
+                |fields| TypeDefinition::Tuple { fields },
+            ),
+            nom::combinator::map(
+                nom::sequence::preceded(nom::bytes::complete::tag(&[5]), primitive),
+                TypeDefinition::Primitive,
+            ),
+            nom::combinator::map(
+                nom::sequence::preceded(nom::bytes::complete::tag(&[6]), type_id),
+                |ty| TypeDefinition::Compact { ty },
+            ),
+            nom::combinator::map(
+                nom::sequence::preceded(
+                    nom::bytes::complete::tag(&[7]),
+                    nom::sequence::pair(type_id, type_id),
+                ),
+                |(bit_store_ty, bit_order_ty)| TypeDefinition::BitSequence {
+                    bit_store_ty,
+                    bit_order_ty,
+                },
+            ),
+        )),
+    )(bytes)
+}
+
+fn primitive(bytes: &[u8]) -> nom::IResult<&[u8], Primitive> {
+    nom::combinator::map_opt(nom::number::complete::u8, |n| match n {
+        0 => Some(Primitive::Bool),
+        1 => Some(Primitive::Char),
+        2 => Some(Primitive::Str),
+        3 => Some(Primitive::U8),
+        4 => Some(Primitive::U16),
+        5 => Some(Primitive::U32),
+        6 => Some(Primitive::U64),
+        7 => Some(Primitive::U128),
+        8 => Some(Primitive::U256),
+        9 => Some(Primitive::I8),
+        10 => Some(Primitive::I16),
+        11 => Some(Primitive::I32),
+        12 => Some(Primitive::I64),
+        13 => Some(Primitive::I128),
+        14 => Some(Primitive::I256),
+        _ => None,
+    })(bytes)
+}
+
+fn field(bytes: &[u8]) -> nom::IResult<&[u8], FieldRef<'_>> {
+    nom::combinator::map(
+        nom::sequence::tuple((
+            util::nom_option_decode(util::nom_string_decode),
+            type_id,
+            util::nom_option_decode(util::nom_string_decode),
+            docs,
+        )),
+        |(name, ty, type_name, docs)| FieldRef {
+            name,
+            ty,
+            type_name,
+            docs,
+        },
+    )(bytes)
+}
+
+fn variant(bytes: &[u8]) -> nom::IResult<&[u8], VariantRef<'_>> {
+    nom::combinator::map(
+        nom::sequence::tuple((
+            util::nom_string_decode,
+            vec_of(field),
+            nom::number::complete::u8,
+            docs,
+        )),
+        |(name, fields, index, docs)| VariantRef {
+            name,
+            fields,
+            index,
+            docs,
+        },
+    )(bytes)
+}
+
+fn pallet_v14(bytes: &[u8]) -> nom::IResult<&[u8], PalletRef<'_>> {
+    nom::error::context(
+        "pallet",
+        nom::combinator::map(
+            nom::sequence::tuple((
+                util::nom_string_decode,
+                util::nom_option_decode(pallet_storage),
+                util::nom_option_decode(type_id),
+                util::nom_option_decode(type_id),
+                vec_of(pallet_constant),
+                util::nom_option_decode(type_id),
+                nom::number::complete::u8,
+            )),
+            |(name, storage, calls_ty, event_ty, constants, error_ty, index)| PalletRef {
+                name,
+                storage,
+                calls_ty,
+                event_ty,
+                constants,
+                error_ty,
+                index,
+                docs: Vec::new(),
+            },
+        ),
+    )(bytes)
+}
+
+fn pallet_v15(bytes: &[u8]) -> nom::IResult<&[u8], PalletRef<'_>> {
+    nom::combinator::map(nom::sequence::pair(pallet_v14, docs), |(pallet, docs)| {
+        PalletRef { docs, ..pallet }
+    })(bytes)
+}
+
+fn pallet_storage(bytes: &[u8]) -> nom::IResult<&[u8], PalletStorageRef<'_>> {
+    nom::combinator::map(
+        nom::sequence::pair(util::nom_string_decode, vec_of(storage_entry)),
+        |(prefix, entries)| PalletStorageRef { prefix, entries },
+    )(bytes)
+}
+
+fn storage_entry(bytes: &[u8]) -> nom::IResult<&[u8], StorageEntryRef<'_>> {
+    nom::error::context(
+        "storage entry",
+        nom::combinator::map(
+            nom::sequence::tuple((
+                util::nom_string_decode,
+                nom::branch::alt((
+                    nom::combinator::map(nom::bytes::complete::tag(&[0]), |_| false),
+                    nom::combinator::map(nom::bytes::complete::tag(&[1]), |_| true),
+                )),
+                storage_entry_type,
+                util::nom_bytes_decode,
+                docs,
+            )),
+            |(name, has_default, ty, default, docs)| StorageEntryRef {
+                name,
+                has_default,
+                ty,
+                default,
+                docs,
+            },
+        ),
+    )(bytes)
+}
+
+fn storage_entry_type(bytes: &[u8]) -> nom::IResult<&[u8], StorageEntryType> {
+    nom::branch::alt((
+        nom::combinator::map(
+            nom::sequence::preceded(nom::bytes::complete::tag(&[0]), type_id),
+            |value_ty| StorageEntryType::Plain { value_ty },
+        ),
+        nom::combinator::map(
+            nom::sequence::preceded(
+                nom::bytes::complete::tag(&[1]),
+                nom::sequence::tuple((vec_of(storage_hasher), type_id, type_id)),
+            ),
+            |(hashers, key_ty, value_ty)| StorageEntryType::Map {
+                hashers,
+                key_ty,
+                value_ty,
+            },
+        ),
+    ))(bytes)
+}
+
+fn storage_hasher(bytes: &[u8]) -> nom::IResult<&[u8], StorageHasher> {
+    nom::combinator::map_opt(nom::number::complete::u8, |n| match n {
+        0 => Some(StorageHasher::Blake2_128),
+        1 => Some(StorageHasher::Blake2_256),
+        2 => Some(StorageHasher::Blake2_128Concat),
+        3 => Some(StorageHasher::Twox128),
+        4 => Some(StorageHasher::Twox256),
+        5 => Some(StorageHasher::Twox64Concat),
+        6 => Some(StorageHasher::Identity),
+        _ => None,
+    })(bytes)
+}
+
+fn pallet_constant(bytes: &[u8]) -> nom::IResult<&[u8], PalletConstantRef<'_>> {
+    nom::combinator::map(
+        nom::sequence::tuple((
+            util::nom_string_decode,
+            type_id,
+            util::nom_bytes_decode,
+            docs,
+        )),
+        |(name, ty, value, docs)| PalletConstantRef {
+            name,
+            ty,
+            value,
+            docs,
+        },
+    )(bytes)
+}
+
+fn extrinsic_v14(bytes: &[u8]) -> nom::IResult<&[u8], ExtrinsicRef<'_>> {
+    nom::error::context(
+        "extrinsic",
+        nom::combinator::map(
+            nom::sequence::tuple((type_id, nom::number::complete::u8, vec_of(signed_extension))),
+            |(ty, version, signed_extensions)| ExtrinsicRef {
+                version,
+                ty: Some(ty),
+                types: None,
+                signed_extensions,
+            },
+        ),
+    )(bytes)
+}
+
+fn extrinsic_v15(bytes: &[u8]) -> nom::IResult<&[u8], ExtrinsicRef<'_>> {
+    nom::error::context(
+        "extrinsic",
+        nom::combinator::map(
+            nom::sequence::tuple((
+                nom::number::complete::u8,
+                type_id,
+                type_id,
+                type_id,
+                type_id,
+                vec_of(signed_extension),
+            )),
+            |(version, address_ty, call_ty, signature_ty, extra_ty, signed_extensions)| {
+                ExtrinsicRef {
+                    version,
+                    ty: None,
+                    types: Some(ExtrinsicTypes {
+                        address_ty,
+                        call_ty,
+                        signature_ty,
+                        extra_ty,
+                    }),
+                    signed_extensions,
+                }
+            },
+        ),
+    )(bytes)
+}
+
+fn signed_extension(bytes: &[u8]) -> nom::IResult<&[u8], SignedExtensionRef<'_>> {
+    nom::combinator::map(
+        nom::sequence::tuple((util::nom_string_decode, type_id, type_id)),
+        |(identifier, ty, additional_signed_ty)| SignedExtensionRef {
+            identifier,
+            ty,
+            additional_signed_ty,
+        },
+    )(bytes)
+}
+
+fn runtime_api(bytes: &[u8]) -> nom::IResult<&[u8], RuntimeApiRef<'_>> {
+    nom::error::context(
+        "runtime api",
+        nom::combinator::map(
+            nom::sequence::tuple((util::nom_string_decode, vec_of(runtime_api_method), docs)),
+            |(name, methods, docs)| RuntimeApiRef {
+                name,
+                methods,
+                docs,
+            },
+        ),
+    )(bytes)
+}
+
+fn runtime_api_method(bytes: &[u8]) -> nom::IResult<&[u8], RuntimeApiMethodRef<'_>> {
+    nom::combinator::map(
+        nom::sequence::tuple((
+            util::nom_string_decode,
+            vec_of(|b| nom::sequence::pair(util::nom_string_decode, type_id)(b)),
+            type_id,
+            docs,
+        )),
+        |(name, inputs, output_ty, docs)| RuntimeApiMethodRef {
+            name,
+            inputs,
+            output_ty,
+            docs,
+        },
+    )(bytes)
+}
+
+fn outer_enums(bytes: &[u8]) -> nom::IResult<&[u8], OuterEnums> {
+    nom::combinator::map(
+        nom::sequence::tuple((type_id, type_id, type_id)),
+        |(call_enum_ty, event_enum_ty, error_enum_ty)| OuterEnums {
+            call_enum_ty,
+            event_enum_ty,
+            error_enum_ty,
+        },
+    )(bytes)
+}
+
+fn custom_value(bytes: &[u8]) -> nom::IResult<&[u8], CustomValueRef<'_>> {
+    nom::combinator::map(
+        nom::sequence::pair(type_id, util::nom_bytes_decode),
+        |(ty, value)| CustomValueRef { ty, value },
+    )(bytes)
+}
+
+fn docs(bytes: &[u8]) -> nom::IResult<&[u8], Vec<&str>> {
+    vec_of(util::nom_string_decode)(bytes)
+}
+
+/// Decodes a type identifier, which is encoded as a SCALE-compact `u32`.
+fn type_id(bytes: &[u8]) -> nom::IResult<&[u8], u32> {
+    nom::combinator::map_opt(util::nom_scale_compact_u64, |id| u32::try_from(id).ok())(bytes)
+}
+
+/// Decodes a SCALE-encoded `Vec` whose elements are decoded with the given parser.
+fn vec_of<'a, O>(
+    inner: impl FnMut(&'a [u8]) -> nom::IResult<&'a [u8], O> + Clone,
+) -> impl FnMut(&'a [u8]) -> nom::IResult<&'a [u8], Vec<O>> {
+    nom::combinator::flat_map(util::nom_scale_compact_usize, move |num_elems| {
+        nom::multi::many_m_n(num_elems, num_elems, inner.clone())
+    })
+}
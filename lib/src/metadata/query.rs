@@ -0,0 +1,354 @@
+// Smoldot
+// Copyright (C) 2023  Pierre Krieger
+// SPDX-License-Identifier: GPL-3.0-or-later WITH Classpath-exception-2.0
+
+// This program is free software: you can redistribute it and/or modify
+// it under the terms of the GNU General Public License as published by
+// the Free Software Foundation, either version 3 of the License, or
+// (at your option) any later version.
+
+// This program is distributed in the hope that it will be useful,
+// but WITHOUT ANY WARRANTY; without even the implied warranty of
+// MERCHANTABILITY or FITNESS FOR A PARTICULAR PURPOSE.  See the
+// GNU General Public License for more details.
+
+// You should have received a copy of the GNU General Public License
+// along with this program.  If not, see <http://www.gnu.org/licenses/>.
+
+//! Runtime call to obtain the metadata.
+//!
+//! If the runtime supports it, the metadata is first requested in the format passed through
+//! [`Config::version`] using `Metadata_metadata_at_version`. If the runtime doesn't support this
+//! function or this format, `Metadata_metadata` is called instead.
+
+use super::{
+    decode_metadata_at_version_return_value, decode_metadata_return_value,
+    metadata_at_version_parameters, DecodeReturnValueError, METADATA_AT_VERSION_FUNCTION_NAME,
+    METADATA_FUNCTION_NAME,
+};
+use crate::executor::{host, runtime_host, storage_diff};
+
+use alloc::vec::Vec;
+use core::iter;
+
+pub use runtime_host::{Nibble, TrieEntryVersion};
+
+/// Configuration for a metadata retrieval process.
+pub struct Config {
+    /// Runtime to obtain the metadata from. Must be built using the Wasm code found at the
+    /// `:code` key of the block storage.
+    pub runtime: host::HostVmPrototype,
+
+    /// Version of the metadata format to request. If the runtime doesn't support this version,
+    /// the metadata is returned in the format chosen by the runtime.
+    pub version: u32,
+
+    /// Maximum log level of the runtime.
+    ///
+    /// > **Note**: This value is opaque from the point of the view of the client, and the runtime
+    /// >           is free to interpret it the way it wants. However, usually values are: `0` for
+    /// >           "off", `1` for "error", `2` for "warn", `3` for "info", `4` for "debug",
+    /// >           and `5` for "trace".
+    pub max_log_level: u32,
+}
+
+/// Problem encountered during a call to [`query_metadata`].
+#[derive(Debug, derive_more::Display, Clone)]
+pub enum Error {
+    /// Error while starting the Wasm virtual machine.
+    #[display(fmt = "{_0}")]
+    WasmStart(host::StartErr),
+    /// Error while running the Wasm virtual machine.
+    #[display(fmt = "{_0}")]
+    WasmVm(runtime_host::ErrorDetail),
+    /// Error while decoding the output of the runtime.
+    #[display(fmt = "{_0}")]
+    OutputDecode(DecodeReturnValueError),
+    /// Runtime called a forbidden host function.
+    ForbiddenHostCall,
+}
+
+/// Obtains the metadata by calling the runtime.
+pub fn query_metadata(config: Config) -> Query {
+    let supports_at_version = config
+        .runtime
+        .runtime_version()
+        .decode()
+        .apis
+        .find_version("Metadata")
+        .is_some_and(|version| version >= 2);
+
+    if supports_at_version {
+        let vm = runtime_host::run(runtime_host::Config {
+            virtual_machine: config.runtime,
+            function_to_call: METADATA_AT_VERSION_FUNCTION_NAME,
+            parameter: metadata_at_version_parameters(config.version),
+            storage_main_trie_changes: storage_diff::TrieDiff::empty(),
+            max_log_level: config.max_log_level,
+            calculate_trie_changes: false,
+            execution_tracing: None,
+        });
+
+        Query::from_start(
+            vm,
+            Stage::AtVersion {
+                max_log_level: config.max_log_level,
+            },
+        )
+    } else {
+        start_metadata(config.runtime, config.max_log_level)
+    }
+}
+
+/// Starts a call to `Metadata_metadata`.
+fn start_metadata(runtime: host::HostVmPrototype, max_log_level: u32) -> Query {
+    let vm = runtime_host::run(runtime_host::Config {
+        virtual_machine: runtime,
+        function_to_call: METADATA_FUNCTION_NAME,
+        parameter: iter::empty::<&[u8]>(),
+        storage_main_trie_changes: storage_diff::TrieDiff::empty(),
+        max_log_level,
+        calculate_trie_changes: false,
+        execution_tracing: None,
+    });
+
+    Query::from_start(vm, Stage::Metadata)
+}
+
+/// Current state of the operation.
+#[must_use]
+pub enum Query {
+    /// Obtaining the metadata is over.
+    Finished {
+        /// Outcome of the call. On success, contains the metadata, which can be decoded with
+        /// [`super::decode`].
+        result: Result<Vec<u8>, Error>,
+        /// Virtual machine initially passed through the configuration.
+        virtual_machine: host::HostVmPrototype,
+    },
+    /// Loading a storage value is required in order to continue.
+    StorageGet(StorageGet),
+    /// Obtaining the Merkle value of the closest descendant of a trie node is required in order
+    /// to continue.
+    ClosestDescendantMerkleValue(ClosestDescendantMerkleValue),
+    /// Fetching the key that follows a given one is required in order to continue.
+    NextKey(NextKey),
+}
+
+impl Query {
+    /// Cancels execution of the virtual machine and returns back the prototype.
+    pub fn into_prototype(self) -> host::HostVmPrototype {
+        match self {
+            Query::Finished {
+                virtual_machine, ..
+            } => virtual_machine,
+            Query::StorageGet(StorageGet(inner, _)) => {
+                runtime_host::RuntimeHostVm::StorageGet(inner).into_prototype()
+            }
+            Query::ClosestDescendantMerkleValue(ClosestDescendantMerkleValue(inner, _)) => {
+                runtime_host::RuntimeHostVm::ClosestDescendantMerkleValue(inner).into_prototype()
+            }
+            Query::NextKey(NextKey(inner, _)) => {
+                runtime_host::RuntimeHostVm::NextKey(inner).into_prototype()
+            }
+        }
+    }
+
+    fn from_start(
+        vm: Result<runtime_host::RuntimeHostVm, (host::StartErr, host::HostVmPrototype)>,
+        stage: Stage,
+    ) -> Self {
+        match vm {
+            Ok(vm) => Query::from_inner(vm, stage),
+            Err((err, virtual_machine)) => Query::Finished {
+                result: Err(Error::WasmStart(err)),
+                virtual_machine,
+            },
+        }
+    }
+
+    fn from_inner(mut inner: runtime_host::RuntimeHostVm, stage: Stage) -> Self {
+        loop {
+            break match inner {
+                runtime_host::RuntimeHostVm::Finished(Ok(success)) => {
+                    // This decoding is done in multiple steps in order to solve borrow checking
+                    // errors.
+                    let result = {
+                        let output = success.virtual_machine.value();
+                        match stage {
+                            Stage::AtVersion { .. } => {
+                                decode_metadata_at_version_return_value(output.as_ref())
+                                    .map(|metadata| metadata.map(|m| m.to_vec()))
+                            }
+                            Stage::Metadata => decode_metadata_return_value(output.as_ref())
+                                .map(|metadata| Some(metadata.to_vec())),
+                        }
+                    };
+
+                    match (result, stage) {
+                        (Ok(Some(metadata)), _) => Query::Finished {
+                            result: Ok(metadata),
+                            virtual_machine: success.virtual_machine.into_prototype(),
+                        },
+                        (Ok(None), Stage::AtVersion { max_log_level }) => {
+                            // The requested version isn't supported. Fall back to the version
+                            // chosen by the runtime.
+                            start_metadata(success.virtual_machine.into_prototype(), max_log_level)
+                        }
+                        (Ok(None), Stage::Metadata) => unreachable!(),
+                        (Err(err), _) => Query::Finished {
+                            result: Err(Error::OutputDecode(err)),
+                            virtual_machine: success.virtual_machine.into_prototype(),
+                        },
+                    }
+                }
+                runtime_host::RuntimeHostVm::Finished(Err(err)) => Query::Finished {
+                    result: Err(Error::WasmVm(err.detail)),
+                    virtual_machine: err.prototype,
+                },
+                runtime_host::RuntimeHostVm::StorageGet(inner) => {
+                    Query::StorageGet(StorageGet(inner, stage))
+                }
+                runtime_host::RuntimeHostVm::ClosestDescendantMerkleValue(inner) => {
+                    Query::ClosestDescendantMerkleValue(ClosestDescendantMerkleValue(inner, stage))
+                }
+                runtime_host::RuntimeHostVm::NextKey(inner) => {
+                    Query::NextKey(NextKey(inner, stage))
+                }
+                runtime_host::RuntimeHostVm::SignatureVerification(sig) => {
+                    inner = sig.verify_and_resume();
+                    continue;
+                }
+                runtime_host::RuntimeHostVm::OffchainStorageSet(req) => {
+                    // Ignore the offchain storage write.
+                    inner = req.resume();
+                    continue;
+                }
+                runtime_host::RuntimeHostVm::Offchain(ctx) => Query::Finished {
+                    result: Err(Error::ForbiddenHostCall),
+                    virtual_machine: ctx.into_prototype(),
+                },
+                req @ (runtime_host::RuntimeHostVm::KeystorePublicKeys(_)
+                | runtime_host::RuntimeHostVm::KeystoreGenerate(_)
+                | runtime_host::RuntimeHostVm::KeystoreSign(_)) => Query::Finished {
+                    result: Err(Error::ForbiddenHostCall),
+                    virtual_machine: req.into_prototype(),
+                },
+            };
+        }
+    }
+}
+
+/// Runtime function currently being called.
+#[derive(Copy, Clone)]
+enum Stage {
+    /// Calling `Metadata_metadata_at_version`.
+    AtVersion {
+        /// Same value as [`Config::max_log_level`].
+        max_log_level: u32,
+    },
+    /// Calling `Metadata_metadata`.
+    Metadata,
+}
+
+/// Loading a storage value is required in order to continue.
+#[must_use]
+pub struct StorageGet(runtime_host::StorageGet, Stage);
+
+impl StorageGet {
+    /// Returns the key whose value must be passed to [`StorageGet::inject_value`].
+    pub fn key(&'_ self) -> impl AsRef<[u8]> + '_ {
+        self.0.key()
+    }
+
+    /// If `Some`, read from the given child trie. If `None`, read from the main trie.
+    pub fn child_trie(&'_ self) -> Option<impl AsRef<[u8]> + '_> {
+        self.0.child_trie()
+    }
+
+    /// Injects the corresponding storage value.
+    pub fn inject_value(
+        self,
+        value: Option<(impl Iterator<Item = impl AsRef<[u8]>>, TrieEntryVersion)>,
+    ) -> Query {
+        Query::from_inner(self.0.inject_value(value), self.1)
+    }
+}
+
+/// Obtaining the Merkle value of the closest descendant of a trie node is required in order
+/// to continue.
+#[must_use]
+pub struct ClosestDescendantMerkleValue(runtime_host::ClosestDescendantMerkleValue, Stage);
+
+impl ClosestDescendantMerkleValue {
+    /// Returns the key whose closest descendant Merkle value must be passed to
+    /// [`ClosestDescendantMerkleValue::inject_merkle_value`].
+    pub fn key(&'_ self) -> impl Iterator<Item = Nibble> + '_ {
+        self.0.key()
+    }
+
+    /// If `Some`, read from the given child trie. If `None`, read from the main trie.
+    pub fn child_trie(&'_ self) -> Option<impl AsRef<[u8]> + '_> {
+        self.0.child_trie()
+    }
+
+    /// Indicate that the value is unknown and resume the calculation.
+    ///
+    /// This function be used if you are unaware of the Merkle value. The algorithm will perform
+    /// the calculation of this Merkle value manually, which takes more time.
+    pub fn resume_unknown(self) -> Query {
+        Query::from_inner(self.0.resume_unknown(), self.1)
+    }
+
+    /// Injects the corresponding Merkle value.
+    ///
+    /// `None` can be passed if there is no descendant or, in the case of a child trie read, in
+    /// order to indicate that the child trie does not exist.
+    pub fn inject_merkle_value(self, merkle_value: Option<&[u8]>) -> Query {
+        Query::from_inner(self.0.inject_merkle_value(merkle_value), self.1)
+    }
+}
+
+/// Fetching the key that follows a given one is required in order to continue.
+#[must_use]
+pub struct NextKey(runtime_host::NextKey, Stage);
+
+impl NextKey {
+    /// Returns the key whose next key must be passed back.
+    pub fn key(&'_ self) -> impl Iterator<Item = Nibble> + '_ {
+        self.0.key()
+    }
+
+    /// If `Some`, read from the given child trie. If `None`, read from the main trie.
+    pub fn child_trie(&'_ self) -> Option<impl AsRef<[u8]> + '_> {
+        self.0.child_trie()
+    }
+
+    /// If `true`, then the provided value must the one superior or equal to the requested key.
+    /// If `false`, then the provided value must be strictly superior to the requested key.
+    pub fn or_equal(&self) -> bool {
+        self.0.or_equal()
+    }
+
+    /// If `true`, then the search must include both branch nodes and storage nodes. If `false`,
+    /// the search only covers storage nodes.
+    pub fn branch_nodes(&self) -> bool {
+        self.0.branch_nodes()
+    }
+
+    /// Returns the prefix the next key must start with. If the next key doesn't start with the
+    /// given prefix, then `None` should be provided.
+    pub fn prefix(&'_ self) -> impl Iterator<Item = Nibble> + '_ {
+        self.0.prefix()
+    }
+
+    /// Injects the key.
+    ///
+    /// # Panic
+    ///
+    /// Panics if the key passed as parameter isn't strictly superior to the requested key.
+    ///
+    pub fn inject_key(self, key: Option<impl Iterator<Item = Nibble>>) -> Query {
+        Query::from_inner(self.0.inject_key(key), self.1)
+    }
+}
@@ -0,0 +1,596 @@
+// Smoldot
+// Copyright (C) 2023  Pierre Krieger
+// SPDX-License-Identifier: GPL-3.0-or-later WITH Classpath-exception-2.0
+
+// This program is free software: you can redistribute it and/or modify
+// it under the terms of the GNU General Public License as published by
+// the Free Software Foundation, either version 3 of the License, or
+// (at your option) any later version.
+
+// This program is distributed in the hope that it will be useful,
+// but WITHOUT ANY WARRANTY; without even the implied warranty of
+// MERCHANTABILITY or FITNESS FOR A PARTICULAR PURPOSE.  See the
+// GNU General Public License for more details.
+
+// You should have received a copy of the GNU General Public License
+// along with this program.  If not, see <http://www.gnu.org/licenses/>.
+
+#![cfg(test)]
+
+use super::{
+    decode, decode_metadata_at_version_return_value, decode_metadata_return_value, storage_key,
+    DecodeError, DecodeStorageKeyError, DecodeValueError, ExtrinsicTypes, OuterEnums, Primitive,
+    StorageEntryType, StorageHasher, StorageKeyError, TypeDefinition, TypeRef, Value,
+};
+
+/// Minimal SCALE encoder used to build metadata by hand.
+#[derive(Default)]
+struct Encoder(Vec<u8>);
+
+impl Encoder {
+    fn u8(mut self, value: u8) -> Self {
+        self.0.push(value);
+        self
+    }
+
+    fn compact(mut self, value: usize) -> Self {
+        assert!(value < 64);
+        self.0.push(u8::try_from(value).unwrap() << 2);
+        self
+    }
+
+    fn str(self, value: &str) -> Self {
+        self.bytes(value.as_bytes())
+    }
+
+    fn bytes(mut self, value: &[u8]) -> Self {
+        self = self.compact(value.len());
+        self.0.extend_from_slice(value);
+        self
+    }
+
+    fn raw(mut self, value: &[u8]) -> Self {
+        self.0.extend_from_slice(value);
+        self
+    }
+}
+
+/// Types registry shared by the metadata of both versions:
+///
+/// - 0: `u32`
+/// - 1: `struct AccountData { free: u32 }`
+/// - 2: `[u8; 32]`
+/// - 3: `u8`
+fn types() -> Encoder {
+    Encoder::default()
+        .compact(4)
+        // `u32`
+        .compact(0)
+        .compact(0)
+        .compact(0)
+        .u8(5)
+        .u8(5)
+        .compact(0)
+        // `AccountData`
+        .compact(1)
+        .compact(1)
+        .str("AccountData")
+        .compact(0)
+        .u8(0)
+        .compact(1)
+        .u8(1)
+        .str("free")
+        .compact(0)
+        .u8(1)
+        .str("u32")
+        .compact(0)
+        .compact(1)
+        .str("Account data.")
+        // `[u8; 32]`
+        .compact(2)
+        .compact(0)
+        .compact(0)
+        .u8(3)
+        .raw(&32u32.to_le_bytes())
+        .compact(3)
+        .compact(0)
+        // `u8`
+        .compact(3)
+        .compact(0)
+        .compact(0)
+        .u8(5)
+        .u8(3)
+        .compact(0)
+}
+
+/// `System` pallet with the `Account` and `Number` storage entries, without the docs.
+fn system_pallet() -> Encoder {
+    Encoder::default()
+        .str("System")
+        // Storage.
+        .u8(1)
+        .str("System")
+        .compact(2)
+        .str("Account")
+        .u8(1)
+        .u8(1)
+        .compact(1)
+        .u8(2)
+        .compact(2)
+        .compact(1)
+        .bytes(&[0, 0, 0, 0])
+        .compact(1)
+        .str("The full account information.")
+        .str("Number")
+        .u8(0)
+        .u8(0)
+        .compact(0)
+        .bytes(&[])
+        .compact(0)
+        // Calls, event.
+        .u8(0)
+        .u8(0)
+        // Constants.
+        .compact(1)
+        .str("BlockHashCount")
+        .compact(0)
+        .bytes(&2400u32.to_le_bytes())
+        .compact(0)
+        // Error.
+        .u8(0)
+        // Index.
+        .u8(0)
+}
+
+fn metadata_v14() -> Vec<u8> {
+    Encoder::default()
+        .raw(b"meta")
+        .u8(14)
+        .raw(&types().0)
+        .compact(1)
+        .raw(&system_pallet().0)
+        // Extrinsic.
+        .compact(0)
+        .u8(4)
+        .compact(1)
+        .str("CheckNonce")
+        .compact(0)
+        .compact(3)
+        // Runtime type.
+        .compact(1)
+        .0
+}
+
+fn metadata_v15() -> Vec<u8> {
+    Encoder::default()
+        .raw(b"meta")
+        .u8(15)
+        .raw(&types().0)
+        .compact(1)
+        .raw(&system_pallet().0)
+        .compact(1)
+        .str("System pallet.")
+        // Extrinsic.
+        .u8(4)
+        .compact(2)
+        .compact(1)
+        .compact(2)
+        .compact(0)
+        .compact(0)
+        // Runtime type.
+        .compact(1)
+        // Runtime APIs.
+        .compact(1)
+        .str("Core")
+        .compact(1)
+        .str("version")
+        .compact(1)
+        .str("at")
+        .compact(0)
+        .compact(1)
+        .compact(0)
+        .compact(0)
+        // Outer enums.
+        .compact(1)
+        .compact(2)
+        .compact(3)
+        // Custom values.
+        .compact(1)
+        .str("foo")
+        .compact(3)
+        .bytes(&[5])
+        .0
+}
+
+#[test]
+fn decode_v14() {
+    let metadata = metadata_v14();
+    let metadata = decode(&metadata).unwrap();
+
+    assert_eq!(metadata.version, 14);
+    assert_eq!(metadata.types.len(), 4);
+    assert_eq!(
+        metadata.type_by_id(0).unwrap().definition,
+        TypeDefinition::Primitive(Primitive::U32)
+    );
+    let account_data = metadata.type_by_id(1).unwrap();
+    assert_eq!(account_data.path, ["AccountData"]);
+    match &account_data.definition {
+        TypeDefinition::Composite { fields } => {
+            assert_eq!(fields.len(), 1);
+            assert_eq!(fields[0].name, Some("free"));
+            assert_eq!(fields[0].ty, 0);
+            assert_eq!(fields[0].type_name, Some("u32"));
+            assert!(fields[0].docs.is_empty());
+        }
+        _ => panic!(),
+    }
+    assert_eq!(account_data.docs, ["Account data."]);
+    assert_eq!(
+        metadata.type_by_id(2).unwrap().definition,
+        TypeDefinition::Array { len: 32, ty: 3 }
+    );
+    assert!(metadata.type_by_id(4).is_none());
+
+    assert_eq!(metadata.pallets.len(), 1);
+    let system = metadata.pallet_by_name("System").unwrap();
+    assert_eq!(system.index, 0);
+    assert!(system.calls_ty.is_none());
+    assert!(system.docs.is_empty());
+    assert_eq!(system.constants[0].name, "BlockHashCount");
+    assert_eq!(system.constants[0].value, 2400u32.to_le_bytes());
+
+    let account = system.storage_entry_by_name("Account").unwrap();
+    assert!(account.has_default);
+    assert_eq!(
+        account.ty,
+        StorageEntryType::Map {
+            hashers: vec![StorageHasher::Blake2_128Concat],
+            key_ty: 2,
+            value_ty: 1
+        }
+    );
+    assert_eq!(account.docs, ["The full account information."]);
+    let number = system.storage_entry_by_name("Number").unwrap();
+    assert!(!number.has_default);
+    assert_eq!(number.ty, StorageEntryType::Plain { value_ty: 0 });
+
+    assert_eq!(metadata.extrinsic.version, 4);
+    assert_eq!(metadata.extrinsic.ty, Some(0));
+    assert!(metadata.extrinsic.types.is_none());
+    assert_eq!(
+        metadata.extrinsic.signed_extensions[0].identifier,
+        "CheckNonce"
+    );
+    assert_eq!(
+        metadata.extrinsic.signed_extensions[0].additional_signed_ty,
+        3
+    );
+    assert_eq!(metadata.runtime_ty, 1);
+    assert!(metadata.apis.is_empty());
+    assert!(metadata.outer_enums.is_none());
+}
+
+#[test]
+fn decode_v15() {
+    let metadata = metadata_v15();
+    let metadata = decode(&metadata).unwrap();
+
+    assert_eq!(metadata.version, 15);
+    assert_eq!(metadata.pallets[0].docs, ["System pallet."]);
+    assert_eq!(metadata.extrinsic.ty, None);
+    assert_eq!(
+        metadata.extrinsic.types,
+        Some(ExtrinsicTypes {
+            address_ty: 2,
+            call_ty: 1,
+            signature_ty: 2,
+            extra_ty: 0,
+        })
+    );
+    assert!(metadata.extrinsic.signed_extensions.is_empty());
+    assert_eq!(metadata.apis.len(), 1);
+    assert_eq!(metadata.apis[0].name, "Core");
+    assert_eq!(metadata.apis[0].methods[0].name, "version");
+    assert_eq!(metadata.apis[0].methods[0].inputs, [("at", 0)]);
+    assert_eq!(metadata.apis[0].methods[0].output_ty, 1);
+    assert_eq!(
+        metadata.outer_enums,
+        Some(OuterEnums {
+            call_enum_ty: 1,
+            event_enum_ty: 2,
+            error_enum_ty: 3,
+        })
+    );
+    assert_eq!(metadata.custom.len(), 1);
+    assert_eq!(metadata.custom[0].0, "foo");
+    assert_eq!(metadata.custom[0].1.value, [5]);
+}
+
+#[test]
+fn decode_errors() {
+    assert!(matches!(
+        decode(b"atem\x0e"),
+        Err(DecodeError::InvalidPrefix)
+    ));
+    assert!(matches!(
+        decode(b"meta\x0d"),
+        Err(DecodeError::UnsupportedVersion(13))
+    ));
+
+    let mut metadata = metadata_v14();
+    metadata.push(0);
+    assert!(matches!(decode(&metadata), Err(DecodeError::InvalidFormat)));
+    metadata.truncate(metadata.len() - 2);
+    assert!(matches!(decode(&metadata), Err(DecodeError::InvalidFormat)));
+}
+
+#[test]
+fn return_values() {
+    let metadata = metadata_v14();
+    let mut encoded = Encoder::default().compact(0).0;
+    assert_eq!(decode_metadata_return_value(&encoded).unwrap(), b"");
+    encoded[0] = 4;
+    assert!(decode_metadata_return_value(&encoded).is_err());
+
+    let encoded = Encoder::default().u8(1).bytes(&metadata[..40]).0;
+    assert_eq!(
+        decode_metadata_at_version_return_value(&encoded).unwrap(),
+        Some(&metadata[..40])
+    );
+    assert_eq!(decode_metadata_at_version_return_value(&[0]).unwrap(), None);
+    assert!(decode_metadata_at_version_return_value(&[2]).is_err());
+}
+
+#[test]
+fn storage_keys() {
+    let metadata = metadata_v14();
+    let metadata = decode(&metadata).unwrap();
+
+    assert_eq!(
+        metadata
+            .storage_key("System", "Number", core::iter::empty())
+            .unwrap(),
+        hex::decode("26aa394eea5630e07c48ae0c9558cef702a5c1b19ab7a04f536c519aca4983ac").unwrap()
+    );
+
+    let account_prefix =
+        hex::decode("26aa394eea5630e07c48ae0c9558cef7b99d880ec681799c0cf30e8886371da9").unwrap();
+    assert_eq!(
+        metadata
+            .storage_key("System", "Account", core::iter::empty())
+            .unwrap(),
+        account_prefix
+    );
+
+    let account_id = [1u8; 32];
+    let mut expected = account_prefix.clone();
+    expected.extend_from_slice(blake2_rfc::blake2b::blake2b(16, &[], &account_id).as_bytes());
+    expected.extend_from_slice(&account_id);
+    assert_eq!(
+        metadata
+            .storage_key("System", "Account", core::iter::once(&account_id[..]))
+            .unwrap(),
+        expected
+    );
+    assert_eq!(
+        storage_key(
+            "System",
+            "Account",
+            [(StorageHasher::Blake2_128Concat, &account_id[..])]
+        ),
+        expected
+    );
+
+    assert_eq!(
+        metadata.storage_key("System", "Number", core::iter::once(&[0u8][..])),
+        Err(StorageKeyError::TooManyKeys)
+    );
+    assert_eq!(
+        metadata.storage_key("Balances", "Account", core::iter::empty()),
+        Err(StorageKeyError::UnknownPallet)
+    );
+    assert_eq!(
+        metadata.storage_key("System", "Events", core::iter::empty()),
+        Err(StorageKeyError::UnknownEntry)
+    );
+}
+
+#[test]
+fn storage_hashers() {
+    let data = b"hello";
+    assert_eq!(StorageHasher::Identity.hash(data), data);
+    assert_eq!(StorageHasher::Blake2_128.hash(data).len(), 16);
+    assert_eq!(StorageHasher::Blake2_256.hash(data).len(), 32);
+    assert_eq!(&StorageHasher::Blake2_128Concat.hash(data)[16..], data);
+    assert_eq!(StorageHasher::Twox128.hash(data).len(), 16);
+    assert_eq!(StorageHasher::Twox256.hash(data).len(), 32);
+    assert_eq!(
+        StorageHasher::Twox64Concat.hash(data)[..8],
+        StorageHasher::Twox128.hash(data)[..8]
+    );
+    assert_eq!(&StorageHasher::Twox64Concat.hash(data)[8..], data);
+}
+
+#[test]
+fn decode_storage_keys() {
+    let metadata = metadata_v14();
+    let metadata = decode(&metadata).unwrap();
+
+    let number_key = metadata
+        .storage_key("System", "Number", core::iter::empty())
+        .unwrap();
+    let decoded = metadata.decode_storage_key(&number_key).unwrap();
+    assert_eq!(decoded.pallet_name, "System");
+    assert_eq!(decoded.entry_name, "Number");
+    assert!(decoded.keys.is_empty());
+    assert_eq!(decoded.value_ty, 0);
+    assert_eq!(decoded.to_string(), "System::Number");
+
+    let account_id = [1u8; 32];
+    let account_key = metadata
+        .storage_key("System", "Account", core::iter::once(&account_id[..]))
+        .unwrap();
+    let decoded = metadata.decode_storage_key(&account_key).unwrap();
+    assert_eq!(decoded.entry_name, "Account");
+    assert_eq!(decoded.keys.len(), 1);
+    assert_eq!(decoded.keys[0].hasher, StorageHasher::Blake2_128Concat);
+    assert_eq!(
+        decoded.keys[0].hash,
+        blake2_rfc::blake2b::blake2b(16, &[], &account_id).as_bytes()
+    );
+    assert_eq!(decoded.keys[0].value, Some(Value::Bytes(&account_id)));
+    assert_eq!(decoded.value_ty, 1);
+    assert_eq!(
+        decoded.to_string(),
+        "System::Account(0x0101010101010101010101010101010101010101010101010101010101010101)"
+    );
+
+    let prefix = metadata
+        .storage_key("System", "Account", core::iter::empty())
+        .unwrap();
+    assert!(metadata
+        .decode_storage_key(&prefix)
+        .unwrap()
+        .keys
+        .is_empty());
+
+    assert_eq!(
+        metadata.decode_storage_key(b":code"),
+        Err(DecodeStorageKeyError::UnknownEntry)
+    );
+    assert_eq!(
+        metadata.decode_storage_key(&storage_key("System", "Events", [])),
+        Err(DecodeStorageKeyError::UnknownEntry)
+    );
+
+    let mut too_long = number_key.clone();
+    too_long.push(0);
+    assert_eq!(
+        metadata.decode_storage_key(&too_long),
+        Err(DecodeStorageKeyError::TrailingData)
+    );
+    assert!(matches!(
+        metadata.decode_storage_key(&account_key[..account_key.len() - 1]),
+        Err(DecodeStorageKeyError::InvalidKey(_))
+    ));
+}
+
+#[test]
+fn decode_values() {
+    let metadata = metadata_v14();
+    let metadata = decode(&metadata).unwrap();
+
+    assert_eq!(
+        metadata.decode_value(0, &2400u32.to_le_bytes()).unwrap(),
+        Value::Unsigned(2400)
+    );
+
+    let encoded = 5u32.to_le_bytes();
+    let account_data = metadata.decode_value(1, &encoded).unwrap();
+    assert_eq!(
+        account_data,
+        Value::Composite {
+            type_name: Some("AccountData"),
+            fields: vec![(Some("free"), Value::Unsigned(5))],
+        }
+    );
+    assert_eq!(account_data.to_string(), "AccountData { free: 5 }");
+
+    assert_eq!(
+        metadata.decode_value(1, &[5, 0, 0]),
+        Err(DecodeValueError::TooShort)
+    );
+    assert_eq!(
+        metadata.decode_value(1, &[5, 0, 0, 0, 0]),
+        Err(DecodeValueError::TrailingData)
+    );
+    assert_eq!(
+        metadata.decode_value_prefix(3, &[7, 8]).unwrap(),
+        (Value::Unsigned(7), &[8][..])
+    );
+    assert_eq!(
+        metadata.decode_value(4, &[]),
+        Err(DecodeValueError::UnknownType(4))
+    );
+}
+
+#[test]
+fn decode_list_lengths() {
+    let metadata = metadata_v14();
+    let mut metadata = decode(&metadata).unwrap();
+
+    // `Vec<u32>`, `()`, and `Vec<()>`.
+    for (id, definition) in [
+        (4, TypeDefinition::Sequence { ty: 0 }),
+        (5, TypeDefinition::Tuple { fields: Vec::new() }),
+        (6, TypeDefinition::Sequence { ty: 5 }),
+    ] {
+        metadata.types.push(TypeRef {
+            id,
+            path: Vec::new(),
+            type_params: Vec::new(),
+            definition,
+            docs: Vec::new(),
+        });
+    }
+
+    assert_eq!(
+        metadata
+            .decode_value(4, &[8, 1, 0, 0, 0, 2, 0, 0, 0])
+            .unwrap(),
+        Value::Sequence(vec![Value::Unsigned(1), Value::Unsigned(2)])
+    );
+    // A length of roughly one billion elements, but only a few bytes of data.
+    assert_eq!(
+        metadata.decode_value(4, &[0xfe, 0xff, 0xff, 0xff, 1, 0, 0, 0]),
+        Err(DecodeValueError::TooShort)
+    );
+
+    let empty = Value::Composite {
+        type_name: None,
+        fields: Vec::new(),
+    };
+    assert_eq!(
+        metadata.decode_value(6, &[12]).unwrap(),
+        Value::Sequence(vec![empty.clone(), empty.clone(), empty])
+    );
+    assert_eq!(
+        metadata.decode_value(6, &[0xfe, 0xff, 0xff, 0xff]),
+        Err(DecodeValueError::TooManyElements)
+    );
+}
+
+#[test]
+fn value_display() {
+    let value = Value::Variant {
+        name: "Some",
+        fields: vec![(
+            None,
+            Value::Composite {
+                type_name: None,
+                fields: vec![
+                    (None, Value::Bool(true)),
+                    (None, Value::Str("foo")),
+                    (
+                        None,
+                        Value::Sequence(vec![Value::Signed(-1), Value::Unsigned(2)]),
+                    ),
+                ],
+            },
+        )],
+    };
+    assert_eq!(value.to_string(), "Some((true, \"foo\", [-1, 2]))");
+
+    let value = Value::Variant {
+        name: "None",
+        fields: Vec::new(),
+    };
+    assert_eq!(value.to_string(), "None");
+
+    let value = Value::BitSequence {
+        num_bits: 3,
+        bytes: &[0b101],
+    };
+    assert_eq!(value.to_string(), "bits(3, 0x05)");
+}
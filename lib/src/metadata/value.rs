@@ -0,0 +1,458 @@
+// Smoldot
+// Copyright (C) 2023  Pierre Krieger
+// SPDX-License-Identifier: GPL-3.0-or-later WITH Classpath-exception-2.0
+
+// This program is free software: you can redistribute it and/or modify
+// it under the terms of the GNU General Public License as published by
+// the Free Software Foundation, either version 3 of the License, or
+// (at your option) any later version.
+
+// This program is distributed in the hope that it will be useful,
+// but WITHOUT ANY WARRANTY; without even the implied warranty of
+// MERCHANTABILITY or FITNESS FOR A PARTICULAR PURPOSE.  See the
+// GNU General Public License for more details.
+
+// You should have received a copy of the GNU General Public License
+// along with this program.  If not, see <http://www.gnu.org/licenses/>.
+
+//! Decoding of SCALE-encoded values using the types registry of the metadata.
+//!
+//! See [the parent module](..) for more information.
+
+use super::{MetadataRef, Primitive, TypeDefinition};
+use crate::util;
+
+use alloc::vec::Vec;
+use core::fmt;
+
+/// Maximum number of nested types that [`MetadataRef::decode_value`] accepts to go through.
+///
+/// Types can be recursive, and a recursive type might not consume any byte of input. This limit
+/// guarantees that decoding always finishes.
+const MAX_DEPTH: u32 = 128;
+
+/// Maximum number of elements that [`MetadataRef::decode_value`] accepts in a list whose elements
+/// don't occupy any space, such as a list of empty tuples.
+///
+/// The encoding of such a list doesn't get any longer when elements are added, and this limit
+/// guarantees that decoding a short input doesn't take an absurd amount of time and memory.
+const MAX_ZERO_SIZE_ELEMENTS: usize = 1024;
+
+impl<'a> MetadataRef<'a> {
+    /// Decodes a SCALE-encoded value of the type with the given identifier.
+    ///
+    /// The entirety of `scale_encoded` must be consumed by the decoding.
+    pub fn decode_value(
+        &self,
+        ty: u32,
+        scale_encoded: &'a [u8],
+    ) -> Result<Value<'a>, DecodeValueError> {
+        let (value, remain) = self.decode_value_prefix(ty, scale_encoded)?;
+        if !remain.is_empty() {
+            return Err(DecodeValueError::TrailingData);
+        }
+        Ok(value)
+    }
+
+    /// Decodes a SCALE-encoded value of the type with the given identifier found at the start of
+    /// `scale_encoded`, and returns it alongside with the rest of the data.
+    pub fn decode_value_prefix(
+        &self,
+        ty: u32,
+        scale_encoded: &'a [u8],
+    ) -> Result<(Value<'a>, &'a [u8]), DecodeValueError> {
+        let mut bytes = scale_encoded;
+        let value = decode_inner(self, ty, &mut bytes, 0)?;
+        Ok((value, bytes))
+    }
+}
+
+/// Error potentially returned by [`MetadataRef::decode_value`].
+#[derive(Debug, Clone, PartialEq, Eq, derive_more::Display)]
+pub enum DecodeValueError {
+    /// The metadata refers to a type that doesn't exist in its types registry.
+    #[display(fmt = "Unknown type identifier: {_0}")]
+    UnknownType(u32),
+    /// The value is shorter than what its type indicates.
+    TooShort,
+    /// The data contains more bytes than the value.
+    TrailingData,
+    /// The data isn't a valid encoding of a value of this type.
+    InvalidEncoding,
+    /// The encoded value doesn't match any of the variants of its type.
+    #[display(fmt = "Unknown variant index: {_0}")]
+    UnknownVariant(u8),
+    /// Types are too deeply nested.
+    TooDeep,
+    /// A list contains too many elements of a type that doesn't occupy any space.
+    TooManyElements,
+}
+
+/// Decoded value.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum Value<'a> {
+    /// Boolean.
+    Bool(bool),
+    /// Unicode character.
+    Char(char),
+    /// String.
+    Str(&'a str),
+    /// Unsigned integer of at most 128 bits, including compact-encoded numbers.
+    Unsigned(u128),
+    /// Signed integer of at most 128 bits.
+    Signed(i128),
+    /// 256 bits unsigned integer, in little endian.
+    U256(&'a [u8]),
+    /// 256 bits signed integer, in little endian.
+    I256(&'a [u8]),
+    /// Struct, tuple struct, or tuple.
+    Composite {
+        /// Last component of the path of the type, for example `AccountData`. `None` for tuples
+        /// and anonymous types.
+        type_name: Option<&'a str>,
+        /// Fields of the value. The names are `None` for tuples and tuple structs.
+        fields: Vec<(Option<&'a str>, Value<'a>)>,
+    },
+    /// Enum.
+    Variant {
+        /// Name of the variant, for example `Some`.
+        name: &'a str,
+        /// Fields of the variant. The names are `None` for tuple variants.
+        fields: Vec<(Option<&'a str>, Value<'a>)>,
+    },
+    /// Sequence or array whose elements aren't bytes.
+    Sequence(Vec<Value<'a>>),
+    /// Sequence or array of bytes.
+    Bytes(&'a [u8]),
+    /// Sequence of bits.
+    BitSequence {
+        /// Number of bits in the sequence.
+        num_bits: usize,
+        /// Storage of the bits, whose layout depends on the type.
+        bytes: &'a [u8],
+    },
+}
+
+impl<'a> fmt::Display for Value<'a> {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            Value::Bool(value) => fmt::Display::fmt(value, f),
+            Value::Char(value) => fmt::Debug::fmt(value, f),
+            Value::Str(value) => fmt::Debug::fmt(value, f),
+            Value::Unsigned(value) => fmt::Display::fmt(value, f),
+            Value::Signed(value) => fmt::Display::fmt(value, f),
+            Value::U256(value) | Value::I256(value) => {
+                // Numbers are printed in big endian hexadecimal.
+                f.write_str("0x")?;
+                value.iter().rev().try_for_each(|b| write!(f, "{b:02x}"))
+            }
+            Value::Composite { type_name, fields } => {
+                if let Some(type_name) = type_name {
+                    f.write_str(type_name)?;
+                }
+                fmt_fields(fields, type_name.is_none(), f)
+            }
+            Value::Variant { name, fields } => {
+                f.write_str(name)?;
+                fmt_fields(fields, false, f)
+            }
+            Value::Sequence(elements) => {
+                f.write_str("[")?;
+                for (index, element) in elements.iter().enumerate() {
+                    if index != 0 {
+                        f.write_str(", ")?;
+                    }
+                    fmt::Display::fmt(element, f)?;
+                }
+                f.write_str("]")
+            }
+            Value::Bytes(bytes) => {
+                f.write_str("0x")?;
+                bytes.iter().try_for_each(|b| write!(f, "{b:02x}"))
+            }
+            Value::BitSequence { num_bits, bytes } => {
+                write!(f, "bits({num_bits}, 0x")?;
+                bytes.iter().try_for_each(|b| write!(f, "{b:02x}"))?;
+                f.write_str(")")
+            }
+        }
+    }
+}
+
+/// Prints the fields of a [`Value::Composite`] or [`Value::Variant`].
+///
+/// If `always_parenthesis` is `false`, nothing is printed if there isn't any field.
+fn fmt_fields(
+    fields: &[(Option<&str>, Value)],
+    always_parenthesis: bool,
+    f: &mut fmt::Formatter<'_>,
+) -> fmt::Result {
+    if fields.is_empty() {
+        return if always_parenthesis {
+            f.write_str("()")
+        } else {
+            Ok(())
+        };
+    }
+
+    let named = fields.iter().all(|(name, _)| name.is_some());
+    f.write_str(if named { " { " } else { "(" })?;
+    for (index, (name, value)) in fields.iter().enumerate() {
+        if index != 0 {
+            f.write_str(", ")?;
+        }
+        if let (true, Some(name)) = (named, name) {
+            write!(f, "{name}: ")?;
+        }
+        fmt::Display::fmt(value, f)?;
+    }
+    f.write_str(if named { " }" } else { ")" })
+}
+
+fn decode_inner<'a>(
+    metadata: &MetadataRef<'a>,
+    ty: u32,
+    bytes: &mut &'a [u8],
+    depth: u32,
+) -> Result<Value<'a>, DecodeValueError> {
+    if depth >= MAX_DEPTH {
+        return Err(DecodeValueError::TooDeep);
+    }
+
+    let type_ref = metadata
+        .type_by_id(ty)
+        .ok_or(DecodeValueError::UnknownType(ty))?;
+
+    match &type_ref.definition {
+        TypeDefinition::Composite { fields } => {
+            let fields = fields
+                .iter()
+                .map(|field| {
+                    Ok((
+                        field.name,
+                        decode_inner(metadata, field.ty, bytes, depth + 1)?,
+                    ))
+                })
+                .collect::<Result<Vec<_>, _>>()?;
+            Ok(Value::Composite {
+                type_name: type_ref.path.last().copied(),
+                fields,
+            })
+        }
+        TypeDefinition::Variant { variants } => {
+            let index = take(bytes, 1)?[0];
+            let variant = variants
+                .iter()
+                .find(|variant| variant.index == index)
+                .ok_or(DecodeValueError::UnknownVariant(index))?;
+            let fields = variant
+                .fields
+                .iter()
+                .map(|field| {
+                    Ok((
+                        field.name,
+                        decode_inner(metadata, field.ty, bytes, depth + 1)?,
+                    ))
+                })
+                .collect::<Result<Vec<_>, _>>()?;
+            Ok(Value::Variant {
+                name: variant.name,
+                fields,
+            })
+        }
+        TypeDefinition::Sequence { ty: elem_ty } => {
+            let len = compact::<usize>(bytes, util::nom_scale_compact_usize)?;
+            decode_list(metadata, *elem_ty, len, bytes, depth)
+        }
+        TypeDefinition::Array { len, ty: elem_ty } => {
+            let len = usize::try_from(*len).map_err(|_| DecodeValueError::TooShort)?;
+            decode_list(metadata, *elem_ty, len, bytes, depth)
+        }
+        TypeDefinition::Tuple { fields } => {
+            let fields = fields
+                .iter()
+                .map(|field_ty| Ok((None, decode_inner(metadata, *field_ty, bytes, depth + 1)?)))
+                .collect::<Result<Vec<_>, _>>()?;
+            Ok(Value::Composite {
+                type_name: None,
+                fields,
+            })
+        }
+        TypeDefinition::Primitive(primitive) => decode_primitive(*primitive, bytes),
+        TypeDefinition::Compact { .. } => {
+            // Compact-encoded values are always numbers, or wrappers around a number, and thus
+            // the actual type doesn't need to be looked up.
+            Ok(Value::Unsigned(compact::<u128>(
+                bytes,
+                util::nom_scale_compact_u128,
+            )?))
+        }
+        TypeDefinition::BitSequence { bit_store_ty, .. } => {
+            let store_bits = match metadata.type_by_id(*bit_store_ty).map(|t| &t.definition) {
+                Some(TypeDefinition::Primitive(Primitive::U8)) => 8,
+                Some(TypeDefinition::Primitive(Primitive::U16)) => 16,
+                Some(TypeDefinition::Primitive(Primitive::U32)) => 32,
+                Some(TypeDefinition::Primitive(Primitive::U64)) => 64,
+                Some(_) => return Err(DecodeValueError::InvalidEncoding),
+                None => return Err(DecodeValueError::UnknownType(*bit_store_ty)),
+            };
+            let num_bits = compact::<usize>(bytes, util::nom_scale_compact_usize)?;
+            let num_bytes = num_bits
+                .div_ceil(store_bits)
+                .checked_mul(store_bits / 8)
+                .ok_or(DecodeValueError::TooShort)?;
+            Ok(Value::BitSequence {
+                num_bits,
+                bytes: take(bytes, num_bytes)?,
+            })
+        }
+    }
+}
+
+/// Decodes `len` elements of type `elem_ty`.
+fn decode_list<'a>(
+    metadata: &MetadataRef<'a>,
+    elem_ty: u32,
+    len: usize,
+    bytes: &mut &'a [u8],
+    depth: u32,
+) -> Result<Value<'a>, DecodeValueError> {
+    if matches!(
+        metadata.type_by_id(elem_ty).map(|t| &t.definition),
+        Some(TypeDefinition::Primitive(Primitive::U8))
+    ) {
+        return Ok(Value::Bytes(take(bytes, len)?));
+    }
+
+    // Every element consumes at least one byte, except for elements of types such as empty
+    // tuples. The length is checked ahead of time in order to not spend an absurd amount of time
+    // and memory on invalid inputs.
+    if is_zero_size(metadata, elem_ty, depth + 1)? {
+        if len > MAX_ZERO_SIZE_ELEMENTS {
+            return Err(DecodeValueError::TooManyElements);
+        }
+    } else if len > bytes.len() {
+        return Err(DecodeValueError::TooShort);
+    }
+
+    let mut elements = Vec::with_capacity(len);
+    for _ in 0..len {
+        elements.push(decode_inner(metadata, elem_ty, bytes, depth + 1)?);
+    }
+    Ok(Value::Sequence(elements))
+}
+
+/// Returns `true` if values of the given type are always encoded as zero bytes.
+fn is_zero_size(metadata: &MetadataRef, ty: u32, depth: u32) -> Result<bool, DecodeValueError> {
+    if depth >= MAX_DEPTH {
+        return Err(DecodeValueError::TooDeep);
+    }
+
+    let type_ref = metadata
+        .type_by_id(ty)
+        .ok_or(DecodeValueError::UnknownType(ty))?;
+
+    match &type_ref.definition {
+        TypeDefinition::Composite { fields } => {
+            for field in fields {
+                if !is_zero_size(metadata, field.ty, depth + 1)? {
+                    return Ok(false);
+                }
+            }
+            Ok(true)
+        }
+        TypeDefinition::Tuple { fields } => {
+            for field_ty in fields {
+                if !is_zero_size(metadata, *field_ty, depth + 1)? {
+                    return Ok(false);
+                }
+            }
+            Ok(true)
+        }
+        TypeDefinition::Array { len, ty: elem_ty } => {
+            Ok(*len == 0 || is_zero_size(metadata, *elem_ty, depth + 1)?)
+        }
+        TypeDefinition::Variant { .. }
+        | TypeDefinition::Sequence { .. }
+        | TypeDefinition::Primitive(_)
+        | TypeDefinition::Compact { .. }
+        | TypeDefinition::BitSequence { .. } => Ok(false),
+    }
+}
+
+fn decode_primitive<'a>(
+    primitive: Primitive,
+    bytes: &mut &'a [u8],
+) -> Result<Value<'a>, DecodeValueError> {
+    Ok(match primitive {
+        Primitive::Bool => match take(bytes, 1)?[0] {
+            0 => Value::Bool(false),
+            1 => Value::Bool(true),
+            _ => return Err(DecodeValueError::InvalidEncoding),
+        },
+        Primitive::Char => {
+            let value = u32::from_le_bytes(<[u8; 4]>::try_from(take(bytes, 4)?).unwrap());
+            Value::Char(char::from_u32(value).ok_or(DecodeValueError::InvalidEncoding)?)
+        }
+        Primitive::Str => {
+            let len = compact::<usize>(bytes, util::nom_scale_compact_usize)?;
+            Value::Str(
+                core::str::from_utf8(take(bytes, len)?)
+                    .map_err(|_| DecodeValueError::InvalidEncoding)?,
+            )
+        }
+        Primitive::U8 => Value::Unsigned(u128::from(take(bytes, 1)?[0])),
+        Primitive::U16 => Value::Unsigned(u128::from(u16::from_le_bytes(
+            <[u8; 2]>::try_from(take(bytes, 2)?).unwrap(),
+        ))),
+        Primitive::U32 => Value::Unsigned(u128::from(u32::from_le_bytes(
+            <[u8; 4]>::try_from(take(bytes, 4)?).unwrap(),
+        ))),
+        Primitive::U64 => Value::Unsigned(u128::from(u64::from_le_bytes(
+            <[u8; 8]>::try_from(take(bytes, 8)?).unwrap(),
+        ))),
+        Primitive::U128 => Value::Unsigned(u128::from_le_bytes(
+            <[u8; 16]>::try_from(take(bytes, 16)?).unwrap(),
+        )),
+        Primitive::U256 => Value::U256(take(bytes, 32)?),
+        Primitive::I8 => Value::Signed(i128::from(i8::from_le_bytes([take(bytes, 1)?[0]]))),
+        Primitive::I16 => Value::Signed(i128::from(i16::from_le_bytes(
+            <[u8; 2]>::try_from(take(bytes, 2)?).unwrap(),
+        ))),
+        Primitive::I32 => Value::Signed(i128::from(i32::from_le_bytes(
+            <[u8; 4]>::try_from(take(bytes, 4)?).unwrap(),
+        ))),
+        Primitive::I64 => Value::Signed(i128::from(i64::from_le_bytes(
+            <[u8; 8]>::try_from(take(bytes, 8)?).unwrap(),
+        ))),
+        Primitive::I128 => Value::Signed(i128::from_le_bytes(
+            <[u8; 16]>::try_from(take(bytes, 16)?).unwrap(),
+        )),
+        Primitive::I256 => Value::I256(take(bytes, 32)?),
+    })
+}
+
+/// Removes the first `len` bytes of `bytes` and returns them.
+fn take<'a>(bytes: &mut &'a [u8], len: usize) -> Result<&'a [u8], DecodeValueError> {
+    if bytes.len() < len {
+        return Err(DecodeValueError::TooShort);
+    }
+    let (taken, remain) = bytes.split_at(len);
+    *bytes = remain;
+    Ok(taken)
+}
+
+/// Decodes a compact-encoded number at the start of `bytes` using the given parser.
+fn compact<'a, T>(
+    bytes: &mut &'a [u8],
+    parser: fn(&'a [u8]) -> nom::IResult<&'a [u8], T>,
+) -> Result<T, DecodeValueError> {
+    match parser(bytes) {
+        Ok((remain, value)) => {
+            *bytes = remain;
+            Ok(value)
+        }
+        Err(nom::Err::Incomplete(_)) => Err(DecodeValueError::TooShort),
+        Err(_) => Err(DecodeValueError::InvalidEncoding),
+    }
+}
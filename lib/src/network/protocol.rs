@@ -35,6 +35,7 @@ mod identify;
 mod kademlia;
 mod state_request;
 mod storage_call_proof;
+mod transactions;
 
 pub use self::block_announces::*;
 pub use self::block_request::*;
@@ -44,3 +45,4 @@ pub use self::identify::*;
 pub use self::kademlia::*;
 pub use self::state_request::*;
 pub use self::storage_call_proof::*;
+pub use self::transactions::*;
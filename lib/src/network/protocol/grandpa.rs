@@ -17,13 +17,16 @@
 
 // TODO: document all this
 
-use crate::finality::{grandpa::commit::decode, justification::decode::PrecommitRef};
+use crate::finality::grandpa::commit::decode;
 
 use alloc::vec::Vec;
 use core::{cmp, iter, mem};
 use nom::Finish as _;
 
-pub use crate::finality::grandpa::commit::decode::{CommitMessageRef, UnsignedPrecommitRef};
+pub use crate::finality::{
+    grandpa::commit::decode::{CommitMessageRef, CompactCommitRef, UnsignedPrecommitRef},
+    justification::decode::PrecommitRef,
+};
 
 #[derive(Debug, Clone, PartialEq, Eq)]
 pub enum GrandpaNotificationRef<'a> {
@@ -42,13 +45,123 @@ impl<'a> GrandpaNotificationRef<'a> {
         block_number_bytes: usize,
     ) -> impl Iterator<Item = impl AsRef<[u8]> + Clone> + Clone {
         match self {
-            GrandpaNotificationRef::Neighbor(n) => iter::once(either::Left(&[2u8]))
-                .chain(n.scale_encoding(block_number_bytes).map(either::Right)),
-            _ => todo!(),
+            GrandpaNotificationRef::Neighbor(n) => either::Left(
+                iter::once(either::Left(either::Left(&[2u8]))).chain(
+                    n.scale_encoding(block_number_bytes)
+                        .map(|b| either::Left(either::Right(b))),
+                ),
+            ),
+            GrandpaNotificationRef::Vote(v) => either::Right(iter::once(either::Right(
+                vote_message_encode(v, block_number_bytes),
+            ))),
+            GrandpaNotificationRef::Commit(c) => either::Right(iter::once(either::Right(
+                commit_message_encode(c, block_number_bytes),
+            ))),
+            GrandpaNotificationRef::CatchUpRequest(r) => {
+                either::Right(iter::once(either::Right(catch_up_request_encode(r))))
+            }
+            GrandpaNotificationRef::CatchUp(c) => either::Right(iter::once(either::Right(
+                catch_up_encode(c, block_number_bytes),
+            ))),
         }
     }
 }
 
+/// Returns the SCALE encoding of a [`GrandpaNotificationRef::Vote`], including the leading
+/// variant byte.
+fn vote_message_encode(vote: &VoteMessageRef, block_number_bytes: usize) -> Vec<u8> {
+    let mut out = Vec::with_capacity(1 + 8 + 8 + 1 + 32 + block_number_bytes + 64 + 32);
+    out.push(0u8);
+    out.extend_from_slice(&vote.round_number.to_le_bytes());
+    out.extend_from_slice(&vote.set_id.to_le_bytes());
+    let (variant, target_hash, target_number) = match &vote.message {
+        MessageRef::Prevote(m) => (0u8, m.target_hash, m.target_number),
+        MessageRef::Precommit(m) => (1u8, m.target_hash, m.target_number),
+        MessageRef::PrimaryPropose(m) => (2u8, m.target_hash, m.target_number),
+    };
+    out.push(variant);
+    out.extend_from_slice(target_hash);
+    encode_block_number(&mut out, target_number, block_number_bytes);
+    out.extend_from_slice(vote.signature);
+    out.extend_from_slice(vote.authority_public_key);
+    out
+}
+
+/// Returns the SCALE encoding of a [`GrandpaNotificationRef::Commit`], including the leading
+/// variant byte.
+fn commit_message_encode(commit: &CommitMessageRef, block_number_bytes: usize) -> Vec<u8> {
+    let mut out = Vec::new();
+    out.push(1u8);
+    out.extend_from_slice(&commit.round_number.to_le_bytes());
+    out.extend_from_slice(&commit.set_id.to_le_bytes());
+    out.extend_from_slice(commit.message.target_hash);
+    encode_block_number(&mut out, commit.message.target_number, block_number_bytes);
+    out.extend_from_slice(
+        crate::util::encode_scale_compact_usize(commit.message.precommits.len()).as_ref(),
+    );
+    for precommit in &commit.message.precommits {
+        out.extend_from_slice(precommit.target_hash);
+        encode_block_number(&mut out, precommit.target_number, block_number_bytes);
+    }
+    out.extend_from_slice(
+        crate::util::encode_scale_compact_usize(commit.message.auth_data.len()).as_ref(),
+    );
+    for (signature, public_key) in &commit.message.auth_data {
+        out.extend_from_slice(&signature[..]);
+        out.extend_from_slice(&public_key[..]);
+    }
+    out
+}
+
+/// Returns the SCALE encoding of a [`GrandpaNotificationRef::CatchUpRequest`], including the
+/// leading variant byte.
+fn catch_up_request_encode(request: &CatchUpRequest) -> Vec<u8> {
+    let mut out = Vec::with_capacity(1 + 8 + 8);
+    out.push(3u8);
+    out.extend_from_slice(&request.round_number.to_le_bytes());
+    out.extend_from_slice(&request.set_id.to_le_bytes());
+    out
+}
+
+/// Returns the SCALE encoding of a [`GrandpaNotificationRef::CatchUp`], including the leading
+/// variant byte.
+fn catch_up_encode(catch_up: &CatchUpRef, block_number_bytes: usize) -> Vec<u8> {
+    let mut out = Vec::new();
+    out.push(4u8);
+    out.extend_from_slice(&catch_up.set_id.to_le_bytes());
+    out.extend_from_slice(&catch_up.round_number.to_le_bytes());
+    out.extend_from_slice(
+        crate::util::encode_scale_compact_usize(catch_up.prevotes.len()).as_ref(),
+    );
+    for prevote in &catch_up.prevotes {
+        out.extend_from_slice(prevote.target_hash);
+        encode_block_number(&mut out, prevote.target_number, block_number_bytes);
+        out.extend_from_slice(prevote.signature);
+        out.extend_from_slice(prevote.authority_public_key);
+    }
+    out.extend_from_slice(
+        crate::util::encode_scale_compact_usize(catch_up.precommits.len()).as_ref(),
+    );
+    for precommit in &catch_up.precommits {
+        out.extend_from_slice(precommit.target_hash);
+        encode_block_number(&mut out, precommit.target_number, block_number_bytes);
+        out.extend_from_slice(precommit.signature);
+        out.extend_from_slice(precommit.authority_public_key);
+    }
+    out.extend_from_slice(catch_up.base_hash);
+    encode_block_number(&mut out, catch_up.base_number, block_number_bytes);
+    out
+}
+
+/// Appends to `out` the little endian encoding of `number` over `block_number_bytes` bytes.
+fn encode_block_number(out: &mut Vec<u8>, number: u64, block_number_bytes: usize) {
+    let bytes = number.to_le_bytes();
+    // TODO: unclear what to do if the block number doesn't fit in `block_number_bytes`
+    debug_assert!(!bytes.iter().skip(block_number_bytes).any(|b| *b != 0));
+    out.extend_from_slice(&bytes[..cmp::min(bytes.len(), block_number_bytes)]);
+    out.extend(iter::repeat(0).take(block_number_bytes.saturating_sub(bytes.len())));
+}
+
 #[derive(Debug, Clone, PartialEq, Eq)]
 pub struct VoteMessageRef<'a> {
     pub round_number: u64,
@@ -446,4 +559,90 @@ mod tests {
 
         assert_eq!(actual, expected);
     }
+
+    #[test]
+    fn vote_encode_decode() {
+        let vote = super::GrandpaNotificationRef::Vote(super::VoteMessageRef {
+            round_number: 12,
+            set_id: 3,
+            message: super::MessageRef::Precommit(super::UnsignedPrecommitRef {
+                target_hash: &[5; 32],
+                target_number: 1234,
+            }),
+            signature: &[6; 64],
+            authority_public_key: &[7; 32],
+        });
+
+        let encoded = vote.scale_encoding(4).fold(Vec::new(), |mut a, b| {
+            a.extend_from_slice(b.as_ref());
+            a
+        });
+
+        assert_eq!(
+            super::decode_grandpa_notification(&encoded, 4).unwrap(),
+            vote
+        );
+    }
+
+    #[test]
+    fn catch_up_request_encode_decode() {
+        let request = super::GrandpaNotificationRef::CatchUpRequest(super::CatchUpRequest {
+            round_number: 3671,
+            set_id: 3490,
+        });
+
+        let encoded = request.scale_encoding(4).fold(Vec::new(), |mut a, b| {
+            a.extend_from_slice(b.as_ref());
+            a
+        });
+
+        assert_eq!(
+            encoded,
+            &[3, 87, 14, 0, 0, 0, 0, 0, 0, 162, 13, 0, 0, 0, 0, 0, 0]
+        );
+        assert_eq!(
+            super::decode_grandpa_notification(&encoded, 4).unwrap(),
+            request
+        );
+    }
+
+    #[test]
+    fn catch_up_encode_decode() {
+        let catch_up = super::GrandpaNotificationRef::CatchUp(super::CatchUpRef {
+            set_id: 3,
+            round_number: 12,
+            prevotes: vec![super::PrevoteRef {
+                target_hash: &[1; 32],
+                target_number: 1233,
+                signature: &[2; 64],
+                authority_public_key: &[3; 32],
+            }],
+            precommits: vec![
+                super::PrecommitRef {
+                    target_hash: &[4; 32],
+                    target_number: 1234,
+                    signature: &[5; 64],
+                    authority_public_key: &[6; 32],
+                },
+                super::PrecommitRef {
+                    target_hash: &[7; 32],
+                    target_number: 1235,
+                    signature: &[8; 64],
+                    authority_public_key: &[9; 32],
+                },
+            ],
+            base_hash: &[10; 32],
+            base_number: 1200,
+        });
+
+        let encoded = catch_up.scale_encoding(4).fold(Vec::new(), |mut a, b| {
+            a.extend_from_slice(b.as_ref());
+            a
+        });
+
+        assert_eq!(
+            super::decode_grandpa_notification(&encoded, 4).unwrap(),
+            catch_up
+        );
+    }
 }
@@ -38,6 +38,7 @@
 use crate::{finality, header};
 
 use alloc::vec::Vec;
+use core::iter;
 
 // TODO: all the constraints explained here should be checked when decoding the message
 
@@ -69,6 +70,27 @@ pub struct GrandpaWarpSyncResponseFragment<'a> {
     pub scale_encoded_justification: &'a [u8],
 }
 
+/// Builds the SCALE encoding of a GrandPa warp sync response.
+pub fn build_grandpa_warp_sync_response<'a>(
+    response: GrandpaWarpSyncResponse<'a>,
+) -> impl Iterator<Item = impl AsRef<[u8]> + 'a> + 'a {
+    iter::once(either::Left(crate::util::encode_scale_compact_usize(
+        response.fragments.len(),
+    )))
+    .chain(response.fragments.into_iter().flat_map(|fragment| {
+        [
+            either::Right(fragment.scale_encoded_header),
+            either::Right(fragment.scale_encoded_justification),
+        ]
+        .into_iter()
+    }))
+    .chain(iter::once(either::Right(if response.is_finished {
+        &[1u8][..]
+    } else {
+        &[0u8][..]
+    })))
+}
+
 /// Error potentially returned by [`decode_grandpa_warp_sync_response`].
 #[derive(Debug, derive_more::Display)]
 #[display(fmt = "Failed to decode response")]
@@ -50,6 +50,8 @@
 
 use crate::util::protobuf;
 
+use alloc::vec::Vec;
+
 /// Description of a state request that can be sent to a peer.
 #[derive(Debug, Clone, PartialEq, Eq)]
 pub struct StateRequest<'a> {
@@ -107,6 +109,70 @@ pub fn build_state_request(
         .chain(protobuf::bool_tag_encode(3, false).map(either::Left))
 }
 
+/// Decodes a state request.
+///
+/// Requests that ask for a response without a Merkle proof are refused, as this mode isn't
+/// supported.
+pub fn decode_state_request(request_bytes: &[u8]) -> Result<StateRequest, DecodeStateRequestError> {
+    let mut parser = nom::combinator::all_consuming::<_, _, nom::error::Error<&[u8]>, _>(
+        nom::combinator::complete(protobuf::message_decode! {
+            #[required] block = 1 => protobuf::bytes_tag_decode,
+            #[repeated(max = 2)] start = 2 => protobuf::bytes_tag_decode,
+            #[optional] no_proof = 3 => protobuf::bool_tag_decode,
+        }),
+    );
+
+    let decoded = match nom::Finish::finish(parser(request_bytes)) {
+        Ok((_, rq)) => rq,
+        Err(_) => return Err(DecodeStateRequestError::ProtobufDecode),
+    };
+
+    if decoded.no_proof.unwrap_or(false) {
+        return Err(DecodeStateRequestError::NoProofUnsupported);
+    }
+
+    let block_hash = <&[u8; 32]>::try_from(decoded.block)
+        .map_err(|_| DecodeStateRequestError::InvalidBlockHashLength)?;
+
+    let start_key = match &decoded.start[..] {
+        [] => StateRequestStart::MainTrie(&[]),
+        [key] => StateRequestStart::MainTrie(key),
+        [child_trie, key] => StateRequestStart::ChildTrieDefault {
+            child_trie: child_trie
+                .strip_prefix(b":child_storage:default:")
+                .ok_or(DecodeStateRequestError::InvalidChildTrie)?,
+            key,
+        },
+        _ => unreachable!(),
+    };
+
+    Ok(StateRequest {
+        block_hash,
+        start_key,
+    })
+}
+
+/// Error potentially returned by [`decode_state_request`].
+#[derive(Debug, derive_more::Display, Clone)]
+pub enum DecodeStateRequestError {
+    /// Error while decoding the Protobuf encoding.
+    ProtobufDecode,
+    /// Block hash length isn't correct.
+    InvalidBlockHashLength,
+    /// Start key designates a child trie that isn't a default child trie.
+    InvalidChildTrie,
+    /// Request asks for a response without a Merkle proof, which isn't supported.
+    NoProofUnsupported,
+}
+
+/// Builds the bytes corresponding to a response to a state request.
+///
+/// Must be passed a Merkle proof containing the storage entries that follow the start key of the
+/// request.
+pub fn build_state_response(proof: &'_ [u8]) -> impl Iterator<Item = impl AsRef<[u8]> + '_> + '_ {
+    protobuf::bytes_tag_encode(2, proof)
+}
+
 /// Decodes a response to a state request.
 ///
 /// On success, contains a Merkle proof.
@@ -132,3 +198,24 @@ pub enum DecodeStateResponseError {
     /// Error while decoding the Protobuf encoding.
     ProtobufDecode,
 }
+
+#[cfg(test)]
+mod tests {
+    #[test]
+    fn request_encode_decode() {
+        let request = super::StateRequest {
+            block_hash: &[0xaa; 32],
+            start_key: super::StateRequestStart::ChildTrieDefault {
+                child_trie: b"foo",
+                key: b"bar",
+            },
+        };
+
+        let encoded = super::build_state_request(request.clone()).fold(Vec::new(), |mut a, b| {
+            a.extend_from_slice(b.as_ref());
+            a
+        });
+
+        assert_eq!(super::decode_state_request(&encoded).unwrap(), request);
+    }
+}
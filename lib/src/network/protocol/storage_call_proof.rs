@@ -17,7 +17,11 @@
 
 use crate::util::protobuf;
 
-use alloc::{borrow::Cow, vec::Vec};
+use alloc::{borrow::Cow, vec, vec::Vec};
+use core::iter;
+
+/// Prefix of the keys of the main trie that refer to a default child trie.
+const DEFAULT_CHILD_STORAGE_KEY_PREFIX: &[u8] = b":child_storage:default:";
 
 /// Description of a storage proof request that can be sent to a peer.
 #[derive(Debug, Clone, PartialEq, Eq)]
@@ -92,6 +96,124 @@ pub fn build_call_proof_request<'a>(
     )
 }
 
+/// Request decoded by [`decode_storage_or_call_proof_request`].
+#[derive(Debug, Clone)]
+pub enum StorageOrCallProofRequest<'a> {
+    /// Request for a storage proof.
+    StorageProof(StorageProofRequestConfig<vec::IntoIter<&'a [u8]>>),
+    /// Request for a storage proof of keys of a default child trie.
+    ChildStorageProof(ChildStorageProofRequestConfig<'a, vec::IntoIter<&'a [u8]>>),
+    /// Request for a call proof.
+    CallProof(CallProofRequestConfig<'a, iter::Once<&'a [u8]>>),
+}
+
+/// Description of a request for a storage proof of keys of a default child trie.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct ChildStorageProofRequestConfig<'a, TKeysIter> {
+    /// Hash of the block to request the storage of.
+    pub block_hash: [u8; 32],
+    /// Name of the default child trie, in other words its key in the main trie without the
+    /// `:child_storage:default:` prefix.
+    pub child_trie: &'a [u8],
+    /// List of storage keys to query within the child trie.
+    pub keys: TKeysIter,
+}
+
+/// Decodes a storage proof request or a call proof request.
+pub fn decode_storage_or_call_proof_request(
+    request_bytes: &[u8],
+) -> Result<StorageOrCallProofRequest, DecodeStorageOrCallProofRequestError> {
+    let mut parser = nom::combinator::all_consuming::<_, _, nom::error::Error<&[u8]>, _>(
+        nom::combinator::complete(protobuf::message_decode! {
+            #[optional] call_request = 1 => protobuf::message_tag_decode(protobuf::message_decode!{
+                #[required] block = 2 => protobuf::bytes_tag_decode,
+                #[required] method = 3 => protobuf::string_tag_decode,
+                #[required] data = 4 => protobuf::bytes_tag_decode,
+            }),
+            #[optional] read_request = 2 => protobuf::message_tag_decode(protobuf::message_decode!{
+                #[required] block = 2 => protobuf::bytes_tag_decode,
+                #[repeated(max = 1024)] keys = 3 => protobuf::bytes_tag_decode,
+            }),
+            #[optional] read_child_request = 4 => protobuf::message_tag_decode(protobuf::message_decode!{
+                #[required] block = 2 => protobuf::bytes_tag_decode,
+                #[required] storage_key = 3 => protobuf::bytes_tag_decode,
+                #[repeated(max = 1024)] keys = 6 => protobuf::bytes_tag_decode,
+            }),
+        }),
+    );
+
+    let decoded = match nom::Finish::finish(parser(request_bytes)) {
+        Ok((_, rq)) => rq,
+        Err(_) => return Err(DecodeStorageOrCallProofRequestError::ProtobufDecode),
+    };
+
+    match (
+        decoded.call_request,
+        decoded.read_request,
+        decoded.read_child_request,
+    ) {
+        (Some(call_request), None, None) => Ok(StorageOrCallProofRequest::CallProof(
+            CallProofRequestConfig {
+                block_hash: <[u8; 32]>::try_from(call_request.block)
+                    .map_err(|_| DecodeStorageOrCallProofRequestError::InvalidBlockHashLength)?,
+                method: Cow::Borrowed(call_request.method),
+                parameter_vectored: iter::once(call_request.data),
+            },
+        )),
+        (None, Some(read_request), None) => Ok(StorageOrCallProofRequest::StorageProof(
+            StorageProofRequestConfig {
+                block_hash: <[u8; 32]>::try_from(read_request.block)
+                    .map_err(|_| DecodeStorageOrCallProofRequestError::InvalidBlockHashLength)?,
+                keys: read_request.keys.into_iter(),
+            },
+        )),
+        (None, None, Some(read_child_request)) => Ok(StorageOrCallProofRequest::ChildStorageProof(
+            ChildStorageProofRequestConfig {
+                block_hash: <[u8; 32]>::try_from(read_child_request.block)
+                    .map_err(|_| DecodeStorageOrCallProofRequestError::InvalidBlockHashLength)?,
+                child_trie: read_child_request
+                    .storage_key
+                    .strip_prefix(DEFAULT_CHILD_STORAGE_KEY_PREFIX)
+                    .ok_or(DecodeStorageOrCallProofRequestError::InvalidChildTrieKey)?,
+                keys: read_child_request.keys.into_iter(),
+            },
+        )),
+        _ => Err(DecodeStorageOrCallProofRequestError::ProtobufDecode),
+    }
+}
+
+/// Error potentially returned by [`decode_storage_or_call_proof_request`].
+#[derive(Debug, derive_more::Display, Clone)]
+pub enum DecodeStorageOrCallProofRequestError {
+    /// Error while decoding the Protobuf encoding.
+    ProtobufDecode,
+    /// Block hash length isn't correct.
+    InvalidBlockHashLength,
+    /// Key of the child trie doesn't start with `:child_storage:default:`.
+    InvalidChildTrieKey,
+}
+
+/// Builds the bytes corresponding to a response to a storage proof request or a call proof
+/// request.
+///
+/// Must be passed the SCALE-encoded Merkle proof, or `None` if the request couldn't be answered.
+pub fn build_storage_or_call_proof_response(
+    ty: StorageOrCallProof,
+    proof: Option<&'_ [u8]>,
+) -> impl Iterator<Item = impl AsRef<[u8]> + '_> + '_ {
+    let field_num = match ty {
+        StorageOrCallProof::CallProof => 1,
+        StorageOrCallProof::StorageProof => 2,
+    };
+
+    protobuf::message_tag_encode(
+        field_num,
+        proof
+            .into_iter()
+            .flat_map(|proof| protobuf::bytes_tag_encode(2, proof)),
+    )
+}
+
 /// Decodes a response to a storage proof request or a call proof request.
 ///
 /// On success, returns a SCALE-encoded Merkle proof, or `None` if the remote couldn't answer
@@ -141,3 +263,42 @@ pub enum StorageOrCallProof {
     StorageProof,
     CallProof,
 }
+
+#[cfg(test)]
+mod tests {
+    #[test]
+    fn decode_child_storage_proof_request() {
+        let mut inner = vec![0x12, 32];
+        inner.extend_from_slice(&[0xaa; 32]);
+        inner.extend_from_slice(&[0x1a, 26]);
+        inner.extend_from_slice(b":child_storage:default:foo");
+        inner.extend_from_slice(&[0x32, 3, 1, 2, 3]);
+        inner.extend_from_slice(&[0x32, 1, 4]);
+        let mut request = vec![0x22, u8::try_from(inner.len()).unwrap()];
+        request.extend_from_slice(&inner);
+
+        match super::decode_storage_or_call_proof_request(&request).unwrap() {
+            super::StorageOrCallProofRequest::ChildStorageProof(rq) => {
+                assert_eq!(rq.block_hash, [0xaa; 32]);
+                assert_eq!(rq.child_trie, b"foo");
+                assert_eq!(rq.keys.collect::<Vec<_>>(), vec![&[1, 2, 3][..], &[4][..]]);
+            }
+            _ => panic!(),
+        }
+    }
+
+    #[test]
+    fn decode_child_storage_proof_request_bad_prefix() {
+        let mut inner = vec![0x12, 32];
+        inner.extend_from_slice(&[0xaa; 32]);
+        inner.extend_from_slice(&[0x1a, 3]);
+        inner.extend_from_slice(b"foo");
+        let mut request = vec![0x22, u8::try_from(inner.len()).unwrap()];
+        request.extend_from_slice(&inner);
+
+        assert!(matches!(
+            super::decode_storage_or_call_proof_request(&request),
+            Err(super::DecodeStorageOrCallProofRequestError::InvalidChildTrieKey)
+        ));
+    }
+}
@@ -0,0 +1,65 @@
+// Smoldot
+// Copyright (C) 2023  Pierre Krieger
+// SPDX-License-Identifier: GPL-3.0-or-later WITH Classpath-exception-2.0
+
+// This program is free software: you can redistribute it and/or modify
+// it under the terms of the GNU General Public License as published by
+// the Free Software Foundation, either version 3 of the License, or
+// (at your option) any later version.
+
+// This program is distributed in the hope that it will be useful,
+// but WITHOUT ANY WARRANTY; without even the implied warranty of
+// MERCHANTABILITY or FITNESS FOR A PARTICULAR PURPOSE.  See the
+// GNU General Public License for more details.
+
+// You should have received a copy of the GNU General Public License
+// along with this program.  If not, see <http://www.gnu.org/licenses/>.
+
+//! The transactions protocol is a notifications protocol.
+//!
+//! Each notification consists in a SCALE-encoded list of transactions. Each transaction is
+//! itself SCALE-encoded, meaning that it is prefixed with its length.
+
+use alloc::vec::Vec;
+use nom::Finish as _;
+
+/// Decodes a transactions notification.
+///
+/// Returns the list of SCALE-encoded transactions found in the notification, in the same format
+/// as the one passed to [`crate::network::service::ChainNetwork::announce_transaction`].
+pub fn decode_transactions_notification(
+    bytes: &[u8],
+) -> Result<Vec<&[u8]>, DecodeTransactionsNotificationError> {
+    let result: Result<_, nom::error::Error<_>> =
+        nom::combinator::all_consuming(nom::combinator::complete(nom::multi::length_count(
+            crate::util::nom_scale_compact_usize,
+            nom::combinator::recognize(crate::util::nom_bytes_decode),
+        )))(bytes)
+        .finish();
+
+    match result {
+        Ok((_, transactions)) => Ok(transactions),
+        Err(err) => Err(DecodeTransactionsNotificationError(err.code)),
+    }
+}
+
+/// Error potentially returned by [`decode_transactions_notification`].
+#[derive(Debug, derive_more::Display)]
+#[display(fmt = "Failed to decode a transactions notification")]
+pub struct DecodeTransactionsNotificationError(nom::error::ErrorKind);
+
+#[cfg(test)]
+mod tests {
+    #[test]
+    fn basic_decode() {
+        let actual =
+            super::decode_transactions_notification(&[8, 12, 1, 2, 3, 8, 0xaa, 0xbb]).unwrap();
+        assert_eq!(actual, vec![&[12, 1, 2, 3][..], &[8, 0xaa, 0xbb][..]]);
+    }
+
+    #[test]
+    fn trailing_data_or_truncated() {
+        assert!(super::decode_transactions_notification(&[4, 12, 1, 2, 3, 0]).is_err());
+        assert!(super::decode_transactions_notification(&[4, 12, 1, 2]).is_err());
+    }
+}
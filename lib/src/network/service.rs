@@ -43,13 +43,15 @@ mod notifications;
 mod requests_responses;
 
 pub use notifications::{
-    EncodedBlockAnnounce, EncodedBlockAnnounceHandshake, EncodedGrandpaCommitMessage, GrandpaState,
+    EncodedBlockAnnounce, EncodedBlockAnnounceHandshake, EncodedGrandpaCatchUpMessage,
+    EncodedGrandpaCommitMessage, EncodedGrandpaVoteMessage, EncodedTransactions, GrandpaState,
     NotificationsOutErr,
 };
 
 pub use requests_responses::{
     BlocksRequestError, BlocksRequestResponseEntryError, CallProofRequestError, DiscoveryError,
-    EncodedGrandpaWarpSyncResponse, EncodedMerkleProof, EncodedStateResponse,
+    EncodedCallProofRequest, EncodedGrandpaWarpSyncResponse, EncodedMerkleProof,
+    EncodedStateRequest, EncodedStateResponse, EncodedStorageProofRequest,
     GrandpaWarpSyncRequestError, KademliaFindNodeError, KademliaOperationId, RequestResult,
     StateRequestError, StorageProofRequestError,
 };
@@ -119,6 +121,15 @@ pub struct ChainConfig {
     /// `true` if incoming block requests are allowed.
     pub allow_inbound_block_requests: bool,
 
+    /// `true` if incoming storage proof and call proof requests are allowed.
+    pub allow_inbound_light_requests: bool,
+
+    /// `true` if incoming GrandPa warp sync requests are allowed.
+    pub allow_inbound_grandpa_warp_sync_requests: bool,
+
+    /// `true` if incoming state requests are allowed.
+    pub allow_inbound_state_requests: bool,
+
     pub in_slots: u32,
 
     pub out_slots: u32,
@@ -237,6 +248,10 @@ struct KBucketsPeer {
 enum InRequestTy {
     Identify { observed_addr: multiaddr::Multiaddr },
     Blocks,
+    StorageProof,
+    CallProof,
+    GrandpaWarpSync,
+    State,
 }
 
 enum OutRequestTy {
@@ -1257,6 +1272,35 @@ pub enum Event {
         message: EncodedGrandpaCommitMessage,
     },
 
+    /// Received a GrandPa catch up message from the network, normally in response to
+    /// [`ChainNetwork::send_grandpa_catch_up_request`].
+    GrandpaCatchUpMessage {
+        /// Identity of the sender of the message.
+        peer_id: PeerId,
+        /// Index of the chain the catch up message relates to.
+        chain_index: usize,
+        message: EncodedGrandpaCatchUpMessage,
+    },
+
+    /// Received a GrandPa catch up request from the network. It should be answered with
+    /// [`ChainNetwork::send_grandpa_catch_up`].
+    GrandpaCatchUpRequest {
+        /// Identity of the sender of the request.
+        peer_id: PeerId,
+        /// Index of the chain the request relates to.
+        chain_index: usize,
+        request: protocol::CatchUpRequest,
+    },
+
+    /// Received a GrandPa vote message from the network.
+    GrandpaVoteMessage {
+        /// Identity of the sender of the message.
+        peer_id: PeerId,
+        /// Index of the chain the vote message relates to.
+        chain_index: usize,
+        message: EncodedGrandpaVoteMessage,
+    },
+
     /// Error in the protocol in a connection, such as failure to decode a message. This event
     /// doesn't have any consequence on the health of the connection, and is purely for diagnostic
     /// purposes.
@@ -1291,6 +1335,67 @@ pub enum Event {
         /// Identifier of the request. Necessary to send back the answer.
         request_id: InRequestId,
     },
+    /// A remote has sent a request for a storage proof.
+    ///
+    /// Can only happen for chains where [`ChainConfig::allow_inbound_light_requests`] is `true`.
+    ///
+    /// You are strongly encouraged to call [`ChainNetwork::respond_storage_proof`].
+    StorageProofRequestIn {
+        /// Remote that has sent the request.
+        peer_id: PeerId,
+        /// Index of the chain concerned by the request.
+        chain_index: usize,
+        /// Information about the request.
+        request: EncodedStorageProofRequest,
+        /// Identifier of the request. Necessary to send back the answer.
+        request_id: InRequestId,
+    },
+    /// A remote has sent a request for a call proof.
+    ///
+    /// Can only happen for chains where [`ChainConfig::allow_inbound_light_requests`] is `true`.
+    ///
+    /// You are strongly encouraged to call [`ChainNetwork::respond_call_proof`].
+    CallProofRequestIn {
+        /// Remote that has sent the request.
+        peer_id: PeerId,
+        /// Index of the chain concerned by the request.
+        chain_index: usize,
+        /// Information about the request.
+        request: EncodedCallProofRequest,
+        /// Identifier of the request. Necessary to send back the answer.
+        request_id: InRequestId,
+    },
+    /// A remote has sent a GrandPa warp sync request.
+    ///
+    /// Can only happen for chains where
+    /// [`ChainConfig::allow_inbound_grandpa_warp_sync_requests`] is `true`.
+    ///
+    /// You are strongly encouraged to call [`ChainNetwork::respond_grandpa_warp_sync`].
+    GrandpaWarpSyncRequestIn {
+        /// Remote that has sent the request.
+        peer_id: PeerId,
+        /// Index of the chain concerned by the request.
+        chain_index: usize,
+        /// Hash of the block the warp sync proof should start at.
+        begin_hash: [u8; 32],
+        /// Identifier of the request. Necessary to send back the answer.
+        request_id: InRequestId,
+    },
+    /// A remote has sent a state request.
+    ///
+    /// Can only happen for chains where [`ChainConfig::allow_inbound_state_requests`] is `true`.
+    ///
+    /// You are strongly encouraged to call [`ChainNetwork::respond_state`].
+    StateRequestIn {
+        /// Remote that has sent the request.
+        peer_id: PeerId,
+        /// Index of the chain concerned by the request.
+        chain_index: usize,
+        /// Information about the request.
+        request: EncodedStateRequest,
+        /// Identifier of the request. Necessary to send back the answer.
+        request_id: InRequestId,
+    },
 
     RequestInCancel {
         request_id: InRequestId,
@@ -1300,10 +1405,18 @@ pub enum Event {
         operation_id: KademliaOperationId,
         result: Result<Vec<(PeerId, Vec<Vec<u8>>)>, DiscoveryError>,
     },
-    /*Transactions {
+
+    /// Received transactions from a peer.
+    ///
+    /// Can only happen after a [`Event::ChainConnected`] with the given `PeerId` and chain index
+    /// combination has happened.
+    Transactions {
+        /// Identity of the sender of the transactions.
         peer_id: PeerId,
+        /// Index of the chain the transactions relate to.
+        chain_index: usize,
         transactions: EncodedTransactions,
-    }*/
+    },
 }
 
 #[derive(Debug, Copy, Clone, PartialEq, Eq, PartialOrd, Ord, Hash)]
@@ -1327,9 +1440,20 @@ pub enum ProtocolError {
     /// Error while decoding a received Grandpa notification.
     #[display(fmt = "Error while decoding a received Grandpa notification: {_0}")]
     BadGrandpaNotification(protocol::DecodeGrandpaNotificationError),
+    /// Error while decoding a received transactions notification.
+    #[display(fmt = "Error while decoding a received transactions notification: {_0}")]
+    BadTransactionsNotification(protocol::DecodeTransactionsNotificationError),
     /// Received an invalid identify request.
     BadIdentifyRequest,
     /// Error while decoding a received blocks request.
     #[display(fmt = "Error while decoding a received blocks request: {_0}")]
     BadBlocksRequest(protocol::DecodeBlockRequestError),
+    /// Error while decoding a received storage proof or call proof request.
+    #[display(fmt = "Error while decoding a received storage or call proof request: {_0}")]
+    BadStorageOrCallProofRequest(protocol::DecodeStorageOrCallProofRequestError),
+    /// Received a GrandPa warp sync request whose length isn't 32 bytes.
+    BadGrandpaWarpSyncRequest,
+    /// Error while decoding a received state request.
+    #[display(fmt = "Error while decoding a received state request: {_0}")]
+    BadStateRequest(protocol::DecodeStateRequestError),
 }
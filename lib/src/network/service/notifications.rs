@@ -567,12 +567,26 @@ where
             let chain_index = notifications_protocol_index / NOTIFICATIONS_PROTOCOLS_PER_CHAIN;
 
             // Don't report events about nodes we don't have an outbound substream with.
-            if !self.open_chains.contains(&(peer_id, chain_index)) {
+            // TODO: cloning of peer_id :(
+            if !self.open_chains.contains(&(peer_id.clone(), chain_index)) {
                 return None;
             }
 
-            // TODO: this is unimplemented
-            None
+            // Check the format of the notification.
+            if let Err(err) = protocol::decode_transactions_notification(&notification) {
+                return Some(Event::ProtocolError {
+                    error: ProtocolError::BadTransactionsNotification(err),
+                    peer_id,
+                });
+            }
+
+            Some(Event::Transactions {
+                chain_index,
+                peer_id,
+                transactions: EncodedTransactions {
+                    message: notification,
+                },
+            })
         } else if notifications_protocol_index % NOTIFICATIONS_PROTOCOLS_PER_CHAIN == 2 {
             let chain_index = notifications_protocol_index / NOTIFICATIONS_PROTOCOLS_PER_CHAIN;
             let block_number_bytes = self.chains[chain_index].chain_config.block_number_bytes;
@@ -603,6 +617,31 @@ where
                         block_number_bytes,
                     },
                 }),
+                protocol::GrandpaNotificationRef::Vote(_) => Some(Event::GrandpaVoteMessage {
+                    chain_index,
+                    peer_id,
+                    message: EncodedGrandpaVoteMessage {
+                        message: notification,
+                        block_number_bytes,
+                    },
+                }),
+                protocol::GrandpaNotificationRef::CatchUp(_) => {
+                    Some(Event::GrandpaCatchUpMessage {
+                        chain_index,
+                        peer_id,
+                        message: EncodedGrandpaCatchUpMessage {
+                            message: notification,
+                            block_number_bytes,
+                        },
+                    })
+                }
+                protocol::GrandpaNotificationRef::CatchUpRequest(request) => {
+                    Some(Event::GrandpaCatchUpRequest {
+                        chain_index,
+                        peer_id,
+                        request,
+                    })
+                }
                 protocol::GrandpaNotificationRef::Neighbor(n) => {
                     Some(Event::GrandpaNeighborPacket {
                         chain_index,
@@ -614,11 +653,6 @@ where
                         },
                     })
                 }
-                _ => {
-                    // Any other type of message is currently ignored. Support for them could be
-                    // added in the future.
-                    None
-                }
             }
         } else {
             // Unrecognized notifications protocol.
@@ -693,6 +727,138 @@ where
             .unwrap() = grandpa_state;
     }
 
+    /// Sends a GrandPa vote to all the peers we have a GrandPa substream with.
+    ///
+    /// > **Note**: The vote isn't validated in any way by this method.
+    ///
+    /// This function might generate a message destined to connections. Use
+    /// [`ChainNetwork::pull_message_to_connection`] to process these messages after it has
+    /// returned.
+    ///
+    /// # Panic
+    ///
+    /// Panics if `chain_index` is out of range.
+    ///
+    pub fn broadcast_grandpa_vote(&mut self, chain_index: usize, vote: protocol::VoteMessageRef) {
+        let notification = protocol::GrandpaNotificationRef::Vote(vote)
+            .scale_encoding(self.chains[chain_index].chain_config.block_number_bytes)
+            .fold(Vec::new(), |mut a, b| {
+                a.extend_from_slice(b.as_ref());
+                a
+            });
+
+        self.inner.broadcast_notification(
+            chain_index * NOTIFICATIONS_PROTOCOLS_PER_CHAIN + 2,
+            notification,
+        );
+    }
+
+    /// Sends a GrandPa commit message to all the peers we have a GrandPa substream with.
+    ///
+    /// > **Note**: The commit isn't validated in any way by this method.
+    ///
+    /// This function might generate a message destined to connections. Use
+    /// [`ChainNetwork::pull_message_to_connection`] to process these messages after it has
+    /// returned.
+    ///
+    /// # Panic
+    ///
+    /// Panics if `chain_index` is out of range.
+    ///
+    pub fn broadcast_grandpa_commit(
+        &mut self,
+        chain_index: usize,
+        commit: protocol::CommitMessageRef,
+    ) {
+        let notification = protocol::GrandpaNotificationRef::Commit(commit)
+            .scale_encoding(self.chains[chain_index].chain_config.block_number_bytes)
+            .fold(Vec::new(), |mut a, b| {
+                a.extend_from_slice(b.as_ref());
+                a
+            });
+
+        self.inner.broadcast_notification(
+            chain_index * NOTIFICATIONS_PROTOCOLS_PER_CHAIN + 2,
+            notification,
+        );
+    }
+
+    /// Sends a GrandPa catch up request to the given peer, asking it to send back the votes of
+    /// the latest round it has completed.
+    ///
+    /// The response, if any, is reported as an [`Event::GrandpaCatchUpMessage`].
+    ///
+    /// This function might generate a message destined a connection. Use
+    /// [`ChainNetwork::pull_message_to_connection`] to process messages after it has returned.
+    ///
+    /// # Panic
+    ///
+    /// Panics if `chain_index` is out of range.
+    /// Panics if there is no GrandPa substream with the given peer. Use
+    /// [`ChainNetwork::can_send_grandpa_messages`] to check this ahead of time.
+    ///
+    pub fn send_grandpa_catch_up_request(
+        &mut self,
+        target: &PeerId,
+        chain_index: usize,
+        request: protocol::CatchUpRequest,
+    ) -> Result<(), QueueNotificationError> {
+        let notification = protocol::GrandpaNotificationRef::CatchUpRequest(request)
+            .scale_encoding(self.chains[chain_index].chain_config.block_number_bytes)
+            .fold(Vec::new(), |mut a, b| {
+                a.extend_from_slice(b.as_ref());
+                a
+            });
+
+        self.inner.queue_notification(
+            target,
+            chain_index * NOTIFICATIONS_PROTOCOLS_PER_CHAIN + 2,
+            notification,
+        )
+    }
+
+    /// Sends a GrandPa catch up message to the given peer, normally in response to an
+    /// [`Event::GrandpaCatchUpRequest`].
+    ///
+    /// > **Note**: The catch up message isn't validated in any way by this method.
+    ///
+    /// This function might generate a message destined a connection. Use
+    /// [`ChainNetwork::pull_message_to_connection`] to process messages after it has returned.
+    ///
+    /// # Panic
+    ///
+    /// Panics if `chain_index` is out of range.
+    /// Panics if there is no GrandPa substream with the given peer. Use
+    /// [`ChainNetwork::can_send_grandpa_messages`] to check this ahead of time.
+    ///
+    pub fn send_grandpa_catch_up(
+        &mut self,
+        target: &PeerId,
+        chain_index: usize,
+        catch_up: protocol::CatchUpRef,
+    ) -> Result<(), QueueNotificationError> {
+        let notification = protocol::GrandpaNotificationRef::CatchUp(catch_up)
+            .scale_encoding(self.chains[chain_index].chain_config.block_number_bytes)
+            .fold(Vec::new(), |mut a, b| {
+                a.extend_from_slice(b.as_ref());
+                a
+            });
+
+        self.inner.queue_notification(
+            target,
+            chain_index * NOTIFICATIONS_PROTOCOLS_PER_CHAIN + 2,
+            notification,
+        )
+    }
+
+    /// Returns `true` if it is allowed to call [`ChainNetwork::send_grandpa_catch_up_request`]
+    /// or [`ChainNetwork::send_grandpa_catch_up`],
+    /// in other words if there is an outbound GrandPa substream currently open with the target.
+    pub fn can_send_grandpa_messages(&self, target: &PeerId, chain_index: usize) -> bool {
+        self.inner
+            .can_queue_notification(target, chain_index * NOTIFICATIONS_PROTOCOLS_PER_CHAIN + 2)
+    }
+
     ///
     /// This function might generate a message destined a connection. Use
     /// [`ChainNetwork::pull_message_to_connection`] to process messages after it has returned.
@@ -868,3 +1034,70 @@ impl fmt::Debug for EncodedGrandpaCommitMessage {
         fmt::Debug::fmt(&self.decode(), f)
     }
 }
+
+/// Undecoded but valid GrandPa catch up message.
+#[derive(Clone)]
+pub struct EncodedGrandpaCatchUpMessage {
+    message: Vec<u8>,
+    block_number_bytes: usize,
+}
+
+impl EncodedGrandpaCatchUpMessage {
+    /// Returns the decoded version of the catch up message.
+    pub fn decode(&self) -> protocol::CatchUpRef {
+        match protocol::decode_grandpa_notification(&self.message, self.block_number_bytes) {
+            Ok(protocol::GrandpaNotificationRef::CatchUp(msg)) => msg,
+            _ => unreachable!(),
+        }
+    }
+}
+
+impl fmt::Debug for EncodedGrandpaCatchUpMessage {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        fmt::Debug::fmt(&self.decode(), f)
+    }
+}
+
+/// Undecoded but valid transactions notification.
+#[derive(Clone)]
+pub struct EncodedTransactions {
+    message: Vec<u8>,
+}
+
+impl EncodedTransactions {
+    /// Returns the list of SCALE-encoded transactions of the notification.
+    pub fn decode(&self) -> Vec<&[u8]> {
+        protocol::decode_transactions_notification(&self.message).unwrap()
+    }
+}
+
+impl fmt::Debug for EncodedTransactions {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        f.debug_list()
+            .entries(self.decode().into_iter().map(hex::encode))
+            .finish()
+    }
+}
+
+/// Undecoded but valid GrandPa vote message.
+#[derive(Clone)]
+pub struct EncodedGrandpaVoteMessage {
+    message: Vec<u8>,
+    block_number_bytes: usize,
+}
+
+impl EncodedGrandpaVoteMessage {
+    /// Returns the decoded version of the vote message.
+    pub fn decode(&self) -> protocol::VoteMessageRef {
+        match protocol::decode_grandpa_notification(&self.message, self.block_number_bytes) {
+            Ok(protocol::GrandpaNotificationRef::Vote(msg)) => msg,
+            _ => unreachable!(),
+        }
+    }
+}
+
+impl fmt::Debug for EncodedGrandpaVoteMessage {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        fmt::Debug::fmt(&self.decode(), f)
+    }
+}
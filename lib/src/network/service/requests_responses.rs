@@ -86,8 +86,7 @@ pub(super) fn protocols<'a>(
                 max_size: 1024 * 512,
             },
             max_response_size: 10 * 1024 * 1024,
-            // TODO: make this configurable
-            inbound_allowed: false,
+            inbound_allowed: chain.allow_inbound_light_requests,
         }))
         .chain(iter::once(peers::ConfigRequestResponse {
             name: match &chain.fork_id {
@@ -110,8 +109,7 @@ pub(super) fn protocols<'a>(
             },
             inbound_config: peers::ConfigRequestResponseIn::Payload { max_size: 32 },
             max_response_size: 16 * 1024 * 1024,
-            // We don't support inbound warp sync requests (yet).
-            inbound_allowed: false,
+            inbound_allowed: chain.allow_inbound_grandpa_warp_sync_requests,
         }))
         .chain(iter::once(peers::ConfigRequestResponse {
             name: match &chain.fork_id {
@@ -125,8 +123,7 @@ pub(super) fn protocols<'a>(
             // is larger than 2MiB, the response is allowed to be bigger, as otherwise it
             // wouldn't be possible to make progress.
             max_response_size: 16 * 1024 * 1024,
-            // We don't support inbound state requests (yet).
-            inbound_allowed: false,
+            inbound_allowed: chain.allow_inbound_state_requests,
         }))
     }))
     .collect()
@@ -310,41 +307,109 @@ where
                     error: ProtocolError::BadIdentifyRequest,
                 }
             }
-        } else if ((protocol_index - 1) % requests_responses::REQUEST_RESPONSE_PROTOCOLS_PER_CHAIN)
-            != 0
-        {
-            // Protocols that receive requests are whitelisted, meaning that no other protocol
-            // indices can reach here.
-            unreachable!()
         } else {
             let chain_index =
                 (protocol_index - 1) / requests_responses::REQUEST_RESPONSE_PROTOCOLS_PER_CHAIN;
 
-            match protocol::decode_block_request(
-                self.chains[chain_index].chain_config.block_number_bytes,
-                &request_payload,
-            ) {
-                Ok(config) => {
-                    let _prev_value = self
-                        .in_requests_types
-                        .insert(request_id, InRequestTy::Blocks);
-                    debug_assert!(_prev_value.is_none());
-
-                    Event::BlocksRequestIn {
-                        peer_id,
-                        chain_index,
-                        config,
-                        request_id,
+            // The order of the protocols is defined in the `protocols` function.
+            // Protocols that receive requests are whitelisted, meaning that only the protocols
+            // that are handled here can be reached.
+            let (in_request_ty, event) = match (protocol_index - 1)
+                % requests_responses::REQUEST_RESPONSE_PROTOCOLS_PER_CHAIN
+            {
+                0 => match protocol::decode_block_request(
+                    self.chains[chain_index].chain_config.block_number_bytes,
+                    &request_payload,
+                ) {
+                    Ok(config) => (
+                        InRequestTy::Blocks,
+                        Event::BlocksRequestIn {
+                            peer_id,
+                            chain_index,
+                            config,
+                            request_id,
+                        },
+                    ),
+                    Err(error) => {
+                        self.inner.respond_in_request(request_id, Err(()));
+                        return Event::ProtocolError {
+                            peer_id,
+                            error: ProtocolError::BadBlocksRequest(error),
+                        };
                     }
-                }
-                Err(error) => {
-                    self.inner.respond_in_request(request_id, Err(()));
-                    Event::ProtocolError {
-                        peer_id,
-                        error: ProtocolError::BadBlocksRequest(error),
+                },
+                1 => match protocol::decode_storage_or_call_proof_request(&request_payload) {
+                    Ok(
+                        protocol::StorageOrCallProofRequest::StorageProof(_)
+                        | protocol::StorageOrCallProofRequest::ChildStorageProof(_),
+                    ) => (
+                        InRequestTy::StorageProof,
+                        Event::StorageProofRequestIn {
+                            peer_id,
+                            chain_index,
+                            request: EncodedStorageProofRequest(request_payload),
+                            request_id,
+                        },
+                    ),
+                    Ok(protocol::StorageOrCallProofRequest::CallProof(_)) => (
+                        InRequestTy::CallProof,
+                        Event::CallProofRequestIn {
+                            peer_id,
+                            chain_index,
+                            request: EncodedCallProofRequest(request_payload),
+                            request_id,
+                        },
+                    ),
+                    Err(error) => {
+                        self.inner.respond_in_request(request_id, Err(()));
+                        return Event::ProtocolError {
+                            peer_id,
+                            error: ProtocolError::BadStorageOrCallProofRequest(error),
+                        };
                     }
-                }
-            }
+                },
+                3 => match <[u8; 32]>::try_from(&request_payload[..]) {
+                    Ok(begin_hash) => (
+                        InRequestTy::GrandpaWarpSync,
+                        Event::GrandpaWarpSyncRequestIn {
+                            peer_id,
+                            chain_index,
+                            begin_hash,
+                            request_id,
+                        },
+                    ),
+                    Err(_) => {
+                        self.inner.respond_in_request(request_id, Err(()));
+                        return Event::ProtocolError {
+                            peer_id,
+                            error: ProtocolError::BadGrandpaWarpSyncRequest,
+                        };
+                    }
+                },
+                4 => match protocol::decode_state_request(&request_payload) {
+                    Ok(_) => (
+                        InRequestTy::State,
+                        Event::StateRequestIn {
+                            peer_id,
+                            chain_index,
+                            request: EncodedStateRequest(request_payload),
+                            request_id,
+                        },
+                    ),
+                    Err(error) => {
+                        self.inner.respond_in_request(request_id, Err(()));
+                        return Event::ProtocolError {
+                            peer_id,
+                            error: ProtocolError::BadStateRequest(error),
+                        };
+                    }
+                },
+                _ => unreachable!(),
+            };
+
+            let _prev_value = self.in_requests_types.insert(request_id, in_request_ty);
+            debug_assert!(_prev_value.is_none());
+            event
         }
     }
 
@@ -859,7 +924,15 @@ where
     ///
     /// This function might generate a message destined a connection. Use
     /// [`ChainNetwork::pull_message_to_connection`] to process messages after it has returned.
-    pub fn respond_identify(&mut self, request_id: InRequestId, agent_version: &str) {
+    ///
+    /// `listen_addrs` contains the list of addresses the local node is listening on and that are
+    /// reported to the remote.
+    pub fn respond_identify<'a>(
+        &mut self,
+        request_id: InRequestId,
+        agent_version: &str,
+        listen_addrs: impl Iterator<Item = &'a multiaddr::Multiaddr>,
+    ) {
         let observed_addr = match self.in_requests_types.remove(&request_id) {
             Some(InRequestTy::Identify { observed_addr }) => observed_addr.into_vec(),
             _ => panic!(),
@@ -870,7 +943,7 @@ where
                 protocol_version: "/substrate/1.0", // TODO: same value as in Substrate, see also https://github.com/paritytech/substrate/issues/14331
                 agent_version,
                 ed25519_public_key: *self.inner.noise_key().libp2p_public_ed25519_key(),
-                listen_addrs: iter::empty(), // TODO:
+                listen_addrs: listen_addrs.map(|a| a.as_ref()),
                 observed_addr: &observed_addr,
                 protocols: self
                     .inner
@@ -923,6 +996,124 @@ where
 
         self.inner.respond_in_request(request_id, response);
     }
+
+    /// Queue the response to send back.
+    ///
+    /// Pass `None` in order to indicate that the proof couldn't be generated, for example
+    /// because the storage of the requested block isn't available locally.
+    ///
+    /// Has no effect if the connection that sends the request no longer exists.
+    ///
+    /// This function might generate a message destined a connection. Use
+    /// [`ChainNetwork::pull_message_to_connection`] to process messages after it has returned.
+    pub fn respond_storage_proof(&mut self, request_id: InRequestId, proof: Option<&[u8]>) {
+        match self.in_requests_types.remove(&request_id) {
+            Some(InRequestTy::StorageProof) => {}
+            _ => panic!(),
+        };
+
+        let response = protocol::build_storage_or_call_proof_response(
+            protocol::StorageOrCallProof::StorageProof,
+            proof,
+        )
+        .fold(Vec::new(), |mut a, b| {
+            a.extend_from_slice(b.as_ref());
+            a
+        });
+
+        self.inner.respond_in_request(request_id, Ok(response));
+    }
+
+    /// Queue the response to send back.
+    ///
+    /// Pass `None` in order to indicate that the proof couldn't be generated, for example
+    /// because the storage of the requested block isn't available locally.
+    ///
+    /// Has no effect if the connection that sends the request no longer exists.
+    ///
+    /// This function might generate a message destined a connection. Use
+    /// [`ChainNetwork::pull_message_to_connection`] to process messages after it has returned.
+    pub fn respond_call_proof(&mut self, request_id: InRequestId, proof: Option<&[u8]>) {
+        match self.in_requests_types.remove(&request_id) {
+            Some(InRequestTy::CallProof) => {}
+            _ => panic!(),
+        };
+
+        let response = protocol::build_storage_or_call_proof_response(
+            protocol::StorageOrCallProof::CallProof,
+            proof,
+        )
+        .fold(Vec::new(), |mut a, b| {
+            a.extend_from_slice(b.as_ref());
+            a
+        });
+
+        self.inner.respond_in_request(request_id, Ok(response));
+    }
+
+    /// Queue the response to send back.
+    ///
+    /// Pass `None` in order to deny the request, for example if the requested block is unknown.
+    ///
+    /// Has no effect if the connection that sends the request no longer exists.
+    ///
+    /// This function might generate a message destined a connection. Use
+    /// [`ChainNetwork::pull_message_to_connection`] to process messages after it has returned.
+    pub fn respond_grandpa_warp_sync(
+        &mut self,
+        request_id: InRequestId,
+        response: Option<protocol::GrandpaWarpSyncResponse>,
+    ) {
+        match self.in_requests_types.remove(&request_id) {
+            Some(InRequestTy::GrandpaWarpSync) => {}
+            _ => panic!(),
+        };
+
+        let response =
+            if let Some(response) = response {
+                Ok(protocol::build_grandpa_warp_sync_response(response).fold(
+                    Vec::new(),
+                    |mut a, b| {
+                        a.extend_from_slice(b.as_ref());
+                        a
+                    },
+                ))
+            } else {
+                Err(())
+            };
+
+        self.inner.respond_in_request(request_id, response);
+    }
+
+    /// Queue the response to send back.
+    ///
+    /// Must be passed a Merkle proof containing the storage entries that follow the start key
+    /// of the request, or `None` in order to deny the request, for example if the storage of the
+    /// requested block isn't available locally.
+    ///
+    /// Has no effect if the connection that sends the request no longer exists.
+    ///
+    /// This function might generate a message destined a connection. Use
+    /// [`ChainNetwork::pull_message_to_connection`] to process messages after it has returned.
+    pub fn respond_state(&mut self, request_id: InRequestId, proof: Option<&[u8]>) {
+        match self.in_requests_types.remove(&request_id) {
+            Some(InRequestTy::State) => {}
+            _ => panic!(),
+        };
+
+        let response = if let Some(proof) = proof {
+            Ok(
+                protocol::build_state_response(proof).fold(Vec::new(), |mut a, b| {
+                    a.extend_from_slice(b.as_ref());
+                    a
+                }),
+            )
+        } else {
+            Err(())
+        };
+
+        self.inner.respond_in_request(request_id, response);
+    }
 }
 
 /// Response to an outgoing request.
@@ -977,6 +1168,82 @@ impl fmt::Debug for EncodedMerkleProof {
     }
 }
 
+/// Undecoded but valid storage proof request.
+#[derive(Clone)]
+pub struct EncodedStorageProofRequest(Vec<u8>);
+
+impl EncodedStorageProofRequest {
+    /// Returns the decoded version of the request.
+    ///
+    /// If the request concerns a default child trie, the keys are keys within this child trie.
+    /// See [`EncodedStorageProofRequest::child_trie`].
+    pub fn decode(&self) -> protocol::StorageProofRequestConfig<alloc::vec::IntoIter<&[u8]>> {
+        match protocol::decode_storage_or_call_proof_request(&self.0) {
+            Ok(protocol::StorageOrCallProofRequest::StorageProof(rq)) => rq,
+            Ok(protocol::StorageOrCallProofRequest::ChildStorageProof(rq)) => {
+                protocol::StorageProofRequestConfig {
+                    block_hash: rq.block_hash,
+                    keys: rq.keys,
+                }
+            }
+            _ => unreachable!(),
+        }
+    }
+
+    /// Returns the name of the default child trie the request concerns, or `None` if the request
+    /// concerns the main trie.
+    pub fn child_trie(&self) -> Option<&[u8]> {
+        match protocol::decode_storage_or_call_proof_request(&self.0) {
+            Ok(protocol::StorageOrCallProofRequest::StorageProof(_)) => None,
+            Ok(protocol::StorageOrCallProofRequest::ChildStorageProof(rq)) => Some(rq.child_trie),
+            _ => unreachable!(),
+        }
+    }
+}
+
+impl fmt::Debug for EncodedStorageProofRequest {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        fmt::Debug::fmt(&self.decode(), f)
+    }
+}
+
+/// Undecoded but valid call proof request.
+#[derive(Clone)]
+pub struct EncodedCallProofRequest(Vec<u8>);
+
+impl EncodedCallProofRequest {
+    /// Returns the decoded version of the request.
+    pub fn decode(&self) -> protocol::CallProofRequestConfig<iter::Once<&[u8]>> {
+        match protocol::decode_storage_or_call_proof_request(&self.0) {
+            Ok(protocol::StorageOrCallProofRequest::CallProof(rq)) => rq,
+            _ => unreachable!(),
+        }
+    }
+}
+
+impl fmt::Debug for EncodedCallProofRequest {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        fmt::Debug::fmt(&self.decode(), f)
+    }
+}
+
+/// Undecoded but valid state request.
+#[derive(Clone)]
+pub struct EncodedStateRequest(Vec<u8>);
+
+impl EncodedStateRequest {
+    /// Returns the decoded version of the request.
+    pub fn decode(&self) -> protocol::StateRequest {
+        protocol::decode_state_request(&self.0).unwrap()
+    }
+}
+
+impl fmt::Debug for EncodedStateRequest {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        fmt::Debug::fmt(&self.decode(), f)
+    }
+}
+
 /// Undecoded but valid GrandPa warp sync response.
 #[derive(Clone)]
 pub struct EncodedGrandpaWarpSyncResponse {
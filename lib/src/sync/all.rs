@@ -1551,6 +1551,46 @@ impl<TRq, TSrc, TBl> AllSync<TRq, TSrc, TBl> {
         }
     }
 
+    /// Update the state machine with a justification that wasn't received as part of a blocks
+    /// response, for example a justification generated locally.
+    ///
+    /// This function only inserts the justification into the state machine, and does not
+    /// immediately verify it.
+    ///
+    /// # Panic
+    ///
+    /// Panics if `source_id` is invalid.
+    ///
+    pub fn inject_justification(
+        &mut self,
+        source_id: SourceId,
+        consensus_engine_id: [u8; 4],
+        scale_encoded_justification: Vec<u8>,
+    ) -> InjectJustificationOutcome {
+        let source_id = self.shared.sources.get(source_id.0).unwrap();
+
+        match (&mut self.inner, source_id) {
+            (AllSyncInner::Optimistic { inner }, SourceMapping::Optimistic(source_id)) => {
+                if inner.inject_justification(
+                    *source_id,
+                    consensus_engine_id,
+                    scale_encoded_justification,
+                ) {
+                    InjectJustificationOutcome::Queued
+                } else {
+                    InjectJustificationOutcome::Discarded
+                }
+            }
+            // TODO: not implemented for the other syncing strategies
+            (AllSyncInner::AllForks(_), _) => InjectJustificationOutcome::Discarded,
+            (AllSyncInner::GrandpaWarpSync { .. }, _) => InjectJustificationOutcome::Discarded,
+
+            // Invalid internal states.
+            (AllSyncInner::Optimistic { .. }, _) => unreachable!(),
+            (AllSyncInner::Poisoned, _) => unreachable!(),
+        }
+    }
+
     /// Inject a response to a previously-emitted blocks request.
     ///
     /// # Panic
@@ -2370,6 +2410,15 @@ pub enum GrandpaCommitMessageOutcome {
     Queued,
 }
 
+/// See [`AllSync::inject_justification`].
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum InjectJustificationOutcome {
+    /// Justification has been silently discarded.
+    Discarded,
+    /// Justification has been queued for later verification.
+    Queued,
+}
+
 // TODO: doc
 #[derive(Debug, Clone)]
 pub struct Block<TBl> {
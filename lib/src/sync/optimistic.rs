@@ -47,6 +47,7 @@
 
 use crate::{
     chain::{blocks_tree, chain_information},
+    finality::justification,
     header,
 };
 
@@ -667,6 +668,42 @@ impl<TRq, TSrc, TBl> OptimisticSync<TRq, TSrc, TBl> {
         user_data
     }
 
+    /// Queues a justification for verification.
+    ///
+    /// Contrary to the justifications found in the blocks responses, the justification can
+    /// concern any non-finalized block of the chain, and not only the block that has just been
+    /// verified. It is verified the next time [`OptimisticSync::process_one`] is called.
+    ///
+    /// Returns `false` and discards the justification if it is known ahead of time that it
+    /// doesn't concern a non-finalized block of the chain.
+    pub fn inject_justification(
+        &mut self,
+        source_id: SourceId,
+        consensus_engine_id: [u8; 4],
+        scale_encoded_justification: Vec<u8>,
+    ) -> bool {
+        // A justification that targets an unknown block would fail to verify and reset the
+        // chain, which we prefer to avoid.
+        if consensus_engine_id == *b"FRNK" {
+            match justification::decode::decode_grandpa(
+                &scale_encoded_justification,
+                self.chain.block_number_bytes(),
+            ) {
+                Ok(decoded) if self.chain.contains_non_finalized_block(decoded.target_hash) => {}
+                _ => return false,
+            }
+        }
+
+        let mut pending = mem::replace(
+            &mut self.inner.pending_encoded_justifications,
+            Vec::new().into_iter(),
+        )
+        .collect::<Vec<_>>();
+        pending.push((consensus_engine_id, scale_encoded_justification, source_id));
+        self.inner.pending_encoded_justifications = pending.into_iter();
+        true
+    }
+
     /// Process the next block in the queue of verification.
     ///
     /// This method takes ownership of the [`OptimisticSync`]. The [`OptimisticSync`] is yielded
@@ -1087,8 +1124,6 @@ impl<TRq, TSrc, TBl> JustificationVerify<TRq, TSrc, TBl> {
             }
         };
 
-        assert!(apply.is_current_best_block()); // TODO: can legitimately fail in case of malicious node
-
         // As part of the finalization, put the justification in the chain that's
         // going to be reported to the user.
         apply
@@ -1109,10 +1144,6 @@ impl<TRq, TSrc, TBl> JustificationVerify<TRq, TSrc, TBl> {
             .rev()
             .collect();
 
-        // Since the best block is now the finalized block, reset the storage
-        // diff.
-        debug_assert!(self.chain.is_empty());
-
         self.inner.finalized_chain_information.chain_information =
             self.chain.as_chain_information().into();
 
@@ -1138,7 +1169,8 @@ pub enum JustificationVerification<TBl> {
         error: blocks_tree::JustificationVerifyError,
     },
 
-    /// Processing of the justification is over. The best block has now been finalized.
+    /// Processing of the justification is over. The block targeted by the justification and
+    /// its ancestors have now been finalized.
     ///
     /// There might be more blocks remaining. Call [`OptimisticSync::process_one`] again.
     Finalized {
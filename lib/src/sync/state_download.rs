@@ -0,0 +1,427 @@
+// Smoldot
+// Copyright (C) 2023  Pierre Krieger
+// SPDX-License-Identifier: GPL-3.0-or-later WITH Classpath-exception-2.0
+
+// This program is free software: you can redistribute it and/or modify
+// it under the terms of the GNU General Public License as published by
+// the Free Software Foundation, either version 3 of the License, or
+// (at your option) any later version.
+
+// This program is distributed in the hope that it will be useful,
+// but WITHOUT ANY WARRANTY; without even the implied warranty of
+// MERCHANTABILITY or FITNESS FOR A PARTICULAR PURPOSE.  See the
+// GNU General Public License for more details.
+
+// You should have received a copy of the GNU General Public License
+// along with this program.  If not, see <http://www.gnu.org/licenses/>.
+
+//! Downloading the entire storage of a block.
+//!
+//! Once a block has been reached through warp syncing, the storage of this block must be
+//! obtained from other nodes before the blocks that follow can be executed.
+//!
+//! The storage is downloaded through so-called *state requests*. Each response to a state
+//! request contains a Merkle proof of the storage entries that follow the start key of the
+//! request, in lexicographic order. Because responses have a size limit, multiple requests are
+//! necessary, each request starting where the previous response has ended.
+//!
+//! For the purpose of state requests, the content of each default child trie immediately follows
+//! the entry of the main trie that refers to it, in other words the entry at the key
+//! `concat(b":child_storage:default:", child_trie)`.
+//!
+//! # Usage
+//!
+//! Create a [`StateDownload`] with [`StateDownload::new`], passing the state trie root found in
+//! the header of the block whose storage to download.
+//!
+//! Use [`StateDownload::next_request`] to determine the request to send, then pass the compact
+//! Merkle proof found in the response (see the [`compact_proof`](crate::trie::compact_proof)
+//! module) to [`StateDownload::inject_response`]. The proof is verified against the state trie
+//! root, meaning that the source of the response doesn't need to be trusted. Repeat until
+//! [`StateDownload::is_finished`] returns `true`.
+//!
+//! Once finished, [`StateDownload::into_trie_nodes`] returns the list of all the trie nodes of
+//! the storage, including branch nodes and the nodes of the child tries.
+//!
+//! > **Note**: The content of the storage is kept in memory until the download is finished.
+//!
+
+use crate::trie::{self, compact_proof, proof_decode, trie_node, Nibble, TrieEntryVersion};
+
+use alloc::{
+    collections::{BTreeMap, BTreeSet},
+    vec::Vec,
+};
+
+mod tests;
+
+/// Prefix of the keys of the main trie that refer to a default child trie.
+const CHILD_TRIE_PREFIX: &[u8] = b":child_storage:default:";
+
+/// Configuration for [`StateDownload::new`].
+#[derive(Debug)]
+pub struct Config {
+    /// Hash of the block whose storage to download.
+    pub block_hash: [u8; 32],
+
+    /// State trie root hash found in the header of the block.
+    pub state_trie_root: [u8; 32],
+}
+
+/// Download of the storage of a block in progress.
+pub struct StateDownload {
+    /// See [`Config::block_hash`].
+    block_hash: [u8; 32],
+
+    /// See [`Config::state_trie_root`].
+    state_trie_root: [u8; 32],
+
+    /// Position of the download.
+    cursor: Cursor,
+
+    /// Merkle values of the roots of the child tries found so far.
+    child_trie_roots: BTreeSet<[u8; 32]>,
+
+    /// Trie nodes downloaded so far, indexed by their Merkle value.
+    trie_nodes: BTreeMap<Vec<u8>, Node>,
+
+    /// Number of storage entries that have been downloaded so far.
+    num_storage_entries: u64,
+}
+
+#[derive(Clone)]
+enum Cursor {
+    /// Downloading the content of the main trie.
+    MainTrie {
+        /// Last key that has been downloaded, or `None` if nothing has been downloaded yet.
+        last_key: Option<Vec<u8>>,
+    },
+    /// Downloading the content of a child trie.
+    ChildTrie {
+        /// Name of the child trie, without the [`CHILD_TRIE_PREFIX`].
+        child_trie: Vec<u8>,
+        /// Merkle value of the root of the child trie.
+        root: [u8; 32],
+        /// Last key within the child trie that has been downloaded, or `None` if nothing has been
+        /// downloaded yet.
+        last_key: Option<Vec<u8>>,
+    },
+    /// Download is finished.
+    Finished,
+}
+
+struct Node {
+    partial_key: Vec<Nibble>,
+    children_merkle_values: [Option<Vec<u8>>; 16],
+    storage_value: Option<(Vec<u8>, TrieEntryVersion)>,
+    storage_value_is_child_trie_root: bool,
+}
+
+impl StateDownload {
+    /// Initializes a new download.
+    pub fn new(config: Config) -> Self {
+        StateDownload {
+            block_hash: config.block_hash,
+            state_trie_root: config.state_trie_root,
+            cursor: Cursor::MainTrie { last_key: None },
+            child_trie_roots: BTreeSet::new(),
+            trie_nodes: BTreeMap::new(),
+            num_storage_entries: 0,
+        }
+    }
+
+    /// Returns the value that was passed as [`Config::block_hash`].
+    pub fn block_hash(&self) -> &[u8; 32] {
+        &self.block_hash
+    }
+
+    /// Returns the value that was passed as [`Config::state_trie_root`].
+    pub fn state_trie_root(&self) -> &[u8; 32] {
+        &self.state_trie_root
+    }
+
+    /// Returns `true` if the entire storage has been downloaded.
+    pub fn is_finished(&self) -> bool {
+        matches!(self.cursor, Cursor::Finished)
+    }
+
+    /// Returns the number of storage entries, including the ones of the child tries, that have
+    /// been downloaded so far.
+    pub fn num_storage_entries(&self) -> u64 {
+        self.num_storage_entries
+    }
+
+    /// Returns the number of trie nodes that have been downloaded so far.
+    pub fn num_trie_nodes(&self) -> usize {
+        self.trie_nodes.len()
+    }
+
+    /// Returns the state request to send next, or `None` if the download is finished.
+    ///
+    /// Sending the same request multiple times, to the same or different sources, is allowed.
+    pub fn next_request(&self) -> Option<StateRequest<'_>> {
+        match &self.cursor {
+            Cursor::MainTrie { last_key } => Some(StateRequest {
+                block_hash: &self.block_hash,
+                child_trie: None,
+                start_key: last_key.as_deref().unwrap_or(&[]),
+            }),
+            Cursor::ChildTrie {
+                child_trie,
+                last_key,
+                ..
+            } => Some(StateRequest {
+                block_hash: &self.block_hash,
+                child_trie: Some(child_trie),
+                start_key: last_key.as_deref().unwrap_or(&[]),
+            }),
+            Cursor::Finished => None,
+        }
+    }
+
+    /// Injects the compact Merkle proof found in a response to the request returned by
+    /// [`StateDownload::next_request`].
+    ///
+    /// On error, the state of the download is left unchanged and the same request should be sent
+    /// again, ideally to a different source.
+    pub fn inject_response(&mut self, proof: &[u8]) -> Result<(), Error> {
+        let regular_proof = compact_proof::decode(proof)
+            .map_err(Error::InvalidCompactProof)?
+            .regular_proof;
+        let decoded_proof = proof_decode::decode_and_verify_proof(proof_decode::Config {
+            proof: &regular_proof,
+            hash_function: trie::HashFunction::Blake2,
+        })
+        .map_err(Error::InvalidProof)?;
+
+        // Walk through the storage entries that follow the cursor for as long as the proof
+        // contains them.
+        // The cursor and the other fields are only updated once the proof has been entirely
+        // processed, so that they are left untouched in case of error.
+        let mut cursor = self.cursor.clone();
+        let mut new_child_trie_roots = Vec::new();
+        let mut num_new_entries = 0;
+        let mut made_progress = false;
+        loop {
+            let (trie_root, last_key) = match &cursor {
+                Cursor::MainTrie { last_key } => (&self.state_trie_root, last_key),
+                Cursor::ChildTrie { root, last_key, .. } => (root, last_key),
+                Cursor::Finished => break,
+            };
+
+            let key_before =
+                trie::bytes_to_nibbles(last_key.iter().flatten().copied()).collect::<Vec<_>>();
+            let next_key = match decoded_proof.next_key(
+                trie_root,
+                &key_before,
+                last_key.is_none(),
+                &[],
+                false,
+            ) {
+                Ok(k) => k,
+                Err(proof_decode::IncompleteProofError()) => break,
+            };
+
+            let Some(next_key) = next_key else {
+                // The end of the trie has been reached. The download continues with the entry of
+                // the main trie that follows the child trie, if any.
+                made_progress = true;
+                cursor = match cursor {
+                    Cursor::ChildTrie { child_trie, .. } => Cursor::MainTrie {
+                        last_key: Some(
+                            CHILD_TRIE_PREFIX
+                                .iter()
+                                .chain(&child_trie)
+                                .copied()
+                                .collect(),
+                        ),
+                    },
+                    _ => Cursor::Finished,
+                };
+                continue;
+            };
+
+            // Tries used by the runtime can't contain keys made of an uneven number of nibbles.
+            if next_key.len() % 2 != 0 {
+                return Err(Error::InvalidKey);
+            }
+            let next_key =
+                trie::nibbles_to_bytes_suffix_extend(next_key.iter().copied()).collect::<Vec<_>>();
+
+            let storage_value = match decoded_proof.storage_value(trie_root, &next_key) {
+                Ok(Some((value, _))) => value,
+                Ok(None) => unreachable!(),
+                Err(proof_decode::IncompleteProofError()) => break,
+            };
+
+            num_new_entries += 1;
+            made_progress = true;
+            cursor = match cursor {
+                Cursor::MainTrie { .. } => {
+                    if let Some(child_trie) = next_key.strip_prefix(CHILD_TRIE_PREFIX) {
+                        let root = <[u8; 32]>::try_from(storage_value)
+                            .map_err(|_| Error::InvalidChildTrieRoot)?;
+                        new_child_trie_roots.push(root);
+                        Cursor::ChildTrie {
+                            child_trie: child_trie.to_vec(),
+                            root,
+                            last_key: None,
+                        }
+                    } else {
+                        Cursor::MainTrie {
+                            last_key: Some(next_key),
+                        }
+                    }
+                }
+                Cursor::ChildTrie {
+                    child_trie, root, ..
+                } => Cursor::ChildTrie {
+                    child_trie,
+                    root,
+                    last_key: Some(next_key),
+                },
+                Cursor::Finished => unreachable!(),
+            };
+        }
+
+        if !made_progress {
+            return Err(Error::NoProgress);
+        }
+
+        self.cursor = cursor;
+        self.num_storage_entries += num_new_entries;
+        self.child_trie_roots.extend(new_child_trie_roots);
+
+        // Store all the trie nodes found in the proof that belong to the storage of the block.
+        // Nodes whose storage value is missing from the proof are skipped. They are guaranteed to
+        // be found in the response to a later request.
+        for (entry_key, entry) in decoded_proof.iter_ordered() {
+            let is_main_trie = *entry_key.trie_root_hash == self.state_trie_root;
+            if !is_main_trie && !self.child_trie_roots.contains(entry_key.trie_root_hash) {
+                continue;
+            }
+
+            let storage_value = match entry.trie_node_info.storage_value {
+                proof_decode::StorageValue::Known { value, inline } => Some((
+                    value.to_vec(),
+                    if inline {
+                        TrieEntryVersion::V0
+                    } else {
+                        TrieEntryVersion::V1
+                    },
+                )),
+                proof_decode::StorageValue::None => None,
+                proof_decode::StorageValue::HashKnownValueMissing(_) => continue,
+            };
+
+            // The Merkle value of the root node is always a hash, even if the node value is
+            // shorter than 32 bytes.
+            let node_hash = blake2_rfc::blake2b::blake2b(32, &[], entry.node_value);
+            let merkle_value = if entry.node_value.len() >= 32
+                || node_hash.as_bytes() == &entry_key.trie_root_hash[..]
+            {
+                node_hash.as_bytes().to_vec()
+            } else {
+                entry.node_value.to_vec()
+            };
+
+            if self.trie_nodes.contains_key(&merkle_value) {
+                continue;
+            }
+
+            // The node value has already been decoded and verified when decoding the proof.
+            let decoded_node_value = trie_node::decode(entry.node_value).unwrap();
+
+            let storage_value_is_child_trie_root = is_main_trie
+                && storage_value.is_some()
+                && entry_key.key.len() % 2 == 0
+                && trie::nibbles_to_bytes_suffix_extend(entry_key.key.iter().copied())
+                    .take(CHILD_TRIE_PREFIX.len())
+                    .eq(CHILD_TRIE_PREFIX.iter().copied());
+
+            self.trie_nodes.insert(
+                merkle_value,
+                Node {
+                    partial_key: decoded_node_value.partial_key.collect(),
+                    children_merkle_values: decoded_node_value
+                        .children
+                        .map(|child| child.map(|c| c.to_vec())),
+                    storage_value,
+                    storage_value_is_child_trie_root,
+                },
+            );
+        }
+
+        Ok(())
+    }
+
+    /// Returns the list of all the trie nodes of the storage of the block, in no particular
+    /// order.
+    ///
+    /// # Panic
+    ///
+    /// Panics if [`StateDownload::is_finished`] returns `false`.
+    ///
+    pub fn into_trie_nodes(self) -> impl ExactSizeIterator<Item = TrieNode> {
+        assert!(self.is_finished());
+        self.trie_nodes
+            .into_iter()
+            .map(|(merkle_value, node)| TrieNode {
+                merkle_value,
+                partial_key: node.partial_key,
+                children_merkle_values: node.children_merkle_values,
+                storage_value: node.storage_value,
+                storage_value_is_child_trie_root: node.storage_value_is_child_trie_root,
+            })
+    }
+}
+
+/// State request to send to a source. See [`StateDownload::next_request`].
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct StateRequest<'a> {
+    /// Hash of the block to make the request against.
+    pub block_hash: &'a [u8; 32],
+    /// Name of the default child trie in which to start, or `None` for the main trie.
+    pub child_trie: Option<&'a [u8]>,
+    /// Key within the trie after which the response should start. The response is allowed to
+    /// include this key as well.
+    pub start_key: &'a [u8],
+}
+
+/// Trie node of the storage of the block. See [`StateDownload::into_trie_nodes`].
+#[derive(Debug, Clone)]
+pub struct TrieNode {
+    /// Merkle value of the node.
+    pub merkle_value: Vec<u8>,
+    /// Partial key of the node.
+    pub partial_key: Vec<Nibble>,
+    /// Merkle values of the children of the node.
+    pub children_merkle_values: [Option<Vec<u8>>; 16],
+    /// Storage value of the node, if any.
+    ///
+    /// The version is [`TrieEntryVersion::V1`] if the storage value is hashed in the node value,
+    /// and [`TrieEntryVersion::V0`] otherwise. Storage values shorter than 33 bytes are never
+    /// hashed, and thus both versions are equivalent for them.
+    pub storage_value: Option<(Vec<u8>, TrieEntryVersion)>,
+    /// `true` if the node belongs to the main trie and its storage value is the Merkle value of
+    /// the root of a child trie.
+    pub storage_value_is_child_trie_root: bool,
+}
+
+/// Error potentially returned by [`StateDownload::inject_response`].
+#[derive(Debug, derive_more::Display, Clone)]
+pub enum Error {
+    /// The compact Merkle proof can't be turned into a regular proof.
+    #[display(fmt = "Invalid compact Merkle proof: {_0}")]
+    InvalidCompactProof(compact_proof::DecodeError),
+    /// The Merkle proof is invalid.
+    #[display(fmt = "Invalid Merkle proof: {_0}")]
+    InvalidProof(proof_decode::Error),
+    /// The response doesn't contain any storage entry that follows the start key of the request.
+    NoProgress,
+    /// The proof contains a storage entry whose key has an uneven number of nibbles.
+    InvalidKey,
+    /// The storage entry of the main trie that refers to a child trie doesn't contain a valid
+    /// Merkle value.
+    InvalidChildTrieRoot,
+}
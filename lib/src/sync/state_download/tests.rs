@@ -0,0 +1,432 @@
+// Smoldot
+// Copyright (C) 2023  Pierre Krieger
+// SPDX-License-Identifier: GPL-3.0-or-later WITH Classpath-exception-2.0
+
+// This program is free software: you can redistribute it and/or modify
+// it under the terms of the GNU General Public License as published by
+// the Free Software Foundation, either version 3 of the License, or
+// (at your option) any later version.
+
+// This program is distributed in the hope that it will be useful,
+// but WITHOUT ANY WARRANTY; without even the implied warranty of
+// MERCHANTABILITY or FITNESS FOR A PARTICULAR PURPOSE.  See the
+// GNU General Public License for more details.
+
+// You should have received a copy of the GNU General Public License
+// along with this program.  If not, see <http://www.gnu.org/licenses/>.
+
+#![cfg(test)]
+
+use super::{Config, Error, StateDownload, StateRequest, CHILD_TRIE_PREFIX};
+use crate::{
+    network::protocol,
+    trie::{self, trie_node, trie_structure, Nibble},
+};
+
+use alloc::collections::{BTreeMap, BTreeSet};
+use core::array;
+use rand::distributions::{Distribution as _, Uniform};
+
+/// Trie whose node values have all been calculated.
+struct TestTrie {
+    /// Node value of each node of the trie, indexed by the full key of the node. If the storage
+    /// value is hashed within the node value, also contains the unhashed storage value.
+    nodes: BTreeMap<Vec<Nibble>, (Vec<u8>, Option<Vec<u8>>)>,
+    /// Merkle value of the root node.
+    root: [u8; 32],
+    /// Full key of the root node.
+    root_key: Vec<Nibble>,
+    /// List of storage keys of the trie, ordered.
+    keys: Vec<Vec<u8>>,
+}
+
+impl TestTrie {
+    /// Builds the trie. Storage values of 33 bytes or more are hashed, as in version 1 of the
+    /// trie.
+    fn new(entries: &BTreeMap<Vec<u8>, Vec<u8>>) -> Self {
+        let mut trie = trie_structure::TrieStructure::<(Option<&[u8]>, Option<Vec<u8>>)>::new();
+        for (key, value) in entries {
+            match trie.node(trie::bytes_to_nibbles(key.iter().copied())) {
+                trie_structure::Entry::Vacant(e) => {
+                    e.insert_storage_value()
+                        .insert((Some(value), None), (None, None));
+                }
+                trie_structure::Entry::Occupied(trie_structure::NodeAccess::Branch(mut e)) => {
+                    *e.user_data() = (Some(value), None);
+                    e.insert_storage_value();
+                }
+                trie_structure::Entry::Occupied(trie_structure::NodeAccess::Storage(_)) => {
+                    unreachable!()
+                }
+            }
+        }
+
+        let mut nodes = BTreeMap::new();
+        let mut root = None;
+        let mut root_key = None;
+
+        for node_index in trie.iter_ordered().collect::<Vec<_>>().into_iter().rev() {
+            let mut node_access = trie.node_by_index(node_index).unwrap();
+
+            let children = array::from_fn::<_, 16, _>(|n| {
+                node_access
+                    .child(Nibble::try_from(u8::try_from(n).unwrap()).unwrap())
+                    .map(|mut child| child.user_data().1.as_ref().unwrap().clone())
+            });
+
+            let storage_value = node_access.user_data().0;
+            let storage_value_hash =
+                storage_value.map(|v| blake2_rfc::blake2b::blake2b(32, &[], v));
+            let (storage_value, unhashed_storage_value) = match storage_value {
+                Some(v) if v.len() >= 33 => (
+                    trie_node::StorageValue::Hashed(
+                        <&[u8; 32]>::try_from(storage_value_hash.as_ref().unwrap().as_bytes())
+                            .unwrap(),
+                    ),
+                    Some(v.to_vec()),
+                ),
+                Some(v) => (trie_node::StorageValue::Unhashed(v), None),
+                None => (trie_node::StorageValue::None, None),
+            };
+
+            let node_value = trie_node::encode_to_vec(trie_node::Decoded {
+                children,
+                partial_key: node_access.partial_key().collect::<Vec<_>>().into_iter(),
+                storage_value,
+            })
+            .unwrap();
+
+            let is_root_node = node_access.is_root_node();
+            let merkle_value = if is_root_node || node_value.len() >= 32 {
+                blake2_rfc::blake2b::blake2b(32, &[], &node_value)
+                    .as_bytes()
+                    .to_vec()
+            } else {
+                node_value.clone()
+            };
+            if is_root_node {
+                root = Some(<[u8; 32]>::try_from(&merkle_value[..]).unwrap());
+            }
+
+            node_access.into_user_data().1 = Some(merkle_value);
+            let full_key = trie
+                .node_full_key_by_index(node_index)
+                .unwrap()
+                .collect::<Vec<_>>();
+            if is_root_node {
+                root_key = Some(full_key.clone());
+            }
+            nodes.insert(full_key, (node_value, unhashed_storage_value));
+        }
+
+        TestTrie {
+            nodes,
+            root: root.unwrap(),
+            root_key: root_key.unwrap(),
+            keys: entries.keys().cloned().collect(),
+        }
+    }
+
+    /// Adds to `proof_entries` the nodes traversed when looking up the given key. The unhashed
+    /// storage value is only included for the node at the key itself.
+    fn add_lookup(&self, key: &[u8], proof_entries: &mut BTreeSet<Vec<u8>>) {
+        let key = trie::bytes_to_nibbles(key.iter().copied()).collect::<Vec<_>>();
+        for (node_key, (node_value, unhashed_storage_value)) in &self.nodes {
+            if !key.starts_with(node_key) {
+                continue;
+            }
+            if *node_key == self.root_key || node_value.len() >= 32 {
+                proof_entries.insert(node_value.clone());
+            }
+            if let (true, Some(value)) = (*node_key == key, unhashed_storage_value) {
+                proof_entries.insert(value.clone());
+            }
+        }
+    }
+}
+
+/// Storage of a block made of a main trie and default child tries.
+struct TestStorage {
+    main_trie: TestTrie,
+    child_tries: BTreeMap<Vec<u8>, TestTrie>,
+    /// Number of storage entries, including the ones of the child tries.
+    num_entries: u64,
+}
+
+impl TestStorage {
+    fn new(
+        mut main_trie_entries: BTreeMap<Vec<u8>, Vec<u8>>,
+        child_tries_entries: BTreeMap<Vec<u8>, BTreeMap<Vec<u8>, Vec<u8>>>,
+    ) -> Self {
+        let mut child_tries = BTreeMap::new();
+        let mut num_entries = 0;
+        for (child_trie, entries) in child_tries_entries {
+            let child_trie_trie = TestTrie::new(&entries);
+            num_entries += u64::try_from(entries.len()).unwrap();
+            main_trie_entries.insert(
+                CHILD_TRIE_PREFIX
+                    .iter()
+                    .chain(&child_trie)
+                    .copied()
+                    .collect(),
+                child_trie_trie.root.to_vec(),
+            );
+            child_tries.insert(child_trie, child_trie_trie);
+        }
+        num_entries += u64::try_from(main_trie_entries.len()).unwrap();
+
+        TestStorage {
+            main_trie: TestTrie::new(&main_trie_entries),
+            child_tries,
+            num_entries,
+        }
+    }
+
+    /// Builds the response to the given request, containing at most `max_entries` entries
+    /// following the start key.
+    fn answer(&self, request: &StateRequest, max_entries: usize) -> Vec<u8> {
+        // All the storage entries, in the order in which they are sent.
+        let all_entries = self.main_trie.keys.iter().flat_map(|main_key| {
+            let child_trie = main_key
+                .strip_prefix(CHILD_TRIE_PREFIX)
+                .map(|c| (c, &self.child_tries[c]));
+            let child_trie_entries = child_trie
+                .into_iter()
+                .flat_map(|(c, trie)| trie.keys.iter().map(move |k| (Some(c), &k[..])));
+            core::iter::once((None, &main_key[..])).chain(child_trie_entries)
+        });
+
+        let start_main_key = match request.child_trie {
+            Some(child_trie) => CHILD_TRIE_PREFIX
+                .iter()
+                .chain(child_trie)
+                .copied()
+                .collect(),
+            None => request.start_key.to_vec(),
+        };
+        let entries_after_start =
+            all_entries.skip_while(|(child_trie, key)| match (child_trie, request.child_trie) {
+                (None, _) => **key <= *start_main_key,
+                (Some(c), None) => {
+                    CHILD_TRIE_PREFIX
+                        .iter()
+                        .chain(*c)
+                        .copied()
+                        .collect::<Vec<_>>()
+                        <= start_main_key
+                }
+                (Some(c), Some(rq_c)) => {
+                    let child_key = CHILD_TRIE_PREFIX
+                        .iter()
+                        .chain(*c)
+                        .copied()
+                        .collect::<Vec<_>>();
+                    child_key < start_main_key
+                        || (*c == rq_c
+                            && !request.start_key.is_empty()
+                            && *key <= request.start_key)
+                }
+            });
+
+        let mut proof_entries = BTreeSet::new();
+        self.main_trie
+            .add_lookup(&start_main_key, &mut proof_entries);
+        if let Some(child_trie) = request.child_trie {
+            self.child_tries[child_trie].add_lookup(request.start_key, &mut proof_entries);
+        }
+        for (child_trie, key) in entries_after_start.take(max_entries) {
+            match child_trie {
+                None => self.main_trie.add_lookup(key, &mut proof_entries),
+                Some(child_trie) => {
+                    let child_trie_key = CHILD_TRIE_PREFIX
+                        .iter()
+                        .chain(child_trie)
+                        .copied()
+                        .collect::<Vec<_>>();
+                    self.main_trie
+                        .add_lookup(&child_trie_key, &mut proof_entries);
+                    self.child_tries[child_trie].add_lookup(key, &mut proof_entries);
+                }
+            }
+        }
+
+        let mut proof = crate::util::encode_scale_compact_usize(proof_entries.len())
+            .as_ref()
+            .to_vec();
+        for entry in proof_entries {
+            proof.extend_from_slice(crate::util::encode_scale_compact_usize(entry.len()).as_ref());
+            proof.extend_from_slice(&entry);
+        }
+
+        // State responses contain compact proofs.
+        trie::compact_proof::encode(&proof, &self.main_trie.root).unwrap()
+    }
+
+    /// Returns the Merkle values of all the nodes of all the tries.
+    fn merkle_values(&self) -> BTreeSet<Vec<u8>> {
+        let mut out = BTreeSet::new();
+        for trie in core::iter::once(&self.main_trie).chain(self.child_tries.values()) {
+            for (key, (node_value, _)) in &trie.nodes {
+                if *key == trie.root_key || node_value.len() >= 32 {
+                    out.insert(
+                        blake2_rfc::blake2b::blake2b(32, &[], node_value)
+                            .as_bytes()
+                            .to_vec(),
+                    );
+                } else {
+                    out.insert(node_value.clone());
+                }
+            }
+        }
+        out
+    }
+}
+
+fn random_entries(max_entries: u32) -> BTreeMap<Vec<u8>, Vec<u8>> {
+    fn uniform_sample(min: u32, max: u32) -> u32 {
+        Uniform::new_inclusive(min, max).sample(&mut rand::thread_rng())
+    }
+
+    let mut entries = BTreeMap::new();
+    for _ in 0..uniform_sample(1, max_entries) {
+        let key = (0..uniform_sample(1, 4))
+            .map(|_| u8::try_from(uniform_sample(0, 3)).unwrap())
+            .collect::<Vec<_>>();
+        let value = (0..uniform_sample(0, 48))
+            .map(|_| u8::try_from(uniform_sample(0, 255)).unwrap())
+            .collect::<Vec<_>>();
+        entries.insert(key, value);
+    }
+    entries
+}
+
+fn download(storage: &TestStorage, max_entries_per_response: usize) -> StateDownload {
+    let mut download = StateDownload::new(Config {
+        block_hash: [0; 32],
+        state_trie_root: storage.main_trie.root,
+    });
+
+    while let Some(request) = download.next_request() {
+        let response = storage.answer(&request, max_entries_per_response);
+        download.inject_response(&response).unwrap();
+    }
+
+    download
+}
+
+#[test]
+fn single_response() {
+    let storage = TestStorage::new(random_entries(32), BTreeMap::new());
+    let download = download(&storage, usize::max_value());
+    assert_eq!(download.num_storage_entries(), storage.num_entries);
+    assert_eq!(
+        download
+            .into_trie_nodes()
+            .map(|n| n.merkle_value)
+            .collect::<BTreeSet<_>>(),
+        storage.merkle_values()
+    );
+}
+
+#[test]
+fn random_storage_with_child_tries() {
+    // Repeat the test many times due to randomness.
+    for _ in 0..256 {
+        let child_tries = (0..Uniform::new_inclusive(0, 3).sample(&mut rand::thread_rng()))
+            .map(|n| (vec![b'a' + n], random_entries(16)))
+            .collect::<BTreeMap<_, _>>();
+        let storage = TestStorage::new(random_entries(64), child_tries);
+
+        let max_entries_per_response = Uniform::new_inclusive(1, 8).sample(&mut rand::thread_rng());
+        let download = download(&storage, max_entries_per_response);
+        assert!(download.is_finished());
+        assert_eq!(download.num_storage_entries(), storage.num_entries);
+
+        let trie_nodes = download.into_trie_nodes().collect::<Vec<_>>();
+        assert_eq!(
+            trie_nodes
+                .iter()
+                .map(|n| n.merkle_value.clone())
+                .collect::<BTreeSet<_>>(),
+            storage.merkle_values()
+        );
+        assert_eq!(
+            trie_nodes
+                .iter()
+                .filter(|n| n.storage_value_is_child_trie_root)
+                .count(),
+            storage.child_tries.len()
+        );
+    }
+}
+
+#[test]
+fn empty_response_is_error() {
+    let storage = TestStorage::new(random_entries(32), BTreeMap::new());
+    let mut download = StateDownload::new(Config {
+        block_hash: [0; 32],
+        state_trie_root: storage.main_trie.root,
+    });
+
+    assert!(matches!(
+        download.inject_response(&[0]),
+        Err(Error::InvalidCompactProof(_))
+    ));
+    assert_eq!(
+        download.next_request().unwrap(),
+        StateRequest {
+            block_hash: &[0; 32],
+            child_trie: None,
+            start_key: &[],
+        }
+    );
+}
+
+#[test]
+fn response_of_other_trie_ignored() {
+    let storage = TestStorage::new(random_entries(32), BTreeMap::new());
+    let other_storage = TestStorage::new(random_entries(32), BTreeMap::new());
+    let mut download = StateDownload::new(Config {
+        block_hash: [0; 32],
+        state_trie_root: storage.main_trie.root,
+    });
+
+    let response = other_storage.answer(&download.next_request().unwrap(), usize::max_value());
+    if storage.main_trie.root != other_storage.main_trie.root {
+        assert!(matches!(
+            download.inject_response(&response),
+            Err(Error::NoProgress)
+        ));
+        assert_eq!(download.num_trie_nodes(), 0);
+    }
+}
+
+#[test]
+fn state_protocol_response() {
+    // Response to a `/state/2` request, as sent on the wire, for a storage made of a single entry
+    // `a => hello`. The Protobuf message contains a compact proof made of the root node only.
+    let response = hex::decode("120a042042611468656c6c6f").unwrap();
+    let compact_proof = protocol::decode_state_response(&response).unwrap();
+
+    let state_trie_root = trie::trie_root(
+        trie::TrieEntryVersion::V1,
+        trie::HashFunction::Blake2,
+        &[(b"a", b"hello")],
+    );
+    let mut download = StateDownload::new(Config {
+        block_hash: [0; 32],
+        state_trie_root,
+    });
+
+    download.inject_response(compact_proof).unwrap();
+    assert!(download.is_finished());
+    assert_eq!(download.num_storage_entries(), 1);
+
+    let trie_nodes = download.into_trie_nodes().collect::<Vec<_>>();
+    assert_eq!(trie_nodes.len(), 1);
+    assert_eq!(trie_nodes[0].merkle_value, state_trie_root);
+    assert_eq!(
+        trie_nodes[0].storage_value.as_ref().unwrap().0,
+        b"hello".to_vec()
+    );
+}
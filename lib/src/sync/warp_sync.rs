@@ -1262,6 +1262,7 @@ impl<TSrc, TRq> BuildRuntime<TSrc, TRq> {
             let decoded_downloaded_runtime =
                 match proof_decode::decode_and_verify_proof(proof_decode::Config {
                     proof: &downloaded_runtime[..],
+                    hash_function: trie::HashFunction::Blake2,
                 }) {
                     Ok(p) => p,
                     Err(err) => {
@@ -1411,6 +1412,9 @@ impl<TSrc, TRq> BuildRuntime<TSrc, TRq> {
                 module: &finalized_storage_code,
                 heap_pages: decoded_heap_pages,
                 exec_hint,
+                compiled_module_cache: None,
+                resource_limits: Default::default(),
+                allocation_strategy: Default::default(),
                 allow_unresolved_imports,
             }) {
                 Ok(runtime) => runtime,
@@ -1559,6 +1563,7 @@ impl<TSrc, TRq> BuildChainInformation<TSrc, TRq> {
                     let decoded_proof =
                         match proof_decode::decode_and_verify_proof(proof_decode::Config {
                             proof: proof.into_iter(),
+                            hash_function: trie::HashFunction::Blake2,
                         }) {
                             Ok(d) => d,
                             Err(err) => {
@@ -755,6 +755,9 @@ impl<TTx> Pool<TTx> {
             self.includable.insert((result.priority, id));
         }
 
+        let _was_in = self.not_validated.remove(&id);
+        debug_assert!(_was_in);
+
         self.transactions[id.0].validation = Some((block_number_validated_against, result));
     }
 
@@ -178,6 +178,8 @@ pub enum InvalidTransaction {
     /// A transaction with a mandatory dispatch. This is invalid; only inherent extrinsics are
     /// allowed to have mandatory dispatches.
     MandatoryDispatch,
+    /// The sending address is disabled or known to be invalid.
+    BadSigner,
 }
 
 /// An unknown transaction validity.
@@ -335,6 +337,7 @@ pub fn validate_transaction(
                 storage_main_trie_changes: storage_diff::TrieDiff::empty(),
                 max_log_level: config.max_log_level,
                 calculate_trie_changes: false,
+                execution_tracing: None,
             });
 
             // Information used later, after `Core_initialize_block` is done.
@@ -372,6 +375,7 @@ pub fn validate_transaction(
                 storage_main_trie_changes: storage_diff::TrieDiff::empty(),
                 max_log_level: config.max_log_level,
                 calculate_trie_changes: false,
+                execution_tracing: None,
             });
 
             match vm {
@@ -461,6 +465,7 @@ impl Query {
                         storage_main_trie_changes: success.storage_changes.into_main_trie_diff(),
                         max_log_level: info.max_log_level,
                         calculate_trie_changes: false,
+                        execution_tracing: None,
                     });
 
                     match vm {
@@ -499,6 +504,12 @@ impl Query {
                     result: Err(Error::ForbiddenHostCall),
                     virtual_machine: ctx.into_prototype(),
                 },
+                req @ (runtime_host::RuntimeHostVm::KeystorePublicKeys(_)
+                | runtime_host::RuntimeHostVm::KeystoreGenerate(_)
+                | runtime_host::RuntimeHostVm::KeystoreSign(_)) => Query::Finished {
+                    result: Err(Error::ForbiddenHostCall),
+                    virtual_machine: req.into_prototype(),
+                },
             };
         }
     }
@@ -568,6 +579,12 @@ impl Query {
                     result: Err(Error::ForbiddenHostCall),
                     virtual_machine: ctx.into_prototype(),
                 },
+                req @ (runtime_host::RuntimeHostVm::KeystorePublicKeys(_)
+                | runtime_host::RuntimeHostVm::KeystoreGenerate(_)
+                | runtime_host::RuntimeHostVm::KeystoreSign(_)) => Query::Finished {
+                    result: Err(Error::ForbiddenHostCall),
+                    virtual_machine: req.into_prototype(),
+                },
             };
         }
     }
@@ -797,7 +814,9 @@ fn valid_transaction(bytes: &[u8]) -> nom::IResult<&[u8], ValidTransaction> {
     )(bytes)
 }
 
-fn transaction_validity_error(bytes: &[u8]) -> nom::IResult<&[u8], TransactionValidityError> {
+pub(crate) fn transaction_validity_error(
+    bytes: &[u8],
+) -> nom::IResult<&[u8], TransactionValidityError> {
     nom::error::context(
         "transaction validity error",
         nom::branch::alt((
@@ -851,6 +870,9 @@ fn invalid_transaction(bytes: &[u8]) -> nom::IResult<&[u8], InvalidTransaction>
             nom::combinator::map(nom::bytes::streaming::tag(&[9]), |_| {
                 InvalidTransaction::MandatoryDispatch
             }),
+            nom::combinator::map(nom::bytes::streaming::tag(&[10]), |_| {
+                InvalidTransaction::BadSigner
+            }),
         )),
     )(bytes)
 }
@@ -17,7 +17,10 @@
 
 #![cfg(test)]
 
-use crate::{executor, header, trie::proof_decode};
+use crate::{
+    executor, header,
+    trie::{self, proof_decode},
+};
 use core::iter;
 
 #[test]
@@ -30,12 +33,16 @@ fn validate_from_proof() {
         module: hex::decode(&test.runtime_code).unwrap(),
         heap_pages: executor::DEFAULT_HEAP_PAGES,
         allow_unresolved_imports: true,
+        compiled_module_cache: None,
+        resource_limits: Default::default(),
+        allocation_strategy: Default::default(),
         exec_hint: executor::vm::ExecHint::Oneshot,
     })
     .unwrap();
 
     let call_proof = proof_decode::decode_and_verify_proof(proof_decode::Config {
         proof: hex::decode(&test.call_proof).unwrap(),
+        hash_function: trie::HashFunction::Blake2,
     })
     .unwrap();
 
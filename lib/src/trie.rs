@@ -109,6 +109,8 @@ mod nibble;
 
 pub mod branch_search;
 pub mod calculate_root;
+pub mod compact_proof;
+pub mod ordered_proof;
 pub mod prefix_proof;
 pub mod proof_decode;
 pub mod proof_encode;
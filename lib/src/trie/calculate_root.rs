@@ -312,7 +312,15 @@ impl StorageValue {
         let storage_value_hash = if let Some((value, TrieEntryVersion::V1)) = storage_value.as_ref()
         {
             if value.as_ref().len() >= 33 {
-                Some(blake2_rfc::blake2b::blake2b(32, &[], value.as_ref()))
+                Some(match self.calculation.hash_function {
+                    HashFunction::Blake2 => *<&[u8; 32]>::try_from(
+                        blake2_rfc::blake2b::blake2b(32, &[], value.as_ref()).as_bytes(),
+                    )
+                    .unwrap_or_else(|_| unreachable!()),
+                    HashFunction::Keccak256 => {
+                        <sha3::Keccak256 as sha3::Digest>::digest(value.as_ref()).into()
+                    }
+                })
             } else {
                 None
             }
@@ -326,10 +334,9 @@ impl StorageValue {
                 children: array::from_fn(|n| calculated_elem.children[n].as_ref()),
                 partial_key: calculated_elem.partial_key.iter().copied(),
                 storage_value: match (storage_value.as_ref(), storage_value_hash.as_ref()) {
-                    (_, Some(storage_value_hash)) => trie_node::StorageValue::Hashed(
-                        <&[u8; 32]>::try_from(storage_value_hash.as_bytes())
-                            .unwrap_or_else(|_| unreachable!()),
-                    ),
+                    (_, Some(storage_value_hash)) => {
+                        trie_node::StorageValue::Hashed(storage_value_hash)
+                    }
                     (Some((value, _)), _) => trie_node::StorageValue::Unhashed(value.as_ref()),
                     (None, _) => trie_node::StorageValue::None,
                 },
@@ -0,0 +1,680 @@
+// Smoldot
+// Copyright (C) 2023  Pierre Krieger
+// SPDX-License-Identifier: GPL-3.0-or-later WITH Classpath-exception-2.0
+
+// This program is free software: you can redistribute it and/or modify
+// it under the terms of the GNU General Public License as published by
+// the Free Software Foundation, either version 3 of the License, or
+// (at your option) any later version.
+
+// This program is distributed in the hope that it will be useful,
+// but WITHOUT ANY WARRANTY; without even the implied warranty of
+// MERCHANTABILITY or FITNESS FOR A PARTICULAR PURPOSE.  See the
+// GNU General Public License for more details.
+
+// You should have received a copy of the GNU General Public License
+// along with this program.  If not, see <http://www.gnu.org/licenses/>.
+
+//! Compact trie proofs.
+//!
+//! A compact proof is an alternative encoding of a trie proof where the information that the
+//! verifier can recalculate is omitted. This is the format of the proofs found in the responses
+//! to state requests.
+//!
+//! Like a regular proof (see the [`proof_decode`](super::proof_decode) module), a compact proof
+//! is a SCALE-encoded `Vec<Vec<u8>>`. Its differences with regular proofs are:
+//!
+//! - The node values are ordered. The nodes of each trie are ordered depth-first, in other words
+//!   each node is followed with the nodes of its children in the order of their nibble.
+//! - When the child of a node is included in the proof, its Merkle value is replaced in the node
+//!   value of the parent with an empty Merkle value. Children whose node value is inlined within
+//!   their parent are left untouched.
+//! - When the storage value of a node is hashed and this storage value is included in the proof,
+//!   the node value is prefixed with a `0x01` byte, its storage value is replaced with an empty
+//!   unhashed storage value, and the actual storage value immediately follows the node value.
+//! - The nodes of the main trie come first. They are followed with the nodes of each default
+//!   child trie whose root node is part of the proof, in the order of the keys in the main trie
+//!   that refer to these child tries.
+//!
+//! This module provides [`encode`], which turns a regular proof into a compact proof, and
+//! [`decode`], which turns a compact proof back into a regular proof that can then be passed to
+//! [`proof_decode::decode_and_verify_proof`](super::proof_decode::decode_and_verify_proof).
+
+use super::{nibble, trie_node};
+
+use alloc::vec::Vec;
+use core::iter;
+use hashbrown::HashMap;
+
+/// Prefix of all the keys in the main trie that refer to a default child trie.
+const DEFAULT_CHILD_STORAGE_KEY_PREFIX: &[u8] = b":child_storage:default:";
+
+/// First byte of the node values whose storage value follows them.
+const ESCAPE_HEADER: u8 = 0x01;
+
+/// Turns a regular proof into a compact proof.
+///
+/// `trie_root_hash` must be the hash of the root node of the main trie. The default child tries
+/// whose root node is found in the proof are included in the compact proof. All the other
+/// entries of the regular proof that can't be reached from `trie_root_hash` are ignored.
+pub fn encode(regular_proof: &[u8], trie_root_hash: &[u8; 32]) -> Result<Vec<u8>, EncodeError> {
+    let entries = decode_entries(regular_proof).map_err(|()| EncodeError::InvalidFormat)?;
+    let entries = entries
+        .into_iter()
+        .map(|entry| (blake2_hash(entry), entry))
+        .collect::<HashMap<_, _, fnv::FnvBuildHasher>>();
+
+    let Some(root_node_value) = entries.get(trie_root_hash) else {
+        return Err(EncodeError::MissingTrieRoot);
+    };
+
+    let mut compact_entries = Vec::with_capacity(entries.len());
+    encode_trie(root_node_value, &entries, &mut compact_entries)?;
+
+    let child_trie_roots =
+        child_trie_roots(root_node_value, &entries).map_err(|err| match err {
+            ChildTrieRootsError::InvalidNode(err) => EncodeError::InvalidTrieNode(err),
+            ChildTrieRootsError::InvalidChildTrieRoot => EncodeError::InvalidChildTrieRoot,
+        })?;
+    for child_trie_root in child_trie_roots {
+        // Child tries whose root node isn't in the proof are allowed to be missing.
+        if let Some(node_value) = entries.get(&child_trie_root) {
+            encode_trie(node_value, &entries, &mut compact_entries)?;
+        }
+    }
+
+    Ok(encode_entries(compact_entries.iter()))
+}
+
+/// Error potentially returned by [`encode`].
+#[derive(Debug, derive_more::Display, Clone)]
+pub enum EncodeError {
+    /// Failed to decode the regular proof.
+    InvalidFormat,
+    /// The node value of the root node of the main trie isn't in the proof.
+    MissingTrieRoot,
+    /// One of the node values of the proof is invalid.
+    #[display(fmt = "Invalid trie node: {_0}")]
+    InvalidTrieNode(trie_node::Error),
+    /// One of the node values of the proof contains a partial key but no children and no
+    /// storage value.
+    NodeWithoutChildrenNorStorageValue,
+    /// A key of the main trie that refers to a default child trie has a storage value whose
+    /// length isn't 32 bytes.
+    InvalidChildTrieRoot,
+}
+
+/// Outcome of [`decode`].
+#[derive(Debug, Clone)]
+pub struct Decoded {
+    /// Hash of the root node of the main trie. The API user is expected to compare it with the
+    /// state root of the block the proof is supposed to concern.
+    pub trie_root_hash: [u8; 32],
+
+    /// SCALE-encoded regular proof containing the same trie nodes as the compact proof. Can be
+    /// passed to [`proof_decode::decode_and_verify_proof`](super::proof_decode::decode_and_verify_proof).
+    pub regular_proof: Vec<u8>,
+}
+
+/// Turns a compact proof into a regular proof.
+///
+/// The structure of the compact proof is verified. In particular, every default child trie
+/// found in the compact proof must be referred to by the main trie, in the right order.
+pub fn decode(compact_proof: &[u8]) -> Result<Decoded, DecodeError> {
+    let compact_entries = decode_entries(compact_proof).map_err(|()| DecodeError::InvalidFormat)?;
+    let mut compact_entries = compact_entries.into_iter().peekable();
+
+    // All the node values and storage values that have been recalculated. The keys are the
+    // hashes of the values.
+    let mut entries = HashMap::<[u8; 32], Vec<u8>, fnv::FnvBuildHasher>::with_capacity_and_hasher(
+        compact_entries.len(),
+        Default::default(),
+    );
+
+    let trie_root_hash = decode_trie(&mut compact_entries, &mut entries)?;
+
+    // Each child trie found in the proof must match one of the child tries referred to by the
+    // main trie. Child tries referred to by the main trie are however allowed to be missing.
+    let child_trie_roots =
+        child_trie_roots(&entries[&trie_root_hash], &entries).map_err(|err| match err {
+            ChildTrieRootsError::InvalidNode(err) => DecodeError::InvalidTrieNode(err),
+            ChildTrieRootsError::InvalidChildTrieRoot => DecodeError::InvalidChildTrieRoot,
+        })?;
+    let mut decoded_child_trie = None;
+    for child_trie_root in child_trie_roots {
+        if decoded_child_trie.is_none() && compact_entries.peek().is_some() {
+            decoded_child_trie = Some(decode_trie(&mut compact_entries, &mut entries)?);
+        }
+
+        if decoded_child_trie == Some(child_trie_root) {
+            decoded_child_trie = None;
+        }
+    }
+
+    if decoded_child_trie.is_some() {
+        return Err(DecodeError::ExtraneousChildTrie);
+    }
+    if compact_entries.next().is_some() {
+        return Err(DecodeError::ExtraneousEntry);
+    }
+
+    Ok(Decoded {
+        trie_root_hash,
+        regular_proof: encode_entries(entries.values()),
+    })
+}
+
+/// Error potentially returned by [`decode`].
+#[derive(Debug, derive_more::Display, Clone)]
+pub enum DecodeError {
+    /// Failed to decode the compact proof.
+    InvalidFormat,
+    /// One of the node values of the proof is invalid.
+    #[display(fmt = "Invalid trie node: {_0}")]
+    InvalidTrieNode(trie_node::Error),
+    /// A node value prefixed with the escape byte doesn't have an empty storage value.
+    InvalidEscapedNode,
+    /// The proof ends in the middle of a trie.
+    IncompleteTrie,
+    /// The proof contains a child trie that isn't referred to by the main trie, or in the wrong
+    /// order.
+    ExtraneousChildTrie,
+    /// The proof contains entries after the last trie.
+    ExtraneousEntry,
+    /// One of the node values of the proof contains a partial key but no children and no
+    /// storage value.
+    NodeWithoutChildrenNorStorageValue,
+    /// A key of the main trie that refers to a default child trie has a storage value whose
+    /// length isn't 32 bytes.
+    InvalidChildTrieRoot,
+}
+
+/// Pushes to `out` the compact entries of the trie whose root node is `root_node_value`.
+fn encode_trie(
+    root_node_value: &[u8],
+    entries: &HashMap<[u8; 32], &[u8], fnv::FnvBuildHasher>,
+    out: &mut Vec<Vec<u8>>,
+) -> Result<(), EncodeError> {
+    // Stack of node values remaining to encode. Nodes are processed depth-first, and children
+    // are thus pushed in reverse order.
+    let mut stack = Vec::with_capacity(32);
+    stack.push(root_node_value);
+
+    while let Some(node_value) = stack.pop() {
+        let decoded = trie_node::decode(node_value).map_err(EncodeError::InvalidTrieNode)?;
+
+        // Children that are part of the proof are replaced with an empty Merkle value.
+        let mut children_in_proof = Vec::with_capacity(16);
+        let children = decoded.children.map(|child| {
+            let child = child?;
+            match <&[u8; 32]>::try_from(child)
+                .ok()
+                .and_then(|hash| entries.get(hash))
+            {
+                Some(child_node_value) => {
+                    children_in_proof.push(*child_node_value);
+                    Some(&[][..])
+                }
+                None => Some(child),
+            }
+        });
+        stack.extend(children_in_proof.into_iter().rev());
+
+        // Storage values that are part of the proof are moved out of the node value.
+        let (storage_value, escaped_storage_value) = match decoded.storage_value {
+            trie_node::StorageValue::Hashed(hash) => match entries.get(hash) {
+                Some(value) => (trie_node::StorageValue::Unhashed(&[]), Some(*value)),
+                None => (decoded.storage_value, None),
+            },
+            _ => (decoded.storage_value, None),
+        };
+
+        let encoded = trie_node::encode_to_vec(trie_node::Decoded {
+            partial_key: decoded.partial_key,
+            children,
+            storage_value,
+        })
+        .map_err(|_| EncodeError::NodeWithoutChildrenNorStorageValue)?;
+
+        match escaped_storage_value {
+            Some(value) => {
+                out.push(iter::once(ESCAPE_HEADER).chain(encoded).collect());
+                out.push(value.to_vec());
+            }
+            None => out.push(encoded),
+        }
+    }
+
+    Ok(())
+}
+
+/// Decodes the compact entries of one trie and inserts the recalculated node values and storage
+/// values in `entries`. Returns the hash of the root node of the trie.
+fn decode_trie<'a>(
+    compact_entries: &mut impl Iterator<Item = &'a [u8]>,
+    entries: &mut HashMap<[u8; 32], Vec<u8>, fnv::FnvBuildHasher>,
+) -> Result<[u8; 32], DecodeError> {
+    struct StackEntry<'a> {
+        partial_key: trie_node::DecodedPartialKey<'a>,
+        children: [Option<Vec<u8>>; 16],
+        storage_value: StorageValue<'a>,
+        /// Index of the next child to examine.
+        next_child: usize,
+    }
+
+    enum StorageValue<'a> {
+        None,
+        Unhashed(&'a [u8]),
+        Hashed([u8; 32]),
+    }
+
+    let mut stack = Vec::<StackEntry>::with_capacity(32);
+
+    loop {
+        // Decode the next node value of the proof.
+        let compact_entry = compact_entries.next().ok_or(DecodeError::IncompleteTrie)?;
+        let (node_value, is_escaped) = match compact_entry.split_first() {
+            Some((&ESCAPE_HEADER, rest)) => (rest, true),
+            _ => (compact_entry, false),
+        };
+        let decoded = trie_node::decode(node_value).map_err(DecodeError::InvalidTrieNode)?;
+
+        let storage_value = match (decoded.storage_value, is_escaped) {
+            (trie_node::StorageValue::Unhashed(&[]), true) => {
+                let value = compact_entries.next().ok_or(DecodeError::IncompleteTrie)?;
+                let hash = blake2_hash(value);
+                entries.insert(hash, value.to_vec());
+                StorageValue::Hashed(hash)
+            }
+            (_, true) => return Err(DecodeError::InvalidEscapedNode),
+            (trie_node::StorageValue::None, false) => StorageValue::None,
+            (trie_node::StorageValue::Unhashed(value), false) => StorageValue::Unhashed(value),
+            (trie_node::StorageValue::Hashed(hash), false) => StorageValue::Hashed(*hash),
+        };
+
+        stack.push(StackEntry {
+            partial_key: decoded.partial_key,
+            children: decoded.children.map(|child| child.map(|c| c.to_vec())),
+            storage_value,
+            next_child: 0,
+        });
+
+        // Find the next child whose node value is the next entry of the proof. If the node on
+        // top of the stack has no such child, it is complete and can be popped.
+        loop {
+            let top = stack.last_mut().unwrap();
+            if let Some(child_index) = (top.next_child..16)
+                .find(|n| top.children[*n].as_ref().is_some_and(|c| c.is_empty()))
+            {
+                top.next_child = child_index;
+                break;
+            }
+
+            let complete = stack.pop().unwrap();
+            let node_value = trie_node::encode_to_vec(trie_node::Decoded {
+                partial_key: complete.partial_key,
+                children: complete.children,
+                storage_value: match &complete.storage_value {
+                    StorageValue::None => trie_node::StorageValue::None,
+                    StorageValue::Unhashed(value) => trie_node::StorageValue::Unhashed(value),
+                    StorageValue::Hashed(hash) => trie_node::StorageValue::Hashed(hash),
+                },
+            })
+            .map_err(|_| DecodeError::NodeWithoutChildrenNorStorageValue)?;
+
+            // Note that the node is always referred to by its hash, even if its node value is
+            // shorter than 32 bytes, as only nodes referred to by their hash are omitted.
+            let hash = blake2_hash(&node_value);
+            entries.insert(hash, node_value);
+
+            match stack.last_mut() {
+                Some(parent) => {
+                    parent.children[parent.next_child] = Some(hash.to_vec());
+                    parent.next_child += 1;
+                }
+                None => return Ok(hash),
+            }
+        }
+    }
+}
+
+/// Error potentially returned by [`child_trie_roots`].
+enum ChildTrieRootsError {
+    InvalidNode(trie_node::Error),
+    InvalidChildTrieRoot,
+}
+
+/// Returns the list of the roots of the default child tries referred to by the main trie, in
+/// the order of their key in the main trie.
+///
+/// Nodes and storage values that are missing from `entries` are silently skipped.
+fn child_trie_roots(
+    root_node_value: &[u8],
+    entries: &HashMap<[u8; 32], impl AsRef<[u8]>, fnv::FnvBuildHasher>,
+) -> Result<Vec<[u8; 32]>, ChildTrieRootsError> {
+    let prefix = nibble::bytes_to_nibbles(DEFAULT_CHILD_STORAGE_KEY_PREFIX.iter().copied())
+        .collect::<Vec<_>>();
+
+    let mut out = Vec::new();
+
+    // Stack of node values remaining to visit, and the key of their parent plus their nibble
+    // within the parent. Children are pushed in reverse order so that nodes are visited in the
+    // order of their keys.
+    let mut stack = Vec::<(&[u8], Vec<nibble::Nibble>)>::with_capacity(32);
+    stack.push((root_node_value, Vec::new()));
+
+    while let Some((node_value, mut key)) = stack.pop() {
+        let decoded = trie_node::decode(node_value).map_err(ChildTrieRootsError::InvalidNode)?;
+        key.extend(decoded.partial_key);
+
+        // Skip nodes whose descendants can't be child trie keys.
+        if !(prefix.starts_with(&key) || key.starts_with(&prefix)) {
+            continue;
+        }
+
+        if key.starts_with(&prefix) && key.len() % 2 == 0 {
+            let value = match decoded.storage_value {
+                trie_node::StorageValue::None => None,
+                trie_node::StorageValue::Unhashed(value) => Some(value),
+                trie_node::StorageValue::Hashed(hash) => entries.get(hash).map(|v| v.as_ref()),
+            };
+
+            if let Some(value) = value {
+                out.push(
+                    <[u8; 32]>::try_from(value)
+                        .map_err(|_| ChildTrieRootsError::InvalidChildTrieRoot)?,
+                );
+            }
+        }
+
+        for (child_index, child) in decoded.children.iter().enumerate().rev() {
+            let Some(child) = child else { continue };
+            let child_node_value = if child.len() == 32 {
+                match entries.get(<&[u8; 32]>::try_from(*child).unwrap()) {
+                    Some(node_value) => node_value.as_ref(),
+                    None => continue,
+                }
+            } else {
+                child
+            };
+
+            let mut child_key = key.clone();
+            child_key.push(nibble::Nibble::try_from(u8::try_from(child_index).unwrap()).unwrap());
+            stack.push((child_node_value, child_key));
+        }
+    }
+
+    Ok(out)
+}
+
+/// Decodes a SCALE-encoded `Vec<Vec<u8>>`.
+fn decode_entries(scale_encoded: &[u8]) -> Result<Vec<&[u8]>, ()> {
+    let (_, entries) = nom::combinator::all_consuming(nom::combinator::flat_map(
+        crate::util::nom_scale_compact_usize,
+        |num_elems| nom::multi::many_m_n(num_elems, num_elems, crate::util::nom_bytes_decode),
+    ))(scale_encoded)
+    .map_err(|_: nom::Err<nom::error::Error<&[u8]>>| ())?;
+    Ok(entries)
+}
+
+/// SCALE-encodes a list of entries into a `Vec<Vec<u8>>`.
+fn encode_entries(entries: impl ExactSizeIterator<Item = impl AsRef<[u8]>>) -> Vec<u8> {
+    let mut out = crate::util::encode_scale_compact_usize(entries.len())
+        .as_ref()
+        .to_vec();
+    for entry in entries {
+        out.extend_from_slice(
+            crate::util::encode_scale_compact_usize(entry.as_ref().len()).as_ref(),
+        );
+        out.extend_from_slice(entry.as_ref());
+    }
+    out
+}
+
+fn blake2_hash(data: &[u8]) -> [u8; 32] {
+    <[u8; 32]>::try_from(blake2_rfc::blake2b::blake2b(32, &[], data).as_bytes()).unwrap()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::super::{nibble, proof_decode, trie_node};
+    use core::iter;
+
+    fn hash(data: &[u8]) -> [u8; 32] {
+        super::blake2_hash(data)
+    }
+
+    fn nibbles(list: &[u8]) -> impl ExactSizeIterator<Item = nibble::Nibble> + Clone {
+        list.iter()
+            .map(|n| nibble::Nibble::try_from(*n).unwrap())
+            .collect::<Vec<_>>()
+            .into_iter()
+    }
+
+    fn leaf(partial_key: &[u8], storage_value: trie_node::StorageValue) -> Vec<u8> {
+        trie_node::encode_to_vec(trie_node::Decoded {
+            partial_key: nibbles(partial_key),
+            children: [None::<&[u8]>; 16],
+            storage_value,
+        })
+        .unwrap()
+    }
+
+    fn branch(
+        partial_key: &[u8],
+        children: impl IntoIterator<Item = (u8, Vec<u8>)>,
+        storage_value: trie_node::StorageValue,
+    ) -> Vec<u8> {
+        let mut children_array: [Option<Vec<u8>>; 16] = Default::default();
+        for (index, merkle_value) in children {
+            children_array[usize::from(index)] = Some(merkle_value);
+        }
+        trie_node::encode_to_vec(trie_node::Decoded {
+            partial_key: nibbles(partial_key),
+            children: children_array,
+            storage_value,
+        })
+        .unwrap()
+    }
+
+    fn scale_list(entries: &[&[u8]]) -> Vec<u8> {
+        super::encode_entries(entries.iter())
+    }
+
+    fn sorted_entries(proof: &[u8]) -> Vec<Vec<u8>> {
+        let mut list = super::decode_entries(proof)
+            .unwrap()
+            .into_iter()
+            .map(|e| e.to_vec())
+            .collect::<Vec<_>>();
+        list.sort();
+        list
+    }
+
+    #[test]
+    fn basic_encode_decode() {
+        let leaf1 = leaf(&[0xa], trie_node::StorageValue::Unhashed(&[0x11; 40]));
+        let leaf2 = leaf(&[0xb], trie_node::StorageValue::Unhashed(&[0x22; 40]));
+        let root = branch(
+            &[],
+            [(1, hash(&leaf1).to_vec()), (2, hash(&leaf2).to_vec())],
+            trie_node::StorageValue::None,
+        );
+        let root_hash = hash(&root);
+
+        let regular_proof = scale_list(&[&leaf2, &root, &leaf1]);
+        let compact_proof = super::encode(&regular_proof, &root_hash).unwrap();
+
+        // The Merkle values of the children are replaced with empty Merkle values, and the nodes
+        // are ordered depth-first.
+        assert_eq!(
+            super::decode_entries(&compact_proof).unwrap(),
+            vec![&[0x80, 0x06, 0x00, 0x00, 0x00][..], &leaf1, &leaf2]
+        );
+
+        let decoded = super::decode(&compact_proof).unwrap();
+        assert_eq!(decoded.trie_root_hash, root_hash);
+        assert_eq!(
+            sorted_entries(&decoded.regular_proof),
+            sorted_entries(&regular_proof)
+        );
+    }
+
+    #[test]
+    fn missing_child_kept() {
+        let leaf1 = leaf(&[0xa], trie_node::StorageValue::Unhashed(&[0x11; 40]));
+        let leaf2 = leaf(&[0xb], trie_node::StorageValue::Unhashed(&[0x22; 40]));
+        let root = branch(
+            &[],
+            [(1, hash(&leaf1).to_vec()), (2, hash(&leaf2).to_vec())],
+            trie_node::StorageValue::None,
+        );
+        let root_hash = hash(&root);
+
+        let compact_proof = super::encode(&scale_list(&[&root, &leaf2]), &root_hash).unwrap();
+        let compact_entries = super::decode_entries(&compact_proof).unwrap();
+        assert_eq!(compact_entries.len(), 2);
+        assert_eq!(compact_entries[1], &leaf2[..]);
+
+        let decoded = super::decode(&compact_proof).unwrap();
+        assert_eq!(decoded.trie_root_hash, root_hash);
+        assert_eq!(
+            sorted_entries(&decoded.regular_proof),
+            sorted_entries(&scale_list(&[&root, &leaf2]))
+        );
+    }
+
+    #[test]
+    fn hashed_storage_value_escaped() {
+        let value = [0x33; 64];
+        let value_hash = hash(&value);
+        let root = leaf(&[1, 2], trie_node::StorageValue::Hashed(&value_hash));
+        let root_hash = hash(&root);
+
+        let regular_proof = scale_list(&[&value, &root]);
+        let compact_proof = super::encode(&regular_proof, &root_hash).unwrap();
+
+        let compact_entries = super::decode_entries(&compact_proof).unwrap();
+        assert_eq!(
+            compact_entries,
+            vec![
+                &iter::once(0x01)
+                    .chain(leaf(&[1, 2], trie_node::StorageValue::Unhashed(&[])))
+                    .collect::<Vec<_>>()[..],
+                &value[..]
+            ]
+        );
+
+        let decoded = super::decode(&compact_proof).unwrap();
+        assert_eq!(decoded.trie_root_hash, root_hash);
+        assert_eq!(
+            sorted_entries(&decoded.regular_proof),
+            sorted_entries(&regular_proof)
+        );
+
+        // The regular proof can then be verified.
+        let verified = proof_decode::decode_and_verify_proof(proof_decode::Config {
+            proof: &decoded.regular_proof,
+            hash_function: super::super::HashFunction::Blake2,
+        })
+        .unwrap();
+        assert_eq!(
+            verified
+                .storage_value(&root_hash, &[0x12])
+                .unwrap()
+                .unwrap()
+                .0,
+            &value[..]
+        );
+    }
+
+    #[test]
+    fn escaped_node_with_storage_value_rejected() {
+        let root = leaf(&[1, 2], trie_node::StorageValue::Unhashed(b"hello"));
+        let compact_proof =
+            scale_list(&[&iter::once(0x01).chain(root).collect::<Vec<_>>(), b"world"]);
+        assert!(matches!(
+            super::decode(&compact_proof),
+            Err(super::DecodeError::InvalidEscapedNode)
+        ));
+    }
+
+    #[test]
+    fn incomplete_trie_rejected() {
+        let root = branch(
+            &[],
+            [(1, Vec::new()), (2, Vec::new())],
+            trie_node::StorageValue::None,
+        );
+        let leaf1 = leaf(&[0xa], trie_node::StorageValue::Unhashed(&[0x11; 40]));
+        assert!(matches!(
+            super::decode(&scale_list(&[&root, &leaf1])),
+            Err(super::DecodeError::IncompleteTrie)
+        ));
+    }
+
+    fn child_trie_test_proof() -> (Vec<u8>, [u8; 32], Vec<u8>) {
+        let child_root = leaf(&[5, 6], trie_node::StorageValue::Unhashed(&[0x44; 40]));
+        let child_root_hash = hash(&child_root);
+
+        let key = b":child_storage:default:foo"
+            .iter()
+            .flat_map(|b| [b >> 4, b & 0xf])
+            .collect::<Vec<_>>();
+        let root = leaf(&key, trie_node::StorageValue::Unhashed(&child_root_hash));
+        let root_hash = hash(&root);
+
+        (scale_list(&[&child_root, &root]), root_hash, child_root)
+    }
+
+    #[test]
+    fn child_trie_included() {
+        let (regular_proof, root_hash, child_root) = child_trie_test_proof();
+
+        let compact_proof = super::encode(&regular_proof, &root_hash).unwrap();
+        let compact_entries = super::decode_entries(&compact_proof).unwrap();
+        assert_eq!(compact_entries.len(), 2);
+        assert_eq!(compact_entries[1], &child_root[..]);
+
+        let decoded = super::decode(&compact_proof).unwrap();
+        assert_eq!(decoded.trie_root_hash, root_hash);
+        assert_eq!(
+            sorted_entries(&decoded.regular_proof),
+            sorted_entries(&regular_proof)
+        );
+    }
+
+    #[test]
+    fn missing_child_trie_allowed() {
+        let (regular_proof, root_hash, _) = child_trie_test_proof();
+        let compact_proof = super::encode(&regular_proof, &root_hash).unwrap();
+        let compact_entries = super::decode_entries(&compact_proof).unwrap();
+
+        let decoded = super::decode(&scale_list(&compact_entries[..1])).unwrap();
+        assert_eq!(decoded.trie_root_hash, root_hash);
+    }
+
+    #[test]
+    fn extraneous_child_trie_rejected() {
+        let (regular_proof, root_hash, _) = child_trie_test_proof();
+        let compact_proof = super::encode(&regular_proof, &root_hash).unwrap();
+        let mut compact_entries = super::decode_entries(&compact_proof).unwrap();
+
+        // Replace the child trie with a different one.
+        let other_child = leaf(&[5, 6], trie_node::StorageValue::Unhashed(&[0x55; 40]));
+        compact_entries[1] = &other_child;
+        assert!(matches!(
+            super::decode(&scale_list(&compact_entries)),
+            Err(super::DecodeError::ExtraneousChildTrie)
+        ));
+    }
+
+    #[test]
+    fn extraneous_entry_rejected() {
+        let root = leaf(&[1, 2], trie_node::StorageValue::Unhashed(b"hello"));
+        assert!(matches!(
+            super::decode(&scale_list(&[&root, b"foo"])),
+            Err(super::DecodeError::ExtraneousEntry)
+        ));
+    }
+}
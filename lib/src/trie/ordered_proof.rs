@@ -0,0 +1,670 @@
+// Smoldot
+// Copyright (C) 2023  Pierre Krieger
+// SPDX-License-Identifier: GPL-3.0-or-later WITH Classpath-exception-2.0
+
+// This program is free software: you can redistribute it and/or modify
+// it under the terms of the GNU General Public License as published by
+// the Free Software Foundation, either version 3 of the License, or
+// (at your option) any later version.
+
+// This program is distributed in the hope that it will be useful,
+// but WITHOUT ANY WARRANTY; without even the implied warranty of
+// MERCHANTABILITY or FITNESS FOR A PARTICULAR PURPOSE.  See the
+// GNU General Public License for more details.
+
+// You should have received a copy of the GNU General Public License
+// along with this program.  If not, see <http://www.gnu.org/licenses/>.
+
+//! Ordered trie proofs whose proven storage values are omitted.
+//!
+//! This is the format of the proofs passed to the `ext_trie_*_verify_proof` host functions, which
+//! Substrate generates with its `generate_trie_proof` function. Like a regular proof (see the
+//! [`proof_decode`](super::proof_decode) module), such a proof is a SCALE-encoded `Vec<Vec<u8>>`.
+//! Its differences with regular proofs are:
+//!
+//! - The node values are ordered depth-first, in other words each node is followed with the
+//!   nodes of its children in the order of their nibble.
+//! - When the child of a node is included in the proof, its Merkle value is replaced in the node
+//!   value of the parent with an empty Merkle value. Children whose node value is inlined within
+//!   their parent are left untouched.
+//! - The storage values of the keys being proven are omitted. A node without children has its
+//!   storage value replaced with an empty unhashed storage value, while a node with children has
+//!   its storage value removed.
+//!
+//! The verifier, who knows the keys and storage values being proven, puts back the storage values
+//! in the nodes, recalculates the Merkle values of the nodes, and compares the Merkle value of
+//! the root node with the expected trie root hash.
+
+use super::{nibble, trie_node, HashFunction, TrieEntryVersion};
+
+use alloc::vec::Vec;
+
+/// Configuration to pass to [`verify`].
+pub struct Config<'a, I> {
+    /// SCALE-encoded list of node values of the proof.
+    pub proof: &'a [u8],
+
+    /// Merkle value of the root node of the trie.
+    pub trie_root_hash: &'a [u8; 32],
+
+    /// List of keys and storage values that the proof must prove. A storage value of `None`
+    /// means that the key must not have any storage value.
+    pub entries: I,
+
+    /// Hash function used to calculate the Merkle values of the nodes and the hashes of the
+    /// storage values.
+    pub hash_function: HashFunction,
+
+    /// Version of the trie entries. Determines whether the storage values of the entries are
+    /// hashed in the trie.
+    pub trie_entries_version: TrieEntryVersion,
+}
+
+/// Verifies that the proof proves the given entries.
+pub fn verify<'a>(
+    config: Config<'a, impl IntoIterator<Item = (&'a [u8], Option<&'a [u8]>)>>,
+) -> Result<(), Error> {
+    let mut proof_entries = decode_entries(config.proof)
+        .map_err(|()| Error::InvalidFormat)?
+        .into_iter();
+
+    let mut entries = config.entries.into_iter().collect::<Vec<_>>();
+    entries.sort_unstable_by_key(|(key, _)| *key);
+    if entries.windows(2).any(|pair| pair[0].0 == pair[1].0) {
+        return Err(Error::DuplicateKey);
+    }
+    let mut entries = entries
+        .into_iter()
+        .map(|(key, value)| {
+            let key = nibble::bytes_to_nibbles(key.iter().copied()).collect::<Vec<_>>();
+            (key, value)
+        })
+        .peekable();
+
+    let hash = |data: &[u8]| -> [u8; 32] {
+        match config.hash_function {
+            HashFunction::Blake2 => {
+                <[u8; 32]>::try_from(blake2_rfc::blake2b::blake2b(32, &[], data).as_bytes())
+                    .unwrap()
+            }
+            HashFunction::Keccak256 => <sha3::Keccak256 as sha3::Digest>::digest(data).into(),
+        }
+    };
+
+    // Nodes whose children are being verified. The last element is the node currently being
+    // verified, and each node is a child of the one before it.
+    let mut stack = Vec::with_capacity(8);
+    stack.push(StackEntry::new(
+        proof_entries.next().ok_or(Error::IncompleteProof)?,
+        Vec::new(),
+        false,
+    )?);
+
+    loop {
+        let current = stack.last_mut().unwrap();
+
+        // Put back the storage values of the entries that belong to the current node, until we
+        // find an entry that belongs to a child of the current node or to a different node.
+        let mut descend_into = None;
+        while let Some((key, value)) = entries.peek() {
+            if !key.starts_with(&current.prefix) {
+                break;
+            }
+
+            match current.match_key(key) {
+                KeyMatch::MatchesLeaf => {
+                    let Some(value) = value else {
+                        return Err(Error::ValueMismatch);
+                    };
+                    current.set_storage_value(value, config.trie_entries_version, hash);
+                }
+                KeyMatch::MatchesBranch => {
+                    if let Some(value) = value {
+                        current.set_storage_value(value, config.trie_entries_version, hash);
+                    }
+                }
+                KeyMatch::NotFound => {
+                    if value.is_some() {
+                        return Err(Error::ValueMismatch);
+                    }
+                }
+                KeyMatch::NotOmitted => return Err(Error::ExtraneousValue),
+                KeyMatch::IsChild(child_prefix_len) => {
+                    descend_into = Some(key[..child_prefix_len].to_vec());
+                    break;
+                }
+            }
+
+            entries.next();
+        }
+
+        if let Some(child_prefix) = descend_into {
+            let child_index = usize::from(u8::from(*child_prefix.last().unwrap()));
+            current.copy_proof_children(child_index);
+
+            // `match_key` guarantees that the child exists.
+            let child = current.proof_children[child_index].unwrap();
+            let child_entry = if child.len() == 32 {
+                return Err(Error::ExtraneousHashReference);
+            } else if child.is_empty() {
+                let node_value = proof_entries.next().ok_or(Error::IncompleteProof)?;
+                StackEntry::new(node_value, child_prefix, false)?
+            } else {
+                StackEntry::new(child, child_prefix, true)?
+            };
+
+            stack.push(child_entry);
+            continue;
+        }
+
+        // All the entries that belong to the current node have been processed. Calculate its
+        // Merkle value and update its parent.
+        let current = stack.pop().unwrap();
+        let is_inline = current.is_inline;
+        let node_value = current.encode();
+        let merkle_value = if is_inline {
+            if node_value.len() > 32 {
+                return Err(Error::InvalidChildReference);
+            }
+            node_value
+        } else {
+            hash(&node_value).to_vec()
+        };
+
+        if let Some(parent) = stack.last_mut() {
+            parent.children[parent.next_child] = Some(merkle_value);
+            parent.next_child += 1;
+        } else {
+            if proof_entries.next().is_some() {
+                return Err(Error::ExtraneousNode);
+            }
+
+            // The root node is never inline, and its Merkle value is thus always a hash.
+            if merkle_value[..] != config.trie_root_hash[..] {
+                return Err(Error::RootMismatch);
+            }
+
+            return Ok(());
+        }
+    }
+}
+
+/// Possible error returned by [`verify`].
+#[derive(Debug, derive_more::Display, Clone)]
+pub enum Error {
+    /// Failed to decode the list of node values of the proof.
+    InvalidFormat,
+    /// One of the node values of the proof is invalid.
+    #[display(fmt = "Invalid trie node: {_0}")]
+    InvalidTrieNode(trie_node::Error),
+    /// The same key has been passed multiple times.
+    DuplicateKey,
+    /// The proof contains the storage value of one of the keys being proven instead of omitting
+    /// it.
+    ExtraneousValue,
+    /// The proof contains more node values than necessary.
+    ExtraneousNode,
+    /// The proof contains the hash of a node value that should have been included in the proof.
+    ExtraneousHashReference,
+    /// The proof is missing node values.
+    IncompleteProof,
+    /// A node value inlined in its parent is more than 32 bytes once its storage value has been
+    /// put back.
+    InvalidChildReference,
+    /// One of the keys being proven has a storage value whose presence doesn't match the proof.
+    ValueMismatch,
+    /// The Merkle value of the root node doesn't match the expected trie root hash.
+    RootMismatch,
+}
+
+/// How a key being proven relates to a node. See [`StackEntry::match_key`].
+enum KeyMatch {
+    /// The key is the key of the node, which has no children and whose storage value is omitted.
+    MatchesLeaf,
+    /// The key is the key of the node, which has children and whose storage value is omitted.
+    MatchesBranch,
+    /// The key is the key of the node, but the storage value isn't omitted.
+    NotOmitted,
+    /// The key isn't in the trie.
+    NotFound,
+    /// The key is the key of a descendant of the child of the node whose key is the first
+    /// nibbles of the key. Contains the number of nibbles.
+    IsChild(usize),
+}
+
+/// Node whose Merkle value is being calculated.
+struct StackEntry<'a> {
+    /// Key of the node, not including its partial key.
+    prefix: Vec<nibble::Nibble>,
+    /// Partial key of the node.
+    partial_key: Vec<nibble::Nibble>,
+    /// Merkle values of the children of the node as found in the proof.
+    proof_children: [Option<&'a [u8]>; 16],
+    /// Merkle values of the children of the node that have been calculated so far. Only the
+    /// children before [`StackEntry::next_child`] are filled.
+    children: [Option<Vec<u8>>; 16],
+    /// Index of the next child whose Merkle value must be filled in [`StackEntry::children`].
+    next_child: usize,
+    /// Storage value of the node. Overwritten if the storage value of the node is omitted.
+    storage_value: trie_node::StorageValue<'a>,
+    /// If `Some`, the storage value of the node is hashed and [`StackEntry::storage_value`] must
+    /// be ignored.
+    storage_value_hash: Option<[u8; 32]>,
+    /// `true` if the node value is inlined within the node value of its parent.
+    is_inline: bool,
+}
+
+impl<'a> StackEntry<'a> {
+    fn new(
+        node_value: &'a [u8],
+        prefix: Vec<nibble::Nibble>,
+        is_inline: bool,
+    ) -> Result<Self, Error> {
+        let decoded = trie_node::decode(node_value).map_err(Error::InvalidTrieNode)?;
+        Ok(StackEntry {
+            prefix,
+            partial_key: decoded.partial_key.collect(),
+            proof_children: decoded.children,
+            children: Default::default(),
+            next_child: 0,
+            storage_value: decoded.storage_value,
+            storage_value_hash: None,
+            is_inline,
+        })
+    }
+
+    /// Determines how the given key relates to this node. The key must start with
+    /// [`StackEntry::prefix`].
+    fn match_key(&self, key: &[nibble::Nibble]) -> KeyMatch {
+        let Some(after_partial_key) = key[self.prefix.len()..].strip_prefix(&self.partial_key[..])
+        else {
+            return KeyMatch::NotFound;
+        };
+
+        if self.proof_children.iter().all(Option::is_none) {
+            if !after_partial_key.is_empty() {
+                return KeyMatch::NotFound;
+            }
+
+            match self.storage_value {
+                trie_node::StorageValue::Unhashed([]) => KeyMatch::MatchesLeaf,
+                // Root node of an empty trie.
+                trie_node::StorageValue::None => KeyMatch::NotFound,
+                _ => KeyMatch::NotOmitted,
+            }
+        } else {
+            match after_partial_key.first() {
+                None if matches!(self.storage_value, trie_node::StorageValue::None) => {
+                    KeyMatch::MatchesBranch
+                }
+                None => KeyMatch::NotOmitted,
+                Some(nibble) if self.proof_children[usize::from(u8::from(*nibble))].is_some() => {
+                    KeyMatch::IsChild(self.prefix.len() + self.partial_key.len() + 1)
+                }
+                Some(_) => KeyMatch::NotFound,
+            }
+        }
+    }
+
+    /// Puts back the omitted storage value of the node.
+    fn set_storage_value(
+        &mut self,
+        value: &'a [u8],
+        trie_entries_version: TrieEntryVersion,
+        hash: impl FnOnce(&[u8]) -> [u8; 32],
+    ) {
+        // With the version 1 of the trie, storage values of 33 bytes or more are hashed.
+        if matches!(trie_entries_version, TrieEntryVersion::V1) && value.len() >= 33 {
+            self.storage_value_hash = Some(hash(value));
+        } else {
+            self.storage_value = trie_node::StorageValue::Unhashed(value);
+            self.storage_value_hash = None;
+        }
+    }
+
+    /// Copies the Merkle values of the children found in the proof to
+    /// [`StackEntry::children`], up to the given index excluded.
+    fn copy_proof_children(&mut self, up_to: usize) {
+        while self.next_child < up_to {
+            self.children[self.next_child] =
+                self.proof_children[self.next_child].map(|child| child.to_vec());
+            self.next_child += 1;
+        }
+    }
+
+    /// Returns the node value of the node with its storage value and the Merkle values of its
+    /// children put back.
+    fn encode(mut self) -> Vec<u8> {
+        self.copy_proof_children(16);
+
+        let storage_value = match &self.storage_value_hash {
+            Some(hash) => trie_node::StorageValue::Hashed(hash),
+            None => self.storage_value,
+        };
+
+        // Can't fail, as the node was successfully decoded and still has either children, a
+        // storage value, or no partial key.
+        trie_node::encode_to_vec(trie_node::Decoded {
+            partial_key: self.partial_key.iter().copied(),
+            children: self.children,
+            storage_value,
+        })
+        .unwrap()
+    }
+}
+
+/// Decodes a SCALE-encoded `Vec<Vec<u8>>`.
+fn decode_entries(scale_encoded: &[u8]) -> Result<Vec<&[u8]>, ()> {
+    let (_, entries) = nom::combinator::all_consuming(nom::combinator::flat_map(
+        crate::util::nom_scale_compact_usize,
+        |num_elems| nom::multi::many_m_n(num_elems, num_elems, crate::util::nom_bytes_decode),
+    ))(scale_encoded)
+    .map_err(|_: nom::Err<nom::error::Error<&[u8]>>| ())?;
+    Ok(entries)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::super::{trie_node, HashFunction, TrieEntryVersion};
+    use super::{Config, Error};
+
+    fn hash(data: &[u8]) -> [u8; 32] {
+        <[u8; 32]>::try_from(blake2_rfc::blake2b::blake2b(32, &[], data).as_bytes()).unwrap()
+    }
+
+    fn encode_proof(entries: &[&[u8]]) -> Vec<u8> {
+        let mut out = crate::util::encode_scale_compact_usize(entries.len())
+            .as_ref()
+            .to_vec();
+        for entry in entries {
+            out.extend_from_slice(crate::util::encode_scale_compact_usize(entry.len()).as_ref());
+            out.extend_from_slice(entry);
+        }
+        out
+    }
+
+    fn node(
+        partial_key: &[u8],
+        children: &[(u8, &[u8])],
+        storage_value: trie_node::StorageValue,
+    ) -> Vec<u8> {
+        let mut node_children: [Option<&[u8]>; 16] = [None; 16];
+        for (index, child) in children {
+            node_children[usize::from(*index)] = Some(child);
+        }
+        trie_node::encode_to_vec(trie_node::Decoded {
+            partial_key: partial_key
+                .iter()
+                .map(|n| super::nibble::Nibble::try_from(*n).unwrap()),
+            children: node_children,
+            storage_value,
+        })
+        .unwrap()
+    }
+
+    fn verify(
+        proof: &[&[u8]],
+        trie_root_hash: &[u8; 32],
+        entries: &[(&[u8], Option<&[u8]>)],
+        trie_entries_version: TrieEntryVersion,
+    ) -> Result<(), Error> {
+        super::verify(Config {
+            proof: &encode_proof(proof),
+            trie_root_hash,
+            entries: entries.iter().copied(),
+            hash_function: HashFunction::Blake2,
+            trie_entries_version,
+        })
+    }
+
+    // Trie containing `0x12 => 0xaa`, `0x1345 => 0xbb`, and a large storage value at `0x13`. The
+    // root node has the partial key `0x1` and two children: the leaf `0x12` at index 2 and the
+    // branch `0x13` at index 3.
+    const LARGE_VALUE: &[u8] = &[0x55; 40];
+
+    fn trie(version: TrieEntryVersion) -> (Vec<u8>, Vec<u8>, Vec<u8>, [u8; 32]) {
+        let leaf_12 = node(&[], &[], trie_node::StorageValue::Unhashed(&[0xaa]));
+        let leaf_1345 = node(&[5], &[], trie_node::StorageValue::Unhashed(&[0xbb]));
+        let large_value_hash = hash(LARGE_VALUE);
+        let branch_13 = node(
+            &[],
+            &[(4, &leaf_1345)],
+            match version {
+                TrieEntryVersion::V0 => trie_node::StorageValue::Unhashed(LARGE_VALUE),
+                TrieEntryVersion::V1 => trie_node::StorageValue::Hashed(&large_value_hash),
+            },
+        );
+        let branch_13_merkle_value = if branch_13.len() >= 32 {
+            hash(&branch_13).to_vec()
+        } else {
+            branch_13.clone()
+        };
+        let root = node(
+            &[1],
+            &[(2, &leaf_12), (3, &branch_13_merkle_value)],
+            trie_node::StorageValue::None,
+        );
+        let root_hash = hash(&root);
+        (root, branch_13, leaf_1345, root_hash)
+    }
+
+    #[test]
+    fn inline_leaf_value() {
+        for version in [TrieEntryVersion::V0, TrieEntryVersion::V1] {
+            let (_, branch_13, _, root_hash) = trie(version);
+            let branch_13_hash = hash(&branch_13);
+            let leaf_12 = node(&[], &[], trie_node::StorageValue::Unhashed(&[]));
+            let proof_root = node(
+                &[1],
+                &[(2, &leaf_12), (3, &branch_13_hash)],
+                trie_node::StorageValue::None,
+            );
+
+            assert!(verify(
+                &[&proof_root],
+                &root_hash,
+                &[(&[0x12], Some(&[0xaa]))],
+                version
+            )
+            .is_ok());
+            assert!(matches!(
+                verify(
+                    &[&proof_root],
+                    &root_hash,
+                    &[(&[0x12], Some(&[0xab]))],
+                    version
+                ),
+                Err(Error::RootMismatch)
+            ));
+        }
+    }
+
+    #[test]
+    fn omitted_branch_value() {
+        for version in [TrieEntryVersion::V0, TrieEntryVersion::V1] {
+            let (_, _, leaf_1345, root_hash) = trie(version);
+            let leaf_12 = node(&[], &[], trie_node::StorageValue::Unhashed(&[0xaa]));
+            let leaf_1345_hash;
+            let leaf_1345_merkle_value = if leaf_1345.len() >= 32 {
+                leaf_1345_hash = hash(&leaf_1345);
+                &leaf_1345_hash[..]
+            } else {
+                &leaf_1345[..]
+            };
+            let proof_branch_13 = node(
+                &[],
+                &[(4, leaf_1345_merkle_value)],
+                trie_node::StorageValue::None,
+            );
+            let proof_root = node(
+                &[1],
+                &[(2, &leaf_12), (3, &[])],
+                trie_node::StorageValue::None,
+            );
+
+            assert!(verify(
+                &[&proof_root, &proof_branch_13],
+                &root_hash,
+                &[(&[0x13], Some(LARGE_VALUE))],
+                version
+            )
+            .is_ok());
+
+            // Proving the storage value with the wrong trie version fails.
+            let other_version = match version {
+                TrieEntryVersion::V0 => TrieEntryVersion::V1,
+                TrieEntryVersion::V1 => TrieEntryVersion::V0,
+            };
+            assert!(matches!(
+                verify(
+                    &[&proof_root, &proof_branch_13],
+                    &root_hash,
+                    &[(&[0x13], Some(LARGE_VALUE))],
+                    other_version
+                ),
+                Err(Error::RootMismatch)
+            ));
+        }
+    }
+
+    #[test]
+    fn absent_key() {
+        let (_, branch_13, _, root_hash) = trie(TrieEntryVersion::V1);
+        let branch_13_hash = hash(&branch_13);
+        let leaf_12 = node(&[], &[], trie_node::StorageValue::Unhashed(&[0xaa]));
+        let proof_root = node(
+            &[1],
+            &[(2, &leaf_12), (3, &branch_13_hash)],
+            trie_node::StorageValue::None,
+        );
+
+        assert!(verify(
+            &[&proof_root],
+            &root_hash,
+            &[(&[0x15], None)],
+            TrieEntryVersion::V1
+        )
+        .is_ok());
+        assert!(matches!(
+            verify(
+                &[&proof_root],
+                &root_hash,
+                &[(&[0x15], Some(&[0xaa]))],
+                TrieEntryVersion::V1
+            ),
+            Err(Error::ValueMismatch)
+        ));
+    }
+
+    #[test]
+    fn value_not_omitted() {
+        let (_, branch_13, _, root_hash) = trie(TrieEntryVersion::V1);
+        let branch_13_hash = hash(&branch_13);
+        let leaf_12 = node(&[], &[], trie_node::StorageValue::Unhashed(&[0xaa]));
+        let proof_root = node(
+            &[1],
+            &[(2, &leaf_12), (3, &branch_13_hash)],
+            trie_node::StorageValue::None,
+        );
+
+        assert!(matches!(
+            verify(
+                &[&proof_root],
+                &root_hash,
+                &[(&[0x12], Some(&[0xaa]))],
+                TrieEntryVersion::V1
+            ),
+            Err(Error::ExtraneousValue)
+        ));
+    }
+
+    #[test]
+    fn hash_reference_instead_of_node() {
+        let (_, branch_13, _, root_hash) = trie(TrieEntryVersion::V1);
+        let branch_13_hash = hash(&branch_13);
+        let leaf_12 = node(&[], &[], trie_node::StorageValue::Unhashed(&[0xaa]));
+        let proof_root = node(
+            &[1],
+            &[(2, &leaf_12), (3, &branch_13_hash)],
+            trie_node::StorageValue::None,
+        );
+
+        assert!(matches!(
+            verify(
+                &[&proof_root],
+                &root_hash,
+                &[(&[0x13], Some(LARGE_VALUE))],
+                TrieEntryVersion::V1
+            ),
+            Err(Error::ExtraneousHashReference)
+        ));
+    }
+
+    #[test]
+    fn incomplete_and_extraneous_nodes() {
+        let (_, branch_13, _, root_hash) = trie(TrieEntryVersion::V1);
+        let leaf_12 = node(&[], &[], trie_node::StorageValue::Unhashed(&[0xaa]));
+        let proof_root = node(
+            &[1],
+            &[(2, &leaf_12), (3, &[])],
+            trie_node::StorageValue::None,
+        );
+
+        assert!(matches!(
+            verify(
+                &[&proof_root],
+                &root_hash,
+                &[(&[0x13], Some(LARGE_VALUE))],
+                TrieEntryVersion::V1
+            ),
+            Err(Error::IncompleteProof)
+        ));
+
+        let branch_13_hash = hash(&branch_13);
+        let leaf_12 = node(&[], &[], trie_node::StorageValue::Unhashed(&[]));
+        let proof_root = node(
+            &[1],
+            &[(2, &leaf_12), (3, &branch_13_hash)],
+            trie_node::StorageValue::None,
+        );
+        assert!(matches!(
+            verify(
+                &[&proof_root, &branch_13],
+                &root_hash,
+                &[(&[0x12], Some(&[0xaa]))],
+                TrieEntryVersion::V1
+            ),
+            Err(Error::ExtraneousNode)
+        ));
+    }
+
+    #[test]
+    fn duplicate_key() {
+        let (_, _, _, root_hash) = trie(TrieEntryVersion::V1);
+        assert!(matches!(
+            verify(
+                &[&[0]],
+                &root_hash,
+                &[(&[0x12], Some(&[0xaa])), (&[0x12], None)],
+                TrieEntryVersion::V1
+            ),
+            Err(Error::DuplicateKey)
+        ));
+    }
+
+    #[test]
+    fn empty_trie() {
+        let root_hash = hash(&[0]);
+        assert!(verify(
+            &[&[0]],
+            &root_hash,
+            &[(&[1, 2, 3], None)],
+            TrieEntryVersion::V1
+        )
+        .is_ok());
+        assert!(matches!(
+            verify(&[], &root_hash, &[(&[1, 2, 3], None)], TrieEntryVersion::V1),
+            Err(Error::IncompleteProof)
+        ));
+    }
+}
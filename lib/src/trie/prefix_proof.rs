@@ -27,7 +27,7 @@
 
 // TODO: usage example
 
-use super::{nibble, proof_decode};
+use super::{nibble, proof_decode, HashFunction};
 
 use alloc::{borrow::ToOwned as _, vec, vec::Vec};
 use core::{fmt, iter, mem};
@@ -104,11 +104,13 @@ impl PrefixScan {
     ///
     /// Returns an error if the proof is invalid. In that case, `self` isn't modified.
     pub fn resume(mut self, proof: &[u8]) -> Result<ResumeOutcome, (Self, Error)> {
-        let decoded_proof =
-            match proof_decode::decode_and_verify_proof(proof_decode::Config { proof }) {
-                Ok(d) => d,
-                Err(err) => return Err((self, Error::InvalidProof(err))),
-            };
+        let decoded_proof = match proof_decode::decode_and_verify_proof(proof_decode::Config {
+            proof,
+            hash_function: HashFunction::Blake2,
+        }) {
+            Ok(d) => d,
+            Err(err) => return Err((self, Error::InvalidProof(err))),
+        };
 
         let mut non_terminal_queries = mem::take(&mut self.next_queries);
 
@@ -38,7 +38,7 @@
 //! Once decoded, one can examine the content of the proof, in other words the list of storage
 //! items and values.
 
-use super::{nibble, trie_node, TrieEntryVersion};
+use super::{nibble, trie_node, HashFunction, TrieEntryVersion};
 
 use alloc::{collections::BTreeMap, vec, vec::Vec};
 use core::{fmt, iter, mem, ops};
@@ -48,6 +48,9 @@ pub struct Config<I> {
     /// List of node values of nodes found in the trie. At least one entry corresponding to the
     /// root node of the trie must be present in order for the verification to succeed.
     pub proof: I,
+
+    /// Hash function used by the trie the proof is about.
+    pub hash_function: HashFunction,
 }
 
 /// Verifies whether a proof is correct and returns an object that allows examining its content.
@@ -74,7 +77,7 @@ where
     //
     // This hashmap uses a FNV hasher, theoretically vulnerable to HashDos attacks. While it is
     // possible for an attacker to craft a proof that leads to all entries being in the same
-    // bucket, this proof is going to be invalid (unless the blake2 hash function is broken, which
+    // bucket, this proof is going to be invalid (unless the hash function is broken, which
     // we assume it isn't). So while an attacker can slightly increase the time that this function
     // takes, it is always cause this function to return an error and is actually likely to make
     // the function actually take less time than if it was a legitimate proof.
@@ -96,10 +99,15 @@ where
                     // itself if its length is < 32. In the context of a proof, however, nodes
                     // whose length is < 32 aren't supposed to be their own entry. For this reason,
                     // we only hash each entry.
-                    let hash = *<&[u8; 32]>::try_from(
-                        blake2_rfc::blake2b::blake2b(32, &[], proof_entry).as_bytes(),
-                    )
-                    .unwrap();
+                    let hash = match config.hash_function {
+                        HashFunction::Blake2 => *<&[u8; 32]>::try_from(
+                            blake2_rfc::blake2b::blake2b(32, &[], proof_entry).as_bytes(),
+                        )
+                        .unwrap(),
+                        HashFunction::Keccak256 => {
+                            <sha3::Keccak256 as sha3::Digest>::digest(proof_entry).into()
+                        }
+                    };
 
                     let proof_entry_offset = if proof_entry.is_empty() {
                         0
@@ -903,41 +911,53 @@ impl<T: AsRef<[u8]>> DecodedTrieProof<T> {
                     if let Some(nibble_that_exists) = nibble_that_exists {
                         // The next key of `key_before` is the descendant of `ancestor_key` in
                         // the direction of `nibble_that_exists`.
-                        key_before.push(nibble_that_exists);
-                        if let Some(((_, descendant_key), _)) = self
+                        let mut child_prefix = Vec::with_capacity(ancestor_key.len() + 1);
+                        child_prefix.extend_from_slice(ancestor_key);
+                        child_prefix.push(nibble_that_exists);
+
+                        let Some(((_, descendant_key), _)) = self
                             .entries
                             .range((
                                 ops::Bound::Included((
                                     *trie_root_merkle_value,
-                                    key_before.to_vec(), // TODO: stupid allocation
+                                    child_prefix.clone(),
                                 )),
                                 ops::Bound::Unbounded,
                             ))
                             .next()
                             .filter(|((h, k), _)| {
-                                h == trie_root_merkle_value && k.starts_with(&key_before)
+                                h == trie_root_merkle_value && k.starts_with(&child_prefix)
                             })
-                        {
-                            key_before = descendant_key.clone();
-                        } else {
+                        else {
                             // We know that there is a descendant but it is not in the proof.
                             return Err(IncompleteProofError());
+                        };
+
+                        // If the child is in the direction of `key_before`, its key can be
+                        // inferior to `key_before` if it diverges from it, in which case the
+                        // entire sub-trie of the child must be skipped.
+                        if *descendant_key >= key_before {
+                            key_before = descendant_key.clone();
+                            continue;
                         }
+
+                        key_before = child_prefix;
                     } else {
                         // `ancestor_key` has no children that can possibly be superior
                         // to `key_before`. Advance to finding the first sibling after
                         // `ancestor_key`.
                         key_before.truncate(ancestor_key.len());
-                        loop {
-                            let Some(nibble) = key_before.pop() else {
-                                // `key_before` is equal to `0xffff...` and thus can't
-                                // have any next sibling.
-                                return Ok(None);
-                            };
-                            if let Some(new_nibble) = nibble.checked_add(1) {
-                                key_before.push(new_nibble);
-                                break;
-                            }
+                    }
+
+                    loop {
+                        let Some(nibble) = key_before.pop() else {
+                            // `key_before` is equal to `0xffff...` and thus can't
+                            // have any next sibling.
+                            return Ok(None);
+                        };
+                        if let Some(new_nibble) = nibble.checked_add(1) {
+                            key_before.push(new_nibble);
+                            break;
                         }
                     }
                 }
@@ -1209,7 +1229,11 @@ impl<'a> fmt::Binary for Children<'a> {
 mod tests {
     #[test]
     fn empty_is_valid() {
-        let _ = super::decode_and_verify_proof(super::Config { proof: &[0] }).unwrap();
+        let _ = super::decode_and_verify_proof(super::Config {
+            proof: &[0],
+            hash_function: super::HashFunction::Blake2,
+        })
+        .unwrap();
     }
 
     #[test]
@@ -1320,7 +1344,11 @@ mod tests {
             <[u8; 32]>::try_from(&bytes[..]).unwrap()
         };
 
-        let decoded = super::decode_and_verify_proof(super::Config { proof }).unwrap();
+        let decoded = super::decode_and_verify_proof(super::Config {
+            proof,
+            hash_function: super::HashFunction::Blake2,
+        })
+        .unwrap();
 
         let requested_key = hex::decode("9c5d795d0297be56027a4b2464e3339763e6d3c1fb15805edfd024172ea4817d7081542596adb05d6140c170ac479edf7cfd5aa35357590acfe5d11a804d944e").unwrap();
         let obtained = decoded.storage_value(&trie_root, &requested_key).unwrap();
@@ -1368,7 +1396,11 @@ mod tests {
             215, 134, 15, 252, 135, 67, 129, 21, 16, 20, 211, 97, 217,
         ];
 
-        let decoded = super::decode_and_verify_proof(super::Config { proof }).unwrap();
+        let decoded = super::decode_and_verify_proof(super::Config {
+            proof,
+            hash_function: super::HashFunction::Blake2,
+        })
+        .unwrap();
 
         let requested_key =
             hex::decode("f0c365c3cf59d671eb72da0e7a4113c49f1f0515f462cdcf84e0f1d6045dfcbb")
@@ -1385,7 +1417,11 @@ mod tests {
             4, 64, 66, 3, 52, 120, 31, 215, 222, 245, 16, 76, 51, 181, 0, 245, 192, 194,
         ];
 
-        let proof = super::decode_and_verify_proof(super::Config { proof }).unwrap();
+        let proof = super::decode_and_verify_proof(super::Config {
+            proof,
+            hash_function: super::HashFunction::Blake2,
+        })
+        .unwrap();
 
         assert!(proof
             .closest_descendant_merkle_value(
@@ -1405,6 +1441,7 @@ mod tests {
             proof: &[
                 4, 60, 128, 3, 0, 20, 65, 0, 8, 104, 105, 20, 65, 0, 8, 104, 105,
             ],
+            hash_function: super::HashFunction::Blake2,
         })
         .unwrap();
 
@@ -1419,6 +1456,51 @@ mod tests {
             .is_ok());
     }
 
+    #[test]
+    fn next_key_skips_diverging_subtrie() {
+        // One root node with two inlined children at keys `00` and `10`.
+        let proof = super::decode_and_verify_proof(super::Config {
+            proof: &[
+                4, 60, 128, 3, 0, 20, 65, 0, 8, 104, 105, 20, 65, 0, 8, 104, 105,
+            ],
+            hash_function: super::HashFunction::Blake2,
+        })
+        .unwrap();
+
+        let trie_root = [
+            15, 224, 134, 90, 11, 145, 174, 197, 185, 253, 233, 197, 95, 101, 197, 10, 78, 28, 137,
+            217, 102, 198, 242, 100, 90, 96, 9, 204, 213, 69, 174, 4,
+        ];
+        let nibbles = |n: &[u8]| {
+            n.iter()
+                .map(|n| super::nibble::Nibble::try_from(*n).unwrap())
+                .collect::<Vec<_>>()
+        };
+
+        assert_eq!(
+            proof.next_key(&trie_root, &[], true, &[], false).unwrap(),
+            Some(&nibbles(&[0, 0])[..])
+        );
+        assert_eq!(
+            proof
+                .next_key(&trie_root, &nibbles(&[0, 0]), false, &[], false)
+                .unwrap(),
+            Some(&nibbles(&[1, 0])[..])
+        );
+        assert_eq!(
+            proof
+                .next_key(&trie_root, &nibbles(&[0, 3, 5]), true, &[], true)
+                .unwrap(),
+            Some(&nibbles(&[1, 0])[..])
+        );
+        assert_eq!(
+            proof
+                .next_key(&trie_root, &nibbles(&[1, 0]), false, &[], false)
+                .unwrap(),
+            None
+        );
+    }
+
     #[test]
     fn identical_non_inline_nodes() {
         // One root node with two identical children and the proof contains the two children
@@ -1442,6 +1524,7 @@ mod tests {
                     108, 117, 101, 32, 105, 115, 32, 109, 111, 114, 101, 32, 116, 104, 97, 110, 32,
                     51, 50, 32, 98, 121, 116, 101, 115, 32, 108, 111, 110, 103
                 ],
+                hash_function: super::HashFunction::Blake2,
             }),
             Err(super::Error::DuplicateProofEntry)
         ));
@@ -1461,6 +1544,7 @@ mod tests {
                 32, 116, 104, 97, 110, 32, 51, 50, 32, 98, 121, 116, 101, 115, 32, 108, 111, 110,
                 103,
             ],
+            hash_function: super::HashFunction::Blake2,
         })
         .unwrap();
     }
@@ -447,7 +447,7 @@ fn blake2_hash(data: &[u8]) -> [u8; 32] {
 
 #[cfg(test)]
 mod tests {
-    use super::super::{nibble, proof_decode, trie_node, trie_structure};
+    use super::super::{nibble, proof_decode, trie_node, trie_structure, HashFunction};
     use core::array;
     use rand::distributions::{Distribution as _, Uniform};
 
@@ -605,8 +605,11 @@ mod tests {
             let proof = proof_builder.build_to_vec();
 
             // Verify the correctness of the proof.
-            let proof =
-                proof_decode::decode_and_verify_proof(proof_decode::Config { proof }).unwrap();
+            let proof = proof_decode::decode_and_verify_proof(proof_decode::Config {
+                proof,
+                hash_function: HashFunction::Blake2,
+            })
+            .unwrap();
             assert!(proof
                 .closest_descendant_merkle_value(&trie_root_hash, &[])
                 .is_ok());
@@ -660,6 +663,7 @@ mod tests {
         // The proof builder should de-duplicate the two children, otherwise the proof is invalid.
         proof_decode::decode_and_verify_proof(proof_decode::Config {
             proof: proof_builder.build_to_vec(),
+            hash_function: HashFunction::Blake2,
         })
         .unwrap();
     }
@@ -462,23 +462,13 @@ pub fn verify_header(config: VerifyConfig) -> Result<VerifySuccess, VerifyError>
     // claim. If the block is a secondary slot claim, we need to make sure that the author
     // is indeed the one that is expected.
     if !is_primary_slot {
-        // Expected author is determined based on `blake2(randomness | slot_number)`.
-        let hash = {
-            let mut hash = blake2_rfc::blake2b::Blake2b::new(32);
-            hash.update(block_epoch_info.randomness);
-            hash.update(&slot_number.to_le_bytes());
-            hash.finalize()
-        };
-
-        // The expected authority index is `hash % num_authorities`.
-        let expected_authority_index = {
-            let hash = num_bigint::BigUint::from_bytes_be(hash.as_bytes());
-            let authorities_len = num_bigint::BigUint::from(block_epoch_info.authorities.len());
-            debug_assert_ne!(block_epoch_info.authorities.len(), 0);
-            hash % authorities_len
-        };
+        let expected_authority_index = secondary_slot_author(
+            block_epoch_info.randomness,
+            slot_number,
+            block_epoch_info.authorities.len(),
+        );
 
-        if u32::try_from(expected_authority_index).map_or(true, |v| v != authority_index) {
+        if expected_authority_index != authority_index {
             return Err(VerifyError::BadSecondarySlotAuthor);
         }
     }
@@ -491,6 +481,36 @@ pub fn verify_header(config: VerifyConfig) -> Result<VerifySuccess, VerifyError>
     })
 }
 
+/// Calculates the index within the list of authorities of the epoch of the authority that is
+/// allowed to claim the given slot as a secondary slot.
+///
+/// # Panic
+///
+/// Panics if `num_authorities` is 0.
+///
+pub(crate) fn secondary_slot_author(
+    epoch_randomness: &[u8; 32],
+    slot_number: u64,
+    num_authorities: usize,
+) -> u32 {
+    assert_ne!(num_authorities, 0);
+
+    // Expected author is determined based on `blake2(randomness | slot_number)`.
+    let hash = {
+        let mut hash = blake2_rfc::blake2b::Blake2b::new(32);
+        hash.update(epoch_randomness);
+        hash.update(&slot_number.to_le_bytes());
+        hash.finalize()
+    };
+
+    // The expected authority index is `hash % num_authorities`.
+    let hash = num_bigint::BigUint::from_bytes_be(hash.as_bytes());
+    let authorities_len = num_bigint::BigUint::from(num_authorities);
+    // The remainder is always strictly inferior to `num_authorities`, and it is assumed that
+    // there can't be more than `u32::max_value()` authorities.
+    u32::try_from(hash % authorities_len).unwrap()
+}
+
 /// Calculates the primary selection threshold for a given authority, taking
 /// into account `c` (`1 - c` represents the probability of a slot being empty).
 ///
@@ -504,7 +524,7 @@ pub fn verify_header(config: VerifyConfig) -> Result<VerifySuccess, VerifyError>
 /// Panics if `authorities_weights` is empty.
 /// Panics if `authority_weight` is 0.
 ///
-fn calculate_primary_threshold(
+pub(crate) fn calculate_primary_threshold(
     c: (u64, u64),
     authorities_weights: impl ExactSizeIterator<Item = u64>,
     authority_weight: u64, // TODO: use a NonZeroU64 once crate::header also has weights that use NonZeroU64
@@ -525,3 +545,91 @@ fn calculate_primary_threshold(
         .to_u128()
         .unwrap()
 }
+
+#[cfg(test)]
+mod tests {
+    #[test]
+    fn secondary_slot_author_single_authority() {
+        for slot_number in [0, 1, 12345, u64::max_value()] {
+            assert_eq!(super::secondary_slot_author(&[0xaa; 32], slot_number, 1), 0);
+        }
+    }
+
+    #[test]
+    fn secondary_slot_author_known_values() {
+        // Expected values are `blake2_256(randomness | slot_number_le) % num_authorities`, the
+        // hash being interpreted as big endian.
+        let randomness = {
+            let mut randomness = [0; 32];
+            for (n, byte) in randomness.iter_mut().enumerate() {
+                *byte = u8::try_from(n).unwrap();
+            }
+            randomness
+        };
+
+        assert_eq!(
+            super::secondary_slot_author(&randomness, 285_000_000, 10),
+            4
+        );
+        assert_eq!(super::secondary_slot_author(&randomness, 12345, 7), 3);
+    }
+
+    #[test]
+    fn secondary_slot_author_in_bounds() {
+        for _ in 0..256 {
+            let num_authorities = usize::from(rand::random::<u8>()) + 1;
+            let author =
+                super::secondary_slot_author(&rand::random(), rand::random(), num_authorities);
+            assert!(usize::try_from(author).unwrap() < num_authorities);
+        }
+    }
+
+    #[test]
+    #[should_panic]
+    fn secondary_slot_author_no_authority() {
+        super::secondary_slot_author(&[0; 32], 0, 0);
+    }
+
+    #[test]
+    fn primary_threshold_single_authority() {
+        // With a single authority, the threshold is exactly `c * 2^128`.
+        assert_eq!(
+            super::calculate_primary_threshold((1, 4), [1].into_iter(), 1),
+            1u128 << 126
+        );
+        assert_eq!(
+            super::calculate_primary_threshold((0, 1), [1].into_iter(), 1),
+            0
+        );
+    }
+
+    #[test]
+    fn primary_threshold_known_values() {
+        // `(1 - (1 - 1/4)^(1/2)) * 2^128`, rounded down.
+        assert_eq!(
+            super::calculate_primary_threshold((1, 4), [1, 1].into_iter(), 1),
+            45589192707508239153098207983084503040
+        );
+        // Only the ratio between the authority weight and the total weight matters.
+        assert_eq!(
+            super::calculate_primary_threshold((1, 4), [1, 1, 2].into_iter(), 2),
+            45589192707508239153098207983084503040
+        );
+    }
+
+    #[test]
+    fn primary_threshold_increases_with_weight() {
+        let weights = [1, 2, 3, 4];
+        let thresholds = weights
+            .iter()
+            .map(|w| super::calculate_primary_threshold((1, 4), weights.iter().copied(), *w))
+            .collect::<Vec<_>>();
+        assert!(thresholds.windows(2).all(|w| w[0] < w[1]));
+    }
+
+    #[test]
+    #[should_panic]
+    fn primary_threshold_no_authority() {
+        super::calculate_primary_threshold((1, 4), [].into_iter(), 1);
+    }
+}
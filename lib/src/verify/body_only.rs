@@ -267,6 +267,7 @@ pub fn verify(
             max_log_level: config.max_log_level,
             // Calculating the trie changes is done at the next step.
             calculate_trie_changes: false,
+            execution_tracing: None,
         });
 
         match vm {
@@ -364,6 +365,7 @@ impl VerifyInner {
                                 .into_main_trie_diff(),
                             max_log_level: 0,
                             calculate_trie_changes: self.calculate_trie_changes,
+                            execution_tracing: None,
                         });
 
                         match vm {
@@ -498,6 +500,14 @@ impl VerifyInner {
                 (runtime_host::RuntimeHostVm::Offchain(ctx), _phase) => {
                     return Verify::Finished(Err((Error::ForbiddenHostCall, ctx.into_prototype())))
                 }
+                (
+                    req @ (runtime_host::RuntimeHostVm::KeystorePublicKeys(_)
+                    | runtime_host::RuntimeHostVm::KeystoreGenerate(_)
+                    | runtime_host::RuntimeHostVm::KeystoreSign(_)),
+                    _phase,
+                ) => {
+                    return Verify::Finished(Err((Error::ForbiddenHostCall, req.into_prototype())))
+                }
             }
         }
     }
@@ -740,6 +750,9 @@ impl RuntimeCompilation {
             heap_pages: self.heap_pages,
             exec_hint: vm::ExecHint::CompileAheadOfTime,
             allow_unresolved_imports: false,
+            compiled_module_cache: None,
+            resource_limits: Default::default(),
+            allocation_strategy: Default::default(),
         }) {
             Ok(vm) => vm,
             Err(err) => {
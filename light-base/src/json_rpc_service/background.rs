@@ -307,6 +307,7 @@ impl<TPlat: PlatformRef> Background<TPlat> {
             | methods::MethodCall::state_queryStorageAt { .. }
             | methods::MethodCall::state_subscribeRuntimeVersion { .. }
             | methods::MethodCall::state_subscribeStorage { .. }
+            | methods::MethodCall::state_traceBlock { .. }
             | methods::MethodCall::state_unsubscribeRuntimeVersion { .. }
             | methods::MethodCall::state_unsubscribeStorage { .. }
             | methods::MethodCall::system_accountNextIndex { .. }
@@ -420,6 +421,9 @@ impl<TPlat: PlatformRef> Background<TPlat> {
             methods::MethodCall::system_chainType {} => {
                 self.system_chain_type(request).await;
             }
+            methods::MethodCall::system_dryRun { .. } => {
+                self.system_dry_run(request).await;
+            }
             methods::MethodCall::system_health {} => {
                 self.system_health(request).await;
             }
@@ -507,8 +511,8 @@ impl<TPlat: PlatformRef> Background<TPlat> {
             | methods::MethodCall::state_getStorageHash { .. }
             | methods::MethodCall::state_getStorageSize { .. }
             | methods::MethodCall::state_queryStorage { .. }
+            | methods::MethodCall::state_traceBlock { .. }
             | methods::MethodCall::system_addReservedPeer { .. }
-            | methods::MethodCall::system_dryRun { .. }
             | methods::MethodCall::system_networkState { .. }
             | methods::MethodCall::system_removeReservedPeer { .. }) => {
                 // TODO: implement the ones that make sense to implement ^
@@ -581,6 +585,7 @@ impl<TPlat: PlatformRef> Background<TPlat> {
             | methods::MethodCall::state_queryStorageAt { .. }
             | methods::MethodCall::state_subscribeRuntimeVersion { .. }
             | methods::MethodCall::state_subscribeStorage { .. }
+            | methods::MethodCall::state_traceBlock { .. }
             | methods::MethodCall::state_unsubscribeRuntimeVersion { .. }
             | methods::MethodCall::state_unsubscribeStorage { .. }
             | methods::MethodCall::system_accountNextIndex { .. }
@@ -1057,6 +1062,7 @@ impl<TPlat: PlatformRef> Background<TPlat> {
             storage_main_trie_changes: Default::default(),
             max_log_level: 0,
             calculate_trie_changes: false,
+            execution_tracing: None,
         }) {
             Ok(vm) => vm,
             Err((err, prototype)) => {
@@ -1148,6 +1154,12 @@ impl<TPlat: PlatformRef> Background<TPlat> {
                         .unlock(runtime_host::RuntimeHostVm::Offchain(ctx).into_prototype());
                     break Err(RuntimeCallError::ForbiddenHostCall);
                 }
+                req @ (runtime_host::RuntimeHostVm::KeystorePublicKeys(_)
+                | runtime_host::RuntimeHostVm::KeystoreGenerate(_)
+                | runtime_host::RuntimeHostVm::KeystoreSign(_)) => {
+                    runtime_call_lock.unlock(req.into_prototype());
+                    break Err(RuntimeCallError::ForbiddenHostCall);
+                }
             }
         }
     }
@@ -1348,6 +1348,7 @@ impl<TPlat: PlatformRef> ChainHeadFollowTask<TPlat> {
                             storage_main_trie_changes: Default::default(),
                             max_log_level: 0,
                             calculate_trie_changes: false,
+                            execution_tracing: None,
                         }) {
                             Err((error, prototype)) => {
                                 runtime_call_lock.unlock(prototype);
@@ -1495,6 +1496,20 @@ impl<TPlat: PlatformRef> ChainHeadFollowTask<TPlat> {
                                             }).await;
                                             break;
                                         }
+                                        req @ (runtime_host::RuntimeHostVm::KeystorePublicKeys(_)
+                                        | runtime_host::RuntimeHostVm::KeystoreGenerate(_)
+                                        | runtime_host::RuntimeHostVm::KeystoreSign(_)) => {
+                                            runtime_call_lock.unlock(req.into_prototype());
+                                            let _ = to_main_task.send(OperationEvent {
+                                                operation_id: operation_id.clone(),
+                                                is_done: true,
+                                                notification: methods::FollowEvent::OperationError {
+                                                    operation_id: operation_id.clone().into(),
+                                                    error: "Runtime has called a keystore host function".to_string().into(),
+                                                }
+                                            }).await;
+                                            break;
+                                        }
                                     }
                                 }
                             }
@@ -460,6 +460,69 @@ impl<TPlat: PlatformRef> Background<TPlat> {
         }
     }
 
+    /// Handles a call to [`methods::MethodCall::system_dryRun`].
+    pub(super) async fn system_dry_run(self: &Arc<Self>, request: service::RequestProcess) {
+        let methods::MethodCall::system_dryRun { extrinsic, hash } = request.request() else {
+            unreachable!()
+        };
+
+        let block_hash = if let Some(hash) = hash {
+            hash.0
+        } else {
+            let (tx, rx) = oneshot::channel();
+            self.to_legacy
+                .lock()
+                .await
+                .send(legacy_state_sub::Message::CurrentBestBlockHash { result_tx: tx })
+                .await
+                .unwrap();
+            rx.await.unwrap()
+        };
+
+        // The extrinsic is applied directly on top of the storage of the block, without
+        // initializing a new block beforehand, in the same way as Substrate does.
+        let result = self
+            .runtime_call(
+                &block_hash,
+                "BlockBuilder",
+                json_rpc::dry_run::SUPPORTED_API_VERSIONS,
+                json_rpc::dry_run::APPLY_EXTRINSIC_FUNCTION_NAME,
+                iter::once(&extrinsic.0),
+                3,
+                Duration::from_secs(10),
+                NonZeroU32::new(3).unwrap(),
+            )
+            .await;
+
+        match result {
+            // The result is always returned in the format of the latest version of the API.
+            Ok(result) => match json_rpc::dry_run::decode_apply_extrinsic_result(
+                &result.return_value,
+                result.api_version,
+            ) {
+                Ok(result) => request.respond(methods::Response::system_dryRun(
+                    methods::HexString(json_rpc::dry_run::encode_apply_extrinsic_result(&result)),
+                )),
+                Err(error) => request.fail(json_rpc::parse::ErrorResponse::ServerError(
+                    -32000,
+                    &format!("Failed to decode runtime output: {error}"),
+                )),
+            },
+            Err(error) => {
+                log::warn!(
+                    target: &self.log_target,
+                    "Returning error from `system_dryRun`. \
+                    API user might not function properly. Error: {}",
+                    error
+                );
+                request.fail(json_rpc::parse::ErrorResponse::ServerError(
+                    -32000,
+                    &error.to_string(),
+                ));
+            }
+        }
+    }
+
     /// Handles a call to [`methods::MethodCall::state_getKeys`].
     pub(super) async fn state_get_keys(self: &Arc<Self>, request: service::RequestProcess) {
         let methods::MethodCall::state_getKeys { prefix, hash } = request.request() else {
@@ -77,9 +77,12 @@ use futures_util::{future, FutureExt as _};
 use hashbrown::{hash_map::Entry, HashMap};
 use itertools::Itertools as _;
 use smoldot::{
-    chain, chain_spec, header,
+    chain, chain_spec, executor, header,
     informant::HashDisplay,
-    libp2p::{connection, multiaddr, peer_id},
+    libp2p::{
+        connection::{self, tls},
+        multiaddr, peer_id,
+    },
 };
 
 mod database;
@@ -670,12 +673,18 @@ impl<TPlat: platform::PlatformRef, TChain> Client<TPlat, TChain> {
             Entry::Vacant(entry) => {
                 // Key used by the networking. Represents the identity of the node on the
                 // peer-to-peer network.
-                let network_noise_key = {
+                // The certificate presented on QUIC connections is signed with the same libp2p key.
+                let (network_noise_key, network_quic_certificate) = {
                     let mut noise_static_key = zeroize::Zeroizing::new([0u8; 32]);
                     self.platform.fill_random_bytes(&mut *noise_static_key);
+                    let mut certificate_key = zeroize::Zeroizing::new([0u8; 32]);
+                    self.platform.fill_random_bytes(&mut *certificate_key);
                     let mut libp2p_key = zeroize::Zeroizing::new([0u8; 32]);
                     self.platform.fill_random_bytes(&mut *libp2p_key);
-                    connection::NoiseKey::new(&libp2p_key, &noise_static_key)
+                    (
+                        connection::NoiseKey::new(&libp2p_key, &noise_static_key),
+                        tls::generate_certificate(&libp2p_key, &certificate_key),
+                    )
                 };
 
                 // Version of the client when requested through the networking.
@@ -730,6 +739,7 @@ impl<TPlat: platform::PlatformRef, TChain> Client<TPlat, TChain> {
                             relay_chain.as_ref().map(|(r, _)| r),
                             network_identify_agent_version,
                             network_noise_key,
+                            network_quic_certificate,
                         )
                         .await;
 
@@ -1090,6 +1100,7 @@ async fn start_services<TPlat: platform::PlatformRef>(
     relay_chain: Option<&ChainServices<TPlat>>,
     network_identify_agent_version: String,
     network_noise_key: connection::NoiseKey,
+    network_quic_certificate: tls::Certificate,
 ) -> ChainServices<TPlat> {
     // Since `network_noise_key` is moved out below, use it to build the network identity ahead
     // of the network service starting.
@@ -1103,6 +1114,7 @@ async fn start_services<TPlat: platform::PlatformRef>(
             num_events_receivers: 1, // Configures the length of `network_event_receivers`
             identify_agent_version: network_identify_agent_version,
             noise_key: network_noise_key,
+            quic_certificate: network_quic_certificate,
             chains: vec![network_service::ConfigChain {
                 log_name: log_name.clone(),
                 has_grandpa_protocol: matches!(
@@ -1161,6 +1173,13 @@ async fn start_services<TPlat: platform::PlatformRef>(
                 platform: platform.clone(),
                 sync_service: sync_service.clone(),
                 genesis_block_scale_encoded_header,
+                // The runtime of a parachain is provided by the parachain itself and isn't
+                // trusted. The fuel limit bounds the CPU time that a single call can take, and
+                // is large enough to not be reached by any legitimate runtime call.
+                resource_limits: executor::vm::ResourceLimits {
+                    fuel: Some(50_000_000_000),
+                    max_memory_pages: None,
+                },
             })
             .await,
         );
@@ -1203,6 +1222,7 @@ async fn start_services<TPlat: platform::PlatformRef>(
                 platform: platform.clone(),
                 sync_service: sync_service.clone(),
                 genesis_block_scale_encoded_header,
+                resource_limits: Default::default(),
             })
             .await,
         );
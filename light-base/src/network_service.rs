@@ -45,7 +45,7 @@ use alloc::{
     sync::Arc,
     vec::{self, Vec},
 };
-use core::{cmp, mem, num::NonZeroUsize, pin::Pin, task::Poll, time::Duration};
+use core::{cmp, iter, mem, num::NonZeroUsize, pin::Pin, task::Poll, time::Duration};
 use futures_channel::oneshot;
 use futures_lite::FutureExt as _;
 use futures_util::{future, stream, StreamExt as _};
@@ -54,7 +54,12 @@ use itertools::Itertools as _;
 use smoldot::{
     header,
     informant::{BytesDisplay, HashDisplay},
-    libp2p::{connection, multiaddr::Multiaddr, peer_id::PeerId, peers},
+    libp2p::{
+        connection::{self, tls},
+        multiaddr::Multiaddr,
+        peer_id::PeerId,
+        peers,
+    },
     network::{protocol, service},
 };
 
@@ -73,6 +78,10 @@ pub struct Config<TPlat> {
     /// Key to use for the encryption layer of all the connections. Gives the node its identity.
     pub noise_key: connection::NoiseKey,
 
+    /// Certificate presented to the remote when opening QUIC connections. Must have been
+    /// generated from the same libp2p private key as [`Config::noise_key`].
+    pub quic_certificate: tls::Certificate,
+
     /// Number of event receivers returned by [`NetworkService::new`].
     pub num_events_receivers: usize,
 
@@ -162,6 +171,9 @@ impl<TPlat: PlatformRef> NetworkService<TPlat> {
                 genesis_hash: chain.genesis_block_hash,
                 role: protocol::Role::Light,
                 allow_inbound_block_requests: false,
+                allow_inbound_light_requests: false,
+                allow_inbound_grandpa_warp_sync_requests: false,
+                allow_inbound_state_requests: false,
             });
 
             log_chain_names.push(chain.log_name);
@@ -204,6 +216,7 @@ impl<TPlat: PlatformRef> NetworkService<TPlat> {
             Box::pin(
                 background_task(BackgroundTask {
                     identify_agent_version: config.identify_agent_version,
+                    quic_certificate: Arc::new(config.quic_certificate),
                     log_chain_names: log_chain_names.clone(),
                     messages_tx: messages_tx.clone(),
                     network: service::ChainNetwork::new(service::Config {
@@ -868,6 +881,9 @@ struct BackgroundTask<TPlat: PlatformRef> {
     /// Value provided through [`Config::identify_agent_version`].
     identify_agent_version: String,
 
+    /// Value provided through [`Config::quic_certificate`].
+    quic_certificate: Arc<tls::Certificate>,
+
     /// Names of the various chains the network service connects to. Used only for logging
     /// purposes.
     log_chain_names: Vec<String>,
@@ -1099,7 +1115,7 @@ async fn background_task<TPlat: PlatformRef>(mut task: BackgroundTask<TPlat>) {
                 //TODO: task name
                 task.platform.spawn_task(
                     "".into(),
-                    tasks::webrtc_multi_stream_connection_task::<TPlat>(
+                    tasks::multi_stream_connection_task::<TPlat>(
                         connection,
                         multiaddr.to_string(),
                         task.platform.clone(),
@@ -1694,11 +1710,18 @@ async fn background_task<TPlat: PlatformRef>(mut task: BackgroundTask<TPlat>) {
                     "Connection({}) => IdentifyRequest",
                     peer_id,
                 );
-                task.network
-                    .respond_identify(request_id, &task.identify_agent_version);
+                task.network.respond_identify(
+                    request_id,
+                    &task.identify_agent_version,
+                    iter::empty(),
+                );
                 continue;
             }
-            WhatHappened::NetworkEvent(service::Event::BlocksRequestIn { .. }) => unreachable!(),
+            WhatHappened::NetworkEvent(service::Event::BlocksRequestIn { .. })
+            | WhatHappened::NetworkEvent(service::Event::StorageProofRequestIn { .. })
+            | WhatHappened::NetworkEvent(service::Event::CallProofRequestIn { .. })
+            | WhatHappened::NetworkEvent(service::Event::GrandpaWarpSyncRequestIn { .. })
+            | WhatHappened::NetworkEvent(service::Event::StateRequestIn { .. }) => unreachable!(),
             WhatHappened::NetworkEvent(service::Event::RequestInCancel { .. }) => {
                 // All incoming requests are immediately answered.
                 unreachable!()
@@ -1741,6 +1764,64 @@ async fn background_task<TPlat: PlatformRef>(mut task: BackgroundTask<TPlat>) {
                     message,
                 }
             }
+            WhatHappened::NetworkEvent(service::Event::GrandpaVoteMessage {
+                chain_index,
+                peer_id,
+                ..
+            }) => {
+                // The light client doesn't take part in GrandPa rounds, and votes are ignored.
+                log::debug!(
+                    target: "network",
+                    "Connection({}, {}) => GrandpaVoteMessage",
+                    peer_id,
+                    &task.log_chain_names[chain_index],
+                );
+                continue;
+            }
+            WhatHappened::NetworkEvent(service::Event::GrandpaCatchUpMessage {
+                chain_index,
+                peer_id,
+                ..
+            }) => {
+                // The light client never sends catch up requests, and catch up messages are
+                // thus unsolicited.
+                log::debug!(
+                    target: "network",
+                    "Connection({}, {}) => GrandpaCatchUpMessage",
+                    peer_id,
+                    &task.log_chain_names[chain_index],
+                );
+                continue;
+            }
+            WhatHappened::NetworkEvent(service::Event::GrandpaCatchUpRequest {
+                chain_index,
+                peer_id,
+                ..
+            }) => {
+                // The light client isn't a GrandPa voter and has no votes to send back.
+                log::debug!(
+                    target: "network",
+                    "Connection({}, {}) => GrandpaCatchUpRequest",
+                    peer_id,
+                    &task.log_chain_names[chain_index],
+                );
+                continue;
+            }
+            WhatHappened::NetworkEvent(service::Event::Transactions {
+                chain_index,
+                peer_id,
+                ..
+            }) => {
+                // The light client doesn't have a transactions pool, and the transactions
+                // gossiped by full nodes are ignored.
+                log::debug!(
+                    target: "network",
+                    "Connection({}, {}) => Transactions",
+                    peer_id,
+                    &task.log_chain_names[chain_index],
+                );
+                continue;
+            }
             WhatHappened::NetworkEvent(service::Event::ProtocolError { peer_id, error }) => {
                 // TODO: handle properly?
                 log::warn!(
@@ -1770,6 +1851,7 @@ async fn background_task<TPlat: PlatformRef>(mut task: BackgroundTask<TPlat>) {
                 let connect_task = tasks::connection_task(
                     start_connect,
                     task.platform.clone(),
+                    task.quic_certificate.clone(),
                     task.messages_tx.clone(),
                     is_important,
                 );
@@ -17,20 +17,24 @@
 
 use super::ToBackground;
 use crate::platform::{
-    address_parse, ConnectError, MultiStreamWebRtcConnection, PlatformRef, SubstreamDirection,
+    address_parse, ConnectError, MultiStreamConnection, PlatformRef, SubstreamDirection,
 };
 
-use alloc::{boxed::Box, string::String};
+use alloc::{boxed::Box, string::String, sync::Arc};
 use core::{pin, time::Duration};
 use futures_lite::FutureExt as _;
 use futures_util::{future, stream::FuturesUnordered, FutureExt as _, StreamExt as _};
-use smoldot::{libp2p::collection::SubstreamFate, network::service};
+use smoldot::{
+    libp2p::{collection::SubstreamFate, connection::tls},
+    network::service,
+};
 
 /// Asynchronous task managing a specific connection, including the connection process and the
 /// processing of the connection after it's been open.
 pub(super) async fn connection_task<TPlat: PlatformRef>(
     start_connect: service::StartConnect<TPlat::Instant>,
     platform: TPlat,
+    quic_certificate: Arc<tls::Certificate>,
     messages_tx: async_channel::Sender<ToBackground<TPlat>>,
     is_important: bool,
 ) {
@@ -44,16 +48,19 @@ pub(super) async fn connection_task<TPlat: PlatformRef>(
     // Convert the `multiaddr` (typically of the form `/ip4/a.b.c.d/tcp/d/ws`) into a future.
     // The future returns an error if the multiaddr isn't supported.
     let socket = {
-        let address = address_parse::multiaddr_to_address(&start_connect.multiaddr)
-            .ok()
-            .filter(|addr| {
-                platform.supports_connection_type(match &addr {
-                    address_parse::AddressOrMultiStreamAddress::Address(addr) => From::from(addr),
-                    address_parse::AddressOrMultiStreamAddress::MultiStreamAddress(addr) => {
-                        From::from(addr)
-                    }
-                })
-            });
+        let address =
+            address_parse::multiaddr_to_address(&start_connect.multiaddr, &quic_certificate)
+                .ok()
+                .filter(|addr| {
+                    platform.supports_connection_type(match &addr {
+                        address_parse::AddressOrMultiStreamAddress::Address(addr) => {
+                            From::from(addr)
+                        }
+                        address_parse::AddressOrMultiStreamAddress::MultiStreamAddress(addr) => {
+                            From::from(addr)
+                        }
+                    })
+                });
         let socket = address.map(|addr| match addr {
             address_parse::AddressOrMultiStreamAddress::Address(addr) => either::Left(
                 platform
@@ -149,7 +156,7 @@ pub(super) async fn connection_task<TPlat: PlatformRef>(
                 .await
                 .unwrap();
         }
-        either::Right(MultiStreamWebRtcConnection {
+        either::Right(MultiStreamConnection::WebRtc {
             connection,
             local_tls_certificate_sha256,
             remote_tls_certificate_sha256,
@@ -177,6 +184,23 @@ pub(super) async fn connection_task<TPlat: PlatformRef>(
                 .await
                 .unwrap();
         }
+        either::Right(MultiStreamConnection::Quic {
+            connection,
+            remote_tls_certificate,
+        }) => {
+            messages_tx
+                .send(ToBackground::ConnectionAttemptOkMultiStream {
+                    pending_id: start_connect.id,
+                    connection,
+                    expected_peer_id: start_connect.expected_peer_id,
+                    multiaddr: start_connect.multiaddr,
+                    handshake_kind: service::MultiStreamHandshakeKind::Quic {
+                        remote_tls_certificate,
+                    },
+                })
+                .await
+                .unwrap();
+        }
     }
 }
 
@@ -325,12 +349,7 @@ pub(super) async fn single_stream_connection_task<TPlat: PlatformRef>(
 }
 
 /// Asynchronous task managing a specific multi-stream connection after it's been open.
-///
-/// > **Note**: This function is specific to WebRTC in the sense that it checks whether the reading
-/// >           and writing sides of substreams never close, and adjusts the size of the write
-/// >           buffer to not go over the frame size limit of WebRTC. It can easily be made more
-/// >           general-purpose.
-pub(super) async fn webrtc_multi_stream_connection_task<TPlat: PlatformRef>(
+pub(super) async fn multi_stream_connection_task<TPlat: PlatformRef>(
     mut connection: TPlat::MultiStream,
     address: String,
     platform: TPlat,
@@ -352,6 +371,10 @@ pub(super) async fn webrtc_multi_stream_connection_task<TPlat: PlatformRef>(
     let mut when_substreams_rw_ready = FuturesUnordered::<
         pin::Pin<Box<dyn future::Future<Output = (pin::Pin<Box<TPlat::Stream>>, usize)> + Send>>,
     >::new();
+    // Notified whenever a message from the coordinator has been injected in the connection task.
+    // Such a message might have queued data to send on any of the substreams, in which case they
+    // must all be processed again.
+    let coordinator_message_injected = event_listener::Event::new();
     // Identifier to assign to the next substream.
     let mut next_substream_id = 0; // TODO: weird API
 
@@ -389,7 +412,9 @@ pub(super) async fn webrtc_multi_stream_connection_task<TPlat: PlatformRef>(
                 // must be called. Because we only call `read_write_access` when `message_sending`
                 // is `None`, we also call `wait_read_write_again` only when `message_sending` is
                 // `None`.
-                let fut = if message_sending.is_none() {
+                // `FuturesUnordered` yields `None` when empty, after which `select_next_some`
+                // panics if polled again. For this reason, it isn't polled when empty.
+                let fut = if message_sending.is_none() && !when_substreams_rw_ready.is_empty() {
                     Some(when_substreams_rw_ready.select_next_some())
                 } else {
                     None
@@ -441,6 +466,7 @@ pub(super) async fn webrtc_multi_stream_connection_task<TPlat: PlatformRef>(
         match what_happened {
             WhatHappened::CoordinatorMessage(message) => {
                 connection_task.inject_coordinator_message(message);
+                coordinator_message_injected.notify(usize::max_value());
             }
             WhatHappened::CoordinatorDead => return,
             WhatHappened::SocketEvent(mut socket, substream_id) => {
@@ -511,8 +537,12 @@ pub(super) async fn webrtc_multi_stream_connection_task<TPlat: PlatformRef>(
                 if let SubstreamFate::Continue = substream_fate {
                     when_substreams_rw_ready.push({
                         let platform = platform.clone();
+                        let coordinator_message_injected = coordinator_message_injected.listen();
                         Box::pin(async move {
-                            platform.wait_read_write_again(socket.as_mut());
+                            platform
+                                .wait_read_write_again(socket.as_mut())
+                                .or(coordinator_message_injected)
+                                .await;
                             (socket, substream_id)
                         })
                     });
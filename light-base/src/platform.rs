@@ -15,9 +15,10 @@
 // You should have received a copy of the GNU General Public License
 // along with this program.  If not, see <http://www.gnu.org/licenses/>.
 
-use alloc::{borrow::Cow, string::String};
+use alloc::{borrow::Cow, string::String, vec::Vec};
 use core::{fmt, future::Future, ops, pin::Pin, str, time::Duration};
 use futures_util::future;
+use smoldot::libp2p::connection::tls;
 
 pub use smoldot::libp2p::read_write;
 
@@ -27,6 +28,7 @@ pub use smoldot::libp2p::with_buffers;
 
 pub mod address_parse;
 pub mod default;
+pub mod memory;
 
 /// Access to a platform's capabilities.
 ///
@@ -60,7 +62,7 @@ pub trait PlatformRef: Clone + Send + Sync + 'static {
     /// have been gracefully closed in the past.
     type Stream: Send + 'static;
     type StreamConnectFuture: Future<Output = Result<Self::Stream, ConnectError>> + Send + 'static;
-    type MultiStreamConnectFuture: Future<Output = Result<MultiStreamWebRtcConnection<Self::MultiStream>, ConnectError>>
+    type MultiStreamConnectFuture: Future<Output = Result<MultiStreamConnection<Self::MultiStream>, ConnectError>>
         + Send
         + 'static;
     type ReadWriteAccess<'a>: ops::DerefMut<Target = read_write::ReadWrite<Self::Instant>> + 'a;
@@ -199,14 +201,27 @@ pub trait PlatformRef: Clone + Send + Sync + 'static {
 
 /// Established multistream connection information. See [`PlatformRef::connect_multistream`].
 #[derive(Debug)]
-pub struct MultiStreamWebRtcConnection<TConnection> {
-    /// Object representing the WebRTC connection.
-    pub connection: TConnection,
-    /// SHA256 hash of the TLS certificate used by the local node at the DTLS layer.
-    pub local_tls_certificate_sha256: [u8; 32],
-    /// SHA256 hash of the TLS certificate used by the remote node at the DTLS layer.
-    // TODO: consider caching the information that was passed in the address instead of passing it back
-    pub remote_tls_certificate_sha256: [u8; 32],
+pub enum MultiStreamConnection<TConnection> {
+    /// Connection opened from a [`MultiStreamAddress::WebRtc`].
+    WebRtc {
+        /// Object representing the WebRTC connection.
+        connection: TConnection,
+        /// SHA256 hash of the TLS certificate used by the local node at the DTLS layer.
+        local_tls_certificate_sha256: [u8; 32],
+        /// SHA256 hash of the TLS certificate used by the remote node at the DTLS layer.
+        // TODO: consider caching the information that was passed in the address instead of passing it back
+        remote_tls_certificate_sha256: [u8; 32],
+    },
+    /// Connection opened from a [`MultiStreamAddress::Quic`].
+    Quic {
+        /// Object representing the QUIC connection.
+        connection: TConnection,
+        /// DER-encoded X.509 certificate presented by the remote during the TLS handshake.
+        ///
+        /// The implementation of the [`PlatformRef`] trait isn't required to verify that this
+        /// certificate is a valid libp2p certificate, as this is done by the API user.
+        remote_tls_certificate: Vec<u8>,
+    },
 }
 
 /// Direction in which a substream has been opened. See [`PlatformRef::next_substream`].
@@ -257,6 +272,12 @@ pub enum ConnectionType {
     WebRtcIpv4,
     /// Libp2p-specific WebRTC flavour.
     WebRtcIpv6,
+    /// QUIC connection using the libp2p TLS certificates.
+    QuicIpv4,
+    /// QUIC connection using the libp2p TLS certificates.
+    QuicIpv6,
+    /// In-process connection. See [`Address::Memory`].
+    Memory,
 }
 
 impl<'a> From<&'a Address<'a>> for ConnectionType {
@@ -285,12 +306,13 @@ impl<'a> From<&'a Address<'a>> for ConnectionType {
                 secure: *secure,
                 remote_is_localhost: hostname.eq_ignore_ascii_case("localhost"),
             },
+            Address::Memory { .. } => ConnectionType::Memory,
         }
     }
 }
 
-impl<'a> From<&'a MultiStreamAddress> for ConnectionType {
-    fn from(address: &'a MultiStreamAddress) -> ConnectionType {
+impl<'a> From<&'a MultiStreamAddress<'a>> for ConnectionType {
+    fn from(address: &'a MultiStreamAddress<'a>) -> ConnectionType {
         match address {
             MultiStreamAddress::WebRtc {
                 ip: IpAddr::V4(_), ..
@@ -298,6 +320,12 @@ impl<'a> From<&'a MultiStreamAddress> for ConnectionType {
             MultiStreamAddress::WebRtc {
                 ip: IpAddr::V6(_), ..
             } => ConnectionType::WebRtcIpv6,
+            MultiStreamAddress::Quic {
+                ip: IpAddr::V4(_), ..
+            } => ConnectionType::QuicIpv4,
+            MultiStreamAddress::Quic {
+                ip: IpAddr::V6(_), ..
+            } => ConnectionType::QuicIpv6,
         }
     }
 }
@@ -353,12 +381,20 @@ pub enum Address<'a> {
         /// `true` for WebSocket secure connections.
         secure: bool,
     },
+
+    /// In-process connection, corresponding to a `/memory/...` multiaddress. Typically only
+    /// supported by implementations of [`PlatformRef`] designed for testing purposes, such as
+    /// [`memory::MemoryPlatform`].
+    Memory {
+        /// Port to connect to.
+        port: u64,
+    },
 }
 
 /// Address passed to [`PlatformRef::connect_multistream`].
 // TODO: we don't differentiate between Dns4 and Dns6
 #[derive(Debug, Copy, Clone, PartialEq, Eq)]
-pub enum MultiStreamAddress {
+pub enum MultiStreamAddress<'a> {
     /// Libp2p-specific WebRTC flavour.
     ///
     /// The implementation the [`PlatformRef`] trait is responsible for opening the SCTP
@@ -374,6 +410,35 @@ pub enum MultiStreamAddress {
         // TODO: consider providing a reference here; right now there's some issues with multiaddr preventing that
         remote_certificate_sha256: [u8; 32],
     },
+
+    /// QUIC connection.
+    ///
+    /// The implementation of the [`PlatformRef`] trait is responsible for performing the QUIC
+    /// and TLS handshakes, including negotiating the `libp2p` ALPN protocol. It must accept any
+    /// certificate presented by the remote, provided that the remote proves that it owns the
+    /// corresponding private key. The API user of the [`PlatformRef`] trait is responsible for
+    /// verifying the libp2p extension of the certificate.
+    ///
+    /// See <https://github.com/libp2p/specs/blob/master/quic/README.md>.
+    Quic {
+        /// IP address to connect to.
+        ip: IpAddr,
+        /// UDP port to connect to.
+        port: u16,
+        /// Version of the QUIC protocol to use.
+        version: QuicVersion,
+        /// Certificate and private key to present to the remote during the TLS handshake.
+        local_tls_certificate: &'a tls::Certificate,
+    },
+}
+
+/// Version of the QUIC protocol. See [`MultiStreamAddress::Quic`].
+#[derive(Debug, Copy, Clone, PartialEq, Eq)]
+pub enum QuicVersion {
+    /// Draft 29 of the QUIC specification. Corresponds to `/quic` multiaddresses.
+    Draft29,
+    /// Version 1 of QUIC, as defined in RFC 9000. Corresponds to `/quic-v1` multiaddresses.
+    V1,
 }
 
 /// Either an IPv4 or IPv6 address.
@@ -15,21 +15,38 @@
 // You should have received a copy of the GNU General Public License
 // along with this program.  If not, see <http://www.gnu.org/licenses/>.
 
-use smoldot::libp2p::{multiaddr::ProtocolRef, multihash, Multiaddr};
+use smoldot::libp2p::{connection::tls, multiaddr::ProtocolRef, multihash, Multiaddr};
 
-use super::{Address, IpAddr, MultiStreamAddress};
+use super::{Address, IpAddr, MultiStreamAddress, QuicVersion};
 use core::str;
 
 pub enum AddressOrMultiStreamAddress<'a> {
     Address(Address<'a>),
-    MultiStreamAddress(MultiStreamAddress),
+    MultiStreamAddress(MultiStreamAddress<'a>),
 }
 
 /// Parses a [`Multiaddr`] into an [`Address`] or [`MultiStreamAddress`].
-pub fn multiaddr_to_address(multiaddr: &Multiaddr) -> Result<AddressOrMultiStreamAddress, Error> {
+///
+/// The `local_tls_certificate` is put in the [`MultiStreamAddress::Quic`] that is returned if
+/// the multiaddress is a QUIC multiaddress.
+pub fn multiaddr_to_address<'a>(
+    multiaddr: &'a Multiaddr,
+    local_tls_certificate: &'a tls::Certificate,
+) -> Result<AddressOrMultiStreamAddress<'a>, Error> {
     let mut iter = multiaddr.iter().fuse();
 
     let proto1 = iter.next().ok_or(Error::UnknownCombination)?;
+
+    // Memory addresses are the only ones that consist of a single component.
+    if let ProtocolRef::Memory(port) = proto1 {
+        if iter.next().is_some() {
+            return Err(Error::UnknownCombination);
+        }
+        return Ok(AddressOrMultiStreamAddress::Address(Address::Memory {
+            port,
+        }));
+    }
+
     let proto2 = iter.next().ok_or(Error::UnknownCombination)?;
     let proto3 = iter.next();
     let proto4 = iter.next();
@@ -110,10 +127,9 @@ pub fn multiaddr_to_address(multiaddr: &Multiaddr) -> Result<AddressOrMultiStrea
             if multihash.hash_algorithm_code() != 12 {
                 return Err(Error::NonSha256Certhash);
             }
-            let Ok(&remote_certificate_sha256) = <&[u8; 32]>::try_from(multihash.data())
-                else {
-                    return Err(Error::InvalidMultihashLength);
-                };
+            let Ok(&remote_certificate_sha256) = <&[u8; 32]>::try_from(multihash.data()) else {
+                return Err(Error::InvalidMultihashLength);
+            };
             AddressOrMultiStreamAddress::MultiStreamAddress(MultiStreamAddress::WebRtc {
                 ip: IpAddr::V4(ip),
                 port,
@@ -132,10 +148,9 @@ pub fn multiaddr_to_address(multiaddr: &Multiaddr) -> Result<AddressOrMultiStrea
             if multihash.hash_algorithm_code() != 12 {
                 return Err(Error::NonSha256Certhash);
             }
-            let Ok(&remote_certificate_sha256) = <&[u8; 32]>::try_from(multihash.data())
-                else {
-                    return Err(Error::InvalidMultihashLength);
-                };
+            let Ok(&remote_certificate_sha256) = <&[u8; 32]>::try_from(multihash.data()) else {
+                return Err(Error::InvalidMultihashLength);
+            };
             AddressOrMultiStreamAddress::MultiStreamAddress(MultiStreamAddress::WebRtc {
                 ip: IpAddr::V6(ip),
                 port,
@@ -143,6 +158,39 @@ pub fn multiaddr_to_address(multiaddr: &Multiaddr) -> Result<AddressOrMultiStrea
             })
         }
 
+        (ProtocolRef::Ip4(ip), ProtocolRef::Udp(port), Some(ProtocolRef::Quic), None) => {
+            AddressOrMultiStreamAddress::MultiStreamAddress(MultiStreamAddress::Quic {
+                ip: IpAddr::V4(ip),
+                port,
+                version: QuicVersion::Draft29,
+                local_tls_certificate,
+            })
+        }
+        (ProtocolRef::Ip6(ip), ProtocolRef::Udp(port), Some(ProtocolRef::Quic), None) => {
+            AddressOrMultiStreamAddress::MultiStreamAddress(MultiStreamAddress::Quic {
+                ip: IpAddr::V6(ip),
+                port,
+                version: QuicVersion::Draft29,
+                local_tls_certificate,
+            })
+        }
+        (ProtocolRef::Ip4(ip), ProtocolRef::Udp(port), Some(ProtocolRef::QuicV1), None) => {
+            AddressOrMultiStreamAddress::MultiStreamAddress(MultiStreamAddress::Quic {
+                ip: IpAddr::V4(ip),
+                port,
+                version: QuicVersion::V1,
+                local_tls_certificate,
+            })
+        }
+        (ProtocolRef::Ip6(ip), ProtocolRef::Udp(port), Some(ProtocolRef::QuicV1), None) => {
+            AddressOrMultiStreamAddress::MultiStreamAddress(MultiStreamAddress::Quic {
+                ip: IpAddr::V6(ip),
+                port,
+                version: QuicVersion::V1,
+                local_tls_certificate,
+            })
+        }
+
         _ => return Err(Error::UnknownCombination),
     })
 }
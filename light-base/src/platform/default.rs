@@ -20,7 +20,7 @@
 
 use super::{
     with_buffers, Address, ConnectError, ConnectionType, IpAddr, MultiStreamAddress,
-    MultiStreamWebRtcConnection, PlatformRef, SubstreamDirection,
+    MultiStreamConnection, PlatformRef, SubstreamDirection,
 };
 
 use alloc::{borrow::Cow, sync::Arc};
@@ -33,10 +33,28 @@ use std::{
     time::{Instant, UNIX_EPOCH},
 };
 
+#[cfg(feature = "quic")]
+use super::QuicVersion;
+#[cfg(feature = "quic")]
+use futures_util::stream::FuturesUnordered;
+#[cfg(feature = "quic")]
+use smoldot::libp2p::quic;
+#[cfg(feature = "quic")]
+use std::{
+    net::{Ipv4Addr, Ipv6Addr},
+    sync::Mutex,
+};
+
 /// Implementation of the [`PlatformRef`] trait that leverages the operating system.
+///
+/// QUIC connections are only supported if the `quic` feature is enabled.
 pub struct DefaultPlatform {
     client_name: String,
     client_version: String,
+    /// QUIC endpoints used to open outgoing QUIC connections, for IPv4 and IPv6 respectively.
+    /// Lazily created when the first connection is opened, then shared between all connections.
+    #[cfg(feature = "quic")]
+    quic_endpoints: Mutex<[Option<quinn::Endpoint>; 2]>,
 }
 
 impl DefaultPlatform {
@@ -44,24 +62,45 @@ impl DefaultPlatform {
         Arc::new(DefaultPlatform {
             client_name,
             client_version,
+            #[cfg(feature = "quic")]
+            quic_endpoints: Mutex::new([None, None]),
         })
     }
+
+    /// Returns the QUIC endpoint to use in order to reach an address of the given IP family,
+    /// creating it if necessary.
+    #[cfg(feature = "quic")]
+    fn quic_endpoint(&self, ipv6: bool) -> io::Result<quinn::Endpoint> {
+        let mut endpoints = self.quic_endpoints.lock().unwrap();
+        let entry = &mut endpoints[usize::from(ipv6)];
+        if let Some(endpoint) = entry {
+            return Ok(endpoint.clone());
+        }
+
+        let bind_address = if ipv6 {
+            SocketAddr::from((Ipv6Addr::UNSPECIFIED, 0))
+        } else {
+            SocketAddr::from((Ipv4Addr::UNSPECIFIED, 0))
+        };
+        let endpoint = quinn::Endpoint::client(bind_address)?;
+        *entry = Some(endpoint.clone());
+        Ok(endpoint)
+    }
 }
 
 impl PlatformRef for Arc<DefaultPlatform> {
     type Delay = futures_util::future::Map<smol::Timer, fn(Instant) -> ()>;
     type Instant = Instant;
-    type MultiStream = std::convert::Infallible; // TODO: replace with `!` once stable: https://github.com/rust-lang/rust/issues/35121
+    type MultiStream = MultiStream;
     type Stream = Stream;
     type StreamConnectFuture = future::BoxFuture<'static, Result<Self::Stream, ConnectError>>;
-    type MultiStreamConnectFuture = future::BoxFuture<
-        'static,
-        Result<MultiStreamWebRtcConnection<Self::MultiStream>, ConnectError>,
-    >;
+    type MultiStreamConnectFuture =
+        future::BoxFuture<'static, Result<MultiStreamConnection<Self::MultiStream>, ConnectError>>;
     type ReadWriteAccess<'a> = with_buffers::ReadWriteAccess<'a>;
     type StreamUpdateFuture<'a> = future::BoxFuture<'a, ()>;
     type StreamErrorRef<'a> = &'a io::Error;
-    type NextSubstreamFuture<'a> = future::Pending<Option<(Self::Stream, SubstreamDirection)>>;
+    type NextSubstreamFuture<'a> =
+        future::BoxFuture<'a, Option<(Self::Stream, SubstreamDirection)>>;
 
     fn now_from_unix_epoch(&self) -> Duration {
         // Intentionally panic if the time is configured earlier than the UNIX EPOCH.
@@ -110,7 +149,11 @@ impl PlatformRef for Arc<DefaultPlatform> {
                 | ConnectionType::WebSocketIpv4 { .. }
                 | ConnectionType::WebSocketIpv6 { .. }
                 | ConnectionType::WebSocketDns { secure: false, .. }
-        )
+        ) || (cfg!(feature = "quic")
+            && matches!(
+                connection_type,
+                ConnectionType::QuicIpv4 | ConnectionType::QuicIpv6
+            ))
     }
 
     fn connect_stream(&self, multiaddr: Address) -> Self::StreamConnectFuture {
@@ -187,26 +230,125 @@ impl PlatformRef for Arc<DefaultPlatform> {
                 }
             };
 
+            #[cfg(feature = "quic")]
+            let socket = future::Either::Left(socket);
             Ok(Stream(with_buffers::WithBuffers::new(socket)))
         })
     }
 
-    fn connect_multistream(&self, _address: MultiStreamAddress) -> Self::MultiStreamConnectFuture {
-        panic!()
+    #[cfg(not(feature = "quic"))]
+    fn connect_multistream(&self, _: MultiStreamAddress) -> Self::MultiStreamConnectFuture {
+        // The API user of the `PlatformRef` trait is never supposed to open connections of
+        // a type that isn't supported.
+        unreachable!()
     }
 
+    #[cfg(not(feature = "quic"))]
     fn open_out_substream(&self, c: &mut Self::MultiStream) {
-        // This function can only be called with so-called "multi-stream" connections. We never
-        // open such connection.
         match *c {}
     }
 
-    fn next_substream(&self, c: &'_ mut Self::MultiStream) -> Self::NextSubstreamFuture<'_> {
-        // This function can only be called with so-called "multi-stream" connections. We never
-        // open such connection.
+    #[cfg(not(feature = "quic"))]
+    fn next_substream<'a>(&self, c: &'a mut Self::MultiStream) -> Self::NextSubstreamFuture<'a> {
         match *c {}
     }
 
+    #[cfg(feature = "quic")]
+    fn connect_multistream(&self, address: MultiStreamAddress) -> Self::MultiStreamConnectFuture {
+        let (socket_addr, version, local_tls_certificate) = match address {
+            MultiStreamAddress::Quic {
+                ip: IpAddr::V4(ip),
+                port,
+                version,
+                local_tls_certificate,
+            } => (SocketAddr::from((ip, port)), version, local_tls_certificate),
+            MultiStreamAddress::Quic {
+                ip: IpAddr::V6(ip),
+                port,
+                version,
+                local_tls_certificate,
+            } => (SocketAddr::from((ip, port)), version, local_tls_certificate),
+
+            // The API user of the `PlatformRef` trait is never supposed to open connections of
+            // a type that isn't supported.
+            _ => unreachable!(),
+        };
+
+        let mut client_config = quic::client_config(local_tls_certificate);
+        client_config.version(match version {
+            QuicVersion::Draft29 => quic::DRAFT_29_VERSION,
+            QuicVersion::V1 => quic::VERSION_1,
+        });
+
+        let endpoint = self.quic_endpoint(socket_addr.is_ipv6());
+
+        Box::pin(async move {
+            let connecting = endpoint
+                .and_then(|endpoint| {
+                    endpoint
+                        .connect_with(client_config, socket_addr, quic::SERVER_NAME)
+                        .map_err(|err| io::Error::new(io::ErrorKind::Other, err))
+                })
+                .map_err(|err| ConnectError {
+                    message: format!("Failed to open QUIC connection: {err}"),
+                })?;
+
+            let connection = connecting.await.map_err(|err| ConnectError {
+                message: format!("Failed to reach peer: {err}"),
+            })?;
+
+            let remote_tls_certificate =
+                quic::remote_certificate(&connection).ok_or_else(|| ConnectError {
+                    message: "Remote didn't present any TLS certificate".into(),
+                })?;
+
+            Ok(MultiStreamConnection::Quic {
+                connection: MultiStream {
+                    connection,
+                    opening_out_substreams: FuturesUnordered::new(),
+                },
+                remote_tls_certificate,
+            })
+        })
+    }
+
+    #[cfg(feature = "quic")]
+    fn open_out_substream(&self, c: &mut Self::MultiStream) {
+        let connection = c.connection.clone();
+        c.opening_out_substreams
+            .push(smol::spawn(async move { connection.open_bi().await.ok() }));
+    }
+
+    #[cfg(feature = "quic")]
+    fn next_substream<'a>(&self, c: &'a mut Self::MultiStream) -> Self::NextSubstreamFuture<'a> {
+        Box::pin(async move {
+            let outbound = async {
+                if c.opening_out_substreams.is_empty() {
+                    future::pending().await
+                } else {
+                    let substream =
+                        futures_util::StreamExt::select_next_some(&mut c.opening_out_substreams)
+                            .await?;
+                    Some((substream, SubstreamDirection::Outbound))
+                }
+            };
+
+            let inbound = async {
+                let substream = c.connection.accept_bi().await.ok()?;
+                Some((substream, SubstreamDirection::Inbound))
+            };
+
+            // If opening a substream fails, the connection has been killed.
+            let ((send, recv), direction) = futures_lite::FutureExt::or(outbound, inbound).await?;
+            Some((
+                Stream(with_buffers::WithBuffers::new(future::Either::Right(
+                    quic::Substream::new(send, recv),
+                ))),
+                direction,
+            ))
+        })
+    }
+
     fn read_write_access<'a>(
         &self,
         stream: Pin<&'a mut Self::Stream>,
@@ -228,6 +370,25 @@ impl PlatformRef for Arc<DefaultPlatform> {
 
 /// Implementation detail of [`DefaultPlatform`].
 #[pin_project::pin_project]
-pub struct Stream(#[pin] with_buffers::WithBuffers<TcpOrWs>);
+pub struct Stream(#[pin] with_buffers::WithBuffers<StreamInner>);
+
+#[cfg(feature = "quic")]
+type StreamInner = future::Either<TcpOrWs, quic::Substream>;
+#[cfg(not(feature = "quic"))]
+type StreamInner = TcpOrWs;
+
+/// Implementation detail of [`DefaultPlatform`].
+#[cfg(not(feature = "quic"))]
+pub enum MultiStream {}
+
+/// Implementation detail of [`DefaultPlatform`].
+#[cfg(feature = "quic")]
+pub struct MultiStream {
+    connection: quinn::Connection,
+    /// Substreams being opened following calls to [`PlatformRef::open_out_substream`]. Yield
+    /// `None` if the connection has been killed.
+    opening_out_substreams:
+        FuturesUnordered<smol::Task<Option<(quinn::SendStream, quinn::RecvStream)>>>,
+}
 
 type TcpOrWs = future::Either<smol::net::TcpStream, websocket::Connection<smol::net::TcpStream>>;
@@ -0,0 +1,242 @@
+// Smoldot
+// Copyright (C) 2023  Pierre Krieger
+// SPDX-License-Identifier: GPL-3.0-or-later WITH Classpath-exception-2.0
+
+// This program is free software: you can redistribute it and/or modify
+// it under the terms of the GNU General Public License as published by
+// the Free Software Foundation, either version 3 of the License, or
+// (at your option) any later version.
+
+// This program is distributed in the hope that it will be useful,
+// but WITHOUT ANY WARRANTY; without even the implied warranty of
+// MERCHANTABILITY or FITNESS FOR A PARTICULAR PURPOSE.  See the
+// GNU General Public License for more details.
+
+// You should have received a copy of the GNU General Public License
+// along with this program.  If not, see <http://www.gnu.org/licenses/>.
+
+//! Implementation of the [`PlatformRef`] trait that doesn't interact with the outside world,
+//! designed for testing purposes.
+//!
+//! Connections can only be opened towards `/memory/...` addresses of a [`MemoryNetwork`] shared
+//! with the other nodes of the test, and time is driven by a [`Clock`] that only moves forward
+//! when [`Clock::advance`] is called.
+//!
+//! The same [`Clock`] can also drive the time of the other nodes of the test, such as full
+//! nodes, through [`Clock::now`], [`Clock::now_from_unix_epoch`] and [`Clock::sleep_until`].
+
+#![cfg(feature = "std")]
+#![cfg_attr(docsrs, doc(cfg(feature = "std")))]
+
+use super::{
+    with_buffers, Address, ConnectError, ConnectionType, MultiStreamAddress, MultiStreamConnection,
+    PlatformRef, SubstreamDirection,
+};
+
+use alloc::{borrow::Cow, sync::Arc};
+use core::{convert::Infallible, future::Future, pin::Pin, time::Duration};
+use futures_util::future;
+use rand::SeedableRng as _;
+use std::{io, sync::Mutex};
+
+pub use smoldot::libp2p::memory::{MemoryNetwork, MemoryStream};
+
+/// Clock whose time only moves forward when [`Clock::advance`] is called.
+///
+/// Cloning a [`Clock`] returns a handle to the same clock, making it possible to share a clock
+/// between multiple [`MemoryPlatform`]s.
+#[derive(Clone)]
+pub struct Clock {
+    inner: Arc<ClockInner>,
+}
+
+struct ClockInner {
+    /// Value returned by [`PlatformRef::now_from_unix_epoch`] when the clock was created.
+    unix_time_start: Duration,
+    /// Time elapsed since the clock was created.
+    elapsed: Mutex<Duration>,
+    /// Notified whenever [`ClockInner::elapsed`] is modified.
+    on_advance: event_listener::Event,
+}
+
+impl Clock {
+    /// Creates a new clock. The parameter is the time since the UNIX epoch that the clock
+    /// initially reports.
+    pub fn new(unix_time_start: Duration) -> Self {
+        Clock {
+            inner: Arc::new(ClockInner {
+                unix_time_start,
+                elapsed: Mutex::new(Duration::new(0, 0)),
+                on_advance: event_listener::Event::new(),
+            }),
+        }
+    }
+
+    /// Returns the time elapsed since the clock was created.
+    pub fn now(&self) -> Duration {
+        *self.inner.elapsed.lock().unwrap()
+    }
+
+    /// Returns the time since the UNIX epoch that the clock currently reports.
+    pub fn now_from_unix_epoch(&self) -> Duration {
+        self.inner.unix_time_start + self.now()
+    }
+
+    /// Moves the clock forward, waking up all the timers that have now elapsed.
+    pub fn advance(&self, duration: Duration) {
+        *self.inner.elapsed.lock().unwrap() += duration;
+        self.inner.on_advance.notify(usize::max_value());
+    }
+
+    /// Returns a future that becomes ready once the clock has reached the given value of
+    /// [`Clock::now`].
+    pub fn sleep_until(&self, when: Duration) -> impl Future<Output = ()> + Send + 'static {
+        let inner = self.inner.clone();
+        async move {
+            loop {
+                // The listener must be created before checking the time, in order to not miss
+                // a notification that would happen in between.
+                let listener = inner.on_advance.listen();
+                if *inner.elapsed.lock().unwrap() >= when {
+                    break;
+                }
+                listener.await;
+            }
+        }
+    }
+}
+
+/// Implementation of the [`PlatformRef`] trait whose connections and time are simulated.
+/// See the module-level documentation.
+pub struct MemoryPlatform {
+    client_name: String,
+    client_version: String,
+    network: MemoryNetwork,
+    clock: Clock,
+    /// Source of all the randomness, so that two platforms created with the same seed generate
+    /// the same random numbers.
+    randomness: Mutex<rand_chacha::ChaCha20Rng>,
+}
+
+impl MemoryPlatform {
+    pub fn new(
+        client_name: String,
+        client_version: String,
+        network: MemoryNetwork,
+        clock: Clock,
+        randomness_seed: [u8; 32],
+    ) -> Arc<Self> {
+        Arc::new(MemoryPlatform {
+            client_name,
+            client_version,
+            network,
+            clock,
+            randomness: Mutex::new(rand_chacha::ChaCha20Rng::from_seed(randomness_seed)),
+        })
+    }
+}
+
+impl PlatformRef for Arc<MemoryPlatform> {
+    type Delay = future::BoxFuture<'static, ()>;
+    // Time elapsed since the creation of the `Clock`.
+    type Instant = Duration;
+    // Multi-stream connections aren't supported.
+    type MultiStream = Infallible;
+    type Stream = with_buffers::WithBuffers<MemoryStream, Duration>;
+    type StreamConnectFuture = future::Ready<Result<Self::Stream, ConnectError>>;
+    type MultiStreamConnectFuture =
+        future::Pending<Result<MultiStreamConnection<Self::MultiStream>, ConnectError>>;
+    type ReadWriteAccess<'a> = with_buffers::ReadWriteAccess<'a, Duration>;
+    type StreamUpdateFuture<'a> = future::BoxFuture<'a, ()>;
+    type StreamErrorRef<'a> = &'a io::Error;
+    type NextSubstreamFuture<'a> = future::Pending<Option<(Self::Stream, SubstreamDirection)>>;
+
+    fn now_from_unix_epoch(&self) -> Duration {
+        self.clock.now_from_unix_epoch()
+    }
+
+    fn now(&self) -> Self::Instant {
+        self.clock.now()
+    }
+
+    fn fill_random_bytes(&self, buffer: &mut [u8]) {
+        rand::RngCore::fill_bytes(&mut *self.randomness.lock().unwrap(), buffer);
+    }
+
+    fn sleep(&self, duration: Duration) -> Self::Delay {
+        Box::pin(self.clock.sleep_until(self.clock.now() + duration))
+    }
+
+    fn sleep_until(&self, when: Self::Instant) -> Self::Delay {
+        Box::pin(self.clock.sleep_until(when))
+    }
+
+    fn spawn_task(
+        &self,
+        _task_name: Cow<str>,
+        task: impl future::Future<Output = ()> + Send + 'static,
+    ) {
+        smol::spawn(task).detach();
+    }
+
+    fn client_name(&self) -> Cow<str> {
+        Cow::Borrowed(&self.client_name)
+    }
+
+    fn client_version(&self) -> Cow<str> {
+        Cow::Borrowed(&self.client_version)
+    }
+
+    fn supports_connection_type(&self, connection_type: ConnectionType) -> bool {
+        matches!(connection_type, ConnectionType::Memory)
+    }
+
+    fn connect_stream(&self, address: Address) -> Self::StreamConnectFuture {
+        let Address::Memory { port } = address else {
+            // The API user of the `PlatformRef` trait is never supposed to open connections of
+            // a type that isn't supported.
+            unreachable!()
+        };
+
+        future::ready(
+            self.network
+                .connect(port)
+                .map(with_buffers::WithBuffers::new)
+                .map_err(|err| ConnectError {
+                    message: format!("Failed to reach peer: {err}"),
+                }),
+        )
+    }
+
+    fn connect_multistream(&self, _: MultiStreamAddress) -> Self::MultiStreamConnectFuture {
+        // The API user of the `PlatformRef` trait is never supposed to open connections of
+        // a type that isn't supported.
+        unreachable!()
+    }
+
+    fn open_out_substream(&self, connection: &mut Self::MultiStream) {
+        match *connection {}
+    }
+
+    fn next_substream<'a>(
+        &self,
+        connection: &'a mut Self::MultiStream,
+    ) -> Self::NextSubstreamFuture<'a> {
+        match *connection {}
+    }
+
+    fn read_write_access<'a>(
+        &self,
+        stream: Pin<&'a mut Self::Stream>,
+    ) -> Result<Self::ReadWriteAccess<'a>, &'a io::Error> {
+        stream.read_write_access(self.clock.now())
+    }
+
+    fn wait_read_write_again<'a>(
+        &self,
+        stream: Pin<&'a mut Self::Stream>,
+    ) -> Self::StreamUpdateFuture<'a> {
+        let clock = self.clock.clone();
+        Box::pin(stream.wait_read_write_again(move |when| clock.sleep_until(when)))
+    }
+}
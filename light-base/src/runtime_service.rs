@@ -99,6 +99,10 @@ pub struct Config<TPlat: PlatformRef> {
 
     /// Header of the genesis block of the chain, in SCALE encoding.
     pub genesis_block_scale_encoded_header: Vec<u8>,
+
+    /// Limits on the resources that the calls to the runtimes compiled by this service can
+    /// use. Should be set when the runtimes of the chain aren't trusted.
+    pub resource_limits: executor::vm::ResourceLimits,
 }
 
 /// Identifies a runtime currently pinned within a [`RuntimeService`].
@@ -113,6 +117,9 @@ pub struct RuntimeService<TPlat: PlatformRef> {
     /// Fields behind a `Mutex`. Should only be locked for short-lived operations.
     guarded: Arc<Mutex<Guarded<TPlat>>>,
 
+    /// See [`Config::resource_limits`].
+    resource_limits: executor::vm::ResourceLimits,
+
     /// Handle to abort the background task.
     background_task_abort: future::AbortHandle,
 }
@@ -166,8 +173,9 @@ impl<TPlat: PlatformRef> RuntimeService<TPlat> {
             let sync_service = config.sync_service.clone();
             let guarded = guarded.clone();
             let platform = config.platform.clone();
+            let resource_limits = config.resource_limits;
             let (abortable, abort) = future::abortable(async move {
-                run_background(log_target, platform, sync_service, guarded).await;
+                run_background(log_target, platform, sync_service, guarded, resource_limits).await;
             });
             background_task_abort = abort;
             abortable.map(|_| ()).boxed()
@@ -176,6 +184,7 @@ impl<TPlat: PlatformRef> RuntimeService<TPlat> {
         RuntimeService {
             sync_service: config.sync_service,
             guarded,
+            resource_limits: config.resource_limits,
             background_task_abort,
         }
     }
@@ -551,7 +560,12 @@ impl<TPlat: PlatformRef> RuntimeService<TPlat> {
             existing_runtime
         } else {
             // No identical runtime was found. Try compiling the new runtime.
-            let runtime = SuccessfulRuntime::from_storage(&storage_code, &storage_heap_pages).await;
+            let runtime = SuccessfulRuntime::from_storage(
+                &storage_code,
+                &storage_heap_pages,
+                self.resource_limits,
+            )
+            .await;
             let runtime = Arc::new(Runtime {
                 heap_pages: storage_heap_pages,
                 runtime_code: storage_code,
@@ -824,6 +838,7 @@ impl<TPlat: PlatformRef> RuntimeAccess<TPlat> {
         let call_proof = call_proof.and_then(|call_proof| {
             proof_decode::decode_and_verify_proof(proof_decode::Config {
                 proof: call_proof.decode().to_owned(), // TODO: to_owned() inefficiency, need some help from the networking to obtain the owned data
+                hash_function: trie::HashFunction::Blake2,
             })
             .map_err(RuntimeCallError::StorageRetrieval)
         });
@@ -1178,6 +1193,7 @@ async fn run_background<TPlat: PlatformRef>(
     platform: TPlat,
     sync_service: Arc<sync_service::SyncService<TPlat>>,
     guarded: Arc<Mutex<Guarded<TPlat>>>,
+    resource_limits: executor::vm::ResourceLimits,
 ) {
     loop {
         // The buffer size should be large enough so that, if the CPU is busy, it doesn't
@@ -1385,6 +1401,7 @@ async fn run_background<TPlat: PlatformRef>(
             platform: platform.clone(),
             sync_service: sync_service.clone(),
             guarded: guarded.clone(),
+            resource_limits,
             blocks_stream: subscription.new_blocks.boxed(),
             wake_up_new_necessary_download: future::pending().boxed().fuse(),
             runtime_downloads: stream::FuturesUnordered::new(),
@@ -1587,6 +1604,9 @@ struct Background<TPlat: PlatformRef> {
 
     guarded: Arc<Mutex<Guarded<TPlat>>>,
 
+    /// See [`Config::resource_limits`].
+    resource_limits: executor::vm::ResourceLimits,
+
     /// Stream of notifications coming from the sync service.
     blocks_stream: Pin<Box<dyn Stream<Item = sync_service::Notification> + Send>>,
 
@@ -1640,7 +1660,12 @@ impl<TPlat: PlatformRef> Background<TPlat> {
         let runtime = if let Some(existing_runtime) = existing_runtime {
             existing_runtime
         } else {
-            let runtime = SuccessfulRuntime::from_storage(&storage_code, &storage_heap_pages).await;
+            let runtime = SuccessfulRuntime::from_storage(
+                &storage_code,
+                &storage_heap_pages,
+                self.resource_limits,
+            )
+            .await;
             match &runtime {
                 Ok(runtime) => {
                     log::info!(
@@ -2177,6 +2202,7 @@ impl SuccessfulRuntime {
     async fn from_storage(
         code: &Option<Vec<u8>>,
         heap_pages: &Option<Vec<u8>>,
+        resource_limits: executor::vm::ResourceLimits,
     ) -> Result<Self, RuntimeError> {
         // Since compiling the runtime is a CPU-intensive operation, we yield once before.
         futures_lite::future::yield_now().await;
@@ -2197,6 +2223,9 @@ impl SuccessfulRuntime {
             heap_pages,
             exec_hint,
             allow_unresolved_imports: false,
+            compiled_module_cache: None,
+            resource_limits,
+            allocation_strategy: Default::default(),
         }) {
             Ok(vm) => {
                 return Ok(SuccessfulRuntime {
@@ -2215,6 +2244,9 @@ impl SuccessfulRuntime {
                     heap_pages,
                     exec_hint,
                     allow_unresolved_imports: true,
+                    compiled_module_cache: None,
+                    resource_limits,
+                    allocation_strategy: Default::default(),
                 }) {
                     Ok(vm) => {
                         log::warn!(
@@ -565,6 +565,7 @@ impl<TPlat: PlatformRef> SyncService<TPlat> {
 
             let decoded_proof = match proof_decode::decode_and_verify_proof(proof_decode::Config {
                 proof: proof.decode(),
+                hash_function: trie::HashFunction::Blake2,
             }) {
                 Ok(d) => d,
                 Err(err) => {
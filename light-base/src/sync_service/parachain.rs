@@ -1232,6 +1232,7 @@ async fn parahead<TPlat: PlatformRef>(
         max_log_level: 0,
         storage_main_trie_changes: Default::default(),
         calculate_trie_changes: false,
+        execution_tracing: None,
     }) {
         Ok(vm) => vm,
         Err((err, prototype)) => {
@@ -1321,6 +1322,12 @@ async fn parahead<TPlat: PlatformRef>(
                     .unlock(runtime_host::RuntimeHostVm::Offchain(req).into_prototype());
                 return Err(ParaheadError::OffchainWorkerHostFunction);
             }
+            req @ (runtime_host::RuntimeHostVm::KeystorePublicKeys(_)
+            | runtime_host::RuntimeHostVm::KeystoreGenerate(_)
+            | runtime_host::RuntimeHostVm::KeystoreSign(_)) => {
+                runtime_call_lock.unlock(req.into_prototype());
+                return Err(ParaheadError::KeystoreHostFunction);
+            }
         }
     };
 
@@ -1357,6 +1364,8 @@ enum ParaheadError {
     InvalidRuntimeOutput(para::Error),
     /// Runtime has called an offchain worker host function.
     OffchainWorkerHostFunction,
+    /// Runtime has called a keystore host function.
+    KeystoreHostFunction,
     /// Runtime service subscription is no longer valid.
     ObsoleteSubscription,
 }
@@ -1372,6 +1381,7 @@ impl ParaheadError {
             ParaheadError::NoCore => false,
             ParaheadError::InvalidRuntimeOutput(_) => false,
             ParaheadError::OffchainWorkerHostFunction => false,
+            ParaheadError::KeystoreHostFunction => false,
             ParaheadError::ObsoleteSubscription => false,
         }
     }
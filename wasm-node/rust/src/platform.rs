@@ -56,7 +56,7 @@ impl smoldot_light::platform::PlatformRef for PlatformRef {
         Box<
             dyn future::Future<
                     Output = Result<
-                        smoldot_light::platform::MultiStreamWebRtcConnection<Self::MultiStream>,
+                        smoldot_light::platform::MultiStreamConnection<Self::MultiStream>,
                         ConnectError,
                     >,
                 > + Send,
@@ -170,6 +170,10 @@ impl smoldot_light::platform::PlatformRef for PlatformRef {
             smoldot_light::platform::ConnectionType::WebSocketDns { secure: true, .. } => 14,
             smoldot_light::platform::ConnectionType::WebRtcIpv4 => 16,
             smoldot_light::platform::ConnectionType::WebRtcIpv6 => 17,
+            // QUIC can't be implemented in the browser.
+            smoldot_light::platform::ConnectionType::QuicIpv4
+            | smoldot_light::platform::ConnectionType::QuicIpv6 => return false,
+            smoldot_light::platform::ConnectionType::Memory => return false,
         };
 
         unsafe { bindings::connection_type_supported(ty) != 0 }
@@ -233,6 +237,7 @@ impl smoldot_light::platform::PlatformRef for PlatformRef {
                 .chain(port.to_be_bytes())
                 .chain(hostname.as_bytes().iter().copied())
                 .collect(),
+            smoldot_light::platform::Address::Memory { .. } => unreachable!(),
         };
 
         let write_closable = match address {
@@ -240,6 +245,7 @@ impl smoldot_light::platform::PlatformRef for PlatformRef {
             | smoldot_light::platform::Address::TcpDns { .. } => true,
             smoldot_light::platform::Address::WebSocketIp { .. }
             | smoldot_light::platform::Address::WebSocketDns { .. } => false,
+            smoldot_light::platform::Address::Memory { .. } => unreachable!(),
         };
 
         unsafe {
@@ -339,6 +345,10 @@ impl smoldot_light::platform::PlatformRef for PlatformRef {
                 .chain(remote_certificate_sha256.iter().copied())
                 .chain(no_std_net::Ipv6Addr::from(ip).to_string().bytes())
                 .collect(),
+
+            // The API user of the `PlatformRef` trait is never supposed to open connections of
+            // a type that isn't supported.
+            smoldot_light::platform::MultiStreamAddress::Quic { .. } => unreachable!(),
         };
 
         unsafe {
@@ -389,7 +399,7 @@ impl smoldot_light::platform::PlatformRef for PlatformRef {
                     ..
                 } => {
                     *connection_handles_alive += 1;
-                    Ok(smoldot_light::platform::MultiStreamWebRtcConnection {
+                    Ok(smoldot_light::platform::MultiStreamConnection::WebRtc {
                         connection: MultiStreamWrapper(connection_id),
                         local_tls_certificate_sha256: *local_tls_certificate_sha256,
                         remote_tls_certificate_sha256: *remote_tls_certificate_sha256,